@@ -1,6 +1,21 @@
 #[derive(Debug, Clone, PartialEq)]
 pub struct Program {
     pub statements: Vec<Statement>,
+    /// Source location of each entry in `statements`, index for index.
+    pub spans: Vec<Span>,
+}
+
+/// Identifies a source file in a [`crate::SourceMap`]. Sources parsed on their own use
+/// `FileId(0)`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct FileId(pub usize);
+
+/// Byte range of a syntax node within its source file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub file: FileId,
+    pub start: usize,
+    pub end: usize,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -25,6 +40,10 @@ pub struct EnvironmentDef {
     pub statements: Vec<MaterialStatement>,
 }
 
+/// `volume Name { ... }`: a participating medium with the same body as an environment plus
+/// property assignments such as `density = 2.0;`.
+pub type VolumeDef = EnvironmentDef;
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct SkeletonDef {
     pub name: String,
@@ -37,14 +56,41 @@ pub struct FunctionDef {
     pub name: String,
     pub params: Vec<String>,
     pub body: Vec<MaterialFunctionStatement>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum MaterialFunctionStatement {
-    Binding { name: String, expr: Expr },
-    Return { expr: Expr },
+    Binding {
+        name: String,
+        mutable: bool,
+        expr: Expr,
+    },
+    Assign {
+        name: String,
+        expr: Expr,
+    },
+    Return {
+        expr: Expr,
+    },
+    If {
+        cond: Expr,
+        then_body: Vec<MaterialFunctionStatement>,
+        else_body: Vec<MaterialFunctionStatement>,
+    },
+    For {
+        var: String,
+        start: Expr,
+        end: Expr,
+        body: Vec<MaterialFunctionStatement>,
+    },
+    Break,
 }
 
+/// Upper bound on the iterations of a single `for` loop. Longer ranges are
+/// truncated so every function body stays bounded.
+pub const MAX_LOOP_ITERATIONS: usize = 1024;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum MaterialStatement {
     Binding {
@@ -59,6 +105,7 @@ pub enum MaterialStatement {
         name: String,
         params: Vec<String>,
         body: Vec<MaterialFunctionStatement>,
+        span: Span,
     },
 }
 
@@ -74,6 +121,7 @@ pub enum SdfStatement {
         name: String,
         params: Vec<String>,
         body: Vec<SdfFunctionStatement>,
+        span: Span,
     },
 }
 
@@ -121,10 +169,25 @@ pub enum Statement {
     SdfDef(SdfDef),
     SkeletonDef(SkeletonDef),
     EnvironmentDef(EnvironmentDef),
+    VolumeDef(VolumeDef),
+}
+
+/// An expression and the source it was parsed from, so evaluation errors can point at the
+/// failing expression rather than the statement around it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Self { kind, span }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub enum Expr {
+pub enum ExprKind {
     Number(f64),
     String(String),
     Array(Vec<Expr>),
@@ -154,6 +217,18 @@ pub enum Expr {
         op: UnaryOp,
         expr: Box<Expr>,
     },
+    If {
+        cond: Box<Expr>,
+        then_branch: Box<Expr>,
+        else_branch: Box<Expr>,
+    },
+    /// `for var in start..end { body }`, evaluating `body` once per index into an array.
+    For {
+        var: String,
+        start: Box<Expr>,
+        end: Box<Expr>,
+        body: Box<Expr>,
+    },
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -163,9 +238,32 @@ pub enum BinaryOp {
     Intersect,
     Mul,
     Div,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    pub fn is_comparison(self) -> bool {
+        matches!(
+            self,
+            Self::Less
+                | Self::LessEqual
+                | Self::Greater
+                | Self::GreaterEqual
+                | Self::Equal
+                | Self::NotEqual
+        )
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum UnaryOp {
     Neg,
+    Not,
 }
@@ -0,0 +1,528 @@
+//! Reference text for built-in functions and object types, used by editor tooling.
+//! Keep in sync with `docs/docs/math.md`, `booleans.md`, `objects.md` and `lights.md`.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuiltinFunctionDoc {
+    pub name: &'static str,
+    pub signature: &'static str,
+    pub description: &'static str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuiltinObjectDoc {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub fields: &'static [&'static str],
+}
+
+const fn function(
+    name: &'static str,
+    signature: &'static str,
+    description: &'static str,
+) -> BuiltinFunctionDoc {
+    BuiltinFunctionDoc {
+        name,
+        signature,
+        description,
+    }
+}
+
+const BUILTIN_FUNCTIONS: &[BuiltinFunctionDoc] = &[
+    function(
+        "vec3",
+        "vec3(x) / vec3(x, y, z)",
+        "Builds a vec3; the one-argument form broadcasts the same value to all components.",
+    ),
+    function("dot", "dot(a, b)", "Dot product between two vectors."),
+    function("length", "length(v)", "Vector length."),
+    function(
+        "normalize",
+        "normalize(v)",
+        "Returns a unit-length version of the vector.",
+    ),
+    function(
+        "abs",
+        "abs(x)",
+        "Absolute value for scalars, or per-component absolute value for vec3.",
+    ),
+    function("min", "min(a, b)", "Scalar or per-component minimum."),
+    function("max", "max(a, b)", "Scalar or per-component maximum."),
+    function(
+        "clamp",
+        "clamp(x, a, b)",
+        "Restricts a value or vector to a range.",
+    ),
+    function(
+        "mix",
+        "mix(a, b, t)",
+        "Blends between a and b; 0 gives a, 1 gives b.",
+    ),
+    function(
+        "step",
+        "step(edge, x)",
+        "Returns 0 below the edge and 1 at or above it.",
+    ),
+    function(
+        "smoothstep",
+        "smoothstep(a, b, x)",
+        "Smoothly blends from 0 at a to 1 at b.",
+    ),
+    function("sin", "sin(x)", "Sine."),
+    function("cos", "cos(x)", "Cosine."),
+    function("sqrt", "sqrt(x)", "Square root."),
+    function("floor", "floor(x)", "Rounds down."),
+    function("ceil", "ceil(x)", "Rounds up."),
+    function("fract", "fract(x)", "Fractional part of a value."),
+    function(
+        "value_noise_3d",
+        "value_noise_3d(p[, scale])",
+        "Smooth scalar value noise in 3D; larger scale makes the noise field denser.",
+    ),
+    function(
+        "fbm_3d",
+        "fbm_3d(p, octaves[, scale[, lacunarity]])",
+        "Fractal 3D noise built from repeated value-noise octaves; more octaves add detail, \
+         scale sets the base frequency, and lacunarity controls how quickly frequency increases.",
+    ),
+    function(
+        "box_shell_sdf",
+        "box_shell_sdf(p, half, wall, round)",
+        "Hollow rounded box shell with preserved outer dimensions.",
+    ),
+    function(
+        "cylinder_x_sdf",
+        "cylinder_x_sdf(p, radius, half_len)",
+        "Cylinder SDF aligned to the X axis.",
+    ),
+    function(
+        "cylinder_y_sdf",
+        "cylinder_y_sdf(p, radius, half_len)",
+        "Cylinder SDF aligned to the Y axis.",
+    ),
+    function(
+        "cylinder_z_sdf",
+        "cylinder_z_sdf(p, radius, half_len)",
+        "Cylinder SDF aligned to the Z axis.",
+    ),
+    function(
+        "hole_line_x_sdf",
+        "hole_line_x_sdf(p, radius, half_len, spacing, count)",
+        "Repeated X-aligned cylindrical holes spaced along local Z.",
+    ),
+    function(
+        "hole_line_y_sdf",
+        "hole_line_y_sdf(p, radius, half_len, spacing, count)",
+        "Repeated Y-aligned cylindrical holes spaced along local Z.",
+    ),
+    function(
+        "hole_line_z_sdf",
+        "hole_line_z_sdf(p, radius, half_len, spacing, count)",
+        "Repeated Z-aligned cylindrical holes spaced along local Z.",
+    ),
+    function(
+        "union_all",
+        "union_all(list)",
+        "Unions every object in a (possibly nested) array into one balanced boolean tree.",
+    ),
+    function(
+        "union_round",
+        "a.union_round(b, r)",
+        "Union with a rounded blend of radius r.",
+    ),
+    function(
+        "union_chamfer",
+        "a.union_chamfer(b, r)",
+        "Union with a 45 degree chamfer of width r.",
+    ),
+    function(
+        "union_columns",
+        "a.union_columns(b, r, n)",
+        "Union whose seam is cut into n columns over width r.",
+    ),
+    function(
+        "union_stairs",
+        "a.union_stairs(b, r, n)",
+        "Union whose seam is cut into n steps over width r.",
+    ),
+    function(
+        "union_soft",
+        "a.union_soft(b, r)",
+        "Exponential soft union with blend width r.",
+    ),
+    function(
+        "intersect_round",
+        "a.intersect_round(b, r)",
+        "Intersection with a rounded edge of radius r.",
+    ),
+    function(
+        "intersect_chamfer",
+        "a.intersect_chamfer(b, r)",
+        "Intersection with a chamfered edge of width r.",
+    ),
+    function(
+        "intersect_columns",
+        "a.intersect_columns(b, r, n)",
+        "Intersection whose edge is cut into n columns over width r.",
+    ),
+    function(
+        "intersect_stairs",
+        "a.intersect_stairs(b, r, n)",
+        "Intersection whose edge is cut into n steps over width r.",
+    ),
+    function(
+        "diff_round",
+        "a.diff_round(b, r)",
+        "Difference with a rounded edge of radius r.",
+    ),
+    function(
+        "diff_chamfer",
+        "a.diff_chamfer(b, r)",
+        "Difference with a chamfered edge of width r.",
+    ),
+    function(
+        "diff_columns",
+        "a.diff_columns(b, r, n)",
+        "Difference whose edge is cut into n columns over width r.",
+    ),
+    function(
+        "diff_stairs",
+        "a.diff_stairs(b, r, n)",
+        "Difference whose edge is cut into n steps over width r.",
+    ),
+    function(
+        "pipe",
+        "a.pipe(b, r)",
+        "Keeps only a pipe of radius r along the intersection seam.",
+    ),
+    function(
+        "engrave",
+        "a.engrave(b, r)",
+        "Engraves the shape of b into a with depth r.",
+    ),
+    function(
+        "groove",
+        "a.groove(b, ra, rb)",
+        "Cuts a groove of width ra and depth rb where b meets a.",
+    ),
+    function(
+        "tongue",
+        "a.tongue(b, ra, rb)",
+        "Adds a tongue of width ra and height rb where b meets a.",
+    ),
+    function(
+        "attach",
+        "attach(other, Top|Bottom|Left|Right|Front|Back[, gap])",
+        "Places the object against an anchor of other.",
+    ),
+    function(
+        "face_to",
+        "face_to(other[, Anchor])",
+        "Rotates the object to face other.",
+    ),
+    function(
+        "repeat_x",
+        "repeat_x(spacing, count)",
+        "Repeats the object count times along X.",
+    ),
+    function(
+        "repeat_y",
+        "repeat_y(spacing, count)",
+        "Repeats the object count times along Y.",
+    ),
+    function(
+        "repeat_z",
+        "repeat_z(spacing, count)",
+        "Repeats the object count times along Z.",
+    ),
+    function(
+        "mirror_x",
+        "mirror_x()",
+        "Mirrors the object across the YZ plane.",
+    ),
+    function(
+        "mirror_y",
+        "mirror_y()",
+        "Mirrors the object across the XZ plane.",
+    ),
+    function(
+        "mirror_z",
+        "mirror_z()",
+        "Mirrors the object across the XY plane.",
+    ),
+    function(
+        "slice_x",
+        "slice_x(min, max)",
+        "Keeps the part of the object between min and max on X.",
+    ),
+    function(
+        "slice_y",
+        "slice_y(min, max)",
+        "Keeps the part of the object between min and max on Y.",
+    ),
+    function(
+        "slice_z",
+        "slice_z(min, max)",
+        "Keeps the part of the object between min and max on Z.",
+    ),
+    function(
+        "noise",
+        "noise(octaves[, scale[, lacunarity]])",
+        "Displaces the object surface with fractal noise.",
+    ),
+];
+
+const BUILTIN_OBJECTS: &[BuiltinObjectDoc] = &[
+    BuiltinObjectDoc {
+        name: "Sphere",
+        description: "Sphere primitive.",
+        fields: &["radius", "shell", "pos", "rot", "material"],
+    },
+    BuiltinObjectDoc {
+        name: "Box",
+        description: "Box primitive with optional rounding and shell.",
+        fields: &["size", "round", "shell", "pos", "rot", "material"],
+    },
+    BuiltinObjectDoc {
+        name: "Cylinder",
+        description: "Cylinder primitive aligned to Y.",
+        fields: &[
+            "radius", "height", "round", "shell", "pos", "rot", "material",
+        ],
+    },
+    BuiltinObjectDoc {
+        name: "Torus",
+        description: "Torus primitive lying in the XZ plane.",
+        fields: &["major_radius", "minor_radius", "pos", "rot", "material"],
+    },
+    BuiltinObjectDoc {
+        name: "ExtrudePolygon",
+        description: "Regular N-gon extruded along Y.",
+        fields: &[
+            "sides", "radius", "height", "round", "shell", "pos", "rot", "material",
+        ],
+    },
+    BuiltinObjectDoc {
+        name: "Room",
+        description: "Floor plus optional walls and ceiling with separate material slots.",
+        fields: &[
+            "width",
+            "height",
+            "depth",
+            "wall_thickness",
+            "floor_material",
+            "wall_material",
+            "back_wall_material",
+            "front_wall_material",
+            "left_wall_material",
+            "right_wall_material",
+            "ceiling_material",
+            "show_floor",
+            "show_back_wall",
+            "show_front_wall",
+            "show_left_wall",
+            "show_right_wall",
+            "show_ceiling",
+            "pos",
+            "rot",
+        ],
+    },
+    BuiltinObjectDoc {
+        name: "Lambert",
+        description: "Diffuse material.",
+        fields: &["color"],
+    },
+    BuiltinObjectDoc {
+        name: "Metal",
+        description: "Conductor material.",
+        fields: &["color", "roughness", "conductor"],
+    },
+    BuiltinObjectDoc {
+        name: "Dielectric",
+        description: "Glass-like transmissive material.",
+        fields: &[
+            "color",
+            "ior",
+            "abbe",
+            "cauchy_b",
+            "roughness",
+            "thin_walled",
+        ],
+    },
+    BuiltinObjectDoc {
+        name: "OpenPbr",
+        description: "Layered OpenPBR surface with coat, fuzz, thin film and anisotropic specular.",
+        fields: &[
+            "base_color",
+            "base_metalness",
+            "specular_weight",
+            "specular_color",
+            "specular_roughness",
+            "specular_ior",
+            "specular_anisotropy",
+            "specular_rotation",
+            "transmission_weight",
+            "coat_weight",
+            "coat_color",
+            "coat_roughness",
+            "coat_ior",
+            "fuzz_weight",
+            "fuzz_color",
+            "fuzz_roughness",
+            "thin_film_weight",
+            "thin_film_thickness",
+            "thin_film_ior",
+            "thin_walled",
+        ],
+    },
+    BuiltinObjectDoc {
+        name: "Camera",
+        description: "Pinhole camera; an aperture or f-stop turns it into a thin lens with depth of field, and `projection` selects ortho, equirect, or cubemap views.",
+        fields: &[
+            "origin",
+            "target",
+            "fov_y",
+            "projection",
+            "ortho_height",
+            "stereo",
+            "eye_separation",
+            "aperture",
+            "f_stop",
+            "focus",
+            "focus_distance",
+            "blades",
+            "blade_rotation",
+            "origin_open",
+            "origin_close",
+            "target_open",
+            "target_close",
+            "motion",
+        ],
+    },
+    BuiltinObjectDoc {
+        name: "PointLight",
+        description: "Infinitely small point light.",
+        fields: &["position", "color", "intensity"],
+    },
+    BuiltinObjectDoc {
+        name: "SphereLight",
+        description: "Spherical area light with soft shadows.",
+        fields: &["position", "radius", "color", "intensity", "samples"],
+    },
+    BuiltinObjectDoc {
+        name: "SpotLight",
+        description: "Point light limited to a cone with a soft edge.",
+        fields: &[
+            "position",
+            "direction",
+            "target",
+            "angle",
+            "falloff",
+            "color",
+            "intensity",
+        ],
+    },
+    BuiltinObjectDoc {
+        name: "DirectionalLight",
+        description: "Distant sun-like light; its angular diameter softens shadows.",
+        fields: &["direction", "angle", "color", "intensity", "samples"],
+    },
+    BuiltinObjectDoc {
+        name: "RectLight",
+        description: "Rectangular area light, such as a window or ceiling panel.",
+        fields: &[
+            "position",
+            "normal",
+            "target",
+            "width",
+            "height",
+            "color",
+            "intensity",
+            "two_sided",
+            "spread",
+            "samples",
+        ],
+    },
+    BuiltinObjectDoc {
+        name: "DiskLight",
+        description: "Round area light.",
+        fields: &[
+            "position",
+            "normal",
+            "target",
+            "radius",
+            "color",
+            "intensity",
+            "two_sided",
+            "spread",
+            "samples",
+        ],
+    },
+    BuiltinObjectDoc {
+        name: "EnvLight",
+        description: "Uniform environment light.",
+        fields: &["color", "intensity"],
+    },
+    BuiltinObjectDoc {
+        name: "HdriEnvironment",
+        description: "Image-based lighting from an .hdr or .exr latitude-longitude map.",
+        fields: &["path", "rotation", "intensity"],
+    },
+    BuiltinObjectDoc {
+        name: "Fog",
+        description: "Homogeneous scattering haze filling the scene, optionally below a height.",
+        fields: &["density", "color", "anisotropy", "height"],
+    },
+    BuiltinObjectDoc {
+        name: "Volume",
+        description: "Uniform participating medium for an object's `volume` field.",
+        fields: &["density", "color", "anisotropy"],
+    },
+    BuiltinObjectDoc {
+        name: "RenderSettings",
+        description: "Scene-level render settings; CLI flags override them.",
+        fields: &[
+            "width",
+            "height",
+            "max_steps",
+            "max_dist",
+            "epsilon",
+            "step_scale",
+            "accel",
+            "spp",
+            "bounces",
+            "min_spp",
+            "noise_threshold",
+            "denoise",
+            "spectral",
+            "time_limit",
+            "target_noise",
+            "exposure",
+            "tonemap",
+            "dither",
+            "shutter_open",
+            "shutter_close",
+            "env_resolution",
+            "env_samples",
+        ],
+    },
+];
+
+#[must_use]
+pub fn builtin_function_docs() -> &'static [BuiltinFunctionDoc] {
+    BUILTIN_FUNCTIONS
+}
+
+#[must_use]
+pub fn builtin_function_doc(name: &str) -> Option<&'static BuiltinFunctionDoc> {
+    BUILTIN_FUNCTIONS.iter().find(|doc| doc.name == name)
+}
+
+#[must_use]
+pub fn builtin_object_docs() -> &'static [BuiltinObjectDoc] {
+    BUILTIN_OBJECTS
+}
+
+#[must_use]
+pub fn builtin_object_doc(name: &str) -> Option<&'static BuiltinObjectDoc> {
+    BUILTIN_OBJECTS.iter().find(|doc| doc.name == name)
+}
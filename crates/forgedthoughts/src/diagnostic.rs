@@ -0,0 +1,115 @@
+use std::fmt;
+
+use crate::ast::{FileId, Span};
+use crate::hash::Fnv1a;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceFile {
+    pub name: String,
+    pub source: String,
+}
+
+/// Sources loaded for one program, indexed by [`FileId`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn add(&mut self, name: impl Into<String>, source: impl Into<String>) -> FileId {
+        self.files.push(SourceFile {
+            name: name.into(),
+            source: source.into(),
+        });
+        FileId(self.files.len() - 1)
+    }
+
+    #[must_use]
+    pub fn file(&self, id: FileId) -> Option<&SourceFile> {
+        self.files.get(id.0)
+    }
+
+    /// FNV-1a hash of every file's name and source, in load order.
+    #[must_use]
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = Fnv1a::new();
+        for file in &self.files {
+            hasher.write_str(&file.name);
+            hasher.write_str(&file.source);
+        }
+        hasher.finish()
+    }
+
+    /// Builds a diagnostic pointing at `span`, or `None` if its file is unknown.
+    #[must_use]
+    pub fn diagnostic(&self, span: Span, message: impl Into<String>) -> Option<Diagnostic> {
+        let file = self.file(span.file)?;
+        Some(Diagnostic::new(file, span.start, span.end, message))
+    }
+}
+
+/// An error located in a source file, rendered as `file:line:col: message` followed by
+/// the offending line and a caret.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub file: String,
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based column, counted in characters.
+    pub column: usize,
+    pub line_text: String,
+    /// Number of characters to underline, at least 1.
+    pub width: usize,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// Locates the byte range `start..end` in `file`. Offsets past the end point at the
+    /// end of the source.
+    #[must_use]
+    pub fn new(file: &SourceFile, start: usize, end: usize, message: impl Into<String>) -> Self {
+        let source = file.source.as_str();
+        let start = floor_char_boundary(source, start);
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |i| start + i);
+        let line_text = source[line_start..line_end].trim_end_matches('\r');
+        let end = floor_char_boundary(source, end.clamp(start, line_end));
+        Self {
+            file: file.name.clone(),
+            line: source[..start].matches('\n').count() + 1,
+            column: source[line_start..start].chars().count() + 1,
+            line_text: line_text.to_string(),
+            width: source[start..end].chars().count().max(1),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let gutter = " ".repeat(self.line.to_string().len());
+        writeln!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.line, self.column, self.message
+        )?;
+        writeln!(f, "{gutter} |")?;
+        writeln!(f, "{} | {}", self.line, self.line_text)?;
+        write!(
+            f,
+            "{gutter} | {}{}",
+            " ".repeat(self.column - 1),
+            "^".repeat(self.width)
+        )
+    }
+}
+
+fn floor_char_boundary(source: &str, offset: usize) -> usize {
+    let mut offset = offset.min(source.len());
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
@@ -1,20 +1,22 @@
 use std::{
     collections::{HashMap, HashSet},
     env,
+    path::PathBuf,
     sync::{Mutex, OnceLock},
 };
 
 use thiserror::Error;
 
 use crate::ast::{
-    BinaryOp, EnvironmentDef, Expr, FunctionDef, MaterialDef, MaterialFunctionStatement,
-    MaterialStatement, Program, SdfDef, SdfFunctionStatement, SdfStatement, SkeletonDef,
-    SkeletonStatement, Statement, UnaryOp,
+    BinaryOp, EnvironmentDef, Expr, ExprKind, FunctionDef, MAX_LOOP_ITERATIONS, MaterialDef,
+    MaterialFunctionStatement, MaterialStatement, Program, SdfDef, SdfFunctionStatement,
+    SdfStatement, SkeletonDef, SkeletonStatement, Span, Statement, UnaryOp, VolumeDef,
 };
 use crate::jit::{
     JitCapture, JitFunction, JitModifierDistanceFunction, JitSdfDistanceFunction,
     JitSdfVec3Function, JitVec3Function, compile_jit_function, compile_material_vec3_function,
-    compile_modifier_distance_function, compile_sdf_distance_function, compile_sdf_vec3_function,
+    compile_modifier_distance_function, compile_sdf_distance_function, compile_sdf_scalar_function,
+    compile_sdf_vec3_function,
 };
 use crate::vm::{VmFunction, VmInstruction, compile_function};
 
@@ -85,6 +87,12 @@ pub struct EvalState {
     pub sdf_defs: HashMap<String, SdfDef>,
     pub skeleton_defs: HashMap<String, SkeletonDef>,
     pub environment_defs: HashMap<String, EnvironmentDef>,
+    pub volume_defs: HashMap<String, VolumeDef>,
+    /// Native `density(p)` of each volume definition that fits the JIT subset.
+    pub jitted_volume_density_functions: HashMap<String, JitSdfDistanceFunction>,
+    /// Directory of the scene file; relative asset paths such as environment maps resolve
+    /// against it.
+    pub base_dir: Option<PathBuf>,
 }
 
 #[derive(Debug, Error)]
@@ -111,14 +119,54 @@ pub enum EvalError {
     BuiltinNumericOrVec3Args(&'static str),
     #[error("builtin {0} expects vec3 args")]
     BuiltinVec3Args(&'static str),
+    #[error("builtin {0} expects a non-empty array of objects")]
+    BuiltinObjectArrayArgs(&'static str),
     #[error("unary operation requires numeric or vec3 operand")]
     UnaryTypeMismatch,
     #[error("binary operation requires numeric operands")]
     BinaryTypeMismatch,
+    #[error("condition requires a numeric operand")]
+    ConditionTypeMismatch,
     #[error("material call depth exceeded")]
     MaterialCallDepthExceeded,
     #[error("layout operation requires bounded object values")]
     UnsupportedLayoutObject,
+    /// An error raised while evaluating the expression or statement at `span`.
+    #[error("{error}")]
+    At { span: Span, error: Box<EvalError> },
+}
+
+impl EvalError {
+    /// Location of the expression or statement that failed, when known.
+    #[must_use]
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Self::At { span, .. } => Some(*span),
+            _ => None,
+        }
+    }
+
+    /// The underlying error without its location.
+    #[must_use]
+    pub fn kind(&self) -> &EvalError {
+        match self {
+            Self::At { error, .. } => error.kind(),
+            other => other,
+        }
+    }
+
+    /// Locates the error at `span` unless it already has a location, so the innermost
+    /// expression that failed is the one reported.
+    #[must_use]
+    pub fn at(self, span: Span) -> Self {
+        match self {
+            Self::At { .. } => self,
+            error => Self::At {
+                span,
+                error: Box::new(error),
+            },
+        }
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -136,29 +184,65 @@ struct SdfRuntime<'a> {
 }
 
 pub fn eval_program(program: &Program) -> Result<EvalState, EvalError> {
-    let mut state = EvalState {
-        bindings: HashMap::new(),
-        function_defs: HashMap::new(),
-        compiled_functions: HashMap::new(),
-        jitted_functions: HashMap::new(),
-        compiled_material_functions: HashMap::new(),
-        jitted_material_functions: HashMap::new(),
-        jitted_material_vec3_functions: HashMap::new(),
-        jitted_sdf_distance_functions: HashMap::new(),
-        jitted_sdf_vec3_functions: HashMap::new(),
-        jitted_sdf_functions: HashMap::new(),
-        compiled_sdf_functions: HashMap::new(),
-        material_defs: HashMap::new(),
-        sdf_defs: HashMap::new(),
-        skeleton_defs: HashMap::new(),
-        environment_defs: HashMap::new(),
-    };
+    let mut state = EvalState::empty();
+    for (index, stmt) in program.statements.iter().enumerate() {
+        eval_statement(stmt, &mut state).map_err(|error| locate_error(program, index, error))?;
+    }
+    Ok(state)
+}
 
-    for stmt in &program.statements {
-        eval_statement(stmt, &mut state)?;
+/// Evaluates every statement even after failures and returns all errors. A failed
+/// `let` or `var` leaves its name unbound, so later "undefined identifier" errors for
+/// that name are dropped rather than reported again.
+pub fn eval_program_collecting(program: &Program) -> (EvalState, Vec<EvalError>) {
+    let mut state = EvalState::empty();
+    let mut errors = Vec::new();
+    let mut failed = HashSet::new();
+    for (index, stmt) in program.statements.iter().enumerate() {
+        let Err(error) = eval_statement(stmt, &mut state) else {
+            continue;
+        };
+        if let Statement::Binding { name, .. } = stmt {
+            failed.insert(name.clone());
+        }
+        if matches!(error.kind(), EvalError::UndefinedIdentifier(name) if failed.contains(name)) {
+            continue;
+        }
+        errors.push(locate_error(program, index, error));
     }
+    (state, errors)
+}
 
-    Ok(state)
+fn locate_error(program: &Program, index: usize, error: EvalError) -> EvalError {
+    match program.spans.get(index) {
+        Some(span) => error.at(*span),
+        None => error,
+    }
+}
+
+impl EvalState {
+    fn empty() -> Self {
+        Self {
+            bindings: HashMap::new(),
+            function_defs: HashMap::new(),
+            compiled_functions: HashMap::new(),
+            jitted_functions: HashMap::new(),
+            compiled_material_functions: HashMap::new(),
+            jitted_material_functions: HashMap::new(),
+            jitted_material_vec3_functions: HashMap::new(),
+            jitted_sdf_distance_functions: HashMap::new(),
+            jitted_sdf_vec3_functions: HashMap::new(),
+            jitted_sdf_functions: HashMap::new(),
+            compiled_sdf_functions: HashMap::new(),
+            material_defs: HashMap::new(),
+            sdf_defs: HashMap::new(),
+            skeleton_defs: HashMap::new(),
+            environment_defs: HashMap::new(),
+            volume_defs: HashMap::new(),
+            jitted_volume_density_functions: HashMap::new(),
+            base_dir: None,
+        }
+    }
 }
 
 fn jit_enabled() -> bool {
@@ -229,7 +313,9 @@ fn eval_statement(stmt: &Statement, state: &mut EvalState) -> Result<(), EvalErr
             let mut jitted = HashMap::new();
             let mut jitted_vec3 = HashMap::new();
             for stmt in &def.statements {
-                if let MaterialStatement::Function { name, params, body } = stmt
+                if let MaterialStatement::Function {
+                    name, params, body, ..
+                } = stmt
                     && let Some(vm) = crate::vm::compile_function_parts(params, body)
                 {
                     if jit_enabled()
@@ -240,7 +326,7 @@ fn eval_statement(stmt: &Statement, state: &mut EvalState) -> Result<(), EvalErr
                         let capture_names = collect_vm_capture_names(&rewritten_vm);
                         let jit_vm = VmFunction {
                             params: capture_names.clone(),
-                            code: rewritten_vm.code.clone(),
+                            ..rewritten_vm
                         };
                         if let Some(compiled_jit) =
                             compile_jit_function(&format!("{}_{}", def.name, name), &jit_vm)
@@ -287,7 +373,9 @@ fn eval_statement(stmt: &Statement, state: &mut EvalState) -> Result<(), EvalErr
             let mut jitted = HashMap::new();
             let mut jitted_vec3 = HashMap::new();
             for stmt in &def.statements {
-                if let SdfStatement::Function { name, params, body } = stmt
+                if let SdfStatement::Function {
+                    name, params, body, ..
+                } = stmt
                     && let Some(vm) = crate::vm::compile_function_parts(params, body)
                 {
                     if jit_enabled()
@@ -328,6 +416,18 @@ fn eval_statement(stmt: &Statement, state: &mut EvalState) -> Result<(), EvalErr
             state.environment_defs.insert(def.name.clone(), def.clone());
             Ok(())
         }
+        Statement::VolumeDef(def) => {
+            state.volume_defs.insert(def.name.clone(), def.clone());
+            if jit_enabled()
+                && let Some(density_jit) =
+                    compile_sdf_scalar_function(&volume_sdf_def(def), "density")
+            {
+                state
+                    .jitted_volume_density_functions
+                    .insert(def.name.clone(), density_jit);
+            }
+            Ok(())
+        }
     }
 }
 
@@ -420,6 +520,7 @@ fn eval_expr(expr: &Expr, state: &EvalState) -> Result<Value, EvalError> {
     eval_expr_in_material_scope(expr, state, &HashMap::new(), None, 0)
 }
 
+/// Evaluates `expr`, locating a failure at the innermost expression that raised it.
 fn eval_expr_in_material_scope(
     expr: &Expr,
     state: &EvalState,
@@ -427,10 +528,21 @@ fn eval_expr_in_material_scope(
     material_runtime: Option<MaterialRuntime<'_>>,
     top_level_depth: usize,
 ) -> Result<Value, EvalError> {
-    match expr {
-        Expr::Number(n) => Ok(Value::Number(*n as f32)),
-        Expr::String(value) => Ok(Value::String(value.clone())),
-        Expr::Array(items) => {
+    eval_expr_kind_in_material_scope(expr, state, locals, material_runtime, top_level_depth)
+        .map_err(|error| error.at(expr.span))
+}
+
+fn eval_expr_kind_in_material_scope(
+    expr: &Expr,
+    state: &EvalState,
+    locals: &HashMap<String, Value>,
+    material_runtime: Option<MaterialRuntime<'_>>,
+    top_level_depth: usize,
+) -> Result<Value, EvalError> {
+    match &expr.kind {
+        ExprKind::Number(n) => Ok(Value::Number(*n as f32)),
+        ExprKind::String(value) => Ok(Value::String(value.clone())),
+        ExprKind::Array(items) => {
             let mut values = Vec::with_capacity(items.len());
             for item in items {
                 values.push(eval_expr_in_material_scope(
@@ -443,7 +555,7 @@ fn eval_expr_in_material_scope(
             }
             Ok(Value::Array(values))
         }
-        Expr::FunctionLiteral { params, body } => Ok(Value::Function(FunctionValue {
+        ExprKind::FunctionLiteral { params, body } => Ok(Value::Function(FunctionValue {
             params: params.clone(),
             body: body.clone(),
             captures: locals.clone(),
@@ -451,7 +563,7 @@ fn eval_expr_in_material_scope(
             jitted_vec3: compile_function_literal_vec3(params, body, locals),
             jitted_distance_post: compile_function_literal_distance_post(params, body, locals),
         })),
-        Expr::Ident(name) => {
+        ExprKind::Ident(name) => {
             if let Some(value) = locals.get(name) {
                 return Ok(value.clone());
             }
@@ -464,7 +576,7 @@ fn eval_expr_in_material_scope(
                 .ok_or_else(|| EvalError::UndefinedIdentifier(name.clone()))?;
             Ok(binding.value.clone())
         }
-        Expr::ObjectLiteral { type_name, fields } => {
+        ExprKind::ObjectLiteral { type_name, fields } => {
             let mut resolved_fields = HashMap::new();
             for (name, field_expr) in fields {
                 resolved_fields.insert(
@@ -484,14 +596,21 @@ fn eval_expr_in_material_scope(
                 fields: resolved_fields,
             }))
         }
-        Expr::Binary { lhs, op, rhs } => {
+        ExprKind::Binary {
+            lhs,
+            op: op @ (BinaryOp::And | BinaryOp::Or),
+            rhs,
+        } => eval_logical(*op, lhs, rhs, |expr| {
+            eval_expr_in_material_scope(expr, state, locals, material_runtime, top_level_depth)
+        }),
+        ExprKind::Binary { lhs, op, rhs } => {
             let left =
                 eval_expr_in_material_scope(lhs, state, locals, material_runtime, top_level_depth)?;
             let right =
                 eval_expr_in_material_scope(rhs, state, locals, material_runtime, top_level_depth)?;
             eval_binary(left, *op, right)
         }
-        Expr::Member { target, field } => {
+        ExprKind::Member { target, field } => {
             if let Some(name) = flatten_member_expr(expr)
                 && let Some(binding) = state.bindings.get(&name)
             {
@@ -513,7 +632,7 @@ fn eval_expr_in_material_scope(
                 .cloned()
                 .ok_or(EvalError::UndefinedIdentifier(field.clone()))
         }
-        Expr::Call { callee, args } => eval_call(
+        ExprKind::Call { callee, args } => eval_call(
             callee,
             args,
             state,
@@ -521,7 +640,7 @@ fn eval_expr_in_material_scope(
             material_runtime,
             top_level_depth,
         ),
-        Expr::Unary { op, expr } => {
+        ExprKind::Unary { op, expr } => {
             let value = eval_expr_in_material_scope(
                 expr,
                 state,
@@ -531,13 +650,40 @@ fn eval_expr_in_material_scope(
             )?;
             eval_unary(*op, value)
         }
+        ExprKind::If {
+            cond,
+            then_branch,
+            else_branch,
+        } => {
+            let cond = eval_expr_in_material_scope(
+                cond,
+                state,
+                locals,
+                material_runtime,
+                top_level_depth,
+            )?;
+            let branch = if is_truthy(&cond)? {
+                then_branch
+            } else {
+                else_branch
+            };
+            eval_expr_in_material_scope(branch, state, locals, material_runtime, top_level_depth)
+        }
+        ExprKind::For {
+            var,
+            start,
+            end,
+            body,
+        } => eval_for_expr(var, start, end, body, locals, |expr, locals| {
+            eval_expr_in_material_scope(expr, state, locals, material_runtime, top_level_depth)
+        }),
     }
 }
 
 fn flatten_member_expr(expr: &Expr) -> Option<String> {
-    match expr {
-        Expr::Ident(name) => Some(name.clone()),
-        Expr::Member { target, field } => {
+    match &expr.kind {
+        ExprKind::Ident(name) => Some(name.clone()),
+        ExprKind::Member { target, field } => {
             Some(format!("{}.{}", flatten_member_expr(target)?, field))
         }
         _ => None,
@@ -1233,22 +1379,38 @@ fn eval_binary(lhs: Value, op: BinaryOp, rhs: Value) -> Result<Value, EvalError>
             BinaryOp::Intersect => return Err(EvalError::BinaryTypeMismatch),
             BinaryOp::Mul => left * right,
             BinaryOp::Div => left / right,
+            BinaryOp::And => bool_number(*left != 0.0 && *right != 0.0),
+            BinaryOp::Or => bool_number(*left != 0.0 || *right != 0.0),
+            _ => compare_numbers(*left, op, *right),
         };
         return Ok(Value::Number(out));
     }
 
-    if matches!(
+    if let (Value::String(left), Value::String(right)) = (&lhs, &rhs) {
+        return match op {
+            BinaryOp::Equal => Ok(Value::Number(bool_number(left == right))),
+            BinaryOp::NotEqual => Ok(Value::Number(bool_number(left != right))),
+            _ => Err(EvalError::BinaryTypeMismatch),
+        };
+    }
+
+    if (matches!(
         op,
         BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div
-    ) && let (Some(left), Some(right)) =
-        (as_broadcastable_vec3(&lhs), as_broadcastable_vec3(&rhs))
+    ) || op.is_comparison())
+        && let (Some(left), Some(right)) =
+            (as_broadcastable_vec3(&lhs), as_broadcastable_vec3(&rhs))
     {
         let out = match op {
             BinaryOp::Add => [left[0] + right[0], left[1] + right[1], left[2] + right[2]],
             BinaryOp::Sub => [left[0] - right[0], left[1] - right[1], left[2] - right[2]],
             BinaryOp::Mul => [left[0] * right[0], left[1] * right[1], left[2] * right[2]],
             BinaryOp::Div => [left[0] / right[0], left[1] / right[1], left[2] / right[2]],
-            BinaryOp::Intersect => return Err(EvalError::BinaryTypeMismatch),
+            _ => [
+                compare_numbers(left[0], op, right[0]),
+                compare_numbers(left[1], op, right[1]),
+                compare_numbers(left[2], op, right[2]),
+            ],
         };
         return Ok(vec3_value(out));
     }
@@ -1278,7 +1440,7 @@ fn eval_binary(lhs: Value, op: BinaryOp, rhs: Value) -> Result<Value, EvalError>
                 BinaryOp::Add => "add",
                 BinaryOp::Sub => "sub",
                 BinaryOp::Intersect => "intersect",
-                BinaryOp::Mul | BinaryOp::Div => unreachable!(),
+                _ => unreachable!(),
             };
             let mut fields = HashMap::new();
             fields.insert("lhs".to_string(), lhs);
@@ -1288,8 +1450,46 @@ fn eval_binary(lhs: Value, op: BinaryOp, rhs: Value) -> Result<Value, EvalError>
                 fields,
             }))
         }
-        BinaryOp::Mul | BinaryOp::Div => Err(EvalError::BinaryTypeMismatch),
+        _ => Err(EvalError::BinaryTypeMismatch),
+    }
+}
+
+fn compare_numbers(left: f32, op: BinaryOp, right: f32) -> f32 {
+    bool_number(match op {
+        BinaryOp::Less => left < right,
+        BinaryOp::LessEqual => left <= right,
+        BinaryOp::Greater => left > right,
+        BinaryOp::GreaterEqual => left >= right,
+        BinaryOp::Equal => left == right,
+        BinaryOp::NotEqual => left != right,
+        _ => unreachable!(),
+    })
+}
+
+fn bool_number(value: bool) -> f32 {
+    if value { 1.0 } else { 0.0 }
+}
+
+fn is_truthy(value: &Value) -> Result<bool, EvalError> {
+    match value {
+        Value::Number(v) => Ok(*v != 0.0),
+        _ => Err(EvalError::ConditionTypeMismatch),
+    }
+}
+
+/// Short-circuiting `&&` / `||`; the right operand is only evaluated when it decides the result.
+fn eval_logical(
+    op: BinaryOp,
+    lhs: &Expr,
+    rhs: &Expr,
+    mut eval: impl FnMut(&Expr) -> Result<Value, EvalError>,
+) -> Result<Value, EvalError> {
+    let left = is_truthy(&eval(lhs)?)?;
+    if left == (op == BinaryOp::Or) {
+        return Ok(Value::Number(bool_number(left)));
     }
+    let right = is_truthy(&eval(rhs)?)?;
+    Ok(Value::Number(bool_number(right)))
 }
 
 fn build_sdf_member_value(
@@ -1753,6 +1953,13 @@ fn eval_unary(op: UnaryOp, value: Value) -> Result<Value, EvalError> {
                 Ok(vec3_value([-v[0], -v[1], -v[2]]))
             }
         },
+        UnaryOp::Not => match value {
+            Value::Number(v) => Ok(Value::Number(bool_number(v == 0.0))),
+            _ => {
+                let v = as_vec3(&value).ok_or(EvalError::UnaryTypeMismatch)?;
+                Ok(vec3_value(v.map(|c| bool_number(c == 0.0))))
+            }
+        },
     }
 }
 
@@ -1764,7 +1971,7 @@ fn eval_call(
     material_runtime: Option<MaterialRuntime<'_>>,
     top_level_depth: usize,
 ) -> Result<Value, EvalError> {
-    if !matches!(callee, Expr::Ident(_) | Expr::Member { .. }) {
+    if !matches!(callee.kind, ExprKind::Ident(_) | ExprKind::Member { .. }) {
         let callee_value =
             eval_expr_in_material_scope(callee, state, locals, material_runtime, top_level_depth)?;
         let arg_values = eval_arg_values(args, state, locals, material_runtime, top_level_depth)?;
@@ -1774,8 +1981,8 @@ fn eval_call(
         return Err(EvalError::UnsupportedCall);
     }
 
-    match callee {
-        Expr::Ident(name) => {
+    match &callee.kind {
+        ExprKind::Ident(name) => {
             let arg_values =
                 eval_arg_values(args, state, locals, material_runtime, top_level_depth)?;
             if let Some(value) = eval_ident_call(name, &arg_values)? {
@@ -1788,6 +1995,7 @@ fn eval_call(
                             name: fn_name,
                             params,
                             body,
+                            ..
                         } if fn_name == name => Some((params.clone(), body.clone())),
                         _ => None,
                     })
@@ -1816,8 +2024,8 @@ fn eval_call(
             }
             Err(EvalError::UnsupportedCall)
         }
-        Expr::Member { target, field } => {
-            if let Expr::Ident(type_name) = target.as_ref() {
+        ExprKind::Member { target, field } => {
+            if let ExprKind::Ident(type_name) = &target.kind {
                 let arg_values =
                     eval_arg_values(args, state, locals, material_runtime, top_level_depth)?;
                 if let Some(value) = eval_primitive_static_call(type_name, field, &arg_values)? {
@@ -2041,108 +2249,111 @@ pub fn eval_function_value(
 
     if let Some(compiled) = &function.compiled {
         let mut stack = Vec::new();
-        for instruction in &compiled.code {
-            match instruction {
-                VmInstruction::PushNumber(v) => stack.push(Value::Number(*v)),
-                VmInstruction::PushString(v) => stack.push(Value::String(v.clone())),
-                VmInstruction::LoadName(name) => {
-                    if let Some(value) = locals.get(name) {
-                        stack.push(value.clone());
-                    } else if let Some(value) = builtin_symbol_value(name) {
-                        stack.push(value);
-                    } else if let Some(binding) = state.bindings.get(name) {
-                        stack.push(binding.value.clone());
-                    } else {
-                        return Err(EvalError::UndefinedIdentifier(name.clone()));
+        let mut pc = 0;
+        let mut run = || -> Result<Value, EvalError> {
+            while let Some(instruction) = compiled.code.get(pc) {
+                pc += 1;
+                match instruction {
+                    VmInstruction::PushNumber(v) => stack.push(Value::Number(*v)),
+                    VmInstruction::PushString(v) => stack.push(Value::String(v.clone())),
+                    VmInstruction::LoadName(name) => {
+                        if let Some(value) = locals.get(name) {
+                            stack.push(value.clone());
+                        } else if let Some(value) = builtin_symbol_value(name) {
+                            stack.push(value);
+                        } else if let Some(binding) = state.bindings.get(name) {
+                            stack.push(binding.value.clone());
+                        } else {
+                            return Err(EvalError::UndefinedIdentifier(name.clone()));
+                        }
                     }
-                }
-                VmInstruction::BuildArray(count) => {
-                    if stack.len() < *count {
-                        return Err(EvalError::UnsupportedCall);
+                    VmInstruction::BuildArray(count) => {
+                        if stack.len() < *count {
+                            return Err(EvalError::UnsupportedCall);
+                        }
+                        let start = stack.len() - *count;
+                        let values = stack.drain(start..).collect::<Vec<_>>();
+                        stack.push(Value::Array(values));
                     }
-                    let start = stack.len() - *count;
-                    let values = stack.drain(start..).collect::<Vec<_>>();
-                    stack.push(Value::Array(values));
-                }
-                VmInstruction::BuildObject {
-                    type_name,
-                    field_names,
-                } => {
-                    if stack.len() < field_names.len() {
-                        return Err(EvalError::UnsupportedCall);
+                    VmInstruction::BuildObject {
+                        type_name,
+                        field_names,
+                    } => {
+                        if stack.len() < field_names.len() {
+                            return Err(EvalError::UnsupportedCall);
+                        }
+                        let start = stack.len() - field_names.len();
+                        let values = stack.drain(start..).collect::<Vec<_>>();
+                        let mut fields = HashMap::new();
+                        for (name, value) in field_names.iter().cloned().zip(values.into_iter()) {
+                            fields.insert(name, value);
+                        }
+                        augment_object_literal_fields(state, type_name, &mut fields)?;
+                        stack.push(Value::Object(ObjectValue {
+                            type_name: Some(type_name.clone()),
+                            fields,
+                        }));
                     }
-                    let start = stack.len() - field_names.len();
-                    let values = stack.drain(start..).collect::<Vec<_>>();
-                    let mut fields = HashMap::new();
-                    for (name, value) in field_names.iter().cloned().zip(values.into_iter()) {
-                        fields.insert(name, value);
+                    VmInstruction::LoadMember(field) => {
+                        let value = stack.pop().ok_or(EvalError::UnsupportedCall)?;
+                        let obj = as_object(&value)?;
+                        let member = obj
+                            .fields
+                            .get(field)
+                            .cloned()
+                            .ok_or_else(|| EvalError::UndefinedIdentifier(field.clone()))?;
+                        stack.push(member);
                     }
-                    augment_object_literal_fields(state, type_name, &mut fields)?;
-                    stack.push(Value::Object(ObjectValue {
-                        type_name: Some(type_name.clone()),
-                        fields,
-                    }));
-                }
-                VmInstruction::LoadMember(field) => {
-                    let value = stack.pop().ok_or(EvalError::UnsupportedCall)?;
-                    let obj = as_object(&value)?;
-                    let member = obj
-                        .fields
-                        .get(field)
-                        .cloned()
-                        .ok_or_else(|| EvalError::UndefinedIdentifier(field.clone()))?;
-                    stack.push(member);
-                }
-                VmInstruction::Unary(op) => {
-                    let value = stack.pop().ok_or(EvalError::UnsupportedCall)?;
-                    stack.push(eval_unary(*op, value)?);
-                }
-                VmInstruction::Binary(op) => {
-                    let rhs = stack.pop().ok_or(EvalError::UnsupportedCall)?;
-                    let lhs = stack.pop().ok_or(EvalError::UnsupportedCall)?;
-                    stack.push(eval_binary(lhs, *op, rhs)?);
-                }
-                VmInstruction::CallNamed { name, argc } => {
-                    if stack.len() < *argc {
-                        return Err(EvalError::UnsupportedCall);
+                    VmInstruction::Unary(op) => {
+                        let value = stack.pop().ok_or(EvalError::UnsupportedCall)?;
+                        stack.push(eval_unary(*op, value)?);
                     }
-                    let start = stack.len() - *argc;
-                    let args = stack.drain(start..).collect::<Vec<_>>();
-                    if let Some(value) = eval_ident_call(name, &args)? {
-                        stack.push(value);
-                    } else if let Some(def) = state.function_defs.get(name)
-                        && let Some(value) = eval_top_level_function_call(state, def, &args, 1)?
-                    {
-                        stack.push(value);
-                    } else {
-                        return Err(EvalError::UnsupportedCall);
+                    VmInstruction::Binary(op) => {
+                        let rhs = stack.pop().ok_or(EvalError::UnsupportedCall)?;
+                        let lhs = stack.pop().ok_or(EvalError::UnsupportedCall)?;
+                        stack.push(eval_binary(lhs, *op, rhs)?);
                     }
+                    VmInstruction::CallNamed { name, argc } => {
+                        if stack.len() < *argc {
+                            return Err(EvalError::UnsupportedCall);
+                        }
+                        let start = stack.len() - *argc;
+                        let args = stack.drain(start..).collect::<Vec<_>>();
+                        if let Some(value) = eval_ident_call(name, &args)? {
+                            stack.push(value);
+                        } else if let Some(def) = state.function_defs.get(name)
+                            && let Some(value) = eval_top_level_function_call(state, def, &args, 1)?
+                        {
+                            stack.push(value);
+                        } else {
+                            return Err(EvalError::UnsupportedCall);
+                        }
+                    }
+                    VmInstruction::StoreLocal(name) => {
+                        let value = stack.pop().ok_or(EvalError::UnsupportedCall)?;
+                        locals.insert(name.clone(), value);
+                    }
+                    VmInstruction::Jump(target) => pc = *target,
+                    VmInstruction::JumpIfFalse(target) => {
+                        let value = stack.pop().ok_or(EvalError::UnsupportedCall)?;
+                        if !is_truthy(&value)? {
+                            pc = *target;
+                        }
+                    }
+                    VmInstruction::Return => return stack.pop().ok_or(EvalError::UnsupportedCall),
                 }
-                VmInstruction::StoreLocal(name) => {
-                    let value = stack.pop().ok_or(EvalError::UnsupportedCall)?;
-                    locals.insert(name.clone(), value);
-                }
-                VmInstruction::Return => return stack.pop().ok_or(EvalError::UnsupportedCall),
-            }
-        }
-        return Err(EvalError::UndefinedIdentifier(
-            "vm function literal missing return".to_string(),
-        ));
-    }
-
-    for stmt in &function.body {
-        match stmt {
-            MaterialFunctionStatement::Binding { name, expr } => {
-                let value = eval_expr_in_material_scope(expr, state, &locals, None, 0)?;
-                locals.insert(name.clone(), value);
-            }
-            MaterialFunctionStatement::Return { expr } => {
-                return eval_expr_in_material_scope(expr, state, &locals, None, 0);
             }
-        }
+            Err(EvalError::UndefinedIdentifier(
+                "vm function literal missing return".to_string(),
+            ))
+        };
+        return run().map_err(|error| locate_vm_error(compiled, pc, error));
     }
 
-    Err(EvalError::UnsupportedCall)
+    exec_function_body(&function.body, &mut locals, None, &mut |expr, locals| {
+        eval_expr_in_material_scope(expr, state, locals, None, 0)
+    })?
+    .ok_or(EvalError::UnsupportedCall)
 }
 
 fn compile_function_literal_vec3(
@@ -2153,25 +2364,14 @@ fn compile_function_literal_vec3(
     if !jit_enabled() || !captures.is_empty() || params.len() != 1 {
         return None;
     }
-    let sdf_body = body
-        .iter()
-        .map(|stmt| match stmt {
-            MaterialFunctionStatement::Binding { name, expr } => SdfFunctionStatement::Binding {
-                name: name.clone(),
-                expr: expr.clone(),
-            },
-            MaterialFunctionStatement::Return { expr } => {
-                SdfFunctionStatement::Return { expr: expr.clone() }
-            }
-        })
-        .collect::<Vec<_>>();
     let def = SdfDef {
         name: "__anon_modifier__".to_string(),
         metadata: vec![],
         statements: vec![SdfStatement::Function {
             name: "__anon__".to_string(),
             params: params.to_vec(),
-            body: sdf_body,
+            body: body.to_vec(),
+            span: Span::default(),
         }],
     };
     compile_sdf_vec3_function(&def, "__anon__")
@@ -2185,19 +2385,7 @@ fn compile_function_literal_distance_post(
     if !jit_enabled() || !captures.is_empty() || params.len() != 2 {
         return None;
     }
-    let sdf_body = body
-        .iter()
-        .map(|stmt| match stmt {
-            MaterialFunctionStatement::Binding { name, expr } => SdfFunctionStatement::Binding {
-                name: name.clone(),
-                expr: expr.clone(),
-            },
-            MaterialFunctionStatement::Return { expr } => {
-                SdfFunctionStatement::Return { expr: expr.clone() }
-            }
-        })
-        .collect::<Vec<_>>();
-    compile_modifier_distance_function("__anon__", params, &sdf_body)
+    compile_modifier_distance_function("__anon__", params, body)
 }
 
 fn eval_arg_values(
@@ -2220,6 +2408,20 @@ fn eval_arg_values(
     Ok(values)
 }
 
+/// Flattens nested arrays of objects, as produced by nested `for` expressions.
+fn collect_union_items(value: &Value, items: &mut Vec<Value>) -> Result<(), EvalError> {
+    match value {
+        Value::Array(values) => values
+            .iter()
+            .try_for_each(|value| collect_union_items(value, items)),
+        Value::Object(_) => {
+            items.push(value.clone());
+            Ok(())
+        }
+        _ => Err(EvalError::BuiltinObjectArrayArgs("union_all")),
+    }
+}
+
 fn eval_ident_call(name: &str, args: &[Value]) -> Result<Option<Value>, EvalError> {
     let value = match name {
         "anchor" => {
@@ -2235,6 +2437,21 @@ fn eval_ident_call(name: &str, args: &[Value]) -> Result<Option<Value>, EvalErro
                 .point;
             vec3_value(point)
         }
+        "union_all" => {
+            let [list] = args else {
+                return Err(EvalError::BuiltinObjectArrayArgs("union_all"));
+            };
+            let mut items = Vec::new();
+            collect_union_items(list, &mut items)?;
+            match items.len() {
+                0 => return Err(EvalError::BuiltinObjectArrayArgs("union_all")),
+                1 => items.remove(0),
+                _ => Value::Object(ObjectValue {
+                    type_name: Some("union_all".to_string()),
+                    fields: HashMap::from([("items".to_string(), Value::Array(items))]),
+                }),
+            }
+        }
         "vec3" => {
             if args.len() != 1 && args.len() != 3 {
                 return Err(EvalError::InvalidVec3Call);
@@ -2757,9 +2974,9 @@ pub fn eval_material_function_with_overrides(
         .statements
         .iter()
         .find_map(|stmt| match stmt {
-            MaterialStatement::Function { name, params, body } if name == function_name => {
-                Some((params.clone(), body.clone()))
-            }
+            MaterialStatement::Function {
+                name, params, body, ..
+            } if name == function_name => Some((params.clone(), body.clone())),
             _ => None,
         })
         .ok_or_else(|| EvalError::UndefinedIdentifier(function_name.to_string()))?;
@@ -2872,13 +3089,114 @@ pub fn eval_environment_function(
         .statements
         .iter()
         .find_map(|stmt| match stmt {
-            MaterialStatement::Function { name, params, body } if name == function_name => {
-                Some((params.clone(), body.clone()))
-            }
+            MaterialStatement::Function {
+                name, params, body, ..
+            } if name == function_name => Some((params.clone(), body.clone())),
+            _ => None,
+        })
+        .ok_or_else(|| EvalError::UndefinedIdentifier(function_name.to_string()))?;
+    let scope = EnvironmentScope {
+        statements: &def.statements,
+        overrides: None,
+    };
+    eval_environment_function_body(state, scope, &params, &body, arg_values, 0)
+}
+
+pub fn eval_volume_function(
+    state: &EvalState,
+    volume_name: &str,
+    function_name: &str,
+    arg_values: &[Value],
+    overrides: Option<&ObjectValue>,
+) -> Result<Value, EvalError> {
+    let def = state
+        .volume_defs
+        .get(volume_name)
+        .ok_or_else(|| EvalError::UndefinedIdentifier(volume_name.to_string()))?;
+    let (params, body) = def
+        .statements
+        .iter()
+        .find_map(|stmt| match stmt {
+            MaterialStatement::Function {
+                name, params, body, ..
+            } if name == function_name => Some((params.clone(), body.clone())),
             _ => None,
         })
         .ok_or_else(|| EvalError::UndefinedIdentifier(function_name.to_string()))?;
-    eval_environment_function_body(state, def, &params, &body, arg_values, 0)
+    let scope = EnvironmentScope {
+        statements: &def.statements,
+        overrides,
+    };
+    eval_environment_function_body(state, scope, &params, &body, arg_values, 0)
+}
+
+/// Values of a volume's `let` bindings and properties, evaluated in source order with the
+/// instance's overrides taking the place of the written expressions. Instance fields the
+/// definition does not declare are included as given.
+pub fn eval_volume_values(
+    state: &EvalState,
+    volume_name: &str,
+    overrides: Option<&ObjectValue>,
+) -> Result<HashMap<String, Value>, EvalError> {
+    let def = state
+        .volume_defs
+        .get(volume_name)
+        .ok_or_else(|| EvalError::UndefinedIdentifier(volume_name.to_string()))?;
+    let scope = EnvironmentScope {
+        statements: &def.statements,
+        overrides,
+    };
+    let mut values = HashMap::new();
+    for stmt in &def.statements {
+        match stmt {
+            MaterialStatement::Binding { name, expr }
+            | MaterialStatement::Property { name, expr } => {
+                let value = match material_override_value(overrides, name) {
+                    Some(value) => value.clone(),
+                    None => eval_expr_in_environment_scope(expr, state, &values, scope, 0)?,
+                };
+                values.insert(name.clone(), value);
+            }
+            MaterialStatement::Function { .. } => {}
+        }
+    }
+    if let Some(overrides) = overrides {
+        for (name, value) in &overrides.fields {
+            values.entry(name.clone()).or_insert_with(|| value.clone());
+        }
+    }
+    Ok(values)
+}
+
+/// The bindings and functions of a volume in the shape the SDF JIT compiles.
+fn volume_sdf_def(def: &VolumeDef) -> SdfDef {
+    let statements = def
+        .statements
+        .iter()
+        .filter_map(|stmt| match stmt {
+            MaterialStatement::Binding { name, expr } => Some(SdfStatement::Binding {
+                name: name.clone(),
+                expr: expr.clone(),
+            }),
+            MaterialStatement::Function {
+                name,
+                params,
+                body,
+                span,
+            } => Some(SdfStatement::Function {
+                name: name.clone(),
+                params: params.clone(),
+                body: body.clone(),
+                span: *span,
+            }),
+            MaterialStatement::Property { .. } => None,
+        })
+        .collect();
+    SdfDef {
+        name: def.name.clone(),
+        metadata: Vec::new(),
+        statements,
+    }
 }
 
 pub fn eval_sdf_function(
@@ -2971,9 +3289,9 @@ pub fn eval_sdf_function_args_with_overrides(
         .statements
         .iter()
         .find_map(|stmt| match stmt {
-            SdfStatement::Function { name, params, body } if name == function_name => {
-                Some((params.clone(), body.clone()))
-            }
+            SdfStatement::Function {
+                name, params, body, ..
+            } if name == function_name => Some((params.clone(), body.clone())),
             _ => None,
         })
         .ok_or_else(|| EvalError::UndefinedIdentifier(function_name.to_string()))?;
@@ -3023,9 +3341,9 @@ pub fn eval_sdf_zero_arg_function_with_overrides(
         .statements
         .iter()
         .find_map(|stmt| match stmt {
-            SdfStatement::Function { name, params, body } if name == function_name => {
-                Some((params.clone(), body.clone()))
-            }
+            SdfStatement::Function {
+                name, params, body, ..
+            } if name == function_name => Some((params.clone(), body.clone())),
             _ => None,
         })
         .ok_or_else(|| EvalError::UndefinedIdentifier(function_name.to_string()))?;
@@ -3117,102 +3435,114 @@ fn execute_sdf_vm_function(
     }
 
     let mut stack = Vec::new();
-    for instruction in &function.code {
-        match instruction {
-            VmInstruction::PushNumber(v) => stack.push(Value::Number(*v)),
-            VmInstruction::PushString(v) => stack.push(Value::String(v.clone())),
-            VmInstruction::LoadName(name) => {
-                if let Some(value) = locals.get(name) {
-                    stack.push(value.clone());
-                } else if let Some(value) = builtin_symbol_value(name) {
-                    stack.push(value);
-                } else if let Some(binding) = state.bindings.get(name) {
-                    stack.push(binding.value.clone());
-                } else {
-                    return Err(EvalError::UndefinedIdentifier(name.clone()));
-                }
-            }
-            VmInstruction::BuildArray(count) => {
-                if stack.len() < *count {
-                    return Err(EvalError::UnsupportedCall);
+    let mut pc = 0;
+    let mut run = || -> Result<Value, EvalError> {
+        while let Some(instruction) = function.code.get(pc) {
+            pc += 1;
+            match instruction {
+                VmInstruction::PushNumber(v) => stack.push(Value::Number(*v)),
+                VmInstruction::PushString(v) => stack.push(Value::String(v.clone())),
+                VmInstruction::LoadName(name) => {
+                    if let Some(value) = locals.get(name) {
+                        stack.push(value.clone());
+                    } else if let Some(value) = builtin_symbol_value(name) {
+                        stack.push(value);
+                    } else if let Some(binding) = state.bindings.get(name) {
+                        stack.push(binding.value.clone());
+                    } else {
+                        return Err(EvalError::UndefinedIdentifier(name.clone()));
+                    }
                 }
-                let start = stack.len() - *count;
-                let values = stack.drain(start..).collect::<Vec<_>>();
-                stack.push(Value::Array(values));
-            }
-            VmInstruction::BuildObject {
-                type_name,
-                field_names,
-            } => {
-                if stack.len() < field_names.len() {
-                    return Err(EvalError::UnsupportedCall);
+                VmInstruction::BuildArray(count) => {
+                    if stack.len() < *count {
+                        return Err(EvalError::UnsupportedCall);
+                    }
+                    let start = stack.len() - *count;
+                    let values = stack.drain(start..).collect::<Vec<_>>();
+                    stack.push(Value::Array(values));
                 }
-                let start = stack.len() - field_names.len();
-                let values = stack.drain(start..).collect::<Vec<_>>();
-                let mut fields = HashMap::new();
-                for (name, value) in field_names.iter().cloned().zip(values.into_iter()) {
-                    fields.insert(name, value);
+                VmInstruction::BuildObject {
+                    type_name,
+                    field_names,
+                } => {
+                    if stack.len() < field_names.len() {
+                        return Err(EvalError::UnsupportedCall);
+                    }
+                    let start = stack.len() - field_names.len();
+                    let values = stack.drain(start..).collect::<Vec<_>>();
+                    let mut fields = HashMap::new();
+                    for (name, value) in field_names.iter().cloned().zip(values.into_iter()) {
+                        fields.insert(name, value);
+                    }
+                    augment_object_literal_fields(state, type_name, &mut fields)?;
+                    stack.push(Value::Object(ObjectValue {
+                        type_name: Some(type_name.clone()),
+                        fields,
+                    }));
                 }
-                augment_object_literal_fields(state, type_name, &mut fields)?;
-                stack.push(Value::Object(ObjectValue {
-                    type_name: Some(type_name.clone()),
-                    fields,
-                }));
-            }
-            VmInstruction::LoadMember(field) => {
-                let value = stack.pop().ok_or(EvalError::UnsupportedCall)?;
-                let obj = as_object(&value)?;
-                let member = obj
-                    .fields
-                    .get(field)
-                    .cloned()
-                    .ok_or_else(|| EvalError::UndefinedIdentifier(field.clone()))?;
-                stack.push(member);
-            }
-            VmInstruction::Unary(op) => {
-                let value = stack.pop().ok_or(EvalError::UnsupportedCall)?;
-                stack.push(eval_unary(*op, value)?);
-            }
-            VmInstruction::Binary(op) => {
-                let rhs = stack.pop().ok_or(EvalError::UnsupportedCall)?;
-                let lhs = stack.pop().ok_or(EvalError::UnsupportedCall)?;
-                stack.push(eval_binary(lhs, *op, rhs)?);
-            }
-            VmInstruction::CallNamed { name, argc } => {
-                if stack.len() < *argc {
-                    return Err(EvalError::UnsupportedCall);
+                VmInstruction::LoadMember(field) => {
+                    let value = stack.pop().ok_or(EvalError::UnsupportedCall)?;
+                    let obj = as_object(&value)?;
+                    let member = obj
+                        .fields
+                        .get(field)
+                        .cloned()
+                        .ok_or_else(|| EvalError::UndefinedIdentifier(field.clone()))?;
+                    stack.push(member);
                 }
-                let start = stack.len() - *argc;
-                let args = stack.drain(start..).collect::<Vec<_>>();
-                if let Some(compiled) = state
-                    .compiled_sdf_functions
-                    .get(sdf_name)
-                    .and_then(|functions| functions.get(name))
-                {
-                    stack.push(execute_sdf_vm_function(
-                        state,
-                        sdf_name,
-                        name,
-                        compiled,
-                        args,
-                        overrides,
-                        depth + 1,
-                    )?);
-                } else {
-                    stack.push(eval_named_vm_call(state, name, &args, depth)?);
+                VmInstruction::Unary(op) => {
+                    let value = stack.pop().ok_or(EvalError::UnsupportedCall)?;
+                    stack.push(eval_unary(*op, value)?);
                 }
+                VmInstruction::Binary(op) => {
+                    let rhs = stack.pop().ok_or(EvalError::UnsupportedCall)?;
+                    let lhs = stack.pop().ok_or(EvalError::UnsupportedCall)?;
+                    stack.push(eval_binary(lhs, *op, rhs)?);
+                }
+                VmInstruction::CallNamed { name, argc } => {
+                    if stack.len() < *argc {
+                        return Err(EvalError::UnsupportedCall);
+                    }
+                    let start = stack.len() - *argc;
+                    let args = stack.drain(start..).collect::<Vec<_>>();
+                    if let Some(compiled) = state
+                        .compiled_sdf_functions
+                        .get(sdf_name)
+                        .and_then(|functions| functions.get(name))
+                    {
+                        stack.push(execute_sdf_vm_function(
+                            state,
+                            sdf_name,
+                            name,
+                            compiled,
+                            args,
+                            overrides,
+                            depth + 1,
+                        )?);
+                    } else {
+                        stack.push(eval_named_vm_call(state, name, &args, depth)?);
+                    }
+                }
+                VmInstruction::StoreLocal(name) => {
+                    let value = stack.pop().ok_or(EvalError::UnsupportedCall)?;
+                    locals.insert(name.clone(), value);
+                }
+                VmInstruction::Jump(target) => pc = *target,
+                VmInstruction::JumpIfFalse(target) => {
+                    let value = stack.pop().ok_or(EvalError::UnsupportedCall)?;
+                    if !is_truthy(&value)? {
+                        pc = *target;
+                    }
+                }
+                VmInstruction::Return => return stack.pop().ok_or(EvalError::UnsupportedCall),
             }
-            VmInstruction::StoreLocal(name) => {
-                let value = stack.pop().ok_or(EvalError::UnsupportedCall)?;
-                locals.insert(name.clone(), value);
-            }
-            VmInstruction::Return => return stack.pop().ok_or(EvalError::UnsupportedCall),
         }
-    }
 
-    Err(EvalError::UndefinedIdentifier(
-        "vm sdf function missing return".to_string(),
-    ))
+        Err(EvalError::UndefinedIdentifier(
+            "vm sdf function missing return".to_string(),
+        ))
+    };
+    run().map_err(|error| locate_vm_error(function, pc, error))
 }
 
 fn eval_sdf_binding_with_overrides(
@@ -3296,43 +3626,19 @@ fn eval_sdf_function_body(
         }
     }
 
-    for stmt in body {
-        match stmt {
-            MaterialFunctionStatement::Binding { name, expr } => {
-                let value = if let Some(value) = sdf_override_value(overrides, name) {
-                    value.clone()
-                } else {
-                    eval_sdf_expr(
-                        expr,
-                        state,
-                        &locals,
-                        Some(SdfRuntime {
-                            def,
-                            depth,
-                            overrides,
-                        }),
-                    )?
-                };
-                locals.insert(name.clone(), value);
-            }
-            MaterialFunctionStatement::Return { expr } => {
-                return eval_sdf_expr(
-                    expr,
-                    state,
-                    &locals,
-                    Some(SdfRuntime {
-                        def,
-                        depth,
-                        overrides,
-                    }),
-                );
-            }
-        }
-    }
-
-    Err(EvalError::UndefinedIdentifier(
-        "sdf function missing return".to_string(),
-    ))
+    exec_function_body(body, &mut locals, overrides, &mut |expr, locals| {
+        eval_sdf_expr(
+            expr,
+            state,
+            locals,
+            Some(SdfRuntime {
+                def,
+                depth,
+                overrides,
+            }),
+        )
+    })?
+    .ok_or_else(|| EvalError::UndefinedIdentifier("sdf function missing return".to_string()))
 }
 
 fn sdf_override_locals(overrides: Option<&ObjectValue>) -> HashMap<String, Value> {
@@ -3351,17 +3657,26 @@ fn eval_sdf_expr(
     locals: &HashMap<String, Value>,
     sdf_runtime: Option<SdfRuntime<'_>>,
 ) -> Result<Value, EvalError> {
-    match expr {
-        Expr::Number(n) => Ok(Value::Number(*n as f32)),
-        Expr::String(value) => Ok(Value::String(value.clone())),
-        Expr::Array(items) => {
+    eval_sdf_expr_kind(expr, state, locals, sdf_runtime).map_err(|error| error.at(expr.span))
+}
+
+fn eval_sdf_expr_kind(
+    expr: &Expr,
+    state: &EvalState,
+    locals: &HashMap<String, Value>,
+    sdf_runtime: Option<SdfRuntime<'_>>,
+) -> Result<Value, EvalError> {
+    match &expr.kind {
+        ExprKind::Number(n) => Ok(Value::Number(*n as f32)),
+        ExprKind::String(value) => Ok(Value::String(value.clone())),
+        ExprKind::Array(items) => {
             let mut values = Vec::with_capacity(items.len());
             for item in items {
                 values.push(eval_sdf_expr(item, state, locals, sdf_runtime)?);
             }
             Ok(Value::Array(values))
         }
-        Expr::FunctionLiteral { params, body } => Ok(Value::Function(FunctionValue {
+        ExprKind::FunctionLiteral { params, body } => Ok(Value::Function(FunctionValue {
             params: params.clone(),
             body: body.clone(),
             captures: locals.clone(),
@@ -3369,7 +3684,7 @@ fn eval_sdf_expr(
             jitted_vec3: compile_function_literal_vec3(params, body, locals),
             jitted_distance_post: compile_function_literal_distance_post(params, body, locals),
         })),
-        Expr::Ident(name) => {
+        ExprKind::Ident(name) => {
             if let Some(value) = locals.get(name) {
                 return Ok(value.clone());
             }
@@ -3382,7 +3697,7 @@ fn eval_sdf_expr(
                 .ok_or_else(|| EvalError::UndefinedIdentifier(name.clone()))?;
             Ok(binding.value.clone())
         }
-        Expr::ObjectLiteral { type_name, fields } => {
+        ExprKind::ObjectLiteral { type_name, fields } => {
             let mut resolved_fields = HashMap::new();
             for (name, field_expr) in fields {
                 resolved_fields.insert(
@@ -3396,12 +3711,19 @@ fn eval_sdf_expr(
                 fields: resolved_fields,
             }))
         }
-        Expr::Binary { lhs, op, rhs } => {
+        ExprKind::Binary {
+            lhs,
+            op: op @ (BinaryOp::And | BinaryOp::Or),
+            rhs,
+        } => eval_logical(*op, lhs, rhs, |expr| {
+            eval_sdf_expr(expr, state, locals, sdf_runtime)
+        }),
+        ExprKind::Binary { lhs, op, rhs } => {
             let left = eval_sdf_expr(lhs, state, locals, sdf_runtime)?;
             let right = eval_sdf_expr(rhs, state, locals, sdf_runtime)?;
             eval_binary(left, *op, right)
         }
-        Expr::Member { target, field } => {
+        ExprKind::Member { target, field } => {
             let base = eval_sdf_expr(target, state, locals, sdf_runtime)?;
             let obj = as_object(&base)?;
             obj.fields
@@ -3409,11 +3731,32 @@ fn eval_sdf_expr(
                 .cloned()
                 .ok_or(EvalError::UndefinedIdentifier(field.clone()))
         }
-        Expr::Call { callee, args } => eval_sdf_call(callee, args, state, locals, sdf_runtime),
-        Expr::Unary { op, expr } => {
+        ExprKind::Call { callee, args } => eval_sdf_call(callee, args, state, locals, sdf_runtime),
+        ExprKind::Unary { op, expr } => {
             let value = eval_sdf_expr(expr, state, locals, sdf_runtime)?;
             eval_unary(*op, value)
         }
+        ExprKind::If {
+            cond,
+            then_branch,
+            else_branch,
+        } => {
+            let cond = eval_sdf_expr(cond, state, locals, sdf_runtime)?;
+            let branch = if is_truthy(&cond)? {
+                then_branch
+            } else {
+                else_branch
+            };
+            eval_sdf_expr(branch, state, locals, sdf_runtime)
+        }
+        ExprKind::For {
+            var,
+            start,
+            end,
+            body,
+        } => eval_for_expr(var, start, end, body, locals, |expr, locals| {
+            eval_sdf_expr(expr, state, locals, sdf_runtime)
+        }),
     }
 }
 
@@ -3424,8 +3767,8 @@ fn eval_sdf_call(
     locals: &HashMap<String, Value>,
     sdf_runtime: Option<SdfRuntime<'_>>,
 ) -> Result<Value, EvalError> {
-    match callee {
-        Expr::Ident(name) => {
+    match &callee.kind {
+        ExprKind::Ident(name) => {
             let arg_values = eval_sdf_arg_values(args, state, locals, sdf_runtime)?;
             if let Some(value) = eval_ident_call(name, &arg_values)? {
                 return Ok(value);
@@ -3437,6 +3780,7 @@ fn eval_sdf_call(
                             name: fn_name,
                             params,
                             body,
+                            ..
                         } if fn_name == name => Some((params.clone(), body.clone())),
                         _ => None,
                     })
@@ -3469,7 +3813,7 @@ fn eval_sdf_call(
             }
             Err(EvalError::UnsupportedCall)
         }
-        Expr::Member { target, field } => {
+        ExprKind::Member { target, field } => {
             if is_sdf_member_operator(field) {
                 let base = eval_sdf_expr(target, state, locals, sdf_runtime)?;
                 let arg_values = eval_sdf_arg_values(args, state, locals, sdf_runtime)?;
@@ -3557,41 +3901,20 @@ fn eval_material_function_body(
         }
     }
 
-    for stmt in body {
-        match stmt {
-            MaterialFunctionStatement::Binding { name, expr } => {
-                let value = eval_expr_in_material_scope(
-                    expr,
-                    state,
-                    &locals,
-                    Some(MaterialRuntime {
-                        def,
-                        depth,
-                        overrides,
-                    }),
-                    depth,
-                )?;
-                locals.insert(name.clone(), value);
-            }
-            MaterialFunctionStatement::Return { expr } => {
-                return eval_expr_in_material_scope(
-                    expr,
-                    state,
-                    &locals,
-                    Some(MaterialRuntime {
-                        def,
-                        depth,
-                        overrides,
-                    }),
-                    depth,
-                );
-            }
-        }
-    }
-
-    Err(EvalError::UndefinedIdentifier(
-        "material function missing return".to_string(),
-    ))
+    exec_function_body(body, &mut locals, None, &mut |expr, locals| {
+        eval_expr_in_material_scope(
+            expr,
+            state,
+            locals,
+            Some(MaterialRuntime {
+                def,
+                depth,
+                overrides,
+            }),
+            depth,
+        )
+    })?
+    .ok_or_else(|| EvalError::UndefinedIdentifier("material function missing return".to_string()))
 }
 
 fn populate_material_locals(
@@ -3653,103 +3976,115 @@ fn execute_material_vm_function(
     populate_material_locals(state, def, overrides, depth, &mut locals)?;
 
     let mut stack = Vec::new();
-    for instruction in &function.code {
-        match instruction {
-            VmInstruction::PushNumber(v) => stack.push(Value::Number(*v)),
-            VmInstruction::PushString(v) => stack.push(Value::String(v.clone())),
-            VmInstruction::LoadName(name) => {
-                if let Some(value) = locals.get(name) {
-                    stack.push(value.clone());
-                } else if let Some(value) = builtin_symbol_value(name) {
-                    stack.push(value);
-                } else if let Some(binding) = state.bindings.get(name) {
-                    stack.push(binding.value.clone());
-                } else {
-                    return Err(EvalError::UndefinedIdentifier(name.clone()));
+    let mut pc = 0;
+    let mut run = || -> Result<Value, EvalError> {
+        while let Some(instruction) = function.code.get(pc) {
+            pc += 1;
+            match instruction {
+                VmInstruction::PushNumber(v) => stack.push(Value::Number(*v)),
+                VmInstruction::PushString(v) => stack.push(Value::String(v.clone())),
+                VmInstruction::LoadName(name) => {
+                    if let Some(value) = locals.get(name) {
+                        stack.push(value.clone());
+                    } else if let Some(value) = builtin_symbol_value(name) {
+                        stack.push(value);
+                    } else if let Some(binding) = state.bindings.get(name) {
+                        stack.push(binding.value.clone());
+                    } else {
+                        return Err(EvalError::UndefinedIdentifier(name.clone()));
+                    }
                 }
-            }
-            VmInstruction::BuildArray(count) => {
-                if stack.len() < *count {
-                    return Err(EvalError::UnsupportedCall);
+                VmInstruction::BuildArray(count) => {
+                    if stack.len() < *count {
+                        return Err(EvalError::UnsupportedCall);
+                    }
+                    let start = stack.len() - *count;
+                    let values = stack.drain(start..).collect::<Vec<_>>();
+                    stack.push(Value::Array(values));
                 }
-                let start = stack.len() - *count;
-                let values = stack.drain(start..).collect::<Vec<_>>();
-                stack.push(Value::Array(values));
-            }
-            VmInstruction::BuildObject {
-                type_name,
-                field_names,
-            } => {
-                if stack.len() < field_names.len() {
-                    return Err(EvalError::UnsupportedCall);
+                VmInstruction::BuildObject {
+                    type_name,
+                    field_names,
+                } => {
+                    if stack.len() < field_names.len() {
+                        return Err(EvalError::UnsupportedCall);
+                    }
+                    let start = stack.len() - field_names.len();
+                    let values = stack.drain(start..).collect::<Vec<_>>();
+                    let mut fields = HashMap::new();
+                    for (name, value) in field_names.iter().cloned().zip(values.into_iter()) {
+                        fields.insert(name, value);
+                    }
+                    augment_object_literal_fields(state, type_name, &mut fields)?;
+                    stack.push(Value::Object(ObjectValue {
+                        type_name: Some(type_name.clone()),
+                        fields,
+                    }));
                 }
-                let start = stack.len() - field_names.len();
-                let values = stack.drain(start..).collect::<Vec<_>>();
-                let mut fields = HashMap::new();
-                for (name, value) in field_names.iter().cloned().zip(values.into_iter()) {
-                    fields.insert(name, value);
+                VmInstruction::LoadMember(field) => {
+                    let value = stack.pop().ok_or(EvalError::UnsupportedCall)?;
+                    let obj = as_object(&value)?;
+                    let member = obj
+                        .fields
+                        .get(field)
+                        .cloned()
+                        .ok_or_else(|| EvalError::UndefinedIdentifier(field.clone()))?;
+                    stack.push(member);
                 }
-                augment_object_literal_fields(state, type_name, &mut fields)?;
-                stack.push(Value::Object(ObjectValue {
-                    type_name: Some(type_name.clone()),
-                    fields,
-                }));
-            }
-            VmInstruction::LoadMember(field) => {
-                let value = stack.pop().ok_or(EvalError::UnsupportedCall)?;
-                let obj = as_object(&value)?;
-                let member = obj
-                    .fields
-                    .get(field)
-                    .cloned()
-                    .ok_or_else(|| EvalError::UndefinedIdentifier(field.clone()))?;
-                stack.push(member);
-            }
-            VmInstruction::Unary(op) => {
-                let value = stack.pop().ok_or(EvalError::UnsupportedCall)?;
-                stack.push(eval_unary(*op, value)?);
-            }
-            VmInstruction::Binary(op) => {
-                let rhs = stack.pop().ok_or(EvalError::UnsupportedCall)?;
-                let lhs = stack.pop().ok_or(EvalError::UnsupportedCall)?;
-                stack.push(eval_binary(lhs, *op, rhs)?);
-            }
-            VmInstruction::CallNamed { name, argc } => {
-                if stack.len() < *argc {
-                    return Err(EvalError::UnsupportedCall);
+                VmInstruction::Unary(op) => {
+                    let value = stack.pop().ok_or(EvalError::UnsupportedCall)?;
+                    stack.push(eval_unary(*op, value)?);
                 }
-                let start = stack.len() - *argc;
-                let args = stack.drain(start..).collect::<Vec<_>>();
-                if let Some(compiled) = state
-                    .compiled_material_functions
-                    .get(material_name)
-                    .and_then(|functions| functions.get(name))
-                {
-                    let params = compiled.params.clone();
-                    stack.push(execute_material_vm_function(
-                        state,
-                        material_name,
-                        compiled,
-                        &params,
-                        args,
-                        overrides,
-                        depth + 1,
-                    )?);
-                } else {
-                    stack.push(eval_named_vm_call(state, name, &args, depth)?);
+                VmInstruction::Binary(op) => {
+                    let rhs = stack.pop().ok_or(EvalError::UnsupportedCall)?;
+                    let lhs = stack.pop().ok_or(EvalError::UnsupportedCall)?;
+                    stack.push(eval_binary(lhs, *op, rhs)?);
                 }
+                VmInstruction::CallNamed { name, argc } => {
+                    if stack.len() < *argc {
+                        return Err(EvalError::UnsupportedCall);
+                    }
+                    let start = stack.len() - *argc;
+                    let args = stack.drain(start..).collect::<Vec<_>>();
+                    if let Some(compiled) = state
+                        .compiled_material_functions
+                        .get(material_name)
+                        .and_then(|functions| functions.get(name))
+                    {
+                        let params = compiled.params.clone();
+                        stack.push(execute_material_vm_function(
+                            state,
+                            material_name,
+                            compiled,
+                            &params,
+                            args,
+                            overrides,
+                            depth + 1,
+                        )?);
+                    } else {
+                        stack.push(eval_named_vm_call(state, name, &args, depth)?);
+                    }
+                }
+                VmInstruction::StoreLocal(name) => {
+                    let value = stack.pop().ok_or(EvalError::UnsupportedCall)?;
+                    locals.insert(name.clone(), value);
+                }
+                VmInstruction::Jump(target) => pc = *target,
+                VmInstruction::JumpIfFalse(target) => {
+                    let value = stack.pop().ok_or(EvalError::UnsupportedCall)?;
+                    if !is_truthy(&value)? {
+                        pc = *target;
+                    }
+                }
+                VmInstruction::Return => return stack.pop().ok_or(EvalError::UnsupportedCall),
             }
-            VmInstruction::StoreLocal(name) => {
-                let value = stack.pop().ok_or(EvalError::UnsupportedCall)?;
-                locals.insert(name.clone(), value);
-            }
-            VmInstruction::Return => return stack.pop().ok_or(EvalError::UnsupportedCall),
         }
-    }
 
-    Err(EvalError::UndefinedIdentifier(
-        "vm material function missing return".to_string(),
-    ))
+        Err(EvalError::UndefinedIdentifier(
+            "vm material function missing return".to_string(),
+        ))
+    };
+    run().map_err(|error| locate_vm_error(function, pc, error))
 }
 
 fn material_override_locals(overrides: Option<&ObjectValue>) -> HashMap<String, Value> {
@@ -3765,9 +4100,17 @@ fn material_override_value<'a>(
     overrides.and_then(|object| object.fields.get(name))
 }
 
+/// Statements an `environment` or `volume` function runs against, with the per-instance
+/// values that replace its `let` bindings.
+#[derive(Clone, Copy)]
+struct EnvironmentScope<'a> {
+    statements: &'a [MaterialStatement],
+    overrides: Option<&'a ObjectValue>,
+}
+
 fn eval_environment_function_body(
     state: &EvalState,
-    def: &EnvironmentDef,
+    scope: EnvironmentScope<'_>,
     params: &[String],
     body: &[MaterialFunctionStatement],
     arg_values: &[Value],
@@ -3781,55 +4124,61 @@ fn eval_environment_function_body(
         locals.insert(param.clone(), value.clone());
     }
 
-    for stmt in &def.statements {
+    for stmt in scope.statements {
         match stmt {
             MaterialStatement::Binding { name, expr } => {
-                let value = eval_expr_in_environment_scope(expr, state, &locals, def, depth)?;
+                let value = match material_override_value(scope.overrides, name) {
+                    Some(value) => value.clone(),
+                    None => eval_expr_in_environment_scope(expr, state, &locals, scope, depth)?,
+                };
                 locals.insert(name.clone(), value);
             }
             MaterialStatement::Property { .. } | MaterialStatement::Function { .. } => {}
         }
     }
 
-    for stmt in body {
-        match stmt {
-            MaterialFunctionStatement::Binding { name, expr } => {
-                let value = eval_expr_in_environment_scope(expr, state, &locals, def, depth)?;
-                locals.insert(name.clone(), value);
-            }
-            MaterialFunctionStatement::Return { expr } => {
-                return eval_expr_in_environment_scope(expr, state, &locals, def, depth);
-            }
-        }
-    }
-
-    Err(EvalError::UndefinedIdentifier(
-        "environment function missing return".to_string(),
-    ))
+    exec_function_body(body, &mut locals, None, &mut |expr, locals| {
+        eval_expr_in_environment_scope(expr, state, locals, scope, depth)
+    })?
+    .ok_or_else(|| {
+        EvalError::UndefinedIdentifier("environment function missing return".to_string())
+    })
 }
 
 fn eval_expr_in_environment_scope(
     expr: &Expr,
     state: &EvalState,
     locals: &HashMap<String, Value>,
-    def: &EnvironmentDef,
+    scope: EnvironmentScope<'_>,
+    depth: usize,
+) -> Result<Value, EvalError> {
+    eval_expr_kind_in_environment_scope(expr, state, locals, scope, depth)
+        .map_err(|error| error.at(expr.span))
+}
+
+fn eval_expr_kind_in_environment_scope(
+    expr: &Expr,
+    state: &EvalState,
+    locals: &HashMap<String, Value>,
+    scope: EnvironmentScope<'_>,
     depth: usize,
 ) -> Result<Value, EvalError> {
-    match expr {
-        Expr::Call { callee, args } => match callee.as_ref() {
-            Expr::Ident(name) => {
+    match &expr.kind {
+        ExprKind::Call { callee, args } => match &callee.kind {
+            ExprKind::Ident(name) => {
                 let arg_values = args
                     .iter()
-                    .map(|arg| eval_expr_in_environment_scope(arg, state, locals, def, depth))
+                    .map(|arg| eval_expr_in_environment_scope(arg, state, locals, scope, depth))
                     .collect::<Result<Vec<_>, _>>()?;
                 if let Some(value) = eval_ident_call(name, &arg_values)? {
                     return Ok(value);
                 }
-                if let Some((params, body)) = def.statements.iter().find_map(|stmt| match stmt {
+                if let Some((params, body)) = scope.statements.iter().find_map(|stmt| match stmt {
                     MaterialStatement::Function {
                         name: fn_name,
                         params,
                         body,
+                        ..
                     } if fn_name == name => Some((params.clone(), body.clone())),
                     _ => None,
                 }) {
@@ -3838,7 +4187,7 @@ fn eval_expr_in_environment_scope(
                     }
                     return eval_environment_function_body(
                         state,
-                        def,
+                        scope,
                         &params,
                         &body,
                         &arg_values,
@@ -3853,11 +4202,11 @@ fn eval_expr_in_environment_scope(
                 }
                 Err(EvalError::UnsupportedCall)
             }
-            Expr::Member { .. } => {
+            ExprKind::Member { .. } => {
                 let flattened = flatten_member_expr(callee).ok_or(EvalError::UnsupportedCall)?;
                 let arg_values = args
                     .iter()
-                    .map(|arg| eval_expr_in_environment_scope(arg, state, locals, def, depth))
+                    .map(|arg| eval_expr_in_environment_scope(arg, state, locals, scope, depth))
                     .collect::<Result<Vec<_>, _>>()?;
                 if let Some(top) = state.function_defs.get(&flattened)
                     && let Some(value) =
@@ -3869,6 +4218,27 @@ fn eval_expr_in_environment_scope(
             }
             _ => Err(EvalError::UnsupportedCall),
         },
+        ExprKind::If {
+            cond,
+            then_branch,
+            else_branch,
+        } => {
+            let cond = eval_expr_in_environment_scope(cond, state, locals, scope, depth)?;
+            let branch = if is_truthy(&cond)? {
+                then_branch
+            } else {
+                else_branch
+            };
+            eval_expr_in_environment_scope(branch, state, locals, scope, depth)
+        }
+        ExprKind::For {
+            var,
+            start,
+            end,
+            body,
+        } => eval_for_expr(var, start, end, body, locals, |expr, locals| {
+            eval_expr_in_environment_scope(expr, state, locals, scope, depth)
+        }),
         _ => eval_expr_in_material_scope(expr, state, locals, None, depth),
     }
 }
@@ -3985,51 +4355,93 @@ fn rewrite_material_stmt_for_jit(
     stmt: &MaterialFunctionStatement,
 ) -> Option<MaterialFunctionStatement> {
     Some(match stmt {
-        MaterialFunctionStatement::Binding { name, expr } => MaterialFunctionStatement::Binding {
+        MaterialFunctionStatement::Binding {
+            name,
+            mutable,
+            expr,
+        } => MaterialFunctionStatement::Binding {
             name: name.clone(),
+            mutable: *mutable,
             expr: rewrite_expr_for_jit(expr)?,
         },
+        MaterialFunctionStatement::Assign { name, expr } => MaterialFunctionStatement::Assign {
+            name: name.clone(),
+            expr: rewrite_expr_for_jit(expr)?,
+        },
+        MaterialFunctionStatement::Break => MaterialFunctionStatement::Break,
         MaterialFunctionStatement::Return { expr } => MaterialFunctionStatement::Return {
             expr: rewrite_expr_for_jit(expr)?,
         },
+        MaterialFunctionStatement::If {
+            cond,
+            then_body,
+            else_body,
+        } => MaterialFunctionStatement::If {
+            cond: rewrite_expr_for_jit(cond)?,
+            then_body: rewrite_material_body_for_jit(then_body)?,
+            else_body: rewrite_material_body_for_jit(else_body)?,
+        },
+        MaterialFunctionStatement::For {
+            var,
+            start,
+            end,
+            body,
+        } => MaterialFunctionStatement::For {
+            var: var.clone(),
+            start: rewrite_expr_for_jit(start)?,
+            end: rewrite_expr_for_jit(end)?,
+            body: rewrite_material_body_for_jit(body)?,
+        },
     })
 }
 
 fn rewrite_expr_for_jit(expr: &Expr) -> Option<Expr> {
-    Some(match expr {
-        Expr::Number(_) | Expr::String(_) | Expr::Ident(_) => expr.clone(),
-        Expr::Array(items) => Expr::Array(
+    let kind = match &expr.kind {
+        ExprKind::Number(_) | ExprKind::String(_) | ExprKind::Ident(_) => {
+            return Some(expr.clone());
+        }
+        ExprKind::Array(items) => ExprKind::Array(
             items
                 .iter()
                 .map(rewrite_expr_for_jit)
                 .collect::<Option<Vec<_>>>()?,
         ),
-        Expr::ObjectLiteral { type_name, fields } => Expr::ObjectLiteral {
+        ExprKind::ObjectLiteral { type_name, fields } => ExprKind::ObjectLiteral {
             type_name: type_name.clone(),
             fields: fields
                 .iter()
                 .map(|(name, expr)| Some((name.clone(), rewrite_expr_for_jit(expr)?)))
                 .collect::<Option<Vec<_>>>()?,
         },
-        Expr::Binary { lhs, op, rhs } => Expr::Binary {
+        ExprKind::Binary { lhs, op, rhs } => ExprKind::Binary {
             lhs: Box::new(rewrite_expr_for_jit(lhs)?),
             op: *op,
             rhs: Box::new(rewrite_expr_for_jit(rhs)?),
         },
-        Expr::Unary { op, expr } => Expr::Unary {
+        ExprKind::Unary { op, expr } => ExprKind::Unary {
             op: *op,
             expr: Box::new(rewrite_expr_for_jit(expr)?),
         },
-        Expr::Call { callee, args } => Expr::Call {
+        ExprKind::If {
+            cond,
+            then_branch,
+            else_branch,
+        } => ExprKind::If {
+            cond: Box::new(rewrite_expr_for_jit(cond)?),
+            then_branch: Box::new(rewrite_expr_for_jit(then_branch)?),
+            else_branch: Box::new(rewrite_expr_for_jit(else_branch)?),
+        },
+        ExprKind::Call { callee, args } => ExprKind::Call {
             callee: Box::new(rewrite_expr_for_jit(callee)?),
             args: args
                 .iter()
                 .map(rewrite_expr_for_jit)
                 .collect::<Option<Vec<_>>>()?,
         },
-        Expr::Member { .. } => Expr::Ident(flatten_member_expr(expr)?),
-        Expr::FunctionLiteral { .. } => return None,
-    })
+        ExprKind::Member { .. } => ExprKind::Ident(flatten_member_expr(expr)?),
+        ExprKind::FunctionLiteral { .. } | ExprKind::For { .. } => return None,
+    };
+    Some(Expr::new(kind, expr.span))
 }
 
 fn numeric_vm_args(function: &VmFunction, locals: &HashMap<String, Value>) -> Option<Vec<f32>> {
@@ -4100,88 +4512,111 @@ fn execute_vm_function(
     }
     let mut stack = Vec::new();
 
-    for instruction in &function.code {
-        match instruction {
-            VmInstruction::PushNumber(v) => stack.push(Value::Number(*v)),
-            VmInstruction::PushString(v) => stack.push(Value::String(v.clone())),
-            VmInstruction::LoadName(name) => {
-                if let Some(value) = locals.get(name) {
-                    stack.push(value.clone());
-                } else if let Some(value) = builtin_symbol_value(name) {
-                    stack.push(value);
-                } else if let Some(binding) = state.bindings.get(name) {
-                    stack.push(binding.value.clone());
-                } else {
-                    return Err(EvalError::UndefinedIdentifier(name.clone()));
+    let mut pc = 0;
+    let mut run = || -> Result<Value, EvalError> {
+        while let Some(instruction) = function.code.get(pc) {
+            pc += 1;
+            match instruction {
+                VmInstruction::PushNumber(v) => stack.push(Value::Number(*v)),
+                VmInstruction::PushString(v) => stack.push(Value::String(v.clone())),
+                VmInstruction::LoadName(name) => {
+                    if let Some(value) = locals.get(name) {
+                        stack.push(value.clone());
+                    } else if let Some(value) = builtin_symbol_value(name) {
+                        stack.push(value);
+                    } else if let Some(binding) = state.bindings.get(name) {
+                        stack.push(binding.value.clone());
+                    } else {
+                        return Err(EvalError::UndefinedIdentifier(name.clone()));
+                    }
                 }
-            }
-            VmInstruction::BuildArray(count) => {
-                if stack.len() < *count {
-                    return Err(EvalError::UnsupportedCall);
+                VmInstruction::BuildArray(count) => {
+                    if stack.len() < *count {
+                        return Err(EvalError::UnsupportedCall);
+                    }
+                    let start = stack.len() - *count;
+                    let values = stack.drain(start..).collect::<Vec<_>>();
+                    stack.push(Value::Array(values));
                 }
-                let start = stack.len() - *count;
-                let values = stack.drain(start..).collect::<Vec<_>>();
-                stack.push(Value::Array(values));
-            }
-            VmInstruction::BuildObject {
-                type_name,
-                field_names,
-            } => {
-                if stack.len() < field_names.len() {
-                    return Err(EvalError::UnsupportedCall);
+                VmInstruction::BuildObject {
+                    type_name,
+                    field_names,
+                } => {
+                    if stack.len() < field_names.len() {
+                        return Err(EvalError::UnsupportedCall);
+                    }
+                    let start = stack.len() - field_names.len();
+                    let values = stack.drain(start..).collect::<Vec<_>>();
+                    let mut fields = HashMap::new();
+                    for (name, value) in field_names.iter().cloned().zip(values.into_iter()) {
+                        fields.insert(name, value);
+                    }
+                    augment_object_literal_fields(state, type_name, &mut fields)?;
+                    stack.push(Value::Object(ObjectValue {
+                        type_name: Some(type_name.clone()),
+                        fields,
+                    }));
                 }
-                let start = stack.len() - field_names.len();
-                let values = stack.drain(start..).collect::<Vec<_>>();
-                let mut fields = HashMap::new();
-                for (name, value) in field_names.iter().cloned().zip(values.into_iter()) {
-                    fields.insert(name, value);
+                VmInstruction::LoadMember(field) => {
+                    let value = stack.pop().ok_or(EvalError::UnsupportedCall)?;
+                    let obj = as_object(&value)?;
+                    let member = obj
+                        .fields
+                        .get(field)
+                        .cloned()
+                        .ok_or_else(|| EvalError::UndefinedIdentifier(field.clone()))?;
+                    stack.push(member);
                 }
-                augment_object_literal_fields(state, type_name, &mut fields)?;
-                stack.push(Value::Object(ObjectValue {
-                    type_name: Some(type_name.clone()),
-                    fields,
-                }));
-            }
-            VmInstruction::LoadMember(field) => {
-                let value = stack.pop().ok_or(EvalError::UnsupportedCall)?;
-                let obj = as_object(&value)?;
-                let member = obj
-                    .fields
-                    .get(field)
-                    .cloned()
-                    .ok_or_else(|| EvalError::UndefinedIdentifier(field.clone()))?;
-                stack.push(member);
-            }
-            VmInstruction::Unary(op) => {
-                let value = stack.pop().ok_or(EvalError::UnsupportedCall)?;
-                stack.push(eval_unary(*op, value)?);
-            }
-            VmInstruction::Binary(op) => {
-                let rhs = stack.pop().ok_or(EvalError::UnsupportedCall)?;
-                let lhs = stack.pop().ok_or(EvalError::UnsupportedCall)?;
-                stack.push(eval_binary(lhs, *op, rhs)?);
-            }
-            VmInstruction::CallNamed { name, argc } => {
-                if stack.len() < *argc {
-                    return Err(EvalError::UnsupportedCall);
+                VmInstruction::Unary(op) => {
+                    let value = stack.pop().ok_or(EvalError::UnsupportedCall)?;
+                    stack.push(eval_unary(*op, value)?);
+                }
+                VmInstruction::Binary(op) => {
+                    let rhs = stack.pop().ok_or(EvalError::UnsupportedCall)?;
+                    let lhs = stack.pop().ok_or(EvalError::UnsupportedCall)?;
+                    stack.push(eval_binary(lhs, *op, rhs)?);
+                }
+                VmInstruction::CallNamed { name, argc } => {
+                    if stack.len() < *argc {
+                        return Err(EvalError::UnsupportedCall);
+                    }
+                    let start = stack.len() - *argc;
+                    let args = stack.drain(start..).collect::<Vec<_>>();
+                    stack.push(eval_named_vm_call(state, name, &args, depth)?);
+                }
+                VmInstruction::StoreLocal(name) => {
+                    let value = stack.pop().ok_or(EvalError::UnsupportedCall)?;
+                    locals.insert(name.clone(), value);
+                }
+                VmInstruction::Jump(target) => pc = *target,
+                VmInstruction::JumpIfFalse(target) => {
+                    let value = stack.pop().ok_or(EvalError::UnsupportedCall)?;
+                    if !is_truthy(&value)? {
+                        pc = *target;
+                    }
+                }
+                VmInstruction::Return => {
+                    return stack.pop().ok_or(EvalError::UnsupportedCall);
                 }
-                let start = stack.len() - *argc;
-                let args = stack.drain(start..).collect::<Vec<_>>();
-                stack.push(eval_named_vm_call(state, name, &args, depth)?);
-            }
-            VmInstruction::StoreLocal(name) => {
-                let value = stack.pop().ok_or(EvalError::UnsupportedCall)?;
-                locals.insert(name.clone(), value);
-            }
-            VmInstruction::Return => {
-                return stack.pop().ok_or(EvalError::UnsupportedCall);
             }
         }
-    }
 
-    Err(EvalError::UndefinedIdentifier(
-        "vm function missing return".to_string(),
-    ))
+        Err(EvalError::UndefinedIdentifier(
+            "vm function missing return".to_string(),
+        ))
+    };
+    run().map_err(|error| locate_vm_error(function, pc, error))
+}
+
+/// Locates an error at the source of the VM instruction before `pc`, the one that failed.
+fn locate_vm_error(function: &VmFunction, pc: usize, error: EvalError) -> EvalError {
+    match pc
+        .checked_sub(1)
+        .and_then(|index| function.spans.get(index))
+    {
+        Some(span) => error.at(*span),
+        None => error,
+    }
 }
 
 fn eval_top_level_function_call(
@@ -4208,23 +4643,188 @@ fn eval_top_level_function_call(
         locals.insert(param.clone(), value.clone());
     }
 
-    for stmt in &def.body {
+    let value = exec_function_body(&def.body, &mut locals, None, &mut |expr, locals| {
+        eval_expr_in_material_scope(expr, state, locals, None, depth)
+    })?
+    .ok_or_else(|| {
+        EvalError::UndefinedIdentifier("function missing return".to_string()).at(def.span)
+    })?;
+    Ok(Some(value))
+}
+
+/// Runs the statements of a Forge function body and yields the first returned value.
+///
+/// `if` and `for` blocks are lexically scoped: bindings made inside a block are dropped
+/// again when the block ends, while assignments to outer `var` bindings persist.
+/// Bindings whose names appear in `overrides` keep the override value.
+fn exec_function_body(
+    body: &[MaterialFunctionStatement],
+    locals: &mut HashMap<String, Value>,
+    overrides: Option<&ObjectValue>,
+    eval: &mut impl FnMut(&Expr, &HashMap<String, Value>) -> Result<Value, EvalError>,
+) -> Result<Option<Value>, EvalError> {
+    let mut mutable = HashSet::new();
+    match exec_function_block(body, locals, &mut mutable, overrides, eval)? {
+        BodyFlow::Return(value) => Ok(Some(*value)),
+        BodyFlow::Next | BodyFlow::Break => Ok(None),
+    }
+}
+
+enum BodyFlow {
+    Next,
+    Break,
+    Return(Box<Value>),
+}
+
+fn exec_function_block(
+    body: &[MaterialFunctionStatement],
+    locals: &mut HashMap<String, Value>,
+    mutable: &mut HashSet<String>,
+    overrides: Option<&ObjectValue>,
+    eval: &mut impl FnMut(&Expr, &HashMap<String, Value>) -> Result<Value, EvalError>,
+) -> Result<BodyFlow, EvalError> {
+    for stmt in body {
         match stmt {
-            MaterialFunctionStatement::Binding { name, expr } => {
-                let value = eval_expr_in_material_scope(expr, state, &locals, None, depth)?;
+            MaterialFunctionStatement::Binding {
+                name,
+                mutable: is_mutable,
+                expr,
+            } => {
+                let value = match overrides.and_then(|object| object.fields.get(name)) {
+                    Some(value) => value.clone(),
+                    None => eval(expr, locals)?,
+                };
+                locals.insert(name.clone(), value);
+                if *is_mutable {
+                    mutable.insert(name.clone());
+                } else {
+                    mutable.remove(name);
+                }
+            }
+            MaterialFunctionStatement::Assign { name, expr } => {
+                if !mutable.contains(name) {
+                    return Err(EvalError::ImmutableBinding(name.clone()));
+                }
+                let value = eval(expr, locals)?;
                 locals.insert(name.clone(), value);
             }
             MaterialFunctionStatement::Return { expr } => {
-                return Ok(Some(eval_expr_in_material_scope(
-                    expr, state, &locals, None, depth,
-                )?));
+                return Ok(BodyFlow::Return(Box::new(eval(expr, locals)?)));
+            }
+            MaterialFunctionStatement::Break => return Ok(BodyFlow::Break),
+            MaterialFunctionStatement::If {
+                cond,
+                then_body,
+                else_body,
+            } => {
+                let block = if is_truthy(&eval(cond, locals)?)? {
+                    then_body
+                } else {
+                    else_body
+                };
+                let flow = exec_scoped_block(block, None, locals, mutable, overrides, eval)?;
+                if !matches!(flow, BodyFlow::Next) {
+                    return Ok(flow);
+                }
+            }
+            MaterialFunctionStatement::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                let indices = loop_indices(eval(start, locals)?, eval(end, locals)?)?;
+                for index in indices {
+                    match exec_scoped_block(
+                        body,
+                        Some((var, index)),
+                        locals,
+                        mutable,
+                        overrides,
+                        eval,
+                    )? {
+                        BodyFlow::Next => {}
+                        BodyFlow::Break => break,
+                        flow => return Ok(flow),
+                    }
+                }
             }
         }
     }
+    Ok(BodyFlow::Next)
+}
+
+/// Yields the indices of `start..end`: `start + k` for each whole `k` below `end - start`,
+/// capped at `MAX_LOOP_ITERATIONS`. The VM and the JIT count loops the same way.
+fn loop_indices(start: Value, end: Value) -> Result<impl Iterator<Item = f32>, EvalError> {
+    let (Value::Number(start), Value::Number(end)) = (start, end) else {
+        return Err(EvalError::BinaryTypeMismatch);
+    };
+    let count = (end - start).ceil().clamp(0.0, MAX_LOOP_ITERATIONS as f32) as usize;
+    Ok((0..count).map(move |i| start + i as f32))
+}
 
-    Err(EvalError::UndefinedIdentifier(
-        "function missing return".to_string(),
-    ))
+/// Evaluates `for var in start..end { body }` into an array with one item per index.
+fn eval_for_expr(
+    var: &str,
+    start: &Expr,
+    end: &Expr,
+    body: &Expr,
+    locals: &HashMap<String, Value>,
+    mut eval: impl FnMut(&Expr, &HashMap<String, Value>) -> Result<Value, EvalError>,
+) -> Result<Value, EvalError> {
+    let indices = loop_indices(eval(start, locals)?, eval(end, locals)?)?;
+    let mut body_locals = locals.clone();
+    let mut items = Vec::new();
+    for index in indices {
+        body_locals.insert(var.to_string(), Value::Number(index));
+        items.push(eval(body, &body_locals)?);
+    }
+    Ok(Value::Array(items))
+}
+
+/// Runs a nested block, optionally binding a loop variable, and restores every name the
+/// block shadowed once it finishes.
+fn exec_scoped_block(
+    block: &[MaterialFunctionStatement],
+    loop_var: Option<(&String, f32)>,
+    locals: &mut HashMap<String, Value>,
+    mutable: &mut HashSet<String>,
+    overrides: Option<&ObjectValue>,
+    eval: &mut impl FnMut(&Expr, &HashMap<String, Value>) -> Result<Value, EvalError>,
+) -> Result<BodyFlow, EvalError> {
+    let shadowed = loop_var
+        .map(|(name, _)| name)
+        .into_iter()
+        .chain(block.iter().filter_map(|stmt| match stmt {
+            MaterialFunctionStatement::Binding { name, .. } => Some(name),
+            _ => None,
+        }))
+        .map(|name| {
+            (
+                name.clone(),
+                locals.get(name).cloned(),
+                mutable.contains(name),
+            )
+        })
+        .collect::<Vec<_>>();
+    if let Some((name, index)) = loop_var {
+        locals.insert(name.clone(), Value::Number(index));
+        mutable.remove(name);
+    }
+    let flow = exec_function_block(block, locals, mutable, overrides, eval);
+    for (name, previous, was_mutable) in shadowed.into_iter().rev() {
+        match previous {
+            Some(value) => locals.insert(name.clone(), value),
+            None => locals.remove(&name),
+        };
+        if was_mutable {
+            mutable.insert(name);
+        } else {
+            mutable.remove(&name);
+        }
+    }
+    flow
 }
 
 fn map_value1(
@@ -4589,7 +5189,7 @@ fn anchor_sign(op: BinaryOp) -> f32 {
     match op {
         BinaryOp::Add => 1.0,
         BinaryOp::Sub => -1.0,
-        BinaryOp::Intersect | BinaryOp::Mul | BinaryOp::Div => unreachable!(),
+        _ => unreachable!(),
     }
 }
 
@@ -4781,6 +5381,11 @@ fn bounds_from_value(value: &Value, pos: [f32; 3]) -> Option<Bounds3> {
     }
 }
 
+/// Center of an object's layout bounds, used to aim at objects and parts outside evaluation.
+pub(crate) fn object_center(value: &Value) -> Option<[f32; 3]> {
+    object_bounds(value).map(|bounds| bounds.center())
+}
+
 fn object_bounds(value: &Value) -> Option<Bounds3> {
     let obj = as_object(value).ok()?;
     let pos = object_position(value);
@@ -4856,6 +5461,15 @@ fn object_bounds(value: &Value) -> Option<Bounds3> {
             object_bounds(obj.fields.get("lhs")?)?.union(object_bounds(obj.fields.get("rhs")?)?),
         ),
         "sub" => object_bounds(obj.fields.get("lhs")?),
+        "union_all" => {
+            let Value::Array(items) = obj.fields.get("items")? else {
+                return None;
+            };
+            items
+                .iter()
+                .map(object_bounds)
+                .reduce(|lhs, rhs| Some(lhs?.union(rhs?)))?
+        }
         "union_round" | "union_chamfer" | "union_columns" | "union_stairs" | "union_soft"
         | "intersect_round" | "intersect_chamfer" | "intersect_columns" | "intersect_stairs"
         | "diff_round" | "diff_chamfer" | "diff_columns" | "diff_stairs" | "pipe" | "engrave"
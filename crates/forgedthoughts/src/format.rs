@@ -0,0 +1,1277 @@
+//! Pretty-printer from [`Program`] back to Forge source.
+//!
+//! Output is laid out from the AST alone. When the original source is available, its
+//! tokens are used to put comments and blank lines back between statements and entries
+//! and to keep the spelling of number and hex color literals.
+
+use crate::ast::{
+    BinaryOp, Expr, ExprKind, MaterialFunctionStatement, MaterialStatement, Program, SdfStatement,
+    SkeletonStatement, Span, Statement, UnaryOp,
+};
+use crate::lexer::{Comment, Token, TokenKind, tokenize_with_comments};
+use crate::parser::{ParseError, parse_hex_color, parse_program};
+
+const INDENT: &str = "  ";
+const MAX_WIDTH: usize = 100;
+/// How many source literals a printed number may skip ahead to find its own spelling.
+const LITERAL_LOOKAHEAD: usize = 4;
+
+/// Formats `source` in canonical Forge style. Comments and single blank lines between
+/// statements are kept, as is the spelling of each number and hex color.
+///
+/// Comments between object fields, list items and call arguments stay next to their
+/// entry. An expression with a comment anywhere else, such as between the operands of a
+/// binary expression, is kept as written.
+pub fn format_source(source: &str) -> Result<String, ParseError> {
+    let program = parse_program(source)?;
+    let (tokens, comments) = tokenize_with_comments(source)?;
+    Ok(Formatter::new(Some(Layout::new(source, tokens, comments))).program(&program))
+}
+
+/// Formats a program without its source, e.g. one built or rewritten in code. Numbers
+/// are printed in their shortest decimal form and hex colors as `vec3` objects.
+#[must_use]
+pub fn format_program(program: &Program) -> String {
+    Formatter::new(None).program(program)
+}
+
+/// Token positions of the original source, used to place comments and recover literal
+/// spellings while printing.
+struct Layout<'a> {
+    source: &'a str,
+    tokens: Vec<Token>,
+    comments: Vec<Comment>,
+    /// Number and hex color tokens, in source order.
+    literals: Vec<usize>,
+    /// `fn` tokens that start function literals, in source order.
+    function_literals: Vec<usize>,
+}
+
+/// A statement or definition entry in the source, as token index and byte range.
+#[derive(Debug, Clone, Copy)]
+struct Item {
+    token: usize,
+    start: usize,
+    end: usize,
+}
+
+/// A `{ ... }` body in the source and the items directly inside it.
+#[derive(Debug, Clone)]
+struct Block {
+    open: usize,
+    items: Vec<Item>,
+    close: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EntryKind {
+    Metadata,
+    Model,
+    Statement,
+}
+
+impl<'a> Layout<'a> {
+    fn new(source: &'a str, tokens: Vec<Token>, comments: Vec<Comment>) -> Self {
+        let literals = tokens
+            .iter()
+            .enumerate()
+            .filter(|(_, token)| {
+                matches!(token.kind, TokenKind::Number(_) | TokenKind::HexColor(_))
+            })
+            .map(|(index, _)| index)
+            .collect();
+        let function_literals = tokens
+            .windows(2)
+            .enumerate()
+            .filter(|(_, pair)| pair[0].kind == TokenKind::Fn && pair[1].kind == TokenKind::LParen)
+            .map(|(index, _)| index)
+            .collect();
+        Self {
+            source,
+            tokens,
+            comments,
+            literals,
+            function_literals,
+        }
+    }
+
+    fn kind(&self, index: usize) -> Option<&TokenKind> {
+        self.tokens.get(index).map(|token| &token.kind)
+    }
+
+    fn offset(&self, index: usize) -> usize {
+        self.tokens
+            .get(index)
+            .map_or(self.source.len(), |t| t.start)
+    }
+
+    fn token_at(&self, offset: usize) -> usize {
+        self.tokens.partition_point(|token| token.start < offset)
+    }
+
+    /// Start of the `key: value` object field whose value is `value`.
+    fn field_start(&self, value: &Expr) -> usize {
+        self.offset(self.token_at(value.span.start).saturating_sub(2))
+    }
+
+    /// Source spelling of the literal token at `index`.
+    fn lexeme(&self, index: usize) -> String {
+        match self.kind(index) {
+            Some(TokenKind::HexColor(hex)) => format!("#{hex}"),
+            _ => {
+                let rest = &self.source[self.offset(index)..];
+                let len = rest
+                    .char_indices()
+                    .find(|&(i, ch)| {
+                        !(ch.is_ascii_digit() || (ch == '.' && !rest[i + 1..].starts_with('.')))
+                    })
+                    .map_or(rest.len(), |(i, _)| i);
+                rest[..len].to_string()
+            }
+        }
+    }
+
+    /// The `{` opening the body that follows `from`, or `None` when the construct has an
+    /// `= expr;` body instead.
+    fn find_block(&self, from: usize) -> Option<usize> {
+        let mut depth = 0_usize;
+        let mut index = from;
+        loop {
+            match self.kind(index)? {
+                TokenKind::LParen | TokenKind::LBracket => depth += 1,
+                TokenKind::RParen | TokenKind::RBracket => depth = depth.checked_sub(1)?,
+                TokenKind::LBrace if depth == 0 => return Some(index),
+                TokenKind::Equal | TokenKind::Semicolon if depth == 0 => return None,
+                _ => {}
+            }
+            index += 1;
+        }
+    }
+
+    fn block_after(&self, from: usize) -> Option<Block> {
+        self.block(self.find_block(from)?)
+    }
+
+    fn block(&self, open: usize) -> Option<Block> {
+        if self.kind(open) != Some(&TokenKind::LBrace) {
+            return None;
+        }
+        let mut items = Vec::new();
+        let mut index = open + 1;
+        while self.kind(index)? != &TokenKind::RBrace {
+            let last = self.item_end(index)?;
+            items.push(Item {
+                token: index,
+                start: self.offset(index),
+                end: self.offset(last) + 1,
+            });
+            index = last + 1;
+        }
+        Some(Block {
+            open,
+            items,
+            close: index,
+        })
+    }
+
+    /// Last token of the item starting at `first`: its `;`, or the closing `}` of an
+    /// `fn`, `if` or `for` body.
+    fn item_end(&self, first: usize) -> Option<usize> {
+        let has_body = matches!(
+            self.kind(first),
+            Some(TokenKind::Fn | TokenKind::If | TokenKind::For)
+        );
+        let mut expr_body = false;
+        let mut depth = 0_usize;
+        let mut index = first;
+        loop {
+            match self.kind(index)? {
+                TokenKind::LParen | TokenKind::LBracket | TokenKind::LBrace => depth += 1,
+                TokenKind::RParen | TokenKind::RBracket => depth = depth.checked_sub(1)?,
+                TokenKind::RBrace => {
+                    depth = depth.checked_sub(1)?;
+                    if depth == 0
+                        && has_body
+                        && !expr_body
+                        && self.kind(index + 1) != Some(&TokenKind::Else)
+                    {
+                        return Some(index);
+                    }
+                }
+                TokenKind::Equal if depth == 0 => expr_body = true,
+                TokenKind::Semicolon if depth == 0 => return Some(index),
+                _ => {}
+            }
+            index += 1;
+        }
+    }
+
+    /// The `if` token continuing an `else if` chain after the body closed at `close`.
+    fn else_if(&self, close: usize) -> Option<usize> {
+        if self.kind(close + 1) != Some(&TokenKind::Else) {
+            return None;
+        }
+        match self.kind(close + 2)? {
+            TokenKind::If => Some(close + 2),
+            TokenKind::LBrace => match self.block(close + 2)?.items.as_slice() {
+                [item] if self.kind(item.token) == Some(&TokenKind::If) => Some(item.token),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn else_block(&self, close: usize) -> Option<Block> {
+        if self.kind(close + 1) != Some(&TokenKind::Else) {
+            return None;
+        }
+        self.block(close + 2)
+    }
+
+    fn entry_kind(&self, item: &Item) -> EntryKind {
+        match (self.kind(item.token), self.kind(item.token + 1)) {
+            (Some(TokenKind::Ident(name)), Some(TokenKind::Colon)) if name == "model" => {
+                EntryKind::Model
+            }
+            (Some(TokenKind::Ident(_)), Some(TokenKind::Colon)) => EntryKind::Metadata,
+            _ => EntryKind::Statement,
+        }
+    }
+}
+
+/// Progress through the layout, saved and restored when an expression is printed twice
+/// to try both layouts.
+#[derive(Debug, Clone, Copy, Default)]
+struct Cursor {
+    comment: usize,
+    literal: usize,
+    function_literal: usize,
+    /// End of the last item or comment printed, for blank line detection.
+    last_end: usize,
+}
+
+enum Postfix<'p> {
+    Field(&'p str),
+    /// Arguments and the end of the call, after its `)`.
+    Call(&'p [Expr], usize),
+}
+
+enum Entry<'p> {
+    Metadata(&'p str, &'p Expr),
+    Model(&'p str),
+    Material(&'p MaterialStatement),
+    Sdf(&'p SdfStatement),
+    Skeleton(&'p SkeletonStatement),
+}
+
+struct Formatter<'a> {
+    layout: Option<Layout<'a>>,
+    cursor: Cursor,
+    out: String,
+    indent: usize,
+}
+
+impl<'a> Formatter<'a> {
+    fn new(layout: Option<Layout<'a>>) -> Self {
+        Self {
+            layout,
+            cursor: Cursor::default(),
+            out: String::new(),
+            indent: 0,
+        }
+    }
+
+    fn program(mut self, program: &Program) -> String {
+        let items = self
+            .layout
+            .as_ref()
+            .filter(|_| program.spans.len() == program.statements.len())
+            .map(|layout| {
+                program
+                    .spans
+                    .iter()
+                    .map(|span| Item {
+                        token: layout.token_at(span.start),
+                        start: span.start,
+                        end: span.end,
+                    })
+                    .collect::<Vec<_>>()
+            });
+        let end = self.layout.as_ref().map_or(0, |layout| layout.source.len());
+        // Without a source, separate definitions from their neighbours by a blank line.
+        let mut previous_definition = None;
+        self.sequence(
+            &program.statements,
+            items.as_deref().map(|items| (items, end)),
+            |f, stmt, item| {
+                if item.is_none() {
+                    let definition = !matches!(
+                        stmt,
+                        Statement::Import { .. }
+                            | Statement::Export(_)
+                            | Statement::Binding { .. }
+                            | Statement::Assign { .. }
+                    );
+                    if previous_definition.is_some_and(|previous| previous || definition) {
+                        f.out.push('\n');
+                    }
+                    previous_definition = Some(definition);
+                }
+                f.statement(stmt, item.map(|item| item.token));
+            },
+        );
+        self.out
+    }
+
+    /// Prints `entries` one after another. With `items`, which match `entries` one for
+    /// one, comments before `end` are printed in between.
+    fn sequence<T>(
+        &mut self,
+        entries: &[T],
+        items: Option<(&[Item], usize)>,
+        mut print: impl FnMut(&mut Self, &T, Option<Item>),
+    ) {
+        let mut first = true;
+        for (index, entry) in entries.iter().enumerate() {
+            let item = items.map(|(items, _)| items[index]);
+            if let Some(item) = item {
+                self.comments_before(item.start, &mut first);
+                if !first && self.blank_line_before(item.start) {
+                    self.out.push('\n');
+                }
+            }
+            print(self, entry, item);
+            if let Some(item) = item {
+                self.trailing_comments(item.end);
+            }
+            first = false;
+        }
+        if let Some((_, end)) = items {
+            self.comments_before(end, &mut first);
+        }
+    }
+
+    fn comments_before(&mut self, offset: usize, first: &mut bool) {
+        while let Some(comment) = self.next_comment(offset) {
+            if !*first && self.blank_line_before(comment.start) {
+                self.out.push('\n');
+            }
+            self.line(&comment.text);
+            self.cursor.last_end = comment.end;
+            *first = false;
+        }
+    }
+
+    /// Prints comments left inside the item that ends at `end` on their own lines, or
+    /// keeps a comment on the item's last line at the end of that line.
+    fn trailing_comments(&mut self, end: usize) {
+        let mut moved = false;
+        while let Some(comment) = self.next_comment(end) {
+            self.line(&comment.text);
+            moved = true;
+        }
+        self.cursor.last_end = end;
+        let Some(layout) = &self.layout else {
+            return;
+        };
+        let next_token = layout.offset(layout.token_at(end));
+        if let Some(comment) = layout.comments.get(self.cursor.comment)
+            && !moved
+            && comment.start < next_token
+            && !layout.source[end..comment.start].contains('\n')
+        {
+            self.out.pop();
+            self.out.push(' ');
+            self.out.push_str(&comment.text);
+            self.out.push('\n');
+            self.cursor.last_end = comment.end;
+            self.cursor.comment += 1;
+        }
+    }
+
+    /// Prints the comments before `offset` on their own lines at `pad`, e.g. between
+    /// object fields.
+    fn comment_lines(&mut self, offset: usize, pad: &str, text: &mut String) {
+        while let Some(comment) = self.next_comment(offset) {
+            text.push_str(pad);
+            text.push_str(&comment.text);
+            text.push('\n');
+        }
+    }
+
+    /// Ends a field or list item line that ended at `end` in the source, with its `,`
+    /// when `more` follow and any comment after it on the same line before `next`.
+    fn entry_end(&mut self, more: bool, end: usize, next: usize, text: &mut String) {
+        if more {
+            text.push(',');
+        }
+        if let Some(layout) = &self.layout
+            && let Some(comment) = layout.comments.get(self.cursor.comment)
+            && comment.start < next
+            && layout
+                .source
+                .get(end..comment.start)
+                .is_some_and(|gap| !gap.contains('\n'))
+        {
+            text.push(' ');
+            text.push_str(&comment.text);
+            self.cursor.comment += 1;
+        }
+        text.push('\n');
+    }
+
+    /// Source text of `expr` when it holds a comment that has no place in the printed
+    /// layout, such as between the operands of a binary expression. The comments and
+    /// literals inside are consumed.
+    fn verbatim(&mut self, expr: &Expr) -> Option<String> {
+        let layout = self.layout.as_ref()?;
+        let span = expr.span;
+        let stray = layout
+            .comments
+            .iter()
+            .skip(self.cursor.comment)
+            .take_while(|comment| comment.start < span.end)
+            .any(|comment| comment.start >= span.start && is_stray_comment(expr, comment.start));
+        if !stray {
+            return None;
+        }
+        let text = layout.source.get(span.start..span.end)?.to_string();
+        let comment = layout
+            .comments
+            .partition_point(|comment| comment.start < span.end);
+        let literal = layout
+            .literals
+            .partition_point(|&index| layout.offset(index) < span.end);
+        let function_literal = layout
+            .function_literals
+            .partition_point(|&index| layout.offset(index) < span.end);
+        self.cursor.comment = comment;
+        self.cursor.literal = self.cursor.literal.max(literal);
+        self.cursor.function_literal = function_literal;
+        Some(text)
+    }
+
+    fn next_comment(&mut self, before: usize) -> Option<Comment> {
+        let comment = self
+            .layout
+            .as_ref()?
+            .comments
+            .get(self.cursor.comment)
+            .filter(|comment| comment.start < before)?
+            .clone();
+        self.cursor.comment += 1;
+        Some(comment)
+    }
+
+    fn blank_line_before(&self, offset: usize) -> bool {
+        self.layout.as_ref().is_some_and(|layout| {
+            layout
+                .source
+                .get(self.cursor.last_end..offset)
+                .is_some_and(|gap| gap.matches('\n').count() >= 2)
+        })
+    }
+
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.indent {
+            self.out.push_str(INDENT);
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn block_after(&self, token: Option<usize>) -> Option<Block> {
+        self.layout.as_ref()?.block_after(token? + 1)
+    }
+
+    fn statement(&mut self, stmt: &Statement, at: Option<usize>) {
+        match stmt {
+            Statement::Import { path, alias } => match alias {
+                Some(alias) => self.line(&format!("import {} as {alias};", quote(path))),
+                None => self.line(&format!("import {};", quote(path))),
+            },
+            Statement::Export(names) if names.is_empty() => self.line("export {};"),
+            Statement::Export(names) => self.line(&format!("export {{ {} }};", names.join(", "))),
+            Statement::Binding {
+                name,
+                mutable,
+                expr,
+            } => self.assignment(&format!("{} {name} = ", binding_keyword(*mutable)), expr),
+            Statement::Assign { path, expr } => {
+                self.assignment(&format!("{} = ", path.join(".")), expr);
+            }
+            Statement::FunctionDef(def) => self.function(&def.name, &def.params, &def.body, at),
+            Statement::MaterialDef(def) => {
+                let statements = def.statements.iter().map(Entry::Material).collect();
+                self.definition(
+                    &format!("material {}", def.name),
+                    &def.metadata,
+                    Some(&def.model),
+                    statements,
+                    at,
+                );
+            }
+            Statement::SdfDef(def) => {
+                let statements = def.statements.iter().map(Entry::Sdf).collect();
+                self.definition(
+                    &format!("sdf {}", def.name),
+                    &def.metadata,
+                    None,
+                    statements,
+                    at,
+                );
+            }
+            Statement::EnvironmentDef(def) => {
+                let statements = def.statements.iter().map(Entry::Material).collect();
+                self.definition(
+                    &format!("environment {}", def.name),
+                    &def.metadata,
+                    None,
+                    statements,
+                    at,
+                );
+            }
+            Statement::VolumeDef(def) => {
+                let statements = def.statements.iter().map(Entry::Material).collect();
+                self.definition(
+                    &format!("volume {}", def.name),
+                    &def.metadata,
+                    None,
+                    statements,
+                    at,
+                );
+            }
+            Statement::SkeletonDef(def) => {
+                let statements = def.statements.iter().map(Entry::Skeleton).collect();
+                self.definition(
+                    &format!("skeleton {}", def.name),
+                    &def.metadata,
+                    None,
+                    statements,
+                    at,
+                );
+            }
+        }
+    }
+
+    /// Prints `{prefix}{expr};` on one statement line.
+    fn assignment(&mut self, prefix: &str, expr: &Expr) {
+        let col = self.indent * INDENT.len() + prefix.len();
+        let value = self.expr(expr, self.indent, col, false);
+        self.line(&format!("{prefix}{value};"));
+    }
+
+    fn function(
+        &mut self,
+        name: &str,
+        params: &[String],
+        body: &[MaterialFunctionStatement],
+        at: Option<usize>,
+    ) {
+        let header = format!("fn {name}({})", params.join(", "));
+        let block = self.block_after(at);
+        // Keep the `fn f(x) = expr;` form where the source used it.
+        if at.is_some()
+            && block.is_none()
+            && let [MaterialFunctionStatement::Return { expr }] = body
+        {
+            self.assignment(&format!("{header} = "), expr);
+            return;
+        }
+        self.line(&format!("{header} {{"));
+        self.body(body, block);
+        self.line("}");
+    }
+
+    /// Prints a function body one level deeper, without its braces.
+    fn body(&mut self, body: &[MaterialFunctionStatement], block: Option<Block>) {
+        let block = block.filter(|block| block.items.len() == body.len());
+        self.indent += 1;
+        let items = self.enter_block(block.as_ref());
+        self.sequence(
+            body,
+            items.as_ref().map(|(items, end)| (items.as_slice(), *end)),
+            |f, stmt, item| {
+                f.function_statement(stmt, item.map(|item| item.token));
+            },
+        );
+        self.indent -= 1;
+    }
+
+    fn enter_block(&mut self, block: Option<&Block>) -> Option<(Vec<Item>, usize)> {
+        let block = block?;
+        let layout = self.layout.as_ref()?;
+        self.cursor.last_end = layout.offset(block.open) + 1;
+        Some((block.items.clone(), layout.offset(block.close)))
+    }
+
+    fn function_statement(&mut self, stmt: &MaterialFunctionStatement, at: Option<usize>) {
+        match stmt {
+            MaterialFunctionStatement::Binding {
+                name,
+                mutable,
+                expr,
+            } => self.assignment(&format!("{} {name} = ", binding_keyword(*mutable)), expr),
+            MaterialFunctionStatement::Assign { name, expr } => {
+                self.assignment(&format!("{name} = "), expr);
+            }
+            MaterialFunctionStatement::Return { expr } => self.assignment("return ", expr),
+            MaterialFunctionStatement::Break => self.line("break;"),
+            MaterialFunctionStatement::If {
+                cond,
+                then_body,
+                else_body,
+            } => self.if_statement("", cond, then_body, else_body, at),
+            MaterialFunctionStatement::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                let prefix = format!("for {var} in ");
+                let col = self.indent * INDENT.len() + prefix.len();
+                let start = self.expr(start, self.indent, col, false);
+                let col = advance(col, &start) + 2;
+                let end = self.expr(end, self.indent, col, true);
+                self.line(&format!("{prefix}{start}..{end} {{"));
+                let block = self.block_after(at);
+                self.body(body, block);
+                self.line("}");
+            }
+        }
+    }
+
+    fn if_statement(
+        &mut self,
+        lead: &str,
+        cond: &Expr,
+        then_body: &[MaterialFunctionStatement],
+        else_body: &[MaterialFunctionStatement],
+        at: Option<usize>,
+    ) {
+        let prefix = format!("{lead}if ");
+        let col = self.indent * INDENT.len() + prefix.len();
+        let cond = self.expr(cond, self.indent, col, true);
+        self.line(&format!("{prefix}{cond} {{"));
+        let then_block = self.block_after(at);
+        let close = then_block.as_ref().map(|block| block.close);
+        self.body(then_body, then_block);
+        match else_body {
+            [] => self.line("}"),
+            [
+                MaterialFunctionStatement::If {
+                    cond,
+                    then_body,
+                    else_body,
+                },
+            ] => {
+                let next = close.and_then(|close| self.layout.as_ref()?.else_if(close));
+                self.if_statement("} else ", cond, then_body, else_body, next);
+            }
+            _ => {
+                self.line("} else {");
+                let block = close.and_then(|close| self.layout.as_ref()?.else_block(close));
+                self.body(else_body, block);
+                self.line("}");
+            }
+        }
+    }
+
+    /// Prints a `material`, `sdf`, `environment`, `volume` or `skeleton` definition. Metadata,
+    /// `model` and statements keep their source order when it is known.
+    fn definition(
+        &mut self,
+        header: &str,
+        metadata: &[(String, Expr)],
+        model: Option<&str>,
+        statements: Vec<Entry<'_>>,
+        at: Option<usize>,
+    ) {
+        let block = self.block_after(at);
+        let ordered = block.as_ref().and_then(|block| {
+            let layout = self.layout.as_ref()?;
+            let kinds = block
+                .items
+                .iter()
+                .map(|item| layout.entry_kind(item))
+                .collect::<Vec<_>>();
+            let count = |kind| kinds.iter().filter(|k| **k == kind).count();
+            let model_written = count(EntryKind::Model) == 1;
+            let model_matches = match model {
+                Some(model) => model_written || model == "Standard",
+                None => count(EntryKind::Model) == 0,
+            };
+            if count(EntryKind::Metadata) != metadata.len()
+                || count(EntryKind::Statement) != statements.len()
+                || count(EntryKind::Model) > 1
+                || !model_matches
+            {
+                return None;
+            }
+            Some(kinds)
+        });
+
+        let mut metadata_entries = metadata
+            .iter()
+            .map(|(key, expr)| Entry::Metadata(key, expr));
+        let mut statement_entries = statements.into_iter();
+        let entries = match &ordered {
+            Some(kinds) => kinds
+                .iter()
+                .filter_map(|kind| match kind {
+                    EntryKind::Metadata => metadata_entries.next(),
+                    EntryKind::Model => model.map(Entry::Model),
+                    EntryKind::Statement => statement_entries.next(),
+                })
+                .collect::<Vec<_>>(),
+            None => metadata_entries
+                .chain(model.filter(|model| *model != "Standard").map(Entry::Model))
+                .chain(statement_entries)
+                .collect(),
+        };
+
+        self.line(&format!("{header} {{"));
+        self.indent += 1;
+        let block = block.filter(|_| ordered.is_some());
+        let items = self.enter_block(block.as_ref());
+        self.sequence(
+            &entries,
+            items.as_ref().map(|(items, end)| (items.as_slice(), *end)),
+            |f, entry, item| {
+                f.entry(entry, item.map(|item| item.token));
+            },
+        );
+        self.indent -= 1;
+        self.line("};");
+    }
+
+    fn entry(&mut self, entry: &Entry<'_>, at: Option<usize>) {
+        match entry {
+            Entry::Metadata(key, expr) => self.assignment(&format!("{key}: "), expr),
+            Entry::Model(model) => self.line(&format!("model: {model};")),
+            Entry::Material(MaterialStatement::Binding { name, expr })
+            | Entry::Sdf(SdfStatement::Binding { name, expr })
+            | Entry::Skeleton(SkeletonStatement::Binding { name, expr }) => {
+                self.assignment(&format!("let {name} = "), expr);
+            }
+            Entry::Material(MaterialStatement::Property { name, expr }) => {
+                self.assignment(&format!("{name} = "), expr);
+            }
+            Entry::Material(MaterialStatement::Function {
+                name, params, body, ..
+            })
+            | Entry::Sdf(SdfStatement::Function {
+                name, params, body, ..
+            }) => {
+                self.function(name, params, body, at);
+            }
+            Entry::Skeleton(SkeletonStatement::Joint { name, expr }) => {
+                self.assignment(&format!("joint {name} = "), expr);
+            }
+            Entry::Skeleton(SkeletonStatement::Bone { name, start, end }) => {
+                self.line(&format!("bone {name} = {start}, {end};"));
+            }
+            Entry::Skeleton(SkeletonStatement::Chain {
+                name,
+                start,
+                mid,
+                end,
+            }) => self.line(&format!("chain {name} = {start}, {mid}, {end};")),
+        }
+    }
+
+    /// Renders `expr` starting at column `col`. Continuation lines are indented for
+    /// nesting `level`. In a `restricted` position (an `if` condition or `for` range
+    /// end) object literals are parenthesized so their `{` is not read as the body.
+    fn expr(&mut self, expr: &Expr, level: usize, col: usize, restricted: bool) -> String {
+        if let Some(text) = self.verbatim(expr) {
+            return text;
+        }
+        match &expr.kind {
+            ExprKind::Number(value) => self.number(*value),
+            ExprKind::String(value) => quote(value),
+            ExprKind::Ident(name) => name.clone(),
+            ExprKind::Array(items) => self.list("[", "]", items, expr.span.end, level, col),
+            ExprKind::Call { .. } | ExprKind::Member { .. } => {
+                self.postfix(expr, level, col, restricted)
+            }
+            ExprKind::ObjectLiteral { type_name, fields } => {
+                let text = self.object(type_name, fields, expr.span, level);
+                if restricted && !text.starts_with('#') {
+                    format!("({text})")
+                } else {
+                    text
+                }
+            }
+            ExprKind::Binary { lhs, op, rhs } => {
+                let precedence = binary_precedence(*op);
+                let lhs = self.operand_at(lhs, precedence, false, level, col, restricted);
+                let op = binary_symbol(*op);
+                let col = advance(col, &lhs) + op.len() + 2;
+                let rhs = self.operand_at(rhs, precedence, true, level, col, restricted);
+                format!("{lhs} {op} {rhs}")
+            }
+            ExprKind::Unary { op, expr } => {
+                let symbol = match op {
+                    UnaryOp::Neg => "-",
+                    UnaryOp::Not => "!",
+                };
+                let operand =
+                    self.operand_at(expr, UNARY_PRECEDENCE, false, level, col + 1, restricted);
+                format!("{symbol}{operand}")
+            }
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => self.if_expr(cond, then_branch, else_branch, level, col),
+            ExprKind::For {
+                var,
+                start,
+                end,
+                body,
+            } => self.for_expr(var, start, end, body, level, col),
+            ExprKind::FunctionLiteral { params, body } => {
+                self.function_literal(params, body, level)
+            }
+        }
+    }
+
+    /// Renders an operand of an operator with the given precedence, parenthesized when
+    /// it would otherwise bind differently.
+    fn operand_at(
+        &mut self,
+        expr: &Expr,
+        precedence: u8,
+        right: bool,
+        level: usize,
+        col: usize,
+        restricted: bool,
+    ) -> String {
+        let own = expr_precedence(expr);
+        if own < precedence || (right && own == precedence) || is_block_expr(expr) {
+            format!("({})", self.expr(expr, level, col + 1, false))
+        } else {
+            self.expr(expr, level, col, restricted)
+        }
+    }
+
+    /// Renders a chain of member accesses and calls. After a multi-line base, such as an
+    /// object literal, a chain of two or more methods puts each one on its own line.
+    fn postfix(&mut self, expr: &Expr, level: usize, col: usize, restricted: bool) -> String {
+        let mut segments = Vec::new();
+        let mut base = expr;
+        loop {
+            match &base.kind {
+                ExprKind::Member { target, field } => {
+                    segments.push(Postfix::Field(field));
+                    base = target;
+                }
+                ExprKind::Call { callee, args } => {
+                    segments.push(Postfix::Call(args, base.span.end));
+                    base = callee;
+                }
+                _ => break,
+            }
+        }
+        segments.reverse();
+
+        let mut text = if matches!(base.kind, ExprKind::Number(_)) {
+            format!("({})", self.expr(base, level, col + 1, false))
+        } else {
+            self.operand_at(base, POSTFIX_PRECEDENCE, false, level, col, restricted)
+        };
+        let fields = segments
+            .iter()
+            .filter(|segment| matches!(segment, Postfix::Field(_)))
+            .count();
+        let split = text.contains('\n') && fields >= 2;
+        let args_level = if split { level + 1 } else { level };
+        for segment in segments {
+            match segment {
+                Postfix::Field(field) => {
+                    if split {
+                        text.push('\n');
+                        text.push_str(&INDENT.repeat(level + 1));
+                    }
+                    text.push('.');
+                    text.push_str(field);
+                }
+                Postfix::Call(args, end) => {
+                    text = self.list(&format!("{text}("), ")", args, end, args_level, col);
+                }
+            }
+        }
+        text
+    }
+
+    /// Renders call arguments or array items on one line when they fit, otherwise one
+    /// per line. `end` is the source end of the list, after its closing bracket; a list
+    /// with comments inside goes one item per line so they stay with their items.
+    fn list(
+        &mut self,
+        open: &str,
+        close: &str,
+        items: &[Expr],
+        end: usize,
+        level: usize,
+        col: usize,
+    ) -> String {
+        if items.is_empty() {
+            return format!("{open}{close}");
+        }
+        let close_at = end.saturating_sub(1);
+        let saved = self.cursor;
+        let commented = self.layout.as_ref().is_some_and(|layout| {
+            layout
+                .comments
+                .get(self.cursor.comment)
+                .is_some_and(|comment| comment.start < close_at)
+        });
+        if !commented {
+            let mut item_col = advance(col, open);
+            let mut parts = Vec::with_capacity(items.len());
+            for item in items {
+                let text = self.expr(item, level, item_col, false);
+                item_col = advance(item_col, &text) + 2;
+                parts.push(text);
+            }
+            let inline = format!("{}{close}", parts.join(", "));
+            if fits(advance(col, open), &inline) {
+                return format!("{open}{inline}");
+            }
+            self.cursor = saved;
+        }
+
+        let pad = INDENT.repeat(level + 1);
+        let item_col = (level + 1) * INDENT.len();
+        let mut text = format!("{open}\n");
+        for (index, item) in items.iter().enumerate() {
+            self.comment_lines(item.span.start, &pad, &mut text);
+            text.push_str(&pad);
+            text.push_str(&self.expr(item, level + 1, item_col, false));
+            let next = items
+                .get(index + 1)
+                .map_or(close_at, |next| next.span.start);
+            self.entry_end(index + 1 < items.len(), item.span.end, next, &mut text);
+        }
+        self.comment_lines(close_at, &pad, &mut text);
+        text.push_str(&INDENT.repeat(level));
+        text.push_str(close);
+        text
+    }
+
+    fn object(
+        &mut self,
+        type_name: &str,
+        fields: &[(String, Expr)],
+        span: Span,
+        level: usize,
+    ) -> String {
+        if let Some(hex) = self.hex_color(type_name, fields) {
+            return hex;
+        }
+        let open = if type_name == "anonymous" {
+            "{".to_string()
+        } else {
+            format!("{type_name} {{")
+        };
+        if fields.is_empty() {
+            return format!("{open}}}");
+        }
+        let pad = INDENT.repeat(level + 1);
+        let close_at = span.end.saturating_sub(1);
+        let field_start = |f: &Self, value: &Expr| {
+            f.layout
+                .as_ref()
+                .map_or(close_at, |layout| layout.field_start(value))
+        };
+        let mut text = format!("{open}\n");
+        for (index, (key, value)) in fields.iter().enumerate() {
+            let start = field_start(self, value);
+            self.comment_lines(start, &pad, &mut text);
+            let col = pad.len() + key.len() + 2;
+            let value_text = self.expr(value, level + 1, col, false);
+            text.push_str(&format!("{pad}{key}: {value_text}"));
+            let next = fields
+                .get(index + 1)
+                .map_or(close_at, |(_, next)| field_start(self, next));
+            self.entry_end(index + 1 < fields.len(), value.span.end, next, &mut text);
+        }
+        self.comment_lines(close_at, &pad, &mut text);
+        text.push_str(&INDENT.repeat(level));
+        text.push('}');
+        text
+    }
+
+    fn if_expr(
+        &mut self,
+        cond: &Expr,
+        then_branch: &Expr,
+        else_branch: &Expr,
+        level: usize,
+        col: usize,
+    ) -> String {
+        let saved = self.cursor;
+        let cond_text = self.expr(cond, level, col + 3, true);
+        let then_col = advance(col + 3, &cond_text) + 3;
+        let then_text = self.expr(then_branch, level, then_col, false);
+        let else_col = advance(then_col, &then_text) + 9;
+        let else_text = self.expr(else_branch, level, else_col, false);
+        let else_part = if matches!(else_branch.kind, ExprKind::If { .. }) {
+            else_text
+        } else {
+            format!("{{ {else_text} }}")
+        };
+        let inline = format!("if {cond_text} {{ {then_text} }} else {else_part}");
+        if fits(col, &inline) {
+            return inline;
+        }
+
+        self.cursor = saved;
+        let pad = INDENT.repeat(level + 1);
+        let inner = (level + 1) * INDENT.len();
+        let cond_text = self.expr(cond, level, col + 3, true);
+        let then_text = self.expr(then_branch, level + 1, inner, false);
+        let else_part = if matches!(else_branch.kind, ExprKind::If { .. }) {
+            self.expr(else_branch, level, level * INDENT.len() + 7, false)
+        } else {
+            let else_text = self.expr(else_branch, level + 1, inner, false);
+            format!("{{\n{pad}{else_text}\n{}}}", INDENT.repeat(level))
+        };
+        format!(
+            "if {cond_text} {{\n{pad}{then_text}\n{}}} else {else_part}",
+            INDENT.repeat(level)
+        )
+    }
+
+    fn for_expr(
+        &mut self,
+        var: &str,
+        start: &Expr,
+        end: &Expr,
+        body: &Expr,
+        level: usize,
+        col: usize,
+    ) -> String {
+        let prefix = format!("for {var} in ");
+        let start_col = col + prefix.len();
+        let start = self.expr(start, level, start_col, false);
+        let end_col = advance(start_col, &start) + 2;
+        let end = self.expr(end, level, end_col, true);
+        let header = format!("{prefix}{start}..{end}");
+        let body_state = self.cursor;
+        let body_text = self.expr(body, level, advance(col, &header) + 3, false);
+        let inline = format!("{header} {{ {body_text} }}");
+        if fits(col, &inline) {
+            return inline;
+        }
+
+        self.cursor = body_state;
+        let body_text = self.expr(body, level + 1, (level + 1) * INDENT.len(), false);
+        format!(
+            "{header} {{\n{}{body_text}\n{}}}",
+            INDENT.repeat(level + 1),
+            INDENT.repeat(level)
+        )
+    }
+
+    fn function_literal(
+        &mut self,
+        params: &[String],
+        body: &[MaterialFunctionStatement],
+        level: usize,
+    ) -> String {
+        let at = self.layout.as_ref().and_then(|layout| {
+            let index = layout
+                .function_literals
+                .get(self.cursor.function_literal)
+                .copied();
+            self.cursor.function_literal += 1;
+            index
+        });
+        let block = self.block_after(at);
+        let outer = std::mem::take(&mut self.out);
+        let indent = std::mem::replace(&mut self.indent, level);
+        self.body(body, block);
+        self.indent = indent;
+        let text = std::mem::replace(&mut self.out, outer);
+        format!(
+            "fn({}) {{\n{text}{}}}",
+            params.join(", "),
+            INDENT.repeat(level)
+        )
+    }
+
+    fn number(&mut self, value: f64) -> String {
+        self.source_literal(|kind| matches!(kind, TokenKind::Number(number) if *number == value))
+            .unwrap_or_else(|| {
+                let text = value.to_string();
+                if text.contains('.') || !value.is_finite() {
+                    text
+                } else {
+                    format!("{text}.0")
+                }
+            })
+    }
+
+    /// Recovers `#rrggbb` for a `vec3` object that came from a hex color in the source.
+    fn hex_color(&mut self, type_name: &str, fields: &[(String, Expr)]) -> Option<String> {
+        let [
+            (
+                x,
+                Expr {
+                    kind: ExprKind::Number(r),
+                    ..
+                },
+            ),
+            (
+                y,
+                Expr {
+                    kind: ExprKind::Number(g),
+                    ..
+                },
+            ),
+            (
+                z,
+                Expr {
+                    kind: ExprKind::Number(b),
+                    ..
+                },
+            ),
+        ] = fields
+        else {
+            return None;
+        };
+        if type_name != "vec3" || (x.as_str(), y.as_str(), z.as_str()) != ("x", "y", "z") {
+            return None;
+        }
+        let rgb = [*r, *g, *b];
+        self.source_literal(
+            |kind| matches!(kind, TokenKind::HexColor(hex) if parse_hex_color(hex) == Some(rgb)),
+        )
+    }
+
+    /// Spelling of the next source literal matching `matches`, consuming it.
+    fn source_literal(&mut self, matches: impl Fn(&TokenKind) -> bool) -> Option<String> {
+        let layout = self.layout.as_ref()?;
+        let start = self.cursor.literal;
+        let (offset, &token) = layout
+            .literals
+            .iter()
+            .skip(start)
+            .take(LITERAL_LOOKAHEAD)
+            .enumerate()
+            .find(|(_, index)| layout.kind(**index).is_some_and(&matches))?;
+        self.cursor.literal = start + offset + 1;
+        Some(layout.lexeme(token))
+    }
+}
+
+const UNARY_PRECEDENCE: u8 = 7;
+const POSTFIX_PRECEDENCE: u8 = 8;
+
+fn binary_precedence(op: BinaryOp) -> u8 {
+    match op {
+        BinaryOp::Or => 1,
+        BinaryOp::And => 2,
+        BinaryOp::Equal | BinaryOp::NotEqual => 3,
+        BinaryOp::Less | BinaryOp::LessEqual | BinaryOp::Greater | BinaryOp::GreaterEqual => 4,
+        BinaryOp::Add | BinaryOp::Sub | BinaryOp::Intersect => 5,
+        BinaryOp::Mul | BinaryOp::Div => 6,
+    }
+}
+
+fn binary_symbol(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "+",
+        BinaryOp::Sub => "-",
+        BinaryOp::Intersect => "&",
+        BinaryOp::Mul => "*",
+        BinaryOp::Div => "/",
+        BinaryOp::Less => "<",
+        BinaryOp::LessEqual => "<=",
+        BinaryOp::Greater => ">",
+        BinaryOp::GreaterEqual => ">=",
+        BinaryOp::Equal => "==",
+        BinaryOp::NotEqual => "!=",
+        BinaryOp::And => "&&",
+        BinaryOp::Or => "||",
+    }
+}
+
+fn expr_precedence(expr: &Expr) -> u8 {
+    match &expr.kind {
+        ExprKind::Binary { op, .. } => binary_precedence(*op),
+        ExprKind::Unary { .. } => UNARY_PRECEDENCE,
+        _ => POSTFIX_PRECEDENCE,
+    }
+}
+
+/// Whether a comment at `offset` inside `expr` falls outside its operands and outside
+/// the places where comments are printed: between object fields, list items and call
+/// arguments, and inside function bodies.
+fn is_stray_comment(expr: &Expr, offset: usize) -> bool {
+    let inside = |expr: &Expr| (expr.span.start..expr.span.end).contains(&offset);
+    match &expr.kind {
+        ExprKind::ObjectLiteral { .. } | ExprKind::Array(_) | ExprKind::FunctionLiteral { .. } => {
+            false
+        }
+        // `postfix` prints the chain without visiting its links, so they are checked here.
+        ExprKind::Call { callee, .. } => inside(callee) && is_stray_comment(callee, offset),
+        ExprKind::Member { target, .. } => !inside(target) || is_stray_comment(target, offset),
+        ExprKind::Binary { lhs, rhs, .. } => !inside(lhs) && !inside(rhs),
+        ExprKind::Unary { expr, .. } => !inside(expr),
+        ExprKind::If {
+            cond,
+            then_branch,
+            else_branch,
+        } => !inside(cond) && !inside(then_branch) && !inside(else_branch),
+        ExprKind::For {
+            start, end, body, ..
+        } => !inside(start) && !inside(end) && !inside(body),
+        ExprKind::Number(_) | ExprKind::String(_) | ExprKind::Ident(_) => true,
+    }
+}
+
+/// Expressions ending in a `{ ... }` body, parenthesized when used as an operand.
+fn is_block_expr(expr: &Expr) -> bool {
+    matches!(
+        expr.kind,
+        ExprKind::If { .. } | ExprKind::For { .. } | ExprKind::FunctionLiteral { .. }
+    )
+}
+
+fn binding_keyword(mutable: bool) -> &'static str {
+    if mutable { "var" } else { "let" }
+}
+
+fn quote(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for ch in value.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            other => out.push(other),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Column after printing `text` starting at `col`.
+fn advance(col: usize, text: &str) -> usize {
+    match text.rsplit_once('\n') {
+        Some((_, last)) => last.chars().count(),
+        None => col + text.chars().count(),
+    }
+}
+
+/// Whether `text` stays on one line within the width, leaving room for a `;` or `,`.
+fn fits(col: usize, text: &str) -> bool {
+    !text.contains('\n') && col + text.chars().count() < MAX_WIDTH
+}
@@ -1,6 +1,7 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use cranelift_codegen::ir::{AbiParam, InstBuilder, types};
+use cranelift_codegen::ir::condcodes::{FloatCC, IntCC};
+use cranelift_codegen::ir::{AbiParam, Block, InstBuilder, types};
 use cranelift_codegen::isa::CallConv;
 use cranelift_codegen::settings::{self, Configurable};
 use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
@@ -8,18 +9,226 @@ use cranelift_jit::{JITBuilder, JITModule};
 use cranelift_module::{Linkage, Module, default_libcall_names};
 
 use crate::ast::{
-    BinaryOp, Expr, MaterialDef, MaterialFunctionStatement, MaterialStatement, SdfDef,
-    SdfFunctionStatement, SdfStatement, UnaryOp,
+    BinaryOp, Expr, ExprKind, MAX_LOOP_ITERATIONS, MaterialDef, MaterialFunctionStatement,
+    MaterialStatement, SdfDef, SdfFunctionStatement, SdfStatement, UnaryOp,
 };
 use crate::vm::{VmFunction, VmInstruction};
 
 type JitScalar = f32;
 const JIT_TYPE: types::Type = types::F32;
 
+/// Inlined Forge helper calls deeper than this give up on JIT and use the interpreter,
+/// which also keeps recursive helpers from inlining forever.
+const MAX_INLINE_DEPTH: usize = 8;
+
 fn jit_const(fb: &mut FunctionBuilder<'_>, value: f32) -> cranelift_codegen::ir::Value {
     fb.ins().f32const(value as JitScalar)
 }
 
+fn emit_truth(
+    fb: &mut FunctionBuilder<'_>,
+    value: cranelift_codegen::ir::Value,
+) -> cranelift_codegen::ir::Value {
+    let zero = jit_const(fb, 0.0);
+    fb.ins().fcmp(FloatCC::NotEqual, value, zero)
+}
+
+fn emit_flag_number(
+    fb: &mut FunctionBuilder<'_>,
+    flag: cranelift_codegen::ir::Value,
+) -> cranelift_codegen::ir::Value {
+    let zero = jit_const(fb, 0.0);
+    let one = jit_const(fb, 1.0);
+    fb.ins().select(flag, one, zero)
+}
+
+fn emit_scalar_not(
+    fb: &mut FunctionBuilder<'_>,
+    value: cranelift_codegen::ir::Value,
+) -> cranelift_codegen::ir::Value {
+    let zero = jit_const(fb, 0.0);
+    let flag = fb.ins().fcmp(FloatCC::Equal, value, zero);
+    emit_flag_number(fb, flag)
+}
+
+fn emit_scalar_binary(
+    fb: &mut FunctionBuilder<'_>,
+    op: BinaryOp,
+    lhs: cranelift_codegen::ir::Value,
+    rhs: cranelift_codegen::ir::Value,
+) -> Option<cranelift_codegen::ir::Value> {
+    let cc = match op {
+        BinaryOp::Add => return Some(fb.ins().fadd(lhs, rhs)),
+        BinaryOp::Sub => return Some(fb.ins().fsub(lhs, rhs)),
+        BinaryOp::Mul => return Some(fb.ins().fmul(lhs, rhs)),
+        BinaryOp::Div => return Some(fb.ins().fdiv(lhs, rhs)),
+        BinaryOp::Intersect => return None,
+        BinaryOp::And | BinaryOp::Or => {
+            let lhs = emit_truth(fb, lhs);
+            let rhs = emit_truth(fb, rhs);
+            let flag = if op == BinaryOp::And {
+                fb.ins().band(lhs, rhs)
+            } else {
+                fb.ins().bor(lhs, rhs)
+            };
+            return Some(emit_flag_number(fb, flag));
+        }
+        BinaryOp::Less => FloatCC::LessThan,
+        BinaryOp::LessEqual => FloatCC::LessThanOrEqual,
+        BinaryOp::Greater => FloatCC::GreaterThan,
+        BinaryOp::GreaterEqual => FloatCC::GreaterThanOrEqual,
+        BinaryOp::Equal => FloatCC::Equal,
+        BinaryOp::NotEqual => FloatCC::NotEqual,
+    };
+    let flag = fb.ins().fcmp(cc, lhs, rhs);
+    Some(emit_flag_number(fb, flag))
+}
+
+/// Branches on a Forge truth value and leaves the builder in the `then` block.
+/// Returns the `else` block and the join block both sides continue into.
+fn emit_branch(fb: &mut FunctionBuilder<'_>, cond: cranelift_codegen::ir::Value) -> (Block, Block) {
+    let then_block = fb.create_block();
+    let else_block = fb.create_block();
+    let join = fb.create_block();
+    let flag = emit_truth(fb, cond);
+    fb.ins().brif(flag, then_block, &[], else_block, &[]);
+    fb.switch_to_block(then_block);
+    (else_block, join)
+}
+
+/// Ends the current block with a jump that carries `values` into `merge`. The first
+/// incoming edge declares the block params; an edge with a different shape fails.
+fn emit_merge_jump(
+    fb: &mut FunctionBuilder<'_>,
+    merge: Block,
+    values: &[cranelift_codegen::ir::Value],
+) -> Option<()> {
+    if fb.block_params(merge).is_empty() {
+        for _ in values {
+            fb.append_block_param(merge, JIT_TYPE);
+        }
+    } else if fb.block_params(merge).len() != values.len() {
+        return None;
+    }
+    fb.ins().jump(merge, values);
+    Some(())
+}
+
+/// Declares Cranelift variables holding a `var` binding, one per component, so loops
+/// and branches can reassign it.
+fn declare_mutable_local(
+    fb: &mut FunctionBuilder<'_>,
+    next_var: &mut usize,
+    values: &[cranelift_codegen::ir::Value],
+) -> Vec<Variable> {
+    values
+        .iter()
+        .map(|value| {
+            let var = Variable::from_u32(*next_var as u32);
+            *next_var += 1;
+            fb.declare_var(var, JIT_TYPE);
+            fb.def_var(var, *value);
+            var
+        })
+        .collect()
+}
+
+/// Reassigns a `var` binding; changing its shape between scalar and vec3 fails.
+fn assign_mutable_local(
+    fb: &mut FunctionBuilder<'_>,
+    vars: &[Variable],
+    values: &[cranelift_codegen::ir::Value],
+) -> Option<()> {
+    if vars.len() != values.len() {
+        return None;
+    }
+    for (var, value) in vars.iter().zip(values) {
+        fb.def_var(*var, *value);
+    }
+    Some(())
+}
+
+fn read_mutable_local(
+    fb: &mut FunctionBuilder<'_>,
+    vars: &[Variable],
+) -> Vec<cranelift_codegen::ir::Value> {
+    vars.iter().map(|var| fb.use_var(*var)).collect()
+}
+
+struct JitLoop {
+    counter: Variable,
+    header: Block,
+    exit: Block,
+    index: cranelift_codegen::ir::Value,
+}
+
+/// Opens `for index in start..end` and leaves the builder in the loop body. The loop
+/// counts whole iterations in an integer, capped at `MAX_LOOP_ITERATIONS`, and derives
+/// `index = start + k` from it, so a large `start` cannot stall the count.
+fn emit_loop_header(
+    fb: &mut FunctionBuilder<'_>,
+    next_var: &mut usize,
+    start: cranelift_codegen::ir::Value,
+    end: cranelift_codegen::ir::Value,
+) -> JitLoop {
+    let span = fb.ins().fsub(end, start);
+    let span = fb.ins().ceil(span);
+    let max = jit_const(fb, MAX_LOOP_ITERATIONS as f32);
+    let span = fb.ins().fmin(span, max);
+    // Saturating conversion: NaN becomes zero iterations.
+    let count = fb.ins().fcvt_to_sint_sat(types::I32, span);
+    let counter = Variable::from_u32(*next_var as u32);
+    *next_var += 1;
+    fb.declare_var(counter, types::I32);
+    let zero = fb.ins().iconst(types::I32, 0);
+    fb.def_var(counter, zero);
+    let header = fb.create_block();
+    let body = fb.create_block();
+    let exit = fb.create_block();
+    fb.ins().jump(header, &[]);
+    fb.switch_to_block(header);
+    let k = fb.use_var(counter);
+    let more = fb.ins().icmp(IntCC::SignedLessThan, k, count);
+    fb.ins().brif(more, body, &[], exit, &[]);
+    fb.switch_to_block(body);
+    let k = fb.use_var(counter);
+    let offset = fb.ins().fcvt_from_sint(JIT_TYPE, k);
+    let index = fb.ins().fadd(start, offset);
+    JitLoop {
+        counter,
+        header,
+        exit,
+        index,
+    }
+}
+
+/// Steps the loop counter and jumps back to the loop header.
+fn emit_loop_latch(fb: &mut FunctionBuilder<'_>, jit_loop: &JitLoop) {
+    let k = fb.use_var(jit_loop.counter);
+    let next = fb.ins().iadd_imm(k, 1);
+    fb.def_var(jit_loop.counter, next);
+    fb.ins().jump(jit_loop.header, &[]);
+}
+
+/// Returns the block for a VM jump target together with the operand stack depth it
+/// expects. The stack occupies the leading block params; loop headers may gain more
+/// params later from variable uses before they are sealed.
+fn vm_jump_block(
+    fb: &mut FunctionBuilder<'_>,
+    blocks: &mut HashMap<usize, (Block, usize)>,
+    target: usize,
+    depth: usize,
+) -> Option<Block> {
+    let (block, expected) = *blocks.entry(target).or_insert_with(|| {
+        let block = fb.create_block();
+        for _ in 0..depth {
+            fb.append_block_param(block, JIT_TYPE);
+        }
+        (block, depth)
+    });
+    (expected == depth).then_some(block)
+}
+
 fn vec2_len(x: JitScalar, y: JitScalar) -> JitScalar {
     (x * x + y * y).sqrt()
 }
@@ -430,7 +639,36 @@ pub fn compile_jit_function(name: &str, function: &VmFunction) -> Option<JitFunc
     let mut stack: Vec<cranelift_codegen::ir::Value> = Vec::new();
     let mut next_var = function.params.len();
 
-    for instruction in &function.code {
+    // Jump targets become blocks whose params carry the operand stack across the edge.
+    let jump_targets = function
+        .code
+        .iter()
+        .filter_map(|instruction| match instruction {
+            VmInstruction::Jump(target) | VmInstruction::JumpIfFalse(target) => Some(*target),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+    let mut target_blocks = HashMap::new();
+    let mut reachable = true;
+
+    for (pc, instruction) in function.code.iter().enumerate() {
+        if jump_targets.contains(&pc) {
+            if reachable {
+                let block = vm_jump_block(&mut fb, &mut target_blocks, pc, stack.len())?;
+                fb.ins().jump(block, &stack);
+            }
+            match target_blocks.get(&pc) {
+                Some(&(block, depth)) => {
+                    fb.switch_to_block(block);
+                    stack = fb.block_params(block)[..depth].to_vec();
+                    reachable = true;
+                }
+                None => reachable = false,
+            }
+        }
+        if !reachable {
+            continue;
+        }
         match instruction {
             VmInstruction::PushNumber(v) => stack.push(jit_const(&mut fb, *v)),
             VmInstruction::LoadName(name) => {
@@ -441,16 +679,14 @@ pub fn compile_jit_function(name: &str, function: &VmFunction) -> Option<JitFunc
                 let value = stack.pop()?;
                 stack.push(fb.ins().fneg(value));
             }
+            VmInstruction::Unary(UnaryOp::Not) => {
+                let value = stack.pop()?;
+                stack.push(emit_scalar_not(&mut fb, value));
+            }
             VmInstruction::Binary(op) => {
                 let rhs = stack.pop()?;
                 let lhs = stack.pop()?;
-                let out = match op {
-                    BinaryOp::Add => fb.ins().fadd(lhs, rhs),
-                    BinaryOp::Sub => fb.ins().fsub(lhs, rhs),
-                    BinaryOp::Mul => fb.ins().fmul(lhs, rhs),
-                    BinaryOp::Div => fb.ins().fdiv(lhs, rhs),
-                    BinaryOp::Intersect => return None,
-                };
+                let out = emit_scalar_binary(&mut fb, *op, lhs, rhs)?;
                 stack.push(out);
             }
             VmInstruction::CallNamed { name, argc } => {
@@ -464,15 +700,35 @@ pub fn compile_jit_function(name: &str, function: &VmFunction) -> Option<JitFunc
             }
             VmInstruction::StoreLocal(name) => {
                 let value = stack.pop()?;
-                let var = Variable::from_u32(next_var as u32);
-                next_var += 1;
-                fb.declare_var(var, JIT_TYPE);
+                let var = match vars.get(name) {
+                    Some(var) => *var,
+                    None => {
+                        let var = Variable::from_u32(next_var as u32);
+                        next_var += 1;
+                        fb.declare_var(var, JIT_TYPE);
+                        vars.insert(name.clone(), var);
+                        var
+                    }
+                };
                 fb.def_var(var, value);
-                vars.insert(name.clone(), var);
+            }
+            VmInstruction::Jump(target) => {
+                let block = vm_jump_block(&mut fb, &mut target_blocks, *target, stack.len())?;
+                fb.ins().jump(block, &stack);
+                reachable = false;
+            }
+            VmInstruction::JumpIfFalse(target) => {
+                let cond = stack.pop()?;
+                let block = vm_jump_block(&mut fb, &mut target_blocks, *target, stack.len())?;
+                let next = fb.create_block();
+                let flag = emit_truth(&mut fb, cond);
+                fb.ins().brif(flag, next, &[], block, &stack);
+                fb.switch_to_block(next);
             }
             VmInstruction::Return => {
                 let value = stack.pop()?;
                 fb.ins().return_(&[value]);
+                reachable = false;
             }
             VmInstruction::PushString(_)
             | VmInstruction::BuildArray(_)
@@ -480,7 +736,12 @@ pub fn compile_jit_function(name: &str, function: &VmFunction) -> Option<JitFunc
             | VmInstruction::LoadMember(_) => return None,
         }
     }
+    if reachable {
+        return None;
+    }
 
+    fb.seal_all_blocks();
+    fb.finalize();
     finalize_scalar_function(module, func_id, ctx, function.params.len())
 }
 
@@ -490,21 +751,50 @@ enum SdfJitValue {
     Vec3([cranelift_codegen::ir::Value; 3]),
 }
 
+impl SdfJitValue {
+    fn components(&self) -> Vec<cranelift_codegen::ir::Value> {
+        match self {
+            Self::Scalar(value) => vec![*value],
+            Self::Vec3(values) => values.to_vec(),
+        }
+    }
+
+    fn from_components(values: &[cranelift_codegen::ir::Value]) -> Option<Self> {
+        match values {
+            [value] => Some(Self::Scalar(*value)),
+            [x, y, z] => Some(Self::Vec3([*x, *y, *z])),
+            _ => None,
+        }
+    }
+}
+
 struct SdfJitContext<'a, 'b> {
     fb: &'a mut FunctionBuilder<'b>,
     module: &'a mut JITModule,
     locals: HashMap<String, SdfJitValue>,
+    /// `var` bindings, kept apart from `locals` so assignments survive loops and branches.
+    mutable_locals: HashMap<String, Vec<Variable>>,
     functions: &'a HashMap<String, (Vec<String>, Vec<SdfFunctionStatement>)>,
     captures: &'a HashMap<String, Variable>,
+    inline_depth: usize,
+    next_var: usize,
+    loop_exits: Vec<Block>,
 }
 
 pub fn compile_sdf_distance_function(def: &SdfDef) -> Option<JitSdfDistanceFunction> {
+    compile_sdf_scalar_function(def, "distance")
+}
+
+/// Compiles `fn function_name(p)` of `def` to a native function of `p` returning a scalar,
+/// with the top-level bindings it reads passed in as captures.
+pub fn compile_sdf_scalar_function(
+    def: &SdfDef,
+    function_name: &str,
+) -> Option<JitSdfDistanceFunction> {
     let (params, body) = def.statements.iter().find_map(|stmt| match stmt {
-        SdfStatement::Function { name, params, body }
-            if name == "distance" && params.len() == 1 =>
-        {
-            Some((params.clone(), body.clone()))
-        }
+        SdfStatement::Function {
+            name, params, body, ..
+        } if name == function_name && params.len() == 1 => Some((params.clone(), body.clone())),
         _ => None,
     })?;
 
@@ -520,9 +810,9 @@ pub fn compile_sdf_distance_function(def: &SdfDef) -> Option<JitSdfDistanceFunct
         .statements
         .iter()
         .filter_map(|stmt| match stmt {
-            SdfStatement::Function { name, params, body } => {
-                Some((name.clone(), (params.clone(), body.clone())))
-            }
+            SdfStatement::Function {
+                name, params, body, ..
+            } => Some((name.clone(), (params.clone(), body.clone()))),
             _ => None,
         })
         .collect::<HashMap<_, _>>();
@@ -540,7 +830,11 @@ pub fn compile_sdf_distance_function(def: &SdfDef) -> Option<JitSdfDistanceFunct
     sig.returns.push(AbiParam::new(JIT_TYPE));
 
     let func_id = module
-        .declare_function(&format!("{}_distance_vec3", def.name), Linkage::Local, &sig)
+        .declare_function(
+            &format!("{}_{function_name}_vec3", def.name),
+            Linkage::Local,
+            &sig,
+        )
         .ok()?;
     let mut ctx = module.make_context();
     ctx.func.signature = sig;
@@ -572,26 +866,20 @@ pub fn compile_sdf_distance_function(def: &SdfDef) -> Option<JitSdfDistanceFunct
         fb: &mut fb,
         module: &mut module,
         locals,
+        mutable_locals: HashMap::new(),
         functions: &functions,
         captures: &captures,
+        inline_depth: 0,
+        next_var: capture_names.len(),
+        loop_exits: Vec::new(),
     };
 
-    for stmt in &body {
-        match stmt {
-            SdfFunctionStatement::Binding { name, expr } => {
-                let value = compile_sdf_expr(expr, &mut jit_ctx)?;
-                jit_ctx.locals.insert(name.clone(), value);
-            }
-            SdfFunctionStatement::Return { expr } => {
-                let value = compile_sdf_expr(expr, &mut jit_ctx)?;
-                let SdfJitValue::Scalar(value) = value else {
-                    return None;
-                };
-                jit_ctx.fb.ins().return_(&[value]);
-            }
-        }
-    }
+    let SdfJitValue::Scalar(value) = compile_sdf_body(&body, &mut jit_ctx)? else {
+        return None;
+    };
+    jit_ctx.fb.ins().return_(&[value]);
 
+    fb.seal_all_blocks();
     fb.finalize();
     module.define_function(func_id, &mut ctx).ok()?;
     module.clear_context(&mut ctx);
@@ -606,11 +894,9 @@ pub fn compile_sdf_distance_function(def: &SdfDef) -> Option<JitSdfDistanceFunct
 
 pub fn compile_sdf_vec3_function(def: &SdfDef, function_name: &str) -> Option<JitSdfVec3Function> {
     let (params, body) = def.statements.iter().find_map(|stmt| match stmt {
-        SdfStatement::Function { name, params, body }
-            if name == function_name && params.len() == 1 =>
-        {
-            Some((params.clone(), body.clone()))
-        }
+        SdfStatement::Function {
+            name, params, body, ..
+        } if name == function_name && params.len() == 1 => Some((params.clone(), body.clone())),
         _ => None,
     })?;
 
@@ -626,9 +912,9 @@ pub fn compile_sdf_vec3_function(def: &SdfDef, function_name: &str) -> Option<Ji
         .statements
         .iter()
         .filter_map(|stmt| match stmt {
-            SdfStatement::Function { name, params, body } => {
-                Some((name.clone(), (params.clone(), body.clone())))
-            }
+            SdfStatement::Function {
+                name, params, body, ..
+            } => Some((name.clone(), (params.clone(), body.clone()))),
             _ => None,
         })
         .collect::<HashMap<_, _>>();
@@ -716,26 +1002,20 @@ pub fn compile_modifier_distance_function(
         fb: &mut fb,
         module: &mut module,
         locals,
+        mutable_locals: HashMap::new(),
         functions: &functions,
         captures: &captures,
+        inline_depth: 0,
+        next_var: 0,
+        loop_exits: Vec::new(),
     };
 
-    for stmt in body {
-        match stmt {
-            SdfFunctionStatement::Binding { name, expr } => {
-                let value = compile_sdf_expr(expr, &mut jit_ctx)?;
-                jit_ctx.locals.insert(name.clone(), value);
-            }
-            SdfFunctionStatement::Return { expr } => {
-                let value = compile_sdf_expr(expr, &mut jit_ctx)?;
-                let SdfJitValue::Scalar(value) = value else {
-                    return None;
-                };
-                jit_ctx.fb.ins().return_(&[value]);
-            }
-        }
-    }
+    let SdfJitValue::Scalar(value) = compile_sdf_body(body, &mut jit_ctx)? else {
+        return None;
+    };
+    jit_ctx.fb.ins().return_(&[value]);
 
+    fb.seal_all_blocks();
     fb.finalize();
     module.define_function(func_id, &mut ctx).ok()?;
     module.clear_context(&mut ctx);
@@ -752,12 +1032,34 @@ enum MaterialJitValue {
     Vec3([cranelift_codegen::ir::Value; 3]),
 }
 
+impl MaterialJitValue {
+    fn components(&self) -> Vec<cranelift_codegen::ir::Value> {
+        match self {
+            Self::Scalar(value) => vec![*value],
+            Self::Vec3(values) => values.to_vec(),
+        }
+    }
+
+    fn from_components(values: &[cranelift_codegen::ir::Value]) -> Option<Self> {
+        match values {
+            [value] => Some(Self::Scalar(*value)),
+            [x, y, z] => Some(Self::Vec3([*x, *y, *z])),
+            _ => None,
+        }
+    }
+}
+
 struct MaterialJitContext<'a, 'b> {
     fb: &'a mut FunctionBuilder<'b>,
     module: &'a mut JITModule,
     locals: HashMap<String, MaterialJitValue>,
+    /// `var` bindings, kept apart from `locals` so assignments survive loops and branches.
+    mutable_locals: HashMap<String, Vec<Variable>>,
     functions: &'a HashMap<String, (Vec<String>, Vec<MaterialFunctionStatement>)>,
     captures: &'a HashMap<String, MaterialJitValue>,
+    inline_depth: usize,
+    next_var: usize,
+    loop_exits: Vec<Block>,
 }
 
 pub fn compile_material_vec3_function(
@@ -765,9 +1067,9 @@ pub fn compile_material_vec3_function(
     function_name: &str,
 ) -> Option<(Vec<JitCapture>, JitVec3Function)> {
     let (params, body) = def.statements.iter().find_map(|stmt| match stmt {
-        MaterialStatement::Function { name, params, body } if name == function_name => {
-            Some((params.clone(), body.clone()))
-        }
+        MaterialStatement::Function {
+            name, params, body, ..
+        } if name == function_name => Some((params.clone(), body.clone())),
         _ => None,
     })?;
     if params.len() != 1 {
@@ -779,9 +1081,9 @@ pub fn compile_material_vec3_function(
         .statements
         .iter()
         .filter_map(|stmt| match stmt {
-            MaterialStatement::Function { name, params, body } => {
-                Some((name.clone(), (params.clone(), body.clone())))
-            }
+            MaterialStatement::Function {
+                name, params, body, ..
+            } => Some((name.clone(), (params.clone(), body.clone()))),
             _ => None,
         })
         .collect::<HashMap<_, _>>();
@@ -871,23 +1173,16 @@ fn material_function_returns_vec3(
         fb: &mut fb,
         module: &mut module,
         locals: HashMap::new(),
+        mutable_locals: HashMap::new(),
         functions,
         captures: &capture_vars,
+        inline_depth: 0,
+        next_var: 0,
+        loop_exits: Vec::new(),
     };
-    for stmt in body {
-        match stmt {
-            MaterialFunctionStatement::Binding { name, expr } => {
-                let value = compile_material_expr(expr, &mut jit_ctx)?;
-                jit_ctx.locals.insert(name.clone(), value);
-            }
-            MaterialFunctionStatement::Return { expr } => {
-                let value = compile_material_expr(expr, &mut jit_ctx)?;
-                return Some(matches!(value, MaterialJitValue::Vec3(_)));
-            }
-        }
-    }
+    let value = compile_material_body(body, &mut jit_ctx)?;
     let _ = func_id;
-    None
+    Some(matches!(value, MaterialJitValue::Vec3(_)))
 }
 
 fn compile_material_vec3_component(
@@ -957,25 +1252,19 @@ fn compile_material_vec3_component(
         fb: &mut fb,
         module: &mut module,
         locals: HashMap::new(),
+        mutable_locals: HashMap::new(),
         functions,
         captures: &capture_vars,
+        inline_depth: 0,
+        next_var: next_param,
+        loop_exits: Vec::new(),
     };
-    for stmt in body {
-        match stmt {
-            MaterialFunctionStatement::Binding { name, expr } => {
-                let value = compile_material_expr(expr, &mut jit_ctx)?;
-                jit_ctx.locals.insert(name.clone(), value);
-            }
-            MaterialFunctionStatement::Return { expr } => {
-                let value = compile_material_expr(expr, &mut jit_ctx)?;
-                let component_value = match value {
-                    MaterialJitValue::Scalar(value) => value,
-                    MaterialJitValue::Vec3(values) => values[component],
-                };
-                jit_ctx.fb.ins().return_(&[component_value]);
-            }
-        }
-    }
+    let component_value = match compile_material_body(body, &mut jit_ctx)? {
+        MaterialJitValue::Scalar(value) => value,
+        MaterialJitValue::Vec3(values) => values[component],
+    };
+    jit_ctx.fb.ins().return_(&[component_value]);
+    fb.seal_all_blocks();
     fb.finalize();
     finalize_scalar_function(module, func_id, ctx, argc)
 }
@@ -1008,9 +1297,9 @@ fn collect_sdf_distance_captures(
         functions: &HashMap<String, (Vec<String>, Vec<SdfFunctionStatement>)>,
         seen_functions: &mut Vec<String>,
     ) {
-        match expr {
-            Expr::Number(_) | Expr::String(_) => {}
-            Expr::Array(items) => {
+        match &expr.kind {
+            ExprKind::Number(_) | ExprKind::String(_) => {}
+            ExprKind::Array(items) => {
                 for item in items {
                     collect_expr(
                         item,
@@ -1022,7 +1311,7 @@ fn collect_sdf_distance_captures(
                     );
                 }
             }
-            Expr::Ident(name) => {
+            ExprKind::Ident(name) => {
                 if !locals.iter().any(|local| local == name)
                     && top_level_bindings.iter().any(|binding| binding == name)
                     && !captures.iter().any(|capture| capture == name)
@@ -1030,7 +1319,7 @@ fn collect_sdf_distance_captures(
                     captures.push(name.clone());
                 }
             }
-            Expr::ObjectLiteral { fields, .. } => {
+            ExprKind::ObjectLiteral { fields, .. } => {
                 for (_, value) in fields {
                     collect_expr(
                         value,
@@ -1042,7 +1331,7 @@ fn collect_sdf_distance_captures(
                     );
                 }
             }
-            Expr::Binary { lhs, rhs, .. } => {
+            ExprKind::Binary { lhs, rhs, .. } => {
                 collect_expr(
                     lhs,
                     locals,
@@ -1060,7 +1349,7 @@ fn collect_sdf_distance_captures(
                     seen_functions,
                 );
             }
-            Expr::Member { target, .. } => {
+            ExprKind::Member { target, .. } => {
                 collect_expr(
                     target,
                     locals,
@@ -1070,7 +1359,7 @@ fn collect_sdf_distance_captures(
                     seen_functions,
                 );
             }
-            Expr::Unary { expr, .. } => {
+            ExprKind::Unary { expr, .. } => {
                 collect_expr(
                     expr,
                     locals,
@@ -1080,39 +1369,65 @@ fn collect_sdf_distance_captures(
                     seen_functions,
                 );
             }
-            Expr::Call { callee, args } => {
-                if let Expr::Ident(name) = callee.as_ref()
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                for expr in [cond, then_branch, else_branch] {
+                    collect_expr(
+                        expr,
+                        locals,
+                        captures,
+                        top_level_bindings,
+                        functions,
+                        seen_functions,
+                    );
+                }
+            }
+            ExprKind::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                for bound in [start, end] {
+                    collect_expr(
+                        bound,
+                        locals,
+                        captures,
+                        top_level_bindings,
+                        functions,
+                        seen_functions,
+                    );
+                }
+                let mut body_locals = locals.to_vec();
+                body_locals.push(var.clone());
+                collect_expr(
+                    body,
+                    &body_locals,
+                    captures,
+                    top_level_bindings,
+                    functions,
+                    seen_functions,
+                );
+            }
+            ExprKind::Call { callee, args } => {
+                if let ExprKind::Ident(name) = &callee.kind
                     && let Some((params, body)) = functions.get(name)
                     && !seen_functions.iter().any(|seen| seen == name)
                 {
                     seen_functions.push(name.clone());
                     let mut fn_locals = locals.to_vec();
                     fn_locals.extend(params.iter().cloned());
-                    for stmt in body {
-                        match stmt {
-                            SdfFunctionStatement::Binding { name, expr } => {
-                                collect_expr(
-                                    expr,
-                                    &fn_locals,
-                                    captures,
-                                    top_level_bindings,
-                                    functions,
-                                    seen_functions,
-                                );
-                                fn_locals.push(name.clone());
-                            }
-                            SdfFunctionStatement::Return { expr } => {
-                                collect_expr(
-                                    expr,
-                                    &fn_locals,
-                                    captures,
-                                    top_level_bindings,
-                                    functions,
-                                    seen_functions,
-                                );
-                            }
-                        }
-                    }
+                    collect_body(
+                        body,
+                        &mut fn_locals,
+                        captures,
+                        top_level_bindings,
+                        functions,
+                        seen_functions,
+                    );
                     seen_functions.pop();
                 }
                 for arg in args {
@@ -1126,56 +1441,130 @@ fn collect_sdf_distance_captures(
                     );
                 }
             }
-            Expr::FunctionLiteral { .. } => {}
+            ExprKind::FunctionLiteral { .. } => {}
         }
     }
 
-    let mut captures = Vec::new();
-    let mut locals = params.to_vec();
-    let mut seen_functions = Vec::new();
-    for stmt in body {
-        match stmt {
-            SdfFunctionStatement::Binding { name, expr } => {
-                collect_expr(
-                    expr,
-                    &locals,
-                    &mut captures,
-                    top_level_bindings,
-                    functions,
-                    &mut seen_functions,
-                );
-                locals.push(name.clone());
-            }
-            SdfFunctionStatement::Return { expr } => {
-                collect_expr(
-                    expr,
-                    &locals,
-                    &mut captures,
-                    top_level_bindings,
-                    functions,
-                    &mut seen_functions,
-                );
+    fn collect_body(
+        body: &[SdfFunctionStatement],
+        locals: &mut Vec<String>,
+        captures: &mut Vec<String>,
+        top_level_bindings: &[String],
+        functions: &HashMap<String, (Vec<String>, Vec<SdfFunctionStatement>)>,
+        seen_functions: &mut Vec<String>,
+    ) {
+        for stmt in body {
+            match stmt {
+                SdfFunctionStatement::Binding { name, expr, .. } => {
+                    collect_expr(
+                        expr,
+                        locals,
+                        captures,
+                        top_level_bindings,
+                        functions,
+                        seen_functions,
+                    );
+                    locals.push(name.clone());
+                }
+                SdfFunctionStatement::Assign { expr, .. }
+                | SdfFunctionStatement::Return { expr } => {
+                    collect_expr(
+                        expr,
+                        locals,
+                        captures,
+                        top_level_bindings,
+                        functions,
+                        seen_functions,
+                    );
+                }
+                SdfFunctionStatement::If {
+                    cond,
+                    then_body,
+                    else_body,
+                } => {
+                    collect_expr(
+                        cond,
+                        locals,
+                        captures,
+                        top_level_bindings,
+                        functions,
+                        seen_functions,
+                    );
+                    for block in [then_body, else_body] {
+                        collect_body(
+                            block,
+                            &mut locals.clone(),
+                            captures,
+                            top_level_bindings,
+                            functions,
+                            seen_functions,
+                        );
+                    }
+                }
+                SdfFunctionStatement::For {
+                    var,
+                    start,
+                    end,
+                    body,
+                } => {
+                    for bound in [start, end] {
+                        collect_expr(
+                            bound,
+                            locals,
+                            captures,
+                            top_level_bindings,
+                            functions,
+                            seen_functions,
+                        );
+                    }
+                    let mut body_locals = locals.clone();
+                    body_locals.push(var.clone());
+                    collect_body(
+                        body,
+                        &mut body_locals,
+                        captures,
+                        top_level_bindings,
+                        functions,
+                        seen_functions,
+                    );
+                }
+                SdfFunctionStatement::Break => {}
             }
         }
     }
+
+    let mut captures = Vec::new();
+    let mut locals = params.to_vec();
+    let mut seen_functions = Vec::new();
+    collect_body(
+        body,
+        &mut locals,
+        &mut captures,
+        top_level_bindings,
+        functions,
+        &mut seen_functions,
+    );
     captures.sort();
     captures.dedup();
     captures
 }
 
 fn compile_sdf_expr(expr: &Expr, ctx: &mut SdfJitContext<'_, '_>) -> Option<SdfJitValue> {
-    match expr {
-        Expr::Number(value) => Some(SdfJitValue::Scalar(jit_const(ctx.fb, *value as f32))),
-        Expr::Ident(name) => {
+    match &expr.kind {
+        ExprKind::Number(value) => Some(SdfJitValue::Scalar(jit_const(ctx.fb, *value as f32))),
+        ExprKind::Ident(name) => {
             if let Some(value) = ctx.locals.get(name) {
                 return Some(*value);
             }
+            if let Some(vars) = ctx.mutable_locals.get(name) {
+                return SdfJitValue::from_components(&read_mutable_local(ctx.fb, vars));
+            }
             if let Some(var) = ctx.captures.get(name) {
                 return Some(SdfJitValue::Scalar(ctx.fb.use_var(*var)));
             }
             None
         }
-        Expr::Member { target, field } => {
+        ExprKind::Member { target, field } => {
             let value = compile_sdf_expr(target, ctx)?;
             let SdfJitValue::Vec3(vec) = value else {
                 return None;
@@ -1187,7 +1576,7 @@ fn compile_sdf_expr(expr: &Expr, ctx: &mut SdfJitContext<'_, '_>) -> Option<SdfJ
                 _ => None,
             }
         }
-        Expr::ObjectLiteral { type_name, fields } if type_name == "vec3" => {
+        ExprKind::ObjectLiteral { type_name, fields } if type_name == "vec3" => {
             let zero = jit_const(ctx.fb, 0.0);
             let mut values = [zero, zero, zero];
             for (name, expr) in fields {
@@ -1203,7 +1592,7 @@ fn compile_sdf_expr(expr: &Expr, ctx: &mut SdfJitContext<'_, '_>) -> Option<SdfJ
             }
             Some(SdfJitValue::Vec3(values))
         }
-        Expr::Unary {
+        ExprKind::Unary {
             op: UnaryOp::Neg,
             expr,
         } => match compile_sdf_expr(expr, ctx)? {
@@ -1214,13 +1603,41 @@ fn compile_sdf_expr(expr: &Expr, ctx: &mut SdfJitContext<'_, '_>) -> Option<SdfJ
                 ctx.fb.ins().fneg(values[2]),
             ])),
         },
-        Expr::Binary { lhs, op, rhs } => {
+        ExprKind::Unary {
+            op: UnaryOp::Not,
+            expr,
+        } => match compile_sdf_expr(expr, ctx)? {
+            SdfJitValue::Scalar(value) => Some(SdfJitValue::Scalar(emit_scalar_not(ctx.fb, value))),
+            SdfJitValue::Vec3(values) => Some(SdfJitValue::Vec3([
+                emit_scalar_not(ctx.fb, values[0]),
+                emit_scalar_not(ctx.fb, values[1]),
+                emit_scalar_not(ctx.fb, values[2]),
+            ])),
+        },
+        ExprKind::Binary { lhs, op, rhs } => {
             let lhs = compile_sdf_expr(lhs, ctx)?;
             let rhs = compile_sdf_expr(rhs, ctx)?;
             compile_sdf_binary(*op, lhs, rhs, ctx)
         }
-        Expr::Call { callee, args } => {
-            if let Expr::Ident(name) = callee.as_ref() {
+        ExprKind::If {
+            cond,
+            then_branch,
+            else_branch,
+        } => {
+            let SdfJitValue::Scalar(cond) = compile_sdf_expr(cond, ctx)? else {
+                return None;
+            };
+            let (else_block, join) = emit_branch(ctx.fb, cond);
+            let value = compile_sdf_expr(then_branch, ctx)?;
+            emit_merge_jump(ctx.fb, join, &value.components())?;
+            ctx.fb.switch_to_block(else_block);
+            let value = compile_sdf_expr(else_branch, ctx)?;
+            emit_merge_jump(ctx.fb, join, &value.components())?;
+            ctx.fb.switch_to_block(join);
+            SdfJitValue::from_components(ctx.fb.block_params(join))
+        }
+        ExprKind::Call { callee, args } => {
+            if let ExprKind::Ident(name) = &callee.kind {
                 if let Some((params, body)) = ctx.functions.get(name).cloned() {
                     let arg_values = args
                         .iter()
@@ -1234,8 +1651,8 @@ fn compile_sdf_expr(expr: &Expr, ctx: &mut SdfJitContext<'_, '_>) -> Option<SdfJ
                     .collect::<Option<Vec<_>>>()?;
                 return compile_sdf_builtin(name, &arg_values, ctx);
             }
-            if let Expr::Member { target, field } = callee.as_ref()
-                && let Expr::Ident(type_name) = target.as_ref()
+            if let ExprKind::Member { target, field } = &callee.kind
+                && let ExprKind::Ident(type_name) = &target.kind
             {
                 let arg_values = args
                     .iter()
@@ -1443,25 +1860,134 @@ fn compile_inline_sdf_function(
     args: Vec<SdfJitValue>,
     ctx: &mut SdfJitContext<'_, '_>,
 ) -> Option<SdfJitValue> {
+    if ctx.inline_depth >= MAX_INLINE_DEPTH {
+        return None;
+    }
     let old_locals = ctx.locals.clone();
+    let old_mutable_locals = std::mem::take(&mut ctx.mutable_locals);
     for (param, value) in params.iter().zip(args.into_iter()) {
         ctx.locals.insert(param.clone(), value);
     }
-    let mut result = None;
+    ctx.inline_depth += 1;
+    let result = compile_sdf_body(body, ctx);
+    ctx.inline_depth -= 1;
+    ctx.locals = old_locals;
+    ctx.mutable_locals = old_mutable_locals;
+    result
+}
+
+/// Compiles a function body to the value it returns. Every `return` jumps to one exit
+/// block, so early returns inside `if` statements merge through its block params.
+fn compile_sdf_body(
+    body: &[SdfFunctionStatement],
+    ctx: &mut SdfJitContext<'_, '_>,
+) -> Option<SdfJitValue> {
+    let exit = ctx.fb.create_block();
+    if compile_sdf_block(body, exit, ctx)? {
+        return None;
+    }
+    ctx.fb.switch_to_block(exit);
+    SdfJitValue::from_components(ctx.fb.block_params(exit))
+}
+
+/// Returns whether control falls off the end of `body` without returning.
+fn compile_sdf_block(
+    body: &[SdfFunctionStatement],
+    exit: Block,
+    ctx: &mut SdfJitContext<'_, '_>,
+) -> Option<bool> {
     for stmt in body {
         match stmt {
-            SdfFunctionStatement::Binding { name, expr } => {
+            SdfFunctionStatement::Binding {
+                name,
+                mutable,
+                expr,
+            } => {
                 let value = compile_sdf_expr(expr, ctx)?;
-                ctx.locals.insert(name.clone(), value);
+                if *mutable {
+                    let vars =
+                        declare_mutable_local(ctx.fb, &mut ctx.next_var, &value.components());
+                    ctx.locals.remove(name);
+                    ctx.mutable_locals.insert(name.clone(), vars);
+                } else {
+                    ctx.mutable_locals.remove(name);
+                    ctx.locals.insert(name.clone(), value);
+                }
+            }
+            SdfFunctionStatement::Assign { name, expr } => {
+                let value = compile_sdf_expr(expr, ctx)?;
+                let vars = ctx.mutable_locals.get(name)?;
+                assign_mutable_local(ctx.fb, vars, &value.components())?;
+            }
+            SdfFunctionStatement::Break => {
+                let exit = *ctx.loop_exits.last()?;
+                ctx.fb.ins().jump(exit, &[]);
+                return Some(false);
             }
             SdfFunctionStatement::Return { expr } => {
-                result = Some(compile_sdf_expr(expr, ctx)?);
-                break;
+                let value = compile_sdf_expr(expr, ctx)?;
+                emit_merge_jump(ctx.fb, exit, &value.components())?;
+                return Some(false);
+            }
+            SdfFunctionStatement::If {
+                cond,
+                then_body,
+                else_body,
+            } => {
+                let SdfJitValue::Scalar(cond) = compile_sdf_expr(cond, ctx)? else {
+                    return None;
+                };
+                let (else_block, join) = emit_branch(ctx.fb, cond);
+                let locals = ctx.locals.clone();
+                let mutable_locals = ctx.mutable_locals.clone();
+                let then_falls = compile_sdf_block(then_body, exit, ctx)?;
+                if then_falls {
+                    ctx.fb.ins().jump(join, &[]);
+                }
+                ctx.locals = locals.clone();
+                ctx.mutable_locals = mutable_locals.clone();
+                ctx.fb.switch_to_block(else_block);
+                let else_falls = compile_sdf_block(else_body, exit, ctx)?;
+                if else_falls {
+                    ctx.fb.ins().jump(join, &[]);
+                }
+                ctx.locals = locals;
+                ctx.mutable_locals = mutable_locals;
+                if !then_falls && !else_falls {
+                    return Some(false);
+                }
+                ctx.fb.switch_to_block(join);
+            }
+            SdfFunctionStatement::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                let (SdfJitValue::Scalar(start), SdfJitValue::Scalar(end)) =
+                    (compile_sdf_expr(start, ctx)?, compile_sdf_expr(end, ctx)?)
+                else {
+                    return None;
+                };
+                let jit_loop = emit_loop_header(ctx.fb, &mut ctx.next_var, start, end);
+                let locals = ctx.locals.clone();
+                let mutable_locals = ctx.mutable_locals.clone();
+                ctx.locals
+                    .insert(var.clone(), SdfJitValue::Scalar(jit_loop.index));
+                ctx.mutable_locals.remove(var);
+                ctx.loop_exits.push(jit_loop.exit);
+                let body_falls = compile_sdf_block(body, exit, ctx);
+                ctx.loop_exits.pop();
+                ctx.locals = locals;
+                ctx.mutable_locals = mutable_locals;
+                if body_falls? {
+                    emit_loop_latch(ctx.fb, &jit_loop);
+                }
+                ctx.fb.switch_to_block(jit_loop.exit);
             }
         }
     }
-    ctx.locals = old_locals;
-    result
+    Some(true)
 }
 
 fn emit_rotate_vec3(
@@ -1553,26 +2079,20 @@ fn compile_sdf_vec3_component(
         fb: &mut fb,
         module: &mut module,
         locals,
+        mutable_locals: HashMap::new(),
         functions,
         captures: &captures,
+        inline_depth: 0,
+        next_var: capture_names.len(),
+        loop_exits: Vec::new(),
     };
-    for stmt in body {
-        match stmt {
-            SdfFunctionStatement::Binding { name, expr } => {
-                let value = compile_sdf_expr(expr, &mut jit_ctx)?;
-                jit_ctx.locals.insert(name.clone(), value);
-            }
-            SdfFunctionStatement::Return { expr } => {
-                let value = compile_sdf_expr(expr, &mut jit_ctx)?;
-                let component_value = match value {
-                    SdfJitValue::Scalar(value) => value,
-                    SdfJitValue::Vec3(values) => values[component],
-                };
-                jit_ctx.fb.ins().return_(&[component_value]);
-            }
-        }
-    }
+    let component_value = match compile_sdf_body(body, &mut jit_ctx)? {
+        SdfJitValue::Scalar(value) => value,
+        SdfJitValue::Vec3(values) => values[component],
+    };
+    jit_ctx.fb.ins().return_(&[component_value]);
 
+    fb.seal_all_blocks();
     fb.finalize();
     finalize_scalar_function(module, func_id, ctx, argc)
 }
@@ -1583,17 +2103,16 @@ fn compile_sdf_binary(
     rhs: SdfJitValue,
     ctx: &mut SdfJitContext<'_, '_>,
 ) -> Option<SdfJitValue> {
+    if matches!(op, BinaryOp::And | BinaryOp::Or)
+        && !matches!((lhs, rhs), (SdfJitValue::Scalar(_), SdfJitValue::Scalar(_)))
+    {
+        return None;
+    }
     let scalar = |ctx: &mut SdfJitContext<'_, '_>,
                   lhs: cranelift_codegen::ir::Value,
                   rhs: cranelift_codegen::ir::Value|
      -> Option<cranelift_codegen::ir::Value> {
-        Some(match op {
-            BinaryOp::Add => ctx.fb.ins().fadd(lhs, rhs),
-            BinaryOp::Sub => ctx.fb.ins().fsub(lhs, rhs),
-            BinaryOp::Mul => ctx.fb.ins().fmul(lhs, rhs),
-            BinaryOp::Div => ctx.fb.ins().fdiv(lhs, rhs),
-            BinaryOp::Intersect => return None,
-        })
+        emit_scalar_binary(ctx.fb, op, lhs, rhs)
     };
 
     match (lhs, rhs) {
@@ -1929,12 +2448,26 @@ fn infer_material_expr_kind(
     expr: &Expr,
     known: &HashMap<String, JitCaptureKind>,
 ) -> Option<JitCaptureKind> {
-    match expr {
-        Expr::Number(_) => Some(JitCaptureKind::Scalar),
-        Expr::Ident(name) => known.get(name).copied(),
-        Expr::ObjectLiteral { type_name, .. } if type_name == "vec3" => Some(JitCaptureKind::Vec3),
-        Expr::Unary { expr, .. } => infer_material_expr_kind(expr, known),
-        Expr::Binary { lhs, rhs, .. } => match (
+    match &expr.kind {
+        ExprKind::Number(_) => Some(JitCaptureKind::Scalar),
+        ExprKind::Ident(name) => known.get(name).copied(),
+        ExprKind::ObjectLiteral { type_name, .. } if type_name == "vec3" => {
+            Some(JitCaptureKind::Vec3)
+        }
+        ExprKind::Unary { expr, .. } => infer_material_expr_kind(expr, known),
+        ExprKind::If {
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            let kind = infer_material_expr_kind(then_branch, known)?;
+            (infer_material_expr_kind(else_branch, known)? == kind).then_some(kind)
+        }
+        ExprKind::Binary {
+            op: BinaryOp::And | BinaryOp::Or,
+            ..
+        } => Some(JitCaptureKind::Scalar),
+        ExprKind::Binary { lhs, rhs, .. } => match (
             infer_material_expr_kind(lhs, known),
             infer_material_expr_kind(rhs, known),
         ) {
@@ -1946,15 +2479,15 @@ fn infer_material_expr_kind(
             }
             _ => None,
         },
-        Expr::Member { target, field } => {
+        ExprKind::Member { target, field } => {
             if matches!(field.as_str(), "x" | "y" | "z") {
                 Some(JitCaptureKind::Scalar)
             } else {
                 infer_material_expr_kind(target, known)
             }
         }
-        Expr::Call { callee, args } => {
-            let Expr::Ident(name) = callee.as_ref() else {
+        ExprKind::Call { callee, args } => {
+            let ExprKind::Ident(name) = &callee.kind else {
                 return None;
             };
             match name.as_str() {
@@ -2026,9 +2559,9 @@ fn collect_material_vec3_captures(
         functions: &HashMap<String, (Vec<String>, Vec<MaterialFunctionStatement>)>,
         seen_functions: &mut Vec<String>,
     ) {
-        match expr {
-            Expr::Number(_) | Expr::String(_) => {}
-            Expr::Array(items) => {
+        match &expr.kind {
+            ExprKind::Number(_) | ExprKind::String(_) => {}
+            ExprKind::Array(items) => {
                 for item in items {
                     collect_expr(
                         item,
@@ -2040,7 +2573,7 @@ fn collect_material_vec3_captures(
                     );
                 }
             }
-            Expr::Ident(name) => {
+            ExprKind::Ident(name) => {
                 if !locals.iter().any(|local| local == name)
                     && let Some(kind) = known_capture_kind(name, top_level_bindings)
                     && !captures.iter().any(|capture| capture.name == *name)
@@ -2051,7 +2584,7 @@ fn collect_material_vec3_captures(
                     });
                 }
             }
-            Expr::ObjectLiteral { fields, .. } => {
+            ExprKind::ObjectLiteral { fields, .. } => {
                 for (_, value) in fields {
                     collect_expr(
                         value,
@@ -2063,7 +2596,7 @@ fn collect_material_vec3_captures(
                     );
                 }
             }
-            Expr::Binary { lhs, rhs, .. } => {
+            ExprKind::Binary { lhs, rhs, .. } => {
                 collect_expr(
                     lhs,
                     locals,
@@ -2081,7 +2614,7 @@ fn collect_material_vec3_captures(
                     seen_functions,
                 );
             }
-            Expr::Member { target, .. } => {
+            ExprKind::Member { target, .. } => {
                 if let Some(name) = flatten_expr(expr)
                     && let Some(kind) = known_capture_kind(&name, top_level_bindings)
                 {
@@ -2099,7 +2632,7 @@ fn collect_material_vec3_captures(
                     );
                 }
             }
-            Expr::Unary { expr, .. } => {
+            ExprKind::Unary { expr, .. } => {
                 collect_expr(
                     expr,
                     locals,
@@ -2109,39 +2642,65 @@ fn collect_material_vec3_captures(
                     seen_functions,
                 );
             }
-            Expr::Call { callee, args } => {
-                if let Expr::Ident(name) = callee.as_ref()
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                for expr in [cond, then_branch, else_branch] {
+                    collect_expr(
+                        expr,
+                        locals,
+                        captures,
+                        top_level_bindings,
+                        functions,
+                        seen_functions,
+                    );
+                }
+            }
+            ExprKind::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                for bound in [start, end] {
+                    collect_expr(
+                        bound,
+                        locals,
+                        captures,
+                        top_level_bindings,
+                        functions,
+                        seen_functions,
+                    );
+                }
+                let mut body_locals = locals.to_vec();
+                body_locals.push(var.clone());
+                collect_expr(
+                    body,
+                    &body_locals,
+                    captures,
+                    top_level_bindings,
+                    functions,
+                    seen_functions,
+                );
+            }
+            ExprKind::Call { callee, args } => {
+                if let ExprKind::Ident(name) = &callee.kind
                     && let Some((params, body)) = functions.get(name)
                     && !seen_functions.iter().any(|seen| seen == name)
                 {
                     seen_functions.push(name.clone());
                     let mut fn_locals = locals.to_vec();
                     fn_locals.extend(params.iter().cloned());
-                    for stmt in body {
-                        match stmt {
-                            MaterialFunctionStatement::Binding { name, expr } => {
-                                collect_expr(
-                                    expr,
-                                    &fn_locals,
-                                    captures,
-                                    top_level_bindings,
-                                    functions,
-                                    seen_functions,
-                                );
-                                fn_locals.push(name.clone());
-                            }
-                            MaterialFunctionStatement::Return { expr } => {
-                                collect_expr(
-                                    expr,
-                                    &fn_locals,
-                                    captures,
-                                    top_level_bindings,
-                                    functions,
-                                    seen_functions,
-                                );
-                            }
-                        }
-                    }
+                    collect_body(
+                        body,
+                        &mut fn_locals,
+                        captures,
+                        top_level_bindings,
+                        functions,
+                        seen_functions,
+                    );
                     seen_functions.pop();
                 }
                 for arg in args {
@@ -2155,47 +2714,118 @@ fn collect_material_vec3_captures(
                     );
                 }
             }
-            Expr::FunctionLiteral { .. } => {}
+            ExprKind::FunctionLiteral { .. } => {}
         }
     }
 
-    let mut captures = Vec::new();
-    let mut locals = params.to_vec();
-    let mut seen_functions = Vec::new();
-    for stmt in body {
-        match stmt {
-            MaterialFunctionStatement::Binding { name, expr } => {
-                collect_expr(
-                    expr,
-                    &locals,
-                    &mut captures,
-                    top_level_bindings,
-                    functions,
-                    &mut seen_functions,
-                );
-                locals.push(name.clone());
-            }
-            MaterialFunctionStatement::Return { expr } => {
-                collect_expr(
-                    expr,
-                    &locals,
-                    &mut captures,
-                    top_level_bindings,
-                    functions,
-                    &mut seen_functions,
-                );
+    fn collect_body(
+        body: &[MaterialFunctionStatement],
+        locals: &mut Vec<String>,
+        captures: &mut Vec<JitCapture>,
+        top_level_bindings: &HashMap<String, JitCaptureKind>,
+        functions: &HashMap<String, (Vec<String>, Vec<MaterialFunctionStatement>)>,
+        seen_functions: &mut Vec<String>,
+    ) {
+        for stmt in body {
+            match stmt {
+                MaterialFunctionStatement::Binding { name, expr, .. } => {
+                    collect_expr(
+                        expr,
+                        locals,
+                        captures,
+                        top_level_bindings,
+                        functions,
+                        seen_functions,
+                    );
+                    locals.push(name.clone());
+                }
+                MaterialFunctionStatement::Assign { expr, .. }
+                | MaterialFunctionStatement::Return { expr } => {
+                    collect_expr(
+                        expr,
+                        locals,
+                        captures,
+                        top_level_bindings,
+                        functions,
+                        seen_functions,
+                    );
+                }
+                MaterialFunctionStatement::If {
+                    cond,
+                    then_body,
+                    else_body,
+                } => {
+                    collect_expr(
+                        cond,
+                        locals,
+                        captures,
+                        top_level_bindings,
+                        functions,
+                        seen_functions,
+                    );
+                    for block in [then_body, else_body] {
+                        collect_body(
+                            block,
+                            &mut locals.clone(),
+                            captures,
+                            top_level_bindings,
+                            functions,
+                            seen_functions,
+                        );
+                    }
+                }
+                MaterialFunctionStatement::For {
+                    var,
+                    start,
+                    end,
+                    body,
+                } => {
+                    for bound in [start, end] {
+                        collect_expr(
+                            bound,
+                            locals,
+                            captures,
+                            top_level_bindings,
+                            functions,
+                            seen_functions,
+                        );
+                    }
+                    let mut body_locals = locals.clone();
+                    body_locals.push(var.clone());
+                    collect_body(
+                        body,
+                        &mut body_locals,
+                        captures,
+                        top_level_bindings,
+                        functions,
+                        seen_functions,
+                    );
+                }
+                MaterialFunctionStatement::Break => {}
             }
         }
     }
+
+    let mut captures = Vec::new();
+    let mut locals = params.to_vec();
+    let mut seen_functions = Vec::new();
+    collect_body(
+        body,
+        &mut locals,
+        &mut captures,
+        top_level_bindings,
+        functions,
+        &mut seen_functions,
+    );
     captures.sort_by(|a, b| a.name.cmp(&b.name));
     captures.dedup_by(|a, b| a.name == b.name);
     captures
 }
 
 fn flatten_expr(expr: &Expr) -> Option<String> {
-    match expr {
-        Expr::Ident(name) => Some(name.clone()),
-        Expr::Member { target, field } => Some(format!("{}.{}", flatten_expr(target)?, field)),
+    match &expr.kind {
+        ExprKind::Ident(name) => Some(name.clone()),
+        ExprKind::Member { target, field } => Some(format!("{}.{}", flatten_expr(target)?, field)),
         _ => None,
     }
 }
@@ -2204,18 +2834,21 @@ fn compile_material_expr(
     expr: &Expr,
     ctx: &mut MaterialJitContext<'_, '_>,
 ) -> Option<MaterialJitValue> {
-    match expr {
-        Expr::Number(value) => Some(MaterialJitValue::Scalar(jit_const(ctx.fb, *value as f32))),
-        Expr::Ident(name) => {
+    match &expr.kind {
+        ExprKind::Number(value) => Some(MaterialJitValue::Scalar(jit_const(ctx.fb, *value as f32))),
+        ExprKind::Ident(name) => {
             if let Some(value) = ctx.locals.get(name) {
                 return Some(*value);
             }
+            if let Some(vars) = ctx.mutable_locals.get(name) {
+                return MaterialJitValue::from_components(&read_mutable_local(ctx.fb, vars));
+            }
             if let Some(value) = ctx.captures.get(name) {
                 return Some(*value);
             }
             None
         }
-        Expr::Member { target, field } => {
+        ExprKind::Member { target, field } => {
             if let Some(name) = flatten_expr(expr)
                 && let Some(value) = ctx.captures.get(&name)
             {
@@ -2232,7 +2865,7 @@ fn compile_material_expr(
                 _ => None,
             }
         }
-        Expr::ObjectLiteral { type_name, fields } if type_name == "vec3" => {
+        ExprKind::ObjectLiteral { type_name, fields } if type_name == "vec3" => {
             let zero = jit_const(ctx.fb, 0.0);
             let mut values = [zero, zero, zero];
             for (name, expr) in fields {
@@ -2248,7 +2881,7 @@ fn compile_material_expr(
             }
             Some(MaterialJitValue::Vec3(values))
         }
-        Expr::Unary {
+        ExprKind::Unary {
             op: UnaryOp::Neg,
             expr,
         } => match compile_material_expr(expr, ctx)? {
@@ -2261,13 +2894,43 @@ fn compile_material_expr(
                 ctx.fb.ins().fneg(values[2]),
             ])),
         },
-        Expr::Binary { lhs, op, rhs } => {
+        ExprKind::Unary {
+            op: UnaryOp::Not,
+            expr,
+        } => match compile_material_expr(expr, ctx)? {
+            MaterialJitValue::Scalar(value) => {
+                Some(MaterialJitValue::Scalar(emit_scalar_not(ctx.fb, value)))
+            }
+            MaterialJitValue::Vec3(values) => Some(MaterialJitValue::Vec3([
+                emit_scalar_not(ctx.fb, values[0]),
+                emit_scalar_not(ctx.fb, values[1]),
+                emit_scalar_not(ctx.fb, values[2]),
+            ])),
+        },
+        ExprKind::Binary { lhs, op, rhs } => {
             let lhs = compile_material_expr(lhs, ctx)?;
             let rhs = compile_material_expr(rhs, ctx)?;
             compile_material_binary(*op, lhs, rhs, ctx)
         }
-        Expr::Call { callee, args } => {
-            if let Expr::Ident(name) = callee.as_ref() {
+        ExprKind::If {
+            cond,
+            then_branch,
+            else_branch,
+        } => {
+            let MaterialJitValue::Scalar(cond) = compile_material_expr(cond, ctx)? else {
+                return None;
+            };
+            let (else_block, join) = emit_branch(ctx.fb, cond);
+            let value = compile_material_expr(then_branch, ctx)?;
+            emit_merge_jump(ctx.fb, join, &value.components())?;
+            ctx.fb.switch_to_block(else_block);
+            let value = compile_material_expr(else_branch, ctx)?;
+            emit_merge_jump(ctx.fb, join, &value.components())?;
+            ctx.fb.switch_to_block(join);
+            MaterialJitValue::from_components(ctx.fb.block_params(join))
+        }
+        ExprKind::Call { callee, args } => {
+            if let ExprKind::Ident(name) = &callee.kind {
                 if let Some((params, body)) = ctx.functions.get(name).cloned() {
                     let arg_values = args
                         .iter()
@@ -2281,8 +2944,8 @@ fn compile_material_expr(
                     .collect::<Option<Vec<_>>>()?;
                 return compile_material_builtin(name, &arg_values, ctx);
             }
-            if let Expr::Member { target, field } = callee.as_ref()
-                && let Expr::Ident(type_name) = target.as_ref()
+            if let ExprKind::Member { target, field } = &callee.kind
+                && let ExprKind::Ident(type_name) = &target.kind
             {
                 let arg_values = args
                     .iter()
@@ -2396,25 +3059,132 @@ fn compile_inline_material_function(
     args: Vec<MaterialJitValue>,
     ctx: &mut MaterialJitContext<'_, '_>,
 ) -> Option<MaterialJitValue> {
+    if ctx.inline_depth >= MAX_INLINE_DEPTH {
+        return None;
+    }
     let old_locals = ctx.locals.clone();
+    let old_mutable_locals = std::mem::take(&mut ctx.mutable_locals);
     for (param, value) in params.iter().zip(args.into_iter()) {
         ctx.locals.insert(param.clone(), value);
     }
-    let mut result = None;
+    ctx.inline_depth += 1;
+    let result = compile_material_body(body, ctx);
+    ctx.inline_depth -= 1;
+    ctx.locals = old_locals;
+    ctx.mutable_locals = old_mutable_locals;
+    result
+}
+
+fn compile_material_body(
+    body: &[MaterialFunctionStatement],
+    ctx: &mut MaterialJitContext<'_, '_>,
+) -> Option<MaterialJitValue> {
+    let exit = ctx.fb.create_block();
+    if compile_material_block(body, exit, ctx)? {
+        return None;
+    }
+    ctx.fb.switch_to_block(exit);
+    MaterialJitValue::from_components(ctx.fb.block_params(exit))
+}
+
+fn compile_material_block(
+    body: &[MaterialFunctionStatement],
+    exit: Block,
+    ctx: &mut MaterialJitContext<'_, '_>,
+) -> Option<bool> {
     for stmt in body {
         match stmt {
-            MaterialFunctionStatement::Binding { name, expr } => {
+            MaterialFunctionStatement::Binding {
+                name,
+                mutable,
+                expr,
+            } => {
                 let value = compile_material_expr(expr, ctx)?;
-                ctx.locals.insert(name.clone(), value);
+                if *mutable {
+                    let vars =
+                        declare_mutable_local(ctx.fb, &mut ctx.next_var, &value.components());
+                    ctx.locals.remove(name);
+                    ctx.mutable_locals.insert(name.clone(), vars);
+                } else {
+                    ctx.mutable_locals.remove(name);
+                    ctx.locals.insert(name.clone(), value);
+                }
+            }
+            MaterialFunctionStatement::Assign { name, expr } => {
+                let value = compile_material_expr(expr, ctx)?;
+                let vars = ctx.mutable_locals.get(name)?;
+                assign_mutable_local(ctx.fb, vars, &value.components())?;
+            }
+            MaterialFunctionStatement::Break => {
+                let exit = *ctx.loop_exits.last()?;
+                ctx.fb.ins().jump(exit, &[]);
+                return Some(false);
             }
             MaterialFunctionStatement::Return { expr } => {
-                result = Some(compile_material_expr(expr, ctx)?);
-                break;
+                let value = compile_material_expr(expr, ctx)?;
+                emit_merge_jump(ctx.fb, exit, &value.components())?;
+                return Some(false);
+            }
+            MaterialFunctionStatement::If {
+                cond,
+                then_body,
+                else_body,
+            } => {
+                let MaterialJitValue::Scalar(cond) = compile_material_expr(cond, ctx)? else {
+                    return None;
+                };
+                let (else_block, join) = emit_branch(ctx.fb, cond);
+                let locals = ctx.locals.clone();
+                let mutable_locals = ctx.mutable_locals.clone();
+                let then_falls = compile_material_block(then_body, exit, ctx)?;
+                if then_falls {
+                    ctx.fb.ins().jump(join, &[]);
+                }
+                ctx.locals = locals.clone();
+                ctx.mutable_locals = mutable_locals.clone();
+                ctx.fb.switch_to_block(else_block);
+                let else_falls = compile_material_block(else_body, exit, ctx)?;
+                if else_falls {
+                    ctx.fb.ins().jump(join, &[]);
+                }
+                ctx.locals = locals;
+                ctx.mutable_locals = mutable_locals;
+                if !then_falls && !else_falls {
+                    return Some(false);
+                }
+                ctx.fb.switch_to_block(join);
+            }
+            MaterialFunctionStatement::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                let (MaterialJitValue::Scalar(start), MaterialJitValue::Scalar(end)) = (
+                    compile_material_expr(start, ctx)?,
+                    compile_material_expr(end, ctx)?,
+                ) else {
+                    return None;
+                };
+                let jit_loop = emit_loop_header(ctx.fb, &mut ctx.next_var, start, end);
+                let locals = ctx.locals.clone();
+                let mutable_locals = ctx.mutable_locals.clone();
+                ctx.locals
+                    .insert(var.clone(), MaterialJitValue::Scalar(jit_loop.index));
+                ctx.mutable_locals.remove(var);
+                ctx.loop_exits.push(jit_loop.exit);
+                let body_falls = compile_material_block(body, exit, ctx);
+                ctx.loop_exits.pop();
+                ctx.locals = locals;
+                ctx.mutable_locals = mutable_locals;
+                if body_falls? {
+                    emit_loop_latch(ctx.fb, &jit_loop);
+                }
+                ctx.fb.switch_to_block(jit_loop.exit);
             }
         }
     }
-    ctx.locals = old_locals;
-    result
+    Some(true)
 }
 
 fn compile_material_binary(
@@ -2423,17 +3193,19 @@ fn compile_material_binary(
     rhs: MaterialJitValue,
     ctx: &mut MaterialJitContext<'_, '_>,
 ) -> Option<MaterialJitValue> {
+    if matches!(op, BinaryOp::And | BinaryOp::Or)
+        && !matches!(
+            (lhs, rhs),
+            (MaterialJitValue::Scalar(_), MaterialJitValue::Scalar(_))
+        )
+    {
+        return None;
+    }
     let scalar = |ctx: &mut MaterialJitContext<'_, '_>,
                   lhs: cranelift_codegen::ir::Value,
                   rhs: cranelift_codegen::ir::Value|
      -> Option<cranelift_codegen::ir::Value> {
-        Some(match op {
-            BinaryOp::Add => ctx.fb.ins().fadd(lhs, rhs),
-            BinaryOp::Sub => ctx.fb.ins().fsub(lhs, rhs),
-            BinaryOp::Mul => ctx.fb.ins().fmul(lhs, rhs),
-            BinaryOp::Div => ctx.fb.ins().fdiv(lhs, rhs),
-            BinaryOp::Intersect => return None,
-        })
+        emit_scalar_binary(ctx.fb, op, lhs, rhs)
     };
     match (lhs, rhs) {
         (MaterialJitValue::Scalar(lhs), MaterialJitValue::Scalar(rhs)) => {
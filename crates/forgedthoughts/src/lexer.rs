@@ -4,6 +4,8 @@ use thiserror::Error;
 pub struct Token {
     pub kind: TokenKind,
     pub start: usize,
+    /// Byte offset just past the token.
+    pub end: usize,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -14,15 +16,30 @@ pub enum TokenKind {
     Return,
     Import,
     Export,
+    If,
+    Else,
+    For,
+    In,
+    Break,
     Ident(String),
     String(String),
     HexColor(String),
     Number(f64),
     Equal,
+    EqualEqual,
+    Bang,
+    BangEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    AmpAmp,
+    PipePipe,
     Semicolon,
     Colon,
     Comma,
     Dot,
+    DotDot,
     Plus,
     Minus,
     Amp,
@@ -36,6 +53,16 @@ pub enum TokenKind {
     RBrace,
 }
 
+/// A `//` line comment, which the token stream itself leaves out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comment {
+    pub start: usize,
+    /// End of the comment text, before the newline.
+    pub end: usize,
+    /// Comment text including the leading `//`, without trailing whitespace.
+    pub text: String,
+}
+
 #[derive(Debug, Error)]
 pub enum LexError {
     #[error("unexpected character '{ch}' at byte {offset}")]
@@ -46,11 +73,49 @@ pub enum LexError {
     InvalidHexColor { lexeme: String, offset: usize },
 }
 
+impl LexError {
+    #[must_use]
+    pub fn offset(&self) -> usize {
+        match self {
+            Self::UnexpectedChar { offset, .. }
+            | Self::InvalidNumber { offset, .. }
+            | Self::InvalidHexColor { offset, .. } => *offset,
+        }
+    }
+}
+
 pub fn tokenize(input: &str) -> Result<Vec<Token>, LexError> {
+    let (tokens, errors) = tokenize_recovering(input);
+    match errors.into_iter().next() {
+        Some(err) => Err(err),
+        None => Ok(tokens),
+    }
+}
+
+/// Tokenizes all of `input`, collecting every error. Unexpected characters are skipped
+/// and malformed literals become a `0` number so the parser can keep going.
+pub fn tokenize_recovering(input: &str) -> (Vec<Token>, Vec<LexError>) {
+    let (tokens, _, errors) = lex(input);
+    (tokens, errors)
+}
+
+/// Like [`tokenize`], but also returns the comments in source order.
+pub fn tokenize_with_comments(input: &str) -> Result<(Vec<Token>, Vec<Comment>), LexError> {
+    let (tokens, comments, errors) = lex(input);
+    match errors.into_iter().next() {
+        Some(err) => Err(err),
+        None => Ok((tokens, comments)),
+    }
+}
+
+fn lex(input: &str) -> (Vec<Token>, Vec<Comment>, Vec<LexError>) {
     let mut chars = input.char_indices().peekable();
     let mut tokens = Vec::new();
+    let mut comments = Vec::new();
+    let mut errors = Vec::new();
 
     while let Some((offset, ch)) = chars.next() {
+        let token_count = tokens.len();
         match ch {
             ' ' | '\t' | '\r' | '\n' => {}
             '"' => {
@@ -80,12 +145,9 @@ pub fn tokenize(input: &str) -> Result<Vec<Token>, LexError> {
                     }
                 }
                 if !closed {
-                    return Err(LexError::UnexpectedChar { ch, offset });
+                    errors.push(LexError::UnexpectedChar { ch, offset });
                 }
-                tokens.push(Token {
-                    kind: TokenKind::String(value),
-                    start: offset,
-                });
+                tokens.push(simple(TokenKind::String(value), offset));
             }
             '#' => {
                 let mut lexeme = String::from("#");
@@ -98,33 +160,85 @@ pub fn tokenize(input: &str) -> Result<Vec<Token>, LexError> {
                     }
                 }
                 let digits = &lexeme[1..];
-                if !(digits.len() == 3 || digits.len() == 6) {
-                    return Err(LexError::InvalidHexColor { lexeme, offset });
+                if digits.len() == 3 || digits.len() == 6 {
+                    tokens.push(simple(
+                        TokenKind::HexColor(digits.to_ascii_lowercase()),
+                        offset,
+                    ));
+                } else {
+                    errors.push(LexError::InvalidHexColor { lexeme, offset });
+                    tokens.push(simple(TokenKind::Number(0.0), offset));
                 }
-                tokens.push(Token {
-                    kind: TokenKind::HexColor(digits.to_ascii_lowercase()),
-                    start: offset,
-                });
             }
             '/' => {
-                if let Some((_, '/')) = chars.peek() {
-                    for (_, c) in chars.by_ref() {
-                        if c == '\n' {
+                if chars.next_if(|(_, next)| *next == '/').is_some() {
+                    let mut end = input.len();
+                    while let Some((next_offset, next)) = chars.peek() {
+                        if *next == '\n' {
+                            end = *next_offset;
                             break;
                         }
+                        chars.next();
                     }
+                    comments.push(Comment {
+                        start: offset,
+                        end,
+                        text: input[offset..end].trim_end().to_string(),
+                    });
                 } else {
                     tokens.push(simple(TokenKind::Slash, offset));
                 }
             }
-            '=' => tokens.push(simple(TokenKind::Equal, offset)),
+            '=' => {
+                if chars.next_if(|(_, next)| *next == '=').is_some() {
+                    tokens.push(simple(TokenKind::EqualEqual, offset));
+                } else {
+                    tokens.push(simple(TokenKind::Equal, offset));
+                }
+            }
+            '!' => {
+                if chars.next_if(|(_, next)| *next == '=').is_some() {
+                    tokens.push(simple(TokenKind::BangEqual, offset));
+                } else {
+                    tokens.push(simple(TokenKind::Bang, offset));
+                }
+            }
+            '<' => {
+                if chars.next_if(|(_, next)| *next == '=').is_some() {
+                    tokens.push(simple(TokenKind::LessEqual, offset));
+                } else {
+                    tokens.push(simple(TokenKind::Less, offset));
+                }
+            }
+            '>' => {
+                if chars.next_if(|(_, next)| *next == '=').is_some() {
+                    tokens.push(simple(TokenKind::GreaterEqual, offset));
+                } else {
+                    tokens.push(simple(TokenKind::Greater, offset));
+                }
+            }
+            '|' if chars.next_if(|(_, next)| *next == '|').is_some() => {
+                tokens.push(simple(TokenKind::PipePipe, offset));
+            }
             ';' => tokens.push(simple(TokenKind::Semicolon, offset)),
             ':' => tokens.push(simple(TokenKind::Colon, offset)),
             ',' => tokens.push(simple(TokenKind::Comma, offset)),
-            '.' => tokens.push(simple(TokenKind::Dot, offset)),
+            '.' => {
+                if chars.next_if(|(_, next)| *next == '.').is_some() {
+                    tokens.push(simple(TokenKind::DotDot, offset));
+                } else {
+                    tokens.push(simple(TokenKind::Dot, offset));
+                }
+            }
             '+' => tokens.push(simple(TokenKind::Plus, offset)),
             '-' => tokens.push(simple(TokenKind::Minus, offset)),
-            '&' => tokens.push(simple(TokenKind::Amp, offset)),
+            '&' => {
+                if chars.next_if(|(_, next)| *next == '&').is_some() {
+                    tokens.push(simple(TokenKind::AmpAmp, offset));
+                } else {
+                    tokens.push(simple(TokenKind::Amp, offset));
+                }
+            }
             '*' => tokens.push(simple(TokenKind::Star, offset)),
             '(' => tokens.push(simple(TokenKind::LParen, offset)),
             ')' => tokens.push(simple(TokenKind::RParen, offset)),
@@ -150,18 +264,22 @@ pub fn tokenize(input: &str) -> Result<Vec<Token>, LexError> {
                     "return" => TokenKind::Return,
                     "import" => TokenKind::Import,
                     "export" => TokenKind::Export,
+                    "if" => TokenKind::If,
+                    "else" => TokenKind::Else,
+                    "for" => TokenKind::For,
+                    "in" => TokenKind::In,
+                    "break" => TokenKind::Break,
                     _ => TokenKind::Ident(lexeme),
                 };
 
-                tokens.push(Token {
-                    kind,
-                    start: offset,
-                });
+                tokens.push(simple(kind, offset));
             }
             c if c.is_ascii_digit() => {
                 let mut lexeme = String::from(c);
-                while let Some((_, next)) = chars.peek() {
-                    if next.is_ascii_digit() || *next == '.' {
+                while let Some((next_offset, next)) = chars.peek() {
+                    // `0..8` is a range, not the number `0.` followed by `.8`.
+                    let starts_range = *next == '.' && input[next_offset + 1..].starts_with('.');
+                    if next.is_ascii_digit() || (*next == '.' && !starts_range) {
                         lexeme.push(*next);
                         chars.next();
                     } else {
@@ -169,25 +287,37 @@ pub fn tokenize(input: &str) -> Result<Vec<Token>, LexError> {
                     }
                 }
 
-                let value: f64 = lexeme.parse().map_err(|_| LexError::InvalidNumber {
-                    lexeme: lexeme.clone(),
-                    offset,
-                })?;
-
-                tokens.push(Token {
-                    kind: TokenKind::Number(value),
-                    start: offset,
+                let value: f64 = lexeme.parse().unwrap_or_else(|_| {
+                    errors.push(LexError::InvalidNumber {
+                        lexeme: lexeme.clone(),
+                        offset,
+                    });
+                    0.0
                 });
+
+                tokens.push(simple(TokenKind::Number(value), offset));
             }
-            _ => return Err(LexError::UnexpectedChar { ch, offset }),
+            _ => errors.push(LexError::UnexpectedChar { ch, offset }),
+        }
+        if tokens.len() > token_count
+            && let Some(token) = tokens.last_mut()
+        {
+            token.end = chars
+                .peek()
+                .map_or(input.len(), |(next_offset, _)| *next_offset);
         }
     }
 
-    Ok(tokens)
+    (tokens, comments, errors)
 }
 
+/// A token starting at `start`. `lex` fills in `end` once the token's characters are consumed.
 fn simple(kind: TokenKind, start: usize) -> Token {
-    Token { kind, start }
+    Token {
+        kind,
+        start,
+        end: start,
+    }
 }
 
 fn is_ident_start(ch: char) -> bool {
@@ -1,5 +1,9 @@
 mod ast;
+mod builtin_docs;
+mod diagnostic;
 mod eval;
+mod format;
+mod hash;
 mod jit;
 mod lexer;
 mod materials;
@@ -14,30 +18,51 @@ use std::{
     path::{Path, PathBuf},
 };
 
-pub use ast::{BinaryOp, Expr, Program, Statement, UnaryOp};
+pub use ast::{BinaryOp, Expr, ExprKind, FileId, Program, Span, Statement, UnaryOp};
+pub use builtin_docs::{
+    BuiltinFunctionDoc, BuiltinObjectDoc, builtin_function_doc, builtin_function_docs,
+    builtin_object_doc, builtin_object_docs,
+};
+pub use diagnostic::{Diagnostic, SourceFile, SourceMap};
 pub use eval::{
     Binding, EvalError, EvalState, FunctionValue, ObjectValue, Value, eval_environment_function,
     eval_function_value, eval_material_function, eval_material_function_with_overrides,
     eval_material_properties, eval_material_properties_with_overrides, eval_program,
-    eval_sdf_function, eval_sdf_function_args_with_overrides, eval_sdf_function_with_overrides,
-    eval_sdf_vec3_function_with_overrides, eval_sdf_zero_arg_function,
-    eval_sdf_zero_arg_function_with_overrides, eval_top_level_function,
+    eval_program_collecting, eval_sdf_function, eval_sdf_function_args_with_overrides,
+    eval_sdf_function_with_overrides, eval_sdf_vec3_function_with_overrides,
+    eval_sdf_zero_arg_function, eval_sdf_zero_arg_function_with_overrides, eval_top_level_function,
+    eval_volume_function, eval_volume_values,
+};
+pub use format::{format_program, format_source};
+use hash::Fnv1a;
+pub use lexer::{
+    Comment, LexError, Token, TokenKind, tokenize, tokenize_recovering, tokenize_with_comments,
 };
 pub use materials::{
-    BlendedMaterial, BsdfSample as MaterialBsdfSample, ColorPattern, DielectricMaterial,
-    LambertMaterial, Material, MaterialBsdf, MaterialKindTag, MaterialParams, MediumParams,
-    MetalMaterial, SampleInput as MaterialSampleInput, SubsurfaceParams,
+    BlendedMaterial, BsdfSample as MaterialBsdfSample, ColorPattern, ComplexIor, Conductor,
+    DielectricMaterial, LambertMaterial, Material, MaterialBsdf, MaterialKindTag, MaterialParams,
+    MediumParams, MetalMaterial, OpenPbrLayers, SampleInput as MaterialSampleInput,
+    SubsurfaceParams,
+};
+pub use parser::{
+    ParseError, RecoveredProgram, parse_program, parse_program_in_file, parse_program_recovering,
 };
-pub use parser::{ParseError, parse_program};
 pub use render_api::{
-    Bsdf, Camera, CameraKind, EnvLight, Integrator, Light, LightSample, MaterialKind,
-    MaterialModel, OpenPbrMaterial, PinholeCamera, PointLight, PreviewIntegrator, Ray, Spectrum,
-    SphereLight, SurfaceHit, Vec3,
+    AreaLight, AreaLightSample, Bsdf, Camera, CameraKind, CubemapCamera, DirectionalLight,
+    DiskLight, EnvLight, EquirectCamera, Integrator, Light, LightSample, MaterialKind,
+    MaterialModel, MotionCamera, OpenPbrMaterial, OrthographicCamera, PinholeCamera, PointLight,
+    PreviewIntegrator, Ray, RectLight, Spectrum, SphereLight, SpotLight, SurfaceHit,
+    ThinLensCamera, Vec3,
 };
 pub use renderer::{
-    AccelMode, PreviewProgress, RayDebugAov, RayProgress, RaySettings, RenderError, RenderOptions,
-    SceneRenderSettings, extract_scene_render_settings, render_depth_png,
-    render_depth_png_with_accel, render_preview_progressive_with_accel, render_ray_png_with_accel,
+    AccelMode, AovSample, DisplayTransform, OutputFormat, PathtraceCheckpoint, PathtraceProgress,
+    PathtraceSettings, PreviewProgress, RayDebugAov, RayProgress, RaySettings, RenderError,
+    RenderLayers, RenderOptions, RenderShard, SceneRenderSettings, ShardLayers, Shutter, Tonemap,
+    extract_scene_render_settings, merge_shards, parse_tonemap, render_depth_png,
+    render_depth_png_with_accel, render_pathtrace_checkpointed_with_accel,
+    render_pathtrace_layers_with_accel, render_pathtrace_png_with_accel,
+    render_pathtrace_progressive_with_accel, render_preview_layers_with_accel,
+    render_preview_progressive_with_accel, render_ray_layers_with_accel, render_ray_png_with_accel,
     render_ray_progressive_with_accel,
 };
 use thiserror::Error;
@@ -69,6 +94,20 @@ const BUILTIN_LIBRARY: &[BuiltinLibraryItem] = &[
         tags: &["material", "metal", "gold", "reflective", "warm"],
         source: include_str!("../library/materials/gold.ft"),
     },
+    BuiltinLibraryItem {
+        category: BuiltinLibraryCategory::Materials,
+        name: "MeasuredGold",
+        path: "materials/gold.ft",
+        description: "Gold from its measured complex index of refraction instead of an RGB tint.",
+        tags: &[
+            "material",
+            "metal",
+            "gold",
+            "reflective",
+            "physically-based",
+        ],
+        source: include_str!("../library/materials/gold.ft"),
+    },
     BuiltinLibraryItem {
         category: BuiltinLibraryCategory::Materials,
         name: "Glass",
@@ -83,6 +122,34 @@ const BUILTIN_LIBRARY: &[BuiltinLibraryItem] = &[
         ],
         source: include_str!("../library/materials/glass.ft"),
     },
+    BuiltinLibraryItem {
+        category: BuiltinLibraryCategory::Materials,
+        name: "CrownGlass",
+        path: "materials/glass.ft",
+        description: "Borosilicate crown glass (BK7) with measured dispersion for spectral renders.",
+        tags: &[
+            "material",
+            "glass",
+            "dielectric",
+            "dispersion",
+            "physically-based",
+        ],
+        source: include_str!("../library/materials/glass.ft"),
+    },
+    BuiltinLibraryItem {
+        category: BuiltinLibraryCategory::Materials,
+        name: "Diamond",
+        path: "materials/glass.ft",
+        description: "Diamond with its high index and strong dispersion; shows fire in spectral renders.",
+        tags: &[
+            "material",
+            "gem",
+            "dielectric",
+            "dispersion",
+            "physically-based",
+        ],
+        source: include_str!("../library/materials/glass.ft"),
+    },
     BuiltinLibraryItem {
         category: BuiltinLibraryCategory::Materials,
         name: "CheckerFloor",
@@ -266,6 +333,9 @@ pub enum CoreError {
     Eval(#[from] EvalError),
     #[error("render error: {0}")]
     Render(#[from] RenderError),
+    /// A parse or eval error located in one of the loaded scene files.
+    #[error("{0}")]
+    Located(Box<Diagnostic>),
 }
 
 pub fn resolve_scene_path(
@@ -278,16 +348,162 @@ pub fn resolve_scene_path(
 }
 
 pub fn load_and_eval_scene(scene_path: &Path) -> Result<EvalState, CoreError> {
-    let program = load_program_with_imports(scene_path)?;
-    let state = eval_program(&program)?;
+    let (program, sources) = load_program_with_sources(scene_path)?;
+    let mut state = eval_program(&program).map_err(|err| locate_eval_error(&sources, err))?;
+    state.base_dir = scene_path.parent().map(Path::to_path_buf);
     Ok(state)
 }
 
+/// Result of [`check_scene`]: the state of everything that evaluated, plus every parse
+/// and eval error found along the way.
+#[derive(Debug)]
+pub struct SceneCheck {
+    pub state: EvalState,
+    pub errors: Vec<CoreError>,
+}
+
+/// Reads the `.ft` file at `path` and returns its current source along with the
+/// formatted source. Parse errors are located in the file.
+pub fn format_file(path: &Path) -> Result<(String, String), CoreError> {
+    let source = fs::read_to_string(path).map_err(|source| CoreError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let mut sources = SourceMap::default();
+    let file = sources.add(path.display().to_string(), source.clone());
+    let formatted =
+        format_source(&source).map_err(|err| locate_parse_error(&sources, file, err))?;
+    Ok((source, formatted))
+}
+
+/// Parses and evaluates a scene without stopping at the first error. Parse errors in any
+/// imported file are collected first; statements that did parse are still evaluated so
+/// unrelated eval errors show up in the same run. Failures that prevent loading at all,
+/// such as a missing import, are returned as `Err`.
+pub fn check_scene(scene_path: &Path) -> Result<SceneCheck, CoreError> {
+    check_scene_with_overrides(scene_path, &HashMap::new())
+}
+
+/// Like [`check_scene`], but files listed in `overrides` are read from the given text
+/// instead of disk, e.g. unsaved editor buffers.
+pub fn check_scene_with_overrides(
+    scene_path: &Path,
+    overrides: &HashMap<PathBuf, String>,
+) -> Result<SceneCheck, CoreError> {
+    let (program, mut load) = load_program_recovering(scene_path, overrides)?;
+    let (mut state, eval_errors) = eval_program_collecting(&program);
+    state.base_dir = scene_path.parent().map(Path::to_path_buf);
+    let sources = &load.sources;
+    load.errors.extend(
+        eval_errors
+            .into_iter()
+            // A statement that failed to parse leaves its name unbound; don't report
+            // every use of it again.
+            .filter(|err| {
+                !matches!(err.kind(), EvalError::UndefinedIdentifier(name)
+                    if load.unparsed_names.contains(name))
+            })
+            .map(|err| locate_eval_error(sources, err)),
+    );
+    Ok(SceneCheck {
+        state,
+        errors: load.errors,
+    })
+}
+
 pub fn load_program_with_imports(scene_path: &Path) -> Result<Program, CoreError> {
+    load_program_with_sources(scene_path).map(|(program, _)| program)
+}
+
+/// FNV-1a hash of a scene's source, all of its imports and the asset files its evaluated
+/// `state` reads, which identifies the scene a render checkpoint was taken from.
+pub fn scene_content_hash(scene_path: &Path, state: &EvalState) -> Result<u64, CoreError> {
+    let (_, sources) = load_program_with_sources(scene_path)?;
+    let mut hasher = Fnv1a::new();
+    hasher.write_u64(sources.content_hash());
+    for path in renderer::scene_asset_paths(state) {
+        let bytes = fs::read(&path).map_err(|source| CoreError::Io {
+            path: path.clone(),
+            source,
+        })?;
+        hasher.write_u64(bytes.len() as u64);
+        hasher.write(&bytes);
+    }
+    Ok(hasher.finish())
+}
+
+/// Loads a scene and its imports, returning the merged program together with every
+/// source it was parsed from so statement spans can be resolved to `file:line:col`.
+pub fn load_program_with_sources(scene_path: &Path) -> Result<(Program, SourceMap), CoreError> {
+    let (program, sources, errors) = load_program_with_overrides(scene_path, &HashMap::new())?;
+    match errors.into_iter().next() {
+        Some(err) => Err(err),
+        None => Ok((program, sources)),
+    }
+}
+
+/// Loads a scene and its imports past any parse errors, which are returned alongside the
+/// statements that did parse. Files in `overrides` (keyed by canonical path) are read from
+/// memory instead of disk.
+pub fn load_program_with_overrides(
+    scene_path: &Path,
+    overrides: &HashMap<PathBuf, String>,
+) -> Result<(Program, SourceMap, Vec<CoreError>), CoreError> {
+    let (program, load) = load_program_recovering(scene_path, overrides)?;
+    Ok((program, load.sources, load.errors))
+}
+
+/// Sources and parse errors gathered while loading a scene and its imports.
+struct SourceLoad<'a> {
+    overrides: &'a HashMap<PathBuf, String>,
+    sources: SourceMap,
+    errors: Vec<CoreError>,
+    unparsed_names: HashSet<String>,
+}
+
+fn load_program_recovering<'a>(
+    scene_path: &Path,
+    overrides: &'a HashMap<PathBuf, String>,
+) -> Result<(Program, SourceLoad<'a>), CoreError> {
     let mut loaded = HashSet::new();
     let mut stack = Vec::new();
-    let statements = load_program_statements(scene_path, &mut loaded, &mut stack)?;
-    Ok(Program { statements })
+    let mut load = SourceLoad {
+        overrides,
+        sources: SourceMap::default(),
+        errors: Vec::new(),
+        unparsed_names: HashSet::new(),
+    };
+    let (statements, spans) =
+        load_program_statements(scene_path, &mut loaded, &mut stack, &mut load)?
+            .into_iter()
+            .unzip();
+    Ok((Program { statements, spans }, load))
+}
+
+fn locate_eval_error(sources: &SourceMap, err: EvalError) -> CoreError {
+    let located = err
+        .span()
+        .and_then(|span| sources.diagnostic(span, format!("eval error: {}", err.kind())));
+    match located {
+        Some(diagnostic) => CoreError::Located(Box::new(diagnostic)),
+        None => CoreError::Eval(err),
+    }
+}
+
+fn locate_parse_error(sources: &SourceMap, file: FileId, err: ParseError) -> CoreError {
+    let offset = err
+        .offset()
+        .or_else(|| sources.file(file).map(|f| f.source.len()))
+        .unwrap_or(0);
+    let span = Span {
+        file,
+        start: offset,
+        end: offset + 1,
+    };
+    match sources.diagnostic(span, format!("parse error: {err}")) {
+        Some(diagnostic) => CoreError::Located(Box::new(diagnostic)),
+        None => CoreError::Parse(err),
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -300,7 +516,8 @@ fn load_program_statements(
     scene_path: &Path,
     loaded: &mut HashSet<(ImportKey, Option<String>)>,
     stack: &mut Vec<ImportKey>,
-) -> Result<Vec<Statement>, CoreError> {
+    load: &mut SourceLoad<'_>,
+) -> Result<Vec<(Statement, Span)>, CoreError> {
     let canonical = scene_path.canonicalize().map_err(|source| CoreError::Io {
         path: scene_path.to_path_buf(),
         source,
@@ -311,6 +528,7 @@ fn load_program_statements(
         Some(canonical.parent().unwrap_or(Path::new("."))),
         loaded,
         stack,
+        load,
     )
 }
 
@@ -320,7 +538,8 @@ fn load_import_source(
     parent_dir: Option<&Path>,
     loaded: &mut HashSet<(ImportKey, Option<String>)>,
     stack: &mut Vec<ImportKey>,
-) -> Result<Vec<Statement>, CoreError> {
+    load: &mut SourceLoad<'_>,
+) -> Result<Vec<(Statement, Span)>, CoreError> {
     if loaded.contains(&(key.clone(), namespace.clone())) {
         return Ok(Vec::new());
     }
@@ -333,10 +552,13 @@ fn load_import_source(
     stack.push(key.clone());
     let (source, base_dir) = match &key {
         ImportKey::File(path) => (
-            fs::read_to_string(path).map_err(|source| CoreError::Io {
-                path: path.clone(),
-                source,
-            })?,
+            match load.overrides.get(path) {
+                Some(text) => text.clone(),
+                None => fs::read_to_string(path).map_err(|source| CoreError::Io {
+                    path: path.clone(),
+                    source,
+                })?,
+            },
             Some(path.parent().unwrap_or(Path::new(".")).to_path_buf()),
         ),
         ImportKey::Builtin(path) => (builtin_library_source(path).unwrap_or("").to_string(), None),
@@ -352,10 +574,25 @@ fn load_import_source(
         });
     }
 
-    let program = parse_program(&source)?;
+    let file = load.sources.add(import_key_display(&key), source.as_str());
+    let recovered = parse_program_recovering(&source, file);
+    for err in recovered.errors {
+        let located = locate_parse_error(&load.sources, file, err);
+        load.errors.push(located);
+    }
+    load.unparsed_names.extend(
+        recovered
+            .unparsed_names
+            .into_iter()
+            .map(|name| match &namespace {
+                Some(alias) => qualify_name(alias, &name),
+                None => name,
+            }),
+    );
+    let program = recovered.program;
     let export_names = collect_export_names(&program.statements);
     let mut statements = Vec::new();
-    for stmt in program.statements {
+    for (stmt, span) in program.statements.into_iter().zip(program.spans) {
         match stmt {
             Statement::Import { path, alias } => {
                 let resolved = resolve_import(&path, base_dir.as_deref())?;
@@ -365,10 +602,11 @@ fn load_import_source(
                     base_dir.as_deref(),
                     loaded,
                     stack,
+                    load,
                 )?);
             }
             Statement::Export(_) => {}
-            other => statements.push(other),
+            other => statements.push((other, span)),
         }
     }
     if let Some(export_names) = export_names {
@@ -469,44 +707,31 @@ pub fn builtin_library_item_metadata(item: &BuiltinLibraryItem) -> BuiltinLibrar
 
 fn extract_builtin_metadata(item: &BuiltinLibraryItem) -> Option<BuiltinLibraryMetadata> {
     let program = parse_program(item.source).ok()?;
-    for stmt in program.statements {
-        match stmt {
-            Statement::MaterialDef(def) if def.name == item.name => {
-                return Some(metadata_from_pairs(
-                    item.name,
-                    item.description,
-                    item.tags,
-                    &def.metadata,
-                ));
-            }
-            Statement::SdfDef(def) if def.name == item.name => {
-                return Some(metadata_from_pairs(
-                    item.name,
-                    item.description,
-                    item.tags,
-                    &def.metadata,
-                ));
-            }
-            Statement::SkeletonDef(def) if def.name == item.name => {
-                return Some(metadata_from_pairs(
-                    item.name,
-                    item.description,
-                    item.tags,
-                    &def.metadata,
-                ));
-            }
-            Statement::EnvironmentDef(def) if def.name == item.name => {
-                return Some(metadata_from_pairs(
-                    item.name,
-                    item.description,
-                    item.tags,
-                    &def.metadata,
-                ));
-            }
-            _ => {}
+    program.statements.iter().find_map(|stmt| {
+        let (name, metadata) = statement_metadata(stmt)?;
+        (name == item.name)
+            .then(|| metadata_from_pairs(item.name, item.description, item.tags, metadata))
+    })
+}
+
+/// Metadata (`description`, `tags`, `params`, ...) declared by a material, sdf, skeleton
+/// or environment definition.
+#[must_use]
+pub fn definition_metadata(stmt: &Statement) -> Option<BuiltinLibraryMetadata> {
+    let (name, metadata) = statement_metadata(stmt)?;
+    Some(metadata_from_pairs(name, "", &[], metadata))
+}
+
+fn statement_metadata(stmt: &Statement) -> Option<(&str, &[(String, Expr)])> {
+    match stmt {
+        Statement::MaterialDef(def) => Some((&def.name, &def.metadata)),
+        Statement::SdfDef(def) => Some((&def.name, &def.metadata)),
+        Statement::SkeletonDef(def) => Some((&def.name, &def.metadata)),
+        Statement::EnvironmentDef(def) | Statement::VolumeDef(def) => {
+            Some((&def.name, &def.metadata))
         }
+        _ => None,
     }
-    None
 }
 
 fn metadata_from_pairs(
@@ -558,28 +783,28 @@ fn metadata_from_pairs(
 }
 
 fn metadata_string(expr: &Expr) -> Option<String> {
-    match expr {
-        Expr::String(value) => Some(value.clone()),
+    match &expr.kind {
+        ExprKind::String(value) => Some(value.clone()),
         _ => None,
     }
 }
 
 fn metadata_string_array(expr: &Expr) -> Option<Vec<String>> {
-    match expr {
-        Expr::Array(items) => items.iter().map(metadata_string).collect(),
+    match &expr.kind {
+        ExprKind::Array(items) => items.iter().map(metadata_string).collect(),
         _ => None,
     }
 }
 
 fn metadata_params(expr: &Expr) -> Option<Vec<BuiltinLibraryParam>> {
-    let Expr::Array(items) = expr else {
+    let ExprKind::Array(items) = &expr.kind else {
         return None;
     };
     items.iter().map(metadata_param).collect()
 }
 
 fn metadata_param(expr: &Expr) -> Option<BuiltinLibraryParam> {
-    let Expr::ObjectLiteral { fields, .. } = expr else {
+    let ExprKind::ObjectLiteral { fields, .. } = &expr.kind else {
         return None;
     };
     let mut name = None;
@@ -612,17 +837,17 @@ fn metadata_param(expr: &Expr) -> Option<BuiltinLibraryParam> {
 }
 
 fn metadata_scalar_string(expr: &Expr) -> Option<String> {
-    match expr {
-        Expr::String(value) => Some(value.clone()),
-        Expr::Number(value) => Some(value.to_string()),
-        Expr::Ident(value) => Some(value.clone()),
+    match &expr.kind {
+        ExprKind::String(value) => Some(value.clone()),
+        ExprKind::Number(value) => Some(value.to_string()),
+        ExprKind::Ident(value) => Some(value.clone()),
         _ => None,
     }
 }
 
-fn namespace_statements(statements: Vec<Statement>, alias: &str) -> Vec<Statement> {
+fn namespace_statements(statements: Vec<(Statement, Span)>, alias: &str) -> Vec<(Statement, Span)> {
     let mut top_level_names = HashSet::new();
-    for stmt in &statements {
+    for (stmt, _) in &statements {
         match stmt {
             Statement::Binding { name, .. } => {
                 top_level_names.insert(name.clone());
@@ -639,7 +864,7 @@ fn namespace_statements(statements: Vec<Statement>, alias: &str) -> Vec<Statemen
             Statement::SkeletonDef(def) => {
                 top_level_names.insert(def.name.clone());
             }
-            Statement::EnvironmentDef(def) => {
+            Statement::EnvironmentDef(def) | Statement::VolumeDef(def) => {
                 top_level_names.insert(def.name.clone());
             }
             Statement::Assign { .. } | Statement::Import { .. } | Statement::Export(_) => {}
@@ -648,7 +873,7 @@ fn namespace_statements(statements: Vec<Statement>, alias: &str) -> Vec<Statemen
 
     statements
         .into_iter()
-        .map(|stmt| namespace_statement(stmt, alias, &top_level_names))
+        .map(|(stmt, span)| (namespace_statement(stmt, alias, &top_level_names), span))
         .collect()
 }
 
@@ -672,23 +897,8 @@ fn namespace_statement(stmt: Statement, alias: &str, names: &HashSet<String>) ->
             for param in &def.params {
                 fn_scope.insert(param.clone());
             }
-            let mut rewritten_body = Vec::with_capacity(def.body.len());
-            for stmt in def.body {
-                match stmt {
-                    ast::MaterialFunctionStatement::Binding { name, expr } => {
-                        let expr = namespace_expr(expr, alias, names, &fn_scope);
-                        fn_scope.insert(name.clone());
-                        rewritten_body.push(ast::MaterialFunctionStatement::Binding { name, expr });
-                    }
-                    ast::MaterialFunctionStatement::Return { expr } => {
-                        rewritten_body.push(ast::MaterialFunctionStatement::Return {
-                            expr: namespace_expr(expr, alias, names, &fn_scope),
-                        });
-                    }
-                }
-            }
             def.name = qualify_name(alias, &def.name);
-            def.body = rewritten_body;
+            def.body = namespace_function_body(def.body, alias, names, fn_scope);
             Statement::FunctionDef(def)
         }
         Statement::MaterialDef(mut def) => {
@@ -720,31 +930,23 @@ fn namespace_statement(stmt: Statement, alias: &str, names: &HashSet<String>) ->
                             expr: namespace_expr(expr, alias, names, &scope),
                         }
                     }
-                    ast::MaterialStatement::Function { name, params, body } => {
+                    ast::MaterialStatement::Function {
+                        name,
+                        params,
+                        body,
+                        span,
+                    } => {
                         let mut fn_scope = scope.clone();
                         for param in &params {
                             fn_scope.insert(param.clone());
                         }
-                        let mut rewritten_body = Vec::with_capacity(body.len());
-                        for stmt in body {
-                            match stmt {
-                                ast::MaterialFunctionStatement::Binding { name, expr } => {
-                                    let expr = namespace_expr(expr, alias, names, &fn_scope);
-                                    fn_scope.insert(name.clone());
-                                    rewritten_body.push(ast::MaterialFunctionStatement::Binding {
-                                        name,
-                                        expr,
-                                    });
-                                }
-                                ast::MaterialFunctionStatement::Return { expr } => {
-                                    rewritten_body.push(ast::MaterialFunctionStatement::Return {
-                                        expr: namespace_expr(expr, alias, names, &fn_scope),
-                                    });
-                                }
-                            }
+                        let body = namespace_function_body(body, alias, names, fn_scope);
+                        ast::MaterialStatement::Function {
+                            name,
+                            params,
+                            body,
+                            span,
                         }
-                        let body = rewritten_body;
-                        ast::MaterialStatement::Function { name, params, body }
                     }
                 })
                 .collect();
@@ -771,94 +973,33 @@ fn namespace_statement(stmt: Statement, alias: &str, names: &HashSet<String>) ->
                         name,
                         expr: namespace_expr(expr, alias, names, &scope),
                     },
-                    ast::SdfStatement::Function { name, params, body } => {
+                    ast::SdfStatement::Function {
+                        name,
+                        params,
+                        body,
+                        span,
+                    } => {
                         let mut fn_scope = scope.clone();
                         for param in &params {
                             fn_scope.insert(param.clone());
                         }
-                        let mut rewritten_body = Vec::with_capacity(body.len());
-                        for stmt in body {
-                            match stmt {
-                                ast::MaterialFunctionStatement::Binding { name, expr } => {
-                                    let expr = namespace_expr(expr, alias, names, &fn_scope);
-                                    fn_scope.insert(name.clone());
-                                    rewritten_body.push(ast::MaterialFunctionStatement::Binding {
-                                        name,
-                                        expr,
-                                    });
-                                }
-                                ast::MaterialFunctionStatement::Return { expr } => {
-                                    rewritten_body.push(ast::MaterialFunctionStatement::Return {
-                                        expr: namespace_expr(expr, alias, names, &fn_scope),
-                                    });
-                                }
-                            }
+                        let body = namespace_function_body(body, alias, names, fn_scope);
+                        ast::SdfStatement::Function {
+                            name,
+                            params,
+                            body,
+                            span,
                         }
-                        let body = rewritten_body;
-                        ast::SdfStatement::Function { name, params, body }
                     }
                 })
                 .collect();
             Statement::SdfDef(def)
         }
-        Statement::EnvironmentDef(mut def) => {
-            let mut scope = HashSet::new();
-            for stmt in &def.statements {
-                if let ast::MaterialStatement::Binding { name, .. } = stmt {
-                    scope.insert(name.clone());
-                }
-            }
-            def.name = qualify_name(alias, &def.name);
-            def.metadata = def
-                .metadata
-                .into_iter()
-                .map(|(name, expr)| (name, namespace_expr(expr, alias, names, &scope)))
-                .collect();
-            def.statements = def
-                .statements
-                .into_iter()
-                .map(|stmt| match stmt {
-                    ast::MaterialStatement::Binding { name, expr } => {
-                        ast::MaterialStatement::Binding {
-                            name,
-                            expr: namespace_expr(expr, alias, names, &scope),
-                        }
-                    }
-                    ast::MaterialStatement::Property { name, expr } => {
-                        ast::MaterialStatement::Property {
-                            name,
-                            expr: namespace_expr(expr, alias, names, &scope),
-                        }
-                    }
-                    ast::MaterialStatement::Function { name, params, body } => {
-                        let mut fn_scope = scope.clone();
-                        for param in &params {
-                            fn_scope.insert(param.clone());
-                        }
-                        let mut rewritten_body = Vec::with_capacity(body.len());
-                        for stmt in body {
-                            match stmt {
-                                ast::MaterialFunctionStatement::Binding { name, expr } => {
-                                    let expr = namespace_expr(expr, alias, names, &fn_scope);
-                                    fn_scope.insert(name.clone());
-                                    rewritten_body.push(ast::MaterialFunctionStatement::Binding {
-                                        name,
-                                        expr,
-                                    });
-                                }
-                                ast::MaterialFunctionStatement::Return { expr } => {
-                                    rewritten_body.push(ast::MaterialFunctionStatement::Return {
-                                        expr: namespace_expr(expr, alias, names, &fn_scope),
-                                    });
-                                }
-                            }
-                        }
-                        let body = rewritten_body;
-                        ast::MaterialStatement::Function { name, params, body }
-                    }
-                })
-                .collect();
-            Statement::EnvironmentDef(def)
+        Statement::EnvironmentDef(def) => {
+            Statement::EnvironmentDef(namespace_environment_def(def, alias, names))
+        }
+        Statement::VolumeDef(def) => {
+            Statement::VolumeDef(namespace_environment_def(def, alias, names))
         }
         Statement::SkeletonDef(mut def) => {
             let mut scope = HashSet::new();
@@ -926,11 +1067,11 @@ fn collect_export_names(statements: &[Statement]) -> Option<HashSet<String>> {
 }
 
 fn filter_exported_statements(
-    statements: Vec<Statement>,
+    statements: Vec<(Statement, Span)>,
     export_names: &HashSet<String>,
-) -> Vec<Statement> {
+) -> Vec<(Statement, Span)> {
     let mut by_name = HashMap::new();
-    for stmt in &statements {
+    for (stmt, _) in &statements {
         match stmt {
             Statement::Binding { name, .. } => {
                 by_name.insert(name.clone(), stmt.clone());
@@ -947,7 +1088,7 @@ fn filter_exported_statements(
             Statement::SkeletonDef(def) => {
                 by_name.insert(def.name.clone(), stmt.clone());
             }
-            Statement::EnvironmentDef(def) => {
+            Statement::EnvironmentDef(def) | Statement::VolumeDef(def) => {
                 by_name.insert(def.name.clone(), stmt.clone());
             }
             Statement::Assign { .. } | Statement::Import { .. } | Statement::Export(_) => {}
@@ -972,13 +1113,13 @@ fn filter_exported_statements(
 
     statements
         .into_iter()
-        .filter(|stmt| match stmt {
+        .filter(|(stmt, _)| match stmt {
             Statement::Binding { name, .. } => keep.contains(name),
             Statement::FunctionDef(def) => keep.contains(&def.name),
             Statement::MaterialDef(def) => keep.contains(&def.name),
             Statement::SdfDef(def) => keep.contains(&def.name),
             Statement::SkeletonDef(def) => keep.contains(&def.name),
-            Statement::EnvironmentDef(def) => keep.contains(&def.name),
+            Statement::EnvironmentDef(def) | Statement::VolumeDef(def) => keep.contains(&def.name),
             Statement::Assign { path, .. } => path.first().is_some_and(|name| keep.contains(name)),
             Statement::Import { .. } | Statement::Export(_) => false,
         })
@@ -995,17 +1136,7 @@ fn statement_dependencies(stmt: &Statement) -> HashSet<String> {
             for param in &def.params {
                 scope.insert(param.clone());
             }
-            for stmt in &def.body {
-                match stmt {
-                    ast::MaterialFunctionStatement::Binding { name, expr } => {
-                        deps.extend(expr_dependencies(expr, &scope));
-                        scope.insert(name.clone());
-                    }
-                    ast::MaterialFunctionStatement::Return { expr } => {
-                        deps.extend(expr_dependencies(expr, &scope));
-                    }
-                }
-            }
+            function_body_dependencies(&def.body, scope, &mut deps);
             deps
         }
         Statement::MaterialDef(def) => {
@@ -1030,17 +1161,7 @@ fn statement_dependencies(stmt: &Statement) -> HashSet<String> {
                         for param in params {
                             fn_scope.insert(param.clone());
                         }
-                        for stmt in body {
-                            match stmt {
-                                ast::MaterialFunctionStatement::Binding { name, expr } => {
-                                    deps.extend(expr_dependencies(expr, &fn_scope));
-                                    fn_scope.insert(name.clone());
-                                }
-                                ast::MaterialFunctionStatement::Return { expr } => {
-                                    deps.extend(expr_dependencies(expr, &fn_scope));
-                                }
-                            }
-                        }
+                        function_body_dependencies(body, fn_scope, &mut deps);
                     }
                 }
             }
@@ -1067,17 +1188,7 @@ fn statement_dependencies(stmt: &Statement) -> HashSet<String> {
                         for param in params {
                             fn_scope.insert(param.clone());
                         }
-                        for stmt in body {
-                            match stmt {
-                                ast::MaterialFunctionStatement::Binding { name, expr } => {
-                                    deps.extend(expr_dependencies(expr, &fn_scope));
-                                    fn_scope.insert(name.clone());
-                                }
-                                ast::MaterialFunctionStatement::Return { expr } => {
-                                    deps.extend(expr_dependencies(expr, &fn_scope));
-                                }
-                            }
-                        }
+                        function_body_dependencies(body, fn_scope, &mut deps);
                     }
                 }
             }
@@ -1129,7 +1240,7 @@ fn statement_dependencies(stmt: &Statement) -> HashSet<String> {
             }
             deps
         }
-        Statement::EnvironmentDef(def) => {
+        Statement::EnvironmentDef(def) | Statement::VolumeDef(def) => {
             let mut deps = HashSet::new();
             let mut scope = HashSet::new();
             for stmt in &def.statements {
@@ -1151,17 +1262,7 @@ fn statement_dependencies(stmt: &Statement) -> HashSet<String> {
                         for param in params {
                             fn_scope.insert(param.clone());
                         }
-                        for stmt in body {
-                            match stmt {
-                                ast::MaterialFunctionStatement::Binding { name, expr } => {
-                                    deps.extend(expr_dependencies(expr, &fn_scope));
-                                    fn_scope.insert(name.clone());
-                                }
-                                ast::MaterialFunctionStatement::Return { expr } => {
-                                    deps.extend(expr_dependencies(expr, &fn_scope));
-                                }
-                            }
-                        }
+                        function_body_dependencies(body, fn_scope, &mut deps);
                     }
                 }
             }
@@ -1171,88 +1272,271 @@ fn statement_dependencies(stmt: &Statement) -> HashSet<String> {
     }
 }
 
+fn function_body_dependencies(
+    body: &[ast::MaterialFunctionStatement],
+    mut scope: HashSet<String>,
+    deps: &mut HashSet<String>,
+) {
+    for stmt in body {
+        match stmt {
+            ast::MaterialFunctionStatement::Binding { name, expr, .. } => {
+                deps.extend(expr_dependencies(expr, &scope));
+                scope.insert(name.clone());
+            }
+            ast::MaterialFunctionStatement::Assign { expr, .. }
+            | ast::MaterialFunctionStatement::Return { expr } => {
+                deps.extend(expr_dependencies(expr, &scope));
+            }
+            ast::MaterialFunctionStatement::Break => {}
+            ast::MaterialFunctionStatement::If {
+                cond,
+                then_body,
+                else_body,
+            } => {
+                deps.extend(expr_dependencies(cond, &scope));
+                function_body_dependencies(then_body, scope.clone(), deps);
+                function_body_dependencies(else_body, scope.clone(), deps);
+            }
+            ast::MaterialFunctionStatement::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                deps.extend(expr_dependencies(start, &scope));
+                deps.extend(expr_dependencies(end, &scope));
+                let mut body_scope = scope.clone();
+                body_scope.insert(var.clone());
+                function_body_dependencies(body, body_scope, deps);
+            }
+        }
+    }
+}
+
 fn expr_dependencies(expr: &Expr, local_scope: &HashSet<String>) -> HashSet<String> {
     let mut deps = HashSet::new();
-    match expr {
-        Expr::String(_) => {}
-        Expr::Array(items) => {
+    match &expr.kind {
+        ExprKind::String(_) => {}
+        ExprKind::Array(items) => {
             for item in items {
                 deps.extend(expr_dependencies(item, local_scope));
             }
         }
-        Expr::Ident(name) => {
+        ExprKind::Ident(name) => {
             if !local_scope.contains(name) {
                 deps.insert(name.clone());
             }
         }
-        Expr::ObjectLiteral { type_name, fields } => {
+        ExprKind::ObjectLiteral { type_name, fields } => {
             deps.insert(type_name.clone());
             for (_, expr) in fields {
                 deps.extend(expr_dependencies(expr, local_scope));
             }
         }
-        Expr::Binary { lhs, rhs, .. } => {
+        ExprKind::Binary { lhs, rhs, .. } => {
             deps.extend(expr_dependencies(lhs, local_scope));
             deps.extend(expr_dependencies(rhs, local_scope));
         }
-        Expr::Member { .. } => {
+        ExprKind::Member { .. } => {
             if let Some(name) = flatten_expr_name(expr)
                 && !local_scope.contains(name.split('.').next().unwrap_or_default())
             {
                 deps.insert(name);
             }
         }
-        Expr::Call { callee, args } => {
+        ExprKind::Call { callee, args } => {
             deps.extend(expr_dependencies(callee, local_scope));
             for arg in args {
                 deps.extend(expr_dependencies(arg, local_scope));
             }
         }
-        Expr::Unary { expr, .. } => {
+        ExprKind::Unary { expr, .. } => {
             deps.extend(expr_dependencies(expr, local_scope));
         }
-        Expr::FunctionLiteral { .. } => {}
-        Expr::Number(_) => {}
+        ExprKind::If {
+            cond,
+            then_branch,
+            else_branch,
+        } => {
+            deps.extend(expr_dependencies(cond, local_scope));
+            deps.extend(expr_dependencies(then_branch, local_scope));
+            deps.extend(expr_dependencies(else_branch, local_scope));
+        }
+        ExprKind::For {
+            var,
+            start,
+            end,
+            body,
+        } => {
+            deps.extend(expr_dependencies(start, local_scope));
+            deps.extend(expr_dependencies(end, local_scope));
+            let mut body_scope = local_scope.clone();
+            body_scope.insert(var.clone());
+            deps.extend(expr_dependencies(body, &body_scope));
+        }
+        ExprKind::FunctionLiteral { .. } => {}
+        ExprKind::Number(_) => {}
     }
     deps
 }
 
 fn flatten_expr_name(expr: &Expr) -> Option<String> {
-    match expr {
-        Expr::Ident(name) => Some(name.clone()),
-        Expr::Member { target, field } => Some(format!("{}.{}", flatten_expr_name(target)?, field)),
+    match &expr.kind {
+        ExprKind::Ident(name) => Some(name.clone()),
+        ExprKind::Member { target, field } => {
+            Some(format!("{}.{}", flatten_expr_name(target)?, field))
+        }
         _ => None,
     }
 }
 
+/// Qualifies an `environment` or `volume` definition and the names its body refers to.
+fn namespace_environment_def(
+    mut def: ast::EnvironmentDef,
+    alias: &str,
+    names: &HashSet<String>,
+) -> ast::EnvironmentDef {
+    let mut scope = HashSet::new();
+    for stmt in &def.statements {
+        if let ast::MaterialStatement::Binding { name, .. } = stmt {
+            scope.insert(name.clone());
+        }
+    }
+    def.name = qualify_name(alias, &def.name);
+    def.metadata = def
+        .metadata
+        .into_iter()
+        .map(|(name, expr)| (name, namespace_expr(expr, alias, names, &scope)))
+        .collect();
+    def.statements = def
+        .statements
+        .into_iter()
+        .map(|stmt| match stmt {
+            ast::MaterialStatement::Binding { name, expr } => ast::MaterialStatement::Binding {
+                name,
+                expr: namespace_expr(expr, alias, names, &scope),
+            },
+            ast::MaterialStatement::Property { name, expr } => ast::MaterialStatement::Property {
+                name,
+                expr: namespace_expr(expr, alias, names, &scope),
+            },
+            ast::MaterialStatement::Function {
+                name,
+                params,
+                body,
+                span,
+            } => {
+                let mut fn_scope = scope.clone();
+                for param in &params {
+                    fn_scope.insert(param.clone());
+                }
+                let body = namespace_function_body(body, alias, names, fn_scope);
+                ast::MaterialStatement::Function {
+                    name,
+                    params,
+                    body,
+                    span,
+                }
+            }
+        })
+        .collect();
+    def
+}
+
+fn namespace_function_body(
+    body: Vec<ast::MaterialFunctionStatement>,
+    alias: &str,
+    names: &HashSet<String>,
+    mut fn_scope: HashSet<String>,
+) -> Vec<ast::MaterialFunctionStatement> {
+    let mut rewritten_body = Vec::with_capacity(body.len());
+    for stmt in body {
+        match stmt {
+            ast::MaterialFunctionStatement::Binding {
+                name,
+                mutable,
+                expr,
+            } => {
+                let expr = namespace_expr(expr, alias, names, &fn_scope);
+                fn_scope.insert(name.clone());
+                rewritten_body.push(ast::MaterialFunctionStatement::Binding {
+                    name,
+                    mutable,
+                    expr,
+                });
+            }
+            ast::MaterialFunctionStatement::Assign { name, expr } => {
+                rewritten_body.push(ast::MaterialFunctionStatement::Assign {
+                    expr: namespace_expr(expr, alias, names, &fn_scope),
+                    name,
+                });
+            }
+            ast::MaterialFunctionStatement::Break => {
+                rewritten_body.push(ast::MaterialFunctionStatement::Break);
+            }
+            ast::MaterialFunctionStatement::Return { expr } => {
+                rewritten_body.push(ast::MaterialFunctionStatement::Return {
+                    expr: namespace_expr(expr, alias, names, &fn_scope),
+                });
+            }
+            ast::MaterialFunctionStatement::If {
+                cond,
+                then_body,
+                else_body,
+            } => {
+                rewritten_body.push(ast::MaterialFunctionStatement::If {
+                    cond: namespace_expr(cond, alias, names, &fn_scope),
+                    then_body: namespace_function_body(then_body, alias, names, fn_scope.clone()),
+                    else_body: namespace_function_body(else_body, alias, names, fn_scope.clone()),
+                });
+            }
+            ast::MaterialFunctionStatement::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                let mut body_scope = fn_scope.clone();
+                body_scope.insert(var.clone());
+                rewritten_body.push(ast::MaterialFunctionStatement::For {
+                    start: namespace_expr(start, alias, names, &fn_scope),
+                    end: namespace_expr(end, alias, names, &fn_scope),
+                    body: namespace_function_body(body, alias, names, body_scope),
+                    var,
+                });
+            }
+        }
+    }
+    rewritten_body
+}
+
 fn namespace_expr(
     expr: Expr,
     alias: &str,
     top_level_names: &HashSet<String>,
     local_scope: &HashSet<String>,
 ) -> Expr {
-    match expr {
-        Expr::String(_) => expr,
-        Expr::Array(items) => Expr::Array(
+    let kind = match expr.kind {
+        ExprKind::Array(items) => ExprKind::Array(
             items
                 .into_iter()
                 .map(|item| namespace_expr(item, alias, top_level_names, local_scope))
                 .collect(),
         ),
-        Expr::Ident(name) => {
+        ExprKind::Ident(name) => {
             if top_level_names.contains(&name) && !local_scope.contains(&name) {
-                Expr::Ident(qualify_name(alias, &name))
+                ExprKind::Ident(qualify_name(alias, &name))
             } else {
-                Expr::Ident(name)
+                ExprKind::Ident(name)
             }
         }
-        Expr::ObjectLiteral { type_name, fields } => {
+        ExprKind::ObjectLiteral { type_name, fields } => {
             let type_name = if top_level_names.contains(&type_name) {
                 qualify_name(alias, &type_name)
             } else {
                 type_name
             };
-            Expr::ObjectLiteral {
+            ExprKind::ObjectLiteral {
                 type_name,
                 fields: fields
                     .into_iter()
@@ -1265,29 +1549,65 @@ fn namespace_expr(
                     .collect(),
             }
         }
-        Expr::Binary { lhs, op, rhs } => Expr::Binary {
+        ExprKind::Binary { lhs, op, rhs } => ExprKind::Binary {
             lhs: Box::new(namespace_expr(*lhs, alias, top_level_names, local_scope)),
             op,
             rhs: Box::new(namespace_expr(*rhs, alias, top_level_names, local_scope)),
         },
-        Expr::Member { target, field } => Expr::Member {
+        ExprKind::Member { target, field } => ExprKind::Member {
             target: Box::new(namespace_expr(*target, alias, top_level_names, local_scope)),
             field,
         },
-        Expr::Call { callee, args } => Expr::Call {
+        ExprKind::Call { callee, args } => ExprKind::Call {
             callee: Box::new(namespace_expr(*callee, alias, top_level_names, local_scope)),
             args: args
                 .into_iter()
                 .map(|arg| namespace_expr(arg, alias, top_level_names, local_scope))
                 .collect(),
         },
-        Expr::Unary { op, expr } => Expr::Unary {
+        ExprKind::Unary { op, expr } => ExprKind::Unary {
             op,
             expr: Box::new(namespace_expr(*expr, alias, top_level_names, local_scope)),
         },
-        Expr::FunctionLiteral { params, body } => Expr::FunctionLiteral { params, body },
-        Expr::Number(_) => expr,
-    }
+        ExprKind::If {
+            cond,
+            then_branch,
+            else_branch,
+        } => ExprKind::If {
+            cond: Box::new(namespace_expr(*cond, alias, top_level_names, local_scope)),
+            then_branch: Box::new(namespace_expr(
+                *then_branch,
+                alias,
+                top_level_names,
+                local_scope,
+            )),
+            else_branch: Box::new(namespace_expr(
+                *else_branch,
+                alias,
+                top_level_names,
+                local_scope,
+            )),
+        },
+        ExprKind::For {
+            var,
+            start,
+            end,
+            body,
+        } => {
+            let mut body_scope = local_scope.clone();
+            body_scope.insert(var.clone());
+            ExprKind::For {
+                start: Box::new(namespace_expr(*start, alias, top_level_names, local_scope)),
+                end: Box::new(namespace_expr(*end, alias, top_level_names, local_scope)),
+                body: Box::new(namespace_expr(*body, alias, top_level_names, &body_scope)),
+                var,
+            }
+        }
+        kind @ (ExprKind::String(_) | ExprKind::Number(_) | ExprKind::FunctionLiteral { .. }) => {
+            kind
+        }
+    };
+    Expr::new(kind, expr.span)
 }
 
 fn namespace_path(path: Vec<String>, alias: &str, names: &HashSet<String>) -> Vec<String> {
@@ -1308,12 +1628,13 @@ fn qualify_name(alias: &str, name: &str) -> String {
 #[cfg(test)]
 mod tests {
     use super::{
-        CoreError, ObjectValue, Value, eval_environment_function,
+        CoreError, FileId, ObjectValue, Statement, Value, check_scene, eval_environment_function,
         eval_material_function_with_overrides, eval_material_properties_with_overrides,
         eval_program, eval_sdf_function, eval_sdf_function_args_with_overrides,
         eval_sdf_function_with_overrides, eval_sdf_vec3_function_with_overrides,
         eval_sdf_zero_arg_function, eval_sdf_zero_arg_function_with_overrides,
-        eval_top_level_function, load_and_eval_scene, load_program_with_imports, parse_program,
+        eval_top_level_function, format_program, format_source, load_and_eval_scene,
+        load_program_with_imports, parse_program, parse_program_recovering, scene_content_hash,
     };
     use std::{
         collections::HashMap,
@@ -1992,6 +2313,303 @@ mod tests {
         );
     }
 
+    #[test]
+    fn evaluates_conditionals_and_comparisons() {
+        let source = r#"
+            fn sign(x) {
+              if x < 0.0 {
+                return -1.0;
+              } else if x == 0.0 {
+                return 0.0;
+              }
+              return 1.0;
+            }
+
+            fn band(x) {
+              let v = 1.0;
+              if x > 2.0 && x <= 4.0 {
+                let v = 10.0;
+                return v + x;
+              }
+              if !(x > 0.0) || x == 1.0 {
+                let v = 5.0;
+              }
+              return if x >= 2.0 { v * 2.0 } else { v * 3.0 };
+            }
+
+            let a = sign(-3.0);
+            let lazy = 0.0 && missing;
+            let either = 1.0 || missing;
+            let mask = vec3(1.0, 2.0, 3.0) > 1.5;
+            let same = "gold" == "gold";
+        "#;
+        let program = parse_program(source).expect("program should parse");
+        let state = eval_program(&program).expect("program should evaluate");
+        assert!(state.jitted_functions.contains_key("sign"));
+        assert!(state.jitted_functions.contains_key("band"));
+        for (x, expected) in [(-3.0, -1.0), (0.0, 0.0), (2.5, 1.0)] {
+            assert_eq!(
+                eval_top_level_function(&state, "sign", &[Value::Number(x)])
+                    .expect("function should evaluate"),
+                Value::Number(expected)
+            );
+        }
+        for (x, expected) in [(3.0, 13.0), (1.0, 3.0), (-1.0, 3.0), (5.0, 2.0)] {
+            assert_eq!(
+                eval_top_level_function(&state, "band", &[Value::Number(x)])
+                    .expect("function should evaluate"),
+                Value::Number(expected)
+            );
+        }
+        let binding = |name: &str| state.bindings.get(name).expect("binding").value.clone();
+        assert_eq!(binding("a"), Value::Number(-1.0));
+        assert_eq!(binding("lazy"), Value::Number(0.0));
+        assert_eq!(binding("either"), Value::Number(1.0));
+        assert_eq!(binding("same"), Value::Number(1.0));
+        let Value::Object(mask) = binding("mask") else {
+            panic!("mask should be a vec3");
+        };
+        assert_eq!(mask.fields.get("x"), Some(&Value::Number(0.0)));
+        assert_eq!(mask.fields.get("z"), Some(&Value::Number(1.0)));
+
+        assert!(parse_program("let x = if 1.0 { 2.0 };").is_err());
+        assert!(parse_program("let x = 1.0 | 2.0;").is_err());
+    }
+
+    #[test]
+    fn vm_and_interpreter_agree_on_logical_operands() {
+        let source = r#"
+            fn either(v) {
+              return 0.0 || v;
+            }
+
+            fn both(v) {
+              return 1.0 && v;
+            }
+        "#;
+        let program = parse_program(source).expect("program should parse");
+        let vm_state = eval_program(&program).expect("program should evaluate");
+        assert!(vm_state.compiled_functions.contains_key("either"));
+        let mut interpreter_state = vm_state.clone();
+        interpreter_state.compiled_functions.clear();
+        interpreter_state.jitted_functions.clear();
+
+        let vector = Value::Object(ObjectValue {
+            type_name: Some("vec3".to_string()),
+            fields: HashMap::from([
+                ("x".to_string(), Value::Number(0.0)),
+                ("y".to_string(), Value::Number(2.0)),
+                ("z".to_string(), Value::Number(0.0)),
+            ]),
+        });
+        for name in ["either", "both"] {
+            for arg in [Value::Number(0.0), Value::Number(3.0), vector.clone()] {
+                let args = [arg];
+                let vm = eval_top_level_function(&vm_state, name, &args).ok();
+                let interpreter = eval_top_level_function(&interpreter_state, name, &args).ok();
+                assert_eq!(vm, interpreter, "{name}({:?})", args[0]);
+            }
+        }
+        assert_eq!(
+            eval_top_level_function(&vm_state, "either", &[Value::Number(3.0)]).ok(),
+            Some(Value::Number(1.0))
+        );
+        assert!(eval_top_level_function(&vm_state, "both", &[vector]).is_err());
+    }
+
+    #[test]
+    fn jits_sdf_distance_with_branches() {
+        let source = r#"
+            sdf Capsule {
+              let radius = 0.5;
+
+              fn cap(p, y) {
+                return length(p - vec3(0.0, y, 0.0)) - radius;
+              }
+
+              fn distance(p) {
+                if p.y > 1.0 {
+                  return cap(p, 1.0);
+                }
+                let d = if p.y < -1.0 { cap(p, -1.0) } else { length(vec3(p.x, 0.0, p.z)) - radius };
+                return d;
+              }
+            };
+        "#;
+        let program = parse_program(source).expect("program should parse");
+        let state = eval_program(&program).expect("program should evaluate");
+        assert!(state.jitted_sdf_distance_functions.contains_key("Capsule"));
+        for (y, expected) in [(3.0, 1.5), (-2.0, 0.5), (0.0, -0.5)] {
+            let value = eval_sdf_function(
+                &state,
+                "Capsule",
+                "distance",
+                Value::Object(ObjectValue {
+                    type_name: Some("vec3".to_string()),
+                    fields: HashMap::from([
+                        ("x".to_string(), Value::Number(0.0)),
+                        ("y".to_string(), Value::Number(y)),
+                        ("z".to_string(), Value::Number(0.0)),
+                    ]),
+                }),
+            )
+            .expect("distance evaluation should succeed");
+            assert_eq!(value, Value::Number(expected));
+        }
+    }
+
+    #[test]
+    fn evaluates_bounded_for_loops() {
+        let source = r#"
+            fn sum_below(n) {
+              var total = 0.0;
+              for i in 0..n {
+                if i == 5.0 {
+                  break;
+                }
+                total = total + i;
+              }
+              return total;
+            }
+
+            fn first_square_over(limit) {
+              for i in 1..100 {
+                if i * i > limit {
+                  return i;
+                }
+              }
+              return 0.0;
+            }
+
+            fn capped() {
+              var count = 0.0;
+              for i in 0..100000 {
+                count = count + 1.0;
+              }
+              return count;
+            }
+
+            fn frozen() {
+              let x = 1.0;
+              x = 2.0;
+              return x;
+            }
+
+            fn far_count(n) {
+              var count = 0.0;
+              for i in n..(n + 64.0) {
+                count = count + 1.0;
+              }
+              return count;
+            }
+        "#;
+        let program = parse_program(source).expect("program should parse");
+        let state = eval_program(&program).expect("program should evaluate");
+        assert!(state.jitted_functions.contains_key("sum_below"));
+        assert!(state.jitted_functions.contains_key("first_square_over"));
+        let call = |name: &str, args: &[Value]| {
+            eval_top_level_function(&state, name, args).expect("function should evaluate")
+        };
+        assert_eq!(call("sum_below", &[Value::Number(3.0)]), Value::Number(3.0));
+        assert_eq!(
+            call("sum_below", &[Value::Number(10.0)]),
+            Value::Number(10.0)
+        );
+        assert_eq!(
+            call("first_square_over", &[Value::Number(50.0)]),
+            Value::Number(8.0)
+        );
+        assert_eq!(call("capped", &[]), Value::Number(1024.0));
+        assert!(eval_top_level_function(&state, "frozen", &[]).is_err());
+
+        // `n + 1.0 == n` at this magnitude, so the loop has to count iterations instead.
+        let far = [Value::Number(1.0e8)];
+        assert!(state.compiled_functions.contains_key("far_count"));
+        assert_eq!(call("far_count", &far), Value::Number(64.0));
+        assert_eq!(
+            state.jitted_functions["far_count"].invoke(&[1.0e8]),
+            Some(64.0)
+        );
+        let mut interpreter_state = state.clone();
+        interpreter_state.compiled_functions.clear();
+        assert_eq!(
+            eval_top_level_function(&interpreter_state, "far_count", &far).ok(),
+            Some(Value::Number(64.0))
+        );
+
+        assert!(parse_program("fn f() { break; }").is_err());
+        assert!(parse_program("fn f() { for i in 0..3 { let g = fn() { break; }; } }").is_err());
+    }
+
+    #[test]
+    fn jits_sdf_distance_with_loops() {
+        let source = r#"
+            sdf Beads {
+              let count = 4.0;
+
+              fn distance(p) {
+                var d = 1000.0;
+                for i in 0..count {
+                  let center = vec3(i * 2.0, 0.0, 0.0);
+                  d = min(d, length(p - center) - 0.5);
+                }
+                return d;
+              }
+            };
+
+            sdf FarBeads {
+              let base = 100000000.0;
+
+              fn distance(p) {
+                var count = 0.0;
+                for i in base..(base + 64.0) {
+                  count = count + 1.0;
+                }
+                return length(p) - count * 0.01;
+              }
+            };
+        "#;
+        let program = parse_program(source).expect("program should parse");
+        let state = eval_program(&program).expect("program should evaluate");
+        assert!(state.jitted_sdf_distance_functions.contains_key("Beads"));
+        assert!(state.jitted_sdf_distance_functions.contains_key("FarBeads"));
+        let far = eval_sdf_function(
+            &state,
+            "FarBeads",
+            "distance",
+            Value::Object(ObjectValue {
+                type_name: Some("vec3".to_string()),
+                fields: HashMap::from([
+                    ("x".to_string(), Value::Number(0.0)),
+                    ("y".to_string(), Value::Number(0.0)),
+                    ("z".to_string(), Value::Number(0.0)),
+                ]),
+            }),
+        )
+        .expect("distance evaluation should succeed");
+        let Value::Number(far) = far else {
+            panic!("distance should be a number");
+        };
+        assert!((far + 0.64).abs() < 1.0e-5, "{far}");
+        for (x, expected) in [(6.0, -0.5), (3.0, 0.5), (10.0, 3.5)] {
+            let value = eval_sdf_function(
+                &state,
+                "Beads",
+                "distance",
+                Value::Object(ObjectValue {
+                    type_name: Some("vec3".to_string()),
+                    fields: HashMap::from([
+                        ("x".to_string(), Value::Number(x)),
+                        ("y".to_string(), Value::Number(0.0)),
+                        ("z".to_string(), Value::Number(0.0)),
+                    ]),
+                }),
+            )
+            .expect("distance evaluation should succeed");
+            assert_eq!(value, Value::Number(expected));
+        }
+    }
+
     #[test]
     fn parses_import_statement() {
         let program = parse_program("import \"materials/gold.ft\";").expect("parse should work");
@@ -2299,6 +2917,221 @@ mod tests {
         assert!(matches!(err, CoreError::ImportCycle(_)));
     }
 
+    #[test]
+    fn locates_errors_in_imported_files() {
+        let dir = temp_test_dir("imports_diagnostics");
+        fs::create_dir_all(&dir).expect("temp dir should exist");
+        fs::write(
+            dir.join("shapes.ft"),
+            "fn radius(x) {\n    return x * scale;\n}\n",
+        )
+        .expect("shapes.ft should write");
+        fs::write(
+            dir.join("main.ft"),
+            "import \"./shapes.ft\";\nlet a = 1.0;\n  let r = radius(a);\n",
+        )
+        .expect("main.ft should write");
+
+        let err = load_and_eval_scene(&dir.join("main.ft")).expect_err("eval should fail");
+        let CoreError::Located(diagnostic) = err else {
+            panic!("expected a located error, got {err}");
+        };
+        assert!(diagnostic.file.ends_with("shapes.ft"));
+        assert_eq!((diagnostic.line, diagnostic.column), (2, 16));
+        assert_eq!(
+            diagnostic.message,
+            "eval error: undefined identifier 'scale'"
+        );
+        let rendered = diagnostic.to_string();
+        assert!(rendered.ends_with("2 |     return x * scale;\n  |                ^^^^^"));
+
+        fs::write(
+            dir.join("shapes.ft"),
+            "fn radius(x) {\n    return x * 2.0\n}\n",
+        )
+        .expect("shapes.ft should write");
+        let err = load_and_eval_scene(&dir.join("main.ft")).expect_err("parse should fail");
+        let CoreError::Located(diagnostic) = err else {
+            panic!("expected a located error, got {err}");
+        };
+        assert!(diagnostic.file.ends_with("shapes.ft"));
+        assert_eq!((diagnostic.line, diagnostic.column), (3, 1));
+        assert!(diagnostic.message.starts_with("parse error: expected ;"));
+    }
+
+    #[test]
+    fn formats_source_keeping_comments_and_literals() {
+        let source = r#"// Header comment.
+import "Gold" as gold;
+
+
+let c=#EBC757;let n = 800; // width
+fn twice(x) = x*2.0;
+
+material Rust {
+  model: Metal; // model first
+  name: "Rust";
+  // Base tint.
+  color = vec3(0.5,0.2 , 0.1);
+  fn roughness(ctx) {
+    // Noisy breakup.
+    if ctx.uv.x < 0.5 { return 0.2; } else if ctx.uv.x<0.8 { return 0.3; } else { return 0.4; }
+  }
+};
+var s = Sphere{radius: 1.0}.attach(floor, Top).offset_x(-0.5);
+s.domain = fn(p) {
+  // Warp.
+  return (p);
+};
+// Trailing file comment.
+"#;
+        let expected = r#"// Header comment.
+import "Gold" as gold;
+
+let c = #ebc757;
+let n = 800; // width
+fn twice(x) = x * 2.0;
+
+material Rust {
+  model: Metal; // model first
+  name: "Rust";
+  // Base tint.
+  color = vec3(0.5, 0.2, 0.1);
+  fn roughness(ctx) {
+    // Noisy breakup.
+    if ctx.uv.x < 0.5 {
+      return 0.2;
+    } else if ctx.uv.x < 0.8 {
+      return 0.3;
+    } else {
+      return 0.4;
+    }
+  }
+};
+var s = Sphere {
+  radius: 1.0
+}
+  .attach(floor, Top)
+  .offset_x(-0.5);
+s.domain = fn(p) {
+  // Warp.
+  return p;
+};
+// Trailing file comment.
+"#;
+        let formatted = format_source(source).expect("source should format");
+        assert_eq!(formatted, expected);
+        assert_eq!(
+            format_source(&formatted).expect("formatted source should format"),
+            formatted
+        );
+
+        let program = parse_program("let c = #fff; fn f(x) = x;").expect("source should parse");
+        assert_eq!(
+            format_program(&program),
+            "let c = vec3 {\n  x: 1.0,\n  y: 1.0,\n  z: 1.0\n};\n\nfn f(x) {\n  return x;\n}\n"
+        );
+    }
+
+    #[test]
+    fn formats_source_keeping_comments_inside_expressions() {
+        let source = r#"let s = Sphere {
+  radius: 1.0, // unit
+  // Centered.
+  pos: vec3(0.0,0.0,0.0)
+};
+material Mixed {
+  color = mix(#ff0000, // red
+    #0000ff);
+  roughness = 0.2 + // base
+      0.1*2.0;
+};
+"#;
+        let expected = r#"let s = Sphere {
+  radius: 1.0, // unit
+  // Centered.
+  pos: vec3(0.0, 0.0, 0.0)
+};
+material Mixed {
+  color = mix(
+    #ff0000, // red
+    #0000ff
+  );
+  roughness = 0.2 + // base
+      0.1*2.0;
+};
+"#;
+        let formatted = format_source(source).expect("source should format");
+        assert_eq!(formatted, expected);
+        assert_eq!(
+            format_source(&formatted).expect("formatted source should format"),
+            formatted
+        );
+    }
+
+    #[test]
+    fn parser_recovers_and_collects_every_error() {
+        let source =
+            "let a = 1.0\nlet b = 2.0;\nlet c = #12345;\nfn f(x) { return x +; }\nlet d = b;\n";
+        let recovered = parse_program_recovering(source, FileId::default());
+        let offsets = recovered
+            .errors
+            .iter()
+            .map(|err| err.offset())
+            .collect::<Vec<_>>();
+        assert_eq!(offsets, vec![Some(12), Some(33), Some(61)]);
+        let names = recovered
+            .program
+            .statements
+            .iter()
+            .filter_map(|stmt| match stmt {
+                Statement::Binding { name, .. } => Some(name.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["b", "c", "d"]);
+        assert_eq!(recovered.unparsed_names, vec!["a", "f"]);
+        assert_eq!(recovered.program.spans.len(), 3);
+    }
+
+    #[test]
+    fn check_scene_reports_parse_and_eval_errors_across_imports() {
+        let dir = temp_test_dir("imports_check");
+        fs::create_dir_all(&dir).expect("temp dir should exist");
+        fs::write(
+            dir.join("lib.ft"),
+            "let size = 1.0\nlet gap = 0.5;\nlet broken = ;\n",
+        )
+        .expect("lib.ft should write");
+        fs::write(
+            dir.join("main.ft"),
+            "import \"./lib.ft\";\nlet a = size * 2.0;\nlet b = gap + missing;\nlet c = b + 1.0;\nlet d = broken;\n",
+        )
+        .expect("main.ft should write");
+
+        let check = check_scene(&dir.join("main.ft")).expect("scene should load");
+        let messages = check
+            .errors
+            .iter()
+            .map(|err| match err {
+                CoreError::Located(diagnostic) => {
+                    format!("{}:{}", diagnostic.line, diagnostic.message)
+                }
+                other => other.to_string(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec![
+                "2:parse error: expected ; at byte 15",
+                "3:parse error: unexpected token at byte 43",
+                "3:eval error: undefined identifier 'missing'",
+            ]
+        );
+        assert!(check.state.bindings.contains_key("gap"));
+        assert!(!check.state.bindings.contains_key("c"));
+    }
+
     #[test]
     fn material_properties_accept_instance_overrides() {
         let program = parse_program(
@@ -2816,6 +3649,38 @@ mod tests {
         );
     }
 
+    #[test]
+    fn scene_content_hash_follows_sources_and_assets() {
+        // FNV-1a test vector: checkpoints written by one build must still match the next.
+        let mut hasher = crate::hash::Fnv1a::new();
+        hasher.write(b"a");
+        assert_eq!(hasher.finish(), 0xaf63_dc4c_8601_ec8c);
+
+        let dir = temp_test_dir("content-hash");
+        fs::create_dir_all(&dir).expect("temp dir should exist");
+        let scene = dir.join("main.ft");
+        let source = |radius: f32| {
+            format!(
+                "let sky = HdriEnvironment {{ path: \"sky.hdr\" }};\n\
+                 let scene = Sphere {{ radius: {radius:.1} }};\n"
+            )
+        };
+        let hash = || {
+            let state = load_and_eval_scene(&scene).expect("scene should load");
+            scene_content_hash(&scene, &state).expect("scene should hash")
+        };
+        fs::write(&scene, source(1.0)).expect("scene should write");
+        fs::write(dir.join("sky.hdr"), b"first").expect("hdri should write");
+        let first = hash();
+        assert_eq!(first, hash());
+        fs::write(dir.join("sky.hdr"), b"second").expect("hdri should write");
+        let second = hash();
+        assert_ne!(first, second);
+        fs::write(&scene, source(2.0)).expect("scene should write");
+        assert_ne!(second, hash());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
     fn temp_test_dir(label: &str) -> PathBuf {
         let stamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
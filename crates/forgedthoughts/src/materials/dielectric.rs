@@ -2,6 +2,27 @@ use crate::render_api::{Spectrum, Vec3};
 
 use super::{BsdfSample, DielectricMaterial, SampleInput};
 
+/// Fraunhofer d, F and C lines in micrometres; `ior` is the index at the d line and the Abbe
+/// number compares it with the spread between F and C.
+const LINE_D: f32 = 0.5876;
+const LINE_F: f32 = 0.4861;
+const LINE_C: f32 = 0.6563;
+
+/// Cauchy `B` coefficient in µm² of a glass with index `ior` and Abbe number `abbe`; `0` for
+/// a non-positive Abbe number.
+pub fn cauchy_b_from_abbe(ior: f32, abbe: f32) -> f32 {
+    if abbe <= 0.0 {
+        return 0.0;
+    }
+    (ior - 1.0).max(0.0) / (abbe * (1.0 / (LINE_F * LINE_F) - 1.0 / (LINE_C * LINE_C)))
+}
+
+/// Cauchy's `n(λ) = A + B / λ²` with `A` chosen so `ior` stays the index at the d line.
+pub fn dispersed_ior(ior: f32, cauchy_b: f32, wavelength_nm: f32) -> f32 {
+    let lambda = wavelength_nm * 1.0e-3;
+    ior + cauchy_b * (1.0 / (lambda * lambda) - 1.0 / (LINE_D * LINE_D))
+}
+
 pub fn eval(_material: DielectricMaterial, _normal: Vec3, _wi: Vec3, _wo: Vec3) -> Spectrum {
     Spectrum::black()
 }
@@ -16,13 +37,12 @@ pub fn sample(
     wo: Vec3,
     input: SampleInput,
 ) -> BsdfSample {
-    let ior = material.ior.clamp(1.0, 3.0);
     let (eta_i, eta_t, next_ior) = if material.thin_walled {
         (1.0, 1.0, input.current_ior)
     } else if input.front_face {
-        (input.current_ior, ior, ior)
+        (input.current_ior, input.interior_ior, input.interior_ior)
     } else {
-        (input.current_ior, 1.0, 1.0)
+        (input.current_ior, input.exterior_ior, input.exterior_ior)
     };
     let n = normal.normalize();
     let incident = wo * -1.0;
@@ -69,7 +89,7 @@ fn reflect(v: Vec3, n: Vec3) -> Vec3 {
     v - n * (2.0 * v.dot(n))
 }
 
-fn refract(incident: Vec3, normal: Vec3, eta: f32) -> Option<Vec3> {
+pub(super) fn refract(incident: Vec3, normal: Vec3, eta: f32) -> Option<Vec3> {
     let i = incident.normalize();
     let mut n = normal.normalize();
     let mut cosi = i.dot(n).clamp(-1.0, 1.0);
@@ -86,7 +106,7 @@ fn refract(incident: Vec3, normal: Vec3, eta: f32) -> Option<Vec3> {
     Some((i * eta + n * (eta * cosi - cost)).normalize())
 }
 
-fn fresnel_dielectric_scalar(cos_theta_i: f32, eta_i: f32, eta_t: f32) -> f32 {
+pub(super) fn fresnel_dielectric_scalar(cos_theta_i: f32, eta_i: f32, eta_t: f32) -> f32 {
     let ei = eta_i.max(1.0e-4);
     let et = eta_t.max(1.0e-4);
     if (ei - et).abs() < 1.0e-6 {
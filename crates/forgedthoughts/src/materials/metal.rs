@@ -4,6 +4,130 @@ use crate::render_api::{Spectrum, Vec3};
 
 use super::{BsdfSample, MetalMaterial, SampleInput};
 
+/// Wavelengths in nanometres that stand in for the red, green and blue channels when a
+/// measured spectrum is reduced to RGB.
+pub const RGB_WAVELENGTHS: [f32; 3] = [630.0, 532.0, 465.0];
+
+/// Metals with a measured complex index of refraction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Conductor {
+    Gold,
+    Copper,
+    Aluminium,
+}
+
+/// First wavelength of the conductor tables and the spacing of their samples, in nanometres.
+const TABLE_START: f32 = 380.0;
+const TABLE_STEP: f32 = 40.0;
+
+// (n, k) from 380 to 780 nm. Gold and copper after Johnson & Christy, aluminium after Rakić.
+const GOLD_NK: [(f32, f32); 11] = [
+    (1.70, 1.90),
+    (1.62, 1.94),
+    (1.40, 1.85),
+    (0.97, 1.87),
+    (0.43, 2.46),
+    (0.26, 2.85),
+    (0.20, 3.25),
+    (0.15, 3.70),
+    (0.15, 4.05),
+    (0.15, 4.45),
+    (0.16, 4.75),
+];
+const COPPER_NK: [(f32, f32); 11] = [
+    (1.25, 2.10),
+    (1.16, 2.30),
+    (1.14, 2.48),
+    (1.12, 2.60),
+    (1.04, 2.59),
+    (0.50, 2.80),
+    (0.27, 3.30),
+    (0.21, 3.67),
+    (0.21, 4.10),
+    (0.22, 4.45),
+    (0.24, 4.80),
+];
+const ALUMINIUM_NK: [(f32, f32); 11] = [
+    (0.43, 4.60),
+    (0.53, 5.10),
+    (0.64, 5.60),
+    (0.77, 6.08),
+    (0.92, 6.55),
+    (1.10, 7.00),
+    (1.30, 7.45),
+    (1.54, 7.87),
+    (1.83, 8.31),
+    (2.20, 8.50),
+    (2.63, 8.60),
+];
+
+impl Conductor {
+    /// Looks a preset up by name, ignoring case (`gold`, `copper`, `aluminium`, ...).
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "gold" | "au" => Some(Self::Gold),
+            "copper" | "cu" => Some(Self::Copper),
+            "aluminium" | "aluminum" | "al" => Some(Self::Aluminium),
+            _ => None,
+        }
+    }
+
+    /// Complex index of refraction `(n, k)` at a wavelength in nanometres.
+    #[must_use]
+    pub fn nk(self, wavelength: f32) -> (f32, f32) {
+        let table = match self {
+            Self::Gold => &GOLD_NK,
+            Self::Copper => &COPPER_NK,
+            Self::Aluminium => &ALUMINIUM_NK,
+        };
+        let x = ((wavelength - TABLE_START) / TABLE_STEP).clamp(0.0, (table.len() - 1) as f32);
+        let i = (x as usize).min(table.len() - 2);
+        let t = x - i as f32;
+        let (n0, k0) = table[i];
+        let (n1, k1) = table[i + 1];
+        (n0 + (n1 - n0) * t, k0 + (k1 - k0) * t)
+    }
+}
+
+/// A conductor's complex index of refraction at the three wavelengths its channels carry.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ComplexIor {
+    pub conductor: Conductor,
+    pub eta: Spectrum,
+    pub k: Spectrum,
+}
+
+impl ComplexIor {
+    /// The preset reduced to RGB.
+    #[must_use]
+    pub fn new(conductor: Conductor) -> Self {
+        Self::at_wavelengths(conductor, RGB_WAVELENGTHS)
+    }
+
+    /// The preset looked up at one wavelength per channel.
+    #[must_use]
+    pub fn at_wavelengths(conductor: Conductor, wavelengths: [f32; 3]) -> Self {
+        let [r, g, b] = wavelengths.map(|wavelength| conductor.nk(wavelength));
+        Self {
+            conductor,
+            eta: Spectrum::rgb(r.0, g.0, b.0),
+            k: Spectrum::rgb(r.1, g.1, b.1),
+        }
+    }
+
+    /// Unpolarized Fresnel reflectance from air at the incidence angle `cos_theta`.
+    #[must_use]
+    pub fn fresnel(self, cos_theta: f32) -> Spectrum {
+        let cos = cos_theta.clamp(0.0, 1.0);
+        Spectrum::rgb(
+            fresnel_conductor(cos, self.eta.r, self.k.r),
+            fresnel_conductor(cos, self.eta.g, self.k.g),
+            fresnel_conductor(cos, self.eta.b, self.k.b),
+        )
+    }
+}
+
 pub fn eval(material: MetalMaterial, normal: Vec3, wi: Vec3, wo: Vec3) -> Spectrum {
     let ndotl = normal.dot(wi).max(0.0);
     let ndotv = normal.dot(wo).max(0.0);
@@ -16,7 +140,10 @@ pub fn eval(material: MetalMaterial, normal: Vec3, wi: Vec3, wo: Vec3) -> Spectr
     let alpha = material.roughness.clamp(0.02, 1.0).powi(2);
     let d = ggx_d(ndoth, alpha);
     let g = smith_ggx_g(ndotl, ndotv, alpha);
-    let fresnel = fresnel_schlick(material.color, vdoth);
+    let fresnel = match material.conductor {
+        Some(ior) => material.color * ior.fresnel(vdoth),
+        None => fresnel_schlick(material.color, vdoth),
+    };
     fresnel.scale((d * g) / (4.0 * ndotl * ndotv).max(1.0e-5))
 }
 
@@ -85,6 +212,21 @@ fn fresnel_schlick(f0: Spectrum, cos_theta: f32) -> Spectrum {
     f0 + Spectrum::rgb(1.0, 1.0, 1.0).scale(m) + f0.scale(-m)
 }
 
+fn fresnel_conductor(cos: f32, eta: f32, k: f32) -> f32 {
+    let cos2 = cos * cos;
+    let sin2 = 1.0 - cos2;
+    let t0 = eta * eta - k * k - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).max(0.0).sqrt();
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let t2 = 2.0 * cos * a;
+    let rs = (t1 - t2) / (t1 + t2).max(1.0e-6);
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4).max(1.0e-6);
+    (0.5 * (rs + rp)).clamp(0.0, 1.0)
+}
+
 fn ggx_d(ndoth: f32, alpha: f32) -> f32 {
     let a2 = alpha * alpha;
     let n2 = ndoth * ndoth;
@@ -1,8 +1,12 @@
 mod dielectric;
 mod lambert;
 mod metal;
+mod openpbr;
 mod standard;
 
+pub use metal::{ComplexIor, Conductor};
+pub use openpbr::OpenPbrLayers;
+
 use crate::render_api::{Spectrum, Vec3};
 
 pub type LambertMaterial = MaterialParams;
@@ -15,22 +19,41 @@ pub enum MaterialKindTag {
     Lambert,
     Metal,
     Dielectric,
+    OpenPbr,
 }
 
 #[derive(Clone, Copy, PartialEq)]
 pub struct MediumParams {
-    pub ior: f32,
+    /// Index of refraction inside the medium; `None` takes the one of its material.
+    pub ior: Option<f32>,
     pub absorption_color: Spectrum,
     pub density: f32,
+    /// Scattering coefficient per unit distance; `0` keeps the medium purely absorbing.
+    pub scattering: f32,
+    pub scattering_color: Spectrum,
+    pub anisotropy: f32,
 }
 
 impl MediumParams {
     #[must_use]
     pub fn new(ior: f32, absorption_color: Spectrum, density: f32) -> Self {
         Self {
-            ior,
+            ior: Some(ior),
             absorption_color,
             density,
+            scattering: 0.0,
+            scattering_color: Spectrum::rgb(1.0, 1.0, 1.0),
+            anisotropy: 0.0,
+        }
+    }
+
+    #[must_use]
+    pub fn with_scattering(self, scattering: f32, color: Spectrum, anisotropy: f32) -> Self {
+        Self {
+            scattering,
+            scattering_color: color,
+            anisotropy,
+            ..self
         }
     }
 }
@@ -73,6 +96,10 @@ pub struct MaterialParams {
     pub specular_weight: f32,
     pub specular_color: Spectrum,
     pub ior: f32,
+    /// Cauchy dispersion coefficient in µm²; `0` keeps `ior` the same at every wavelength.
+    pub cauchy_b: f32,
+    /// Measured complex index of refraction that replaces the `color`-based Fresnel of a metal.
+    pub conductor: Option<ComplexIor>,
     pub clearcoat: f32,
     pub clearcoat_roughness: f32,
     pub transmission: f32,
@@ -80,7 +107,12 @@ pub struct MaterialParams {
     pub emission_color: Spectrum,
     pub emission_strength: f32,
     pub medium: Option<MediumParams>,
+    /// Nested-dielectric priority: where transmissive objects overlap, the one with the
+    /// higher value owns the space and the other's surfaces there are ignored.
+    pub priority: u32,
     pub subsurface: Option<SubsurfaceParams>,
+    /// Coat, fuzz, thin-film and anisotropy controls only the `OpenPbr` model reads.
+    pub layers: OpenPbrLayers,
     pub pattern: Option<ColorPattern>,
     pub dynamic_material_id: Option<u32>,
     pub dynamic_override_id: Option<u32>,
@@ -112,6 +144,8 @@ impl MaterialParams {
             specular_weight,
             specular_color,
             ior,
+            cauchy_b: 0.0,
+            conductor: None,
             clearcoat,
             clearcoat_roughness,
             transmission,
@@ -119,7 +153,9 @@ impl MaterialParams {
             emission_color,
             emission_strength,
             medium: None,
+            priority: 0,
             subsurface: None,
+            layers: OpenPbrLayers::default(),
             pattern: None,
             dynamic_material_id: None,
             dynamic_override_id: None,
@@ -136,6 +172,8 @@ impl MaterialParams {
             specular_weight: 1.0,
             specular_color: Spectrum::rgb(1.0, 1.0, 1.0),
             ior: 1.5,
+            cauchy_b: 0.0,
+            conductor: None,
             clearcoat: 0.0,
             clearcoat_roughness: 0.1,
             transmission: 0.0,
@@ -143,7 +181,9 @@ impl MaterialParams {
             emission_color,
             emission_strength,
             medium: None,
+            priority: 0,
             subsurface: None,
+            layers: OpenPbrLayers::default(),
             pattern: None,
             dynamic_material_id: None,
             dynamic_override_id: None,
@@ -165,6 +205,8 @@ impl MaterialParams {
             specular_weight: 1.0,
             specular_color: Spectrum::rgb(1.0, 1.0, 1.0),
             ior: 1.5,
+            cauchy_b: 0.0,
+            conductor: None,
             clearcoat: 0.0,
             clearcoat_roughness: 0.1,
             transmission: 0.0,
@@ -172,7 +214,9 @@ impl MaterialParams {
             emission_color,
             emission_strength,
             medium: None,
+            priority: 0,
             subsurface: None,
+            layers: OpenPbrLayers::default(),
             pattern: None,
             dynamic_material_id: None,
             dynamic_override_id: None,
@@ -196,6 +240,8 @@ impl MaterialParams {
             specular_weight: 1.0,
             specular_color: Spectrum::rgb(1.0, 1.0, 1.0),
             ior,
+            cauchy_b: 0.0,
+            conductor: None,
             clearcoat: 0.0,
             clearcoat_roughness: 0.1,
             transmission: 1.0,
@@ -203,20 +249,44 @@ impl MaterialParams {
             emission_color,
             emission_strength,
             medium: None,
+            priority: 0,
             subsurface: None,
+            layers: OpenPbrLayers::default(),
             pattern: None,
             dynamic_material_id: None,
             dynamic_override_id: None,
         }
     }
+
+    /// Sets the dispersion from an Abbe number, as quoted for optical glass; call it after
+    /// `ior` is final.
+    #[must_use]
+    pub fn with_abbe(self, abbe: f32) -> Self {
+        Self {
+            cauchy_b: dielectric::cauchy_b_from_abbe(self.ior, abbe),
+            ..self
+        }
+    }
+
+    /// Index of refraction at a wavelength in nanometres.
+    #[must_use]
+    pub fn ior_at(&self, wavelength_nm: f32) -> f32 {
+        if self.cauchy_b > 0.0 {
+            dielectric::dispersed_ior(self.ior, self.cauchy_b, wavelength_nm).clamp(1.0, 3.0)
+        } else {
+            self.ior
+        }
+    }
 }
 
 #[derive(Clone, Copy, PartialEq)]
+#[allow(clippy::large_enum_variant)]
 pub enum Material {
     Standard(MaterialParams),
     Lambert(MaterialParams),
     Metal(MaterialParams),
     Dielectric(MaterialParams),
+    OpenPbr(MaterialParams),
     Blend(BlendedMaterial),
 }
 
@@ -245,6 +315,12 @@ pub struct BsdfSample {
 pub struct SampleInput {
     pub front_face: bool,
     pub current_ior: f32,
+    /// Index of refraction beyond the surface when the ray enters the material: that of the
+    /// medium it fills, which need not be the material's own `ior`.
+    pub interior_ior: f32,
+    /// Index of refraction beyond the surface when the ray leaves the material; `1` unless it
+    /// sits inside another medium.
+    pub exterior_ior: f32,
     pub u1: f32,
     pub u2: f32,
     pub u3: f32,
@@ -265,6 +341,7 @@ impl Material {
             Material::Lambert(_) => MaterialKindTag::Lambert,
             Material::Metal(_) => MaterialKindTag::Metal,
             Material::Dielectric(_) => MaterialKindTag::Dielectric,
+            Material::OpenPbr(_) => MaterialKindTag::OpenPbr,
             Material::Blend(_) => MaterialKindTag::Lambert,
         }
     }
@@ -275,7 +352,8 @@ impl Material {
             Material::Standard(m)
             | Material::Lambert(m)
             | Material::Metal(m)
-            | Material::Dielectric(m) => m,
+            | Material::Dielectric(m)
+            | Material::OpenPbr(m) => m,
             Material::Blend(m) => {
                 if m.t < 0.5 {
                     m.a_params
@@ -291,7 +369,8 @@ impl Material {
             Material::Standard(m)
             | Material::Lambert(m)
             | Material::Metal(m)
-            | Material::Dielectric(m) => m.emission_color.scale(m.emission_strength.max(0.0)),
+            | Material::Dielectric(m)
+            | Material::OpenPbr(m) => m.emission_color.scale(m.emission_strength.max(0.0)),
             Material::Blend(m) => lerp_spectrum(
                 material_emission(m.a_model, m.a_params),
                 material_emission(m.b_model, m.b_params),
@@ -306,6 +385,7 @@ impl Material {
             Material::Lambert(m) => lambert::eval(m, normal, wi, wo),
             Material::Metal(m) => metal::eval(m, normal, wi, wo),
             Material::Dielectric(m) => dielectric::eval(m, normal, wi, wo),
+            Material::OpenPbr(m) => openpbr::eval(m, normal, wi, wo),
             Material::Blend(m) => lerp_spectrum(
                 material_eval(m.a_model, m.a_params, normal, wi, wo),
                 material_eval(m.b_model, m.b_params, normal, wi, wo),
@@ -320,6 +400,7 @@ impl Material {
             Material::Lambert(m) => lambert::pdf(m, normal, wi, wo),
             Material::Metal(m) => metal::pdf(m, normal, wi, wo),
             Material::Dielectric(m) => dielectric::pdf(m, normal, wi, wo),
+            Material::OpenPbr(m) => openpbr::pdf(m, normal, wi, wo),
             Material::Blend(m) => {
                 let t = m.t.clamp(0.0, 1.0);
                 material_pdf(m.a_model, m.a_params, normal, wi, wo) * (1.0 - t)
@@ -334,6 +415,7 @@ impl Material {
             Material::Lambert(m) => lambert::sample(m, normal, wo, input),
             Material::Metal(m) => metal::sample(m, normal, wo, input),
             Material::Dielectric(m) => dielectric::sample(m, normal, wo, input),
+            Material::OpenPbr(m) => openpbr::sample(m, normal, wo, input),
             Material::Blend(m) => sample_blend(m, normal, wo, input),
         }
     }
@@ -344,7 +426,8 @@ fn material_emission(model: MaterialKindTag, params: MaterialParams) -> Spectrum
         MaterialKindTag::Standard
         | MaterialKindTag::Lambert
         | MaterialKindTag::Metal
-        | MaterialKindTag::Dielectric => params
+        | MaterialKindTag::Dielectric
+        | MaterialKindTag::OpenPbr => params
             .emission_color
             .scale(params.emission_strength.max(0.0)),
     }
@@ -362,6 +445,7 @@ fn material_eval(
         MaterialKindTag::Lambert => lambert::eval(params, normal, wi, wo),
         MaterialKindTag::Metal => metal::eval(params, normal, wi, wo),
         MaterialKindTag::Dielectric => dielectric::eval(params, normal, wi, wo),
+        MaterialKindTag::OpenPbr => openpbr::eval(params, normal, wi, wo),
     }
 }
 
@@ -377,6 +461,7 @@ fn material_pdf(
         MaterialKindTag::Lambert => lambert::pdf(params, normal, wi, wo),
         MaterialKindTag::Metal => metal::pdf(params, normal, wi, wo),
         MaterialKindTag::Dielectric => dielectric::pdf(params, normal, wi, wo),
+        MaterialKindTag::OpenPbr => openpbr::pdf(params, normal, wi, wo),
     }
 }
 
@@ -392,6 +477,7 @@ fn material_sample(
         MaterialKindTag::Lambert => lambert::sample(params, normal, wo, input),
         MaterialKindTag::Metal => metal::sample(params, normal, wo, input),
         MaterialKindTag::Dielectric => dielectric::sample(params, normal, wo, input),
+        MaterialKindTag::OpenPbr => openpbr::sample(params, normal, wo, input),
     }
 }
 
@@ -0,0 +1,575 @@
+use std::f32::consts::PI;
+use std::sync::OnceLock;
+
+use crate::render_api::{Spectrum, Vec3};
+
+use super::dielectric::{fresnel_dielectric_scalar, refract};
+use super::metal::RGB_WAVELENGTHS;
+use super::{BsdfSample, MaterialParams, SampleInput};
+
+/// The parts of an OpenPBR surface `MaterialParams` has no field for. The coat's weight and
+/// roughness are `clearcoat` and `clearcoat_roughness`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OpenPbrLayers {
+    pub coat_ior: f32,
+    /// Tint of everything seen through the coat.
+    pub coat_color: Spectrum,
+    pub fuzz_weight: f32,
+    pub fuzz_color: Spectrum,
+    pub fuzz_roughness: f32,
+    pub thin_film_weight: f32,
+    /// Film thickness in micrometres.
+    pub thin_film_thickness: f32,
+    pub thin_film_ior: f32,
+    /// Stretches the specular highlight along the tangent; `0` is isotropic.
+    pub specular_anisotropy: f32,
+    /// Turns the tangent about the normal, in whole turns.
+    pub specular_rotation: f32,
+    /// Wavelengths in nanometres the thin film interferes at, one per channel.
+    pub wavelengths: [f32; 3],
+}
+
+impl Default for OpenPbrLayers {
+    fn default() -> Self {
+        Self {
+            coat_ior: 1.6,
+            coat_color: Spectrum::rgb(1.0, 1.0, 1.0),
+            fuzz_weight: 0.0,
+            fuzz_color: Spectrum::rgb(1.0, 1.0, 1.0),
+            fuzz_roughness: 0.5,
+            thin_film_weight: 0.0,
+            thin_film_thickness: 0.5,
+            thin_film_ior: 1.4,
+            specular_anisotropy: 0.0,
+            specular_rotation: 0.0,
+            wavelengths: RGB_WAVELENGTHS,
+        }
+    }
+}
+
+pub fn eval(material: MaterialParams, normal: Vec3, wi: Vec3, wo: Vec3) -> Spectrum {
+    if normal.dot(wi) <= 0.0 || normal.dot(wo) <= 0.0 {
+        return Spectrum::black();
+    }
+    Surface::new(material, normal).eval(wi, wo)
+}
+
+pub fn pdf(material: MaterialParams, normal: Vec3, wi: Vec3, wo: Vec3) -> f32 {
+    if normal.dot(wi) <= 0.0 || normal.dot(wo) <= 0.0 {
+        return 0.0;
+    }
+    Surface::new(material, normal).pdf(wi, wo)
+}
+
+pub fn sample(material: MaterialParams, normal: Vec3, wo: Vec3, input: SampleInput) -> BsdfSample {
+    let surface = Surface::new(material, normal);
+    let weights = surface.lobe_weights(normal.dot(wo).max(0.0));
+    let sum = weights.iter().sum::<f32>();
+    let mut pick = input.u3.clamp(0.0, 1.0) * sum;
+    let lobe = LOBES
+        .into_iter()
+        .zip(weights)
+        .find_map(|(lobe, weight)| {
+            pick -= weight;
+            (pick < 0.0).then_some(lobe)
+        })
+        .unwrap_or(Lobe::Diffuse);
+    let wi = match lobe {
+        Lobe::Transmission => {
+            let pick = weights[Lobe::Transmission as usize] / sum;
+            return surface.sample_transmission(wo, input, pick);
+        }
+        Lobe::Coat => surface.sample_ggx(wo, surface.coat_alpha, surface.coat_alpha, input),
+        Lobe::Specular => surface.sample_ggx(wo, surface.alpha_t, surface.alpha_b, input),
+        Lobe::Fuzz | Lobe::Diffuse => surface.sample_cosine(input),
+    };
+    BsdfSample {
+        wi,
+        f: eval(material, normal, wi, wo),
+        pdf: pdf(material, normal, wi, wo).max(1.0e-6),
+        delta: false,
+        apply_cos: true,
+        transmission: false,
+        thin_walled: false,
+        next_ior: input.current_ior,
+    }
+}
+
+/// Lobes in the order `Surface::lobe_weights` reports them, from the top layer down.
+#[derive(Clone, Copy)]
+enum Lobe {
+    Fuzz,
+    Coat,
+    Specular,
+    Diffuse,
+    Transmission,
+}
+
+const LOBES: [Lobe; 5] = [
+    Lobe::Fuzz,
+    Lobe::Coat,
+    Lobe::Specular,
+    Lobe::Diffuse,
+    Lobe::Transmission,
+];
+
+/// One shading point of the layered model: fuzz over coat over a base that blends a
+/// dielectric (specular over diffuse or refraction) with a metal.
+///
+/// Each layer passes on what its lobe does not reflect towards the viewer, estimated from the
+/// lobe's directional albedo, so the stack never reflects more than it receives.
+struct Surface {
+    params: MaterialParams,
+    layers: OpenPbrLayers,
+    normal: Vec3,
+    tangent: Vec3,
+    bitangent: Vec3,
+    metallic: f32,
+    transmission: f32,
+    /// `specular` times `specular_weight`, the same product `Standard` uses.
+    specular_weight: f32,
+    coat: f32,
+    fuzz: f32,
+    roughness: f32,
+    alpha_t: f32,
+    alpha_b: f32,
+    coat_roughness: f32,
+    coat_alpha: f32,
+    fuzz_roughness: f32,
+    /// Index of refraction above the base: the coat's where it covers the base.
+    outside_ior: f32,
+}
+
+impl Surface {
+    fn new(params: MaterialParams, normal: Vec3) -> Self {
+        let layers = params.layers;
+        let normal = normal.normalize();
+        let helper = if normal.y.abs() < 0.99 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let tangent = helper.cross(normal).normalize();
+        let bitangent = normal.cross(tangent).normalize();
+        let (sin, cos) = (2.0 * PI * layers.specular_rotation).sin_cos();
+        let tangent = (tangent * cos + bitangent * sin).normalize();
+        let bitangent = normal.cross(tangent).normalize();
+
+        let roughness = params.roughness.clamp(0.02, 1.0);
+        let anisotropy = layers.specular_anisotropy.clamp(0.0, 1.0);
+        let alpha = roughness * roughness;
+        let alpha_t = alpha * (2.0 / (1.0 + (1.0 - anisotropy).powi(2))).sqrt();
+        let coat = params.clearcoat.clamp(0.0, 1.0);
+        let coat_roughness = params.clearcoat_roughness.clamp(0.02, 1.0);
+        Self {
+            params,
+            layers,
+            normal,
+            tangent,
+            bitangent,
+            metallic: params.metallic.clamp(0.0, 1.0),
+            transmission: params.transmission.clamp(0.0, 1.0),
+            specular_weight: (params.specular * params.specular_weight).clamp(0.0, 1.0),
+            coat,
+            fuzz: layers.fuzz_weight.clamp(0.0, 1.0),
+            roughness,
+            alpha_t: alpha_t.max(MIN_ALPHA),
+            alpha_b: ((1.0 - anisotropy) * alpha_t).max(MIN_ALPHA),
+            coat_roughness,
+            coat_alpha: (coat_roughness * coat_roughness).max(MIN_ALPHA),
+            fuzz_roughness: layers.fuzz_roughness.clamp(0.0, 1.0),
+            outside_ior: 1.0 + (layers.coat_ior.clamp(1.0, 3.0) - 1.0) * coat,
+        }
+    }
+
+    fn local(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            v.dot(self.tangent),
+            v.dot(self.bitangent),
+            v.dot(self.normal),
+        )
+    }
+
+    fn world(&self, v: Vec3) -> Vec3 {
+        (self.tangent * v.x + self.bitangent * v.y + self.normal * v.z).normalize()
+    }
+
+    fn eval(&self, wi: Vec3, wo: Vec3) -> Spectrum {
+        let (nl, nv) = (self.normal.dot(wi), self.normal.dot(wo));
+        let h = (wi + wo).normalize();
+        let vdoth = wo.dot(h).max(0.0);
+        let (li, lo, lh) = (self.local(wi), self.local(wo), self.local(h));
+
+        let fuzz = self
+            .layers
+            .fuzz_color
+            .scale(self.fuzz * charlie_d(lh.z, self.fuzz_roughness) * ashikhmin_v(nl, nv));
+        let coat = self.coat
+            * fresnel_dielectric_scalar(vdoth, 1.0, self.layers.coat_ior)
+            * ggx_d(lh, self.coat_alpha, self.coat_alpha)
+            * ggx_g(li, lo, self.coat_alpha, self.coat_alpha)
+            / (4.0 * nl * nv).max(1.0e-5);
+
+        let microfacet = ggx_d(lh, self.alpha_t, self.alpha_b)
+            * ggx_g(li, lo, self.alpha_t, self.alpha_b)
+            / (4.0 * nl * nv).max(1.0e-5);
+        let specular = (self.dielectric_fresnel(vdoth).scale(1.0 - self.metallic)
+            + self.metal_fresnel(vdoth).scale(self.metallic))
+        .scale(microfacet);
+        let diffuse = (self.params.color * one_minus(self.dielectric_albedo(nv)))
+            .scale((1.0 - self.metallic) * (1.0 - self.transmission) / PI);
+
+        let base = (specular + diffuse) * self.coat_transmittance(nv);
+        fuzz + (Spectrum::rgb(coat, coat, coat) + base) * self.fuzz_transmittance(nv)
+    }
+
+    fn pdf(&self, wi: Vec3, wo: Vec3) -> f32 {
+        let weights = self.lobe_weights(self.normal.dot(wo).max(0.0));
+        let sum = weights.iter().sum::<f32>();
+        let cosine = self.normal.dot(wi).max(0.0) / PI;
+        if sum <= 1.0e-6 {
+            return cosine;
+        }
+        let (li, lo) = (self.local(wi), self.local(wo));
+        (weights[Lobe::Fuzz as usize] * cosine
+            + weights[Lobe::Coat as usize] * ggx_pdf(li, lo, self.coat_alpha, self.coat_alpha)
+            + weights[Lobe::Specular as usize] * ggx_pdf(li, lo, self.alpha_t, self.alpha_b)
+            + weights[Lobe::Diffuse as usize] * cosine)
+            / sum
+    }
+
+    /// Rough share of the light leaving towards `wo` that each lobe accounts for.
+    fn lobe_weights(&self, nv: f32) -> [f32; 5] {
+        let fuzz = self.fuzz * fuzz_albedo(nv, self.fuzz_roughness) * mean(self.layers.fuzz_color);
+        let after_fuzz = mean(self.fuzz_transmittance(nv));
+        let coat = after_fuzz * self.coat_albedo(nv);
+        let below = mean(self.coat_transmittance(nv)) * after_fuzz;
+        let specular = below
+            * mean(
+                self.dielectric_fresnel(nv).scale(1.0 - self.metallic)
+                    + self.metal_fresnel(nv).scale(self.metallic),
+            )
+            * ggx_albedo(nv, self.roughness);
+        let entering = below * (1.0 - self.metallic) * mean(one_minus(self.dielectric_albedo(nv)));
+        [
+            fuzz,
+            coat,
+            specular,
+            entering * (1.0 - self.transmission) * mean(self.params.color),
+            entering * self.transmission,
+        ]
+    }
+
+    /// Fresnel of the dielectric base against whatever lies above it, scaled by the
+    /// specular weight and tint.
+    fn dielectric_fresnel(&self, cos: f32) -> Spectrum {
+        let ior = self.params.ior.clamp(1.0, 3.0);
+        let plain = fresnel_dielectric_scalar(cos, self.outside_ior, ior);
+        let fresnel = self.with_film(Spectrum::rgb(plain, plain, plain), cos, [ior; 3]);
+        (fresnel * self.params.specular_color).scale(self.specular_weight)
+    }
+
+    /// Fresnel of the metal base: Schlick's curve from `color` bent towards `specular_color`
+    /// near grazing angles (the F82-tint model).
+    fn metal_fresnel(&self, cos: f32) -> Spectrum {
+        let f0 = self.params.color;
+        let tint = self.params.specular_color;
+        let f82 = |f0: f32, tint: f32| {
+            const MU: f32 = 1.0 / 7.0;
+            let a = schlick(f0, MU) * (1.0 - tint) / (MU * (1.0 - MU).powi(6));
+            (schlick(f0, cos) - a * cos * (1.0 - cos).powi(6)).clamp(0.0, 1.0)
+        };
+        let fresnel = Spectrum::rgb(f82(f0.r, tint.r), f82(f0.g, tint.g), f82(f0.b, tint.b));
+        // A real index that reflects `color` at normal incidence stands in for the metal.
+        let eta = |f0: f32| {
+            let r = f0.clamp(0.0, 0.99).sqrt();
+            (1.0 + r) / (1.0 - r)
+        };
+        self.with_film(fresnel, cos, [eta(f0.r), eta(f0.g), eta(f0.b)])
+            .scale(self.specular_weight)
+    }
+
+    /// `fresnel` blended towards the reflectance of the thin film on a substrate of index
+    /// `substrate` per channel.
+    fn with_film(&self, fresnel: Spectrum, cos: f32, substrate: [f32; 3]) -> Spectrum {
+        let weight = self.layers.thin_film_weight.clamp(0.0, 1.0);
+        if weight <= 0.0 {
+            return fresnel;
+        }
+        let film = |channel: usize| {
+            thin_film_reflectance(
+                cos,
+                self.outside_ior,
+                self.layers.thin_film_ior.max(1.0),
+                substrate[channel],
+                self.layers.thin_film_thickness.max(0.0) * 1000.0,
+                self.layers.wavelengths[channel],
+            )
+        };
+        lerp_spectrum(fresnel, Spectrum::rgb(film(0), film(1), film(2)), weight)
+    }
+
+    fn dielectric_albedo(&self, cos: f32) -> Spectrum {
+        self.dielectric_fresnel(cos)
+            .scale(ggx_albedo(cos, self.roughness))
+    }
+
+    fn coat_albedo(&self, cos: f32) -> f32 {
+        self.coat
+            * fresnel_dielectric_scalar(cos, 1.0, self.layers.coat_ior)
+            * ggx_albedo(cos, self.coat_roughness)
+    }
+
+    /// What the coat lets through to and back from the base when seen from `cos`, tinted by
+    /// `coat_color`.
+    fn coat_transmittance(&self, cos: f32) -> Spectrum {
+        lerp_spectrum(
+            Spectrum::rgb(1.0, 1.0, 1.0),
+            self.layers.coat_color,
+            self.coat,
+        )
+        .scale(1.0 - self.coat_albedo(cos))
+    }
+
+    fn fuzz_transmittance(&self, cos: f32) -> Spectrum {
+        one_minus(
+            self.layers
+                .fuzz_color
+                .scale(self.fuzz * fuzz_albedo(cos, self.fuzz_roughness)),
+        )
+    }
+
+    fn sample_cosine(&self, input: SampleInput) -> Vec3 {
+        let u1 = input.u1.clamp(1.0e-6, 1.0 - 1.0e-6);
+        let phi = 2.0 * PI * input.u2.clamp(1.0e-6, 1.0 - 1.0e-6);
+        let r = u1.sqrt();
+        self.world(Vec3::new(r * phi.cos(), r * phi.sin(), (1.0 - u1).sqrt()))
+    }
+
+    fn sample_ggx(&self, wo: Vec3, alpha_t: f32, alpha_b: f32, input: SampleInput) -> Vec3 {
+        let h = self.world(sample_ggx_normal(alpha_t, alpha_b, input.u1, input.u2));
+        (h * (2.0 * wo.dot(h)) - wo).normalize()
+    }
+
+    /// Refraction through a smooth base, chosen with probability `pick`.
+    fn sample_transmission(&self, wo: Vec3, input: SampleInput, pick: f32) -> BsdfSample {
+        let thin_walled = self.params.thin_walled;
+        let (eta_i, eta_t, next_ior) = if thin_walled {
+            (1.0, 1.0, input.current_ior)
+        } else if input.front_face {
+            (input.current_ior, input.interior_ior, input.interior_ior)
+        } else {
+            (input.current_ior, input.exterior_ior, input.exterior_ior)
+        };
+        let nv = self.normal.dot(wo).abs();
+        let above = self.coat_transmittance(nv) * self.fuzz_transmittance(nv);
+        let weight = (1.0 - self.metallic) * self.transmission;
+        let pdf = pick.max(1.0e-6);
+        match refract(wo * -1.0, self.normal, eta_i / eta_t) {
+            Some(wi) => {
+                let fresnel = fresnel_dielectric_scalar(nv, eta_i, eta_t);
+                BsdfSample {
+                    wi,
+                    f: (self.params.color * above).scale(weight * (1.0 - fresnel)),
+                    pdf,
+                    delta: true,
+                    apply_cos: false,
+                    transmission: true,
+                    thin_walled,
+                    next_ior,
+                }
+            }
+            // Total internal reflection keeps what would have refracted on this side.
+            None => BsdfSample {
+                wi: (self.normal * (2.0 * self.normal.dot(wo)) - wo).normalize(),
+                f: above.scale(weight),
+                pdf,
+                delta: true,
+                apply_cos: false,
+                transmission: false,
+                thin_walled,
+                next_ior: input.current_ior,
+            },
+        }
+    }
+}
+
+const MIN_ALPHA: f32 = 4.0e-4;
+
+/// Anisotropic GGX distribution for a half vector in the local frame.
+fn ggx_d(h: Vec3, alpha_t: f32, alpha_b: f32) -> f32 {
+    if h.z <= 0.0 {
+        return 0.0;
+    }
+    let e = (h.x / alpha_t).powi(2) + (h.y / alpha_b).powi(2) + h.z * h.z;
+    1.0 / (PI * alpha_t * alpha_b * e * e)
+}
+
+fn ggx_lambda(w: Vec3, alpha_t: f32, alpha_b: f32) -> f32 {
+    let z2 = (w.z * w.z).max(1.0e-8);
+    let a2_tan2 = ((w.x * alpha_t).powi(2) + (w.y * alpha_b).powi(2)) / z2;
+    0.5 * ((1.0 + a2_tan2).sqrt() - 1.0)
+}
+
+/// Height-correlated Smith masking and shadowing.
+fn ggx_g(wi: Vec3, wo: Vec3, alpha_t: f32, alpha_b: f32) -> f32 {
+    1.0 / (1.0 + ggx_lambda(wi, alpha_t, alpha_b) + ggx_lambda(wo, alpha_t, alpha_b))
+}
+
+fn ggx_pdf(wi: Vec3, wo: Vec3, alpha_t: f32, alpha_b: f32) -> f32 {
+    let h = (wi + wo).normalize();
+    let vdoth = wo.dot(h).abs().max(1.0e-6);
+    ggx_d(h, alpha_t, alpha_b) * h.z.max(0.0) / (4.0 * vdoth)
+}
+
+/// Half vector drawn in proportion to `D(h) cos(θh)`.
+fn sample_ggx_normal(alpha_t: f32, alpha_b: f32, u1: f32, u2: f32) -> Vec3 {
+    let u1 = u1.clamp(1.0e-6, 1.0 - 1.0e-6);
+    let angle = 2.0 * PI * u2;
+    let phi = (alpha_b * angle.sin()).atan2(alpha_t * angle.cos());
+    let (sin_phi, cos_phi) = phi.sin_cos();
+    let inv = (cos_phi / alpha_t).powi(2) + (sin_phi / alpha_b).powi(2);
+    let tan2 = u1 / ((1.0 - u1) * inv);
+    let cos_theta = 1.0 / (1.0 + tan2).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    Vec3::new(sin_theta * cos_phi, sin_theta * sin_phi, cos_theta)
+}
+
+/// "Charlie" sheen distribution of Estevez and Kulla.
+fn charlie_d(ndoth: f32, roughness: f32) -> f32 {
+    let alpha = (roughness * roughness).max(0.01);
+    let sin2 = (1.0 - ndoth * ndoth).max(0.0);
+    (2.0 + 1.0 / alpha) * sin2.powf(0.5 / alpha) / (2.0 * PI)
+}
+
+fn ashikhmin_v(nl: f32, nv: f32) -> f32 {
+    1.0 / (4.0 * (nl + nv - nl * nv)).max(1.0e-5)
+}
+
+fn schlick(f0: f32, cos: f32) -> f32 {
+    f0 + (1.0 - f0) * (1.0 - cos.clamp(0.0, 1.0)).powi(5)
+}
+
+/// Reflectance of a film of index `film` and thickness `thickness_nm` between media of index
+/// `outside` and `substrate`, from the interference of its two interfaces (Airy summation).
+fn thin_film_reflectance(
+    cos: f32,
+    outside: f32,
+    film: f32,
+    substrate: f32,
+    thickness_nm: f32,
+    wavelength_nm: f32,
+) -> f32 {
+    let cos1 = cos.clamp(0.0, 1.0);
+    let sin2 = 1.0 - cos1 * cos1;
+    let cos_in = |ior: f32| 1.0 - sin2 * (outside / ior).powi(2);
+    let (cos2, cos3) = (cos_in(film), cos_in(substrate));
+    if cos2 <= 0.0 || cos3 <= 0.0 {
+        return 1.0;
+    }
+    let (cos2, cos3) = (cos2.sqrt(), cos3.sqrt());
+    let phase = 4.0 * PI * film * thickness_nm * cos2 / wavelength_nm.max(1.0);
+    let airy = |r12: f32, r23: f32| {
+        let cross = 2.0 * r12 * r23 * phase.cos();
+        (r12 * r12 + r23 * r23 + cross) / (1.0 + r12 * r12 * r23 * r23 + cross).max(1.0e-6)
+    };
+    let amplitude = |a: f32, b: f32| (a - b) / (a + b).max(1.0e-6);
+    let s = airy(
+        amplitude(outside * cos1, film * cos2),
+        amplitude(film * cos2, substrate * cos3),
+    );
+    let p = airy(
+        amplitude(film * cos1, outside * cos2),
+        amplitude(substrate * cos2, film * cos3),
+    );
+    (0.5 * (s + p)).clamp(0.0, 1.0)
+}
+
+/// Resolution of the directional albedo tables along incidence cosine and roughness.
+const ALBEDO_SIZE: usize = 16;
+const ALBEDO_STRATA: usize = 32;
+
+type AlbedoTable = [[f32; ALBEDO_SIZE]; ALBEDO_SIZE];
+
+/// Share of the light arriving at `cos` that a white, isotropic GGX lobe reflects.
+fn ggx_albedo(cos: f32, roughness: f32) -> f32 {
+    static TABLE: OnceLock<AlbedoTable> = OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        tabulate_albedo(|wo, roughness, u1, u2| {
+            let alpha = (roughness * roughness).max(MIN_ALPHA);
+            let h = sample_ggx_normal(alpha, alpha, u1, u2);
+            let wi = h * (2.0 * wo.dot(h)) - wo;
+            if wi.z <= 0.0 {
+                return 0.0;
+            }
+            ggx_g(wi, wo, alpha, alpha) * wo.dot(h) / (wo.z * h.z).max(1.0e-6)
+        })
+    });
+    lookup_albedo(table, cos, roughness)
+}
+
+/// Share of the light arriving at `cos` that a white fuzz lobe reflects.
+fn fuzz_albedo(cos: f32, roughness: f32) -> f32 {
+    static TABLE: OnceLock<AlbedoTable> = OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        tabulate_albedo(|wo, roughness, u1, u2| {
+            let r = u1.sqrt();
+            let phi = 2.0 * PI * u2;
+            let wi = Vec3::new(r * phi.cos(), r * phi.sin(), (1.0 - u1).sqrt());
+            let h = (wi + wo).normalize();
+            PI * charlie_d(h.z, roughness) * ashikhmin_v(wi.z, wo.z)
+        })
+    });
+    lookup_albedo(table, cos, roughness)
+}
+
+/// Averages `estimate(wo, roughness, u1, u2)` over stratified samples at each table entry.
+fn tabulate_albedo(estimate: impl Fn(Vec3, f32, f32, f32) -> f32) -> AlbedoTable {
+    let mut table = [[0.0; ALBEDO_SIZE]; ALBEDO_SIZE];
+    for (i, row) in table.iter_mut().enumerate() {
+        let cos = ((i as f32 + 0.5) / ALBEDO_SIZE as f32).clamp(1.0e-3, 1.0);
+        let wo = Vec3::new((1.0 - cos * cos).sqrt(), 0.0, cos);
+        for (j, entry) in row.iter_mut().enumerate() {
+            let roughness = j as f32 / (ALBEDO_SIZE - 1) as f32;
+            let mut sum = 0.0;
+            for a in 0..ALBEDO_STRATA {
+                for b in 0..ALBEDO_STRATA {
+                    let u1 = (a as f32 + 0.5) / ALBEDO_STRATA as f32;
+                    let u2 = (b as f32 + 0.5) / ALBEDO_STRATA as f32;
+                    sum += estimate(wo, roughness, u1, u2);
+                }
+            }
+            *entry = (sum / (ALBEDO_STRATA * ALBEDO_STRATA) as f32).clamp(0.0, 1.0);
+        }
+    }
+    table
+}
+
+fn lookup_albedo(table: &AlbedoTable, cos: f32, roughness: f32) -> f32 {
+    let last = (ALBEDO_SIZE - 1) as f32;
+    let x = (cos.clamp(0.0, 1.0) * ALBEDO_SIZE as f32 - 0.5).clamp(0.0, last);
+    let y = (roughness.clamp(0.0, 1.0) * last).clamp(0.0, last);
+    let (i, j) = (
+        (x as usize).min(ALBEDO_SIZE - 2),
+        (y as usize).min(ALBEDO_SIZE - 2),
+    );
+    let (tx, ty) = (x - i as f32, y - j as f32);
+    let row = |i: usize| table[i][j] * (1.0 - ty) + table[i][j + 1] * ty;
+    row(i) * (1.0 - tx) + row(i + 1) * tx
+}
+
+fn one_minus(s: Spectrum) -> Spectrum {
+    Spectrum::rgb(
+        (1.0 - s.r).max(0.0),
+        (1.0 - s.g).max(0.0),
+        (1.0 - s.b).max(0.0),
+    )
+}
+
+fn mean(s: Spectrum) -> f32 {
+    (s.r + s.g + s.b) / 3.0
+}
+
+fn lerp_spectrum(a: Spectrum, b: Spectrum, t: f32) -> Spectrum {
+    a.scale(1.0 - t) + b.scale(t)
+}
@@ -1,11 +1,11 @@
 use thiserror::Error;
 
 use crate::ast::{
-    BinaryOp, EnvironmentDef, Expr, FunctionDef, MaterialDef, MaterialFunctionStatement,
-    MaterialStatement, Program, SdfDef, SdfStatement, SkeletonDef, SkeletonStatement, Statement,
-    UnaryOp,
+    BinaryOp, EnvironmentDef, Expr, ExprKind, FileId, FunctionDef, MaterialDef,
+    MaterialFunctionStatement, MaterialStatement, Program, SdfDef, SdfStatement, SkeletonDef,
+    SkeletonStatement, Span, Statement, UnaryOp,
 };
-use crate::lexer::{LexError, Token, TokenKind, tokenize};
+use crate::lexer::{LexError, Token, TokenKind, tokenize, tokenize_recovering};
 
 #[derive(Debug, Error)]
 pub enum ParseError {
@@ -22,24 +22,160 @@ pub enum ParseError {
     UnexpectedEof,
 }
 
+impl ParseError {
+    /// Byte offset the error points at, or `None` when input ran out.
+    #[must_use]
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            Self::Lex(err) => Some(err.offset()),
+            Self::Expected { offset, .. } | Self::UnexpectedToken { offset } => Some(*offset),
+            Self::UnexpectedEof => None,
+        }
+    }
+}
+
 pub fn parse_program(source: &str) -> Result<Program, ParseError> {
+    parse_program_in_file(source, FileId::default())
+}
+
+/// Parses `source` and tags every statement span with `file`.
+pub fn parse_program_in_file(source: &str, file: FileId) -> Result<Program, ParseError> {
     let tokens = tokenize(source)?;
-    let mut parser = Parser { tokens, pos: 0 };
+    let mut parser = Parser::new(tokens, file);
     parser.parse_program()
 }
 
+/// Output of [`parse_program_recovering`].
+#[derive(Debug)]
+pub struct RecoveredProgram {
+    /// Every statement that parsed.
+    pub program: Program,
+    /// All lex and parse errors, in source order.
+    pub errors: Vec<ParseError>,
+    /// Names declared by statements that failed to parse, so later uses of them can be
+    /// told apart from genuinely undefined identifiers.
+    pub unparsed_names: Vec<String>,
+}
+
+/// Parses as much of `source` as possible. After an error the parser skips to the next
+/// `;` or closing `}` at statement level and carries on.
+pub fn parse_program_recovering(source: &str, file: FileId) -> RecoveredProgram {
+    let (tokens, lex_errors) = tokenize_recovering(source);
+    let mut parser = Parser::new(tokens, file);
+    let mut errors = lex_errors
+        .into_iter()
+        .map(ParseError::Lex)
+        .collect::<Vec<_>>();
+    let mut statements = Vec::new();
+    let mut spans = Vec::new();
+    let mut unparsed_names = Vec::new();
+    while !parser.is_eof() {
+        let start_pos = parser.pos;
+        match parser.parse_spanned_statement() {
+            Ok((stmt, span)) => {
+                statements.push(stmt);
+                spans.push(span);
+            }
+            Err(err) => {
+                errors.push(err);
+                unparsed_names.extend(parser.declared_name(start_pos));
+                parser.synchronize(start_pos);
+            }
+        }
+    }
+    errors.sort_by_key(|err| err.offset().unwrap_or(usize::MAX));
+    RecoveredProgram {
+        program: Program { statements, spans },
+        errors,
+        unparsed_names,
+    }
+}
+
 struct Parser {
     tokens: Vec<Token>,
     pos: usize,
+    file: FileId,
+    /// Set while parsing an `if` condition, where `ident {` opens the branch body
+    /// rather than an object literal.
+    no_object_literal: bool,
+    /// Number of enclosing `for` loops, so a stray `break` is rejected up front.
+    loop_depth: usize,
 }
 
 impl Parser {
+    fn new(tokens: Vec<Token>, file: FileId) -> Self {
+        Self {
+            tokens,
+            pos: 0,
+            file,
+            no_object_literal: false,
+            loop_depth: 0,
+        }
+    }
+
     fn parse_program(&mut self) -> Result<Program, ParseError> {
         let mut statements = Vec::new();
+        let mut spans = Vec::new();
         while !self.is_eof() {
-            statements.push(self.parse_statement()?);
+            let (stmt, span) = self.parse_spanned_statement()?;
+            statements.push(stmt);
+            spans.push(span);
+        }
+        Ok(Program { statements, spans })
+    }
+
+    fn parse_spanned_statement(&mut self) -> Result<(Statement, Span), ParseError> {
+        let start = self.current_offset();
+        let stmt = self.parse_statement()?;
+        Ok((stmt, self.span_from(start)))
+    }
+
+    /// Name a statement starting at token `start_pos` declares, e.g. `x` in `let x = ...`
+    /// or `Gold` in `material Gold { ... }`.
+    fn declared_name(&self, start_pos: usize) -> Option<String> {
+        let keyword = &self.tokens.get(start_pos)?.kind;
+        let declares = match keyword {
+            TokenKind::Let | TokenKind::Var | TokenKind::Fn => true,
+            TokenKind::Ident(word) => {
+                matches!(
+                    word.as_str(),
+                    "material" | "sdf" | "skeleton" | "environment" | "volume"
+                )
+            }
+            _ => false,
+        };
+        match &self.tokens.get(start_pos + 1)?.kind {
+            TokenKind::Ident(name) if declares => Some(name.clone()),
+            _ => None,
+        }
+    }
+
+    /// Skips past the statement that started at token `start_pos` after an error. Stops
+    /// after a `;` or a `}` that closes the statement's outermost brace, or before a
+    /// top-level keyword such as `let` or `fn`, whichever comes first past the error.
+    fn synchronize(&mut self, start_pos: usize) {
+        let error_pos = self.pos.max(start_pos + 1);
+        self.no_object_literal = false;
+        self.loop_depth = 0;
+        self.pos = start_pos;
+        let mut depth = 0usize;
+        while let Some(token) = self.tokens.get(self.pos) {
+            if self.pos >= error_pos && depth == 0 && starts_statement(&token.kind) {
+                return;
+            }
+            self.pos += 1;
+            match token.kind {
+                TokenKind::LBrace => depth += 1,
+                TokenKind::RBrace => {
+                    depth = depth.saturating_sub(1);
+                    if depth == 0 && self.pos > error_pos {
+                        return;
+                    }
+                }
+                TokenKind::Semicolon if depth == 0 && self.pos > error_pos => return,
+                _ => {}
+            }
         }
-        Ok(Program { statements })
     }
 
     fn parse_statement(&mut self) -> Result<Statement, ParseError> {
@@ -63,7 +199,13 @@ impl Parser {
         }
 
         if self.matches_ident_literal("environment") {
-            return self.parse_environment_def();
+            return Ok(Statement::EnvironmentDef(
+                self.parse_environment_def(false)?,
+            ));
+        }
+
+        if self.matches_ident_literal("volume") {
+            return Ok(Statement::VolumeDef(self.parse_environment_def(true)?));
         }
 
         if self.matches_kind(TokenKind::Fn) {
@@ -110,6 +252,7 @@ impl Parser {
     }
 
     fn parse_function_def(&mut self) -> Result<Statement, ParseError> {
+        let start = self.previous_start();
         let name = self.expect_ident()?;
         let params = self.parse_function_params()?;
         let body = if self.matches_kind(TokenKind::Equal) {
@@ -119,7 +262,12 @@ impl Parser {
         } else {
             self.parse_material_function_body()?
         };
-        Ok(Statement::FunctionDef(FunctionDef { name, params, body }))
+        Ok(Statement::FunctionDef(FunctionDef {
+            name,
+            params,
+            body,
+            span: self.span_from(start),
+        }))
     }
 
     fn parse_material_def(&mut self) -> Result<Statement, ParseError> {
@@ -143,6 +291,7 @@ impl Parser {
             }
 
             if self.matches_kind(TokenKind::Fn) {
+                let start = self.previous_start();
                 let fn_name = self.expect_ident()?;
                 let params = self.parse_function_params()?;
                 let body = if self.matches_kind(TokenKind::Equal) {
@@ -156,6 +305,7 @@ impl Parser {
                     name: fn_name,
                     params,
                     body,
+                    span: self.span_from(start),
                 });
                 continue;
             }
@@ -215,6 +365,7 @@ impl Parser {
             }
 
             if self.matches_kind(TokenKind::Fn) {
+                let start = self.previous_start();
                 let fn_name = self.expect_ident()?;
                 let params = self.parse_function_params()?;
                 let body = if self.matches_kind(TokenKind::Equal) {
@@ -228,6 +379,7 @@ impl Parser {
                     name: fn_name,
                     params,
                     body,
+                    span: self.span_from(start),
                 });
                 continue;
             }
@@ -255,7 +407,9 @@ impl Parser {
         }))
     }
 
-    fn parse_environment_def(&mut self) -> Result<Statement, ParseError> {
+    /// Body of an `environment` definition, or of a `volume` when `properties` allows
+    /// `name = expr;` assignments next to bindings and functions.
+    fn parse_environment_def(&mut self, properties: bool) -> Result<EnvironmentDef, ParseError> {
         let name = self.expect_ident()?;
         self.expect_kind(TokenKind::LBrace, "{")?;
         let mut metadata = Vec::new();
@@ -275,6 +429,7 @@ impl Parser {
             }
 
             if self.matches_kind(TokenKind::Fn) {
+                let start = self.previous_start();
                 let fn_name = self.expect_ident()?;
                 let params = self.parse_function_params()?;
                 let body = if self.matches_kind(TokenKind::Equal) {
@@ -288,11 +443,18 @@ impl Parser {
                     name: fn_name,
                     params,
                     body,
+                    span: self.span_from(start),
                 });
                 continue;
             }
 
             let field = self.expect_ident()?;
+            if properties && self.matches_kind(TokenKind::Equal) {
+                let expr = self.parse_expr()?;
+                self.expect_kind(TokenKind::Semicolon, ";")?;
+                statements.push(MaterialStatement::Property { name: field, expr });
+                continue;
+            }
             if matches!(field.as_str(), "name" | "description" | "tags" | "params") {
                 self.expect_kind(TokenKind::Colon, ":")?;
                 let expr = self.parse_expr()?;
@@ -302,17 +464,21 @@ impl Parser {
             }
 
             return Err(ParseError::Expected {
-                expected: "let, fn, or metadata field",
+                expected: if properties {
+                    "let, fn, property assignment, or metadata field"
+                } else {
+                    "let, fn, or metadata field"
+                },
                 offset: self.current_offset(),
             });
         }
 
         self.expect_kind(TokenKind::Semicolon, ";")?;
-        Ok(Statement::EnvironmentDef(EnvironmentDef {
+        Ok(EnvironmentDef {
             name,
             metadata,
             statements,
-        }))
+        })
     }
 
     fn parse_skeleton_def(&mut self) -> Result<Statement, ParseError> {
@@ -408,12 +574,30 @@ impl Parser {
         self.expect_kind(TokenKind::LBrace, "{")?;
         let mut body = Vec::new();
         while !self.matches_kind(TokenKind::RBrace) {
-            if self.matches_kind(TokenKind::Let) {
+            let mutable = matches!(self.peek_kind(), Some(TokenKind::Var));
+            if self.matches_kind(TokenKind::Let) || self.matches_kind(TokenKind::Var) {
                 let name = self.expect_ident()?;
                 self.expect_kind(TokenKind::Equal, "=")?;
                 let expr = self.parse_expr()?;
                 self.expect_kind(TokenKind::Semicolon, ";")?;
-                body.push(MaterialFunctionStatement::Binding { name, expr });
+                body.push(MaterialFunctionStatement::Binding {
+                    name,
+                    mutable,
+                    expr,
+                });
+                continue;
+            }
+            if let Some(TokenKind::Ident(name)) = self.peek_kind()
+                && matches!(
+                    self.tokens.get(self.pos + 1).map(|t| &t.kind),
+                    Some(TokenKind::Equal)
+                )
+            {
+                let name = name.clone();
+                self.pos += 2;
+                let expr = self.parse_expr()?;
+                self.expect_kind(TokenKind::Semicolon, ";")?;
+                body.push(MaterialFunctionStatement::Assign { name, expr });
                 continue;
             }
             if self.matches_kind(TokenKind::Return) {
@@ -422,14 +606,69 @@ impl Parser {
                 body.push(MaterialFunctionStatement::Return { expr });
                 continue;
             }
+            if self.matches_kind(TokenKind::If) {
+                body.push(self.parse_if_statement()?);
+                continue;
+            }
+            if self.matches_kind(TokenKind::For) {
+                body.push(self.parse_for_statement()?);
+                continue;
+            }
+            if matches!(self.peek_kind(), Some(TokenKind::Break)) {
+                if self.loop_depth == 0 {
+                    return Err(ParseError::UnexpectedToken {
+                        offset: self.current_offset(),
+                    });
+                }
+                self.pos += 1;
+                self.expect_kind(TokenKind::Semicolon, ";")?;
+                body.push(MaterialFunctionStatement::Break);
+                continue;
+            }
             return Err(ParseError::Expected {
-                expected: "let or return",
+                expected: "let, var, assignment, return, if, for, or break",
                 offset: self.current_offset(),
             });
         }
         Ok(body)
     }
 
+    fn parse_if_statement(&mut self) -> Result<MaterialFunctionStatement, ParseError> {
+        let cond = self.parse_condition()?;
+        let then_body = self.parse_material_function_body()?;
+        let else_body = if self.matches_kind(TokenKind::Else) {
+            if self.matches_kind(TokenKind::If) {
+                vec![self.parse_if_statement()?]
+            } else {
+                self.parse_material_function_body()?
+            }
+        } else {
+            Vec::new()
+        };
+        Ok(MaterialFunctionStatement::If {
+            cond,
+            then_body,
+            else_body,
+        })
+    }
+
+    fn parse_for_statement(&mut self) -> Result<MaterialFunctionStatement, ParseError> {
+        let var = self.expect_ident()?;
+        self.expect_kind(TokenKind::In, "in")?;
+        let start = self.parse_expr()?;
+        self.expect_kind(TokenKind::DotDot, "..")?;
+        let end = self.parse_condition()?;
+        self.loop_depth += 1;
+        let body = self.parse_material_function_body();
+        self.loop_depth -= 1;
+        Ok(MaterialFunctionStatement::For {
+            var,
+            start,
+            end,
+            body: body?,
+        })
+    }
+
     fn parse_function_params(&mut self) -> Result<Vec<String>, ParseError> {
         self.expect_kind(TokenKind::LParen, "(")?;
         let mut params = Vec::new();
@@ -470,10 +709,111 @@ impl Parser {
     }
 
     fn parse_expr(&mut self) -> Result<Expr, ParseError> {
-        self.parse_add_sub()
+        self.parse_or()
+    }
+
+    fn parse_nested_expr(&mut self) -> Result<Expr, ParseError> {
+        let restricted = std::mem::replace(&mut self.no_object_literal, false);
+        let expr = self.parse_expr();
+        self.no_object_literal = restricted;
+        expr
+    }
+
+    fn parse_condition(&mut self) -> Result<Expr, ParseError> {
+        let restricted = std::mem::replace(&mut self.no_object_literal, true);
+        let expr = self.parse_expr();
+        self.no_object_literal = restricted;
+        expr
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, ParseError> {
+        let start = self.current_offset();
+        let mut expr = self.parse_and()?;
+        while self.matches_kind(TokenKind::PipePipe) {
+            let rhs = self.parse_and()?;
+            expr = self.expr_from(
+                start,
+                ExprKind::Binary {
+                    lhs: Box::new(expr),
+                    op: BinaryOp::Or,
+                    rhs: Box::new(rhs),
+                },
+            );
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ParseError> {
+        let start = self.current_offset();
+        let mut expr = self.parse_equality()?;
+        while self.matches_kind(TokenKind::AmpAmp) {
+            let rhs = self.parse_equality()?;
+            expr = self.expr_from(
+                start,
+                ExprKind::Binary {
+                    lhs: Box::new(expr),
+                    op: BinaryOp::And,
+                    rhs: Box::new(rhs),
+                },
+            );
+        }
+        Ok(expr)
+    }
+
+    fn parse_equality(&mut self) -> Result<Expr, ParseError> {
+        let start = self.current_offset();
+        let mut expr = self.parse_comparison()?;
+        loop {
+            let op = if self.matches_kind(TokenKind::EqualEqual) {
+                BinaryOp::Equal
+            } else if self.matches_kind(TokenKind::BangEqual) {
+                BinaryOp::NotEqual
+            } else {
+                break;
+            };
+            let rhs = self.parse_comparison()?;
+            expr = self.expr_from(
+                start,
+                ExprKind::Binary {
+                    lhs: Box::new(expr),
+                    op,
+                    rhs: Box::new(rhs),
+                },
+            );
+        }
+        Ok(expr)
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, ParseError> {
+        let start = self.current_offset();
+        let mut expr = self.parse_add_sub()?;
+        loop {
+            let op = if self.matches_kind(TokenKind::Less) {
+                BinaryOp::Less
+            } else if self.matches_kind(TokenKind::LessEqual) {
+                BinaryOp::LessEqual
+            } else if self.matches_kind(TokenKind::Greater) {
+                BinaryOp::Greater
+            } else if self.matches_kind(TokenKind::GreaterEqual) {
+                BinaryOp::GreaterEqual
+            } else {
+                break;
+            };
+            let rhs = self.parse_add_sub()?;
+            expr = self.expr_from(
+                start,
+                ExprKind::Binary {
+                    lhs: Box::new(expr),
+                    op,
+                    rhs: Box::new(rhs),
+                },
+            );
+        }
+        Ok(expr)
     }
 
     fn parse_add_sub(&mut self) -> Result<Expr, ParseError> {
+        let start = self.current_offset();
         let mut expr = self.parse_mul_div()?;
         loop {
             let op = if self.matches_kind(TokenKind::Plus) {
@@ -488,11 +828,14 @@ impl Parser {
 
             if let Some(op) = op {
                 let rhs = self.parse_mul_div()?;
-                expr = Expr::Binary {
-                    lhs: Box::new(expr),
-                    op,
-                    rhs: Box::new(rhs),
-                };
+                expr = self.expr_from(
+                    start,
+                    ExprKind::Binary {
+                        lhs: Box::new(expr),
+                        op,
+                        rhs: Box::new(rhs),
+                    },
+                );
             } else {
                 break;
             }
@@ -501,6 +844,7 @@ impl Parser {
     }
 
     fn parse_mul_div(&mut self) -> Result<Expr, ParseError> {
+        let start = self.current_offset();
         let mut expr = self.parse_unary()?;
         loop {
             let op = if self.matches_kind(TokenKind::Star) {
@@ -513,11 +857,14 @@ impl Parser {
 
             if let Some(op) = op {
                 let rhs = self.parse_unary()?;
-                expr = Expr::Binary {
-                    lhs: Box::new(expr),
-                    op,
-                    rhs: Box::new(rhs),
-                };
+                expr = self.expr_from(
+                    start,
+                    ExprKind::Binary {
+                        lhs: Box::new(expr),
+                        op,
+                        rhs: Box::new(rhs),
+                    },
+                );
             } else {
                 break;
             }
@@ -526,25 +873,43 @@ impl Parser {
     }
 
     fn parse_unary(&mut self) -> Result<Expr, ParseError> {
+        let start = self.current_offset();
         if self.matches_kind(TokenKind::Minus) {
             let expr = self.parse_unary()?;
-            return Ok(Expr::Unary {
-                op: UnaryOp::Neg,
-                expr: Box::new(expr),
-            });
+            return Ok(self.expr_from(
+                start,
+                ExprKind::Unary {
+                    op: UnaryOp::Neg,
+                    expr: Box::new(expr),
+                },
+            ));
+        }
+        if self.matches_kind(TokenKind::Bang) {
+            let expr = self.parse_unary()?;
+            return Ok(self.expr_from(
+                start,
+                ExprKind::Unary {
+                    op: UnaryOp::Not,
+                    expr: Box::new(expr),
+                },
+            ));
         }
         self.parse_postfix()
     }
 
     fn parse_postfix(&mut self) -> Result<Expr, ParseError> {
+        let start = self.current_offset();
         let mut expr = self.parse_primary()?;
         loop {
             if self.matches_kind(TokenKind::Dot) {
                 let field = self.expect_ident()?;
-                expr = Expr::Member {
-                    target: Box::new(expr),
-                    field,
-                };
+                expr = self.expr_from(
+                    start,
+                    ExprKind::Member {
+                        target: Box::new(expr),
+                        field,
+                    },
+                );
                 continue;
             }
 
@@ -552,7 +917,7 @@ impl Parser {
                 let mut args = Vec::new();
                 if !self.matches_kind(TokenKind::RParen) {
                     loop {
-                        args.push(self.parse_expr()?);
+                        args.push(self.parse_nested_expr()?);
                         if self.matches_kind(TokenKind::Comma) {
                             continue;
                         }
@@ -560,10 +925,13 @@ impl Parser {
                         break;
                     }
                 }
-                expr = Expr::Call {
-                    callee: Box::new(expr),
-                    args,
-                };
+                expr = self.expr_from(
+                    start,
+                    ExprKind::Call {
+                        callee: Box::new(expr),
+                        args,
+                    },
+                );
                 continue;
             }
 
@@ -573,6 +941,7 @@ impl Parser {
     }
 
     fn parse_primary(&mut self) -> Result<Expr, ParseError> {
+        let start = self.current_offset();
         if self.matches_kind(TokenKind::Fn) {
             let params = self.parse_function_params()?;
             let body = if self.matches_kind(TokenKind::Equal) {
@@ -580,27 +949,39 @@ impl Parser {
                 self.expect_kind(TokenKind::Semicolon, ";")?;
                 vec![MaterialFunctionStatement::Return { expr }]
             } else {
-                self.parse_material_function_body()?
+                let loop_depth = std::mem::take(&mut self.loop_depth);
+                let body = self.parse_material_function_body();
+                self.loop_depth = loop_depth;
+                body?
             };
-            return Ok(Expr::FunctionLiteral { params, body });
+            return Ok(self.expr_from(start, ExprKind::FunctionLiteral { params, body }));
+        }
+        if self.matches_kind(TokenKind::If) {
+            return self.parse_if_expr(start);
+        }
+        if self.matches_kind(TokenKind::For) {
+            return self.parse_for_expr(start);
         }
         if self.matches_kind(TokenKind::LParen) {
-            let expr = self.parse_expr()?;
+            let expr = self.parse_nested_expr()?;
             self.expect_kind(TokenKind::RParen, ")")?;
             return Ok(expr);
         }
         if self.matches_kind(TokenKind::LBrace) {
             let fields = self.parse_object_fields()?;
-            return Ok(Expr::ObjectLiteral {
-                type_name: "anonymous".to_string(),
-                fields,
-            });
+            return Ok(self.expr_from(
+                start,
+                ExprKind::ObjectLiteral {
+                    type_name: "anonymous".to_string(),
+                    fields,
+                },
+            ));
         }
         if self.matches_kind(TokenKind::LBracket) {
             let mut items = Vec::new();
             if !self.matches_kind(TokenKind::RBracket) {
                 loop {
-                    items.push(self.parse_expr()?);
+                    items.push(self.parse_nested_expr()?);
                     if self.matches_kind(TokenKind::Comma) {
                         continue;
                     }
@@ -608,41 +989,44 @@ impl Parser {
                     break;
                 }
             }
-            return Ok(Expr::Array(items));
+            return Ok(self.expr_from(start, ExprKind::Array(items)));
         }
 
         match self.peek_kind() {
             Some(TokenKind::String(value)) => {
                 let value = value.clone();
                 self.pos += 1;
-                Ok(Expr::String(value))
+                Ok(self.expr_from(start, ExprKind::String(value)))
             }
             Some(TokenKind::HexColor(hex)) => {
                 let hex = hex.clone();
                 self.pos += 1;
-                Ok(hex_color_expr(&hex)?)
+                Ok(hex_color_expr(&hex, self.span_from(start))?)
             }
             Some(TokenKind::Number(value)) => {
                 let value = *value;
                 self.pos += 1;
-                Ok(Expr::Number(value))
+                Ok(self.expr_from(start, ExprKind::Number(value)))
             }
             Some(TokenKind::Ident(_)) => {
-                let segments = self.parse_ident_chain()?;
-                if self.matches_kind(TokenKind::LBrace) {
+                let name = self.expect_ident()?;
+                let mut expr = self.expr_from(start, ExprKind::Ident(name.clone()));
+                let mut type_name = name;
+                while self.matches_kind(TokenKind::Dot) {
+                    let field = self.expect_ident()?;
+                    type_name = format!("{type_name}.{field}");
+                    expr = self.expr_from(
+                        start,
+                        ExprKind::Member {
+                            target: Box::new(expr),
+                            field,
+                        },
+                    );
+                }
+                if !self.no_object_literal && self.matches_kind(TokenKind::LBrace) {
                     let fields = self.parse_object_fields()?;
-                    Ok(Expr::ObjectLiteral {
-                        type_name: segments.join("."),
-                        fields,
-                    })
+                    Ok(self.expr_from(start, ExprKind::ObjectLiteral { type_name, fields }))
                 } else {
-                    let mut expr = Expr::Ident(segments[0].clone());
-                    for field in segments.iter().skip(1) {
-                        expr = Expr::Member {
-                            target: Box::new(expr),
-                            field: field.clone(),
-                        };
-                    }
                     Ok(expr)
                 }
             }
@@ -653,6 +1037,52 @@ impl Parser {
         }
     }
 
+    /// Rest of an `if` expression whose `if` keyword started at `start`.
+    fn parse_if_expr(&mut self, start: usize) -> Result<Expr, ParseError> {
+        let cond = self.parse_condition()?;
+        let then_branch = self.parse_branch_expr()?;
+        self.expect_kind(TokenKind::Else, "else")?;
+        let else_start = self.current_offset();
+        let else_branch = if self.matches_kind(TokenKind::If) {
+            self.parse_if_expr(else_start)?
+        } else {
+            self.parse_branch_expr()?
+        };
+        Ok(self.expr_from(
+            start,
+            ExprKind::If {
+                cond: Box::new(cond),
+                then_branch: Box::new(then_branch),
+                else_branch: Box::new(else_branch),
+            },
+        ))
+    }
+
+    fn parse_for_expr(&mut self, for_start: usize) -> Result<Expr, ParseError> {
+        let var = self.expect_ident()?;
+        self.expect_kind(TokenKind::In, "in")?;
+        let start = self.parse_expr()?;
+        self.expect_kind(TokenKind::DotDot, "..")?;
+        let end = self.parse_condition()?;
+        let body = self.parse_branch_expr()?;
+        Ok(self.expr_from(
+            for_start,
+            ExprKind::For {
+                var,
+                start: Box::new(start),
+                end: Box::new(end),
+                body: Box::new(body),
+            },
+        ))
+    }
+
+    fn parse_branch_expr(&mut self) -> Result<Expr, ParseError> {
+        self.expect_kind(TokenKind::LBrace, "{")?;
+        let expr = self.parse_nested_expr()?;
+        self.expect_kind(TokenKind::RBrace, "}")?;
+        Ok(expr)
+    }
+
     fn parse_object_fields(&mut self) -> Result<Vec<(String, Expr)>, ParseError> {
         let mut fields = Vec::new();
         if self.matches_kind(TokenKind::RBrace) {
@@ -662,7 +1092,7 @@ impl Parser {
         loop {
             let key = self.expect_ident()?;
             self.expect_kind(TokenKind::Colon, ":")?;
-            let value = self.parse_expr()?;
+            let value = self.parse_nested_expr()?;
             fields.push((key, value));
 
             if self.matches_kind(TokenKind::Comma) {
@@ -708,14 +1138,6 @@ impl Parser {
         }
     }
 
-    fn parse_ident_chain(&mut self) -> Result<Vec<String>, ParseError> {
-        let mut segments = vec![self.expect_ident()?];
-        while self.matches_kind(TokenKind::Dot) {
-            segments.push(self.expect_ident()?);
-        }
-        Ok(segments)
-    }
-
     fn expect_kind(&mut self, kind: TokenKind, expected: &'static str) -> Result<(), ParseError> {
         if self.matches_kind(kind) {
             Ok(())
@@ -751,30 +1173,70 @@ impl Parser {
         }
     }
 
+    fn expr_from(&self, start: usize, kind: ExprKind) -> Expr {
+        Expr::new(kind, self.span_from(start))
+    }
+
+    /// Span from byte `start` to the end of the last consumed token.
+    fn span_from(&self, start: usize) -> Span {
+        Span {
+            file: self.file,
+            start,
+            end: self.previous_end(),
+        }
+    }
+
+    /// Start of the last consumed token, e.g. the `fn` keyword of a definition.
+    fn previous_start(&self) -> usize {
+        self.pos
+            .checked_sub(1)
+            .and_then(|pos| self.tokens.get(pos))
+            .map_or(0, |t| t.start)
+    }
+
     fn current_offset(&self) -> usize {
         self.tokens
             .get(self.pos)
             .map_or_else(|| self.tokens.last().map_or(0, |t| t.start), |t| t.start)
     }
 
+    /// End of the last consumed token.
+    fn previous_end(&self) -> usize {
+        self.pos
+            .checked_sub(1)
+            .and_then(|pos| self.tokens.get(pos))
+            .map_or(0, |t| t.end)
+    }
+
     fn is_eof(&self) -> bool {
         self.pos >= self.tokens.len()
     }
 }
 
-fn hex_color_expr(hex: &str) -> Result<Expr, ParseError> {
+fn starts_statement(kind: &TokenKind) -> bool {
+    matches!(
+        kind,
+        TokenKind::Let | TokenKind::Var | TokenKind::Fn | TokenKind::Import | TokenKind::Export
+    )
+}
+
+fn hex_color_expr(hex: &str, span: Span) -> Result<Expr, ParseError> {
     let [r, g, b] = parse_hex_color(hex).ok_or(ParseError::UnexpectedToken { offset: 0 })?;
-    Ok(Expr::ObjectLiteral {
-        type_name: "vec3".to_string(),
-        fields: vec![
-            ("x".to_string(), Expr::Number(r)),
-            ("y".to_string(), Expr::Number(g)),
-            ("z".to_string(), Expr::Number(b)),
-        ],
-    })
+    let number = |value| Expr::new(ExprKind::Number(value), span);
+    Ok(Expr::new(
+        ExprKind::ObjectLiteral {
+            type_name: "vec3".to_string(),
+            fields: vec![
+                ("x".to_string(), number(r)),
+                ("y".to_string(), number(g)),
+                ("z".to_string(), number(b)),
+            ],
+        },
+        span,
+    ))
 }
 
-fn parse_hex_color(hex: &str) -> Option<[f64; 3]> {
+pub(crate) fn parse_hex_color(hex: &str) -> Option<[f64; 3]> {
     match hex.len() {
         3 => {
             let mut values = [0.0; 3];
@@ -104,10 +104,27 @@ impl Mul for Spectrum {
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    /// Shutter time the ray samples; moving objects and cameras are posed at this time.
+    pub time: f32,
 }
 
 pub trait Camera: Send + Sync {
     fn generate_ray(&self, ndc_x: f32, ndc_y: f32) -> Ray;
+
+    /// Generates a ray through the lens point picked by `(lens_u, lens_v)` in `[0, 1)^2`.
+    /// Cameras without an aperture ignore the lens sample.
+    fn generate_ray_through_lens(&self, ndc_x: f32, ndc_y: f32, lens_u: f32, lens_v: f32) -> Ray {
+        let _ = (lens_u, lens_v);
+        self.generate_ray(ndc_x, ndc_y)
+    }
+
+    /// Generates a lens ray at shutter `time`. Static cameras only stamp the time on the ray.
+    fn generate_ray_at(&self, ndc_x: f32, ndc_y: f32, lens_u: f32, lens_v: f32, time: f32) -> Ray {
+        Ray {
+            time,
+            ..self.generate_ray_through_lens(ndc_x, ndc_y, lens_u, lens_v)
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -139,10 +156,300 @@ impl Camera for PinholeCamera {
         Ray {
             origin: self.origin,
             direction: dir,
+            time: 0.0,
+        }
+    }
+}
+
+/// Pinhole camera with a finite aperture that focuses on a plane `focus_distance` along the
+/// view axis. `blades` of 3 or more shape the aperture (and bokeh) as a regular polygon;
+/// fewer gives a round aperture.
+#[derive(Debug, Clone, Copy)]
+pub struct ThinLensCamera {
+    pub origin: Vec3,
+    pub target: Vec3,
+    pub up: Vec3,
+    pub fov_y_degrees: f32,
+    pub aperture_radius: f32,
+    pub focus_distance: f32,
+    pub blades: u32,
+    pub blade_rotation_degrees: f32,
+}
+
+impl Default for ThinLensCamera {
+    fn default() -> Self {
+        Self {
+            origin: Vec3::new(0.0, 0.0, 6.0),
+            target: Vec3::new(0.0, 0.0, 0.0),
+            up: Vec3::new(0.0, 1.0, 0.0),
+            fov_y_degrees: 45.0,
+            aperture_radius: 0.05,
+            focus_distance: 6.0,
+            blades: 0,
+            blade_rotation_degrees: 0.0,
         }
     }
 }
 
+impl ThinLensCamera {
+    fn pinhole(&self) -> PinholeCamera {
+        PinholeCamera {
+            origin: self.origin,
+            target: self.target,
+            up: self.up,
+            fov_y_degrees: self.fov_y_degrees,
+        }
+    }
+
+    /// Maps a unit-square sample to a point on the aperture, in lens-plane units.
+    pub fn sample_aperture(&self, u: f32, v: f32) -> (f32, f32) {
+        let (x, y) = if self.blades >= 3 {
+            sample_regular_polygon(self.blades, u, v)
+        } else {
+            let disk = concentric_disk_sample(u, v);
+            (disk.x, disk.y)
+        };
+        let (sin, cos) = self.blade_rotation_degrees.to_radians().sin_cos();
+        (
+            (x * cos - y * sin) * self.aperture_radius,
+            (x * sin + y * cos) * self.aperture_radius,
+        )
+    }
+}
+
+impl Camera for ThinLensCamera {
+    fn generate_ray(&self, ndc_x: f32, ndc_y: f32) -> Ray {
+        self.pinhole().generate_ray(ndc_x, ndc_y)
+    }
+
+    fn generate_ray_through_lens(&self, ndc_x: f32, ndc_y: f32, lens_u: f32, lens_v: f32) -> Ray {
+        let center = self.generate_ray(ndc_x, ndc_y);
+        if self.aperture_radius <= 0.0 {
+            return center;
+        }
+        let (forward, right, up) = view_basis(self.origin, self.target, self.up);
+        let focus_t = self.focus_distance / center.direction.dot(forward).max(1.0e-4);
+        let focus_point = self.origin + center.direction * focus_t;
+        let (lx, ly) = self.sample_aperture(lens_u, lens_v);
+        let origin = self.origin + right * lx + up * ly;
+        Ray {
+            origin,
+            direction: (focus_point - origin).normalize(),
+            time: 0.0,
+        }
+    }
+}
+
+/// Parallel-projection camera for technical and elevation views. `ortho_height` is the height
+/// of the view in scene units; the width follows the image aspect.
+#[derive(Debug, Clone, Copy)]
+pub struct OrthographicCamera {
+    pub origin: Vec3,
+    pub target: Vec3,
+    pub up: Vec3,
+    pub ortho_height: f32,
+}
+
+impl Default for OrthographicCamera {
+    fn default() -> Self {
+        Self {
+            origin: Vec3::new(0.0, 0.0, 6.0),
+            target: Vec3::new(0.0, 0.0, 0.0),
+            up: Vec3::new(0.0, 1.0, 0.0),
+            ortho_height: 4.0,
+        }
+    }
+}
+
+impl Camera for OrthographicCamera {
+    fn generate_ray(&self, ndc_x: f32, ndc_y: f32) -> Ray {
+        let (forward, right, up) = view_basis(self.origin, self.target, self.up);
+        let half_height = 0.5 * self.ortho_height;
+        Ray {
+            origin: self.origin + right * (ndc_x * half_height) + up * (ndc_y * half_height),
+            direction: forward,
+            time: 0.0,
+        }
+    }
+}
+
+/// Full 360x180 degree latitude-longitude camera centered on the view direction.
+///
+/// Mono output wants a 2:1 image. With `stereo` set, the top half holds the left eye and the
+/// bottom half the right eye (1:1 overall), using omni-directional stereo offsets of
+/// `eye_separation`. `aspect` is the image width over height the rays are generated for.
+#[derive(Debug, Clone, Copy)]
+pub struct EquirectCamera {
+    pub origin: Vec3,
+    pub target: Vec3,
+    pub up: Vec3,
+    pub aspect: f32,
+    pub stereo: bool,
+    pub eye_separation: f32,
+}
+
+impl Default for EquirectCamera {
+    fn default() -> Self {
+        Self {
+            origin: Vec3::new(0.0, 0.0, 0.0),
+            target: Vec3::new(0.0, 0.0, -1.0),
+            up: Vec3::new(0.0, 1.0, 0.0),
+            aspect: 2.0,
+            stereo: false,
+            eye_separation: 0.064,
+        }
+    }
+}
+
+impl Camera for EquirectCamera {
+    fn generate_ray(&self, ndc_x: f32, ndc_y: f32) -> Ray {
+        let (forward, right, up) = view_basis(self.origin, self.target, self.up);
+        let (ndc_y, eye) = if !self.stereo {
+            (ndc_y, 0.0)
+        } else if ndc_y >= 0.0 {
+            (ndc_y * 2.0 - 1.0, -0.5)
+        } else {
+            (ndc_y * 2.0 + 1.0, 0.5)
+        };
+        let longitude = (ndc_x / self.aspect) * PI;
+        let latitude = ndc_y * 0.5 * PI;
+        let (sin_lon, cos_lon) = longitude.sin_cos();
+        let (sin_lat, cos_lat) = latitude.sin_cos();
+        let horizontal = forward * cos_lon + right * sin_lon;
+        // Each eye sits on a circle around the origin, offset along the horizontal tangent.
+        let tangent = right * cos_lon - forward * sin_lon;
+        Ray {
+            origin: self.origin + tangent * (eye * self.eye_separation),
+            direction: (horizontal * cos_lat + up * sin_lat).normalize(),
+            time: 0.0,
+        }
+    }
+}
+
+/// Six 90 degree views laid out as a horizontal strip in the order +X, -X, +Y, -Y, +Z, -Z,
+/// where X is the camera's right, Y its up and Z points back from the view direction, so an
+/// unrotated camera produces world-axis faces. Side faces keep +Y up; the +Y face has -Z
+/// at its bottom edge and the -Y face has -Z at its top edge. The image should be 6:1.
+#[derive(Debug, Clone, Copy)]
+pub struct CubemapCamera {
+    pub origin: Vec3,
+    pub target: Vec3,
+    pub up: Vec3,
+    pub aspect: f32,
+}
+
+impl Default for CubemapCamera {
+    fn default() -> Self {
+        Self {
+            origin: Vec3::new(0.0, 0.0, 0.0),
+            target: Vec3::new(0.0, 0.0, -1.0),
+            up: Vec3::new(0.0, 1.0, 0.0),
+            aspect: 6.0,
+        }
+    }
+}
+
+impl Camera for CubemapCamera {
+    fn generate_ray(&self, ndc_x: f32, ndc_y: f32) -> Ray {
+        let (forward, x, y) = view_basis(self.origin, self.target, self.up);
+        let z = forward * -1.0;
+        let strip = ((ndc_x / self.aspect) * 0.5 + 0.5).clamp(0.0, 1.0) * 6.0;
+        let face = (strip as usize).min(5);
+        let a = (strip - face as f32) * 2.0 - 1.0;
+        let b = ndc_y;
+        // (view axis, face right, face up) for each face.
+        let (axis, face_right, face_up) = match face {
+            0 => (x, z, y),
+            1 => (x * -1.0, z * -1.0, y),
+            2 => (y, x, z),
+            3 => (y * -1.0, x, z * -1.0),
+            4 => (z, x * -1.0, y),
+            _ => (z * -1.0, x, y),
+        };
+        Ray {
+            origin: self.origin,
+            direction: (axis + face_right * a + face_up * b).normalize(),
+            time: 0.0,
+        }
+    }
+}
+
+/// Camera that moves while the shutter is open. `keys` are poses spread evenly from
+/// `shutter_open` to `shutter_close`; rays blend the two poses around their time.
+pub struct MotionCamera {
+    pub keys: Vec<CameraKind>,
+    pub shutter_open: f32,
+    pub shutter_close: f32,
+}
+
+impl MotionCamera {
+    fn blend(&self, time: f32, ray_for: impl Fn(&CameraKind) -> Ray) -> Ray {
+        let span = self.shutter_close - self.shutter_open;
+        let frac = if span > 0.0 {
+            ((time - self.shutter_open) / span).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let segments = self.keys.len().saturating_sub(1);
+        if segments == 0 {
+            return Ray {
+                time,
+                ..ray_for(&self.keys[0])
+            };
+        }
+        let scaled = frac * segments as f32;
+        let index = (scaled as usize).min(segments - 1);
+        let w = scaled - index as f32;
+        let a = ray_for(&self.keys[index]);
+        let b = ray_for(&self.keys[index + 1]);
+        Ray {
+            origin: a.origin * (1.0 - w) + b.origin * w,
+            direction: (a.direction * (1.0 - w) + b.direction * w).normalize(),
+            time,
+        }
+    }
+}
+
+impl Camera for MotionCamera {
+    fn generate_ray(&self, ndc_x: f32, ndc_y: f32) -> Ray {
+        let mid = 0.5 * (self.shutter_open + self.shutter_close);
+        self.blend(mid, |camera| camera.generate_ray(ndc_x, ndc_y))
+    }
+
+    fn generate_ray_through_lens(&self, ndc_x: f32, ndc_y: f32, lens_u: f32, lens_v: f32) -> Ray {
+        let mid = 0.5 * (self.shutter_open + self.shutter_close);
+        self.generate_ray_at(ndc_x, ndc_y, lens_u, lens_v, mid)
+    }
+
+    fn generate_ray_at(&self, ndc_x: f32, ndc_y: f32, lens_u: f32, lens_v: f32, time: f32) -> Ray {
+        self.blend(time, |camera| {
+            camera.generate_ray_through_lens(ndc_x, ndc_y, lens_u, lens_v)
+        })
+    }
+}
+
+/// Forward, right and up unit vectors of a camera looking from `origin` at `target`.
+fn view_basis(origin: Vec3, target: Vec3, up: Vec3) -> (Vec3, Vec3, Vec3) {
+    let forward = (target - origin).normalize();
+    let right = forward.cross(up).normalize();
+    let up = right.cross(forward).normalize();
+    (forward, right, up)
+}
+
+/// Uniform point in a unit-circumradius regular polygon: pick a wedge, then a point inside it.
+fn sample_regular_polygon(blades: u32, u: f32, v: f32) -> (f32, f32) {
+    let scaled = u * blades as f32;
+    let wedge = (scaled as u32).min(blades - 1);
+    let u = scaled - wedge as f32;
+    let step = 2.0 * PI / blades as f32;
+    let (a0, a1) = (wedge as f32 * step, (wedge + 1) as f32 * step);
+    // Uniform sample of the triangle (center, corner a0, corner a1).
+    let su = u.sqrt();
+    let b0 = su * (1.0 - v);
+    let b1 = su * v;
+    (b0 * a0.cos() + b1 * a1.cos(), b0 * a0.sin() + b1 * a1.sin())
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct LightSample {
     pub wi: Vec3,
@@ -258,7 +565,422 @@ impl Light for SphereLight {
     }
 }
 
-fn hammersley_2d(i: u32, n: u32) -> (f32, f32) {
+impl SphereLight {
+    /// Surface radiance giving `intensity` from afar, as a point light of that intensity would.
+    pub fn radiance(&self) -> Spectrum {
+        self.intensity
+            .scale(1.0 / (PI * self.radius * self.radius).max(1.0e-8))
+    }
+
+    /// Solid-angle density of sampling the cone the sphere subtends from a point
+    /// `distance` away from its center; zero from inside the sphere.
+    fn cone_pdf(&self, distance: f32) -> f32 {
+        if distance <= self.radius || self.radius <= 0.0 {
+            return 0.0;
+        }
+        let sin2_max = (self.radius / distance).powi(2);
+        let one_minus_cos_max = sin2_max / (1.0 + (1.0 - sin2_max).max(0.0).sqrt());
+        1.0 / (2.0 * PI * one_minus_cos_max)
+    }
+}
+
+impl AreaLight for SphereLight {
+    /// Samples the cone of directions the sphere covers, which wastes no samples on its far
+    /// side.
+    fn sample_area(&self, point: Vec3, u: f32, v: f32) -> AreaLightSample {
+        let to_center = self.position - point;
+        let distance = to_center.length();
+        let pdf = self.cone_pdf(distance);
+        if pdf <= 0.0 {
+            return AreaLightSample {
+                wi: to_center.normalize(),
+                distance,
+                radiance: Spectrum::black(),
+                pdf: 0.0,
+            };
+        }
+        let axis = to_center * (1.0 / distance);
+        let one_minus_cos_max = 1.0 / (2.0 * PI * pdf);
+        let cos_theta = 1.0 - u * one_minus_cos_max;
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * v;
+        let (tangent, bitangent) = orthonormal_basis(axis);
+        let wi = (tangent * (sin_theta * phi.cos())
+            + bitangent * (sin_theta * phi.sin())
+            + axis * cos_theta)
+            .normalize();
+        // Near intersection with the sphere along `wi`.
+        let along = distance * cos_theta;
+        let disc = self.radius * self.radius - distance * distance * sin_theta * sin_theta;
+        AreaLightSample {
+            wi,
+            distance: along - disc.max(0.0).sqrt(),
+            radiance: self.radiance(),
+            pdf,
+        }
+    }
+
+    fn intersect(&self, origin: Vec3, dir: Vec3) -> Option<AreaLightSample> {
+        let to_center = self.position - origin;
+        let dist2 = to_center.dot(to_center);
+        let along = to_center.dot(dir);
+        let disc = self.radius * self.radius - (dist2 - along * along);
+        if dist2 <= self.radius * self.radius || along <= 0.0 || disc < 0.0 {
+            return None;
+        }
+        let t = along - disc.sqrt();
+        (t > 1.0e-4).then(|| AreaLightSample {
+            wi: dir,
+            distance: t,
+            radiance: self.radiance(),
+            pdf: self.cone_pdf(dist2.sqrt()),
+        })
+    }
+}
+
+/// Light from a far-away source such as the sun, travelling along `direction`.
+///
+/// `angle` is the angular diameter of the source in radians; above zero it softens shadows the
+/// way a real sun does. `irradiance` is what falls on a surface facing the light.
+#[derive(Debug, Clone, Copy)]
+pub struct DirectionalLight {
+    pub direction: Vec3,
+    pub angle: f32,
+    pub irradiance: Spectrum,
+    pub samples: u32,
+}
+
+impl DirectionalLight {
+    pub fn to_light(&self) -> Vec3 {
+        (self.direction * -1.0).normalize()
+    }
+
+    /// Solid angle covered by the source; zero for perfectly parallel light.
+    pub fn solid_angle(&self) -> f32 {
+        2.0 * PI * self.one_minus_cos_half_angle()
+    }
+
+    /// Direction towards the source picked uniformly over its cone by `(u, v)` in `[0, 1)^2`.
+    pub fn sample_direction(&self, u: f32, v: f32) -> Vec3 {
+        let axis = self.to_light();
+        let cos_theta = 1.0 - u * self.one_minus_cos_half_angle();
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * v;
+        let (tangent, bitangent) = orthonormal_basis(axis);
+        (tangent * (sin_theta * phi.cos()) + bitangent * (sin_theta * phi.sin()) + axis * cos_theta)
+            .normalize()
+    }
+
+    /// Radiance seen looking along `dir`: black outside the source cone.
+    pub fn radiance_towards(&self, dir: Vec3) -> Spectrum {
+        let solid_angle = self.solid_angle();
+        if solid_angle <= 0.0 || 1.0 - self.to_light().dot(dir) > self.one_minus_cos_half_angle() {
+            return Spectrum::black();
+        }
+        self.irradiance.scale(1.0 / solid_angle)
+    }
+
+    /// `1 - cos(angle / 2)`, kept precise for sun-sized angles.
+    fn one_minus_cos_half_angle(&self) -> f32 {
+        2.0 * (0.25 * self.angle.max(0.0)).sin().powi(2)
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample_li(&self, point: Vec3) -> LightSample {
+        self.sample_li_indexed(point, 0, 1)
+    }
+
+    fn sample_li_indexed(&self, _point: Vec3, sample_index: u32, sample_count: u32) -> LightSample {
+        let wi = if self.angle > 0.0 {
+            let (u, v) = hammersley_2d(sample_index, sample_count.max(1));
+            self.sample_direction(u, v)
+        } else {
+            self.to_light()
+        };
+        LightSample {
+            wi,
+            radiance: self.irradiance,
+            distance: f32::INFINITY,
+        }
+    }
+
+    fn shadow_sample_count(&self) -> u32 {
+        if self.angle > 0.0 {
+            self.samples.max(1)
+        } else {
+            1
+        }
+    }
+}
+
+/// Point light limited to a cone around `direction`.
+///
+/// `angle` is the full cone angle in radians and `blend` the fraction of it, from the rim
+/// inwards, over which the light fades out.
+#[derive(Debug, Clone, Copy)]
+pub struct SpotLight {
+    pub position: Vec3,
+    pub direction: Vec3,
+    pub intensity: Spectrum,
+    pub angle: f32,
+    pub blend: f32,
+}
+
+impl SpotLight {
+    /// Cone attenuation for light leaving the spot along `dir`.
+    pub fn falloff(&self, dir: Vec3) -> f32 {
+        let cos = self.direction.normalize().dot(dir.normalize());
+        let half = 0.5 * self.angle;
+        let cos_outer = half.cos();
+        let cos_inner = (half * (1.0 - self.blend.clamp(0.0, 1.0))).cos();
+        if cos_inner - cos_outer <= 1.0e-6 {
+            return if cos >= cos_outer { 1.0 } else { 0.0 };
+        }
+        let t = ((cos - cos_outer) / (cos_inner - cos_outer)).clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+impl Light for SpotLight {
+    fn sample_li(&self, point: Vec3) -> LightSample {
+        let to_light = self.position - point;
+        let distance = to_light.length().max(1.0e-4);
+        let wi = to_light * (1.0 / distance);
+        LightSample {
+            wi,
+            radiance: self
+                .intensity
+                .scale(self.falloff(wi * -1.0) / (distance * distance)),
+            distance,
+        }
+    }
+}
+
+/// A point on an area light as seen from a receiver.
+///
+/// `radiance` is what the light emits towards the receiver and `pdf` the solid-angle density
+/// of picking that point; both are zero when the light faces away.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AreaLightSample {
+    pub wi: Vec3,
+    pub distance: f32,
+    pub radiance: Spectrum,
+    pub pdf: f32,
+}
+
+/// Emitters with a surface that can be sampled and hit by rays.
+pub trait AreaLight {
+    /// Picks a point on the light for `point` to receive from, using `(u, v)` in `[0, 1)^2`.
+    fn sample_area(&self, point: Vec3, u: f32, v: f32) -> AreaLightSample;
+
+    /// Where the ray from `origin` along unit `dir` meets the light, if it does.
+    fn intersect(&self, origin: Vec3, dir: Vec3) -> Option<AreaLightSample>;
+}
+
+/// Rectangular area light, such as a window or a softbox.
+///
+/// `right` and `up` are half-extent vectors; the light faces along `right × up`, or both ways
+/// when `two_sided`. `spread` is the full emission angle in radians, and below `PI` narrows the
+/// beam towards the normal.
+#[derive(Debug, Clone, Copy)]
+pub struct RectLight {
+    pub position: Vec3,
+    pub right: Vec3,
+    pub up: Vec3,
+    pub radiance: Spectrum,
+    pub two_sided: bool,
+    pub spread: f32,
+    pub samples: u32,
+}
+
+impl RectLight {
+    fn emitter(&self) -> PlanarEmitter {
+        let cross = self.right.cross(self.up);
+        PlanarEmitter {
+            normal: cross.normalize(),
+            area: 4.0 * cross.length(),
+            radiance: self.radiance,
+            two_sided: self.two_sided,
+            spread: self.spread,
+        }
+    }
+}
+
+impl AreaLight for RectLight {
+    fn sample_area(&self, point: Vec3, u: f32, v: f32) -> AreaLightSample {
+        let on_light = self.position + self.right * (2.0 * u - 1.0) + self.up * (2.0 * v - 1.0);
+        self.emitter().sample_from(point, on_light)
+    }
+
+    fn intersect(&self, origin: Vec3, dir: Vec3) -> Option<AreaLightSample> {
+        let emitter = self.emitter();
+        let hit = emitter.plane_hit(self.position, origin, dir)?;
+        let local = hit - self.position;
+        let a = local.dot(self.right) / self.right.dot(self.right);
+        let b = local.dot(self.up) / self.up.dot(self.up);
+        (a.abs() <= 1.0 && b.abs() <= 1.0).then(|| emitter.sample_from(origin, hit))
+    }
+}
+
+impl Light for RectLight {
+    fn sample_li(&self, point: Vec3) -> LightSample {
+        self.sample_li_indexed(point, 0, self.shadow_sample_count())
+    }
+
+    fn sample_li_indexed(&self, point: Vec3, sample_index: u32, sample_count: u32) -> LightSample {
+        let (u, v) = hammersley_2d(sample_index, sample_count.max(1));
+        self.sample_area(point, u, v).into()
+    }
+
+    fn shadow_sample_count(&self) -> u32 {
+        self.samples.max(1)
+    }
+}
+
+/// Round area light facing along `normal`; see [`RectLight`] for `two_sided` and `spread`.
+#[derive(Debug, Clone, Copy)]
+pub struct DiskLight {
+    pub position: Vec3,
+    pub normal: Vec3,
+    pub radius: f32,
+    pub radiance: Spectrum,
+    pub two_sided: bool,
+    pub spread: f32,
+    pub samples: u32,
+}
+
+impl DiskLight {
+    fn emitter(&self) -> PlanarEmitter {
+        PlanarEmitter {
+            normal: self.normal.normalize(),
+            area: PI * self.radius * self.radius,
+            radiance: self.radiance,
+            two_sided: self.two_sided,
+            spread: self.spread,
+        }
+    }
+}
+
+impl AreaLight for DiskLight {
+    fn sample_area(&self, point: Vec3, u: f32, v: f32) -> AreaLightSample {
+        let (tangent, bitangent) = orthonormal_basis(self.normal.normalize());
+        let disk = concentric_disk_sample(u, v);
+        let on_light =
+            self.position + tangent * (disk.x * self.radius) + bitangent * (disk.y * self.radius);
+        self.emitter().sample_from(point, on_light)
+    }
+
+    fn intersect(&self, origin: Vec3, dir: Vec3) -> Option<AreaLightSample> {
+        let emitter = self.emitter();
+        let hit = emitter.plane_hit(self.position, origin, dir)?;
+        let local = hit - self.position;
+        (local.dot(local) <= self.radius * self.radius).then(|| emitter.sample_from(origin, hit))
+    }
+}
+
+impl Light for DiskLight {
+    fn sample_li(&self, point: Vec3) -> LightSample {
+        self.sample_li_indexed(point, 0, self.shadow_sample_count())
+    }
+
+    fn sample_li_indexed(&self, point: Vec3, sample_index: u32, sample_count: u32) -> LightSample {
+        let (u, v) = hammersley_2d(sample_index, sample_count.max(1));
+        self.sample_area(point, u, v).into()
+    }
+
+    fn shadow_sample_count(&self) -> u32 {
+        self.samples.max(1)
+    }
+}
+
+impl From<AreaLightSample> for LightSample {
+    /// Turns an area sample into the irradiance-style estimate the trace renderer averages.
+    fn from(sample: AreaLightSample) -> Self {
+        let radiance = if sample.pdf > 0.0 {
+            sample.radiance.scale(1.0 / sample.pdf)
+        } else {
+            Spectrum::black()
+        };
+        LightSample {
+            wi: sample.wi,
+            radiance,
+            distance: sample.distance,
+        }
+    }
+}
+
+/// The flat emitting surface shared by rectangle and disk lights.
+struct PlanarEmitter {
+    normal: Vec3,
+    area: f32,
+    radiance: Spectrum,
+    two_sided: bool,
+    spread: f32,
+}
+
+impl PlanarEmitter {
+    fn sample_from(&self, receiver: Vec3, on_light: Vec3) -> AreaLightSample {
+        let to_light = on_light - receiver;
+        let distance = to_light.length().max(1.0e-4);
+        let wi = to_light * (1.0 / distance);
+        let cos_light = -self.normal.dot(wi);
+        let facing = if self.two_sided {
+            cos_light.abs()
+        } else {
+            cos_light
+        };
+        let profile = spread_profile(facing, self.spread);
+        if facing <= 1.0e-6 || profile <= 0.0 || self.area <= 0.0 {
+            return AreaLightSample {
+                wi,
+                distance,
+                radiance: Spectrum::black(),
+                pdf: 0.0,
+            };
+        }
+        AreaLightSample {
+            wi,
+            distance,
+            radiance: self.radiance.scale(profile),
+            pdf: distance * distance / (self.area * facing),
+        }
+    }
+
+    fn plane_hit(&self, point_on_plane: Vec3, origin: Vec3, dir: Vec3) -> Option<Vec3> {
+        let denom = self.normal.dot(dir);
+        if denom.abs() <= 1.0e-6 {
+            return None;
+        }
+        let t = self.normal.dot(point_on_plane - origin) / denom;
+        (t > 1.0e-4).then(|| origin + dir * t)
+    }
+}
+
+/// Emission falloff for a planar light whose beam covers a full angle of `spread` radians.
+fn spread_profile(cos_light: f32, spread: f32) -> f32 {
+    if spread >= PI - 1.0e-4 {
+        return 1.0;
+    }
+    let cos_edge = (0.5 * spread.max(0.0)).cos();
+    if 1.0 - cos_edge <= 1.0e-6 {
+        return 0.0;
+    }
+    ((cos_light - cos_edge) / (1.0 - cos_edge)).clamp(0.0, 1.0)
+}
+
+/// Two unit vectors perpendicular to unit `n` and to each other.
+fn orthonormal_basis(n: Vec3) -> (Vec3, Vec3) {
+    let up = if n.y.abs() < 0.99 {
+        Vec3::new(0.0, 1.0, 0.0)
+    } else {
+        Vec3::new(1.0, 0.0, 0.0)
+    };
+    let tangent = n.cross(up).normalize();
+    (tangent, tangent.cross(n).normalize())
+}
+
+pub(crate) fn hammersley_2d(i: u32, n: u32) -> (f32, f32) {
     let u = (i as f32 + 0.5) / n.max(1) as f32;
     let mut bits = i;
     bits = bits.rotate_right(16);
@@ -308,6 +1030,7 @@ pub struct OpenPbrMaterial {
     pub base_color: Spectrum,
     pub roughness: f32,
     pub metallic: f32,
+    /// Scales `specular_weight`, as on `StandardMaterial`; `1` leaves the weight alone.
     pub specular: f32,
     pub specular_weight: f32,
     pub specular_color: Spectrum,
@@ -328,7 +1051,7 @@ impl Default for OpenPbrMaterial {
             base_color: Spectrum::rgb(0.8, 0.8, 0.8),
             roughness: 0.5,
             metallic: 0.0,
-            specular: 0.5,
+            specular: 1.0,
             specular_weight: 1.0,
             specular_color: Spectrum::rgb(1.0, 1.0, 1.0),
             ior: 1.5,
@@ -362,84 +1085,34 @@ struct OpenPbrBsdf {
 impl Bsdf for OpenPbrBsdf {
     fn evaluate(&self, normal: Vec3, wi: Vec3, wo: Vec3) -> Spectrum {
         let ndotl = normal.dot(wi).max(0.0);
-        let ndotv = normal.dot(wo).max(0.0);
-        if ndotl <= 0.0 || ndotv <= 0.0 {
-            return Spectrum::black();
-        }
-
-        let roughness = self.material.roughness.clamp(0.02, 1.0);
-        let alpha = roughness * roughness;
-        let h = (wi + wo).normalize();
-        let ndoth = normal.dot(h).max(0.0);
-        let vdoth = wo.dot(h).max(0.0);
-
-        let f0_dielectric = fresnel_f0_from_ior(self.material.ior).clamp(0.0, 1.0);
-        let base_f0 = Spectrum::rgb(f0_dielectric, f0_dielectric, f0_dielectric).scale(
-            self.material.specular.clamp(0.0, 1.0) * self.material.specular_weight.clamp(0.0, 1.0),
-        ) * self.material.specular_color;
-        let metal_f0 = self.material.base_color;
-        let f0 = lerp_spectrum(base_f0, metal_f0, self.material.metallic.clamp(0.0, 1.0));
-        let fresnel = fresnel_schlick(f0, vdoth);
-        let d = ggx_d(ndoth, alpha);
-        let g = smith_ggx_g(ndotl, ndotv, alpha);
-        let denom = (4.0 * ndotl * ndotv).max(1.0e-5);
-        let specular = fresnel.scale((d * g) / denom);
-
-        let kd = 1.0 - self.material.metallic.clamp(0.0, 1.0);
-        let diffuse = self.material.base_color.scale((kd / PI) * ndotl);
-
-        let clearcoat = self.material.clearcoat.clamp(0.0, 1.0);
-        let clearcoat_alpha = self.material.clearcoat_roughness.clamp(0.02, 1.0).powi(2);
-        let clearcoat_d = ggx_d(ndoth, clearcoat_alpha);
-        let clearcoat_g = smith_ggx_g(ndotl, ndotv, clearcoat_alpha);
-        let clearcoat_f = fresnel_schlick_scalar(0.04, vdoth);
-        let clearcoat_spec = clearcoat * ((clearcoat_d * clearcoat_g * clearcoat_f) / denom);
-        let clearcoat_term = Spectrum::rgb(clearcoat_spec, clearcoat_spec, clearcoat_spec);
-
-        diffuse + specular.scale(ndotl) + clearcoat_term.scale(ndotl)
+        crate::materials::Material::OpenPbr(self.material.params())
+            .eval(normal, wi, wo)
+            .scale(ndotl)
     }
 }
 
-fn fresnel_f0_from_ior(ior: f32) -> f32 {
-    let i = ior.max(1.0e-3);
-    let r = (i - 1.0) / (i + 1.0);
-    r * r
-}
-
-fn fresnel_schlick(f0: Spectrum, cos_theta: f32) -> Spectrum {
-    let m = (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5);
-    f0 + Spectrum::rgb(1.0, 1.0, 1.0).scale(m) + f0.scale(-m)
-}
-
-fn fresnel_schlick_scalar(f0: f32, cos_theta: f32) -> f32 {
-    let m = (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5);
-    f0 + (1.0 - f0) * m
-}
-
-fn ggx_d(ndoth: f32, alpha: f32) -> f32 {
-    let a2 = alpha * alpha;
-    let n2 = ndoth * ndoth;
-    let denom = (n2 * (a2 - 1.0) + 1.0).max(1.0e-5);
-    a2 / (PI * denom * denom)
-}
-
-fn smith_ggx_g(ndotl: f32, ndotv: f32, alpha: f32) -> f32 {
-    smith_ggx_g1(ndotl, alpha) * smith_ggx_g1(ndotv, alpha)
-}
-
-fn smith_ggx_g1(ndotx: f32, alpha: f32) -> f32 {
-    let a = alpha;
-    let k = (a * a) * 0.5;
-    ndotx / (ndotx * (1.0 - k) + k).max(1.0e-5)
-}
-
-fn lerp_spectrum(a: Spectrum, b: Spectrum, t: f32) -> Spectrum {
-    let tt = t.clamp(0.0, 1.0);
-    Spectrum::rgb(
-        a.r * (1.0 - tt) + b.r * tt,
-        a.g * (1.0 - tt) + b.g * tt,
-        a.b * (1.0 - tt) + b.b * tt,
-    )
+impl OpenPbrMaterial {
+    /// The renderer's `OpenPbr` parameters for this preview material.
+    fn params(&self) -> crate::materials::MaterialParams {
+        let mut params = crate::materials::MaterialParams::standard(
+            self.base_color,
+            self.roughness,
+            self.metallic,
+            self.specular,
+            self.specular_weight,
+            self.specular_color,
+            self.ior,
+            self.clearcoat,
+            self.clearcoat_roughness,
+            self.transmission,
+            self.thin_walled >= 0.5,
+            self.emission_color,
+            self.emission_strength,
+        );
+        params.layers.specular_anisotropy = self.anisotropy.clamp(0.0, 1.0);
+        params.layers.specular_rotation = self.anisotropy_rotation;
+        params
+    }
 }
 
 pub trait Integrator: Send + Sync {
@@ -483,12 +1156,45 @@ impl Integrator for PreviewIntegrator {
 
 pub enum CameraKind {
     Pinhole(PinholeCamera),
+    ThinLens(ThinLensCamera),
+    Orthographic(OrthographicCamera),
+    Equirect(EquirectCamera),
+    Cubemap(CubemapCamera),
+    Motion(MotionCamera),
 }
 
 impl Camera for CameraKind {
     fn generate_ray(&self, ndc_x: f32, ndc_y: f32) -> Ray {
         match self {
             Self::Pinhole(camera) => camera.generate_ray(ndc_x, ndc_y),
+            Self::ThinLens(camera) => camera.generate_ray(ndc_x, ndc_y),
+            Self::Orthographic(camera) => camera.generate_ray(ndc_x, ndc_y),
+            Self::Equirect(camera) => camera.generate_ray(ndc_x, ndc_y),
+            Self::Cubemap(camera) => camera.generate_ray(ndc_x, ndc_y),
+            Self::Motion(camera) => camera.generate_ray(ndc_x, ndc_y),
+        }
+    }
+
+    fn generate_ray_through_lens(&self, ndc_x: f32, ndc_y: f32, lens_u: f32, lens_v: f32) -> Ray {
+        match self {
+            Self::Pinhole(camera) => camera.generate_ray_through_lens(ndc_x, ndc_y, lens_u, lens_v),
+            Self::ThinLens(camera) => {
+                camera.generate_ray_through_lens(ndc_x, ndc_y, lens_u, lens_v)
+            }
+            Self::Motion(camera) => camera.generate_ray_through_lens(ndc_x, ndc_y, lens_u, lens_v),
+            Self::Orthographic(_) | Self::Equirect(_) | Self::Cubemap(_) => {
+                self.generate_ray(ndc_x, ndc_y)
+            }
+        }
+    }
+
+    fn generate_ray_at(&self, ndc_x: f32, ndc_y: f32, lens_u: f32, lens_v: f32, time: f32) -> Ray {
+        match self {
+            Self::Motion(camera) => camera.generate_ray_at(ndc_x, ndc_y, lens_u, lens_v, time),
+            _ => Ray {
+                time,
+                ..self.generate_ray_through_lens(ndc_x, ndc_y, lens_u, lens_v)
+            },
         }
     }
 }
@@ -514,10 +1220,19 @@ impl MaterialModel for MaterialKind {
 #[cfg(test)]
 mod tests {
     use super::{
-        Camera, EnvLight, Integrator, MaterialModel, OpenPbrMaterial, PinholeCamera, PointLight,
-        PreviewIntegrator, Spectrum, Vec3,
+        AreaLight, Camera, CubemapCamera, DirectionalLight, DiskLight, EnvLight, EquirectCamera,
+        Integrator, Light, MaterialModel, OpenPbrMaterial, OrthographicCamera, PinholeCamera,
+        PointLight, PreviewIntegrator, RectLight, Spectrum, SphereLight, SpotLight, ThinLensCamera,
+        Vec3,
     };
 
+    fn assert_dir(actual: Vec3, expected: Vec3) {
+        assert!(
+            (actual - expected).length() < 1.0e-5,
+            "{actual:?} != {expected:?}"
+        );
+    }
+
     #[test]
     fn pinhole_camera_generates_normalized_rays() {
         let camera = PinholeCamera::default();
@@ -526,12 +1241,223 @@ mod tests {
         assert!((len - 1.0).abs() < 1.0e-5);
     }
 
+    #[test]
+    fn thin_lens_rays_converge_on_the_focus_plane() {
+        let camera = ThinLensCamera {
+            aperture_radius: 0.2,
+            focus_distance: 4.0,
+            blades: 6,
+            ..ThinLensCamera::default()
+        };
+        let center = camera.generate_ray(0.3, -0.2);
+        let center_t = camera.focus_distance / center.direction.dot(Vec3::new(0.0, 0.0, -1.0));
+        let focus_point = center.origin + center.direction * center_t;
+        for (u, v) in [(0.1, 0.9), (0.5, 0.5), (0.97, 0.03)] {
+            let ray = camera.generate_ray_through_lens(0.3, -0.2, u, v);
+            assert!((ray.direction.length() - 1.0).abs() < 1.0e-5);
+            let offset = ray.origin - camera.origin;
+            assert!(offset.length() <= camera.aperture_radius + 1.0e-5);
+            let t = (focus_point.z - ray.origin.z) / ray.direction.z;
+            let hit = ray.origin + ray.direction * t;
+            assert!((hit - focus_point).length() < 1.0e-3);
+        }
+
+        let pinhole = PinholeCamera::default();
+        let ray = pinhole.generate_ray_through_lens(0.3, -0.2, 0.1, 0.9);
+        assert_eq!(ray, pinhole.generate_ray(0.3, -0.2));
+    }
+
+    #[test]
+    fn panoramic_and_ortho_cameras_map_the_image_to_expected_directions() {
+        let ortho = OrthographicCamera::default();
+        let ray = ortho.generate_ray(0.5, -1.0);
+        assert_dir(ray.direction, Vec3::new(0.0, 0.0, -1.0));
+        assert_dir(ray.origin, Vec3::new(1.0, -2.0, 6.0));
+
+        let equirect = EquirectCamera::default();
+        assert_dir(
+            equirect.generate_ray(0.0, 0.0).direction,
+            Vec3::new(0.0, 0.0, -1.0),
+        );
+        assert_dir(
+            equirect.generate_ray(1.0, 0.0).direction,
+            Vec3::new(1.0, 0.0, 0.0),
+        );
+        assert_dir(
+            equirect.generate_ray(2.0, 0.0).direction,
+            Vec3::new(0.0, 0.0, 1.0),
+        );
+        assert_dir(
+            equirect.generate_ray(0.3, 1.0).direction,
+            Vec3::new(0.0, 1.0, 0.0),
+        );
+
+        let stereo = EquirectCamera {
+            aspect: 1.0,
+            stereo: true,
+            ..EquirectCamera::default()
+        };
+        let left = stereo.generate_ray(0.0, 0.5);
+        let right = stereo.generate_ray(0.0, -0.5);
+        assert_dir(left.direction, Vec3::new(0.0, 0.0, -1.0));
+        assert_dir(right.direction, Vec3::new(0.0, 0.0, -1.0));
+        assert_dir(left.origin, Vec3::new(-0.032, 0.0, 0.0));
+        assert_dir(right.origin, Vec3::new(0.032, 0.0, 0.0));
+
+        let cubemap = CubemapCamera::default();
+        let face_centers =
+            [-5.0, -3.0, -1.0, 1.0, 3.0, 5.0].map(|x| cubemap.generate_ray(x, 0.0).direction);
+        for (actual, expected) in face_centers.into_iter().zip([
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(-1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 0.0, -1.0),
+        ]) {
+            assert_dir(actual, expected);
+        }
+        // Bottom edge of the +Y face meets the -Z face.
+        let edge = cubemap.generate_ray(-1.0, -1.0).direction;
+        assert_dir(edge, Vec3::new(0.0, 1.0, -1.0).normalize());
+    }
+
     #[test]
     fn openpbr_material_exposes_model_name() {
         let mat = OpenPbrMaterial::default();
         assert_eq!(mat.name(), "openpbr");
     }
 
+    #[test]
+    fn area_and_sun_lights_deliver_their_analytic_irradiance() {
+        let (radius, height) = (0.5_f32, 2.0_f32);
+        let disk = DiskLight {
+            position: Vec3::new(0.0, height, 0.0),
+            normal: Vec3::new(0.0, -1.0, 0.0),
+            radius,
+            radiance: Spectrum::rgb(1.0, 1.0, 1.0),
+            two_sided: false,
+            spread: std::f32::consts::PI,
+            samples: 256,
+        };
+        let point = Vec3::new(0.0, 0.0, 0.0);
+        let irradiance = (0..256)
+            .map(|i| {
+                let sample = disk.sample_li_indexed(point, i, 256);
+                sample.radiance.r * sample.wi.y
+            })
+            .sum::<f32>()
+            / 256.0;
+        let expected = std::f32::consts::PI * radius * radius / (height * height + radius * radius);
+        assert!(
+            (irradiance - expected).abs() < 0.01 * expected,
+            "{irradiance} != {expected}"
+        );
+
+        let behind = disk.sample_area(Vec3::new(0.0, 3.0, 0.0), 0.5, 0.5);
+        assert_eq!(behind.pdf, 0.0);
+        let two_sided = DiskLight {
+            two_sided: true,
+            ..disk
+        };
+        assert!(
+            two_sided
+                .sample_area(Vec3::new(0.0, 3.0, 0.0), 0.5, 0.5)
+                .pdf
+                > 0.0
+        );
+
+        let rect = RectLight {
+            position: Vec3::new(0.0, 0.0, -2.0),
+            right: Vec3::new(1.0, 0.0, 0.0),
+            up: Vec3::new(0.0, 0.5, 0.0),
+            radiance: Spectrum::rgb(2.0, 2.0, 2.0),
+            two_sided: false,
+            spread: std::f32::consts::PI,
+            samples: 1,
+        };
+        let origin = Vec3::new(0.0, 0.0, 0.0);
+        let hit = rect
+            .intersect(origin, Vec3::new(0.0, 0.0, -1.0))
+            .expect("ray should hit the window");
+        assert!((hit.distance - 2.0).abs() < 1.0e-5);
+        assert!((hit.pdf - 4.0 / 2.0).abs() < 1.0e-4);
+        assert!(
+            rect.intersect(origin, Vec3::new(0.0, 0.6, -1.0).normalize())
+                .is_none()
+        );
+        let narrow = RectLight {
+            spread: 30.0_f32.to_radians(),
+            ..rect
+        };
+        let oblique = narrow.sample_area(Vec3::new(3.0, 0.0, 0.0), 0.5, 0.5);
+        assert_eq!(oblique.radiance, Spectrum::black());
+
+        let sun = DirectionalLight {
+            direction: Vec3::new(0.0, -1.0, 0.0),
+            angle: 0.53_f32.to_radians(),
+            irradiance: Spectrum::rgb(3.0, 3.0, 3.0),
+            samples: 8,
+        };
+        let cos_half = (0.5 * sun.angle).cos();
+        for i in 0..8 {
+            let wi = sun.sample_li_indexed(point, i, 8).wi;
+            assert!(wi.y >= cos_half - 1.0e-6);
+        }
+        let disc_radiance = sun.radiance_towards(Vec3::new(0.0, 1.0, 0.0)).r;
+        assert!((disc_radiance * sun.solid_angle() - 3.0).abs() < 1.0e-3);
+        assert_eq!(
+            sun.radiance_towards(Vec3::new(0.0, 1.0, 0.1).normalize()),
+            Spectrum::black()
+        );
+
+        let spot = SpotLight {
+            position: Vec3::new(0.0, 2.0, 0.0),
+            direction: Vec3::new(0.0, -1.0, 0.0),
+            intensity: Spectrum::rgb(4.0, 4.0, 4.0),
+            angle: 60.0_f32.to_radians(),
+            blend: 0.2,
+        };
+        assert_eq!(spot.sample_li(point).radiance.r, 1.0);
+        assert_eq!(spot.sample_li(Vec3::new(2.0, 0.0, 0.0)).radiance.r, 0.0);
+    }
+
+    #[test]
+    fn sphere_light_cone_sampling_matches_point_light_falloff() {
+        let light = SphereLight {
+            position: Vec3::new(0.0, 3.0, 0.0),
+            radius: 0.7,
+            intensity: Spectrum::rgb(9.0, 9.0, 9.0),
+            samples: 1,
+        };
+        let point = Vec3::new(0.0, 0.0, 0.0);
+        let n = 64;
+        let mut irradiance = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let (u, v) = ((i as f32 + 0.5) / n as f32, (j as f32 + 0.5) / n as f32);
+                let sample = light.sample_area(point, u, v);
+                // Between the near pole and the tangent rim of the sphere.
+                assert!(
+                    sample.distance > 2.3 - 1.0e-4 && sample.distance < 8.51_f32.sqrt() + 1.0e-3,
+                    "{}",
+                    sample.distance
+                );
+                let hit = light
+                    .intersect(point, sample.wi)
+                    .expect("sampled direction should hit the sphere");
+                assert!((hit.distance - sample.distance).abs() < 1.0e-3);
+                assert!((hit.pdf - sample.pdf).abs() < 1.0e-3 * sample.pdf);
+                irradiance += sample.radiance.r * sample.wi.y / sample.pdf;
+            }
+        }
+        irradiance /= (n * n) as f32;
+        // A sphere light delivers the inverse-square irradiance of a point light of the same
+        // intensity.
+        assert!((irradiance - 1.0).abs() < 0.01, "{irradiance}");
+        assert!(light.intersect(point, Vec3::new(0.0, -1.0, 0.0)).is_none());
+    }
+
     #[test]
     fn preview_integrator_produces_non_black_with_lights() {
         let camera = PinholeCamera::default();
@@ -1,32 +1,81 @@
 use std::collections::VecDeque;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use image::{ImageError, RgbImage};
 use rayon::prelude::*;
 use thiserror::Error;
 
 use crate::{
-    BlendedMaterial, ColorPattern, EvalState, FunctionValue, Material, MaterialKindTag,
-    MaterialParams, MaterialSampleInput, MediumParams, ObjectValue, SubsurfaceParams, Value,
+    BlendedMaterial, ColorPattern, ComplexIor, Conductor, EvalState, FunctionValue, Material,
+    MaterialKindTag, MaterialParams, MaterialSampleInput, MediumParams, ObjectValue, OpenPbrLayers,
+    SubsurfaceParams, Value,
+    ast::MaterialStatement,
+    eval::object_center,
     eval_environment_function, eval_function_value, eval_material_function_with_overrides,
     eval_material_properties_with_overrides, eval_sdf_function_args_with_overrides,
     eval_sdf_function_with_overrides, eval_sdf_vec3_function_with_overrides,
-    eval_sdf_zero_arg_function_with_overrides,
+    eval_sdf_zero_arg_function_with_overrides, eval_volume_function, eval_volume_values,
+    jit::JitSdfDistanceFunction,
     render_api::{
-        Camera, CameraKind, EnvLight, Light, PinholeCamera, PointLight, Spectrum, SphereLight,
-        Vec3 as ApiVec3,
+        AreaLight, AreaLightSample, Camera, CameraKind, CubemapCamera, DirectionalLight, DiskLight,
+        EnvLight, EquirectCamera, Light, LightSample, MotionCamera, OrthographicCamera,
+        PinholeCamera, PointLight, RectLight, Spectrum, SphereLight, SpotLight, ThinLensCamera,
+        Vec3 as ApiVec3, hammersley_2d,
     },
 };
 
-#[allow(dead_code)]
+#[path = "renderer/checkpoint.rs"]
+mod checkpoint;
+#[path = "renderer/denoise.rs"]
+mod denoise;
+#[path = "renderer/display.rs"]
+mod display;
+#[path = "renderer/hdri.rs"]
+mod hdri;
+#[path = "renderer/layers.rs"]
+mod layers;
+#[path = "renderer/lights.rs"]
+mod lights;
+#[path = "renderer/motion.rs"]
+mod motion;
 #[path = "renderer/path.rs"]
 mod path;
 #[path = "renderer/ray.rs"]
 mod ray;
+#[path = "renderer/shard.rs"]
+mod shard;
+#[path = "renderer/spectral.rs"]
+mod spectral;
+#[path = "renderer/subsurface.rs"]
+mod subsurface;
+#[path = "renderer/volume.rs"]
+mod volume;
+
+pub use checkpoint::PathtraceCheckpoint;
+use denoise::DenoiseInput;
+pub use display::{DisplayTransform, Tonemap};
+use hdri::{EnvMapLight, HdriMap};
+use layers::render_aov_samples;
+pub use layers::{AovSample, OutputFormat, RenderLayers};
+use lights::LightSampler;
+pub use motion::Shutter;
+use motion::{MotionKeys, apply_object_motion, motion_point, swept_bounds};
+pub use shard::{RenderShard, ShardLayers, merge_shards};
+use shard::{covers_pixel, frame_tile_jobs};
+use spectral::{Wavelengths, lift_rgb};
+use subsurface::{
+    diffusion_radiance, random_walk, sample_henyey_greenstein, subsurface_body_weight,
+    subsurface_coat, subsurface_exit, subsurface_params,
+};
+use volume::{
+    Fog, VolumeRegion, compile_volume_region, estimate_direct_phase, has_scattering_media,
+    henyey_greenstein, marched_transmittance, medium_env_light_pdf, medium_shadow_transmittance,
+    parse_fog, sample_medium_scatter, scatter_along_ray, split_volume_objects,
+};
 
 #[derive(Debug, Clone, Copy)]
 pub struct RenderOptions {
@@ -38,6 +87,10 @@ pub struct RenderOptions {
     pub step_scale: f32,
     pub camera_z: f32,
     pub fov_y_degrees: f32,
+    pub display: DisplayTransform,
+    pub shutter: Shutter,
+    /// Restricts rendering to part of the frame; `None` renders all of it.
+    pub shard: Option<RenderShard>,
 }
 
 impl Default for RenderOptions {
@@ -51,6 +104,9 @@ impl Default for RenderOptions {
             step_scale: 0.7,
             camera_z: 6.0,
             fov_y_degrees: 45.0,
+            display: DisplayTransform::default(),
+            shutter: Shutter::default(),
+            shard: None,
         }
     }
 }
@@ -77,15 +133,30 @@ pub struct SceneRenderSettings {
     pub trace_bounces: Option<u32>,
     pub trace_min_spp: Option<u32>,
     pub trace_noise_threshold: Option<f32>,
+    pub denoise: Option<bool>,
+    pub spectral: Option<bool>,
+    pub time_limit_secs: Option<f32>,
+    pub target_noise: Option<f32>,
+    pub exposure: Option<f32>,
+    pub tonemap: Option<Tonemap>,
+    pub dither: Option<bool>,
+    pub shutter_open: Option<f32>,
+    pub shutter_close: Option<f32>,
+    /// Rows of the grid a procedural environment is tabulated into so it can light the scene.
+    /// Unset or 0 keeps it background-only.
+    pub env_resolution: Option<u32>,
+    /// Shadow rays per hit the trace renderer spends on the environment map.
+    pub env_samples: Option<u32>,
 }
 
-#[allow(dead_code)]
 #[derive(Debug, Clone, Copy)]
 pub struct PathtraceProgress {
     pub samples_done: u32,
     pub samples_total: u32,
     pub active_pixels: u32,
     pub elapsed_ms: u128,
+    /// Image-wide relative noise of the accumulated samples.
+    pub noise: f32,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -102,7 +173,6 @@ pub struct PreviewProgress {
     pub elapsed_ms: u128,
 }
 
-#[allow(dead_code)]
 #[derive(Debug, Clone, Copy)]
 pub struct PathtraceSettings {
     pub spp: u32,
@@ -110,9 +180,16 @@ pub struct PathtraceSettings {
     pub preview_every: u32,
     pub min_spp: u32,
     pub noise_threshold: f32,
+    /// Runs the feature-guided denoiser over previews and the final image.
+    pub denoise: bool,
+    /// Traces wavelengths instead of RGB, for dispersion and measured metals.
+    pub spectral: bool,
+    /// Wall-clock budget after which the render stops with the samples it has.
+    pub time_limit: Option<Duration>,
+    /// Image-wide relative noise at which the render stops early; `0` disables it.
+    pub target_noise: f32,
 }
 
-#[allow(dead_code)]
 impl Default for PathtraceSettings {
     fn default() -> Self {
         Self {
@@ -121,6 +198,10 @@ impl Default for PathtraceSettings {
             preview_every: 5,
             min_spp: 8,
             noise_threshold: 0.03,
+            denoise: false,
+            spectral: false,
+            time_limit: None,
+            target_noise: 0.0,
         }
     }
 }
@@ -163,8 +244,20 @@ pub enum RenderError {
     UnsupportedObjectType(String),
     #[error("expected object value for rendering")]
     ExpectedObject,
-    #[error("failed to write png: {0}")]
+    #[error("failed to write image: {0}")]
     Image(#[from] ImageError),
+    #[error("failed to write image: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("cannot resume: {0}")]
+    Checkpoint(String),
+    #[error("invalid shard: {0}")]
+    Shard(String),
+    #[error("cannot load environment map {0}")]
+    Environment(String),
+    #[error("invalid volume: {0}")]
+    Volume(String),
+    #[error("invalid motion: {0}")]
+    Motion(String),
 }
 
 #[derive(Clone)]
@@ -352,6 +445,13 @@ enum SdfNode {
         base: Box<SdfNode>,
         k: f32,
     },
+    /// Object that moves while the shutter is open; `base` is compiled at the `rest` pose.
+    Motion {
+        base: Box<SdfNode>,
+        rest: PrimitiveTransform,
+        motion: Arc<MotionKeys>,
+        bounds: Aabb,
+    },
 }
 
 struct CustomSdfRuntime {
@@ -412,7 +512,6 @@ impl Vec3 {
         self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
     }
 
-    #[allow(dead_code)]
     fn cross(self, rhs: Self) -> Self {
         Self::new(
             self.y * rhs.z - self.z * rhs.y,
@@ -433,25 +532,21 @@ impl Vec3 {
         Self::new(self.x.abs(), self.y.abs(), self.z.abs())
     }
 
-    #[allow(dead_code)]
     fn min(self, rhs: Self) -> Self {
         Self::new(self.x.min(rhs.x), self.y.min(rhs.y), self.z.min(rhs.z))
     }
 
-    #[allow(dead_code)]
     fn max(self, rhs: Self) -> Self {
         Self::new(self.x.max(rhs.x), self.y.max(rhs.y), self.z.max(rhs.z))
     }
 }
 
 #[derive(Clone, Copy)]
-#[allow(dead_code)]
 struct Aabb {
     min: Vec3,
     max: Vec3,
 }
 
-#[allow(dead_code)]
 impl Aabb {
     fn centroid(self) -> Vec3 {
         self.min.add(self.max).mul(0.5)
@@ -485,6 +580,7 @@ struct CompiledScene {
     object_transforms: Vec<PrimitiveTransform>,
     dynamic_material_overrides: Vec<ObjectValue>,
     semantic_lights: Vec<SemanticLight>,
+    volumes: Vec<VolumeRegion>,
 }
 
 struct RenderSetup {
@@ -493,11 +589,18 @@ struct RenderSetup {
     camera: CameraKind,
     lights: Vec<Box<dyn Light>>,
     path_lights: Vec<PathLight>,
+    light_sampler: LightSampler,
     materials: Vec<MaterialKindRt>,
     object_transforms: Vec<PrimitiveTransform>,
     material_def_names: Vec<String>,
     dynamic_material_overrides: Vec<ObjectValue>,
     environment_name: Option<String>,
+    /// Lat-long map lighting the scene: a loaded `HdriEnvironment`, or the procedural
+    /// environment tabulated for importance sampling.
+    env_map: Option<Arc<HdriMap>>,
+    fog: Option<Fog>,
+    /// Objects carrying a `volume`, kept apart from the surfaces in `root`.
+    volumes: Vec<VolumeRegion>,
 }
 
 #[derive(Clone, Copy)]
@@ -508,6 +611,8 @@ struct RayHit {
     front_face: bool,
     object_id: u32,
     material_id: u32,
+    /// Shutter time of the ray that found the hit, so moving objects are posed to match.
+    time: f32,
 }
 
 #[derive(Clone, Copy)]
@@ -524,12 +629,13 @@ struct CompileContext {
     object_transforms: Vec<PrimitiveTransform>,
     dynamic_material_overrides: Vec<ObjectValue>,
     semantic_lights: Vec<SemanticLight>,
+    shutter: Shutter,
 }
 
 type MaterialKindRt = Material;
 
 impl CompileContext {
-    fn new(default_material: MaterialKindRt, _state: &EvalState) -> Self {
+    fn new(default_material: MaterialKindRt, shutter: Shutter) -> Self {
         Self {
             next_object_id: 1,
             default_material,
@@ -537,6 +643,7 @@ impl CompileContext {
             object_transforms: vec![PrimitiveTransform::identity()],
             dynamic_material_overrides: Vec::new(),
             semantic_lights: Vec::new(),
+            shutter,
         }
     }
 
@@ -604,6 +711,13 @@ enum PathLight {
     Env {
         radiance: Spectrum,
     },
+    Sphere(SphereLight),
+    /// The environment map held by the render setup.
+    EnvMap,
+    Directional(DirectionalLight),
+    Spot(SpotLight),
+    Rect(RectLight),
+    Disk(DiskLight),
 }
 
 trait Accelerator {
@@ -611,14 +725,17 @@ trait Accelerator {
     where
         Self: Sized;
 
-    fn distance_info(&self, p: Vec3) -> DistanceInfo;
+    fn distance_info(&self, p: Vec3, time: f32) -> DistanceInfo;
+
+    /// Like [`nearest_surface_info`], together with the node to take the surface normal from.
+    fn nearest_surface(&self, p: Vec3, time: f32) -> (DistanceInfo, &SdfNode);
 
     fn lower_bound(&self, p: Vec3) -> f32;
 
     fn scene_bounds(&self) -> Aabb;
 
-    fn distance(&self, p: Vec3) -> f32 {
-        self.distance_info(p).distance
+    fn distance(&self, p: Vec3, time: f32) -> f32 {
+        self.distance_info(p, time).distance
     }
 }
 
@@ -632,7 +749,7 @@ const CUSTOM_LEAF_FAR_LOWER_BOUND_MIN: f32 = 0.05;
 const CUSTOM_LEAF_FAR_LOWER_BOUND_MAX: f32 = 0.5;
 const CUSTOM_LEAF_FAR_LOWER_BOUND_SCALE: f32 = 0.1;
 
-fn accel_leaf_distance_info(leaf: &AccelLeaf, p: Vec3) -> DistanceInfo {
+fn accel_leaf_distance_info(leaf: &AccelLeaf, p: Vec3, time: f32) -> DistanceInfo {
     if let SdfNode::Custom {
         object_id,
         material_id,
@@ -653,7 +770,7 @@ fn accel_leaf_distance_info(leaf: &AccelLeaf, p: Vec3) -> DistanceInfo {
             };
         }
     }
-    sdf_distance_info(&leaf.node, p)
+    sdf_distance_info(&leaf.node, p, time)
 }
 
 fn collect_accel_leaves(node: &SdfNode, out: &mut Vec<AccelLeaf>) {
@@ -740,12 +857,16 @@ fn leaf_centroid_axis(bounds: Aabb, axis: usize) -> f32 {
     }
 }
 
+/// Finds the leaf whose distance ranks lowest under `measure`: the signed distance itself for
+/// the scene's union, its magnitude for the nearest surface.
 fn bvh_distance_info(
     node: &BvhNode,
     leaves: &[AccelLeaf],
     p: Vec3,
     best: &mut f32,
-) -> Option<DistanceInfo> {
+    measure: fn(f32) -> f32,
+    time: f32,
+) -> Option<(DistanceInfo, usize)> {
     let node_lb = point_aabb_lower_bound(p, node.bounds());
     if node_lb > *best {
         return None;
@@ -753,11 +874,11 @@ fn bvh_distance_info(
     match node {
         BvhNode::Leaf { leaf_index, .. } => {
             let leaf = &leaves[*leaf_index];
-            let info = accel_leaf_distance_info(leaf, p);
-            if info.distance < *best {
-                *best = info.distance;
+            let info = accel_leaf_distance_info(leaf, p, time);
+            if measure(info.distance) < *best {
+                *best = measure(info.distance);
             }
-            Some(info)
+            Some((info, *leaf_index))
         }
         BvhNode::Inner { lhs, rhs, .. } => {
             let lhs_lb = point_aabb_lower_bound(p, lhs.bounds());
@@ -768,16 +889,18 @@ fn bvh_distance_info(
                 (rhs.as_ref(), rhs_lb, lhs.as_ref(), lhs_lb)
             };
             let mut best_info = if first_lb <= *best {
-                bvh_distance_info(first, leaves, p, best)
+                bvh_distance_info(first, leaves, p, best, measure, time)
             } else {
                 None
             };
             if second_lb <= *best
-                && let Some(info) = bvh_distance_info(second, leaves, p, best)
+                && let Some(found) = bvh_distance_info(second, leaves, p, best, measure, time)
             {
                 best_info = match best_info {
-                    Some(current) if current.distance <= info.distance => Some(current),
-                    _ => Some(info),
+                    Some(current) if measure(current.0.distance) <= measure(found.0.distance) => {
+                        Some(current)
+                    }
+                    _ => Some(found),
                 };
             }
             best_info
@@ -859,7 +982,14 @@ impl BrickGrid {
         })
     }
 
-    fn distance_info(&self, leaves: &[AccelLeaf], p: Vec3) -> DistanceInfo {
+    /// Like [`bvh_distance_info`], returning the winning leaf's index with its distance info.
+    fn distance_info(
+        &self,
+        leaves: &[AccelLeaf],
+        p: Vec3,
+        measure: fn(f32) -> f32,
+        time: f32,
+    ) -> (DistanceInfo, usize) {
         let origin = brick_cell_coords(self.bounds, self.dims, p);
         let max_shell = self.dims[0].max(self.dims[1]).max(self.dims[2]);
         let mut best = f32::INFINITY;
@@ -896,17 +1026,17 @@ impl BrickGrid {
                             if lb > best {
                                 continue;
                             }
-                            let info = accel_leaf_distance_info(leaf, p);
-                            if info.distance < best {
-                                best = info.distance;
-                                best_info = Some(info);
+                            let info = accel_leaf_distance_info(leaf, p, time);
+                            if measure(info.distance) < best {
+                                best = measure(info.distance);
+                                best_info = Some((info, leaf_index));
                             }
                         }
                     }
                 }
             }
         }
-        best_info.unwrap_or_else(|| sdf_distance_info(&leaves[0].node, p))
+        best_info.unwrap_or_else(|| (sdf_distance_info(&leaves[0].node, p, time), 0))
     }
 
     fn lower_bound(&self, leaves: &[AccelLeaf], p: Vec3) -> f32 {
@@ -1032,8 +1162,15 @@ impl Accelerator for NaiveAccel {
         Self { scene, bounds }
     }
 
-    fn distance_info(&self, p: Vec3) -> DistanceInfo {
-        sdf_distance_info(&self.scene.root, p)
+    fn distance_info(&self, p: Vec3, time: f32) -> DistanceInfo {
+        sdf_distance_info(&self.scene.root, p, time)
+    }
+
+    fn nearest_surface(&self, p: Vec3, time: f32) -> (DistanceInfo, &SdfNode) {
+        (
+            nearest_surface_info(&self.scene.root, p, time),
+            &self.scene.root,
+        )
     }
 
     fn lower_bound(&self, p: Vec3) -> f32 {
@@ -1067,12 +1204,31 @@ impl Accelerator for BvhAccel {
         }
     }
 
-    fn distance_info(&self, p: Vec3) -> DistanceInfo {
+    fn distance_info(&self, p: Vec3, time: f32) -> DistanceInfo {
         let mut best = f32::INFINITY;
         self.root
             .as_ref()
-            .and_then(|root| bvh_distance_info(root, &self.leaves, p, &mut best))
-            .unwrap_or_else(|| sdf_distance_info(&self.scene.root, p))
+            .and_then(|root| bvh_distance_info(root, &self.leaves, p, &mut best, |d| d, time))
+            .map_or_else(
+                || sdf_distance_info(&self.scene.root, p, time),
+                |(info, _)| info,
+            )
+    }
+
+    fn nearest_surface(&self, p: Vec3, time: f32) -> (DistanceInfo, &SdfNode) {
+        let mut best = f32::INFINITY;
+        self.root
+            .as_ref()
+            .and_then(|root| bvh_distance_info(root, &self.leaves, p, &mut best, f32::abs, time))
+            .map_or_else(
+                || {
+                    (
+                        nearest_surface_info(&self.scene.root, p, time),
+                        &self.scene.root,
+                    )
+                },
+                |(info, leaf)| (info, &self.leaves[leaf].node),
+            )
     }
 
     fn lower_bound(&self, p: Vec3) -> f32 {
@@ -1109,11 +1265,26 @@ impl Accelerator for BricksAccel {
         }
     }
 
-    fn distance_info(&self, p: Vec3) -> DistanceInfo {
+    fn distance_info(&self, p: Vec3, time: f32) -> DistanceInfo {
         self.grid
             .as_ref()
-            .map(|grid| grid.distance_info(&self.leaves, p))
-            .unwrap_or_else(|| sdf_distance_info(&self.scene.root, p))
+            .map(|grid| grid.distance_info(&self.leaves, p, |d| d, time).0)
+            .unwrap_or_else(|| sdf_distance_info(&self.scene.root, p, time))
+    }
+
+    fn nearest_surface(&self, p: Vec3, time: f32) -> (DistanceInfo, &SdfNode) {
+        self.grid.as_ref().map_or_else(
+            || {
+                (
+                    nearest_surface_info(&self.scene.root, p, time),
+                    &self.scene.root,
+                )
+            },
+            |grid| {
+                let (info, leaf) = grid.distance_info(&self.leaves, p, f32::abs, time);
+                (info, &self.leaves[leaf].node)
+            },
+        )
     }
 
     fn lower_bound(&self, p: Vec3) -> f32 {
@@ -1144,8 +1315,8 @@ pub fn render_depth_png_with_accel(
 ) -> Result<(), RenderError> {
     let root = find_scene_root(state).ok_or(RenderError::MissingSceneRoot)?;
     let default_material = parse_material(state, root);
-    let scene = compile_scene(state, root, default_material)?;
-    let setup = build_render_setup(state, &scene, options);
+    let scene = compile_scene(state, root, default_material, options.shutter)?;
+    let setup = build_render_setup(state, &scene, options)?;
     let image = match accel_mode {
         AccelMode::Naive => render_with_accel::<NaiveAccel>(scene, setup, options),
         AccelMode::Bvh => render_with_accel::<BvhAccel>(scene, setup, options),
@@ -1155,7 +1326,6 @@ pub fn render_depth_png_with_accel(
     Ok(())
 }
 
-#[allow(dead_code)]
 pub fn render_pathtrace_png_with_accel(
     state: &EvalState,
     output_path: &Path,
@@ -1170,6 +1340,7 @@ pub fn render_pathtrace_png_with_accel(
         preview_every: spp.max(1),
         min_spp: spp.max(1),
         noise_threshold: 0.0,
+        ..PathtraceSettings::default()
     };
     let image =
         render_pathtrace_progressive_with_accel(state, options, accel_mode, settings, |_, _| {
@@ -1203,36 +1374,52 @@ pub fn render_ray_progressive_with_accel(
     options: RenderOptions,
     accel_mode: AccelMode,
     settings: RaySettings,
-    mut on_tile: impl FnMut(RayProgress, &RgbImage) -> Result<(), RenderError>,
+    on_tile: impl FnMut(RayProgress, &RgbImage) -> Result<(), RenderError>,
 ) -> Result<RgbImage, RenderError> {
+    render_ray_layers_with_accel(state, options, accel_mode, settings, false, on_tile)
+        .map(|layers| layers.image)
+}
+
+/// Like [`render_ray_progressive_with_accel`], but keeps the linear radiance and, with
+/// `with_aovs`, the primary-hit AOVs for float output.
+pub fn render_ray_layers_with_accel(
+    state: &EvalState,
+    options: RenderOptions,
+    accel_mode: AccelMode,
+    settings: RaySettings,
+    with_aovs: bool,
+    mut on_tile: impl FnMut(RayProgress, &RgbImage) -> Result<(), RenderError>,
+) -> Result<RenderLayers, RenderError> {
     let root = find_scene_root(state).ok_or(RenderError::MissingSceneRoot)?;
     let default_material = parse_material(state, root);
-    let scene = compile_scene(state, root, default_material)?;
-    let setup = build_render_setup(state, &scene, options);
-    let image = match accel_mode {
+    let scene = compile_scene(state, root, default_material, options.shutter)?;
+    let setup = build_render_setup(state, &scene, options)?;
+    match accel_mode {
         AccelMode::Naive => render_ray_with_accel_progressive::<NaiveAccel>(
             scene,
             setup,
             options,
             settings,
+            with_aovs,
             &mut on_tile,
-        )?,
+        ),
         AccelMode::Bvh => render_ray_with_accel_progressive::<BvhAccel>(
             scene,
             setup,
             options,
             settings,
+            with_aovs,
             &mut on_tile,
-        )?,
+        ),
         AccelMode::Bricks => render_ray_with_accel_progressive::<BricksAccel>(
             scene,
             setup,
             options,
             settings,
+            with_aovs,
             &mut on_tile,
-        )?,
-    };
-    Ok(image)
+        ),
+    }
 }
 
 pub fn render_preview_progressive_with_accel(
@@ -1241,74 +1428,151 @@ pub fn render_preview_progressive_with_accel(
     accel_mode: AccelMode,
     tile_size: u32,
     aa_samples: u32,
-    mut on_tile: impl FnMut(PreviewProgress, &RgbImage) -> Result<(), RenderError>,
+    on_tile: impl FnMut(PreviewProgress, &RgbImage) -> Result<(), RenderError>,
 ) -> Result<RgbImage, RenderError> {
+    render_preview_layers_with_accel(
+        state, options, accel_mode, tile_size, aa_samples, false, on_tile,
+    )
+    .map(|layers| layers.image)
+}
+
+/// Like [`render_preview_progressive_with_accel`], but keeps the float depth preview and, with
+/// `with_aovs`, the primary-hit AOVs for float output.
+pub fn render_preview_layers_with_accel(
+    state: &EvalState,
+    options: RenderOptions,
+    accel_mode: AccelMode,
+    tile_size: u32,
+    aa_samples: u32,
+    with_aovs: bool,
+    mut on_tile: impl FnMut(PreviewProgress, &RgbImage) -> Result<(), RenderError>,
+) -> Result<RenderLayers, RenderError> {
     let root = find_scene_root(state).ok_or(RenderError::MissingSceneRoot)?;
     let default_material = parse_material(state, root);
-    let scene = compile_scene(state, root, default_material)?;
-    let setup = build_render_setup(state, &scene, options);
-    let image = match accel_mode {
+    let scene = compile_scene(state, root, default_material, options.shutter)?;
+    let setup = build_render_setup(state, &scene, options)?;
+    match accel_mode {
         AccelMode::Naive => render_preview_with_accel_progressive::<NaiveAccel>(
             scene,
             setup,
             options,
             tile_size,
             aa_samples,
+            with_aovs,
             &mut on_tile,
-        )?,
+        ),
         AccelMode::Bvh => render_preview_with_accel_progressive::<BvhAccel>(
             scene,
             setup,
             options,
             tile_size,
             aa_samples,
+            with_aovs,
             &mut on_tile,
-        )?,
+        ),
         AccelMode::Bricks => render_preview_with_accel_progressive::<BricksAccel>(
             scene,
             setup,
             options,
             tile_size,
             aa_samples,
+            with_aovs,
             &mut on_tile,
-        )?,
-    };
-    Ok(image)
+        ),
+    }
 }
 
-#[allow(dead_code)]
 pub fn render_pathtrace_progressive_with_accel(
     state: &EvalState,
     options: RenderOptions,
     accel_mode: AccelMode,
     settings: PathtraceSettings,
-    mut on_preview: impl FnMut(PathtraceProgress, &RgbImage) -> Result<(), RenderError>,
+    on_preview: impl FnMut(PathtraceProgress, &RgbImage) -> Result<(), RenderError>,
 ) -> Result<RgbImage, RenderError> {
+    render_pathtrace_layers_with_accel(state, options, accel_mode, settings, false, on_preview)
+        .map(|layers| layers.image)
+}
+
+/// Like [`render_pathtrace_progressive_with_accel`], but keeps the linear radiance and, with
+/// `with_aovs`, the primary-hit AOVs for float output.
+pub fn render_pathtrace_layers_with_accel(
+    state: &EvalState,
+    options: RenderOptions,
+    accel_mode: AccelMode,
+    settings: PathtraceSettings,
+    with_aovs: bool,
+    on_preview: impl FnMut(PathtraceProgress, &RgbImage) -> Result<(), RenderError>,
+) -> Result<RenderLayers, RenderError> {
+    render_pathtrace_checkpointed_with_accel(
+        state,
+        0,
+        options,
+        accel_mode,
+        settings,
+        with_aovs,
+        None,
+        on_preview,
+        |_| Ok(()),
+    )
+}
+
+/// Like [`render_pathtrace_layers_with_accel`], but continues from `resume` when given and
+/// hands the accumulators to `on_checkpoint` after every preview. `scene_hash` identifies the
+/// scene and its assets, see [`scene_content_hash`](crate::scene_content_hash); a checkpoint
+/// taken from another scene or with other settings is refused.
+#[allow(clippy::too_many_arguments)]
+pub fn render_pathtrace_checkpointed_with_accel(
+    state: &EvalState,
+    scene_hash: u64,
+    options: RenderOptions,
+    accel_mode: AccelMode,
+    settings: PathtraceSettings,
+    with_aovs: bool,
+    resume: Option<PathtraceCheckpoint>,
+    mut on_preview: impl FnMut(PathtraceProgress, &RgbImage) -> Result<(), RenderError>,
+    mut on_checkpoint: impl FnMut(&PathtraceCheckpoint) -> Result<(), RenderError>,
+) -> Result<RenderLayers, RenderError> {
+    let fingerprint = checkpoint::fingerprint(scene_hash, options, accel_mode, settings);
+    if let Some(checkpoint) = &resume {
+        checkpoint.check_resumable(options, fingerprint)?;
+    }
     let root = find_scene_root(state).ok_or(RenderError::MissingSceneRoot)?;
     let default_material = parse_material(state, root);
-    let scene = compile_scene(state, root, default_material)?;
-    let setup = build_render_setup(state, &scene, options);
+    let scene = compile_scene(state, root, default_material, options.shutter)?;
+    let setup = build_render_setup(state, &scene, options)?;
     match accel_mode {
         AccelMode::Naive => render_pathtrace_with_accel_progressive::<NaiveAccel>(
             scene,
             setup,
             options,
             settings,
+            with_aovs,
+            resume,
+            fingerprint,
             &mut on_preview,
+            &mut on_checkpoint,
         ),
         AccelMode::Bvh => render_pathtrace_with_accel_progressive::<BvhAccel>(
             scene,
             setup,
             options,
             settings,
+            with_aovs,
+            resume,
+            fingerprint,
             &mut on_preview,
+            &mut on_checkpoint,
         ),
         AccelMode::Bricks => render_pathtrace_with_accel_progressive::<BricksAccel>(
             scene,
             setup,
             options,
             settings,
+            with_aovs,
+            resume,
+            fingerprint,
             &mut on_preview,
+            &mut on_checkpoint,
         ),
     }
 }
@@ -1350,9 +1614,47 @@ pub fn extract_scene_render_settings(state: &EvalState) -> SceneRenderSettings {
             "adaptive_threshold",
         ],
     );
+    out.denoise = read_number_field(obj, &["denoise"]).map(|v| v != 0.0);
+    out.spectral = read_number_field(obj, &["spectral"]).map(|v| v != 0.0);
+    out.time_limit_secs = read_number_field(obj, &["time_limit"]);
+    out.target_noise = read_number_field(obj, &["target_noise"]);
+    out.exposure = read_number_field(obj, &["exposure"]);
+    out.tonemap = read_tonemap_field(obj, "tonemap");
+    out.dither = read_number_field(obj, &["dither"]).map(|v| v != 0.0);
+    out.shutter_open = read_number_field(obj, &["shutter_open"]);
+    out.shutter_close = read_number_field(obj, &["shutter_close"]);
+    out.env_resolution = read_number_field(obj, &["env_resolution"])
+        .and_then(|v| if v == 0.0 { Some(0) } else { float_to_u32(v) });
+    out.env_samples = read_number_field(obj, &["env_samples"]).and_then(float_to_u32);
     out
 }
 
+fn read_tonemap_field(obj: &ObjectValue, name: &str) -> Option<Tonemap> {
+    parse_tonemap(&read_type_name_field(obj, name)?)
+}
+
+/// Lowercased name from an enum-like object (`Aces{}`, `Ortho{}`) or a string.
+fn read_type_name_field(obj: &ObjectValue, name: &str) -> Option<String> {
+    let name = match obj.fields.get(name)? {
+        Value::Object(v) => v.type_name.as_deref()?,
+        Value::String(v) => v.as_str(),
+        Value::Number(_) | Value::Array(_) | Value::Function(_) => return None,
+    };
+    Some(name.to_ascii_lowercase())
+}
+
+/// Parses a tonemap operator name, ignoring case (`aces`, `AgX`, `Filmic`, ...).
+pub fn parse_tonemap(name: &str) -> Option<Tonemap> {
+    match name.to_ascii_lowercase().as_str() {
+        "linear" | "none" => Some(Tonemap::Linear),
+        "reinhard" => Some(Tonemap::Reinhard),
+        "filmic" | "hable" => Some(Tonemap::Filmic),
+        "aces" => Some(Tonemap::Aces),
+        "agx" => Some(Tonemap::Agx),
+        _ => None,
+    }
+}
+
 fn read_accel_field(obj: &ObjectValue, name: &str) -> Option<AccelMode> {
     let value = obj.fields.get(name)?;
     match value {
@@ -1385,10 +1687,18 @@ fn compile_scene(
     state: &EvalState,
     value: &Value,
     default_material: MaterialKindRt,
+    shutter: Shutter,
 ) -> Result<CompiledScene, RenderError> {
-    let mut ctx = CompileContext::new(default_material, state);
+    let mut ctx = CompileContext::new(default_material, shutter);
     let shared_state = Arc::new(state.clone());
-    let root = compile_sdf(&shared_state, value, &mut ctx)?;
+    let mut volume_objects = Vec::new();
+    let surfaces =
+        split_volume_objects(value, &mut volume_objects).ok_or(RenderError::ExpectedObject)?;
+    let root = compile_sdf(&shared_state, &surfaces, &mut ctx)?;
+    let volumes = volume_objects
+        .iter()
+        .map(|object| compile_volume_region(&shared_state, object, &mut ctx))
+        .collect::<Result<Vec<_>, _>>()?;
     let center = sdf_center(&root);
     Ok(CompiledScene {
         root,
@@ -1397,9 +1707,30 @@ fn compile_scene(
         object_transforms: ctx.object_transforms,
         dynamic_material_overrides: ctx.dynamic_material_overrides,
         semantic_lights: ctx.semantic_lights,
+        volumes,
     })
 }
 
+/// Folds `nodes` into a balanced tree of unions, pairing neighbours so instance lists
+/// keep tight bounds and a shallow tree.
+fn union_tree(mut nodes: Vec<SdfNode>) -> Option<SdfNode> {
+    while nodes.len() > 1 {
+        let mut paired = Vec::with_capacity(nodes.len().div_ceil(2));
+        let mut iter = nodes.into_iter();
+        while let Some(lhs) = iter.next() {
+            paired.push(match iter.next() {
+                Some(rhs) => SdfNode::Union {
+                    lhs: Box::new(lhs),
+                    rhs: Box::new(rhs),
+                },
+                None => lhs,
+            });
+        }
+        nodes = paired;
+    }
+    nodes.pop()
+}
+
 fn compile_sdf(
     state: &Arc<EvalState>,
     value: &Value,
@@ -1555,6 +1886,16 @@ fn compile_sdf(
                 rhs: Box::new(rhs),
             })
         }
+        "union_all" => {
+            let Value::Array(items) = required_field(object, "items")? else {
+                return Err(RenderError::ExpectedObject);
+            };
+            let nodes = items
+                .iter()
+                .map(|item| compile_sdf(state, item, ctx))
+                .collect::<Result<Vec<_>, _>>()?;
+            union_tree(nodes).ok_or(RenderError::ExpectedObject)
+        }
         "intersect" => {
             let lhs = compile_sdf(state, required_field(object, "lhs")?, ctx)?;
             let rhs = compile_sdf(state, required_field(object, "rhs")?, ctx)?;
@@ -1738,7 +2079,8 @@ fn compile_sdf(
         other => Err(RenderError::UnsupportedObjectType(other.to_string())),
     }?;
 
-    apply_object_modifier_hooks(state, object, node)
+    let node = apply_object_modifier_hooks(state, object, node)?;
+    apply_object_motion(state, object, node, ctx.shutter)
 }
 
 fn required_field<'a>(obj: &'a ObjectValue, name: &str) -> Result<&'a Value, RenderError> {
@@ -2321,6 +2663,20 @@ fn remap_sdf_node(
             base: Box::new(remap_sdf_node(*base, ctx, map_transform)),
             k,
         },
+        SdfNode::Motion {
+            base, rest, motion, ..
+        } => {
+            let base = remap_sdf_node(*base, ctx, map_transform);
+            let rest = map_transform(rest);
+            let motion = motion.map(map_transform);
+            let bounds = swept_bounds(sdf_bounds(&base), rest, &motion);
+            SdfNode::Motion {
+                base: Box::new(base),
+                rest,
+                motion: Arc::new(motion),
+                bounds,
+            }
+        }
     }
 }
 
@@ -3637,13 +3993,15 @@ fn render_ray_with_accel_progressive<A: Accelerator + Sync + Send>(
     setup: RenderSetup,
     options: RenderOptions,
     settings: RaySettings,
+    aovs: bool,
     on_tile: &mut impl FnMut(RayProgress, &RgbImage) -> Result<(), RenderError>,
-) -> Result<RgbImage, RenderError> {
+) -> Result<RenderLayers, RenderError> {
     let accel = A::from_scene(scene);
     let aspect = options.width as f32 / options.height as f32;
     let width = options.width as usize;
     let height = options.height as usize;
     let mut buffer = vec![0_u8; width * height * 3];
+    let mut radiance = vec![0.0_f32; width * height * 3];
     let ray_ctx = RayTraceCtx {
         options,
         max_depth: settings.max_depth.max(1),
@@ -3651,7 +4009,7 @@ fn render_ray_with_accel_progressive<A: Accelerator + Sync + Send>(
     let aa_samples = settings.aa_samples.max(1);
     let sample_offsets = pixel_sample_offsets(aa_samples);
     let tile_size = settings.tile_size.max(1) as usize;
-    let tiles = tile_jobs(width, height, tile_size);
+    let tiles = frame_tile_jobs(options, tile_size);
     let tiles_total = tiles.len() as u32;
     let start = Instant::now();
     let mut tiles_done = 0_u32;
@@ -3679,7 +4037,7 @@ fn render_ray_with_accel_progressive<A: Accelerator + Sync + Send>(
                     let Some(job) = job else {
                         break;
                     };
-                    let data = render_ray_tile(
+                    let (data, radiance) = render_ray_tile(
                         accel_ref,
                         setup_ref,
                         options,
@@ -3690,7 +4048,11 @@ fn render_ray_with_accel_progressive<A: Accelerator + Sync + Send>(
                         &sample_offsets,
                         &job,
                     );
-                    let _ = tx.send(TileResult { job, data });
+                    let _ = tx.send(TileResult {
+                        job,
+                        data,
+                        radiance,
+                    });
                 }
             });
         }
@@ -3699,6 +4061,7 @@ fn render_ray_with_accel_progressive<A: Accelerator + Sync + Send>(
         for _ in 0..tiles_total {
             let tile = rx.recv().expect("worker tile result should arrive");
             merge_tile_into_buffer(&mut buffer, width, &tile.job, &tile.data);
+            merge_tile_into_buffer(&mut radiance, width, &tile.job, &tile.radiance);
             tiles_done += 1;
             let image = RgbImage::from_vec(options.width, options.height, buffer.clone())
                 .expect("pixel buffer length must match image dimensions");
@@ -3719,8 +4082,21 @@ fn render_ray_with_accel_progressive<A: Accelerator + Sync + Send>(
         return Err(err);
     }
 
-    Ok(RgbImage::from_vec(options.width, options.height, buffer)
-        .expect("pixel buffer length must match image dimensions"))
+    Ok(RenderLayers {
+        width: options.width,
+        height: options.height,
+        beauty: radiance
+            .chunks_exact(3)
+            .map(|c| [c[0], c[1], c[2]])
+            .collect(),
+        aovs: if aovs {
+            render_aov_samples(&accel, &setup, options)
+        } else {
+            Vec::new()
+        },
+        image: RgbImage::from_vec(options.width, options.height, buffer)
+            .expect("pixel buffer length must match image dimensions"),
+    })
 }
 
 fn render_depth_image(
@@ -3730,10 +4106,17 @@ fn render_depth_image(
     tile_size: u32,
     aa_samples: u32,
 ) -> RgbImage {
-    render_preview_tiled(accel, setup, options, tile_size, aa_samples, &mut |_, _| {
-        Ok(())
-    })
+    render_preview_tiled(
+        accel,
+        setup,
+        options,
+        tile_size,
+        aa_samples,
+        false,
+        &mut |_, _| Ok(()),
+    )
     .expect("preview rendering without callback failure should succeed")
+    .image
 }
 
 fn render_preview_with_accel_progressive<A: Accelerator + Sync + Send>(
@@ -3742,10 +4125,13 @@ fn render_preview_with_accel_progressive<A: Accelerator + Sync + Send>(
     options: RenderOptions,
     tile_size: u32,
     aa_samples: u32,
+    aovs: bool,
     on_tile: &mut impl FnMut(PreviewProgress, &RgbImage) -> Result<(), RenderError>,
-) -> Result<RgbImage, RenderError> {
+) -> Result<RenderLayers, RenderError> {
     let accel = A::from_scene(scene);
-    render_preview_tiled(&accel, &setup, options, tile_size, aa_samples, on_tile)
+    render_preview_tiled(
+        &accel, &setup, options, tile_size, aa_samples, aovs, on_tile,
+    )
 }
 
 fn render_preview_tiled(
@@ -3754,16 +4140,18 @@ fn render_preview_tiled(
     options: RenderOptions,
     tile_size: u32,
     aa_samples: u32,
+    aovs: bool,
     on_tile: &mut impl FnMut(PreviewProgress, &RgbImage) -> Result<(), RenderError>,
-) -> Result<RgbImage, RenderError> {
+) -> Result<RenderLayers, RenderError> {
     let aspect = options.width as f32 / options.height as f32;
     let width = options.width as usize;
     let height = options.height as usize;
     let mut buffer = vec![0_u8; width * height * 3];
+    let mut radiance = vec![0.0_f32; width * height * 3];
     let aa_samples = aa_samples.max(1);
     let sample_offsets = pixel_sample_offsets(aa_samples);
     let tile_size = tile_size.max(1) as usize;
-    let tiles = tile_jobs(width, height, tile_size);
+    let tiles = frame_tile_jobs(options, tile_size);
     let tiles_total = tiles.len() as u32;
     let start = Instant::now();
     let mut tiles_done = 0_u32;
@@ -3791,7 +4179,7 @@ fn render_preview_tiled(
                     let Some(job) = job else {
                         break;
                     };
-                    let data = render_depth_tile(
+                    let (data, radiance) = render_depth_tile(
                         accel_ref,
                         setup_ref,
                         options,
@@ -3800,7 +4188,11 @@ fn render_preview_tiled(
                         &sample_offsets,
                         &job,
                     );
-                    let _ = tx.send(TileResult { job, data });
+                    let _ = tx.send(TileResult {
+                        job,
+                        data,
+                        radiance,
+                    });
                 }
             });
         }
@@ -3809,6 +4201,7 @@ fn render_preview_tiled(
         for _ in 0..tiles_total {
             let tile = rx.recv().expect("worker tile result should arrive");
             merge_tile_into_buffer(&mut buffer, width, &tile.job, &tile.data);
+            merge_tile_into_buffer(&mut radiance, width, &tile.job, &tile.radiance);
             tiles_done += 1;
             let image = RgbImage::from_vec(options.width, options.height, buffer.clone())
                 .expect("pixel buffer length must match image dimensions");
@@ -3829,11 +4222,24 @@ fn render_preview_tiled(
         return Err(err);
     }
 
-    Ok(RgbImage::from_vec(options.width, options.height, buffer)
-        .expect("pixel buffer length must match image dimensions"))
+    Ok(RenderLayers {
+        width: options.width,
+        height: options.height,
+        beauty: radiance
+            .chunks_exact(3)
+            .map(|c| [c[0], c[1], c[2]])
+            .collect(),
+        aovs: if aovs {
+            render_aov_samples(accel, setup, options)
+        } else {
+            Vec::new()
+        },
+        image: RgbImage::from_vec(options.width, options.height, buffer)
+            .expect("pixel buffer length must match image dimensions"),
+    })
 }
 
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 struct TileJob {
     tx: usize,
     ty: usize,
@@ -3844,6 +4250,7 @@ struct TileJob {
 struct TileResult {
     job: TileJob,
     data: Vec<u8>,
+    radiance: Vec<f32>,
 }
 
 fn tile_jobs(width: usize, height: usize, tile_size: usize) -> Vec<TileJob> {
@@ -3861,7 +4268,12 @@ fn tile_jobs(width: usize, height: usize, tile_size: usize) -> Vec<TileJob> {
     jobs
 }
 
-fn merge_tile_into_buffer(buffer: &mut [u8], image_width: usize, job: &TileJob, tile: &[u8]) {
+fn merge_tile_into_buffer<T: Copy>(
+    buffer: &mut [T],
+    image_width: usize,
+    job: &TileJob,
+    tile: &[T],
+) {
     for ly in 0..job.tile_h {
         let dst = ((job.ty + ly) * image_width + job.tx) * 3;
         let src = (ly * job.tile_w) * 3;
@@ -3881,25 +4293,40 @@ fn render_ray_tile(
     aa_samples: u32,
     sample_offsets: &[(f32, f32)],
     job: &TileJob,
-) -> Vec<u8> {
+) -> (Vec<u8>, Vec<f32>) {
     let mut tile = vec![0_u8; job.tile_w * job.tile_h * 3];
+    let mut radiance = vec![0.0_f32; job.tile_w * job.tile_h * 3];
     tile.par_chunks_mut(job.tile_w * 3)
+        .zip(radiance.par_chunks_mut(job.tile_w * 3))
         .enumerate()
-        .for_each(|(ly, row)| {
+        .for_each(|(ly, (row, radiance_row))| {
             let y = job.ty + ly;
             let y_u32 = y as u32;
             for lx in 0..job.tile_w {
                 let x = job.tx + lx;
                 let x_u32 = x as u32;
                 let mut sum = Spectrum::black();
-                for &(sx, sy) in sample_offsets {
+                for (sample_idx, &(sx, sy)) in sample_offsets.iter().enumerate() {
                     let px = ((x_u32 as f32 + sx) / options.width as f32) * 2.0 - 1.0;
                     let py = 1.0 - ((y_u32 as f32 + sy) / options.height as f32) * 2.0;
-                    let ray = setup.camera.generate_ray(px * aspect, py);
+                    let mut lens_rng = XorShift64::new(seed_pixel_sample(
+                        x_u32,
+                        y_u32,
+                        options.width,
+                        sample_idx as u32,
+                    ));
+                    let time = options.shutter.sample_time(&mut lens_rng);
+                    let ray = setup.camera.generate_ray_at(
+                        px * aspect,
+                        py,
+                        lens_rng.next_f32(),
+                        lens_rng.next_f32(),
+                        time,
+                    );
                     let origin = from_api_vec3(ray.origin);
                     let dir = from_api_vec3(ray.direction).normalize();
                     let c = if let Some(aov) = debug_aov {
-                        ray::trace_ray_debug_aov(accel, setup, ray_ctx, origin, dir, aov)
+                        ray::trace_ray_debug_aov(accel, setup, ray_ctx, origin, dir, aov, time)
                     } else {
                         ray::trace_ray_recursive(
                             accel,
@@ -3909,6 +4336,7 @@ fn render_ray_tile(
                             dir,
                             MediumState::air(),
                             0,
+                            time,
                         )
                     };
                     sum = sum + c;
@@ -3917,15 +4345,18 @@ fn render_ray_tile(
                 let rgb = if debug_aov.is_some() {
                     spectrum_to_rgb8(avg)
                 } else {
-                    spectrum_to_rgb8_reinhard(avg)
+                    options.display.to_rgb8(avg, x_u32, y_u32)
                 };
                 let i = lx * 3;
                 row[i] = rgb[0];
                 row[i + 1] = rgb[1];
                 row[i + 2] = rgb[2];
+                radiance_row[i] = avg.r;
+                radiance_row[i + 1] = avg.g;
+                radiance_row[i + 2] = avg.b;
             }
         });
-    tile
+    (tile, radiance)
 }
 
 fn render_depth_tile(
@@ -3936,11 +4367,13 @@ fn render_depth_tile(
     aa_samples: u32,
     sample_offsets: &[(f32, f32)],
     job: &TileJob,
-) -> Vec<u8> {
+) -> (Vec<u8>, Vec<f32>) {
     let mut tile = vec![0_u8; job.tile_w * job.tile_h * 3];
+    let mut radiance = vec![0.0_f32; job.tile_w * job.tile_h * 3];
     tile.par_chunks_mut(job.tile_w * 3)
+        .zip(radiance.par_chunks_mut(job.tile_w * 3))
         .enumerate()
-        .for_each(|(ly, row)| {
+        .for_each(|(ly, (row, radiance_row))| {
             let y = job.ty + ly;
             let y_u32 = y as u32;
             for lx in 0..job.tile_w {
@@ -3951,33 +4384,43 @@ fn render_depth_tile(
                     let px = ((x_u32 as f32 + sx) / options.width as f32) * 2.0 - 1.0;
                     let py = 1.0 - ((y_u32 as f32 + sy) / options.height as f32) * 2.0;
                     let ray = setup.camera.generate_ray(px * aspect, py);
+                    let time = options.shutter.time_at(0.5);
                     let origin = from_api_vec3(ray.origin);
                     let dir = from_api_vec3(ray.direction).normalize();
-                    let hit = raymarch_hit(accel, origin, dir, options, 0.0, options.max_dist);
+                    let hit =
+                        raymarch_hit(accel, origin, dir, options, 0.0, options.max_dist, time);
                     let depth = match hit {
                         Some(hit) => depth_preview_value(hit.t, options.max_dist),
                         None => 0.0,
                     };
                     sum = sum + Spectrum::rgb(depth, depth, depth);
                 }
-                let rgb = spectrum_to_rgb8(sum.scale(1.0 / aa_samples as f32));
+                let avg = sum.scale(1.0 / aa_samples as f32);
+                let rgb = spectrum_to_rgb8(avg);
                 let i = lx * 3;
                 row[i] = rgb[0];
                 row[i + 1] = rgb[1];
                 row[i + 2] = rgb[2];
+                radiance_row[i] = avg.r;
+                radiance_row[i + 1] = avg.g;
+                radiance_row[i + 2] = avg.b;
             }
         });
-    tile
+    (tile, radiance)
 }
 
-#[allow(dead_code)]
+#[allow(clippy::too_many_arguments)]
 fn render_pathtrace_with_accel_progressive<A: Accelerator + Sync>(
     scene: CompiledScene,
     setup: RenderSetup,
     options: RenderOptions,
     settings: PathtraceSettings,
+    aovs: bool,
+    resume: Option<PathtraceCheckpoint>,
+    fingerprint: u64,
     on_preview: &mut impl FnMut(PathtraceProgress, &RgbImage) -> Result<(), RenderError>,
-) -> Result<RgbImage, RenderError> {
+    on_checkpoint: &mut impl FnMut(&PathtraceCheckpoint) -> Result<(), RenderError>,
+) -> Result<RenderLayers, RenderError> {
     use std::sync::atomic::{AtomicUsize, Ordering};
 
     let accel = A::from_scene(scene);
@@ -3988,15 +4431,45 @@ fn render_pathtrace_with_accel_progressive<A: Accelerator + Sync>(
     let preview_every = settings.preview_every.max(1);
     let min_spp = settings.min_spp.max(1).min(spp);
     let noise_threshold = settings.noise_threshold.max(0.0);
-    let mut pixels = vec![PixelAccumulator::new(); width_usize * options.height as usize];
+    let target_noise = settings.target_noise.max(0.0);
+    let (mut samples_done, mut pixels) = match resume {
+        Some(checkpoint) => checkpoint.into_pixels(),
+        None => (
+            0,
+            vec![PixelAccumulator::new(); width_usize * options.height as usize],
+        ),
+    };
+    // Re-derive convergence so a resumed render honours the current adaptive settings.
+    for (idx, pixel) in pixels.iter_mut().enumerate() {
+        let (x, y) = ((idx % width_usize) as u32, (idx / width_usize) as u32);
+        pixel.active = covers_pixel(options, x, y) && !pixel.converged(noise_threshold, min_spp);
+    }
     let start = Instant::now();
+    let features = if settings.denoise || aovs {
+        render_aov_samples(&accel, &setup, options)
+    } else {
+        Vec::new()
+    };
+    let resolve = |pixels: &[PixelAccumulator]| {
+        if settings.denoise {
+            resolve_denoised(pixels, &features, options)
+        } else {
+            pixels
+                .iter()
+                .map(|pixel| {
+                    let avg = pixel.average();
+                    [avg.r, avg.g, avg.b]
+                })
+                .collect()
+        }
+    };
 
-    let mut samples_done = 0_u32;
-    let mut active_pixels = pixels.len() as u32;
+    let mut active_pixels = pixels.iter().filter(|pixel| pixel.active).count() as u32;
+    let mut stopped = false;
 
-    while samples_done < spp && active_pixels > 0 {
+    while samples_done < spp && active_pixels > 0 && !stopped {
         let target = (samples_done + preview_every).min(spp);
-        while samples_done < target && active_pixels > 0 {
+        while samples_done < target && active_pixels > 0 && !stopped {
             let sample_idx = samples_done;
             let active_after = AtomicUsize::new(active_pixels as usize);
             pixels.par_iter_mut().enumerate().for_each(|(idx, pixel)| {
@@ -4011,37 +4484,73 @@ fn render_pathtrace_with_accel_progressive<A: Accelerator + Sync>(
                 let jy = rng.next_f32() - 0.5;
                 let px = (((x_u32 as f32 + 0.5 + jx) / options.width as f32) * 2.0 - 1.0) * aspect;
                 let py = 1.0 - ((y_u32 as f32 + 0.5 + jy) / options.height as f32) * 2.0;
-                let ray = setup.camera.generate_ray(px, py);
+                let time = options.shutter.sample_time(&mut rng);
+                let ray =
+                    setup
+                        .camera
+                        .generate_ray_at(px, py, rng.next_f32(), rng.next_f32(), time);
                 let origin = from_api_vec3(ray.origin);
                 let dir = from_api_vec3(ray.direction).normalize();
-                let sample =
-                    path::trace_path(&accel, &setup, origin, dir, options, max_bounces, &mut rng);
-                pixel.add_sample(sample);
-                if noise_threshold > 0.0
-                    && pixel.count >= min_spp
-                    && pixel.relative_error() <= noise_threshold
-                {
+                let wavelengths = settings
+                    .spectral
+                    .then(|| Wavelengths::sample(rng.next_f32()));
+                let radiance = path::trace_path(
+                    &accel,
+                    &setup,
+                    origin,
+                    dir,
+                    options,
+                    max_bounces,
+                    wavelengths,
+                    &mut rng,
+                    time,
+                );
+                pixel.add_sample(
+                    wavelengths.map_or(radiance, |wavelengths| wavelengths.to_rgb(radiance)),
+                );
+                if pixel.converged(noise_threshold, min_spp) {
                     pixel.active = false;
                     active_after.fetch_sub(1, Ordering::Relaxed);
                 }
             });
             samples_done += 1;
             active_pixels = active_after.load(Ordering::Relaxed) as u32;
+            stopped = settings
+                .time_limit
+                .is_some_and(|limit| start.elapsed() >= limit)
+                || (target_noise > 0.0 && image_noise(&pixels) <= target_noise);
         }
 
-        let image = image_from_pixels(&pixels, options.width, options.height);
+        let image = image_from_radiance(&resolve(&pixels), options);
         on_preview(
             PathtraceProgress {
                 samples_done,
                 samples_total: spp,
                 active_pixels,
                 elapsed_ms: start.elapsed().as_millis(),
+                noise: image_noise(&pixels),
             },
             &image,
         )?;
+        let checkpoint = PathtraceCheckpoint::new(
+            options.width,
+            options.height,
+            samples_done,
+            fingerprint,
+            pixels,
+        );
+        on_checkpoint(&checkpoint)?;
+        pixels = checkpoint.into_pixels().1;
     }
 
-    Ok(image_from_pixels(&pixels, options.width, options.height))
+    let beauty = resolve(&pixels);
+    Ok(RenderLayers {
+        width: options.width,
+        height: options.height,
+        image: image_from_radiance(&beauty, options),
+        beauty,
+        aovs: if aovs { features } else { Vec::new() },
+    })
 }
 
 fn sdf_center(node: &SdfNode) -> Vec3 {
@@ -4084,7 +4593,7 @@ fn sdf_center(node: &SdfNode) -> Vec3 {
         | SdfNode::Groove { lhs, .. }
         | SdfNode::Tongue { lhs, .. } => sdf_center(lhs),
         SdfNode::Slice { base, .. } => sdf_center(base),
-        SdfNode::Smooth { base, .. } => sdf_center(base),
+        SdfNode::Smooth { base, .. } | SdfNode::Motion { base, .. } => sdf_center(base),
     }
 }
 
@@ -4095,6 +4604,7 @@ fn raymarch_hit(
     options: RenderOptions,
     min_t: f32,
     max_t: f32,
+    time: f32,
 ) -> Option<RayHit> {
     let (entry_t, exit_t) = ray_aabb_intersection(origin, dir, accel.scene_bounds())?;
     let max_t = max_t.min(exit_t);
@@ -4105,7 +4615,7 @@ fn raymarch_hit(
             return None;
         }
         let p = origin.add(dir.mul(traveled));
-        let info = accel.distance_info(p);
+        let info = accel.distance_info(p, time);
         let d = info.distance;
         if d.abs() < options.epsilon {
             let refined = refine_hit_distance(
@@ -4116,11 +4626,13 @@ fn raymarch_hit(
                 traveled,
                 options.epsilon,
                 10,
+                time,
             );
             let position = origin.add(dir.mul(refined));
-            let normal = estimate_normal(accel, position, (options.epsilon * 4.0).max(1.0e-5));
+            let normal =
+                estimate_normal(accel, position, (options.epsilon * 4.0).max(1.0e-5), time);
             let front_face = normal.dot(dir) < 0.0;
-            let final_info = accel.distance_info(position);
+            let final_info = accel.distance_info(position, time);
             return Some(RayHit {
                 t: refined,
                 position,
@@ -4128,6 +4640,7 @@ fn raymarch_hit(
                 front_face,
                 object_id: final_info.object_id,
                 material_id: final_info.material_id,
+                time,
             });
         }
         previous_traveled = traveled;
@@ -4138,6 +4651,72 @@ fn raymarch_hit(
     None
 }
 
+/// Distance info of the single shape whose surface lies nearest to `p`. Unions are looked
+/// through, so a surface buried inside another shape still counts; that is where nested media
+/// meet.
+fn nearest_surface_info(node: &SdfNode, p: Vec3, time: f32) -> DistanceInfo {
+    match node {
+        SdfNode::Union { lhs, rhs } => {
+            let l = nearest_surface_info(lhs, p, time);
+            if sdf_lower_bound(rhs, p) > l.distance.abs() {
+                return l;
+            }
+            let r = nearest_surface_info(rhs, p, time);
+            if l.distance.abs() <= r.distance.abs() {
+                l
+            } else {
+                r
+            }
+        }
+        _ => sdf_distance_info(node, p, time),
+    }
+}
+
+/// Like `raymarch_hit`, but stops at every surface of every shape, including the ones the
+/// scene's union hides inside other shapes. Rays inside a medium march this way to find the
+/// media nested in it.
+fn raymarch_nested_hit(
+    accel: &(impl Accelerator + Sync),
+    origin: Vec3,
+    dir: Vec3,
+    options: RenderOptions,
+    min_t: f32,
+    max_t: f32,
+    time: f32,
+) -> Option<RayHit> {
+    let (entry_t, exit_t) = ray_aabb_intersection(origin, dir, accel.scene_bounds())?;
+    let max_t = max_t.min(exit_t);
+    let mut traveled = min_t.max(entry_t.max(0.0));
+    for _ in 0..options.max_steps {
+        if traveled > max_t {
+            return None;
+        }
+        let p = origin.add(dir.mul(traveled));
+        let (info, node) = accel.nearest_surface(p, time);
+        if info.distance.abs() < options.epsilon {
+            let normal = resolve_surface_normal_from_node(
+                node,
+                p,
+                (options.epsilon * 4.0).max(1.0e-5),
+                time,
+            )
+            .unwrap_or_else(|| dir.mul(-1.0));
+            return Some(RayHit {
+                t: traveled,
+                position: p,
+                normal,
+                front_face: normal.dot(dir) < 0.0,
+                object_id: info.object_id,
+                material_id: info.material_id,
+                time,
+            });
+        }
+        traveled += (info.distance.abs() * options.step_scale.clamp(0.05, 1.0))
+            .max((options.epsilon * 0.5).max(1.0e-5));
+    }
+    None
+}
+
 fn ray_aabb_intersection(origin: Vec3, dir: Vec3, aabb: Aabb) -> Option<(f32, f32)> {
     let mut tmin = f32::NEG_INFINITY;
     let mut tmax = f32::INFINITY;
@@ -4177,6 +4756,7 @@ fn secondary_min_t(epsilon: f32) -> f32 {
     epsilon * 2.0
 }
 
+#[allow(clippy::too_many_arguments)]
 fn refine_hit_distance(
     accel: &(impl Accelerator + Sync),
     origin: Vec3,
@@ -4185,11 +4765,12 @@ fn refine_hit_distance(
     mut hi: f32,
     epsilon: f32,
     iterations: u32,
+    time: f32,
 ) -> f32 {
     for _ in 0..iterations {
         let mid = 0.5 * (lo + hi);
         let p = origin.add(dir.mul(mid));
-        let d = accel.distance(p).abs();
+        let d = accel.distance(p, time).abs();
         if d < epsilon {
             hi = mid;
         } else {
@@ -4199,13 +4780,13 @@ fn refine_hit_distance(
     hi
 }
 
-fn estimate_normal(accel: &(impl Accelerator + Sync), p: Vec3, e: f32) -> Vec3 {
-    let dx =
-        accel.distance(Vec3::new(p.x + e, p.y, p.z)) - accel.distance(Vec3::new(p.x - e, p.y, p.z));
-    let dy =
-        accel.distance(Vec3::new(p.x, p.y + e, p.z)) - accel.distance(Vec3::new(p.x, p.y - e, p.z));
-    let dz =
-        accel.distance(Vec3::new(p.x, p.y, p.z + e)) - accel.distance(Vec3::new(p.x, p.y, p.z - e));
+fn estimate_normal(accel: &(impl Accelerator + Sync), p: Vec3, e: f32, time: f32) -> Vec3 {
+    let dx = accel.distance(Vec3::new(p.x + e, p.y, p.z), time)
+        - accel.distance(Vec3::new(p.x - e, p.y, p.z), time);
+    let dy = accel.distance(Vec3::new(p.x, p.y + e, p.z), time)
+        - accel.distance(Vec3::new(p.x, p.y - e, p.z), time);
+    let dz = accel.distance(Vec3::new(p.x, p.y, p.z + e), time)
+        - accel.distance(Vec3::new(p.x, p.y, p.z - e), time);
     let normal = Vec3::new(dx, dy, dz);
     if normal.length() > 1.0e-8 {
         normal.normalize()
@@ -4221,6 +4802,7 @@ fn shade_color(
     lights: &[Box<dyn Light>],
     material: MaterialKindRt,
     bsdf_ctx: BsdfContextBase,
+    time: f32,
 ) -> Spectrum {
     if lights.is_empty() {
         return Spectrum::rgb(1.0, 1.0, 1.0);
@@ -4264,10 +4846,24 @@ fn shade_color(
                 from_api_vec3(wi),
                 max_t,
                 options.epsilon,
+                time,
             );
             if shadow <= 0.0 {
                 continue;
             }
+            let shadow = if has_scattering_media(setup, MediumState::air()) {
+                shadow
+                    * marched_transmittance(
+                        setup,
+                        MediumState::air(),
+                        shadow_origin,
+                        from_api_vec3(wi),
+                        max_t,
+                        time,
+                    )
+            } else {
+                shadow
+            };
             let f = eval_bsdf(setup, material, bsdf_ctx, from_api_vec3(wi));
             color = color + (f * sample.radiance).scale(ndotl * shadow * inv_samples);
         }
@@ -4326,13 +4922,17 @@ fn env_radiance(lights: &[PathLight]) -> Spectrum {
 }
 
 fn environment_color(setup: &RenderSetup, dir: Vec3) -> Option<Spectrum> {
-    let name = setup.environment_name.as_deref()?;
-    let value =
-        eval_environment_function(&setup.state, name, "color", &[vec3_value_value(dir)]).ok()?;
+    match setup.environment_name.as_deref() {
+        Some(name) => procedural_environment_color(&setup.state, name, dir),
+        None => setup.env_map.as_ref().map(|map| map.radiance(dir)),
+    }
+}
+
+fn procedural_environment_color(state: &EvalState, name: &str, dir: Vec3) -> Option<Spectrum> {
+    let value = eval_environment_function(state, name, "color", &[vec3_value_value(dir)]).ok()?;
     spectrum_from_value(&value)
 }
 
-#[allow(dead_code)]
 fn env_light_pdf_for_dir(
     lights: &[PathLight],
     setup: &RenderSetup,
@@ -4340,26 +4940,27 @@ fn env_light_pdf_for_dir(
     bsdf_ctx: BsdfContextBase,
     wi: Vec3,
 ) -> f32 {
-    if lights.is_empty() {
-        return 0.0;
-    }
-    let env_count = lights
-        .iter()
-        .filter(|light| matches!(light, PathLight::Env { .. }))
-        .count() as f32;
-    if env_count <= 0.0 {
-        return 0.0;
-    }
-    let select_pdf = env_count / lights.len() as f32;
-    select_pdf * pdf_bsdf(setup, mat, bsdf_ctx, wi).max(1.0e-6)
+    let sampler = &setup.light_sampler;
+    let env_select_pdf =
+        sampler.infinite_pmf(|index| matches!(lights[index], PathLight::Env { .. }));
+    let env_pdf = if env_select_pdf > 0.0 {
+        env_select_pdf * pdf_bsdf(setup, mat, bsdf_ctx, wi).max(1.0e-6)
+    } else {
+        0.0
+    };
+    let map_pdf = setup.env_map.as_ref().map_or(0.0, |map| {
+        sampler.infinite_pmf(|index| matches!(lights[index], PathLight::EnvMap)) * map.pdf(wi)
+    });
+    env_pdf + map_pdf
 }
 
-#[allow(dead_code)]
 struct DirectLightingCtx<'a, A: Accelerator + Sync> {
     accel: &'a A,
     setup: &'a RenderSetup,
     options: RenderOptions,
     lights: &'a [PathLight],
+    /// Wavelengths of a spectral path, which light colors are upsampled to.
+    wavelengths: Option<Wavelengths>,
 }
 
 #[derive(Clone, Copy)]
@@ -4368,11 +4969,33 @@ struct RayTraceCtx {
     max_depth: u32,
 }
 
+/// Transmissive objects a ray can be nested inside at once; entering another forgets the
+/// outermost.
+const MAX_NESTED_MEDIA: usize = 4;
+
+/// Surfaces of overridden media a single ray segment crosses before giving up.
+const MAX_MEDIUM_CROSSINGS: usize = 16;
+
+/// A transmissive material the ray is inside. Entries are keyed by material rather than
+/// object so the cutter surfaces of a hollow shape close the medium its outer surface opened.
+#[derive(Clone, Copy)]
+struct MediumEntry {
+    material_id: u32,
+    priority: u32,
+    medium: MediumParams,
+}
+
 #[derive(Clone, Copy)]
 struct MediumState {
     ior: f32,
     absorption_color: Spectrum,
     density: f32,
+    scattering: f32,
+    scattering_color: Spectrum,
+    anisotropy: f32,
+    /// Media the ray is inside, in the order it entered them. The fields above come from the
+    /// one with the highest priority, the innermost on ties.
+    stack: [Option<MediumEntry>; MAX_NESTED_MEDIA],
 }
 
 impl MediumState {
@@ -4381,7 +5004,100 @@ impl MediumState {
             ior: 1.0,
             absorption_color: Spectrum::rgb(1.0, 1.0, 1.0),
             density: 0.0,
+            scattering: 0.0,
+            scattering_color: Spectrum::rgb(1.0, 1.0, 1.0),
+            anisotropy: 0.0,
+            stack: [None; MAX_NESTED_MEDIA],
+        }
+    }
+
+    /// Outside every transmissive object, where the scene's `Fog` lives.
+    fn is_air(self) -> bool {
+        self.ior <= 1.0 && self.density <= 0.0 && self.scattering <= 0.0
+    }
+
+    fn from_stack(stack: [Option<MediumEntry>; MAX_NESTED_MEDIA]) -> Self {
+        let air = Self {
+            stack,
+            ..Self::air()
+        };
+        match air.active() {
+            Some(entry) => Self {
+                ior: entry.medium.ior.unwrap_or(1.0),
+                absorption_color: entry.medium.absorption_color,
+                density: entry.medium.density,
+                scattering: entry.medium.scattering,
+                scattering_color: entry.medium.scattering_color,
+                anisotropy: entry.medium.anisotropy,
+                stack,
+            },
+            None => air,
+        }
+    }
+
+    /// The entry that owns the space the ray is in.
+    fn active(&self) -> Option<MediumEntry> {
+        self.stack
+            .iter()
+            .flatten()
+            .fold(None, |best: Option<MediumEntry>, entry| match best {
+                Some(best) if best.priority > entry.priority => Some(best),
+                _ => Some(*entry),
+            })
+    }
+
+    fn entered(self, entry: MediumEntry) -> Self {
+        let mut stack = self.stack;
+        match stack.iter().position(Option::is_none) {
+            Some(index) => stack[index] = Some(entry),
+            None => {
+                stack.rotate_left(1);
+                stack[MAX_NESTED_MEDIA - 1] = Some(entry);
+            }
+        }
+        Self::from_stack(stack)
+    }
+
+    /// The medium after leaving `material_id`; unchanged when the ray was not known to be
+    /// inside it.
+    fn exited(self, material_id: u32) -> Self {
+        let Some(index) = self
+            .stack
+            .iter()
+            .rposition(|entry| entry.is_some_and(|entry| entry.material_id == material_id))
+        else {
+            return self;
+        };
+        let mut stack = self.stack;
+        stack[index..].rotate_left(1);
+        stack[MAX_NESTED_MEDIA - 1] = None;
+        Self::from_stack(stack)
+    }
+
+    /// Index of refraction beyond a surface of `material_id` the ray leaves through.
+    fn exterior_ior(self, material_id: u32) -> f32 {
+        self.exited(material_id).ior
+    }
+
+    /// Whether `hit` lies on a medium that a higher-priority medium around the ray overrides,
+    /// so the ray crosses it without refracting.
+    fn is_false_interface(&self, material: MaterialKindRt, hit: RayHit) -> bool {
+        let Some(active) = self.active() else {
+            return false;
+        };
+        if !is_transmissive(material) || dominant_material_params(material).thin_walled {
+            return false;
         }
+        let priority = if hit.front_face {
+            Some(dominant_material_params(material).priority)
+        } else {
+            self.stack
+                .iter()
+                .flatten()
+                .rfind(|entry| entry.material_id == hit.material_id)
+                .map(|entry| entry.priority)
+        };
+        priority.is_some_and(|priority| priority < active.priority)
     }
 }
 
@@ -4392,13 +5108,21 @@ struct BsdfContextBase {
     normal: Vec3,
     wo: Vec3,
     current_ior: f32,
+    /// Index of refraction beyond the surface when the ray is entering the material, which is
+    /// that of the medium it enters rather than the material's own.
+    interior_ior: f32,
+    /// Index of refraction beyond the surface when the ray is leaving the material.
+    exterior_ior: f32,
+    /// Wavelengths of a spectral path, which the colors of Forge BSDF hooks are upsampled to.
+    wavelengths: Option<Wavelengths>,
 }
 
 fn build_bsdf_context(
     setup: &RenderSetup,
     hit: RayHit,
+    mat: MaterialKindRt,
     view_dir: Vec3,
-    current_ior: f32,
+    medium: MediumState,
 ) -> BsdfContextBase {
     let transform = setup
         .object_transforms
@@ -4407,7 +5131,7 @@ fn build_bsdf_context(
         .unwrap_or_else(PrimitiveTransform::identity);
     let local_position = to_local(hit.position, transform);
     let geometric_normal =
-        resolve_surface_normal_at_hit(&setup.root, hit.position, hit.front_face, 1.0e-4)
+        resolve_surface_normal_at_hit(&setup.root, hit.position, hit.front_face, 1.0e-4, hit.time)
             .unwrap_or_else(|| {
                 if hit.front_face {
                     hit.normal.normalize()
@@ -4444,7 +5168,10 @@ fn build_bsdf_context(
         local_position,
         normal,
         wo: view_dir.normalize(),
-        current_ior,
+        current_ior: medium.ior,
+        interior_ior: transition_medium(mat, hit, medium).ior,
+        exterior_ior: medium.exterior_ior(hit.material_id),
+        wavelengths: None,
     }
 }
 
@@ -4453,48 +5180,55 @@ fn resolve_surface_normal_at_hit(
     p: Vec3,
     front_face: bool,
     epsilon: f32,
+    time: f32,
 ) -> Option<Vec3> {
-    let n = resolve_surface_normal_from_node(node, p, epsilon)?;
+    let n = resolve_surface_normal_from_node(node, p, epsilon, time)?;
     let n = if front_face { n } else { n.mul(-1.0) };
     Some(n.normalize())
 }
 
-fn resolve_surface_normal_from_node(node: &SdfNode, p: Vec3, epsilon: f32) -> Option<Vec3> {
+fn resolve_surface_normal_from_node(
+    node: &SdfNode,
+    p: Vec3,
+    epsilon: f32,
+    time: f32,
+) -> Option<Vec3> {
     match node {
         SdfNode::Sphere { .. }
         | SdfNode::Box { .. }
         | SdfNode::Cylinder { .. }
         | SdfNode::Torus { .. }
         | SdfNode::ExtrudePolygon { .. }
-        | SdfNode::Custom { .. } => Some(estimate_node_normal(node, p, epsilon)),
+        | SdfNode::Custom { .. } => Some(estimate_node_normal(node, p, epsilon, time)),
         SdfNode::DomainModifier { .. }
         | SdfNode::DistancePostModifier { .. }
-        | SdfNode::Noise { .. } => Some(estimate_node_normal(node, p, epsilon)),
+        | SdfNode::Noise { .. }
+        | SdfNode::Motion { .. } => Some(estimate_node_normal(node, p, epsilon, time)),
         SdfNode::Union { lhs, rhs } => {
-            let l = sdf_distance_info(lhs, p);
-            let r = sdf_distance_info(rhs, p);
-            if l.distance <= r.distance {
-                resolve_surface_normal_from_node(lhs, p, epsilon)
+            let l = nearest_surface_info(lhs, p, time);
+            let r = nearest_surface_info(rhs, p, time);
+            if l.distance.abs() <= r.distance.abs() {
+                resolve_surface_normal_from_node(lhs, p, epsilon, time)
             } else {
-                resolve_surface_normal_from_node(rhs, p, epsilon)
+                resolve_surface_normal_from_node(rhs, p, epsilon, time)
             }
         }
         SdfNode::Intersect { lhs, rhs } => {
-            let l = sdf_distance_info(lhs, p);
-            let r = sdf_distance_info(rhs, p);
+            let l = sdf_distance_info(lhs, p, time);
+            let r = sdf_distance_info(rhs, p, time);
             if l.distance >= r.distance {
-                resolve_surface_normal_from_node(lhs, p, epsilon)
+                resolve_surface_normal_from_node(lhs, p, epsilon, time)
             } else {
-                resolve_surface_normal_from_node(rhs, p, epsilon)
+                resolve_surface_normal_from_node(rhs, p, epsilon, time)
             }
         }
         SdfNode::Subtract { lhs, rhs } => {
-            let l = sdf_distance_info(lhs, p);
-            let r = sdf_distance_info(rhs, p);
+            let l = sdf_distance_info(lhs, p, time);
+            let r = sdf_distance_info(rhs, p, time);
             if l.distance >= -r.distance {
-                resolve_surface_normal_from_node(lhs, p, epsilon)
+                resolve_surface_normal_from_node(lhs, p, epsilon, time)
             } else {
-                resolve_surface_normal_from_node(rhs, p, epsilon).map(|n| n.mul(-1.0))
+                resolve_surface_normal_from_node(rhs, p, epsilon, time).map(|n| n.mul(-1.0))
             }
         }
         SdfNode::UnionRound { lhs, rhs, r }
@@ -4506,19 +5240,21 @@ fn resolve_surface_normal_from_node(node: &SdfNode, p: Vec3, epsilon: f32) -> Op
         | SdfNode::UnionStairs { lhs, rhs, r, .. }
         | SdfNode::IntersectColumns { lhs, rhs, r, .. }
         | SdfNode::IntersectStairs { lhs, rhs, r, .. } => {
-            blend_surface_normals(lhs, rhs, p, epsilon, *r, false)
+            blend_surface_normals(lhs, rhs, p, epsilon, *r, false, time)
         }
         SdfNode::DiffRound { lhs, rhs, r }
         | SdfNode::DiffChamfer { lhs, rhs, r }
         | SdfNode::DiffColumns { lhs, rhs, r, .. }
         | SdfNode::DiffStairs { lhs, rhs, r, .. }
         | SdfNode::Pipe { lhs, rhs, r }
-        | SdfNode::Engrave { lhs, rhs, r } => blend_surface_normals(lhs, rhs, p, epsilon, *r, true),
+        | SdfNode::Engrave { lhs, rhs, r } => {
+            blend_surface_normals(lhs, rhs, p, epsilon, *r, true, time)
+        }
         SdfNode::Groove { lhs, rhs, ra, rb } | SdfNode::Tongue { lhs, rhs, ra, rb } => {
-            blend_surface_normals(lhs, rhs, p, epsilon, ra.max(*rb), true)
+            blend_surface_normals(lhs, rhs, p, epsilon, ra.max(*rb), true, time)
         }
-        SdfNode::Slice { base, .. } => resolve_surface_normal_from_node(base, p, epsilon),
-        SdfNode::Smooth { base, .. } => resolve_surface_normal_from_node(base, p, epsilon),
+        SdfNode::Slice { base, .. } => resolve_surface_normal_from_node(base, p, epsilon, time),
+        SdfNode::Smooth { base, .. } => resolve_surface_normal_from_node(base, p, epsilon, time),
     }
 }
 
@@ -4529,11 +5265,12 @@ fn blend_surface_normals(
     epsilon: f32,
     radius: f32,
     difference_mode: bool,
+    time: f32,
 ) -> Option<Vec3> {
-    let l = sdf_distance_info(lhs, p);
-    let r = sdf_distance_info(rhs, p);
-    let left = resolve_surface_normal_from_node(lhs, p, epsilon)?;
-    let mut right = resolve_surface_normal_from_node(rhs, p, epsilon)?;
+    let l = sdf_distance_info(lhs, p, time);
+    let r = sdf_distance_info(rhs, p, time);
+    let left = resolve_surface_normal_from_node(lhs, p, epsilon, time)?;
+    let mut right = resolve_surface_normal_from_node(rhs, p, epsilon, time)?;
     let k = radius.abs().max(1.0e-6);
     let t = if difference_mode {
         right = right.mul(-1.0);
@@ -4551,17 +5288,17 @@ fn blend_surface_normals(
     }
 }
 
-fn estimate_node_normal(node: &SdfNode, p: Vec3, epsilon: f32) -> Vec3 {
+fn estimate_node_normal(node: &SdfNode, p: Vec3, epsilon: f32, time: f32) -> Vec3 {
     let e = epsilon.max(1.0e-5);
     let k1 = Vec3::new(1.0, -1.0, -1.0);
     let k2 = Vec3::new(-1.0, -1.0, 1.0);
     let k3 = Vec3::new(-1.0, 1.0, -1.0);
     let k4 = Vec3::new(1.0, 1.0, 1.0);
     let n = k1
-        .mul(sdf_distance_info(node, p.add(k1.mul(e))).distance)
-        .add(k2.mul(sdf_distance_info(node, p.add(k2.mul(e))).distance))
-        .add(k3.mul(sdf_distance_info(node, p.add(k3.mul(e))).distance))
-        .add(k4.mul(sdf_distance_info(node, p.add(k4.mul(e))).distance));
+        .mul(sdf_distance_info(node, p.add(k1.mul(e)), time).distance)
+        .add(k2.mul(sdf_distance_info(node, p.add(k2.mul(e)), time).distance))
+        .add(k3.mul(sdf_distance_info(node, p.add(k3.mul(e)), time).distance))
+        .add(k4.mul(sdf_distance_info(node, p.add(k4.mul(e)), time).distance));
     if n.length() > 1.0e-6 {
         n.normalize()
     } else {
@@ -4569,26 +5306,19 @@ fn estimate_node_normal(node: &SdfNode, p: Vec3, epsilon: f32) -> Vec3 {
     }
 }
 
-#[allow(dead_code)]
 fn estimate_direct_mis<A: Accelerator + Sync>(
     ctx: &DirectLightingCtx<'_, A>,
     mat: MaterialKindRt,
     hit_point: Vec3,
     bsdf_ctx: BsdfContextBase,
     rng: &mut XorShift64,
+    time: f32,
 ) -> Spectrum {
     if ctx.lights.is_empty() {
         return Spectrum::black();
     }
 
-    let li = sample_one_light(
-        ctx.lights,
-        mat,
-        hit_point,
-        bsdf_ctx.normal,
-        bsdf_ctx.wo,
-        rng,
-    );
+    let li = sample_one_light(ctx.setup, hit_point, Some(bsdf_ctx.normal), rng);
     if li.pdf <= 1.0e-6 {
         return Spectrum::black();
     }
@@ -4609,10 +5339,25 @@ fn estimate_direct_mis<A: Accelerator + Sync>(
         li.wi,
         li.max_t,
         ctx.options.epsilon,
+        time,
     );
     if vis <= 0.0 {
         return Spectrum::black();
     }
+    let vis = if has_scattering_media(ctx.setup, MediumState::air()) {
+        let max_t = li.max_t.min(ctx.options.max_dist);
+        vis * medium_shadow_transmittance(
+            ctx.setup,
+            MediumState::air(),
+            shadow_origin,
+            li.wi,
+            max_t,
+            rng,
+            time,
+        )
+    } else {
+        vis
+    };
 
     let f = eval_bsdf(ctx.setup, mat, bsdf_ctx, li.wi);
     let bsdf_pdf = pdf_bsdf(ctx.setup, mat, bsdf_ctx, li.wi).max(1.0e-6);
@@ -4621,11 +5366,10 @@ fn estimate_direct_mis<A: Accelerator + Sync>(
     } else {
         power_heuristic(li.pdf, bsdf_pdf)
     };
-    (f * li.radiance).scale((cos_theta * vis * w) / li.pdf.max(1.0e-6))
+    (f * lift_rgb(ctx.wavelengths, li.radiance)).scale((cos_theta * vis * w) / li.pdf.max(1.0e-6))
 }
 
 #[derive(Clone, Copy)]
-#[allow(dead_code)]
 struct DirectLightSample {
     wi: Vec3,
     radiance: Spectrum,
@@ -4634,19 +5378,26 @@ struct DirectLightSample {
     delta: bool,
 }
 
-#[allow(dead_code)]
+/// Picks a light and a direction towards it from `hit_point`. Constant environments are
+/// sampled over the hemisphere of `normal`, or over the whole sphere for a point inside a
+/// medium, which passes `None`.
 fn sample_one_light(
-    lights: &[PathLight],
-    _mat: MaterialKindRt,
+    setup: &RenderSetup,
     hit_point: Vec3,
-    normal: Vec3,
-    _wo: Vec3,
+    normal: Option<Vec3>,
     rng: &mut XorShift64,
 ) -> DirectLightSample {
-    let count = lights.len() as f32;
-    let idx = ((rng.next_f32() * count).floor() as usize).min(lights.len() - 1);
-    let select_pdf = 1.0 / count;
-    match lights[idx] {
+    let fallback = normal.unwrap_or(Vec3::new(0.0, 1.0, 0.0));
+    let Some((idx, select_pdf)) = setup.light_sampler.sample(hit_point, rng.next_f32()) else {
+        return DirectLightSample {
+            wi: fallback,
+            radiance: Spectrum::black(),
+            pdf: 0.0,
+            max_t: 0.0,
+            delta: true,
+        };
+    };
+    match setup.path_lights[idx] {
         PathLight::Point {
             position,
             intensity,
@@ -4664,8 +5415,16 @@ fn sample_one_light(
             }
         }
         PathLight::Env { radiance } => {
-            let wi = cosine_sample_hemisphere(normal, rng);
-            let env_pdf = cosine_pdf(normal, wi).max(1.0e-6);
+            let (wi, env_pdf) = match normal {
+                Some(normal) => {
+                    let wi = cosine_sample_hemisphere(normal, rng);
+                    (wi, cosine_pdf(normal, wi).max(1.0e-6))
+                }
+                None => (
+                    uniform_sample_sphere(rng),
+                    1.0 / (4.0 * std::f32::consts::PI),
+                ),
+            };
             DirectLightSample {
                 wi,
                 radiance,
@@ -4674,10 +5433,128 @@ fn sample_one_light(
                 delta: false,
             }
         }
-    }
-}
+        PathLight::EnvMap => {
+            let Some(map) = &setup.env_map else {
+                return DirectLightSample {
+                    wi: fallback,
+                    radiance: Spectrum::black(),
+                    pdf: 0.0,
+                    max_t: 0.0,
+                    delta: true,
+                };
+            };
+            let (wi, pdf) = map.sample(rng.next_f32(), rng.next_f32());
+            DirectLightSample {
+                wi,
+                radiance: environment_color(setup, wi).unwrap_or_else(|| map.radiance(wi)),
+                pdf: select_pdf * pdf,
+                max_t: f32::INFINITY,
+                delta: false,
+            }
+        }
+        PathLight::Directional(light) if light.angle > 0.0 => {
+            let (u, v) = (rng.next_f32(), rng.next_f32());
+            let solid_angle = light.solid_angle();
+            DirectLightSample {
+                wi: from_api_vec3(light.sample_direction(u, v)),
+                radiance: light.irradiance.scale(1.0 / solid_angle),
+                pdf: select_pdf / solid_angle,
+                max_t: f32::INFINITY,
+                delta: false,
+            }
+        }
+        PathLight::Directional(light) => DirectLightSample {
+            wi: from_api_vec3(light.to_light()),
+            radiance: light.irradiance,
+            pdf: select_pdf,
+            max_t: f32::INFINITY,
+            delta: true,
+        },
+        PathLight::Spot(light) => {
+            let to_light = from_api_vec3(light.position).sub(hit_point);
+            let dist = to_light.length().max(1.0e-4);
+            let wi = to_light.mul(1.0 / dist);
+            let falloff = light.falloff(to_api_vec3(wi.mul(-1.0)));
+            DirectLightSample {
+                wi,
+                radiance: light.intensity.scale(falloff / (dist * dist)),
+                pdf: select_pdf,
+                max_t: dist - 1.0e-4,
+                delta: true,
+            }
+        }
+        PathLight::Sphere(light) => sample_area_light(&light, hit_point, select_pdf, rng),
+        PathLight::Rect(light) => sample_area_light(&light, hit_point, select_pdf, rng),
+        PathLight::Disk(light) => sample_area_light(&light, hit_point, select_pdf, rng),
+    }
+}
+
+fn sample_area_light(
+    light: &impl AreaLight,
+    hit_point: Vec3,
+    select_pdf: f32,
+    rng: &mut XorShift64,
+) -> DirectLightSample {
+    let (u, v) = (rng.next_f32(), rng.next_f32());
+    let sample = light.sample_area(to_api_vec3(hit_point), u, v);
+    DirectLightSample {
+        wi: from_api_vec3(sample.wi),
+        radiance: sample.radiance,
+        pdf: select_pdf * sample.pdf,
+        max_t: sample.distance - 1.0e-4,
+        delta: false,
+    }
+}
+
+/// Light a ray picks up from area lights closer than `max_t` and, when it escapes the scene,
+/// from the disc of a directional light.
+///
+/// `bounce` is the point and density of the BSDF sample that spawned the ray, used to weight
+/// the hit against the light sampling in `estimate_direct_mis`; camera rays and delta bounces
+/// pass `None` and take the full emission.
+fn emitter_radiance(
+    setup: &RenderSetup,
+    origin: Vec3,
+    dir: Vec3,
+    max_t: f32,
+    escaped: bool,
+    bounce: Option<(Vec3, f32)>,
+    medium: MediumState,
+) -> Spectrum {
+    let (origin, dir) = (to_api_vec3(origin), to_api_vec3(dir));
+    let mut sum = Spectrum::black();
+    for (index, light) in setup.path_lights.iter().enumerate() {
+        let hit = match light {
+            PathLight::Sphere(light) => light.intersect(origin, dir),
+            PathLight::Rect(light) => light.intersect(origin, dir),
+            PathLight::Disk(light) => light.intersect(origin, dir),
+            PathLight::Directional(light) if escaped && light.angle > 0.0 => {
+                let radiance = light.radiance_towards(dir);
+                (radiance != Spectrum::black()).then(|| AreaLightSample {
+                    wi: dir,
+                    distance: max_t,
+                    radiance,
+                    pdf: 1.0 / light.solid_angle(),
+                })
+            }
+            _ => None,
+        };
+        let Some(hit) = hit.filter(|hit| hit.distance < max_t || escaped) else {
+            continue;
+        };
+        if hit.pdf <= 0.0 {
+            continue;
+        }
+        let weight = bounce.map_or(1.0, |(point, bsdf_pdf)| {
+            let select_pdf = setup.light_sampler.pmf(point, index);
+            power_heuristic(bsdf_pdf.max(1.0e-6), select_pdf * hit.pdf)
+        });
+        sum = sum
+            + apply_medium_attenuation(hit.radiance, medium, hit.distance.min(max_t)).scale(weight);
+    }
+    sum
+}
 
-#[allow(dead_code)]
 fn cosine_sample_hemisphere(normal: Vec3, rng: &mut XorShift64) -> Vec3 {
     let u1 = rng.next_f32().clamp(1.0e-6, 1.0 - 1.0e-6);
     let u2 = rng.next_f32().clamp(1.0e-6, 1.0 - 1.0e-6);
@@ -4698,7 +5575,13 @@ fn cosine_sample_hemisphere(normal: Vec3, rng: &mut XorShift64) -> Vec3 {
     t.mul(x).add(b.mul(y)).add(n.mul(z)).normalize()
 }
 
-#[allow(dead_code)]
+fn uniform_sample_sphere(rng: &mut XorShift64) -> Vec3 {
+    let z = 1.0 - 2.0 * rng.next_f32();
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * std::f32::consts::PI * rng.next_f32();
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
 fn cosine_pdf(normal: Vec3, wi: Vec3) -> f32 {
     normal.dot(wi).max(0.0) / std::f32::consts::PI
 }
@@ -4709,6 +5592,7 @@ fn shadow_visibility(
     dir: Vec3,
     max_t: f32,
     epsilon: f32,
+    time: f32,
 ) -> f32 {
     let mut t = secondary_min_t(epsilon);
     let mut visibility = 1.0_f32;
@@ -4721,7 +5605,7 @@ fn shadow_visibility(
         let h = if lower > (epsilon * 8.0).max(0.02) {
             lower
         } else {
-            accel.distance(p).abs()
+            accel.distance(p, time).abs()
         };
         if h < epsilon * 4.0 {
             return 0.0;
@@ -4732,12 +5616,10 @@ fn shadow_visibility(
     visibility.clamp(0.0, 1.0)
 }
 
-#[allow(dead_code)]
 struct XorShift64 {
     state: u64,
 }
 
-#[allow(dead_code)]
 impl XorShift64 {
     fn new(seed: u64) -> Self {
         let state = if seed == 0 { 0x9E3779B97F4A7C15 } else { seed };
@@ -4759,14 +5641,12 @@ impl XorShift64 {
     }
 }
 
-#[allow(dead_code)]
 fn seed_pixel(x: u32, y: u32, width: u32) -> u64 {
     let idx = u64::from(y) * u64::from(width) + u64::from(x);
     idx.wrapping_mul(0x9E3779B97F4A7C15)
         .wrapping_add(0xBF58476D1CE4E5B9)
 }
 
-#[allow(dead_code)]
 fn seed_pixel_sample(x: u32, y: u32, width: u32, sample: u32) -> u64 {
     let base = seed_pixel(x, y, width);
     let s = u64::from(sample).wrapping_mul(0x94D0_49BB_1331_11EB);
@@ -4774,8 +5654,7 @@ fn seed_pixel_sample(x: u32, y: u32, width: u32, sample: u32) -> u64 {
 }
 
 #[derive(Clone, Copy)]
-#[allow(dead_code)]
-struct PixelAccumulator {
+pub(super) struct PixelAccumulator {
     sum: Spectrum,
     count: u32,
     mean_luma: f32,
@@ -4783,7 +5662,6 @@ struct PixelAccumulator {
     active: bool,
 }
 
-#[allow(dead_code)]
 impl PixelAccumulator {
     fn new() -> Self {
         Self {
@@ -4805,27 +5683,77 @@ impl PixelAccumulator {
         self.m2_luma += delta * delta2;
     }
 
-    fn relative_error(&self) -> f32 {
+    fn average(&self) -> Spectrum {
+        if self.count == 0 {
+            Spectrum::black()
+        } else {
+            self.sum.scale(1.0 / self.count as f32)
+        }
+    }
+
+    /// Variance of the mean luminance, the squared standard error of the estimate.
+    fn mean_variance(&self) -> f32 {
         if self.count < 2 {
             return f32::INFINITY;
         }
         let n = self.count as f32;
-        let variance = (self.m2_luma / (n - 1.0)).max(0.0);
-        let stderr = (variance / n).sqrt();
-        stderr / self.mean_luma.abs().max(1.0e-4)
+        (self.m2_luma / (n - 1.0)).max(0.0) / n
+    }
+
+    fn relative_error(&self) -> f32 {
+        self.mean_variance().sqrt() / self.mean_luma.abs().max(1.0e-4)
+    }
+
+    /// Whether adaptive sampling may stop this pixel.
+    fn converged(&self, noise_threshold: f32, min_spp: u32) -> bool {
+        noise_threshold > 0.0 && self.count >= min_spp && self.relative_error() <= noise_threshold
     }
 }
 
-#[allow(dead_code)]
-fn image_from_pixels(pixels: &[PixelAccumulator], width: u32, height: u32) -> RgbImage {
+/// Relative noise of the sampled pixels: the RMS standard error of pixel luminance over the
+/// mean pixel luminance. Infinite until every sampled pixel has two samples.
+fn image_noise(pixels: &[PixelAccumulator]) -> f32 {
+    let sampled = || pixels.iter().filter(|pixel| pixel.count > 0);
+    let n = sampled().count();
+    if n == 0 {
+        return f32::INFINITY;
+    }
+    let variance = sampled().map(PixelAccumulator::mean_variance).sum::<f32>() / n as f32;
+    let luma = sampled().map(|pixel| pixel.mean_luma).sum::<f32>() / n as f32;
+    variance.sqrt() / luma.abs().max(1.0e-4)
+}
+
+/// Denoised linear radiance of the accumulated pixels, guided by primary-hit `features`.
+fn resolve_denoised(
+    pixels: &[PixelAccumulator],
+    features: &[Option<AovSample>],
+    options: RenderOptions,
+) -> Vec<[f32; 3]> {
+    let input = pixels
+        .iter()
+        .zip(features)
+        .map(|(pixel, features)| {
+            let avg = pixel.average();
+            DenoiseInput {
+                color: [avg.r, avg.g, avg.b],
+                // Single-sample pixels have no variance estimate yet; treat them as very noisy.
+                variance: pixel.mean_variance().min(1.0e6),
+                features: *features,
+            }
+        })
+        .collect::<Vec<_>>();
+    denoise::denoise(options.width, options.height, &input)
+}
+
+fn image_from_radiance(radiance: &[[f32; 3]], options: RenderOptions) -> RgbImage {
+    let (width, height) = (options.width, options.height);
     let mut buffer = vec![0_u8; width as usize * height as usize * 3];
-    for (idx, pixel) in pixels.iter().enumerate() {
-        let avg = if pixel.count == 0 {
-            Spectrum::black()
-        } else {
-            pixel.sum.scale(1.0 / pixel.count as f32)
-        };
-        let rgb = spectrum_to_rgb8(avg);
+    for (idx, rgb) in radiance.iter().enumerate() {
+        let x = idx as u32 % width;
+        let y = idx as u32 / width;
+        let rgb = options
+            .display
+            .to_rgb8(Spectrum::rgb(rgb[0], rgb[1], rgb[2]), x, y);
         let i = idx * 3;
         buffer[i] = rgb[0];
         buffer[i + 1] = rgb[1];
@@ -4854,7 +5782,6 @@ fn pixel_sample_offsets(samples: u32) -> Vec<(f32, f32)> {
 }
 
 #[derive(Clone, Copy)]
-#[allow(dead_code)]
 struct BsdfSample {
     wi: Vec3,
     f: Spectrum,
@@ -4863,10 +5790,8 @@ struct BsdfSample {
     apply_cos: bool,
     transmission: bool,
     thin_walled: bool,
-    next_ior: f32,
 }
 
-#[allow(dead_code)]
 fn sample_bsdf_lobe(
     setup: &RenderSetup,
     mat: MaterialKindRt,
@@ -4885,6 +5810,8 @@ fn sample_bsdf_lobe(
         MaterialSampleInput {
             front_face: bsdf_ctx.hit.front_face,
             current_ior: bsdf_ctx.current_ior,
+            interior_ior: bsdf_ctx.interior_ior,
+            exterior_ior: bsdf_ctx.exterior_ior,
             u1,
             u2,
             u3,
@@ -4898,7 +5825,6 @@ fn sample_bsdf_lobe(
         apply_cos: sampled.apply_cos,
         transmission: sampled.transmission,
         thin_walled: sampled.thin_walled,
-        next_ior: sampled.next_ior,
     }
 }
 
@@ -4909,6 +5835,7 @@ fn eval_bsdf(
     wi: Vec3,
 ) -> Spectrum {
     resolve_bsdf_spectrum_hook(setup, mat, "eval", bsdf_context_value(bsdf_ctx, wi, None))
+        .map(|f| lift_rgb(bsdf_ctx.wavelengths, f))
         .unwrap_or_else(|| {
             mat.eval(
                 to_api_vec3(bsdf_ctx.normal),
@@ -4918,7 +5845,6 @@ fn eval_bsdf(
         })
 }
 
-#[allow(dead_code)]
 fn pdf_bsdf(setup: &RenderSetup, mat: MaterialKindRt, bsdf_ctx: BsdfContextBase, wi: Vec3) -> f32 {
     resolve_bsdf_number_hook(setup, mat, "pdf", bsdf_context_value(bsdf_ctx, wi, None))
         .map(|v| v.max(0.0))
@@ -4931,7 +5857,6 @@ fn pdf_bsdf(setup: &RenderSetup, mat: MaterialKindRt, bsdf_ctx: BsdfContextBase,
         })
 }
 
-#[allow(dead_code)]
 fn sample_bsdf_ft(
     setup: &RenderSetup,
     mat: MaterialKindRt,
@@ -4946,7 +5871,10 @@ fn sample_bsdf_ft(
         "sample",
         bsdf_context_value(bsdf_ctx, bsdf_ctx.normal, Some((u1, u2, u3))),
     )?;
-    bsdf_sample_from_value(&value)
+    bsdf_sample_from_value(&value).map(|sample| BsdfSample {
+        f: lift_rgb(bsdf_ctx.wavelengths, sample.f),
+        ..sample
+    })
 }
 
 fn resolve_bsdf_spectrum_hook(
@@ -4963,7 +5891,6 @@ fn resolve_bsdf_spectrum_hook(
     spectrum_from_value(&value)
 }
 
-#[allow(dead_code)]
 fn resolve_bsdf_number_hook(
     setup: &RenderSetup,
     material: MaterialKindRt,
@@ -4981,7 +5908,6 @@ fn resolve_bsdf_number_hook(
     Some(v)
 }
 
-#[allow(dead_code)]
 fn resolve_bsdf_object_hook(
     setup: &RenderSetup,
     material: MaterialKindRt,
@@ -5018,6 +5944,14 @@ fn bsdf_context_value(
         "current_ior".to_string(),
         Value::Number(bsdf_ctx.current_ior),
     );
+    fields.insert(
+        "interior_ior".to_string(),
+        Value::Number(bsdf_ctx.interior_ior),
+    );
+    fields.insert(
+        "exterior_ior".to_string(),
+        Value::Number(bsdf_ctx.exterior_ior),
+    );
     if let Some((u1, u2, u3)) = sample_randoms {
         fields.insert("u1".to_string(), Value::Number(u1));
         fields.insert("u2".to_string(), Value::Number(u2));
@@ -5029,7 +5963,6 @@ fn bsdf_context_value(
     })
 }
 
-#[allow(dead_code)]
 fn bsdf_sample_from_value(value: &Value) -> Option<BsdfSample> {
     let Value::Object(obj) = value else {
         return None;
@@ -5045,9 +5978,6 @@ fn bsdf_sample_from_value(value: &Value) -> Option<BsdfSample> {
     let apply_cos = read_number_field(obj, &["apply_cos"]).unwrap_or(1.0) >= 0.5;
     let transmission = read_number_field(obj, &["transmission"]).unwrap_or(0.0) >= 0.5;
     let thin_walled = read_number_field(obj, &["thin_walled"]).unwrap_or(0.0) >= 0.5;
-    let next_ior = read_number_field(obj, &["next_ior"])
-        .unwrap_or(1.0)
-        .clamp(1.0, 3.0);
     Some(BsdfSample {
         wi,
         f,
@@ -5056,7 +5986,6 @@ fn bsdf_sample_from_value(value: &Value) -> Option<BsdfSample> {
         apply_cos,
         transmission,
         thin_walled,
-        next_ior,
     })
 }
 
@@ -5094,14 +6023,12 @@ fn refract(incident: Vec3, normal: Vec3, eta: f32) -> Option<Vec3> {
     Some(i.mul(eta).add(n.mul(eta * cosi - cost)).normalize())
 }
 
-#[allow(dead_code)]
 fn power_heuristic(pa: f32, pb: f32) -> f32 {
     let a2 = pa * pa;
     let b2 = pb * pb;
     a2 / (a2 + b2).max(1.0e-6)
 }
 
-#[allow(dead_code)]
 fn spectrum_luminance(s: Spectrum) -> f32 {
     0.2126 * s.r + 0.7152 * s.g + 0.0722 * s.b
 }
@@ -5122,49 +6049,101 @@ fn apply_medium_attenuation(color: Spectrum, medium: MediumState, distance: f32)
     color * medium_transmittance(medium, distance)
 }
 
-fn medium_state_from_material(material: MaterialKindRt) -> Option<MediumState> {
+fn is_transmissive(material: MaterialKindRt) -> bool {
+    match dominant_material_model(material) {
+        MaterialKindTag::Dielectric => true,
+        MaterialKindTag::Standard | MaterialKindTag::OpenPbr => {
+            dominant_material_params(material).transmission > 0.01
+        }
+        MaterialKindTag::Lambert | MaterialKindTag::Metal => false,
+    }
+}
+
+/// The medium filling a transmissive material. Light refracts by the material's `ior`, so
+/// that replaces the one on its `medium`.
+fn material_medium(material: MaterialKindRt) -> Option<MediumParams> {
+    if !is_transmissive(material) {
+        return None;
+    }
     let params = dominant_material_params(material);
-    let explicit = params.medium.map(|medium| MediumState {
-        ior: medium.ior.clamp(1.0, 3.0),
-        absorption_color: medium.absorption_color,
-        density: medium.density.max(0.0),
-    });
-    explicit.or_else(|| {
-        let transmissive =
-            matches!(
-                dominant_material_model(material),
-                MaterialKindTag::Dielectric
-            ) || (matches!(dominant_material_model(material), MaterialKindTag::Standard)
-                && params.transmission > 0.01);
-        transmissive.then_some(MediumState {
-            ior: params.ior.clamp(1.0, 3.0),
-            absorption_color: Spectrum::rgb(1.0, 1.0, 1.0),
-            density: 0.0,
-        })
+    Some(match params.medium {
+        Some(medium) => MediumParams {
+            ior: Some(medium.ior.unwrap_or(params.ior).clamp(1.0, 3.0)),
+            density: medium.density.max(0.0),
+            scattering: medium.scattering.max(0.0),
+            anisotropy: medium.anisotropy.clamp(-0.95, 0.95),
+            ..medium
+        },
+        None => MediumParams::new(
+            params.ior.clamp(1.0, 3.0),
+            Spectrum::rgb(1.0, 1.0, 1.0),
+            0.0,
+        ),
     })
 }
 
-fn transition_medium(
-    material: MaterialKindRt,
-    front_face: bool,
-    current: MediumState,
-) -> MediumState {
-    let transmissive = matches!(
-        dominant_material_model(material),
-        MaterialKindTag::Dielectric
-    ) || matches!(dominant_material_model(material), MaterialKindTag::Standard)
-        && dominant_material_params(material).transmission > 0.01;
-    if !transmissive {
+/// The medium on the far side of a transmissive surface the ray crosses at `hit`.
+fn transition_medium(material: MaterialKindRt, hit: RayHit, current: MediumState) -> MediumState {
+    let Some(medium) = material_medium(material) else {
         return current;
-    }
-    if front_face {
-        medium_state_from_material(material).unwrap_or(current)
+    };
+    if hit.front_face {
+        current.entered(MediumEntry {
+            material_id: hit.material_id,
+            priority: dominant_material_params(material).priority,
+            medium,
+        })
     } else {
-        MediumState::air()
+        current.exited(hit.material_id)
     }
 }
 
-#[allow(dead_code)]
+/// Marches to the next surface within `max_t` that bounds the ray's medium. Inside a medium
+/// that includes the surfaces of shapes nested in it; those of media that a higher-priority
+/// medium around the ray overrides are crossed on the way and update the returned medium.
+/// The hit's `t` stays measured from `origin`.
+#[allow(clippy::too_many_arguments)]
+fn raymarch_medium_hit(
+    accel: &(impl Accelerator + Sync),
+    setup: &RenderSetup,
+    options: RenderOptions,
+    origin: Vec3,
+    dir: Vec3,
+    min_t: f32,
+    max_t: f32,
+    mut medium: MediumState,
+    time: f32,
+) -> (Option<RayHit>, MediumState) {
+    let mut start = origin;
+    let mut travelled = 0.0;
+    let mut min_t = min_t;
+    for _ in 0..MAX_MEDIUM_CROSSINGS {
+        let hit = if medium.active().is_some() {
+            raymarch_nested_hit(accel, start, dir, options, min_t, max_t - travelled, time)
+        } else {
+            raymarch_hit(accel, start, dir, options, min_t, max_t - travelled, time)
+        };
+        let Some(mut hit) = hit else {
+            return (None, medium);
+        };
+        let material = medium
+            .active()
+            .map(|_| resolve_material_at_hit(setup, hit, dir.mul(-1.0).normalize()));
+        match material.filter(|material| medium.is_false_interface(*material, hit)) {
+            Some(material) => medium = transition_medium(material, hit, medium),
+            None => {
+                hit.t += travelled;
+                return (Some(hit), medium);
+            }
+        }
+        let next = offset_ray_origin(hit.position, hit.normal, dir, options.epsilon);
+        travelled += next.sub(start).length();
+        start = next;
+        min_t = secondary_min_t(options.epsilon);
+    }
+    (None, medium)
+}
+
 fn clamp_spectrum(s: Spectrum, max_luma: f32) -> Spectrum {
     let l = spectrum_luminance(s);
     if l <= max_luma || l <= 1.0e-6 {
@@ -5226,19 +6205,6 @@ fn spectrum_to_rgb8(s: Spectrum) -> [u8; 3] {
     [to_u8(s.r), to_u8(s.g), to_u8(s.b)]
 }
 
-fn spectrum_to_rgb8_reinhard(s: Spectrum) -> [u8; 3] {
-    fn tone(v: f32) -> f32 {
-        let x = v.max(0.0);
-        x / (1.0 + x)
-    }
-    fn to_u8(v: f32) -> u8 {
-        let mapped = tone(v).powf(1.0 / 2.2).min(1.0);
-        (mapped * 255.0) as u8
-    }
-    [to_u8(s.r), to_u8(s.g), to_u8(s.b)]
-}
-
-#[allow(dead_code)]
 fn sdf_bounds(node: &SdfNode) -> Aabb {
     match node {
         SdfNode::Sphere {
@@ -5380,7 +6346,8 @@ fn sdf_bounds(node: &SdfNode) -> Aabb {
         }
         SdfNode::DomainModifier { bounds, .. }
         | SdfNode::DistancePostModifier { bounds, .. }
-        | SdfNode::Noise { bounds, .. } => *bounds,
+        | SdfNode::Noise { bounds, .. }
+        | SdfNode::Motion { bounds, .. } => *bounds,
         SdfNode::Smooth { base, k } => sdf_bounds(base).expand(*k * 0.1),
     }
 }
@@ -5537,12 +6504,13 @@ fn sdf_lower_bound(node: &SdfNode, p: Vec3) -> f32 {
         | SdfNode::Slice { .. }
         | SdfNode::DomainModifier { .. }
         | SdfNode::DistancePostModifier { .. }
-        | SdfNode::Noise { .. } => point_aabb_lower_bound(p, sdf_bounds(node)),
+        | SdfNode::Noise { .. }
+        | SdfNode::Motion { .. } => point_aabb_lower_bound(p, sdf_bounds(node)),
         SdfNode::Smooth { base, k } => sdf_lower_bound(base, p) - *k * 0.1,
     }
 }
 
-fn sdf_distance_info(node: &SdfNode, p: Vec3) -> DistanceInfo {
+fn sdf_distance_info(node: &SdfNode, p: Vec3, time: f32) -> DistanceInfo {
     match node {
         SdfNode::Sphere {
             transform,
@@ -5682,7 +6650,7 @@ fn sdf_distance_info(node: &SdfNode, p: Vec3) -> DistanceInfo {
             ..
         } => {
             let q = to_local(p, *transform);
-            sdf_distance_info(base, eval_modifier_domain(runtime, q))
+            sdf_distance_info(base, eval_modifier_domain(runtime, q), time)
         }
         SdfNode::DistancePostModifier {
             base,
@@ -5691,7 +6659,7 @@ fn sdf_distance_info(node: &SdfNode, p: Vec3) -> DistanceInfo {
             ..
         } => {
             let q = to_local(p, *transform);
-            let mut info = sdf_distance_info(base, q);
+            let mut info = sdf_distance_info(base, q, time);
             info.distance = eval_modifier_distance_post(runtime, info.distance, q);
             info
         }
@@ -5704,7 +6672,7 @@ fn sdf_distance_info(node: &SdfNode, p: Vec3) -> DistanceInfo {
             ..
         } => {
             let q = to_local(p, *transform);
-            let mut info = sdf_distance_info(base, q);
+            let mut info = sdf_distance_info(base, q, time);
             info.distance = apply_noise_modifier(q, info.distance, *octaves, *scale, *lacunarity);
             info
         }
@@ -5712,31 +6680,31 @@ fn sdf_distance_info(node: &SdfNode, p: Vec3) -> DistanceInfo {
             let lhs_lb = sdf_lower_bound(lhs, p);
             let rhs_lb = sdf_lower_bound(rhs, p);
             if lhs_lb <= rhs_lb {
-                let l = sdf_distance_info(lhs, p);
+                let l = sdf_distance_info(lhs, p, time);
                 if l.distance <= rhs_lb {
                     l
                 } else {
-                    let r = sdf_distance_info(rhs, p);
+                    let r = sdf_distance_info(rhs, p, time);
                     if l.distance <= r.distance { l } else { r }
                 }
             } else {
-                let r = sdf_distance_info(rhs, p);
+                let r = sdf_distance_info(rhs, p, time);
                 if r.distance <= lhs_lb {
                     r
                 } else {
-                    let l = sdf_distance_info(lhs, p);
+                    let l = sdf_distance_info(lhs, p, time);
                     if l.distance <= r.distance { l } else { r }
                 }
             }
         }
         SdfNode::Intersect { lhs, rhs } => {
-            let l = sdf_distance_info(lhs, p);
-            let r = sdf_distance_info(rhs, p);
+            let l = sdf_distance_info(lhs, p, time);
+            let r = sdf_distance_info(rhs, p, time);
             if l.distance >= r.distance { l } else { r }
         }
         SdfNode::Subtract { lhs, rhs } => {
-            let l = sdf_distance_info(lhs, p);
-            let r = sdf_distance_info(rhs, p);
+            let l = sdf_distance_info(lhs, p, time);
+            let r = sdf_distance_info(rhs, p, time);
             let rd = -r.distance;
             if l.distance >= rd {
                 l
@@ -5749,8 +6717,8 @@ fn sdf_distance_info(node: &SdfNode, p: Vec3) -> DistanceInfo {
             }
         }
         SdfNode::UnionRound { lhs, rhs, r } => {
-            let l = sdf_distance_info(lhs, p);
-            let r_info = sdf_distance_info(rhs, p);
+            let l = sdf_distance_info(lhs, p, time);
+            let r_info = sdf_distance_info(rhs, p, time);
             let distance = op_union_round(l.distance, r_info.distance, *r);
             if l.distance <= r_info.distance {
                 DistanceInfo { distance, ..l }
@@ -5759,8 +6727,8 @@ fn sdf_distance_info(node: &SdfNode, p: Vec3) -> DistanceInfo {
             }
         }
         SdfNode::UnionChamfer { lhs, rhs, r } => {
-            let l = sdf_distance_info(lhs, p);
-            let r_info = sdf_distance_info(rhs, p);
+            let l = sdf_distance_info(lhs, p, time);
+            let r_info = sdf_distance_info(rhs, p, time);
             let distance = op_union_chamfer(l.distance, r_info.distance, *r);
             if l.distance <= r_info.distance {
                 DistanceInfo { distance, ..l }
@@ -5769,8 +6737,8 @@ fn sdf_distance_info(node: &SdfNode, p: Vec3) -> DistanceInfo {
             }
         }
         SdfNode::UnionColumns { lhs, rhs, r, n } => {
-            let l = sdf_distance_info(lhs, p);
-            let r_info = sdf_distance_info(rhs, p);
+            let l = sdf_distance_info(lhs, p, time);
+            let r_info = sdf_distance_info(rhs, p, time);
             let distance = op_union_columns(l.distance, r_info.distance, *r, *n);
             if l.distance <= r_info.distance {
                 DistanceInfo { distance, ..l }
@@ -5779,8 +6747,8 @@ fn sdf_distance_info(node: &SdfNode, p: Vec3) -> DistanceInfo {
             }
         }
         SdfNode::UnionStairs { lhs, rhs, r, n } => {
-            let l = sdf_distance_info(lhs, p);
-            let r_info = sdf_distance_info(rhs, p);
+            let l = sdf_distance_info(lhs, p, time);
+            let r_info = sdf_distance_info(rhs, p, time);
             let distance = op_union_stairs(l.distance, r_info.distance, *r, *n);
             if l.distance <= r_info.distance {
                 DistanceInfo { distance, ..l }
@@ -5789,8 +6757,8 @@ fn sdf_distance_info(node: &SdfNode, p: Vec3) -> DistanceInfo {
             }
         }
         SdfNode::UnionSoft { lhs, rhs, r } => {
-            let l = sdf_distance_info(lhs, p);
-            let r_info = sdf_distance_info(rhs, p);
+            let l = sdf_distance_info(lhs, p, time);
+            let r_info = sdf_distance_info(rhs, p, time);
             let distance = op_union_soft(l.distance, r_info.distance, *r);
             if l.distance <= r_info.distance {
                 DistanceInfo { distance, ..l }
@@ -5799,8 +6767,8 @@ fn sdf_distance_info(node: &SdfNode, p: Vec3) -> DistanceInfo {
             }
         }
         SdfNode::IntersectRound { lhs, rhs, r } => {
-            let l = sdf_distance_info(lhs, p);
-            let r_info = sdf_distance_info(rhs, p);
+            let l = sdf_distance_info(lhs, p, time);
+            let r_info = sdf_distance_info(rhs, p, time);
             let distance = op_intersect_round(l.distance, r_info.distance, *r);
             if l.distance >= r_info.distance {
                 DistanceInfo { distance, ..l }
@@ -5809,8 +6777,8 @@ fn sdf_distance_info(node: &SdfNode, p: Vec3) -> DistanceInfo {
             }
         }
         SdfNode::IntersectChamfer { lhs, rhs, r } => {
-            let l = sdf_distance_info(lhs, p);
-            let r_info = sdf_distance_info(rhs, p);
+            let l = sdf_distance_info(lhs, p, time);
+            let r_info = sdf_distance_info(rhs, p, time);
             let distance = op_intersect_chamfer(l.distance, r_info.distance, *r);
             if l.distance >= r_info.distance {
                 DistanceInfo { distance, ..l }
@@ -5819,8 +6787,8 @@ fn sdf_distance_info(node: &SdfNode, p: Vec3) -> DistanceInfo {
             }
         }
         SdfNode::IntersectColumns { lhs, rhs, r, n } => {
-            let l = sdf_distance_info(lhs, p);
-            let r_info = sdf_distance_info(rhs, p);
+            let l = sdf_distance_info(lhs, p, time);
+            let r_info = sdf_distance_info(rhs, p, time);
             let distance = op_intersect_columns(l.distance, r_info.distance, *r, *n);
             if l.distance >= r_info.distance {
                 DistanceInfo { distance, ..l }
@@ -5829,8 +6797,8 @@ fn sdf_distance_info(node: &SdfNode, p: Vec3) -> DistanceInfo {
             }
         }
         SdfNode::IntersectStairs { lhs, rhs, r, n } => {
-            let l = sdf_distance_info(lhs, p);
-            let r_info = sdf_distance_info(rhs, p);
+            let l = sdf_distance_info(lhs, p, time);
+            let r_info = sdf_distance_info(rhs, p, time);
             let distance = op_intersect_stairs(l.distance, r_info.distance, *r, *n);
             if l.distance >= r_info.distance {
                 DistanceInfo { distance, ..l }
@@ -5839,8 +6807,8 @@ fn sdf_distance_info(node: &SdfNode, p: Vec3) -> DistanceInfo {
             }
         }
         SdfNode::DiffRound { lhs, rhs, r } => {
-            let l = sdf_distance_info(lhs, p);
-            let r_info = sdf_distance_info(rhs, p);
+            let l = sdf_distance_info(lhs, p, time);
+            let r_info = sdf_distance_info(rhs, p, time);
             let rd = -r_info.distance;
             let distance = op_diff_round(l.distance, r_info.distance, *r);
             if l.distance >= rd {
@@ -5854,8 +6822,8 @@ fn sdf_distance_info(node: &SdfNode, p: Vec3) -> DistanceInfo {
             }
         }
         SdfNode::DiffChamfer { lhs, rhs, r } => {
-            let l = sdf_distance_info(lhs, p);
-            let r_info = sdf_distance_info(rhs, p);
+            let l = sdf_distance_info(lhs, p, time);
+            let r_info = sdf_distance_info(rhs, p, time);
             let rd = -r_info.distance;
             let distance = op_diff_chamfer(l.distance, r_info.distance, *r);
             if l.distance >= rd {
@@ -5869,8 +6837,8 @@ fn sdf_distance_info(node: &SdfNode, p: Vec3) -> DistanceInfo {
             }
         }
         SdfNode::DiffColumns { lhs, rhs, r, n } => {
-            let l = sdf_distance_info(lhs, p);
-            let r_info = sdf_distance_info(rhs, p);
+            let l = sdf_distance_info(lhs, p, time);
+            let r_info = sdf_distance_info(rhs, p, time);
             let rd = -r_info.distance;
             let distance = op_diff_columns(l.distance, r_info.distance, *r, *n);
             if l.distance >= rd {
@@ -5884,8 +6852,8 @@ fn sdf_distance_info(node: &SdfNode, p: Vec3) -> DistanceInfo {
             }
         }
         SdfNode::DiffStairs { lhs, rhs, r, n } => {
-            let l = sdf_distance_info(lhs, p);
-            let r_info = sdf_distance_info(rhs, p);
+            let l = sdf_distance_info(lhs, p, time);
+            let r_info = sdf_distance_info(rhs, p, time);
             let rd = -r_info.distance;
             let distance = op_diff_stairs(l.distance, r_info.distance, *r, *n);
             if l.distance >= rd {
@@ -5899,32 +6867,32 @@ fn sdf_distance_info(node: &SdfNode, p: Vec3) -> DistanceInfo {
             }
         }
         SdfNode::Pipe { lhs, rhs, r } => {
-            let l = sdf_distance_info(lhs, p);
-            let r_info = sdf_distance_info(rhs, p);
+            let l = sdf_distance_info(lhs, p, time);
+            let r_info = sdf_distance_info(rhs, p, time);
             DistanceInfo {
                 distance: op_pipe(l.distance, r_info.distance, *r),
                 ..l
             }
         }
         SdfNode::Engrave { lhs, rhs, r } => {
-            let l = sdf_distance_info(lhs, p);
-            let r_info = sdf_distance_info(rhs, p);
+            let l = sdf_distance_info(lhs, p, time);
+            let r_info = sdf_distance_info(rhs, p, time);
             DistanceInfo {
                 distance: op_engrave(l.distance, r_info.distance, *r),
                 ..l
             }
         }
         SdfNode::Groove { lhs, rhs, ra, rb } => {
-            let l = sdf_distance_info(lhs, p);
-            let r_info = sdf_distance_info(rhs, p);
+            let l = sdf_distance_info(lhs, p, time);
+            let r_info = sdf_distance_info(rhs, p, time);
             DistanceInfo {
                 distance: op_groove(l.distance, r_info.distance, *ra, *rb),
                 ..l
             }
         }
         SdfNode::Tongue { lhs, rhs, ra, rb } => {
-            let l = sdf_distance_info(lhs, p);
-            let r_info = sdf_distance_info(rhs, p);
+            let l = sdf_distance_info(lhs, p, time);
+            let r_info = sdf_distance_info(rhs, p, time);
             DistanceInfo {
                 distance: op_tongue(l.distance, r_info.distance, *ra, *rb),
                 ..l
@@ -5936,7 +6904,7 @@ fn sdf_distance_info(node: &SdfNode, p: Vec3) -> DistanceInfo {
             min,
             max,
         } => {
-            let mut info = sdf_distance_info(base, p);
+            let mut info = sdf_distance_info(base, p, time);
             let coord = match axis {
                 0 => p.x,
                 1 => p.y,
@@ -5947,10 +6915,13 @@ fn sdf_distance_info(node: &SdfNode, p: Vec3) -> DistanceInfo {
             info
         }
         SdfNode::Smooth { base, k } => {
-            let mut info = sdf_distance_info(base, p);
+            let mut info = sdf_distance_info(base, p, time);
             info.distance -= *k * 0.1;
             info
         }
+        SdfNode::Motion {
+            base, rest, motion, ..
+        } => sdf_distance_info(base, motion_point(p, *rest, motion, time), time),
     }
 }
 
@@ -6239,25 +7210,104 @@ fn rotate_z(v: Vec3, deg: f32) -> Vec3 {
     Vec3::new(c * v.x - s * v.y, s * v.x + c * v.y, v.z)
 }
 
+/// Default trace-mode shadow rays per hit toward an environment map.
+const DEFAULT_ENV_SAMPLES: u32 = 16;
+
 fn build_render_setup(
     state: &EvalState,
     scene: &CompiledScene,
     options: RenderOptions,
-) -> RenderSetup {
+) -> Result<RenderSetup, RenderError> {
     let camera = parse_camera(state, scene.center, options);
-    let (lights, path_lights) = parse_lights(state, &scene.semantic_lights);
-    RenderSetup {
+    let settings = extract_scene_render_settings(state);
+    let hdri = parse_hdri(state)?;
+    // A loaded map replaces the procedural environment; otherwise the procedural one is
+    // tabulated, when `env_resolution` asks for it, so it can be sampled as a light.
+    let environment_name = hdri
+        .is_none()
+        .then(|| find_environment_name(state))
+        .flatten();
+    let env_map = hdri
+        .or_else(|| {
+            let name = environment_name.as_deref()?;
+            let rows = settings.env_resolution.filter(|&rows| rows > 0)?;
+            Some(HdriMap::tabulate(rows as usize, |dir| {
+                procedural_environment_color(state, name, dir).unwrap_or(Spectrum::black())
+            }))
+        })
+        .map(Arc::new);
+    let env_samples = settings.env_samples.unwrap_or(DEFAULT_ENV_SAMPLES);
+    let (lights, path_lights) = parse_lights(
+        state,
+        &scene.semantic_lights,
+        env_map.as_ref().map(|map| (map, env_samples)),
+    );
+    // Lights at infinity reach no farther than the scene does, or than rays travel.
+    let scene_radius = (sdf_bounds(&scene.root).extent().length() * 0.5).min(options.max_dist);
+    let light_sampler = LightSampler::new(&path_lights, env_map.as_deref(), scene_radius);
+    Ok(RenderSetup {
         state: state.clone(),
         root: scene.root.clone(),
         camera,
         lights,
         path_lights,
+        light_sampler,
         materials: scene.materials.clone(),
         object_transforms: scene.object_transforms.clone(),
         material_def_names: sorted_material_def_names(state),
         dynamic_material_overrides: scene.dynamic_material_overrides.clone(),
-        environment_name: find_environment_name(state),
-    }
+        environment_name,
+        env_map,
+        fog: parse_fog(state),
+        volumes: scene.volumes.clone(),
+    })
+}
+
+/// Loads the map of the first `HdriEnvironment` binding.
+fn parse_hdri(state: &EvalState) -> Result<Option<HdriMap>, RenderError> {
+    let Some((obj, path)) = hdri_environment(state)? else {
+        return Ok(None);
+    };
+    let rotation = read_number_field(obj, &["rotation"]).unwrap_or(0.0);
+    let intensity = read_number_field(obj, &["intensity"])
+        .unwrap_or(1.0)
+        .max(0.0);
+    HdriMap::load(&path, rotation, intensity).map(Some)
+}
+
+/// Files besides the scene source that a render reads: the `HdriEnvironment` map.
+pub(crate) fn scene_asset_paths(state: &EvalState) -> Vec<PathBuf> {
+    hdri_environment(state)
+        .ok()
+        .flatten()
+        .map(|(_, path)| path)
+        .into_iter()
+        .collect()
+}
+
+/// The first `HdriEnvironment` binding, with its `path` resolved against the scene's directory.
+fn hdri_environment(state: &EvalState) -> Result<Option<(&ObjectValue, PathBuf)>, RenderError> {
+    let mut bindings = state.bindings.iter().collect::<Vec<_>>();
+    bindings.sort_unstable_by_key(|(name, _)| name.as_str());
+    let Some(obj) = bindings
+        .into_iter()
+        .find_map(|(_, binding)| match &binding.value {
+            Value::Object(obj) if obj.type_name.as_deref() == Some("HdriEnvironment") => Some(obj),
+            _ => None,
+        })
+    else {
+        return Ok(None);
+    };
+    let Some(Value::String(path)) = obj.fields.get("path") else {
+        return Err(RenderError::Environment(
+            "HdriEnvironment needs a `path` string".to_string(),
+        ));
+    };
+    let path = match &state.base_dir {
+        Some(dir) if Path::new(path).is_relative() => dir.join(path),
+        _ => PathBuf::from(path),
+    };
+    Ok(Some((obj, path)))
 }
 
 fn parse_camera(state: &EvalState, scene_center: Vec3, options: RenderOptions) -> CameraKind {
@@ -6268,30 +7318,190 @@ fn parse_camera(state: &EvalState, scene_center: Vec3, options: RenderOptions) -
             .or_else(|| read_vec3_field(camera_obj, "position"))
             .unwrap_or_else(|| scene_center.add(Vec3::new(0.0, 0.0, options.camera_z)));
         let target = read_vec3_field(camera_obj, "target").unwrap_or(scene_center);
-        let fov = read_number_field(camera_obj, &["fov_y", "fov"]).unwrap_or(options.fov_y_degrees);
-        return CameraKind::Pinhole(PinholeCamera {
+        if let Some(poses) = read_camera_motion(state, camera_obj, origin, target, options.shutter)
+        {
+            return CameraKind::Motion(MotionCamera {
+                keys: poses
+                    .into_iter()
+                    .map(|(origin, target)| camera_from_object(camera_obj, origin, target, options))
+                    .collect(),
+                shutter_open: options.shutter.open,
+                shutter_close: options.shutter.close,
+            });
+        }
+        return camera_from_object(camera_obj, origin, target, options);
+    }
+
+    CameraKind::Pinhole(PinholeCamera {
+        origin: to_api_vec3(scene_center.add(Vec3::new(0.0, 0.0, options.camera_z))),
+        target: to_api_vec3(scene_center),
+        up: ApiVec3::new(0.0, 1.0, 0.0),
+        fov_y_degrees: options.fov_y_degrees,
+    })
+}
+
+/// `(origin, target)` poses across the shutter from `origin_open`/`origin_close`,
+/// `target_open`/`target_close`, or a `motion(t)` hook returning an object with `origin`
+/// and/or `target` (or just the origin as a vec3). `None` keeps the camera static.
+fn read_camera_motion(
+    state: &EvalState,
+    camera_obj: &ObjectValue,
+    origin: Vec3,
+    target: Vec3,
+    shutter: Shutter,
+) -> Option<Vec<(Vec3, Vec3)>> {
+    if let Some(Value::Function(hook)) = camera_obj.fields.get("motion") {
+        let poses = motion::motion_hook_times(shutter)
+            .into_iter()
+            .map(|time| {
+                let pose = eval_function_value(state, hook, &[Value::Number(time)]).ok();
+                camera_pose_from_value(pose, origin, target)
+            })
+            .collect();
+        return Some(poses);
+    }
+    let keyed = ["origin_open", "origin_close", "target_open", "target_close"]
+        .iter()
+        .any(|name| camera_obj.fields.contains_key(*name));
+    keyed.then(|| {
+        let pose = |origin_field: &str, target_field: &str| {
+            (
+                read_vec3_field(camera_obj, origin_field).unwrap_or(origin),
+                read_vec3_field(camera_obj, target_field).unwrap_or(target),
+            )
+        };
+        vec![
+            pose("origin_open", "target_open"),
+            pose("origin_close", "target_close"),
+        ]
+    })
+}
+
+fn camera_pose_from_value(value: Option<Value>, origin: Vec3, target: Vec3) -> (Vec3, Vec3) {
+    match value {
+        Some(Value::Object(pose))
+            if pose.fields.contains_key("origin") || pose.fields.contains_key("target") =>
+        {
+            (
+                read_vec3_field(&pose, "origin").unwrap_or(origin),
+                read_vec3_field(&pose, "target").unwrap_or(target),
+            )
+        }
+        Some(value) => (value_as_vec3(&value).unwrap_or(origin), target),
+        None => (origin, target),
+    }
+}
+
+fn camera_from_object(
+    camera_obj: &ObjectValue,
+    origin: Vec3,
+    target: Vec3,
+    options: RenderOptions,
+) -> CameraKind {
+    let fov = read_number_field(camera_obj, &["fov_y", "fov"]).unwrap_or(options.fov_y_degrees);
+    let aspect = options.width as f32 / options.height.max(1) as f32;
+    match read_type_name_field(camera_obj, "projection").as_deref() {
+        Some("ortho" | "orthographic") => {
+            // Without an explicit height, match the perspective framing at the target.
+            let ortho_height =
+                read_number_field(camera_obj, &["ortho_height"]).unwrap_or_else(|| {
+                    2.0 * target.sub(origin).length() * (0.5 * fov.to_radians()).tan()
+                });
+            return CameraKind::Orthographic(OrthographicCamera {
+                origin: to_api_vec3(origin),
+                target: to_api_vec3(target),
+                up: ApiVec3::new(0.0, 1.0, 0.0),
+                ortho_height: ortho_height.max(1.0e-4),
+            });
+        }
+        Some("equirect" | "equirectangular" | "latlong") => {
+            return CameraKind::Equirect(EquirectCamera {
+                origin: to_api_vec3(origin),
+                target: to_api_vec3(target),
+                up: ApiVec3::new(0.0, 1.0, 0.0),
+                aspect,
+                stereo: read_number_field(camera_obj, &["stereo"]).is_some_and(|v| v != 0.0),
+                eye_separation: read_number_field(camera_obj, &["eye_separation", "ipd"])
+                    .unwrap_or(0.064),
+            });
+        }
+        Some("cubemap" | "cube") => {
+            return CameraKind::Cubemap(CubemapCamera {
+                origin: to_api_vec3(origin),
+                target: to_api_vec3(target),
+                up: ApiVec3::new(0.0, 1.0, 0.0),
+                aspect,
+            });
+        }
+        _ => {}
+    }
+    if let Some(aperture_radius) = read_aperture_radius(camera_obj, fov) {
+        let forward = target.sub(origin).normalize();
+        let focus_distance = read_focus_distance(camera_obj, origin, forward)
+            .unwrap_or_else(|| target.sub(origin).dot(forward));
+        return CameraKind::ThinLens(ThinLensCamera {
             origin: to_api_vec3(origin),
             target: to_api_vec3(target),
             up: ApiVec3::new(0.0, 1.0, 0.0),
             fov_y_degrees: fov,
+            aperture_radius,
+            focus_distance: focus_distance.max(1.0e-3),
+            blades: read_number_field(camera_obj, &["blades"])
+                .map(|v| v.max(0.0) as u32)
+                .unwrap_or(0),
+            blade_rotation_degrees: read_number_field(camera_obj, &["blade_rotation"])
+                .unwrap_or(0.0),
         });
     }
-
     CameraKind::Pinhole(PinholeCamera {
-        origin: to_api_vec3(scene_center.add(Vec3::new(0.0, 0.0, options.camera_z))),
-        target: to_api_vec3(scene_center),
+        origin: to_api_vec3(origin),
+        target: to_api_vec3(target),
         up: ApiVec3::new(0.0, 1.0, 0.0),
-        fov_y_degrees: options.fov_y_degrees,
+        fov_y_degrees: fov,
     })
 }
 
+/// Full-frame sensor height in scene units, assuming scenes are modeled in meters.
+const SENSOR_HEIGHT: f32 = 0.024;
+
+/// Lens radius from `aperture` (radius in scene units) or `f_stop`, which derives the focal
+/// length from the vertical fov on a full-frame sensor. `None` keeps the pinhole camera.
+fn read_aperture_radius(camera_obj: &ObjectValue, fov_y_degrees: f32) -> Option<f32> {
+    let radius = read_number_field(camera_obj, &["aperture", "aperture_radius"]).or_else(|| {
+        let f_stop = read_number_field(camera_obj, &["f_stop", "fstop"])?;
+        let focal_length = 0.5 * SENSOR_HEIGHT / (0.5 * fov_y_degrees.to_radians()).tan();
+        Some(focal_length / (2.0 * f_stop.max(0.1)))
+    })?;
+    (radius > 0.0).then_some(radius)
+}
+
+/// Focus distance along the view axis from `focus_distance`, or from `focus` given as a
+/// distance, a point, or an object or part whose bounds center should be sharp.
+fn read_focus_distance(camera_obj: &ObjectValue, origin: Vec3, forward: Vec3) -> Option<f32> {
+    if let Some(distance) = read_number_field(camera_obj, &["focus_distance"]) {
+        return Some(distance);
+    }
+    let focus = camera_obj.fields.get("focus")?;
+    if let Value::Number(distance) = focus {
+        return Some(*distance);
+    }
+    let point = value_as_vec3(focus)
+        .or_else(|| object_center(focus).map(|[x, y, z]| Vec3::new(x, y, z)))?;
+    Some(point.sub(origin).dot(forward))
+}
+
 fn parse_lights(
     state: &EvalState,
     semantic_lights: &[SemanticLight],
+    env_map: Option<(&Arc<HdriMap>, u32)>,
 ) -> (Vec<Box<dyn Light>>, Vec<PathLight>) {
     let mut lights: Vec<Box<dyn Light>> = Vec::new();
     let mut path_lights: Vec<PathLight> = Vec::new();
-    for binding in state.bindings.values() {
+    // Walk bindings by name so every process builds the same light list; light selection
+    // consumes random numbers, and shards or resumed renders must share sample sequences.
+    let mut bindings = state.bindings.iter().collect::<Vec<_>>();
+    bindings.sort_unstable_by_key(|(name, _)| name.as_str());
+    for (_, binding) in bindings {
         let Value::Object(obj) = &binding.value else {
             continue;
         };
@@ -6329,32 +7539,131 @@ fn parse_lights(
                 let samples = read_number_field(obj, &["samples"])
                     .map(|v| v.max(1.0) as u32)
                     .unwrap_or(8);
-                lights.push(Box::new(SphereLight {
+                let light = SphereLight {
                     position: to_api_vec3(position),
                     radius,
                     intensity,
                     samples,
-                }));
-                path_lights.push(PathLight::Point {
+                };
+                lights.push(Box::new(light));
+                path_lights.push(sphere_path_light(light));
+            }
+            "DirectionalLight" => {
+                let light = DirectionalLight {
+                    direction: to_api_vec3(read_light_direction(
+                        obj,
+                        "direction",
+                        Vec3::new(0.0, 0.0, 0.0),
+                        Vec3::new(-0.4, -1.0, -0.3),
+                    )),
+                    angle: read_number_field(obj, &["angle", "angular_diameter"])
+                        .unwrap_or(0.53)
+                        .clamp(0.0, 90.0)
+                        .to_radians(),
+                    irradiance: read_light_spectrum(obj, "color", "intensity", &["intensity"])
+                        .unwrap_or(Spectrum::rgb(3.0, 3.0, 3.0)),
+                    samples: read_light_samples(obj),
+                };
+                lights.push(Box::new(light));
+                path_lights.push(PathLight::Directional(light));
+            }
+            "SpotLight" => {
+                let position = read_vec3_field(obj, "position").unwrap_or_else(|| read_center(obj));
+                let light = SpotLight {
                     position: to_api_vec3(position),
-                    intensity,
-                });
+                    direction: to_api_vec3(read_light_direction(
+                        obj,
+                        "direction",
+                        position,
+                        Vec3::new(0.0, -1.0, 0.0),
+                    )),
+                    intensity: read_light_spectrum(obj, "color", "intensity", &["intensity"])
+                        .unwrap_or(Spectrum::rgb(8.0, 8.0, 8.0)),
+                    angle: read_number_field(obj, &["angle", "cone_angle"])
+                        .unwrap_or(45.0)
+                        .clamp(0.0, 180.0)
+                        .to_radians(),
+                    blend: read_number_field(obj, &["falloff", "blend"])
+                        .unwrap_or(0.2)
+                        .clamp(0.0, 1.0),
+                };
+                lights.push(Box::new(light));
+                path_lights.push(PathLight::Spot(light));
+            }
+            "RectLight" => {
+                let position = read_vec3_field(obj, "position").unwrap_or_else(|| read_center(obj));
+                let normal = to_api_vec3(read_light_direction(
+                    obj,
+                    "normal",
+                    position,
+                    Vec3::new(0.0, -1.0, 0.0),
+                ));
+                let hint = if normal.y.abs() < 0.99 {
+                    ApiVec3::new(0.0, 1.0, 0.0)
+                } else {
+                    ApiVec3::new(0.0, 0.0, -1.0)
+                };
+                // Width runs horizontally where it can, so wall lights read as windows.
+                let right = hint.cross(normal).normalize();
+                let up = normal.cross(right);
+                let width = read_number_field(obj, &["width"]).unwrap_or(1.0).max(0.0);
+                let height = read_number_field(obj, &["height"]).unwrap_or(1.0).max(0.0);
+                let light = RectLight {
+                    position: to_api_vec3(position),
+                    right: right * (0.5 * width),
+                    up: up * (0.5 * height),
+                    radiance: read_light_spectrum(obj, "color", "intensity", &["intensity"])
+                        .unwrap_or(Spectrum::rgb(8.0, 8.0, 8.0)),
+                    two_sided: read_two_sided(obj),
+                    spread: read_light_spread(obj),
+                    samples: read_light_samples(obj),
+                };
+                lights.push(Box::new(light));
+                path_lights.push(PathLight::Rect(light));
+            }
+            "DiskLight" => {
+                let position = read_vec3_field(obj, "position").unwrap_or_else(|| read_center(obj));
+                let light = DiskLight {
+                    position: to_api_vec3(position),
+                    normal: to_api_vec3(read_light_direction(
+                        obj,
+                        "normal",
+                        position,
+                        Vec3::new(0.0, -1.0, 0.0),
+                    )),
+                    radius: read_number_field(obj, &["radius", "r"])
+                        .unwrap_or(0.5)
+                        .max(0.0),
+                    radiance: read_light_spectrum(obj, "color", "intensity", &["intensity"])
+                        .unwrap_or(Spectrum::rgb(8.0, 8.0, 8.0)),
+                    two_sided: read_two_sided(obj),
+                    spread: read_light_spread(obj),
+                    samples: read_light_samples(obj),
+                };
+                lights.push(Box::new(light));
+                path_lights.push(PathLight::Disk(light));
             }
             _ => {}
         }
     }
 
     for light in semantic_lights {
-        lights.push(Box::new(SphereLight {
+        let light = SphereLight {
             position: to_api_vec3(light.position),
             radius: light.radius,
             intensity: light.intensity,
             samples: light.samples,
+        };
+        lights.push(Box::new(light));
+        path_lights.push(sphere_path_light(light));
+    }
+
+    if let Some((map, samples)) = env_map {
+        lights.push(Box::new(EnvMapLight {
+            map: Arc::clone(map),
+            samples,
         }));
-        path_lights.push(PathLight::Point {
-            position: to_api_vec3(light.position),
-            intensity: light.intensity,
-        });
+        path_lights.push(PathLight::EnvMap);
     }
 
     if lights.is_empty() {
@@ -6387,15 +7696,53 @@ fn parse_lights(
     (lights, path_lights)
 }
 
-fn parse_material(state: &EvalState, scene_root: &Value) -> MaterialKindRt {
-    if let Some(binding) = state.bindings.get("material")
-        && let Some(material) = extract_material_kind(state, &binding.value)
-    {
-        return material;
-    }
-
-    if let Some(material) = extract_material_kind(state, scene_root) {
-        return material;
+/// Sphere lights too small to sample as an area are traced as points.
+fn sphere_path_light(light: SphereLight) -> PathLight {
+    if light.radius > 1.0e-5 {
+        PathLight::Sphere(light)
+    } else {
+        PathLight::Point {
+            position: light.position,
+            intensity: light.intensity,
+        }
+    }
+}
+
+/// Unit direction from the `field` vector, or from `position` towards `target`.
+fn read_light_direction(obj: &ObjectValue, field: &str, position: Vec3, default: Vec3) -> Vec3 {
+    read_vec3_field(obj, field)
+        .or_else(|| read_vec3_field(obj, "target").map(|target| target.sub(position)))
+        .filter(|dir| dir.length() > 1.0e-6)
+        .unwrap_or(default)
+        .normalize()
+}
+
+fn read_light_samples(obj: &ObjectValue) -> u32 {
+    read_number_field(obj, &["samples"])
+        .map(|v| v.max(1.0) as u32)
+        .unwrap_or(8)
+}
+
+fn read_two_sided(obj: &ObjectValue) -> bool {
+    read_number_field(obj, &["two_sided"]).is_some_and(|v| v != 0.0)
+}
+
+fn read_light_spread(obj: &ObjectValue) -> f32 {
+    read_number_field(obj, &["spread"])
+        .unwrap_or(180.0)
+        .clamp(1.0, 180.0)
+        .to_radians()
+}
+
+fn parse_material(state: &EvalState, scene_root: &Value) -> MaterialKindRt {
+    if let Some(binding) = state.bindings.get("material")
+        && let Some(material) = extract_material_kind(state, &binding.value)
+    {
+        return material;
+    }
+
+    if let Some(material) = extract_material_kind(state, scene_root) {
+        return material;
     }
 
     default_material()
@@ -6412,7 +7759,9 @@ fn extract_material_kind(state: &EvalState, value: &Value) -> Option<MaterialKin
         return Some(material_from_object(state, material_obj, None));
     }
 
-    for field_value in obj.fields.values() {
+    let mut fields = obj.fields.iter().collect::<Vec<_>>();
+    fields.sort_unstable_by_key(|(name, _)| name.as_str());
+    for (_, field_value) in fields {
         if let Some(found) = extract_material_kind(state, field_value) {
             return Some(found);
         }
@@ -6456,6 +7805,7 @@ fn material_from_object(
             .max(0.0);
         let mut params = MaterialParams::lambert(color, emission_color, emission_strength);
         params.medium = read_medium_field(obj, "medium");
+        params.priority = read_priority_field(obj);
         params.subsurface = read_subsurface_field(obj, "subsurface");
         params.pattern = read_pattern_field(obj, "pattern");
         return MaterialKindRt::Lambert(params);
@@ -6467,14 +7817,16 @@ fn material_from_object(
         let color = read_spectrum_field(obj, "color")
             .or_else(|| read_spectrum_field(obj, "base_color"))
             .unwrap_or(Spectrum::rgb(0.8, 0.8, 0.8));
-        let roughness = read_number_field(obj, &["roughness"])
+        let roughness = read_number_field(obj, &["roughness", "specular_roughness"])
             .unwrap_or(0.5)
             .clamp(0.0, 1.0);
-        let metallic = read_number_field(obj, &["metallic", "metalness"])
+        let metallic = read_number_field(obj, &["metallic", "metalness", "base_metalness"])
             .unwrap_or(0.0)
             .clamp(0.0, 1.0);
+        // `OpenPbr` leaves its highlight to `specular_weight` unless `specular` is given.
+        let openpbr = type_name.eq_ignore_ascii_case("openpbr");
         let specular = read_number_field(obj, &["specular"])
-            .unwrap_or(0.5)
+            .unwrap_or(if openpbr { 1.0 } else { 0.5 })
             .clamp(0.0, 1.0);
         let specular_weight = read_number_field(obj, &["specular_weight"])
             .unwrap_or(1.0)
@@ -6484,12 +7836,13 @@ fn material_from_object(
         let ior = read_number_field(obj, &["ior", "specular_ior"])
             .unwrap_or(1.5)
             .clamp(1.0, 3.0);
-        let clearcoat = read_number_field(obj, &["clearcoat"])
+        let clearcoat = read_number_field(obj, &["clearcoat", "coat_weight"])
             .unwrap_or(0.0)
             .clamp(0.0, 1.0);
-        let clearcoat_roughness = read_number_field(obj, &["clearcoat_roughness"])
-            .unwrap_or(0.1)
-            .clamp(0.0, 1.0);
+        let clearcoat_roughness =
+            read_number_field(obj, &["clearcoat_roughness", "coat_roughness"])
+                .unwrap_or(0.1)
+                .clamp(0.0, 1.0);
         let transmission = read_number_field(obj, &["transmission", "transmission_weight"])
             .unwrap_or(0.0)
             .clamp(0.0, 1.0);
@@ -6514,15 +7867,27 @@ fn material_from_object(
             emission_color,
             emission_strength,
         );
+        params = read_dispersion_fields(obj, params);
         params.medium = read_medium_field(obj, "medium");
+        params.priority = read_priority_field(obj);
         params.subsurface = read_subsurface_field(obj, "subsurface");
         params.pattern = read_pattern_field(obj, "pattern");
+        if openpbr {
+            params.layers = read_openpbr_layers(obj);
+            return MaterialKindRt::OpenPbr(params);
+        }
         return MaterialKindRt::Standard(params);
     }
     if type_name.eq_ignore_ascii_case("metal") {
+        let conductor = read_conductor_field(obj, "conductor");
+        // A measured preset brings its own color, so the tint defaults to white with one.
         let color = read_spectrum_field(obj, "color")
             .or_else(|| read_spectrum_field(obj, "base_color"))
-            .unwrap_or(Spectrum::rgb(0.9, 0.9, 0.9));
+            .unwrap_or(if conductor.is_some() {
+                Spectrum::rgb(1.0, 1.0, 1.0)
+            } else {
+                Spectrum::rgb(0.9, 0.9, 0.9)
+            });
         let roughness = read_number_field(obj, &["roughness"])
             .unwrap_or(0.1)
             .clamp(0.0, 1.0);
@@ -6532,7 +7897,9 @@ fn material_from_object(
             .unwrap_or(0.0)
             .max(0.0);
         let mut params = MaterialParams::metal(color, roughness, emission_color, emission_strength);
+        params.conductor = conductor.map(ComplexIor::new);
         params.medium = read_medium_field(obj, "medium");
+        params.priority = read_priority_field(obj);
         params.subsurface = read_subsurface_field(obj, "subsurface");
         params.pattern = read_pattern_field(obj, "pattern");
         return MaterialKindRt::Metal(params);
@@ -6561,7 +7928,9 @@ fn material_from_object(
             emission_color,
             emission_strength,
         );
+        params = read_dispersion_fields(obj, params);
         params.medium = read_medium_field(obj, "medium");
+        params.priority = read_priority_field(obj);
         params.subsurface = read_subsurface_field(obj, "subsurface");
         params.pattern = read_pattern_field(obj, "pattern");
         return MaterialKindRt::Dielectric(params);
@@ -6624,6 +7993,7 @@ fn material_from_legacy_object(_state: &EvalState, obj: &ObjectValue) -> Materia
         emission_strength,
     );
     params.medium = read_medium_field(obj, "medium");
+    params.priority = read_priority_field(obj);
     params.subsurface = read_subsurface_field(obj, "subsurface");
     params.pattern = read_pattern_field(obj, "pattern");
     MaterialKindRt::Standard(params)
@@ -6663,6 +8033,21 @@ fn material_from_dynamic_def(
             Spectrum::black(),
             0.0,
         ),
+        "OpenPbr" => MaterialParams::standard(
+            Spectrum::rgb(0.8, 0.8, 0.8),
+            0.3,
+            0.0,
+            1.0,
+            1.0,
+            Spectrum::rgb(1.0, 1.0, 1.0),
+            1.5,
+            0.0,
+            0.0,
+            0.0,
+            false,
+            Spectrum::black(),
+            0.0,
+        ),
         _ => MaterialParams::lambert(Spectrum::rgb(0.8, 0.8, 0.8), Spectrum::black(), 0.0),
     };
     apply_dynamic_material_properties(state, name, overrides, &mut params);
@@ -6675,6 +8060,7 @@ fn material_from_dynamic_def(
     match def.model.as_str() {
         "Metal" => MaterialKindRt::Metal(params),
         "Dielectric" => MaterialKindRt::Dielectric(params),
+        "OpenPbr" => MaterialKindRt::OpenPbr(params),
         "Standard" | "Layered" => MaterialKindRt::Standard(params),
         _ => MaterialKindRt::Standard(params),
     }
@@ -6691,24 +8077,27 @@ fn apply_dynamic_material_properties(
     else {
         return;
     };
+    // The Abbe number is relative to `ior`, which may be set by a later property; an explicit
+    // `cauchy_b` wins over it.
+    let mut abbe = None;
     for (name, value) in properties {
         match name.as_str() {
-            "color" => {
+            "color" | "base_color" => {
                 if let Some(color) = spectrum_from_value(&value) {
                     params.color = color;
                 }
             }
-            "roughness" => {
+            "roughness" | "specular_roughness" => {
                 if let Value::Number(v) = value {
                     params.roughness = (v).clamp(0.0, 1.0);
                 }
             }
-            "ior" => {
+            "ior" | "specular_ior" => {
                 if let Value::Number(v) = value {
                     params.ior = (v).clamp(1.0, 3.0);
                 }
             }
-            "metallic" => {
+            "metallic" | "base_metalness" => {
                 if let Value::Number(v) = value {
                     params.metallic = (v).clamp(0.0, 1.0);
                 }
@@ -6728,17 +8117,17 @@ fn apply_dynamic_material_properties(
                     params.specular_color = color;
                 }
             }
-            "clearcoat" => {
+            "clearcoat" | "coat_weight" => {
                 if let Value::Number(v) = value {
                     params.clearcoat = (v).clamp(0.0, 1.0);
                 }
             }
-            "clearcoat_roughness" => {
+            "clearcoat_roughness" | "coat_roughness" => {
                 if let Value::Number(v) = value {
                     params.clearcoat_roughness = (v).clamp(0.0, 1.0);
                 }
             }
-            "transmission" => {
+            "transmission" | "transmission_weight" => {
                 if let Value::Number(v) = value {
                     params.transmission = (v).clamp(0.0, 1.0);
                 }
@@ -6761,12 +8150,41 @@ fn apply_dynamic_material_properties(
             "medium" => {
                 params.medium = medium_from_value(&value);
             }
+            "priority" => {
+                if let Value::Number(v) = value {
+                    params.priority = v.max(0.0).round() as u32;
+                }
+            }
+            "abbe" => {
+                if let Value::Number(v) = value {
+                    abbe = Some(v);
+                }
+            }
+            "cauchy_b" => {
+                if let Value::Number(v) = value {
+                    params.cauchy_b = v.max(0.0);
+                }
+            }
+            "conductor" => {
+                params.conductor = conductor_from_value(&value).map(ComplexIor::new);
+            }
             "subsurface" => {
                 params.subsurface = subsurface_from_value(&value);
             }
-            _ => {}
+            name => {
+                if let Value::Number(v) = value {
+                    set_openpbr_number(&mut params.layers, name, v);
+                } else if let Some(color) = spectrum_from_value(&value) {
+                    set_openpbr_color(&mut params.layers, name, color);
+                }
+            }
         }
     }
+    if let Some(abbe) = abbe
+        && params.cauchy_b == 0.0
+    {
+        *params = params.with_abbe(abbe);
+    }
 }
 
 fn read_spectrum_field(obj: &ObjectValue, name: &str) -> Option<Spectrum> {
@@ -6827,6 +8245,88 @@ fn read_medium_field(obj: &ObjectValue, name: &str) -> Option<MediumParams> {
     medium_from_value(obj.fields.get(name)?)
 }
 
+fn read_priority_field(obj: &ObjectValue) -> u32 {
+    read_number_field(obj, &["priority"]).map_or(0, |v| v.max(0.0).round() as u32)
+}
+
+/// Dispersion from a material's `cauchy_b` field, or else from its `abbe` number.
+fn read_dispersion_fields(obj: &ObjectValue, params: MaterialParams) -> MaterialParams {
+    match (
+        read_number_field(obj, &["cauchy_b"]),
+        read_number_field(obj, &["abbe"]),
+    ) {
+        (Some(b), _) => MaterialParams {
+            cauchy_b: b.max(0.0),
+            ..params
+        },
+        (None, Some(abbe)) => params.with_abbe(abbe),
+        (None, None) => params,
+    }
+}
+
+/// `OpenPbr` layer fields that take a number; `coat_weight` and `coat_roughness` map to
+/// `clearcoat` and `clearcoat_roughness` instead.
+const OPENPBR_NUMBER_FIELDS: [&str; 8] = [
+    "coat_ior",
+    "fuzz_weight",
+    "fuzz_roughness",
+    "thin_film_weight",
+    "thin_film_thickness",
+    "thin_film_ior",
+    "specular_anisotropy",
+    "specular_rotation",
+];
+const OPENPBR_COLOR_FIELDS: [&str; 2] = ["coat_color", "fuzz_color"];
+
+fn read_openpbr_layers(obj: &ObjectValue) -> OpenPbrLayers {
+    let mut layers = OpenPbrLayers::default();
+    for name in OPENPBR_NUMBER_FIELDS {
+        if let Some(value) = read_number_field(obj, &[name]) {
+            set_openpbr_number(&mut layers, name, value);
+        }
+    }
+    for name in OPENPBR_COLOR_FIELDS {
+        if let Some(color) = read_spectrum_field(obj, name) {
+            set_openpbr_color(&mut layers, name, color);
+        }
+    }
+    layers
+}
+
+fn set_openpbr_number(layers: &mut OpenPbrLayers, name: &str, value: f32) {
+    match name {
+        "coat_ior" => layers.coat_ior = value.clamp(1.0, 3.0),
+        "fuzz_weight" => layers.fuzz_weight = value.clamp(0.0, 1.0),
+        "fuzz_roughness" => layers.fuzz_roughness = value.clamp(0.0, 1.0),
+        "thin_film_weight" => layers.thin_film_weight = value.clamp(0.0, 1.0),
+        "thin_film_thickness" => layers.thin_film_thickness = value.max(0.0),
+        "thin_film_ior" => layers.thin_film_ior = value.clamp(1.0, 3.0),
+        "specular_anisotropy" => layers.specular_anisotropy = value.clamp(0.0, 1.0),
+        "specular_rotation" => layers.specular_rotation = value,
+        _ => {}
+    }
+}
+
+fn set_openpbr_color(layers: &mut OpenPbrLayers, name: &str, color: Spectrum) {
+    match name {
+        "coat_color" => layers.coat_color = color,
+        "fuzz_color" => layers.fuzz_color = color,
+        _ => {}
+    }
+}
+
+fn read_conductor_field(obj: &ObjectValue, name: &str) -> Option<Conductor> {
+    conductor_from_value(obj.fields.get(name)?)
+}
+
+fn conductor_from_value(value: &Value) -> Option<Conductor> {
+    match value {
+        Value::Object(v) => Conductor::from_name(v.type_name.as_deref()?),
+        Value::String(v) => Conductor::from_name(v),
+        Value::Number(_) | Value::Array(_) | Value::Function(_) => None,
+    }
+}
+
 fn read_subsurface_field(obj: &ObjectValue, name: &str) -> Option<SubsurfaceParams> {
     subsurface_from_value(obj.fields.get(name)?)
 }
@@ -6835,13 +8335,26 @@ fn medium_from_value(value: &Value) -> Option<MediumParams> {
     let Value::Object(obj) = value else {
         return None;
     };
-    let ior = read_number_field(obj, &["ior"])
-        .unwrap_or(1.0)
-        .clamp(1.0, 3.0);
+    let ior = read_number_field(obj, &["ior"]).map(|ior| ior.clamp(1.0, 3.0));
     let absorption_color =
         read_spectrum_field(obj, "absorption_color").unwrap_or(Spectrum::rgb(1.0, 1.0, 1.0));
     let density = read_number_field(obj, &["density"]).unwrap_or(0.0).max(0.0);
-    Some(MediumParams::new(ior, absorption_color, density))
+    let scattering = read_number_field(obj, &["scattering"])
+        .unwrap_or(0.0)
+        .max(0.0);
+    let scattering_color =
+        read_spectrum_field(obj, "scattering_color").unwrap_or(Spectrum::rgb(1.0, 1.0, 1.0));
+    let anisotropy = read_number_field(obj, &["anisotropy"])
+        .unwrap_or(0.0)
+        .clamp(-0.95, 0.95);
+    Some(MediumParams {
+        ior,
+        ..MediumParams::new(1.0, absorption_color, density).with_scattering(
+            scattering,
+            scattering_color,
+            anisotropy,
+        )
+    })
 }
 
 fn subsurface_from_value(value: &Value) -> Option<SubsurfaceParams> {
@@ -6995,17 +8508,17 @@ fn resolve_material_from_node(
         | SdfNode::DistancePostModifier { base, .. }
         | SdfNode::Noise { base, .. } => resolve_material_from_node(base, setup, hit, view_dir),
         SdfNode::Union { lhs, rhs } => {
-            let l = sdf_distance_info(lhs, hit.position);
-            let r = sdf_distance_info(rhs, hit.position);
-            if l.distance <= r.distance {
+            let l = nearest_surface_info(lhs, hit.position, hit.time);
+            let r = nearest_surface_info(rhs, hit.position, hit.time);
+            if l.distance.abs() <= r.distance.abs() {
                 resolve_material_from_node(lhs, setup, hit, view_dir)
             } else {
                 resolve_material_from_node(rhs, setup, hit, view_dir)
             }
         }
         SdfNode::Intersect { lhs, rhs } => {
-            let l = sdf_distance_info(lhs, hit.position);
-            let r = sdf_distance_info(rhs, hit.position);
+            let l = sdf_distance_info(lhs, hit.position, hit.time);
+            let r = sdf_distance_info(rhs, hit.position, hit.time);
             if l.distance >= r.distance {
                 resolve_material_from_node(lhs, setup, hit, view_dir)
             } else {
@@ -7013,8 +8526,8 @@ fn resolve_material_from_node(
             }
         }
         SdfNode::Subtract { lhs, rhs } => {
-            let l = sdf_distance_info(lhs, hit.position);
-            let r = sdf_distance_info(rhs, hit.position);
+            let l = sdf_distance_info(lhs, hit.position, hit.time);
+            let r = sdf_distance_info(rhs, hit.position, hit.time);
             if l.distance >= -r.distance {
                 resolve_material_from_node(lhs, setup, hit, view_dir)
             } else {
@@ -7048,6 +8561,15 @@ fn resolve_material_from_node(
         }
         SdfNode::Slice { base, .. } => resolve_material_from_node(base, setup, hit, view_dir),
         SdfNode::Smooth { base, .. } => resolve_material_from_node(base, setup, hit, view_dir),
+        SdfNode::Motion {
+            base, rest, motion, ..
+        } => {
+            let rest_hit = RayHit {
+                position: motion_point(hit.position, *rest, motion, hit.time),
+                ..hit
+            };
+            resolve_material_from_node(base, setup, rest_hit, view_dir)
+        }
     }
 }
 
@@ -7059,17 +8581,17 @@ fn resolve_split_material_from_node(
 ) -> Option<(MaterialKindRt, MaterialKindRt, f32)> {
     match node {
         SdfNode::Union { lhs, rhs } => {
-            let l = sdf_distance_info(lhs, hit.position);
-            let r = sdf_distance_info(rhs, hit.position);
-            if l.distance <= r.distance {
+            let l = nearest_surface_info(lhs, hit.position, hit.time);
+            let r = nearest_surface_info(rhs, hit.position, hit.time);
+            if l.distance.abs() <= r.distance.abs() {
                 resolve_split_material_from_node(lhs, setup, hit, view_dir)
             } else {
                 resolve_split_material_from_node(rhs, setup, hit, view_dir)
             }
         }
         SdfNode::Intersect { lhs, rhs } => {
-            let l = sdf_distance_info(lhs, hit.position);
-            let r = sdf_distance_info(rhs, hit.position);
+            let l = sdf_distance_info(lhs, hit.position, hit.time);
+            let r = sdf_distance_info(rhs, hit.position, hit.time);
             if l.distance >= r.distance {
                 resolve_split_material_from_node(lhs, setup, hit, view_dir)
             } else {
@@ -7077,8 +8599,8 @@ fn resolve_split_material_from_node(
             }
         }
         SdfNode::Subtract { lhs, rhs } => {
-            let l = sdf_distance_info(lhs, hit.position);
-            let r = sdf_distance_info(rhs, hit.position);
+            let l = sdf_distance_info(lhs, hit.position, hit.time);
+            let r = sdf_distance_info(rhs, hit.position, hit.time);
             if l.distance >= -r.distance {
                 resolve_split_material_from_node(lhs, setup, hit, view_dir)
             } else {
@@ -7113,6 +8635,15 @@ fn resolve_split_material_from_node(
         SdfNode::Smooth { base, .. } | SdfNode::Noise { base, .. } => {
             resolve_split_material_from_node(base, setup, hit, view_dir)
         }
+        SdfNode::Motion {
+            base, rest, motion, ..
+        } => {
+            let rest_hit = RayHit {
+                position: motion_point(hit.position, *rest, motion, hit.time),
+                ..hit
+            };
+            resolve_split_material_from_node(base, setup, rest_hit, view_dir)
+        }
         _ => None,
     }
 }
@@ -7126,8 +8657,8 @@ fn blend_node_materials(
     radius: f32,
     difference_mode: bool,
 ) -> Option<MaterialKindRt> {
-    let l = sdf_distance_info(lhs, hit.position);
-    let r = sdf_distance_info(rhs, hit.position);
+    let l = sdf_distance_info(lhs, hit.position, hit.time);
+    let r = sdf_distance_info(rhs, hit.position, hit.time);
     let left = resolve_material_from_node(lhs, setup, hit, view_dir)?;
     let right = resolve_material_from_node(rhs, setup, hit, view_dir)?;
     let k = radius.abs().max(1.0e-6);
@@ -7148,8 +8679,8 @@ fn split_node_materials(
     radius: f32,
     difference_mode: bool,
 ) -> Option<(MaterialKindRt, MaterialKindRt, f32)> {
-    let l = sdf_distance_info(lhs, hit.position);
-    let r = sdf_distance_info(rhs, hit.position);
+    let l = sdf_distance_info(lhs, hit.position, hit.time);
+    let r = sdf_distance_info(rhs, hit.position, hit.time);
     let left = resolve_material_from_node(lhs, setup, hit, view_dir)?;
     let right = resolve_material_from_node(rhs, setup, hit, view_dir)?;
     let k = radius.abs().max(1.0e-6);
@@ -7263,7 +8794,7 @@ fn resolve_leaf_material(
                 .material_defs
                 .get(name)
                 .map(|def| def.model.as_str()),
-            Some("Layered" | "Standard")
+            Some("Layered" | "Standard" | "OpenPbr")
         )
     {
         let mut base_params = dominant_material_params(material);
@@ -7467,10 +8998,50 @@ fn resolve_leaf_material(
             base_params.clearcoat = (coat_weight * coat_mask).clamp(0.0, 1.0);
             base_params.clearcoat_roughness = coat_roughness;
         }
+        if let MaterialKindRt::OpenPbr(_) = material {
+            let number = |function_name: &str| {
+                resolve_dynamic_number(
+                    &setup.state,
+                    &setup.material_def_names,
+                    &setup.dynamic_material_overrides,
+                    material,
+                    hit,
+                    local_position,
+                    view_dir,
+                    function_name,
+                )
+            };
+            if let Some(coat_weight) = number("coat_weight") {
+                base_params.clearcoat = coat_weight.clamp(0.0, 1.0);
+            }
+            if let Some(coat_roughness) = number("coat_roughness") {
+                base_params.clearcoat_roughness = coat_roughness.clamp(0.0, 1.0);
+            }
+            for name in OPENPBR_NUMBER_FIELDS {
+                if let Some(value) = number(name) {
+                    set_openpbr_number(&mut base_params.layers, name, value);
+                }
+            }
+            for name in OPENPBR_COLOR_FIELDS {
+                if let Some(color) = resolve_dynamic_spectrum(
+                    &setup.state,
+                    &setup.material_def_names,
+                    &setup.dynamic_material_overrides,
+                    material,
+                    hit,
+                    local_position,
+                    view_dir,
+                    name,
+                ) {
+                    set_openpbr_color(&mut base_params.layers, name, color);
+                }
+            }
+            return MaterialKindRt::OpenPbr(base_params);
+        }
         return MaterialKindRt::Standard(base_params);
     }
     match material {
-        MaterialKindRt::Standard(mut params) => {
+        MaterialKindRt::Standard(mut params) | MaterialKindRt::OpenPbr(mut params) => {
             params.color =
                 runtime_color.unwrap_or_else(|| resolve_pattern_color(params, local_position));
             params.roughness = runtime_roughness
@@ -7486,7 +9057,11 @@ fn resolve_leaf_material(
             params.emission_strength = runtime_emission_strength
                 .unwrap_or(params.emission_strength)
                 .max(0.0) as f32;
-            MaterialKindRt::Standard(params)
+            if let MaterialKindRt::OpenPbr(_) = material {
+                MaterialKindRt::OpenPbr(params)
+            } else {
+                MaterialKindRt::Standard(params)
+            }
         }
         MaterialKindRt::Lambert(mut params) => {
             params.color =
@@ -7543,9 +9118,12 @@ fn smoothstepf(edge0: f32, edge1: f32, x: f32) -> f32 {
 fn blend_medium(a: Option<MediumParams>, b: Option<MediumParams>, t: f32) -> Option<MediumParams> {
     match (a, b) {
         (Some(a), Some(b)) => Some(MediumParams {
-            ior: a.ior + (b.ior - a.ior) * t,
+            ior: a.ior.zip(b.ior).map(|(a, b)| a + (b - a) * t),
             absorption_color: lerp_spectrum(a.absorption_color, b.absorption_color, t),
             density: a.density + (b.density - a.density) * t,
+            scattering: a.scattering + (b.scattering - a.scattering) * t,
+            scattering_color: lerp_spectrum(a.scattering_color, b.scattering_color, t),
+            anisotropy: a.anisotropy + (b.anisotropy - a.anisotropy) * t,
         }),
         (Some(a), None) => {
             if t < 0.5 {
@@ -7565,6 +9143,13 @@ fn blend_medium(a: Option<MediumParams>, b: Option<MediumParams>, t: f32) -> Opt
     }
 }
 
+fn medium_with_ior(medium: Option<MediumParams>, ior: f32) -> Option<MediumParams> {
+    medium.map(|medium| MediumParams {
+        ior: medium.ior.or(Some(ior)),
+        ..medium
+    })
+}
+
 fn blend_subsurface(
     a: Option<SubsurfaceParams>,
     b: Option<SubsurfaceParams>,
@@ -7599,7 +9184,27 @@ fn blend_subsurface(
     }
 }
 
+fn blend_layers(a: OpenPbrLayers, b: OpenPbrLayers, t: f32) -> OpenPbrLayers {
+    let lerp = |a: f32, b: f32| a + (b - a) * t;
+    OpenPbrLayers {
+        coat_ior: lerp(a.coat_ior, b.coat_ior),
+        coat_color: lerp_spectrum(a.coat_color, b.coat_color, t),
+        fuzz_weight: lerp(a.fuzz_weight, b.fuzz_weight),
+        fuzz_color: lerp_spectrum(a.fuzz_color, b.fuzz_color, t),
+        fuzz_roughness: lerp(a.fuzz_roughness, b.fuzz_roughness),
+        thin_film_weight: lerp(a.thin_film_weight, b.thin_film_weight),
+        thin_film_thickness: lerp(a.thin_film_thickness, b.thin_film_thickness),
+        thin_film_ior: lerp(a.thin_film_ior, b.thin_film_ior),
+        specular_anisotropy: lerp(a.specular_anisotropy, b.specular_anisotropy),
+        specular_rotation: lerp(a.specular_rotation, b.specular_rotation),
+        wavelengths: a.wavelengths,
+    }
+}
+
 fn blend_params(mut a: MaterialParams, b: MaterialParams, t: f32) -> MaterialParams {
+    // A medium without an ior inherits its own material's, so settle that before blending.
+    let medium_a = medium_with_ior(a.medium, a.ior);
+    let medium_b = medium_with_ior(b.medium, b.ior);
     a.color = lerp_spectrum(a.color, b.color, t);
     a.roughness = a.roughness + (b.roughness - a.roughness) * t;
     a.metallic = a.metallic + (b.metallic - a.metallic) * t;
@@ -7607,6 +9212,8 @@ fn blend_params(mut a: MaterialParams, b: MaterialParams, t: f32) -> MaterialPar
     a.specular_weight = a.specular_weight + (b.specular_weight - a.specular_weight) * t;
     a.specular_color = lerp_spectrum(a.specular_color, b.specular_color, t);
     a.ior = a.ior + (b.ior - a.ior) * t;
+    a.cauchy_b = a.cauchy_b + (b.cauchy_b - a.cauchy_b) * t;
+    a.conductor = if t < 0.5 { a.conductor } else { b.conductor };
     a.clearcoat = a.clearcoat + (b.clearcoat - a.clearcoat) * t;
     a.clearcoat_roughness =
         a.clearcoat_roughness + (b.clearcoat_roughness - a.clearcoat_roughness) * t;
@@ -7618,8 +9225,10 @@ fn blend_params(mut a: MaterialParams, b: MaterialParams, t: f32) -> MaterialPar
     };
     a.emission_color = lerp_spectrum(a.emission_color, b.emission_color, t);
     a.emission_strength = a.emission_strength + (b.emission_strength - a.emission_strength) * t;
-    a.medium = blend_medium(a.medium, b.medium, t);
+    a.medium = blend_medium(medium_a, medium_b, t);
+    a.priority = if t < 0.5 { a.priority } else { b.priority };
     a.subsurface = blend_subsurface(a.subsurface, b.subsurface, t);
+    a.layers = blend_layers(a.layers, b.layers, t);
     a.pattern = None;
     a.dynamic_material_id = None;
     a.dynamic_override_id = None;
@@ -7640,6 +9249,9 @@ fn blend_materials(a: MaterialKindRt, b: MaterialKindRt, t: f32) -> MaterialKind
         (MaterialKindRt::Dielectric(a), MaterialKindRt::Dielectric(b)) => {
             MaterialKindRt::Dielectric(blend_params(a, b, t))
         }
+        (MaterialKindRt::OpenPbr(a), MaterialKindRt::OpenPbr(b)) => {
+            MaterialKindRt::OpenPbr(blend_params(a, b, t))
+        }
         (a, b) => MaterialKindRt::Blend(BlendedMaterial {
             a_model: dominant_material_model(a),
             a_params: dominant_material_params(a),
@@ -7656,6 +9268,7 @@ fn dominant_material_model(material: MaterialKindRt) -> MaterialKindTag {
         MaterialKindRt::Lambert(_) => MaterialKindTag::Lambert,
         MaterialKindRt::Metal(_) => MaterialKindTag::Metal,
         MaterialKindRt::Dielectric(_) => MaterialKindTag::Dielectric,
+        MaterialKindRt::OpenPbr(_) => MaterialKindTag::OpenPbr,
         MaterialKindRt::Blend(blend) => {
             if blend.t < 0.5 {
                 blend.a_model
@@ -7671,7 +9284,8 @@ fn dominant_material_params(material: MaterialKindRt) -> MaterialParams {
         MaterialKindRt::Standard(params)
         | MaterialKindRt::Lambert(params)
         | MaterialKindRt::Metal(params)
-        | MaterialKindRt::Dielectric(params) => params,
+        | MaterialKindRt::Dielectric(params)
+        | MaterialKindRt::OpenPbr(params) => params,
         MaterialKindRt::Blend(blend) => {
             if blend.t < 0.5 {
                 blend.a_params
@@ -7962,8 +9576,9 @@ mod tests {
     };
 
     use super::{
-        AccelMode, RaySettings, RenderOptions, extract_scene_render_settings,
-        render_depth_png_with_accel, render_ray_progressive_with_accel,
+        AccelMode, DisplayTransform, PathtraceSettings, RaySettings, RenderOptions,
+        extract_scene_render_settings, render_depth_png_with_accel,
+        render_pathtrace_progressive_with_accel, render_ray_progressive_with_accel,
     };
 
     fn empty_state(bindings: HashMap<String, Binding>) -> EvalState {
@@ -7983,6 +9598,9 @@ mod tests {
             sdf_defs: HashMap::new(),
             skeleton_defs: HashMap::new(),
             environment_defs: HashMap::new(),
+            volume_defs: HashMap::new(),
+            jitted_volume_density_functions: HashMap::new(),
+            base_dir: None,
         }
     }
 
@@ -8254,6 +9872,9 @@ mod tests {
                 step_scale: 0.7,
                 camera_z: 6.0,
                 fov_y_degrees: 35.0,
+                display: DisplayTransform::default(),
+                shutter: super::Shutter::default(),
+                shard: None,
             },
             AccelMode::Naive,
             RaySettings {
@@ -8273,6 +9894,422 @@ mod tests {
         );
     }
 
+    #[test]
+    fn pathtraces_progressively_with_adaptive_sampling() {
+        let mut sphere_fields = HashMap::new();
+        sphere_fields.insert("radius".to_string(), Value::Number(0.9));
+        let sphere = Value::Object(crate::ObjectValue {
+            type_name: Some("Sphere".to_string()),
+            fields: sphere_fields,
+        });
+        let mut bindings = HashMap::new();
+        bindings.insert(
+            "scene".to_string(),
+            Binding {
+                mutable: false,
+                value: sphere,
+            },
+        );
+
+        let mut previews = Vec::new();
+        let image = render_pathtrace_progressive_with_accel(
+            &empty_state(bindings),
+            RenderOptions {
+                width: 24,
+                height: 24,
+                ..RenderOptions::default()
+            },
+            AccelMode::Bvh,
+            PathtraceSettings {
+                spp: 6,
+                max_bounces: 2,
+                preview_every: 2,
+                min_spp: 2,
+                noise_threshold: 0.05,
+                ..PathtraceSettings::default()
+            },
+            |step, _| {
+                previews.push(step);
+                Ok(())
+            },
+        )
+        .expect("pathtrace should succeed");
+
+        assert!(!previews.is_empty());
+        assert!(
+            previews
+                .windows(2)
+                .all(|w| w[0].samples_done < w[1].samples_done)
+        );
+        let last = previews.last().expect("at least one preview");
+        assert!(last.samples_done <= 6);
+        assert_eq!(last.samples_total, 6);
+        assert!(image.pixels().any(|pixel| pixel.0 != [0, 0, 0]));
+    }
+
+    #[test]
+    fn merges_region_and_tile_shards_into_the_full_frame() {
+        let program = parse_program("let scene = Sphere { radius: 0.9 };").expect("parse");
+        let state = eval_program(&program).expect("eval");
+        let options = RenderOptions {
+            width: 20,
+            height: 14,
+            ..RenderOptions::default()
+        };
+        let settings = RaySettings {
+            tile_size: 4,
+            ..RaySettings::default()
+        };
+        let render = |shard| {
+            super::render_ray_layers_with_accel(
+                &state,
+                RenderOptions { shard, ..options },
+                AccelMode::Bvh,
+                settings,
+                true,
+                |_, _| Ok(()),
+            )
+            .expect("render should succeed")
+        };
+        let full = render(None);
+
+        let shards = [
+            super::RenderShard::Region {
+                x0: 0,
+                y0: 0,
+                x1: 20,
+                y1: 5,
+            },
+            super::RenderShard::Tiles {
+                index: 0,
+                count: 2,
+                tile_size: 6,
+            },
+            super::RenderShard::Tiles {
+                index: 1,
+                count: 2,
+                tile_size: 6,
+            },
+        ];
+        let path = std::env::temp_dir().join("forgedthoughts-shard-test.ftshard");
+        let parts = shards
+            .iter()
+            .map(|&shard| {
+                let layers = render(Some(shard));
+                super::ShardLayers::from_layers(&layers, shard, options.display)
+                    .save(&path)
+                    .expect("shard should save");
+                super::ShardLayers::load(&path).expect("shard should load")
+            })
+            .collect::<Vec<_>>();
+        let bytes = std::fs::read(&path).expect("shard should exist");
+        std::fs::write(&path, &bytes[..bytes.len() / 2]).expect("truncate shard");
+        assert!(matches!(
+            super::ShardLayers::load(&path),
+            Err(super::RenderError::Shard(_))
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let merged = super::merge_shards(&parts).expect("shards cover the frame");
+        assert_eq!(merged.beauty, full.beauty);
+        assert_eq!(merged.aovs, full.aovs);
+        assert_eq!(merged.image, full.image);
+        assert!(matches!(
+            super::merge_shards(&parts[..1]),
+            Err(super::RenderError::Shard(_))
+        ));
+    }
+
+    #[test]
+    fn light_bvh_favors_nearby_lamps_with_consistent_probabilities() {
+        let mut lights = (0..24)
+            .map(|i| super::PathLight::Point {
+                position: super::ApiVec3::new((i % 6) as f32 * 4.0, 2.0, (i / 6) as f32 * 4.0),
+                intensity: super::Spectrum::rgb(10.0, 10.0, 10.0),
+            })
+            .collect::<Vec<_>>();
+        lights.push(super::PathLight::Env {
+            radiance: super::Spectrum::rgb(0.1, 0.1, 0.1),
+        });
+        let sampler = super::LightSampler::new(&lights, None, 1.0);
+        let point = super::Vec3::new(0.0, 0.0, 0.0);
+
+        let total = (0..lights.len())
+            .map(|index| sampler.pmf(point, index))
+            .sum::<f32>();
+        assert!((total - 1.0).abs() < 1.0e-5, "{total}");
+        // The dim sky sends far less power through the unit scene than the lamps.
+        let pi = std::f32::consts::PI;
+        let env_power = 4.0 * pi * pi * 0.1;
+        let env_pmf = env_power / (env_power + 24.0 * 4.0 * pi * 10.0);
+        assert!((sampler.pmf(point, 24) - env_pmf).abs() < 1.0e-6);
+
+        let mut picks = [0; 25];
+        for i in 0..1000 {
+            let (index, pmf) = sampler
+                .sample(point, (i as f32 + 0.5) / 1000.0)
+                .expect("lights to pick from");
+            assert!((pmf - sampler.pmf(point, index)).abs() < 1.0e-6);
+            picks[index] += 1;
+        }
+        // The lamp right above the point beats a uniform 1/24 share and the farthest lamp.
+        assert!(picks[0] > 42, "{picks:?}");
+        assert!(picks[0] > 4 * picks[23], "{picks:?}");
+        assert!(
+            super::LightSampler::new(&[], None, 1.0)
+                .sample(point, 0.5)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn lights_at_infinity_are_picked_by_power() {
+        let sun = |irradiance: f32| {
+            super::PathLight::Directional(super::DirectionalLight {
+                direction: super::ApiVec3::new(0.0, -1.0, 0.0),
+                angle: 0.0,
+                irradiance: super::Spectrum::rgb(irradiance, irradiance, irradiance),
+                samples: 1,
+            })
+        };
+        let lights = [sun(9.0), sun(1.0)];
+        let sampler = super::LightSampler::new(&lights, None, 5.0);
+        let point = super::Vec3::new(0.0, 0.0, 0.0);
+
+        assert!((sampler.pmf(point, 0) - 0.9).abs() < 1.0e-6);
+        assert!((sampler.pmf(point, 1) - 0.1).abs() < 1.0e-6);
+        assert!((sampler.infinite_pmf(|index| index == 1) - 0.1).abs() < 1.0e-6);
+        assert_eq!(
+            sampler.sample(point, 0.85),
+            Some((0, sampler.pmf(point, 0)))
+        );
+        assert_eq!(
+            sampler.sample(point, 0.95),
+            Some((1, sampler.pmf(point, 1)))
+        );
+
+        // With no power to go by, each light keeps an equal share.
+        let dark = super::LightSampler::new(&[sun(0.0), sun(0.0)], None, 5.0);
+        assert_eq!(dark.pmf(point, 1), 0.5);
+    }
+
+    #[test]
+    fn parses_sun_spot_and_area_lights_and_sees_windows() {
+        let scene = |normal_z: f32| {
+            format!(
+                "let camera = Camera {{ origin: vec3(0.0, 0.0, 6.0), target: vec3(0.0, 0.0, 0.0), fov_y: 20.0 }};
+                 let sun = DirectionalLight {{ direction: vec3(0.3, -1.0, 0.2), angle: 2.0 }};
+                 let spot = SpotLight {{ position: vec3(0.0, 3.0, 0.0), target: vec3(0.0, 0.0, 0.0), angle: 30.0 }};
+                 let lamp = DiskLight {{ position: vec3(0.0, 3.0, 0.0), radius: 0.2, two_sided: 1 }};
+                 let window = RectLight {{
+                   position: vec3(0.0, 0.0, -2.0),
+                   normal: vec3(0.0, 0.0, {normal_z}),
+                   width: 2.0,
+                   height: 1.5,
+                   color: #ffffff,
+                   intensity: 2.0
+                 }};
+                 let scene = Sphere {{ radius: 0.3, pos: vec3(0.0, -3.0, 0.0) }};"
+            )
+        };
+        let render = |normal_z| {
+            let program = parse_program(&scene(normal_z)).expect("parse");
+            let state = eval_program(&program).expect("eval");
+            let (lights, path_lights) = super::parse_lights(&state, &[], None);
+            assert_eq!(lights.len(), 4);
+            assert!(matches!(
+                path_lights.as_slice(),
+                [
+                    super::PathLight::Disk(_),
+                    super::PathLight::Spot(_),
+                    super::PathLight::Directional(_),
+                    super::PathLight::Rect(_)
+                ]
+            ));
+            super::render_pathtrace_checkpointed_with_accel(
+                &state,
+                0,
+                RenderOptions {
+                    width: 9,
+                    height: 9,
+                    ..RenderOptions::default()
+                },
+                AccelMode::Bvh,
+                PathtraceSettings {
+                    spp: 2,
+                    max_bounces: 2,
+                    noise_threshold: 0.0,
+                    ..PathtraceSettings::default()
+                },
+                false,
+                None,
+                |_, _| Ok(()),
+                |_| Ok(()),
+            )
+            .expect("pathtrace should succeed")
+        };
+
+        // The window faces the camera and fills the middle of the frame with its radiance.
+        let facing = render(1.0);
+        let center = facing.beauty[4 * 9 + 4];
+        assert!(
+            center.iter().all(|c| (c - 2.0).abs() < 1.0e-3),
+            "{center:?}"
+        );
+        // Turned away, a single-sided window is dark from the front.
+        let away = render(-1.0);
+        assert_eq!(away.beauty[4 * 9 + 4], [0.0; 3]);
+    }
+
+    #[test]
+    fn resumed_pathtrace_matches_uninterrupted_render() {
+        let program = parse_program("let scene = Sphere { radius: 0.9 };").expect("parse");
+        let state = eval_program(&program).expect("eval");
+        let options = RenderOptions {
+            width: 12,
+            height: 12,
+            ..RenderOptions::default()
+        };
+        // Adaptive sampling stops converged pixels early, and the resumed render has to stop
+        // the same ones. The short run clamps `min_spp` to its 3 samples, so pixels it stopped
+        // must sample again once resumed.
+        let settings = |spp| PathtraceSettings {
+            spp,
+            max_bounces: 2,
+            preview_every: 2,
+            min_spp: 4,
+            noise_threshold: 0.05,
+            ..PathtraceSettings::default()
+        };
+        let render = |scene_hash, options, settings, resume| {
+            let mut last = None;
+            super::render_pathtrace_checkpointed_with_accel(
+                &state,
+                scene_hash,
+                options,
+                AccelMode::Bvh,
+                settings,
+                false,
+                resume,
+                |_, _| Ok(()),
+                |checkpoint| {
+                    last = Some(checkpoint.clone());
+                    Ok(())
+                },
+            )
+            .map(|layers| (layers, last))
+        };
+
+        let (_, partial) = render(7, options, settings(3), None).expect("pathtrace should succeed");
+        let partial = partial.expect("at least one checkpoint");
+        assert_eq!(partial.samples_done(), 3);
+        let path = std::env::temp_dir().join("forgedthoughts-resume-test.ftck");
+        partial.save(&path).expect("checkpoint should save");
+        let loaded = super::PathtraceCheckpoint::load(&path).expect("checkpoint should load");
+        // A truncated file or a header asking for far more pixels than the file holds is
+        // refused before anything is allocated for it.
+        let bytes = std::fs::read(&path).expect("checkpoint should read");
+        std::fs::write(&path, &bytes[..bytes.len() - 10]).expect("checkpoint should write");
+        let truncated = super::PathtraceCheckpoint::load(&path);
+        assert!(matches!(truncated, Err(super::RenderError::Checkpoint(_))));
+        let mut huge = bytes.clone();
+        huge[8..16].copy_from_slice(&[0xff; 8]);
+        std::fs::write(&path, &huge).expect("checkpoint should write");
+        let huge = super::PathtraceCheckpoint::load(&path);
+        assert!(matches!(huge, Err(super::RenderError::Checkpoint(_))));
+        let _ = std::fs::remove_file(&path);
+
+        let (resumed, finished) =
+            render(7, options, settings(8), Some(loaded)).expect("resume should succeed");
+        let (straight, _) =
+            render(7, options, settings(8), None).expect("pathtrace should succeed");
+        assert!(finished.is_some_and(|checkpoint| checkpoint.samples_done() > 3));
+        assert_eq!(resumed.beauty, straight.beauty);
+
+        let wrong_size = render(
+            7,
+            RenderOptions {
+                width: 8,
+                ..options
+            },
+            settings(8),
+            Some(partial.clone()),
+        );
+        assert!(matches!(wrong_size, Err(super::RenderError::Checkpoint(_))));
+        let other_scene = render(8, options, settings(8), Some(partial.clone()));
+        assert!(matches!(
+            other_scene,
+            Err(super::RenderError::Checkpoint(_))
+        ));
+        let other_settings = render(
+            7,
+            options,
+            PathtraceSettings {
+                max_bounces: 3,
+                ..settings(8)
+            },
+            Some(partial),
+        );
+        assert!(matches!(
+            other_settings,
+            Err(super::RenderError::Checkpoint(_))
+        ));
+    }
+
+    #[test]
+    fn denoiser_smooths_noise_without_crossing_normal_edges() {
+        let (width, height) = (32_u32, 16_u32);
+        let mut rng = super::XorShift64::new(7);
+        let input = (0..width * height)
+            .map(|idx| {
+                let left = idx % width < width / 2;
+                let base = if left { 0.6 } else { 0.1 };
+                let v = base * (0.4 + 1.2 * rng.next_f32());
+                super::DenoiseInput {
+                    color: [v; 3],
+                    variance: (base * 0.35_f32).powi(2),
+                    features: Some(super::AovSample {
+                        depth: 4.0,
+                        normal: if left {
+                            [0.0, 0.0, 1.0]
+                        } else {
+                            [1.0, 0.0, 0.0]
+                        },
+                        albedo: [0.8; 3],
+                        material_id: 0,
+                        ior: 1.0,
+                        transmission: 0.0,
+                        fresnel: 0.0,
+                    }),
+                }
+            })
+            .collect::<Vec<_>>();
+        let output = super::denoise::denoise(width, height, &input);
+
+        let column_stats = |pixels: &[[f32; 3]], x: u32| {
+            let values = (0..height)
+                .map(|y| pixels[(y * width + x) as usize][0])
+                .collect::<Vec<_>>();
+            let mean = values.iter().sum::<f32>() / values.len() as f32;
+            let variance =
+                values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / values.len() as f32;
+            (mean, variance)
+        };
+        let noisy = input.iter().map(|px| px.color).collect::<Vec<_>>();
+        let (_, noisy_variance) = column_stats(&noisy, 4);
+        let (_, denoised_variance) = column_stats(&output, 4);
+        assert!(denoised_variance < noisy_variance * 0.25);
+
+        // Columns on either side of the crease keep their own level.
+        let (inside, _) = column_stats(&output, width / 2 - 1);
+        let (outside, _) = column_stats(&output, width / 2);
+        assert!((inside - 0.6).abs() < 0.1, "left side drifted to {inside}");
+        assert!(
+            (outside - 0.1).abs() < 0.03,
+            "right side drifted to {outside}"
+        );
+    }
+
     #[test]
     fn extracts_render_settings_from_bindings() {
         let mut render_fields = HashMap::new();
@@ -8326,34 +10363,253 @@ mod tests {
     }
 
     #[test]
-    fn extracts_legacy_material_as_dielectric_from_scene_object() {
-        let mut material_fields = HashMap::new();
-        material_fields.insert("roughness".to_string(), Value::Number(0.2));
-        material_fields.insert("metallic".to_string(), Value::Number(0.7));
-        material_fields.insert("specular".to_string(), Value::Number(0.9));
-        material_fields.insert("specular_weight".to_string(), Value::Number(0.8));
-        material_fields.insert("specular_color".to_string(), vec3_value(0.7, 0.8, 0.9));
-        material_fields.insert("ior".to_string(), Value::Number(1.65));
-        material_fields.insert("clearcoat".to_string(), Value::Number(0.3));
-        material_fields.insert("clearcoat_roughness".to_string(), Value::Number(0.12));
-        material_fields.insert("transmission".to_string(), Value::Number(0.55));
-        material_fields.insert("thin_walled".to_string(), Value::Number(1.0));
-        material_fields.insert("anisotropy".to_string(), Value::Number(-0.25));
-        material_fields.insert("anisotropy_rotation".to_string(), Value::Number(0.33));
-        material_fields.insert("emission_color".to_string(), vec3_value(0.1, 0.2, 0.3));
-        material_fields.insert("emission_strength".to_string(), Value::Number(3.0));
-        material_fields.insert("base_color".to_string(), vec3_value(0.9, 0.5, 0.2));
-        let material = Value::Object(crate::ObjectValue {
-            type_name: Some("Material".to_string()),
-            fields: material_fields,
-        });
+    fn applies_display_transform_from_render_settings() {
+        let source = r#"
+            let render = RenderSettings {
+              exposure: -1.0,
+              tonemap: Aces {},
+              dither: 1
+            };
+        "#;
+        let program = parse_program(source).expect("program should parse");
+        let state = eval_program(&program).expect("program should evaluate");
+        let settings = extract_scene_render_settings(&state);
+        assert_eq!(settings.exposure, Some(-1.0));
+        assert_eq!(settings.tonemap, Some(super::Tonemap::Aces));
+        assert_eq!(settings.dither, Some(true));
+        assert_eq!(super::parse_tonemap("AgX"), Some(super::Tonemap::Agx));
+
+        let grey = crate::Spectrum::rgb(0.18, 0.18, 0.18);
+        let linear = DisplayTransform {
+            tonemap: super::Tonemap::Linear,
+            ..DisplayTransform::default()
+        };
+        // sRGB encodes 18% grey at 118, 1.0 at full white, and exposure scales in stops.
+        assert_eq!(linear.to_rgb8(grey, 0, 0), [118; 3]);
+        assert_eq!(
+            linear.to_rgb8(crate::Spectrum::rgb(1.0, 1.0, 1.0), 0, 0),
+            [255; 3]
+        );
+        let brighter = DisplayTransform {
+            exposure: 1.0,
+            ..linear
+        };
+        assert_eq!(
+            brighter.to_rgb8(grey, 0, 0),
+            linear.to_rgb8(crate::Spectrum::rgb(0.36, 0.36, 0.36), 0, 0)
+        );
 
-        let mut sphere_fields = HashMap::new();
-        sphere_fields.insert("material".to_string(), material);
-        let sphere = Value::Object(crate::ObjectValue {
-            type_name: Some("Sphere".to_string()),
-            fields: sphere_fields,
-        });
+        // Every operator keeps ordering and compresses highlights instead of clipping them.
+        for tonemap in [
+            super::Tonemap::Reinhard,
+            super::Tonemap::Filmic,
+            super::Tonemap::Aces,
+            super::Tonemap::Agx,
+        ] {
+            let display = DisplayTransform {
+                tonemap,
+                ..DisplayTransform::default()
+            };
+            let values = [0.05, 0.18, 1.0, 4.0]
+                .map(|v| display.to_rgb8(crate::Spectrum::rgb(v, v, v), 0, 0)[0]);
+            assert!(
+                values.windows(2).all(|w| w[0] < w[1]),
+                "{tonemap:?}: {values:?}"
+            );
+            assert!(values[3] < 255, "{tonemap:?} clipped at 4.0");
+        }
+
+        let dithered = DisplayTransform {
+            dither: true,
+            ..linear
+        };
+        let pixels: Vec<_> = (0..64).map(|x| dithered.to_rgb8(grey, x, 3)[0]).collect();
+        assert!(pixels.iter().all(|v| v.abs_diff(118) <= 1));
+        assert!(pixels.iter().any(|v| *v != 118));
+        assert_eq!(dithered.to_rgb8(grey, 5, 3), dithered.to_rgb8(grey, 5, 3));
+    }
+
+    #[test]
+    fn parses_thin_lens_camera_focused_on_object() {
+        let source = r#"
+            let ball = Sphere { radius: 0.5, pos: vec3(0.0, 0.0, -3.0) };
+            let camera = Camera {
+              origin: vec3(0.0, 0.0, 5.0),
+              target: vec3(0.0, 0.0, 0.0),
+              fov_y: 40.0,
+              f_stop: 2.0,
+              focus: ball,
+              blades: 5
+            };
+        "#;
+        let program = parse_program(source).expect("program should parse");
+        let state = eval_program(&program).expect("program should evaluate");
+        let crate::CameraKind::ThinLens(camera) = super::parse_camera(
+            &state,
+            super::Vec3::new(0.0, 0.0, 0.0),
+            RenderOptions::default(),
+        ) else {
+            panic!("f_stop should select the thin-lens camera");
+        };
+        assert!((camera.focus_distance - 8.0).abs() < 1.0e-4);
+        let focal_length = 0.012 / 20.0_f32.to_radians().tan();
+        assert!((camera.aperture_radius - focal_length / 4.0).abs() < 1.0e-6);
+        assert_eq!(camera.blades, 5);
+
+        let program =
+            parse_program("let camera = Camera { fov_y: 40.0 };").expect("program should parse");
+        let pinhole = eval_program(&program).expect("program should evaluate");
+        assert!(matches!(
+            super::parse_camera(
+                &pinhole,
+                super::Vec3::new(0.0, 0.0, 0.0),
+                RenderOptions::default()
+            ),
+            crate::CameraKind::Pinhole(_)
+        ));
+    }
+
+    #[test]
+    fn parses_camera_projection() {
+        let camera_for = |fields: &str| {
+            let source =
+                format!("let camera = Camera {{ target: vec3(0.0, 0.0, -1.0), {fields} }};");
+            let program = parse_program(&source).expect("program should parse");
+            let state = eval_program(&program).expect("program should evaluate");
+            super::parse_camera(
+                &state,
+                super::Vec3::new(0.0, 0.0, 0.0),
+                RenderOptions {
+                    width: 200,
+                    height: 100,
+                    ..RenderOptions::default()
+                },
+            )
+        };
+        let crate::CameraKind::Orthographic(ortho) =
+            camera_for("projection: Ortho {}, ortho_height: 3.5")
+        else {
+            panic!("Ortho{{}} should select the orthographic camera");
+        };
+        assert_eq!(ortho.ortho_height, 3.5);
+        let crate::CameraKind::Equirect(equirect) =
+            camera_for("projection: \"equirect\", stereo: 1, ipd: 0.07")
+        else {
+            panic!("equirect should select the panoramic camera");
+        };
+        assert!(equirect.stereo);
+        assert_eq!(equirect.eye_separation, 0.07);
+        assert_eq!(equirect.aspect, 2.0);
+        assert!(matches!(
+            camera_for("projection: Cubemap {}"),
+            crate::CameraKind::Cubemap(_)
+        ));
+    }
+
+    #[test]
+    fn poses_moving_objects_and_camera_at_the_sample_time() {
+        let source = r#"
+            let camera = Camera {
+              origin: vec3(0.0, 0.0, 5.0),
+              origin_close: vec3(2.0, 0.0, 5.0),
+              target: vec3(0.0, 0.0, 0.0)
+            };
+            let keyed = Sphere { radius: 0.5, pos_close: vec3(2.0, 0.0, 0.0) };
+            var hooked = Sphere { radius: 0.5, pos: vec3(0.0, 3.0, 0.0) };
+            hooked.motion = fn(t) { return vec3(0.0, 3.0, t * 4.0); };
+            let scene = keyed + hooked;
+        "#;
+        let program = parse_program(source).expect("program should parse");
+        let state = eval_program(&program).expect("program should evaluate");
+        let shutter = super::Shutter {
+            open: 0.0,
+            close: 0.5,
+        };
+        let scene = super::compile_scene(
+            &state,
+            state
+                .bindings
+                .get("scene")
+                .map(|b| &b.value)
+                .expect("scene binding"),
+            super::default_material(),
+            shutter,
+        )
+        .expect("scene should compile");
+
+        let distance_at =
+            |time: f32, p: super::Vec3| super::sdf_distance_info(&scene.root, p, time).distance;
+        // The keyed sphere travels from the origin to x = 2 over the shutter.
+        assert!((distance_at(0.0, super::Vec3::new(0.0, 0.0, 0.0)) + 0.5).abs() < 1.0e-4);
+        assert!((distance_at(0.25, super::Vec3::new(1.0, 0.0, 0.0)) + 0.5).abs() < 1.0e-4);
+        assert!((distance_at(0.5, super::Vec3::new(2.0, 0.0, 0.0)) + 0.5).abs() < 1.0e-4);
+        // The hook gets scene time, so it ends at z = 0.5 * 4.
+        assert!((distance_at(0.5, super::Vec3::new(0.0, 3.0, 2.0)) + 0.5).abs() < 1.0e-4);
+
+        let bounds = super::sdf_bounds(&scene.root);
+        assert!(bounds.min.x <= -0.5 && bounds.max.x >= 2.5);
+        assert!(bounds.max.z >= 2.5);
+
+        let camera = super::parse_camera(
+            &state,
+            super::Vec3::new(0.0, 0.0, 0.0),
+            RenderOptions {
+                shutter,
+                ..RenderOptions::default()
+            },
+        );
+        let crate::CameraKind::Motion(_) = &camera else {
+            panic!("origin_close should make the camera move");
+        };
+        let open = crate::Camera::generate_ray_at(&camera, 0.0, 0.0, 0.5, 0.5, 0.0);
+        let close = crate::Camera::generate_ray_at(&camera, 0.0, 0.0, 0.5, 0.5, 0.5);
+        assert_eq!(open.time, 0.0);
+        assert!((open.origin.x - 0.0).abs() < 1.0e-5);
+        assert!((close.origin.x - 2.0).abs() < 1.0e-5);
+
+        let program = parse_program(
+            "var s = Sphere { radius: 0.5 }; s.motion = fn(t) { return offset * t; };",
+        )
+        .expect("program should parse");
+        let state = eval_program(&program).expect("program should evaluate");
+        let result = super::compile_scene(
+            &state,
+            &state.bindings["s"].value,
+            super::default_material(),
+            shutter,
+        );
+        assert!(matches!(result, Err(super::RenderError::Motion(_))));
+    }
+
+    #[test]
+    fn extracts_legacy_material_as_dielectric_from_scene_object() {
+        let mut material_fields = HashMap::new();
+        material_fields.insert("roughness".to_string(), Value::Number(0.2));
+        material_fields.insert("metallic".to_string(), Value::Number(0.7));
+        material_fields.insert("specular".to_string(), Value::Number(0.9));
+        material_fields.insert("specular_weight".to_string(), Value::Number(0.8));
+        material_fields.insert("specular_color".to_string(), vec3_value(0.7, 0.8, 0.9));
+        material_fields.insert("ior".to_string(), Value::Number(1.65));
+        material_fields.insert("clearcoat".to_string(), Value::Number(0.3));
+        material_fields.insert("clearcoat_roughness".to_string(), Value::Number(0.12));
+        material_fields.insert("transmission".to_string(), Value::Number(0.55));
+        material_fields.insert("thin_walled".to_string(), Value::Number(1.0));
+        material_fields.insert("anisotropy".to_string(), Value::Number(-0.25));
+        material_fields.insert("anisotropy_rotation".to_string(), Value::Number(0.33));
+        material_fields.insert("emission_color".to_string(), vec3_value(0.1, 0.2, 0.3));
+        material_fields.insert("emission_strength".to_string(), Value::Number(3.0));
+        material_fields.insert("base_color".to_string(), vec3_value(0.9, 0.5, 0.2));
+        let material = Value::Object(crate::ObjectValue {
+            type_name: Some("Material".to_string()),
+            fields: material_fields,
+        });
+
+        let mut sphere_fields = HashMap::new();
+        sphere_fields.insert("material".to_string(), material);
+        let sphere = Value::Object(crate::ObjectValue {
+            type_name: Some("Sphere".to_string()),
+            fields: sphere_fields,
+        });
 
         let mut bindings = HashMap::new();
         bindings.insert(
@@ -8479,6 +10735,65 @@ mod tests {
         assert!(thin_walled);
     }
 
+    #[test]
+    fn parses_dispersion_and_measured_conductors() {
+        let mut glass_fields = HashMap::new();
+        glass_fields.insert("ior".to_string(), Value::Number(1.5168));
+        glass_fields.insert("abbe".to_string(), Value::Number(64.17));
+        let glass = Value::Object(crate::ObjectValue {
+            type_name: Some("Dielectric".to_string()),
+            fields: glass_fields,
+        });
+        let mut gold_fields = HashMap::new();
+        gold_fields.insert("conductor".to_string(), Value::String("gold".to_string()));
+        let gold = Value::Object(crate::ObjectValue {
+            type_name: Some("Metal".to_string()),
+            fields: gold_fields,
+        });
+        let state = empty_state(HashMap::new());
+        let object = |material: Value| {
+            let mut fields = HashMap::new();
+            fields.insert("material".to_string(), material);
+            Value::Object(crate::ObjectValue {
+                type_name: Some("Sphere".to_string()),
+                fields,
+            })
+        };
+
+        let super::MaterialKindRt::Dielectric(glass) =
+            super::parse_material(&state, &object(glass))
+        else {
+            panic!("expected Dielectric material");
+        };
+        // BK7 has a Cauchy B of about 0.0042 µm².
+        assert!((glass.cauchy_b - 0.0042).abs() < 2.0e-4);
+        assert!(glass.ior_at(486.1) > glass.ior_at(587.6));
+        assert!(glass.ior_at(587.6) > glass.ior_at(656.3));
+        assert!((glass.ior_at(587.6) - 1.5168).abs() < 1.0e-4);
+
+        let super::MaterialKindRt::Metal(gold) = super::parse_material(&state, &object(gold))
+        else {
+            panic!("expected Metal material");
+        };
+        let f0 = gold.conductor.expect("gold conductor").fresnel(1.0);
+        assert!(f0.r > 0.9 && f0.r > f0.g && f0.g > f0.b);
+    }
+
+    #[test]
+    fn spectral_upsampling_round_trips_colors() {
+        let color = super::Spectrum::rgb(0.8, 0.5, 0.2);
+        let samples = 4096;
+        let mut sum = super::Spectrum::black();
+        for i in 0..samples {
+            let wavelengths = super::Wavelengths::sample((i as f32 + 0.5) / samples as f32);
+            sum = sum + wavelengths.to_rgb(wavelengths.lift(color));
+        }
+        let mean = sum.scale(1.0 / samples as f32);
+        assert!((mean.r - color.r).abs() < 0.02);
+        assert!((mean.g - color.g).abs() < 0.02);
+        assert!((mean.b - color.b).abs() < 0.02);
+    }
+
     #[test]
     fn parses_material_fields_computed_from_ft_expressions() {
         let source = r#"
@@ -8551,9 +10866,11 @@ mod tests {
                 .map(|b| &b.value)
                 .expect("scene binding"),
             super::default_material(),
+            super::Shutter::default(),
         )
         .expect("scene should compile");
-        let setup = super::build_render_setup(&state, &scene, RenderOptions::default());
+        let setup = super::build_render_setup(&state, &scene, RenderOptions::default())
+            .expect("render setup");
 
         let hit_a = super::RayHit {
             t: 1.0,
@@ -8562,6 +10879,7 @@ mod tests {
             front_face: true,
             object_id: 1,
             material_id: 1,
+            time: 0.0,
         };
         let hit_b = super::RayHit {
             t: 1.0,
@@ -8615,9 +10933,11 @@ mod tests {
                 .map(|b| &b.value)
                 .expect("scene binding"),
             super::default_material(),
+            super::Shutter::default(),
         )
         .expect("scene should compile");
-        let setup = super::build_render_setup(&state, &scene, RenderOptions::default());
+        let setup = super::build_render_setup(&state, &scene, RenderOptions::default())
+            .expect("render setup");
 
         let hit_warm = super::RayHit {
             t: 1.0,
@@ -8626,6 +10946,7 @@ mod tests {
             front_face: true,
             object_id: 1,
             material_id: 1,
+            time: 0.0,
         };
         let hit_dark = super::RayHit {
             t: 1.0,
@@ -8705,9 +11026,11 @@ mod tests {
                 .map(|b| &b.value)
                 .expect("scene binding"),
             super::default_material(),
+            super::Shutter::default(),
         )
         .expect("scene should compile");
-        let setup = super::build_render_setup(&state, &scene, RenderOptions::default());
+        let setup = super::build_render_setup(&state, &scene, RenderOptions::default())
+            .expect("render setup");
 
         let hit_smooth = super::RayHit {
             t: 1.0,
@@ -8716,6 +11039,7 @@ mod tests {
             front_face: true,
             object_id: 1,
             material_id: 1,
+            time: 0.0,
         };
         let hit_rough = super::RayHit {
             t: 1.0,
@@ -8739,6 +11063,165 @@ mod tests {
         assert!((mat_smooth.color.r - 0.8).abs() < 1.0e-6);
     }
 
+    #[test]
+    fn resolves_ft_openpbr_material_layers() {
+        let source = r#"
+            material CarPaint {
+              model: OpenPbr;
+              base_color = vec3(0.5, 0.05, 0.08);
+              coat_weight = 1.0;
+              coat_roughness = 0.04;
+              coat_ior = 1.5;
+              fuzz_weight = 0.25;
+              specular_anisotropy = 0.6;
+              fn thin_film_thickness(ctx) {
+                return 0.3 + step(0.0, ctx.local_position.x) * 0.4;
+              }
+            };
+
+            let scene = Sphere {
+              material: CarPaint {}
+            };
+        "#;
+
+        let program = parse_program(source).expect("program should parse");
+        let state = eval_program(&program).expect("program should evaluate");
+        let scene = super::compile_scene(
+            &state,
+            state
+                .bindings
+                .get("scene")
+                .map(|b| &b.value)
+                .expect("scene binding"),
+            super::default_material(),
+            super::Shutter::default(),
+        )
+        .expect("scene should compile");
+        let setup = super::build_render_setup(&state, &scene, RenderOptions::default())
+            .expect("render setup");
+
+        let hit = super::RayHit {
+            t: 1.0,
+            position: super::Vec3::new(0.1, 0.0, 0.0),
+            normal: super::Vec3::new(0.0, 1.0, 0.0),
+            front_face: true,
+            object_id: 1,
+            material_id: 1,
+            time: 0.0,
+        };
+        let super::MaterialKindRt::OpenPbr(mat) =
+            super::resolve_material_at_hit(&setup, hit, super::Vec3::new(0.0, 0.0, 1.0))
+        else {
+            panic!("expected OpenPbr material");
+        };
+
+        assert!((mat.color.r - 0.5).abs() < 1.0e-6);
+        assert!((mat.clearcoat - 1.0).abs() < 1.0e-6);
+        assert!((mat.clearcoat_roughness - 0.04).abs() < 1.0e-6);
+        assert!((mat.layers.coat_ior - 1.5).abs() < 1.0e-6);
+        assert!((mat.layers.fuzz_weight - 0.25).abs() < 1.0e-6);
+        assert!((mat.layers.specular_anisotropy - 0.6).abs() < 1.0e-6);
+        assert!((mat.layers.thin_film_thickness - 0.7).abs() < 1.0e-6);
+        assert!((mat.specular - 1.0).abs() < 1.0e-6);
+    }
+
+    #[test]
+    fn openpbr_specular_scales_specular_weight() {
+        let material = |fields: &str| {
+            let source = format!("let scene = Sphere {{ material: OpenPbr {{ {fields} }} }};");
+            let program = parse_program(&source).expect("program should parse");
+            let state = eval_program(&program).expect("program should evaluate");
+            let scene = &state.bindings.get("scene").expect("scene binding").value;
+            let super::MaterialKindRt::OpenPbr(params) = super::parse_material(&state, scene)
+            else {
+                panic!("expected OpenPbr material");
+            };
+            params
+        };
+        let eval = |params: super::MaterialParams| {
+            let normal = crate::render_api::Vec3::new(0.0, 1.0, 0.0);
+            let wi = crate::render_api::Vec3::new(0.3, 0.9, 0.0).normalize();
+            let wo = crate::render_api::Vec3::new(-0.3, 0.9, 0.1).normalize();
+            crate::materials::Material::OpenPbr(params).eval(normal, wi, wo)
+        };
+
+        // Unlike `Standard`, `specular` defaults to 1 and leaves the OpenPBR weight in charge.
+        let plain = material("metallic: 1.0");
+        assert!((plain.specular - 1.0).abs() < 1.0e-6);
+        let halved = eval(material("metallic: 1.0, specular: 0.5"));
+        let weighted = eval(material("metallic: 1.0, specular_weight: 0.5"));
+        assert!((halved.g - weighted.g).abs() < 1.0e-6);
+        assert!((halved.g - eval(plain).g * 0.5).abs() < 1.0e-5);
+    }
+
+    #[test]
+    fn openpbr_layers_do_not_create_energy() {
+        let mut params = super::MaterialParams::standard(
+            super::Spectrum::rgb(1.0, 1.0, 1.0),
+            0.3,
+            0.0,
+            1.0,
+            1.0,
+            super::Spectrum::rgb(1.0, 1.0, 1.0),
+            1.5,
+            1.0,
+            0.1,
+            0.0,
+            false,
+            super::Spectrum::black(),
+            0.0,
+        );
+        params.layers.fuzz_weight = 1.0;
+        params.layers.thin_film_weight = 1.0;
+        params.layers.specular_anisotropy = 0.5;
+        let normal = crate::render_api::Vec3::new(0.0, 1.0, 0.0);
+        let strata = 64;
+        for wo in [
+            crate::render_api::Vec3::new(0.0, 1.0, 0.0),
+            crate::render_api::Vec3::new(0.6, 0.8, 0.0),
+            crate::render_api::Vec3::new(0.0, 0.2, 0.98).normalize(),
+        ] {
+            for metallic in [0.0, 1.0] {
+                let mut albedo = super::Spectrum::black();
+                for i in 0..strata {
+                    for j in 0..strata {
+                        let sample = super::MaterialKindRt::OpenPbr(super::MaterialParams {
+                            metallic,
+                            ..params
+                        })
+                        .sample(
+                            normal,
+                            wo,
+                            super::MaterialSampleInput {
+                                front_face: true,
+                                current_ior: 1.0,
+                                interior_ior: 1.0,
+                                exterior_ior: 1.0,
+                                u1: (i as f32 + 0.5) / strata as f32,
+                                u2: (j as f32 + 0.5) / strata as f32,
+                                u3: ((i * strata + j) as f32 * 0.618_034).fract(),
+                            },
+                        );
+                        if sample.pdf > 0.0 {
+                            let cos = if sample.apply_cos {
+                                sample.wi.dot(normal).abs()
+                            } else {
+                                1.0
+                            };
+                            albedo = albedo + sample.f.scale(cos / sample.pdf);
+                        }
+                    }
+                }
+                let albedo = albedo.scale(1.0 / (strata * strata) as f32);
+                assert!(
+                    albedo.r < 1.02 && albedo.g < 1.02 && albedo.b < 1.02,
+                    "albedo {albedo:?} for wo {wo:?}, metallic {metallic}"
+                );
+                assert!(albedo.r > 0.9, "albedo {albedo:?} for wo {wo:?}");
+            }
+        }
+    }
+
     #[test]
     fn resolves_ft_material_dynamic_dielectric_hooks() {
         let source = r#"
@@ -8774,9 +11257,11 @@ mod tests {
                 .map(|b| &b.value)
                 .expect("scene binding"),
             super::default_material(),
+            super::Shutter::default(),
         )
         .expect("scene should compile");
-        let setup = super::build_render_setup(&state, &scene, RenderOptions::default());
+        let setup = super::build_render_setup(&state, &scene, RenderOptions::default())
+            .expect("render setup");
 
         let hit_solid = super::RayHit {
             t: 1.0,
@@ -8785,6 +11270,7 @@ mod tests {
             front_face: true,
             object_id: 1,
             material_id: 1,
+            time: 0.0,
         };
         let hit_thin = super::RayHit {
             t: 1.0,
@@ -8839,7 +11325,7 @@ mod tests {
         };
 
         let medium = mat.medium.expect("expected medium params");
-        assert!((medium.ior - 1.33).abs() < 1.0e-6);
+        assert!(medium.ior.is_some_and(|ior| (ior - 1.33).abs() < 1.0e-6));
         assert!((medium.absorption_color.b - 1.0).abs() < 1.0e-6);
         assert!((medium.density - 0.35).abs() < 1.0e-6);
 
@@ -8904,9 +11390,11 @@ mod tests {
                 .map(|b| &b.value)
                 .expect("scene binding"),
             super::default_material(),
+            super::Shutter::default(),
         )
         .expect("scene should compile");
-        let setup = super::build_render_setup(&state, &scene, RenderOptions::default());
+        let setup = super::build_render_setup(&state, &scene, RenderOptions::default())
+            .expect("render setup");
 
         let hit_a = super::RayHit {
             t: 1.0,
@@ -8915,6 +11403,7 @@ mod tests {
             front_face: true,
             object_id: 1,
             material_id: 1,
+            time: 0.0,
         };
         let hit_b = super::RayHit {
             t: 1.0,
@@ -8935,8 +11424,8 @@ mod tests {
 
         let medium_a = mat_a.medium.expect("expected medium params");
         let medium_b = mat_b.medium.expect("expected medium params");
-        assert!((medium_a.ior - 1.33).abs() < 1.0e-6);
-        assert!((medium_b.ior - 1.45).abs() < 1.0e-6);
+        assert!(medium_a.ior.is_some_and(|ior| (ior - 1.33).abs() < 1.0e-6));
+        assert!(medium_b.ior.is_some_and(|ior| (ior - 1.45).abs() < 1.0e-6));
         assert!((medium_a.density - 0.1).abs() < 1.0e-6);
         assert!((medium_b.density - 0.45).abs() < 1.0e-6);
 
@@ -8955,6 +11444,7 @@ mod tests {
             ior: 1.33,
             absorption_color: crate::Spectrum::rgb(0.5, 0.8, 1.0),
             density: 2.0,
+            ..super::MediumState::air()
         };
         let trans = super::medium_transmittance(medium, 1.5);
         assert!((trans.r - 0.125).abs() < 1.0e-6);
@@ -8978,18 +11468,188 @@ mod tests {
             0.25,
         ));
         let material = super::MaterialKindRt::Dielectric(params);
+        let hit = super::RayHit {
+            t: 1.0,
+            position: super::Vec3::new(0.0, 0.0, 0.0),
+            normal: super::Vec3::new(0.0, 1.0, 0.0),
+            front_face: true,
+            object_id: 1,
+            material_id: 1,
+            time: 0.0,
+        };
 
-        let entered = super::transition_medium(material, true, super::MediumState::air());
+        // The medium's own IOR wins; without one it inherits the material's.
+        let entered = super::transition_medium(material, hit, super::MediumState::air());
         assert!((entered.ior - 1.33).abs() < 1.0e-6);
+        let mut inherited = params;
+        inherited.medium = params.medium.map(|medium| crate::MediumParams {
+            ior: None,
+            ..medium
+        });
+        let inherited = super::transition_medium(
+            super::MaterialKindRt::Dielectric(inherited),
+            hit,
+            super::MediumState::air(),
+        );
+        assert!((inherited.ior - 1.52).abs() < 1.0e-6);
         assert!((entered.absorption_color.g - 0.8).abs() < 1.0e-6);
         assert!((entered.density - 0.25).abs() < 1.0e-6);
 
-        let exited = super::transition_medium(material, false, entered);
+        let back = super::RayHit {
+            front_face: false,
+            ..hit
+        };
+        let exited = super::transition_medium(material, back, entered);
         assert!((exited.ior - 1.0).abs() < 1.0e-6);
         assert!((exited.absorption_color.r - 1.0).abs() < 1.0e-6);
         assert!(exited.density.abs() < 1.0e-6);
     }
 
+    #[test]
+    fn refracts_into_a_liquid_by_its_medium_ior() {
+        let source = r#"
+            let scene = Sphere {
+              radius: 1.0,
+              material: Dielectric {
+                ior: 1.52,
+                medium: Medium { ior: 1.33 }
+              }
+            };
+        "#;
+        let program = parse_program(source).expect("program should parse");
+        let state = eval_program(&program).expect("program should evaluate");
+        let scene = super::compile_scene(
+            &state,
+            state
+                .bindings
+                .get("scene")
+                .map(|b| &b.value)
+                .expect("scene binding"),
+            super::default_material(),
+            super::Shutter::default(),
+        )
+        .expect("scene should compile");
+        let setup = super::build_render_setup(&state, &scene, RenderOptions::default())
+            .expect("render setup");
+        let hit = super::RayHit {
+            t: 1.0,
+            position: super::Vec3::new(0.0, 0.0, 1.0),
+            normal: super::Vec3::new(0.0, 0.0, 1.0),
+            front_face: true,
+            object_id: 1,
+            material_id: 1,
+            time: 0.0,
+        };
+        let view = super::Vec3::new(1.0, 0.0, 1.0).normalize();
+        let material = super::resolve_material_at_hit(&setup, hit, view);
+        let ctx = super::build_bsdf_context(&setup, hit, material, view, super::MediumState::air());
+
+        // The ray bends by the liquid it enters, not by the material's own 1.52.
+        assert!((ctx.interior_ior - 1.33).abs() < 1.0e-6);
+        let mut rng = super::XorShift64::new(7);
+        let refracted = (0..64)
+            .map(|_| super::sample_bsdf_lobe(&setup, material, ctx, &mut rng))
+            .find(|sample| sample.transmission)
+            .expect("some samples should refract");
+        let sin_t = std::f32::consts::FRAC_1_SQRT_2 / 1.33;
+        assert!((refracted.wi.x.abs() - sin_t).abs() < 1.0e-4);
+    }
+
+    #[test]
+    fn nested_media_follow_their_priorities() {
+        let dielectric = |ior: f32, priority: u32| {
+            let mut params = crate::MaterialParams::dielectric(
+                crate::Spectrum::rgb(1.0, 1.0, 1.0),
+                ior,
+                0.0,
+                false,
+                crate::Spectrum::black(),
+                0.0,
+            );
+            params.priority = priority;
+            super::MaterialKindRt::Dielectric(params)
+        };
+        let glass = dielectric(1.5, 2);
+        let water = dielectric(1.33, 1);
+        let hit = |material_id: u32, front_face: bool| super::RayHit {
+            t: 1.0,
+            position: super::Vec3::new(0.0, 0.0, 0.0),
+            normal: super::Vec3::new(0.0, 1.0, 0.0),
+            front_face,
+            object_id: material_id,
+            material_id,
+            time: 0.0,
+        };
+        let air = super::MediumState::air();
+
+        // Water overlapping the glass wall: its surface inside the glass is not a boundary,
+        // and the glass hands over to the water when the ray leaves the wall.
+        let in_glass = super::transition_medium(glass, hit(1, true), air);
+        assert!(in_glass.is_false_interface(water, hit(2, true)));
+        let in_both = super::transition_medium(water, hit(2, true), in_glass);
+        assert!((in_both.ior - 1.5).abs() < 1.0e-6);
+        assert!(!in_both.is_false_interface(glass, hit(1, false)));
+        assert!((in_both.exterior_ior(1) - 1.33).abs() < 1.0e-6);
+        let in_water = super::transition_medium(glass, hit(1, false), in_both);
+        assert!((in_water.ior - 1.33).abs() < 1.0e-6);
+
+        // Back into the glass on the far side of the liquid, then out through both.
+        assert!(!in_water.is_false_interface(glass, hit(1, true)));
+        let in_both = super::transition_medium(glass, hit(1, true), in_water);
+        assert!(in_both.is_false_interface(water, hit(2, false)));
+        let in_glass = super::transition_medium(water, hit(2, false), in_both);
+        assert!((in_glass.exterior_ior(1) - 1.0).abs() < 1.0e-6);
+        let outside = super::transition_medium(glass, hit(1, false), in_glass);
+        assert!(outside.active().is_none());
+        assert!((outside.ior - 1.0).abs() < 1.0e-6);
+    }
+
+    #[test]
+    fn nested_hits_agree_across_accelerators() {
+        let program = parse_program(
+            "let outer = Sphere { radius: 1.0 };
+             let inner = Sphere { radius: 0.4 };
+             let side = Sphere { radius: 0.3, pos: vec3(3.0, 0.0, 0.0) };
+             let scene = outer + inner + side;",
+        )
+        .expect("parse");
+        let state = eval_program(&program).expect("eval");
+        let scene = super::compile_scene(
+            &state,
+            &state.bindings.get("scene").expect("scene binding").value,
+            super::default_material(),
+            super::Shutter::default(),
+        )
+        .expect("scene should compile");
+        fn nested_hit<A: super::Accelerator + Sync>(scene: super::CompiledScene) -> super::RayHit {
+            let accel = A::from_scene(scene);
+            // From between the two shells towards the centre, the buried inner sphere is the
+            // next surface even though the outer one encloses it.
+            super::raymarch_nested_hit(
+                &accel,
+                super::Vec3::new(0.0, 0.0, 0.7),
+                super::Vec3::new(0.0, 0.0, -1.0),
+                RenderOptions::default(),
+                0.0,
+                10.0,
+                0.0,
+            )
+            .expect("inner sphere should be hit")
+        }
+
+        let naive = nested_hit::<super::NaiveAccel>(scene.clone());
+        assert!((naive.t - 0.3).abs() < 1.0e-2, "{}", naive.t);
+        assert!(naive.front_face);
+        for hit in [
+            nested_hit::<super::BvhAccel>(scene.clone()),
+            nested_hit::<super::BricksAccel>(scene),
+        ] {
+            assert!((hit.t - naive.t).abs() < 1.0e-3);
+            assert_eq!(hit.object_id, naive.object_id);
+            assert!(hit.normal.sub(naive.normal).length() < 1.0e-3);
+        }
+    }
+
     #[test]
     fn resolves_ft_custom_bsdf_hooks() {
         let source = r#"
@@ -9010,8 +11670,7 @@ mod tests {
                   delta: 0.0,
                   apply_cos: 1.0,
                   transmission: 0.0,
-                  thin_walled: 0.0,
-                  next_ior: ctx.current_ior
+                  thin_walled: 0.0
                 };
               }
             };
@@ -9031,9 +11690,11 @@ mod tests {
                 .map(|b| &b.value)
                 .expect("scene binding"),
             super::default_material(),
+            super::Shutter::default(),
         )
         .expect("scene should compile");
-        let setup = super::build_render_setup(&state, &scene, RenderOptions::default());
+        let setup = super::build_render_setup(&state, &scene, RenderOptions::default())
+            .expect("render setup");
         let hit = super::RayHit {
             t: 1.0,
             position: super::Vec3::new(0.0, 0.0, 1.0),
@@ -9041,6 +11702,7 @@ mod tests {
             front_face: true,
             object_id: 1,
             material_id: 1,
+            time: 0.0,
         };
         let mat = super::resolve_material_at_hit(&setup, hit, super::Vec3::new(0.0, 0.0, 1.0));
         let bsdf_ctx = super::BsdfContextBase {
@@ -9049,6 +11711,9 @@ mod tests {
             normal: super::Vec3::new(0.0, 0.0, 1.0),
             wo: super::Vec3::new(0.0, 0.0, 1.0),
             current_ior: 1.1,
+            interior_ior: 1.0,
+            exterior_ior: 1.0,
+            wavelengths: None,
         };
 
         let eval = super::eval_bsdf(&setup, mat, bsdf_ctx, super::Vec3::new(0.0, 0.0, 1.0));
@@ -9064,7 +11729,6 @@ mod tests {
         assert!((sample.f.r - 0.6).abs() < 1.0e-6);
         assert!((sample.f.g - 0.4).abs() < 1.0e-6);
         assert!((sample.pdf - 0.35).abs() < 1.0e-6);
-        assert!((sample.next_ior - 1.1).abs() < 1.0e-6);
     }
 
     #[test]
@@ -9092,9 +11756,11 @@ mod tests {
                 .map(|b| &b.value)
                 .expect("scene binding"),
             super::default_material(),
+            super::Shutter::default(),
         )
         .expect("scene should compile");
-        let setup = super::build_render_setup(&state, &scene, RenderOptions::default());
+        let setup = super::build_render_setup(&state, &scene, RenderOptions::default())
+            .expect("render setup");
         let hit = super::RayHit {
             t: 1.0,
             position: super::Vec3::new(0.0, 0.0, 1.0),
@@ -9102,9 +11768,16 @@ mod tests {
             front_face: true,
             object_id: 1,
             material_id: 1,
+            time: 0.0,
         };
 
-        let ctx = super::build_bsdf_context(&setup, hit, super::Vec3::new(0.0, 0.0, 1.0), 1.0);
+        let ctx = super::build_bsdf_context(
+            &setup,
+            hit,
+            super::default_material(),
+            super::Vec3::new(0.0, 0.0, 1.0),
+            super::MediumState::air(),
+        );
         assert!(ctx.normal.x > 0.2);
         assert!(ctx.normal.z > 0.9);
     }
@@ -9133,9 +11806,11 @@ mod tests {
                 .map(|b| &b.value)
                 .expect("scene binding"),
             super::default_material(),
+            super::Shutter::default(),
         )
         .expect("scene should compile");
-        let setup = super::build_render_setup(&state, &scene, RenderOptions::default());
+        let setup = super::build_render_setup(&state, &scene, RenderOptions::default())
+            .expect("render setup");
         let hit = super::RayHit {
             t: 1.0,
             position: super::Vec3::new(0.1, 0.0, 0.995),
@@ -9143,9 +11818,16 @@ mod tests {
             front_face: true,
             object_id: 1,
             material_id: 1,
+            time: 0.0,
         };
 
-        let ctx = super::build_bsdf_context(&setup, hit, super::Vec3::new(0.0, 0.0, 1.0), 1.0);
+        let ctx = super::build_bsdf_context(
+            &setup,
+            hit,
+            super::default_material(),
+            super::Vec3::new(0.0, 0.0, 1.0),
+            super::MediumState::air(),
+        );
         assert!(ctx.normal.dot(hit.normal.normalize()) < 0.9999);
     }
 
@@ -9192,9 +11874,11 @@ mod tests {
                 .map(|b| &b.value)
                 .expect("scene binding"),
             super::default_material(),
+            super::Shutter::default(),
         )
         .expect("scene should compile");
-        let setup = super::build_render_setup(&state, &scene, RenderOptions::default());
+        let setup = super::build_render_setup(&state, &scene, RenderOptions::default())
+            .expect("render setup");
         let hit = super::RayHit {
             t: 1.0,
             position: super::Vec3::new(0.1, 0.1, 1.0),
@@ -9202,6 +11886,7 @@ mod tests {
             front_face: true,
             object_id: 1,
             material_id: 1,
+            time: 0.0,
         };
 
         let super::MaterialKindRt::Standard(mat) =
@@ -9209,7 +11894,13 @@ mod tests {
         else {
             panic!("expected Standard material");
         };
-        let ctx = super::build_bsdf_context(&setup, hit, super::Vec3::new(0.0, 0.0, 1.0), 1.0);
+        let ctx = super::build_bsdf_context(
+            &setup,
+            hit,
+            super::default_material(),
+            super::Vec3::new(0.0, 0.0, 1.0),
+            super::MediumState::air(),
+        );
 
         assert!(mat.color.r > 0.7);
         assert!(mat.roughness > 0.8);
@@ -9258,9 +11949,11 @@ mod tests {
                 .map(|b| &b.value)
                 .expect("scene binding"),
             super::default_material(),
+            super::Shutter::default(),
         )
         .expect("scene should compile");
-        let setup = super::build_render_setup(&state, &scene, RenderOptions::default());
+        let setup = super::build_render_setup(&state, &scene, RenderOptions::default())
+            .expect("render setup");
 
         let hit_a = super::RayHit {
             t: 1.0,
@@ -9269,6 +11962,7 @@ mod tests {
             front_face: true,
             object_id: 1,
             material_id: 1,
+            time: 0.0,
         };
         let hit_b = super::RayHit {
             t: 1.0,
@@ -9277,6 +11971,7 @@ mod tests {
             front_face: true,
             object_id: 1,
             material_id: 1,
+            time: 0.0,
         };
 
         let super::MaterialKindRt::Standard(mat_a) =
@@ -9407,14 +12102,298 @@ mod tests {
                 .map(|b| &b.value)
                 .expect("scene binding"),
             super::default_material(),
+            super::Shutter::default(),
         )
         .expect("scene should compile");
-        let setup = super::build_render_setup(&state, &scene, RenderOptions::default());
+        let setup = super::build_render_setup(&state, &scene, RenderOptions::default())
+            .expect("render setup");
         let sky = super::environment_color(&setup, super::Vec3::new(0.0, 1.0, 0.0))
             .expect("environment color should resolve");
         assert!(sky.b > sky.r);
     }
 
+    #[test]
+    fn hdri_sampling_concentrates_on_bright_pixels_with_matching_pdf() {
+        let (width, height) = (16, 8);
+        let mut pixels = vec![[0.05_f32; 3]; width * height];
+        let bright = 2 * width + 11;
+        pixels[bright] = [400.0, 300.0, 200.0];
+        let map = super::HdriMap::from_pixels(width, height, pixels, 0.3, 1.0);
+
+        let mut rng = super::XorShift64::new(7);
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let (dir, pdf) = map.sample(rng.next_f32(), rng.next_f32());
+            assert!((pdf - map.pdf(dir)).abs() <= 1.0e-3 * pdf.max(1.0));
+            if map.radiance(dir).r > 100.0 {
+                hits += 1;
+            }
+        }
+        assert!(hits > 1500, "bright pixel picked {hits} of 2000 times");
+
+        // The density integrates to one over the sphere.
+        let mut integral = 0.0;
+        let samples = 20_000;
+        for _ in 0..samples {
+            let z = 1.0 - 2.0 * rng.next_f32();
+            let phi = 2.0 * std::f32::consts::PI * rng.next_f32();
+            let r = (1.0 - z * z).max(0.0).sqrt();
+            let dir = super::Vec3::new(r * phi.cos(), r * phi.sin(), z);
+            integral += map.pdf(dir) * 4.0 * std::f32::consts::PI;
+        }
+        integral /= samples as f32;
+        assert!((integral - 1.0).abs() < 0.1, "pdf integrates to {integral}");
+    }
+
+    #[test]
+    fn loads_hdri_environment_from_hdr_and_exr_files() {
+        let dir = std::env::temp_dir();
+        let (width, height) = (8_u32, 4_u32);
+        let beauty = vec![[0.25_f32, 0.5, 1.0]; (width * height) as usize];
+        let hdr =
+            image::Rgb32FImage::from_vec(width, height, beauty.iter().flatten().copied().collect())
+                .expect("image buffer");
+        hdr.save(dir.join("forgedthoughts-hdri-test.hdr"))
+            .expect("hdr should save");
+        let layers = super::RenderLayers {
+            width,
+            height,
+            beauty,
+            aovs: Vec::new(),
+            image: image::RgbImage::new(width, height),
+        };
+        std::fs::write(
+            dir.join("forgedthoughts-hdri-test.exr"),
+            layers.encode_exr(),
+        )
+        .expect("exr should save");
+
+        for file in [
+            "forgedthoughts-hdri-test.hdr",
+            "forgedthoughts-hdri-test.exr",
+        ] {
+            let source = format!(
+                r#"
+                let studio = HdriEnvironment {{
+                  path: "{file}",
+                  rotation: 90.0,
+                  intensity: 2.0
+                }};
+
+                let scene = Sphere {{
+                  radius: 1.0
+                }};
+            "#
+            );
+            let program = parse_program(&source).expect("program should parse");
+            let mut state = eval_program(&program).expect("program should evaluate");
+            state.base_dir = Some(dir.clone());
+            let scene = super::compile_scene(
+                &state,
+                &state.bindings.get("scene").expect("scene binding").value,
+                super::default_material(),
+                super::Shutter::default(),
+            )
+            .expect("scene should compile");
+            let setup = super::build_render_setup(&state, &scene, RenderOptions::default())
+                .expect("environment map should load");
+            let sky = super::environment_color(&setup, super::Vec3::new(0.3, 0.5, -0.8))
+                .expect("map should light the background");
+            assert!(
+                (sky.r - 0.5).abs() < 0.01 && (sky.b - 2.0).abs() < 0.02,
+                "{file}"
+            );
+            assert!(matches!(
+                setup.path_lights.as_slice(),
+                [super::PathLight::EnvMap]
+            ));
+        }
+
+        let program = parse_program(r#"let sky = HdriEnvironment { path: "missing.hdr" };"#)
+            .expect("program should parse");
+        let mut state = eval_program(&program).expect("program should evaluate");
+        state.base_dir = Some(dir.clone());
+        assert!(matches!(
+            super::parse_hdri(&state),
+            Err(super::RenderError::Environment(_))
+        ));
+        let _ = std::fs::remove_file(dir.join("forgedthoughts-hdri-test.hdr"));
+        let _ = std::fs::remove_file(dir.join("forgedthoughts-hdri-test.exr"));
+    }
+
+    #[test]
+    fn tabulates_procedural_environment_for_light_sampling() {
+        let sky = |settings: &str| {
+            format!(
+                r#"
+                environment Sky {{
+                  fn color(dir) {{
+                    let sun = pow(clamp(dir.x, 0.0, 1.0), 32.0) * 200.0;
+                    return vec3(0.2 + sun, 0.3 + sun, 0.5 + sun);
+                  }}
+                }};
+
+                let render = RenderSettings {{
+                  {settings}
+                }};
+
+                let scene = Sphere {{
+                  radius: 1.0
+                }};
+            "#
+            )
+        };
+        let setup_for = |source: &str| {
+            let program = parse_program(source).expect("program should parse");
+            let state = eval_program(&program).expect("program should evaluate");
+            let scene = super::compile_scene(
+                &state,
+                &state.bindings.get("scene").expect("scene binding").value,
+                super::default_material(),
+                super::Shutter::default(),
+            )
+            .expect("scene should compile");
+            super::build_render_setup(&state, &scene, RenderOptions::default())
+                .expect("render setup")
+        };
+
+        let setup = setup_for(&sky("env_resolution: 32"));
+        assert!(
+            setup
+                .path_lights
+                .iter()
+                .any(|light| matches!(light, super::PathLight::EnvMap))
+        );
+        let map = setup
+            .env_map
+            .as_ref()
+            .expect("environment should be tabulated");
+        let toward_sun = map.pdf(super::Vec3::new(1.0, 0.0, 0.0));
+        let away = map.pdf(super::Vec3::new(-1.0, 0.0, 0.0));
+        assert!(toward_sun > 100.0 * away, "{toward_sun} vs {away}");
+
+        // Light samples carry the exact procedural color, not the tabulated one.
+        let mut rng = super::XorShift64::new(3);
+        for _ in 0..32 {
+            let (dir, _) = map.sample(rng.next_f32(), rng.next_f32());
+            let exact = super::environment_color(&setup, dir).expect("sky color");
+            let expected = 0.2 + (dir.x.clamp(0.0, 1.0)).powf(32.0) * 200.0;
+            assert!((exact.r - expected).abs() <= 1.0e-3 * expected.max(1.0));
+        }
+
+        for settings in ["env_resolution: 0", "spp: 1"] {
+            let setup = setup_for(&sky(settings));
+            assert!(setup.env_map.is_none());
+            assert!(super::environment_color(&setup, super::Vec3::new(1.0, 0.0, 0.0)).is_some());
+        }
+    }
+
+    #[test]
+    fn random_walk_subsurface_reflects_its_albedo_color() {
+        let program = parse_program("let scene = Sphere { radius: 4.0 };").expect("parse");
+        let state = eval_program(&program).expect("eval");
+        let scene = super::compile_scene(
+            &state,
+            &state.bindings.get("scene").expect("scene binding").value,
+            super::default_material(),
+            super::Shutter::default(),
+        )
+        .expect("scene should compile");
+        let accel = <super::NaiveAccel as super::Accelerator>::from_scene(scene);
+        let options = RenderOptions {
+            max_steps: 256,
+            epsilon: 1.0e-4,
+            ..RenderOptions::default()
+        };
+        let params = super::SubsurfaceParams::new(
+            super::Spectrum::rgb(0.2, 0.5, 0.8),
+            super::ApiVec3::new(0.05, 0.05, 0.05),
+            0.0,
+            1.0,
+        );
+
+        // Light entering a deep, flat-looking body comes back out tinted with `color`.
+        let mut rng = super::XorShift64::new(11);
+        let walks = 3000;
+        let mut sum = super::Spectrum::black();
+        for _ in 0..walks {
+            let dir = super::cosine_sample_hemisphere(super::Vec3::new(0.0, -1.0, 0.0), &mut rng);
+            let origin = super::Vec3::new(0.0, 3.999, 0.0);
+            if let Some(exit) =
+                super::random_walk(&accel, options, params, origin, dir, &mut rng, 0.0)
+            {
+                assert!(
+                    exit.hit.position.y > 3.0,
+                    "walk left far from where it entered"
+                );
+                sum = sum + exit.weight;
+            }
+        }
+        let albedo = sum.scale(1.0 / walks as f32);
+        assert!((albedo.r - 0.2).abs() < 0.08, "red {}", albedo.r);
+        assert!((albedo.g - 0.5).abs() < 0.08, "green {}", albedo.g);
+        assert!((albedo.b - 0.8).abs() < 0.08, "blue {}", albedo.b);
+    }
+
+    #[test]
+    fn volumes_attenuate_by_their_density_field_and_the_fog() {
+        let source = r#"
+            volume Ramp {
+              let strength = 0.5;
+
+              fn density(p) {
+                return p.x * strength + 0.5;
+              }
+            };
+
+            let haze = Fog { density: 0.05 };
+            var ball = Sphere { radius: 0.5 };
+            ball.pos.z = 5.0;
+            let smoke = Box { size: vec3(2.0, 2.0, 2.0), volume: Ramp { density: 1.5 } };
+            let scene = ball + smoke;
+        "#;
+        let program = parse_program(source).expect("program should parse");
+        let state = eval_program(&program).expect("program should evaluate");
+        let scene = super::compile_scene(
+            &state,
+            &state.bindings.get("scene").expect("scene binding").value,
+            super::default_material(),
+            super::Shutter::default(),
+        )
+        .expect("scene should compile");
+        assert_eq!(scene.volumes.len(), 1, "the box should become a volume");
+        let setup = super::build_render_setup(&state, &scene, RenderOptions::default())
+            .expect("render setup");
+
+        // Across x = -3..3 the ramp integrates to 1.5 and the fog adds 0.05 per unit.
+        let air = super::MediumState::air();
+        let origin = super::Vec3::new(-3.0, 0.0, 0.0);
+        let dir = super::Vec3::new(1.0, 0.0, 0.0);
+        let expected = (-1.8_f32).exp();
+        let marched = super::marched_transmittance(&setup, air, origin, dir, 6.0, 0.0);
+        assert!((marched - expected).abs() < 0.01, "marched {marched}");
+
+        let mut rng = super::XorShift64::new(5);
+        let trials = 4000;
+        let mut ratio = 0.0;
+        let mut escaped = 0;
+        for _ in 0..trials {
+            ratio +=
+                super::medium_shadow_transmittance(&setup, air, origin, dir, 6.0, &mut rng, 0.0);
+            if super::sample_medium_scatter(&setup, air, origin, dir, 6.0, &mut rng, 0.0).is_none()
+            {
+                escaped += 1;
+            }
+        }
+        let ratio = ratio / trials as f32;
+        let escaped = escaped as f32 / trials as f32;
+        assert!((ratio - expected).abs() < 0.02, "ratio tracking {ratio}");
+        assert!(
+            (escaped - expected).abs() < 0.03,
+            "delta tracking {escaped}"
+        );
+    }
+
     #[test]
     fn compiles_and_evaluates_custom_ft_sdf() {
         let source = r#"
@@ -9452,11 +12431,12 @@ mod tests {
                 .map(|b| &b.value)
                 .expect("scene binding"),
             super::default_material(),
+            super::Shutter::default(),
         )
         .expect("scene should compile");
 
-        let center = super::sdf_distance_info(&scene.root, super::Vec3::new(0.0, 0.0, 0.0));
-        let surface = super::sdf_distance_info(&scene.root, super::Vec3::new(0.0, 1.0, 0.0));
+        let center = super::sdf_distance_info(&scene.root, super::Vec3::new(0.0, 0.0, 0.0), 0.0);
+        let surface = super::sdf_distance_info(&scene.root, super::Vec3::new(0.0, 1.0, 0.0), 0.0);
         let bounds = super::sdf_bounds(&scene.root);
 
         assert!(center.distance < -0.9);
@@ -9464,6 +12444,105 @@ mod tests {
         assert!(bounds.max.x < 3.0);
     }
 
+    #[test]
+    fn compiles_union_all_of_procedural_instances() {
+        let source = r#"
+            let grid = for x in 0..3 {
+              for z in 0..2 {
+                Sphere {
+                  radius: 0.25,
+                  pos: vec3(x, 0.0, z),
+                  material: Lambert { color: mix(#ff0000, #0000ff, x / 2.0) }
+                }
+              }
+            };
+            let scene = union_all(grid);
+        "#;
+
+        let program = parse_program(source).expect("program should parse");
+        let state = eval_program(&program).expect("program should evaluate");
+        let scene = super::compile_scene(
+            &state,
+            state
+                .bindings
+                .get("scene")
+                .map(|b| &b.value)
+                .expect("scene binding"),
+            super::default_material(),
+            super::Shutter::default(),
+        )
+        .expect("scene should compile");
+
+        let inside = super::sdf_distance_info(&scene.root, super::Vec3::new(2.0, 0.0, 1.0), 0.0);
+        let between = super::sdf_distance_info(&scene.root, super::Vec3::new(1.0, 0.0, 0.5), 0.0);
+        let bounds = super::sdf_bounds(&scene.root);
+
+        assert!((inside.distance + 0.25).abs() < 1.0e-4);
+        assert!((between.distance - 0.25).abs() < 1.0e-4);
+        assert!((bounds.max.x - 2.25).abs() < 1.0e-3);
+        assert!((bounds.min.z + 0.25).abs() < 1.0e-3);
+    }
+
+    #[test]
+    fn renders_float_layers_to_multichannel_exr() {
+        let source = r#"
+            let scene = Sphere {
+              radius: 1.0,
+              material: Lambert { color: vec3(0.8, 0.8, 0.8) }
+            };
+        "#;
+        let program = parse_program(source).expect("program should parse");
+        let state = eval_program(&program).expect("program should evaluate");
+        let options = RenderOptions {
+            width: 16,
+            height: 16,
+            ..RenderOptions::default()
+        };
+        let layers = super::render_ray_layers_with_accel(
+            &state,
+            options,
+            AccelMode::Bvh,
+            RaySettings::default(),
+            true,
+            |_, _| Ok(()),
+        )
+        .expect("layers should render");
+
+        assert_eq!(layers.beauty.len(), 256);
+        assert_eq!(layers.aovs.len(), 256);
+        assert!(layers.aovs[0].is_none());
+        let center = layers.aovs[8 * 16 + 8].expect("center pixel should hit the sphere");
+        assert!((center.depth - (options.camera_z - 1.0)).abs() < 0.05);
+        assert!(center.normal[2] > 0.9);
+
+        let exr = layers.encode_exr();
+        assert_eq!(&exr[..4], &[0x76, 0x2f, 0x31, 0x01]);
+        let header = String::from_utf8_lossy(&exr[..512]);
+        let b = header.find("B\0").expect("beauty channels");
+        let depth = header.find("depth.Z\0").expect("depth channel");
+        let albedo = header.find("albedo.R\0").expect("albedo channel");
+        let normal = header.find("normal.X\0").expect("normal channel");
+        assert!(b < albedo && albedo < depth && depth < normal);
+        // 14 float channels per scanline, each block prefixed by y and byte size.
+        let blocks = 16 * (8 + 14 * 16 * 4);
+        let table = 16 * 8;
+        let header_end = exr.len() - blocks - table;
+        assert_eq!(exr[header_end - 1], 0);
+        let first = u64::from_le_bytes(
+            exr[header_end..header_end + 8]
+                .try_into()
+                .expect("offset table entry"),
+        );
+        assert_eq!(first as usize, header_end + table);
+
+        assert_eq!(
+            super::OutputFormat::from_path(std::path::Path::new("a/b.EXR")),
+            super::OutputFormat::Exr
+        );
+        assert!(super::OutputFormat::from_path(std::path::Path::new("b.hdr")).is_float());
+        assert!(!super::OutputFormat::from_path(std::path::Path::new("b.png")).is_float());
+    }
+
     fn vec3_value(x: f32, y: f32, z: f32) -> Value {
         let mut fields = HashMap::new();
         fields.insert("x".to_string(), Value::Number(x));
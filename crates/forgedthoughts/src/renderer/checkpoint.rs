@@ -0,0 +1,243 @@
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+
+use super::*;
+use crate::hash::Fnv1a;
+
+const MAGIC: &[u8; 4] = b"FTCK";
+const VERSION: u32 = 2;
+/// Magic, version, size, sample count and fingerprint.
+const HEADER_BYTES: u64 = 4 + 4 * 4 + 8;
+/// Five `f32` accumulators and a `u32` sample count.
+const PIXEL_BYTES: u64 = 5 * 4 + 4;
+
+/// Per-pixel accumulators of an unfinished path-traced render.
+///
+/// A render resumed from a checkpoint continues with the sample index it stopped at. Pixels
+/// that had converged are re-derived from their accumulators under the same noise settings,
+/// which the fingerprint pins down, so the result matches an uninterrupted render.
+#[derive(Clone)]
+pub struct PathtraceCheckpoint {
+    width: u32,
+    height: u32,
+    samples_done: u32,
+    /// Hash of the scene source and the settings that shape each sample, see [`fingerprint`].
+    fingerprint: u64,
+    pixels: Vec<PixelAccumulator>,
+}
+
+impl PathtraceCheckpoint {
+    pub(super) fn new(
+        width: u32,
+        height: u32,
+        samples_done: u32,
+        fingerprint: u64,
+        pixels: Vec<PixelAccumulator>,
+    ) -> Self {
+        Self {
+            width,
+            height,
+            samples_done,
+            fingerprint,
+            pixels,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Sample passes accumulated so far.
+    pub fn samples_done(&self) -> u32 {
+        self.samples_done
+    }
+
+    pub(super) fn into_pixels(self) -> (u32, Vec<PixelAccumulator>) {
+        (self.samples_done, self.pixels)
+    }
+
+    /// Writes the checkpoint next to `path` first and renames it into place, so an interrupted
+    /// write never clobbers the previous checkpoint.
+    pub fn save(&self, path: &Path) -> Result<(), RenderError> {
+        write_atomically(path, |out| self.write(out))
+    }
+
+    fn write(&self, out: &mut impl Write) -> Result<(), RenderError> {
+        out.write_all(MAGIC)?;
+        for value in [VERSION, self.width, self.height, self.samples_done] {
+            out.write_all(&value.to_le_bytes())?;
+        }
+        out.write_all(&self.fingerprint.to_le_bytes())?;
+        for pixel in &self.pixels {
+            for value in [
+                pixel.sum.r,
+                pixel.sum.g,
+                pixel.sum.b,
+                pixel.mean_luma,
+                pixel.m2_luma,
+            ] {
+                out.write_all(&value.to_le_bytes())?;
+            }
+            out.write_all(&pixel.count.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, RenderError> {
+        let file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let mut input = BufReader::new(file);
+        let mut magic = [0_u8; 4];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(RenderError::Checkpoint("not a checkpoint file".to_string()));
+        }
+        let version = read_u32(&mut input)?;
+        if version != VERSION {
+            return Err(RenderError::Checkpoint(format!(
+                "unsupported checkpoint version {version}"
+            )));
+        }
+        let width = read_u32(&mut input)?;
+        let height = read_u32(&mut input)?;
+        let samples_done = read_u32(&mut input)?;
+        let mut fingerprint = [0_u8; 8];
+        input.read_exact(&mut fingerprint)?;
+        let fingerprint = u64::from_le_bytes(fingerprint);
+        // Check the header against the file before trusting it with an allocation.
+        let expected_len = u64::from(width)
+            .checked_mul(u64::from(height))
+            .and_then(|pixels| pixels.checked_mul(PIXEL_BYTES))
+            .and_then(|bytes| bytes.checked_add(HEADER_BYTES));
+        if expected_len != Some(file_len) {
+            return Err(RenderError::Checkpoint(format!(
+                "checkpoint header says {width}x{height} but the file holds {file_len} bytes"
+            )));
+        }
+        let mut pixels = Vec::with_capacity(width as usize * height as usize);
+        for _ in 0..width as usize * height as usize {
+            let r = read_f32(&mut input)?;
+            let g = read_f32(&mut input)?;
+            let b = read_f32(&mut input)?;
+            let mean_luma = read_f32(&mut input)?;
+            let m2_luma = read_f32(&mut input)?;
+            let count = read_u32(&mut input)?;
+            pixels.push(PixelAccumulator {
+                sum: Spectrum::rgb(r, g, b),
+                count,
+                mean_luma,
+                m2_luma,
+                // Convergence is re-derived from the current settings on resume.
+                active: true,
+            });
+        }
+        Ok(Self::new(width, height, samples_done, fingerprint, pixels))
+    }
+
+    /// Fails unless the checkpoint was taken at the resolution about to be rendered, from the
+    /// same scene and settings.
+    pub(super) fn check_resumable(
+        &self,
+        options: RenderOptions,
+        fingerprint: u64,
+    ) -> Result<(), RenderError> {
+        if self.width != options.width || self.height != options.height {
+            return Err(RenderError::Checkpoint(format!(
+                "checkpoint is {}x{} but the render is {}x{}",
+                self.width, self.height, options.width, options.height
+            )));
+        }
+        if self.fingerprint != fingerprint {
+            return Err(RenderError::Checkpoint(
+                "checkpoint was rendered from a different scene or settings".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Hashes `scene_hash` with every option that changes what a sample computes or when a pixel
+/// converges. The sample count, preview cadence and stopping criteria are left out so a
+/// resumed render may run longer, and the display transform only applies to the output.
+pub(super) fn fingerprint(
+    scene_hash: u64,
+    options: RenderOptions,
+    accel_mode: AccelMode,
+    settings: PathtraceSettings,
+) -> u64 {
+    let mut hasher = Fnv1a::new();
+    hasher.write_u64(scene_hash);
+    for value in [
+        options.width,
+        options.height,
+        options.max_steps,
+        settings.max_bounces,
+        settings.min_spp,
+        u32::from(settings.spectral),
+    ] {
+        hasher.write_u32(value);
+    }
+    for value in [
+        options.max_dist,
+        options.epsilon,
+        options.step_scale,
+        options.camera_z,
+        options.fov_y_degrees,
+        options.shutter.open,
+        options.shutter.close,
+        settings.noise_threshold,
+    ] {
+        hasher.write_f32(value);
+    }
+    let shard = match options.shard {
+        None => [0; 5],
+        Some(RenderShard::Region { x0, y0, x1, y1 }) => [1, x0, y0, x1, y1],
+        Some(RenderShard::Tiles {
+            index,
+            count,
+            tile_size,
+        }) => [2, index, count, tile_size, 0],
+    };
+    for value in shard {
+        hasher.write_u32(value);
+    }
+    hasher.write_u32(match accel_mode {
+        AccelMode::Naive => 0,
+        AccelMode::Bvh => 1,
+        AccelMode::Bricks => 2,
+    });
+    hasher.finish()
+}
+
+/// Writes to `<path>.partial`, syncs it and renames it over `path`, so readers only ever see
+/// a complete file.
+pub(super) fn write_atomically(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<(), RenderError>,
+) -> Result<(), RenderError> {
+    let mut partial = OsString::from(path.as_os_str());
+    partial.push(".partial");
+    let partial = Path::new(&partial);
+    let mut out = BufWriter::new(File::create(partial)?);
+    write(&mut out)?;
+    out.into_inner()
+        .map_err(|err| err.into_error())?
+        .sync_all()?;
+    fs::rename(partial, path)?;
+    Ok(())
+}
+
+pub(super) fn read_u32(input: &mut impl Read) -> Result<u32, RenderError> {
+    let mut bytes = [0_u8; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub(super) fn read_f32(input: &mut impl Read) -> Result<f32, RenderError> {
+    read_u32(input).map(f32::from_bits)
+}
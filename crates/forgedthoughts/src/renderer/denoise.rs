@@ -0,0 +1,123 @@
+use super::*;
+
+/// À-trous passes; the kernel footprint doubles each pass, so five cover a 61 pixel window.
+const PASSES: u32 = 5;
+/// B3-spline taps of the à-trous kernel.
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+/// Exponent on the normal cosine; higher keeps creases sharper.
+const NORMAL_POWER: i32 = 128;
+/// Relative depth difference per pixel of distance treated as a separate surface.
+const DEPTH_SIGMA: f32 = 0.02;
+/// Standard deviations of luminance difference tolerated between neighbours.
+const LUMA_SIGMA: f32 = 4.0;
+/// Albedo difference treated as a separate material.
+const ALBEDO_SIGMA: f32 = 0.1;
+/// Albedo floor used when dividing it out, so dark materials do not blow up the noise.
+const MIN_ALBEDO: f32 = 0.02;
+
+/// One pixel of denoiser input: mean radiance, the variance of that mean, and the primary hit
+/// features (`None` on background pixels).
+#[derive(Clone, Copy)]
+pub(super) struct DenoiseInput {
+    pub color: [f32; 3],
+    pub variance: f32,
+    pub features: Option<AovSample>,
+}
+
+/// Edge-avoiding à-trous filter guided by the primary-hit normal, depth and albedo.
+///
+/// Albedo is divided out first so texture detail survives and only the lighting is smoothed.
+/// The luminance term is scaled by each pixel's sample variance, which makes converged pixels
+/// from the adaptive sampler hold their value while noisy ones borrow from their neighbours.
+/// Background pixels pass through unchanged.
+pub(super) fn denoise(width: u32, height: u32, input: &[DenoiseInput]) -> Vec<[f32; 3]> {
+    let (width, height) = (width as usize, height as usize);
+    let albedo = input
+        .iter()
+        .map(|px| {
+            px.features.map_or([1.0; 3], |f| {
+                f.albedo.map(|channel| channel.max(MIN_ALBEDO))
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut color = input
+        .iter()
+        .zip(&albedo)
+        .map(|(px, a)| [px.color[0] / a[0], px.color[1] / a[1], px.color[2] / a[2]])
+        .collect::<Vec<_>>();
+    let mut variance = input
+        .iter()
+        .zip(&albedo)
+        .map(|(px, a)| px.variance / luminance(*a).powi(2))
+        .collect::<Vec<_>>();
+
+    for pass in 0..PASSES {
+        let step = 1_isize << pass;
+        let (next_color, next_variance): (Vec<_>, Vec<_>) = (0..width * height)
+            .into_par_iter()
+            .map(|idx| {
+                let Some(center) = input[idx].features else {
+                    return (color[idx], variance[idx]);
+                };
+                let (x, y) = ((idx % width) as isize, (idx / width) as isize);
+                let luma = luminance(color[idx]);
+                let luma_scale = LUMA_SIGMA * variance[idx].max(0.0).sqrt() + 1.0e-4;
+                let mut sum = [0.0_f32; 3];
+                let mut sum_variance = 0.0;
+                let mut weight_sum = 0.0;
+                for (ky, wy) in KERNEL.iter().enumerate() {
+                    let qy = y + (ky as isize - 2) * step;
+                    if qy < 0 || qy >= height as isize {
+                        continue;
+                    }
+                    for (kx, wx) in KERNEL.iter().enumerate() {
+                        let qx = x + (kx as isize - 2) * step;
+                        if qx < 0 || qx >= width as isize {
+                            continue;
+                        }
+                        let q = qy as usize * width + qx as usize;
+                        let Some(other) = input[q].features else {
+                            continue;
+                        };
+                        let edge = edge_weight(&center, &other, step as f32)
+                            * (-(luminance(color[q]) - luma).abs() / luma_scale).exp();
+                        let w = wx * wy * edge;
+                        for c in 0..3 {
+                            sum[c] += color[q][c] * w;
+                        }
+                        sum_variance += variance[q] * w * w;
+                        weight_sum += w;
+                    }
+                }
+                // The center tap always contributes, so the sum stays positive.
+                (
+                    sum.map(|c| c / weight_sum),
+                    sum_variance / (weight_sum * weight_sum),
+                )
+            })
+            .unzip();
+        color = next_color;
+        variance = next_variance;
+    }
+
+    color
+        .iter()
+        .zip(&albedo)
+        .map(|(c, a)| [c[0] * a[0], c[1] * a[1], c[2] * a[2]])
+        .collect()
+}
+
+/// How likely two primary hits lie on the same smooth surface of the same material.
+fn edge_weight(p: &AovSample, q: &AovSample, step: f32) -> f32 {
+    let cos = p.normal[0] * q.normal[0] + p.normal[1] * q.normal[1] + p.normal[2] * q.normal[2];
+    let normal = cos.max(0.0).powi(NORMAL_POWER);
+    let relative_depth = (p.depth - q.depth).abs() / p.depth.max(1.0e-4);
+    let depth = (-relative_depth / (DEPTH_SIGMA * step)).exp();
+    let albedo_dist: f32 = (0..3).map(|c| (p.albedo[c] - q.albedo[c]).powi(2)).sum();
+    let albedo = (-albedo_dist / (2.0 * ALBEDO_SIGMA * ALBEDO_SIGMA)).exp();
+    normal * depth * albedo
+}
+
+fn luminance(c: [f32; 3]) -> f32 {
+    spectrum_luminance(Spectrum::rgb(c[0], c[1], c[2]))
+}
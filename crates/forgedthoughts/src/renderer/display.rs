@@ -0,0 +1,147 @@
+use super::*;
+
+/// Curve that maps scene-linear radiance into the displayable `[0, 1]` range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tonemap {
+    /// Plain clamp; anything above 1.0 clips.
+    Linear,
+    Reinhard,
+    /// Hable's filmic curve with a white point of 11.2.
+    Filmic,
+    /// Hill's fit of the ACES RRT + sRGB ODT.
+    Aces,
+    /// Minimal AgX base look.
+    Agx,
+}
+
+/// Display transform applied when radiance is quantized to 8-bit output.
+///
+/// Radiance is scaled by `2^exposure`, tonemapped, encoded with the sRGB OETF, and optionally
+/// dithered before quantization. Float outputs (`.exr`, `.hdr`) skip this stage.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisplayTransform {
+    pub exposure: f32,
+    pub tonemap: Tonemap,
+    pub dither: bool,
+}
+
+impl Default for DisplayTransform {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            tonemap: Tonemap::Reinhard,
+            dither: false,
+        }
+    }
+}
+
+impl DisplayTransform {
+    /// Converts linear radiance for the pixel at `(x, y)` to display-encoded 8-bit RGB.
+    pub fn to_rgb8(&self, radiance: Spectrum, x: u32, y: u32) -> [u8; 3] {
+        let scale = self.exposure.exp2();
+        let rgb = [radiance.r * scale, radiance.g * scale, radiance.b * scale].map(|v| v.max(0.0));
+        let mapped = match self.tonemap {
+            Tonemap::Linear => rgb,
+            Tonemap::Reinhard => rgb.map(|v| v / (1.0 + v)),
+            Tonemap::Filmic => filmic(rgb),
+            Tonemap::Aces => aces_fitted(rgb),
+            Tonemap::Agx => agx(rgb),
+        };
+        let dither = if self.dither {
+            triangular_dither(x, y)
+        } else {
+            [0.0; 3]
+        };
+        [0, 1, 2].map(|i| {
+            let encoded = srgb_oetf(mapped[i].clamp(0.0, 1.0)) * 255.0 + 0.5 + dither[i];
+            encoded.clamp(0.0, 255.0) as u8
+        })
+    }
+}
+
+fn srgb_oetf(v: f32) -> f32 {
+    if v <= 0.003_130_8 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn filmic(rgb: [f32; 3]) -> [f32; 3] {
+    fn curve(x: f32) -> f32 {
+        const A: f32 = 0.15;
+        const B: f32 = 0.50;
+        const C: f32 = 0.10;
+        const D: f32 = 0.20;
+        const E: f32 = 0.02;
+        const F: f32 = 0.30;
+        ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F
+    }
+    let white = curve(11.2);
+    // Exposure bias of 2.0 as in Hable's reference implementation.
+    rgb.map(|v| curve(v * 2.0) / white)
+}
+
+fn aces_fitted(rgb: [f32; 3]) -> [f32; 3] {
+    const INPUT: [[f32; 3]; 3] = [
+        [0.597_19, 0.354_58, 0.048_23],
+        [0.076_00, 0.908_34, 0.015_66],
+        [0.028_40, 0.133_83, 0.837_77],
+    ];
+    const OUTPUT: [[f32; 3]; 3] = [
+        [1.604_75, -0.531_08, -0.073_67],
+        [-0.102_08, 1.108_13, -0.006_05],
+        [-0.003_27, -0.072_76, 1.076_02],
+    ];
+    let v = mul3(INPUT, rgb).map(|v| {
+        let a = v * (v + 0.024_578_6) - 0.000_090_537;
+        let b = v * (0.983_729 * v + 0.432_951) + 0.238_081;
+        a / b
+    });
+    mul3(OUTPUT, v)
+}
+
+fn agx(rgb: [f32; 3]) -> [f32; 3] {
+    const INSET: [[f32; 3]; 3] = [
+        [0.842_479_06, 0.078_433_6, 0.079_223_75],
+        [0.042_328_24, 0.878_468_6, 0.079_166_13],
+        [0.042_375_65, 0.078_433_6, 0.879_143],
+    ];
+    const OUTSET: [[f32; 3]; 3] = [
+        [1.196_879, -0.098_020_88, -0.099_029_74],
+        [-0.052_896_85, 1.151_903_1, -0.098_961_18],
+        [-0.052_971_635, -0.098_043_46, 1.151_073_7],
+    ];
+    const MIN_EV: f32 = -12.473_93;
+    const MAX_EV: f32 = 4.026_069;
+    let look = mul3(INSET, rgb).map(|v| {
+        let x = ((v.max(1.0e-10).log2() - MIN_EV) / (MAX_EV - MIN_EV)).clamp(0.0, 1.0);
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.002_32
+    });
+    // The AgX curve lands in a 2.2 display encoding; undo it so the sRGB OETF applies once.
+    mul3(OUTSET, look).map(|v| v.max(0.0).powf(2.2))
+}
+
+fn mul3(m: [[f32; 3]; 3], v: [f32; 3]) -> [f32; 3] {
+    m.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+/// Per-pixel triangular noise in `(-1, 1)` LSB, which hides banding in smooth gradients.
+fn triangular_dither(x: u32, y: u32) -> [f32; 3] {
+    fn hash(mut v: u32) -> f32 {
+        v ^= v >> 16;
+        v = v.wrapping_mul(0x7feb_352d);
+        v ^= v >> 15;
+        v = v.wrapping_mul(0x846c_a68b);
+        v ^= v >> 16;
+        v as f32 / u32::MAX as f32
+    }
+    let seed = x.wrapping_mul(1973) ^ y.wrapping_mul(9277);
+    [0_u32, 1, 2].map(|channel| {
+        let base = seed ^ channel.wrapping_mul(26699);
+        hash(base) - hash(base ^ 0x5bd1_e995)
+    })
+}
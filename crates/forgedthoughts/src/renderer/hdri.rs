@@ -0,0 +1,361 @@
+use std::f32::consts::PI;
+use std::fs;
+
+use super::checkpoint::{read_f32, read_u32};
+use super::*;
+
+/// A latitude-longitude environment map with a luminance distribution for importance sampling.
+///
+/// The image center looks down -Z and the top row is straight up; `rotation` turns the map
+/// about the Y axis.
+pub(super) struct HdriMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<[f32; 3]>,
+    rotation: f32,
+    intensity: f32,
+    /// Running row weights, normalized so the last entry is 1.
+    marginal_cdf: Vec<f32>,
+    /// Per row, running pixel weights normalized to end at 1.
+    conditional_cdf: Vec<f32>,
+    /// Pixel weights over their average, i.e. the density over the unit `(u, v)` square.
+    density: Vec<f32>,
+}
+
+impl HdriMap {
+    /// Loads a Radiance `.hdr` or uncompressed OpenEXR map; `rotation` is in degrees.
+    pub(super) fn load(path: &Path, rotation: f32, intensity: f32) -> Result<Self, RenderError> {
+        let error =
+            |reason: String| RenderError::Environment(format!("{}: {reason}", path.display()));
+        let (width, height, pixels) = match OutputFormat::from_path(path) {
+            OutputFormat::Exr => {
+                let bytes = fs::read(path).map_err(|err| error(err.to_string()))?;
+                decode_exr(&bytes).map_err(error)?
+            }
+            _ => {
+                let image = image::open(path)
+                    .map_err(|err| error(err.to_string()))?
+                    .into_rgb32f();
+                let (width, height) = image.dimensions();
+                let pixels = image.pixels().map(|px| px.0).collect();
+                (width as usize, height as usize, pixels)
+            }
+        };
+        if width == 0 || height == 0 {
+            return Err(error("image is empty".to_string()));
+        }
+        Ok(Self::from_pixels(
+            width,
+            height,
+            pixels,
+            rotation.to_radians(),
+            intensity,
+        ))
+    }
+
+    pub(super) fn from_pixels(
+        width: usize,
+        height: usize,
+        pixels: Vec<[f32; 3]>,
+        rotation: f32,
+        intensity: f32,
+    ) -> Self {
+        // Rows near the poles cover less solid angle, so their pixels are picked less often.
+        let weights = pixels
+            .iter()
+            .enumerate()
+            .map(|(idx, px)| {
+                let theta = PI * ((idx / width) as f32 + 0.5) / height as f32;
+                let luma = spectrum_luminance(Spectrum::rgb(px[0], px[1], px[2]));
+                luma.max(0.0) * theta.sin() + 1.0e-6
+            })
+            .collect::<Vec<_>>();
+        let total = weights.iter().sum::<f32>();
+        let average = total / weights.len() as f32;
+
+        let mut conditional_cdf = Vec::with_capacity(weights.len());
+        let mut marginal_cdf = Vec::with_capacity(height);
+        let mut running_rows = 0.0;
+        for row in weights.chunks(width) {
+            let row_total = row.iter().sum::<f32>();
+            let mut running = 0.0;
+            for weight in row {
+                running += weight;
+                conditional_cdf.push(running / row_total);
+            }
+            running_rows += row_total;
+            marginal_cdf.push(running_rows / total);
+        }
+
+        Self {
+            width,
+            height,
+            pixels,
+            rotation,
+            intensity,
+            marginal_cdf,
+            conditional_cdf,
+            density: weights.iter().map(|w| w / average).collect(),
+        }
+    }
+
+    /// Evaluates `color` at the pixel centers of a `2 * rows` by `rows` grid.
+    pub(super) fn tabulate(rows: usize, color: impl Fn(Vec3) -> Spectrum) -> Self {
+        let (width, height) = (2 * rows.max(1), rows.max(1));
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let u = (x as f32 + 0.5) / width as f32;
+                let v = (y as f32 + 0.5) / height as f32;
+                let c = color(lat_long_direction(u, v, 0.0));
+                pixels.push([c.r, c.g, c.b]);
+            }
+        }
+        Self::from_pixels(width, height, pixels, 0.0, 1.0)
+    }
+
+    /// Radiance arriving from direction `dir`.
+    pub(super) fn radiance(&self, dir: Vec3) -> Spectrum {
+        let [r, g, b] = self.pixels[self.pixel_index(dir)];
+        Spectrum::rgb(r, g, b).scale(self.intensity)
+    }
+
+    /// Luminance of the radiance averaged over the sphere of directions.
+    pub(super) fn average_luminance(&self) -> f32 {
+        let (mut sum, mut weight) = (0.0, 0.0);
+        for (idx, px) in self.pixels.iter().enumerate() {
+            let sin_theta = (PI * ((idx / self.width) as f32 + 0.5) / self.height as f32).sin();
+            sum += spectrum_luminance(Spectrum::rgb(px[0], px[1], px[2])).max(0.0) * sin_theta;
+            weight += sin_theta;
+        }
+        if weight > 0.0 {
+            self.intensity * sum / weight
+        } else {
+            0.0
+        }
+    }
+
+    /// Picks a direction in proportion to the map's brightness, with its solid-angle density.
+    pub(super) fn sample(&self, u1: f32, u2: f32) -> (Vec3, f32) {
+        let (row, jitter_v) = search_cdf(&self.marginal_cdf, u1);
+        let start = row * self.width;
+        let (column, jitter_u) = search_cdf(&self.conditional_cdf[start..start + self.width], u2);
+        let u = (column as f32 + jitter_u) / self.width as f32;
+        let v = (row as f32 + jitter_v) / self.height as f32;
+        let dir = lat_long_direction(u, v, self.rotation);
+        (dir, self.pdf(dir))
+    }
+
+    /// Solid-angle density of `sample` returning `dir`.
+    pub(super) fn pdf(&self, dir: Vec3) -> f32 {
+        let (_, v) = self.uv(dir);
+        let sin_theta = (PI * v).sin();
+        if sin_theta <= 1.0e-6 {
+            return 0.0;
+        }
+        self.density[self.pixel_index(dir)] / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn uv(&self, dir: Vec3) -> (f32, f32) {
+        let dir = dir.normalize();
+        let phi = dir.x.atan2(-dir.z) - self.rotation;
+        let u = (phi / (2.0 * PI) + 0.5).rem_euclid(1.0);
+        let v = dir.y.clamp(-1.0, 1.0).acos() / PI;
+        (u, v)
+    }
+
+    fn pixel_index(&self, dir: Vec3) -> usize {
+        let (u, v) = self.uv(dir);
+        let x = ((u * self.width as f32) as usize).min(self.width - 1);
+        let y = ((v * self.height as f32) as usize).min(self.height - 1);
+        y * self.width + x
+    }
+}
+
+/// Trace-mode light casting a fixed set of shadow rays toward the bright parts of an environment map.
+pub(super) struct EnvMapLight {
+    pub(super) map: Arc<HdriMap>,
+    pub(super) samples: u32,
+}
+
+impl Light for EnvMapLight {
+    fn sample_li(&self, point: ApiVec3) -> LightSample {
+        self.sample_li_indexed(point, 0, self.shadow_sample_count())
+    }
+
+    fn sample_li_indexed(
+        &self,
+        _point: ApiVec3,
+        sample_index: u32,
+        sample_count: u32,
+    ) -> LightSample {
+        // The same directions at every hit keep trace renders free of noise; shadows come out
+        // as a few overlapping hard ones instead.
+        let (u1, u2) = hammersley_2d(sample_index, sample_count);
+        let (dir, pdf) = self.map.sample(u1, u2);
+        LightSample {
+            wi: to_api_vec3(dir),
+            radiance: self.map.radiance(dir).scale(1.0 / pdf.max(1.0e-4)),
+            distance: f32::INFINITY,
+        }
+    }
+
+    fn shadow_sample_count(&self) -> u32 {
+        self.samples.max(1)
+    }
+}
+
+fn lat_long_direction(u: f32, v: f32, rotation: f32) -> Vec3 {
+    let phi = (u - 0.5) * 2.0 * PI + rotation;
+    let theta = v * PI;
+    Vec3::new(
+        theta.sin() * phi.sin(),
+        theta.cos(),
+        -theta.sin() * phi.cos(),
+    )
+}
+
+/// First index whose running total exceeds `u`, and where `u` falls inside that entry so the
+/// pick can be spread uniformly across the pixel.
+fn search_cdf(cdf: &[f32], u: f32) -> (usize, f32) {
+    let index = cdf.partition_point(|&value| value <= u).min(cdf.len() - 1);
+    let lo = if index == 0 { 0.0 } else { cdf[index - 1] };
+    let span = (cdf[index] - lo).max(1.0e-12);
+    (index, ((u - lo) / span).clamp(0.0, 0.999_999))
+}
+
+/// Reads the `R`, `G` and `B` (or `Y`) channels of a single-part, uncompressed scanline EXR.
+fn decode_exr(bytes: &[u8]) -> Result<(usize, usize, Vec<[f32; 3]>), String> {
+    let mut input = bytes;
+    let header_error = |_| "truncated EXR header".to_string();
+    if read_u32(&mut input).map_err(header_error)? != 20_000_630 {
+        return Err("not an OpenEXR file".to_string());
+    }
+    let version = read_u32(&mut input).map_err(header_error)?;
+    if version & 0x0000_0200 != 0 || version & 0x0000_1000 != 0 {
+        return Err("tiled and multi-part EXR files are not supported".to_string());
+    }
+
+    // (name, pixel type) in file order, which EXR keeps sorted by name.
+    let mut channels = Vec::new();
+    let mut compression = 0_u8;
+    let mut window = None;
+    loop {
+        let name = read_cstr(&mut input)?;
+        if name.is_empty() {
+            break;
+        }
+        let kind = read_cstr(&mut input)?;
+        let size = read_u32(&mut input).map_err(header_error)? as usize;
+        if input.len() < size {
+            return Err("truncated EXR header".to_string());
+        }
+        let (value, rest) = input.split_at(size);
+        input = rest;
+        match (name.as_str(), kind.as_str()) {
+            ("channels", "chlist") => {
+                let mut list = value;
+                loop {
+                    let channel = read_cstr(&mut list)?;
+                    if channel.is_empty() {
+                        break;
+                    }
+                    let pixel_type = read_u32(&mut list).map_err(header_error)?;
+                    // pLinear, reserved and the x/y sampling rates.
+                    list = list.get(12..).ok_or("truncated EXR channel list")?;
+                    channels.push((channel, pixel_type));
+                }
+            }
+            ("compression", _) => compression = value.first().copied().unwrap_or(0),
+            ("dataWindow", "box2i") => {
+                let mut window_bytes = value;
+                let mut coords = [0_i32; 4];
+                for coord in &mut coords {
+                    *coord = read_u32(&mut window_bytes).map_err(header_error)? as i32;
+                }
+                window = Some(coords);
+            }
+            _ => {}
+        }
+    }
+    if compression != 0 {
+        return Err(
+            "only uncompressed EXR files are supported; re-save without compression or use .hdr"
+                .to_string(),
+        );
+    }
+    let [x0, y0, x1, y1] = window.ok_or("EXR file has no dataWindow")?;
+    let width = (x1 - x0 + 1).max(0) as usize;
+    let height = (y1 - y0 + 1).max(0) as usize;
+    let find = |wanted: &str| channels.iter().position(|(name, _)| name == wanted);
+    let sources = match (find("R"), find("G"), find("B"), find("Y")) {
+        (Some(r), Some(g), Some(b), _) => [r, g, b],
+        (_, _, _, Some(y)) => [y, y, y],
+        _ => return Err("EXR file has no R, G, B or Y channels".to_string()),
+    };
+    let channel_bytes = |pixel_type: u32| if pixel_type == 1 { 2 } else { 4 };
+    let line_bytes = channels
+        .iter()
+        .map(|&(_, pixel_type)| channel_bytes(pixel_type) * width)
+        .sum::<usize>();
+
+    // Skip the line offset table; chunks follow in order.
+    input = input
+        .get(height * 8..)
+        .ok_or("truncated EXR offset table")?;
+    let mut pixels = vec![[0.0_f32; 3]; width * height];
+    for _ in 0..height {
+        let y = read_u32(&mut input).map_err(|_| "truncated EXR scanline")? as i32 - y0;
+        let size = read_u32(&mut input).map_err(|_| "truncated EXR scanline")? as usize;
+        if size != line_bytes || input.len() < size || y < 0 || y as usize >= height {
+            return Err("malformed EXR scanline".to_string());
+        }
+        let (mut line, rest) = input.split_at(size);
+        input = rest;
+        let row = y as usize * width;
+        for (channel, &(_, pixel_type)) in channels.iter().enumerate() {
+            for x in 0..width {
+                let value = match pixel_type {
+                    1 => {
+                        let half = u16::from_le_bytes([line[0], line[1]]);
+                        line = &line[2..];
+                        half_to_f32(half)
+                    }
+                    2 => read_f32(&mut line).map_err(|_| "malformed EXR scanline")?,
+                    _ => {
+                        line = &line[4..];
+                        0.0
+                    }
+                };
+                for (slot, &source) in sources.iter().enumerate() {
+                    if source == channel {
+                        pixels[row + x][slot] = value;
+                    }
+                }
+            }
+        }
+    }
+    Ok((width, height, pixels))
+}
+
+fn read_cstr(input: &mut &[u8]) -> Result<String, String> {
+    let end = input
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or("truncated EXR header")?;
+    let text = String::from_utf8_lossy(&input[..end]).into_owned();
+    *input = &input[end + 1..];
+    Ok(text)
+}
+
+fn half_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = i32::from((half >> 10) & 0x1f);
+    let mantissa = f32::from(half & 0x03ff);
+    match exponent {
+        0 => sign * mantissa * 2.0_f32.powi(-24),
+        31 if mantissa == 0.0 => sign * f32::INFINITY,
+        31 => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2.0_f32.powi(exponent - 15),
+    }
+}
@@ -0,0 +1,214 @@
+use std::fs;
+
+use image::Rgb32FImage;
+
+use super::*;
+
+/// Depth written for pixels whose camera ray leaves the scene without a hit.
+const BACKGROUND_DEPTH: f32 = 1.0e10;
+
+/// Image container chosen from the output file extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Png,
+    Exr,
+    Hdr,
+}
+
+impl OutputFormat {
+    /// Picks the format from the extension of `path`, defaulting to PNG.
+    pub fn from_path(path: &Path) -> Self {
+        let ext = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_ascii_lowercase);
+        match ext.as_deref() {
+            Some("exr") => Self::Exr,
+            Some("hdr") => Self::Hdr,
+            _ => Self::Png,
+        }
+    }
+
+    /// Whether the format stores linear float radiance instead of display pixels.
+    pub fn is_float(self) -> bool {
+        matches!(self, Self::Exr | Self::Hdr)
+    }
+}
+
+/// Raw values of the `RayDebugAov` channels at the primary hit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AovSample {
+    /// Camera-ray distance to the hit; this is also the raw form of `RayDebugAov::HitT`.
+    pub depth: f32,
+    /// World-space shading normal facing the camera.
+    pub normal: [f32; 3],
+    /// Base color of the material at the hit, clamped to `[0, 1]`.
+    pub albedo: [f32; 3],
+    pub material_id: u32,
+    pub ior: f32,
+    pub transmission: f32,
+    pub fresnel: f32,
+}
+
+/// Float framebuffer of a finished render.
+///
+/// `beauty` is the linear radiance before any tonemapping, `image` is the same frame as the
+/// 8-bit display image the PNG path writes, and `aovs` holds one primary-hit sample per pixel
+/// (`None` on background pixels). `aovs` is empty unless the layers were requested.
+#[derive(Debug, Clone)]
+pub struct RenderLayers {
+    pub width: u32,
+    pub height: u32,
+    pub beauty: Vec<[f32; 3]>,
+    pub aovs: Vec<Option<AovSample>>,
+    pub image: RgbImage,
+}
+
+impl RenderLayers {
+    /// Writes the frame in the format selected by the extension of `path`.
+    pub fn save(&self, path: &Path) -> Result<(), RenderError> {
+        match OutputFormat::from_path(path) {
+            OutputFormat::Png => self.image.save(path)?,
+            OutputFormat::Exr => fs::write(path, self.encode_exr())?,
+            OutputFormat::Hdr => self.beauty_image().save(path)?,
+        }
+        Ok(())
+    }
+
+    /// Encodes every layer as an uncompressed scanline OpenEXR file.
+    ///
+    /// Beauty goes to the default `R`, `G`, `B` channels; the AOVs use `depth.Z`,
+    /// `normal.{X,Y,Z}`, `albedo.{R,G,B}`, `material.id` (-1 on background), `ior.Y`, `transmission.Y` and
+    /// `fresnel.Y`.
+    pub fn encode_exr(&self) -> Vec<u8> {
+        let mut channels: Vec<(&str, Vec<f32>)> = vec![
+            ("R", self.beauty.iter().map(|c| c[0]).collect()),
+            ("G", self.beauty.iter().map(|c| c[1]).collect()),
+            ("B", self.beauty.iter().map(|c| c[2]).collect()),
+        ];
+        if !self.aovs.is_empty() {
+            let aov = |f: fn(&AovSample) -> f32, background: f32| -> Vec<f32> {
+                self.aovs
+                    .iter()
+                    .map(|sample| sample.as_ref().map_or(background, f))
+                    .collect()
+            };
+            channels.extend([
+                ("depth.Z", aov(|s| s.depth, BACKGROUND_DEPTH)),
+                ("normal.X", aov(|s| s.normal[0], 0.0)),
+                ("normal.Y", aov(|s| s.normal[1], 0.0)),
+                ("normal.Z", aov(|s| s.normal[2], 0.0)),
+                ("albedo.R", aov(|s| s.albedo[0], 0.0)),
+                ("albedo.G", aov(|s| s.albedo[1], 0.0)),
+                ("albedo.B", aov(|s| s.albedo[2], 0.0)),
+                ("material.id", aov(|s| s.material_id as f32, -1.0)),
+                ("ior.Y", aov(|s| s.ior, 1.0)),
+                ("transmission.Y", aov(|s| s.transmission, 0.0)),
+                ("fresnel.Y", aov(|s| s.fresnel, 0.0)),
+            ]);
+        }
+        encode_exr(self.width, self.height, &mut channels)
+    }
+
+    fn beauty_image(&self) -> Rgb32FImage {
+        let data = self.beauty.iter().flatten().copied().collect();
+        Rgb32FImage::from_vec(self.width, self.height, data)
+            .expect("pixel buffer length must match image dimensions")
+    }
+}
+
+fn encode_exr(width: u32, height: u32, channels: &mut [(&str, Vec<f32>)]) -> Vec<u8> {
+    // Readers require the channel list in byte order, and pixel data follows the same order.
+    channels.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+    let (width, height) = (width as usize, height as usize);
+
+    let mut out = vec![0x76, 0x2f, 0x31, 0x01];
+    out.extend(2_u32.to_le_bytes());
+
+    let mut chlist = Vec::new();
+    for (name, _) in channels.iter() {
+        chlist.extend(name.as_bytes());
+        chlist.push(0);
+        chlist.extend(2_i32.to_le_bytes()); // FLOAT
+        chlist.extend([0, 0, 0, 0]); // pLinear + reserved
+        chlist.extend(1_i32.to_le_bytes());
+        chlist.extend(1_i32.to_le_bytes());
+    }
+    chlist.push(0);
+    let window = [0, 0, width as i32 - 1, height as i32 - 1]
+        .iter()
+        .flat_map(|v: &i32| v.to_le_bytes())
+        .collect::<Vec<_>>();
+    exr_attribute(&mut out, "channels", "chlist", &chlist);
+    exr_attribute(&mut out, "compression", "compression", &[0]);
+    exr_attribute(&mut out, "dataWindow", "box2i", &window);
+    exr_attribute(&mut out, "displayWindow", "box2i", &window);
+    exr_attribute(&mut out, "lineOrder", "lineOrder", &[0]);
+    exr_attribute(
+        &mut out,
+        "pixelAspectRatio",
+        "float",
+        &1.0_f32.to_le_bytes(),
+    );
+    exr_attribute(&mut out, "screenWindowCenter", "v2f", &[0; 8]);
+    exr_attribute(
+        &mut out,
+        "screenWindowWidth",
+        "float",
+        &1.0_f32.to_le_bytes(),
+    );
+    out.push(0);
+
+    let block_size = channels.len() * width * 4;
+    let table_end = out.len() + height * 8;
+    for y in 0..height {
+        let offset = table_end + y * (8 + block_size);
+        out.extend((offset as u64).to_le_bytes());
+    }
+    for y in 0..height {
+        out.extend((y as i32).to_le_bytes());
+        out.extend((block_size as i32).to_le_bytes());
+        for (_, data) in channels.iter() {
+            for value in &data[y * width..(y + 1) * width] {
+                out.extend(value.to_le_bytes());
+            }
+        }
+    }
+    out
+}
+
+fn exr_attribute(out: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    out.extend(name.as_bytes());
+    out.push(0);
+    out.extend(kind.as_bytes());
+    out.push(0);
+    out.extend((value.len() as i32).to_le_bytes());
+    out.extend(value);
+}
+
+/// Runs one primary ray through each pixel center and records its AOVs.
+pub(super) fn render_aov_samples(
+    accel: &(impl Accelerator + Sync),
+    setup: &RenderSetup,
+    options: RenderOptions,
+) -> Vec<Option<AovSample>> {
+    let aspect = options.width as f32 / options.height as f32;
+    let width = options.width as usize;
+    (0..width * options.height as usize)
+        .into_par_iter()
+        .map(|idx| {
+            if !covers_pixel(options, (idx % width) as u32, (idx / width) as u32) {
+                return None;
+            }
+            let x = (idx % width) as f32 + 0.5;
+            let y = (idx / width) as f32 + 0.5;
+            let px = (x / options.width as f32) * 2.0 - 1.0;
+            let py = 1.0 - (y / options.height as f32) * 2.0;
+            let ray = setup.camera.generate_ray(px * aspect, py);
+            let time = options.shutter.time_at(0.5);
+            let origin = from_api_vec3(ray.origin);
+            let dir = from_api_vec3(ray.direction).normalize();
+            ray::sample_primary_aovs(accel, setup, options, origin, dir, time)
+        })
+        .collect()
+}
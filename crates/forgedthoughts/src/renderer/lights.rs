@@ -0,0 +1,307 @@
+use super::*;
+
+/// Picks which light a path-traced shading point samples.
+///
+/// Each light at infinity (environment, sun) and the scene's finite lights as a group get a
+/// share in proportion to their power, that of a light at infinity being what it sends
+/// through the scene's cross-section. Within the group a BVH over the lights is walked from
+/// the root, going to each child in proportion to its power over the squared distance to
+/// it, so a shading point mostly samples the lamps near it even when a scene has dozens of
+/// them.
+pub(super) struct LightSampler {
+    /// Lights at infinity with the probability of picking each.
+    infinite: Vec<(usize, f32)>,
+    /// Probability of picking from the BVH over the finite lights.
+    finite_pmf: f32,
+    nodes: Vec<LightNode>,
+    /// Per light, the left/right turns from the root to its leaf, lowest bit first.
+    trails: Vec<Option<u64>>,
+}
+
+#[derive(Clone, Copy)]
+struct LightNode {
+    center: Vec3,
+    radius: f32,
+    power: f32,
+    kind: LightNodeKind,
+}
+
+#[derive(Clone, Copy)]
+enum LightNodeKind {
+    Leaf(usize),
+    Interior(usize, usize),
+}
+
+/// A finite light as the BVH sees it.
+#[derive(Clone, Copy)]
+struct LightBounds {
+    index: usize,
+    center: Vec3,
+    radius: f32,
+    power: f32,
+}
+
+impl LightSampler {
+    /// `env_map` is the map behind [`PathLight::EnvMap`] and `scene_radius` the radius of a
+    /// sphere around everything lights at infinity can reach.
+    pub(super) fn new(lights: &[PathLight], env_map: Option<&HdriMap>, scene_radius: f32) -> Self {
+        let mut infinite = Vec::new();
+        let mut finite = Vec::new();
+        for (index, light) in lights.iter().enumerate() {
+            match light.bounds() {
+                Some((center, radius)) => finite.push(LightBounds {
+                    index,
+                    center,
+                    radius,
+                    power: light.power(),
+                }),
+                None => infinite.push((index, light.infinite_power(env_map, scene_radius))),
+            }
+        }
+        let mut sampler = Self {
+            infinite,
+            finite_pmf: 0.0,
+            nodes: Vec::new(),
+            trails: vec![None; lights.len()],
+        };
+        if !finite.is_empty() {
+            sampler.build(&mut finite, 0, 0);
+            sampler.finite_pmf = sampler.nodes[0].power;
+        }
+
+        // Powers become probabilities; without any power to go by every share is equal.
+        let total = sampler.finite_pmf + sampler.infinite.iter().map(|&(_, p)| p).sum::<f32>();
+        if total > 0.0 && total.is_finite() {
+            sampler.finite_pmf /= total;
+            for (_, pmf) in &mut sampler.infinite {
+                *pmf /= total;
+            }
+        } else {
+            let slots = sampler.infinite.len() + usize::from(!sampler.nodes.is_empty());
+            let share = 1.0 / slots.max(1) as f32;
+            sampler.finite_pmf = if sampler.nodes.is_empty() { 0.0 } else { share };
+            for (_, pmf) in &mut sampler.infinite {
+                *pmf = share;
+            }
+        }
+        sampler
+    }
+
+    /// Picks a light for `point` with `u` in `[0, 1)`, returning its index and probability.
+    pub(super) fn sample(&self, point: Vec3, u: f32) -> Option<(usize, f32)> {
+        let mut u = u;
+        for &(index, pmf) in &self.infinite {
+            if u < pmf {
+                return Some((index, pmf));
+            }
+            u -= pmf;
+        }
+        if self.nodes.is_empty() || self.finite_pmf <= 0.0 {
+            // Only rounding leaves `u` past the lights at infinity here.
+            return self.infinite.iter().rfind(|&&(_, pmf)| pmf > 0.0).copied();
+        }
+
+        let mut u = (u / self.finite_pmf).clamp(0.0, 1.0);
+        let mut pmf = self.finite_pmf;
+        let mut node = 0;
+        loop {
+            match self.nodes[node].kind {
+                LightNodeKind::Leaf(index) => return Some((index, pmf)),
+                LightNodeKind::Interior(left, right) => {
+                    let p_left = self.left_probability(point, left, right)?;
+                    if u < p_left {
+                        u /= p_left;
+                        pmf *= p_left;
+                        node = left;
+                    } else {
+                        u = (u - p_left) / (1.0 - p_left);
+                        pmf *= 1.0 - p_left;
+                        node = right;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Probability of `sample` picking light `index` at `point`.
+    pub(super) fn pmf(&self, point: Vec3, index: usize) -> f32 {
+        let Some(mut trail) = self.trails.get(index).copied().flatten() else {
+            return self
+                .infinite
+                .iter()
+                .find(|&&(infinite, _)| infinite == index)
+                .map_or(0.0, |&(_, pmf)| pmf);
+        };
+        let mut pmf = self.finite_pmf;
+        let mut node = 0;
+        while let LightNodeKind::Interior(left, right) = self.nodes[node].kind {
+            let Some(p_left) = self.left_probability(point, left, right) else {
+                return 0.0;
+            };
+            if trail & 1 == 0 {
+                pmf *= p_left;
+                node = left;
+            } else {
+                pmf *= 1.0 - p_left;
+                node = right;
+            }
+            trail >>= 1;
+        }
+        pmf
+    }
+
+    /// Combined probability of picking any of the lights at infinity matching `filter`.
+    pub(super) fn infinite_pmf(&self, filter: impl Fn(usize) -> bool) -> f32 {
+        self.infinite
+            .iter()
+            .filter(|&&(index, _)| filter(index))
+            .map(|&(_, pmf)| pmf)
+            .sum()
+    }
+
+    fn left_probability(&self, point: Vec3, left: usize, right: usize) -> Option<f32> {
+        let left = self.nodes[left].importance(point);
+        let right = self.nodes[right].importance(point);
+        let total = left + right;
+        (total > 0.0).then(|| left / total)
+    }
+
+    /// Splits `lights` at the median along the widest axis of their centers.
+    fn build(&mut self, lights: &mut [LightBounds], trail: u64, depth: u32) -> usize {
+        let node = self.nodes.len();
+        if let [light] = lights {
+            self.nodes.push(LightNode {
+                center: light.center,
+                radius: light.radius,
+                power: light.power,
+                kind: LightNodeKind::Leaf(light.index),
+            });
+            self.trails[light.index] = Some(trail);
+            return node;
+        }
+
+        let (mut min, mut max) = (lights[0].center, lights[0].center);
+        for light in lights.iter() {
+            min = min.min(light.center);
+            max = max.max(light.center);
+        }
+        let extent = max.sub(min);
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+        lights.sort_by(|a, b| axis_value(a.center, axis).total_cmp(&axis_value(b.center, axis)));
+
+        self.nodes.push(LightNode {
+            center: Vec3::new(0.0, 0.0, 0.0),
+            radius: 0.0,
+            power: 0.0,
+            kind: LightNodeKind::Leaf(0),
+        });
+        let (first, second) = lights.split_at_mut(lights.len() / 2);
+        let left = self.build(first, trail, depth + 1);
+        let right = self.build(second, trail | (1 << depth.min(63)), depth + 1);
+        let (a, b) = (self.nodes[left], self.nodes[right]);
+        let lo = a
+            .center
+            .sub(Vec3::new(a.radius, a.radius, a.radius))
+            .min(b.center.sub(Vec3::new(b.radius, b.radius, b.radius)));
+        let hi = a
+            .center
+            .add(Vec3::new(a.radius, a.radius, a.radius))
+            .max(b.center.add(Vec3::new(b.radius, b.radius, b.radius)));
+        self.nodes[node] = LightNode {
+            center: lo.add(hi).mul(0.5),
+            radius: hi.sub(lo).length() * 0.5,
+            power: a.power + b.power,
+            kind: LightNodeKind::Interior(left, right),
+        };
+        node
+    }
+}
+
+impl LightNode {
+    /// Power over squared distance, with the distance held at the node's radius once the
+    /// point is inside it.
+    fn importance(&self, point: Vec3) -> f32 {
+        let offset = point.sub(self.center);
+        let dist2 = offset
+            .dot(offset)
+            .max(self.radius * self.radius)
+            .max(1.0e-8);
+        self.power / dist2
+    }
+}
+
+impl PathLight {
+    /// Bounding sphere of a finite light; `None` for lights at infinity.
+    fn bounds(&self) -> Option<(Vec3, f32)> {
+        match self {
+            PathLight::Env { .. } | PathLight::EnvMap | PathLight::Directional(_) => None,
+            PathLight::Point { position, .. } => Some((from_api_vec3(*position), 0.0)),
+            PathLight::Sphere(light) => Some((from_api_vec3(light.position), light.radius)),
+            PathLight::Spot(light) => Some((from_api_vec3(light.position), 0.0)),
+            PathLight::Rect(light) => Some((
+                from_api_vec3(light.position),
+                (light.right + light.up).length(),
+            )),
+            PathLight::Disk(light) => Some((from_api_vec3(light.position), light.radius)),
+        }
+    }
+
+    /// Luminance of the total emitted power, used to weigh finite lights against each other.
+    fn power(&self) -> f32 {
+        use std::f32::consts::PI;
+        match self {
+            PathLight::Env { .. } | PathLight::EnvMap | PathLight::Directional(_) => 0.0,
+            PathLight::Point { intensity, .. } => 4.0 * PI * spectrum_luminance(*intensity),
+            PathLight::Sphere(light) => 4.0 * PI * spectrum_luminance(light.intensity),
+            PathLight::Spot(light) => {
+                let half = 0.5 * light.angle * (1.0 - 0.5 * light.blend);
+                2.0 * PI * (1.0 - half.cos()) * spectrum_luminance(light.intensity)
+            }
+            PathLight::Rect(light) => {
+                let area = 4.0 * light.right.cross(light.up).length();
+                let sides = if light.two_sided { 2.0 } else { 1.0 };
+                PI * area * sides * spectrum_luminance(light.radiance)
+            }
+            PathLight::Disk(light) => {
+                let area = PI * light.radius * light.radius;
+                let sides = if light.two_sided { 2.0 } else { 1.0 };
+                PI * area * sides * spectrum_luminance(light.radiance)
+            }
+        }
+        .max(0.0)
+    }
+
+    /// Luminance of the power a light at infinity sends through a disc of `scene_radius`,
+    /// which weighs it against the finite lights; `0` for finite lights.
+    fn infinite_power(&self, env_map: Option<&HdriMap>, scene_radius: f32) -> f32 {
+        use std::f32::consts::PI;
+        let area = PI * scene_radius * scene_radius;
+        match self {
+            PathLight::Env { radiance } => 4.0 * PI * area * spectrum_luminance(*radiance),
+            PathLight::EnvMap => {
+                4.0 * PI * area * env_map.map_or(0.0, |map| map.average_luminance())
+            }
+            PathLight::Directional(light) => area * spectrum_luminance(light.irradiance),
+            PathLight::Point { .. }
+            | PathLight::Sphere(_)
+            | PathLight::Spot(_)
+            | PathLight::Rect(_)
+            | PathLight::Disk(_) => 0.0,
+        }
+        .max(0.0)
+    }
+}
+
+fn axis_value(v: Vec3, axis: usize) -> f32 {
+    match axis {
+        0 => v.x,
+        1 => v.y,
+        _ => v.z,
+    }
+}
@@ -0,0 +1,204 @@
+use super::*;
+
+/// Number of segments a `motion(t)` hook is sampled into across the shutter interval.
+const MOTION_HOOK_SEGMENTS: usize = 16;
+
+/// Interval the shutter stays open, in scene time. An empty interval disables motion blur and
+/// poses everything at `open`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Shutter {
+    pub open: f32,
+    pub close: f32,
+}
+
+impl Shutter {
+    /// Whether the interval is empty, so samples never need a time of their own.
+    pub fn is_instant(self) -> bool {
+        self.close <= self.open
+    }
+
+    /// Maps `u` in `[0, 1]` onto the shutter interval.
+    pub fn time_at(self, u: f32) -> f32 {
+        if self.is_instant() {
+            self.open
+        } else {
+            self.open + (self.close - self.open) * u
+        }
+    }
+
+    /// Draws a sample time; instant shutters leave `rng` untouched so static renders keep
+    /// their sample sequences.
+    pub(super) fn sample_time(self, rng: &mut XorShift64) -> f32 {
+        if self.is_instant() {
+            self.open
+        } else {
+            self.time_at(rng.next_f32())
+        }
+    }
+
+    fn fraction(self, time: f32) -> f32 {
+        if self.is_instant() {
+            0.0
+        } else {
+            ((time - self.open) / (self.close - self.open)).clamp(0.0, 1.0)
+        }
+    }
+}
+
+/// Scene times at which a `motion(t)` hook is evaluated: evenly spread over the shutter, or
+/// just the opening time when the shutter is instant.
+pub(super) fn motion_hook_times(shutter: Shutter) -> Vec<f32> {
+    if shutter.is_instant() {
+        return vec![shutter.open];
+    }
+    (0..=MOTION_HOOK_SEGMENTS)
+        .map(|i| shutter.time_at(i as f32 / MOTION_HOOK_SEGMENTS as f32))
+        .collect()
+}
+
+/// Object poses spread evenly across the shutter interval.
+#[derive(Clone)]
+pub(super) struct MotionKeys {
+    shutter: Shutter,
+    keys: Vec<PrimitiveTransform>,
+}
+
+impl MotionKeys {
+    fn transform_at(&self, time: f32) -> PrimitiveTransform {
+        let segments = self.keys.len() - 1;
+        if segments == 0 {
+            return self.keys[0];
+        }
+        let scaled = self.shutter.fraction(time) * segments as f32;
+        let index = (scaled as usize).min(segments - 1);
+        lerp_transform(
+            self.keys[index],
+            self.keys[index + 1],
+            scaled - index as f32,
+        )
+    }
+
+    pub(super) fn map(
+        &self,
+        map_transform: impl Fn(PrimitiveTransform) -> PrimitiveTransform,
+    ) -> Self {
+        Self {
+            shutter: self.shutter,
+            keys: self.keys.iter().copied().map(map_transform).collect(),
+        }
+    }
+}
+
+fn lerp_transform(a: PrimitiveTransform, b: PrimitiveTransform, w: f32) -> PrimitiveTransform {
+    PrimitiveTransform {
+        center: a.center.mul(1.0 - w).add(b.center.mul(w)),
+        rot_deg: a.rot_deg.mul(1.0 - w).add(b.rot_deg.mul(w)),
+    }
+}
+
+/// Maps a world point at shutter `time` into the space of the node compiled at `rest`.
+pub(super) fn motion_point(
+    p: Vec3,
+    rest: PrimitiveTransform,
+    motion: &MotionKeys,
+    time: f32,
+) -> Vec3 {
+    let posed = motion.transform_at(time);
+    from_local(to_local(p, posed), rest)
+}
+
+fn from_local(q: Vec3, transform: PrimitiveTransform) -> Vec3 {
+    transform.center.add(transform_offset(transform, q))
+}
+
+/// Wraps `node` in a `Motion` node when the object carries `pos_open`/`pos_close`,
+/// `rot_open`/`rot_close`, or a `motion(t)` hook.
+///
+/// The hook gets the scene time and returns either the position as a vec3 or an object with
+/// `pos` and/or `rot`; missing parts keep the object's own transform. A hook that fails or
+/// returns anything else is an error.
+pub(super) fn apply_object_motion(
+    state: &Arc<EvalState>,
+    object: &ObjectValue,
+    node: SdfNode,
+    shutter: Shutter,
+) -> Result<SdfNode, RenderError> {
+    let rest = read_transform(object);
+    let keys = if let Some(Value::Function(hook)) = object.fields.get("motion") {
+        motion_hook_times(shutter)
+            .into_iter()
+            .map(
+                |time| match eval_function_value(state, hook, &[Value::Number(time)]) {
+                    Ok(Value::Object(pose)) => Ok(pose_from_object(&pose, rest)),
+                    Ok(_) => Err(RenderError::Motion(format!(
+                        "motion({time}) must return a vec3 or an object with pos and/or rot"
+                    ))),
+                    Err(err) => Err(RenderError::Motion(format!("motion({time}): {err}"))),
+                },
+            )
+            .collect::<Result<Vec<_>, _>>()?
+    } else {
+        let keyed = ["pos_open", "pos_close", "rot_open", "rot_close"]
+            .iter()
+            .any(|name| object.fields.contains_key(*name));
+        if !keyed {
+            return Ok(node);
+        }
+        let pose = |pos: &str, rot: &str| PrimitiveTransform {
+            center: read_vec3_field(object, pos).unwrap_or(rest.center),
+            rot_deg: read_vec3_field(object, rot).unwrap_or(rest.rot_deg),
+        };
+        vec![pose("pos_open", "rot_open"), pose("pos_close", "rot_close")]
+    };
+    let motion = MotionKeys { shutter, keys };
+    let bounds = swept_bounds(sdf_bounds(&node), rest, &motion);
+    Ok(SdfNode::Motion {
+        base: Box::new(node),
+        rest,
+        motion: Arc::new(motion),
+        bounds,
+    })
+}
+
+fn pose_from_object(pose: &ObjectValue, rest: PrimitiveTransform) -> PrimitiveTransform {
+    if pose.fields.contains_key("pos") || pose.fields.contains_key("rot") {
+        PrimitiveTransform {
+            center: read_vec3_field(pose, "pos").unwrap_or(rest.center),
+            rot_deg: read_vec3_field(pose, "rot").unwrap_or(rest.rot_deg),
+        }
+    } else {
+        PrimitiveTransform {
+            center: read_center(pose),
+            rot_deg: rest.rot_deg,
+        }
+    }
+}
+
+/// Bounds of `base` (compiled at `rest`) swept across every pose in `motion`.
+pub(super) fn swept_bounds(base: Aabb, rest: PrimitiveTransform, motion: &MotionKeys) -> Aabb {
+    // Subdivide each segment so rotation arcs between keys stay inside the box.
+    const STEPS: usize = 8;
+    let segments = motion.keys.len() - 1;
+    let extremes = [base.min, base.max];
+    let mut swept: Option<Aabb> = None;
+    for i in 0..=segments.max(1) * STEPS {
+        let u = i as f32 / (segments.max(1) * STEPS) as f32;
+        let posed = motion.transform_at(motion.shutter.time_at(u));
+        for corner in 0..8 {
+            let corner = Vec3::new(
+                extremes[corner & 1].x,
+                extremes[(corner >> 1) & 1].y,
+                extremes[(corner >> 2) & 1].z,
+            );
+            let local = to_local(corner, rest);
+            let world = from_local(local, posed);
+            let point = Aabb {
+                min: world,
+                max: world,
+            };
+            swept = Some(swept.map_or(point, |b| b.union(point)));
+        }
+    }
+    let swept = swept.unwrap_or(base);
+    swept.expand(swept.extent().length() * 0.01)
+}
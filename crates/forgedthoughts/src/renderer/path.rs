@@ -1,5 +1,8 @@
 use super::*;
 
+/// Radiance along one camera ray. Spectral paths pass their `wavelengths` and get back the
+/// radiance at each of them, still to be converted to RGB.
+#[allow(clippy::too_many_arguments)]
 pub(super) fn trace_path(
     accel: &(impl Accelerator + Sync),
     setup: &RenderSetup,
@@ -7,7 +10,9 @@ pub(super) fn trace_path(
     mut dir: Vec3,
     options: RenderOptions,
     max_bounces: u32,
+    mut wavelengths: Option<Wavelengths>,
     rng: &mut XorShift64,
+    time: f32,
 ) -> Spectrum {
     let mut throughput = Spectrum::rgb(1.0, 1.0, 1.0);
     let mut radiance = Spectrum::black();
@@ -16,49 +21,183 @@ pub(super) fn trace_path(
         setup,
         options,
         lights: &setup.path_lights,
+        wavelengths,
     };
-    let mut prev_env_mis_pdf: Option<f32> = None;
-    let mut prev_env_mis_ctx: Option<BsdfContextBase> = None;
-    let mut prev_env_mis_mat: Option<MaterialKindRt> = None;
+    let mut prev_bsdf_pdf: Option<f32> = None;
+    let mut prev_bsdf_ctx: Option<BsdfContextBase> = None;
+    let mut prev_bsdf_mat: Option<MaterialKindRt> = None;
     let mut medium = MediumState::air();
+    // Set while the next segment starts inside a shape that scatters light below its surface,
+    // together with the medium around the shape that the walk comes back out into.
+    let mut subsurface: Option<(SubsurfaceParams, MediumState)> = None;
+    // Point and phase density of the medium scattering event the current segment leaves from.
+    let mut prev_scatter: Option<(Vec3, f32)> = None;
 
     for bounce in 0..max_bounces {
-        let min_t = if bounce == 0 {
-            0.0
+        let (hit, mat, bsdf_ctx) = if let Some((params, outside)) = subsurface.take() {
+            let Some(exit) = random_walk(accel, options, params, origin, dir, rng, time) else {
+                break;
+            };
+            throughput = throughput * lift_rgb(wavelengths, exit.weight);
+            medium = outside;
+            let (hit, mat, bsdf_ctx) = subsurface_exit(setup, exit.hit, medium);
+            let (mat, bsdf_ctx) =
+                spectral_hit(&mut wavelengths, &mut throughput, mat, bsdf_ctx, medium);
+            (hit, mat, bsdf_ctx)
         } else {
-            secondary_min_t(options.epsilon)
-        };
-        let Some(hit) = raymarch_hit(accel, origin, dir, options, min_t, options.max_dist) else {
-            let env = apply_medium_attenuation(
-                environment_color(setup, dir).unwrap_or_else(|| env_radiance(&setup.path_lights)),
-                medium,
+            let min_t = if bounce == 0 {
+                0.0
+            } else {
+                secondary_min_t(options.epsilon)
+            };
+            let (hit, crossed) = raymarch_medium_hit(
+                accel,
+                setup,
+                options,
+                origin,
+                dir,
+                min_t,
                 options.max_dist,
+                medium,
+                time,
             );
-            let weight = if let (Some(bsdf_pdf), Some(prev_ctx), Some(prev_mat)) =
-                (prev_env_mis_pdf, prev_env_mis_ctx, prev_env_mis_mat)
-            {
-                let light_pdf =
-                    env_light_pdf_for_dir(&setup.path_lights, setup, prev_mat, prev_ctx, dir)
-                        .max(1.0e-6);
-                power_heuristic(bsdf_pdf.max(1.0e-6), light_pdf)
+            let surface_t = hit.as_ref().map_or(options.max_dist, |hit| hit.t);
+            let scatter = if has_scattering_media(setup, medium) {
+                sample_medium_scatter(setup, medium, origin, dir, surface_t, rng, time)
             } else {
-                1.0
+                None
             };
-            radiance = radiance + (throughput * env).scale(weight);
-            break;
-        };
+            // Overridden surfaces leave the active medium alone, so only the stack a scattering
+            // event continues with depends on which of them lie before it.
+            medium = match scatter {
+                Some(scatter) if medium.active().is_some() => {
+                    raymarch_medium_hit(
+                        accel, setup, options, origin, dir, min_t, scatter.t, medium, time,
+                    )
+                    .1
+                }
+                _ => crossed,
+            };
+            let scattered_from = prev_scatter.take();
+            let emitted = emitter_radiance(
+                setup,
+                origin,
+                dir,
+                scatter.map_or(surface_t, |scatter| scatter.t),
+                hit.is_none() && scatter.is_none(),
+                prev_bsdf_pdf
+                    .zip(prev_bsdf_ctx)
+                    .map(|(pdf, ctx)| (ctx.hit.position, pdf))
+                    .or(scattered_from),
+                medium,
+            );
+            radiance = radiance + throughput * lift_rgb(wavelengths, emitted);
+
+            if let Some(scatter) = scatter {
+                throughput = throughput
+                    * lift_rgb(wavelengths, medium_transmittance(medium, scatter.t))
+                    * lift_rgb(wavelengths, scatter.albedo);
+                let point = origin.add(dir.mul(scatter.t));
+                let direct = estimate_direct_phase(
+                    &direct_ctx,
+                    medium,
+                    point,
+                    dir,
+                    scatter.anisotropy,
+                    rng,
+                    time,
+                );
+                radiance = radiance + clamp_spectrum(throughput * direct, 12.0);
+
+                let next = sample_henyey_greenstein(
+                    dir,
+                    scatter.anisotropy,
+                    rng.next_f32(),
+                    rng.next_f32(),
+                );
+                prev_scatter = Some((point, henyey_greenstein(dir.dot(next), scatter.anisotropy)));
+                prev_bsdf_pdf = None;
+                prev_bsdf_ctx = None;
+                prev_bsdf_mat = None;
+                if bounce >= 2 {
+                    let p = spectrum_luminance(throughput).clamp(0.05, 0.98);
+                    if rng.next_f32() > p {
+                        break;
+                    }
+                    throughput = throughput.scale(1.0 / p);
+                }
+                origin = point;
+                dir = next;
+                continue;
+            }
 
-        throughput = throughput * medium_transmittance(medium, hit.t);
+            let Some(hit) = hit else {
+                let env = apply_medium_attenuation(
+                    environment_color(setup, dir)
+                        .unwrap_or_else(|| env_radiance(&setup.path_lights)),
+                    medium,
+                    options.max_dist,
+                );
+                let weight = if let Some((_, phase_pdf)) = scattered_from {
+                    power_heuristic(phase_pdf, medium_env_light_pdf(setup, dir).max(1.0e-6))
+                } else if let (Some(bsdf_pdf), Some(prev_ctx), Some(prev_mat)) =
+                    (prev_bsdf_pdf, prev_bsdf_ctx, prev_bsdf_mat)
+                {
+                    let light_pdf =
+                        env_light_pdf_for_dir(&setup.path_lights, setup, prev_mat, prev_ctx, dir)
+                            .max(1.0e-6);
+                    power_heuristic(bsdf_pdf.max(1.0e-6), light_pdf)
+                } else {
+                    1.0
+                };
+                radiance = radiance + (throughput * lift_rgb(wavelengths, env)).scale(weight);
+                break;
+            };
+
+            throughput = throughput * lift_rgb(wavelengths, medium_transmittance(medium, hit.t));
+            let mat = resolve_material_at_hit(setup, hit, dir.mul(-1.0).normalize());
+            let bsdf_ctx = build_bsdf_context(setup, hit, mat, dir.mul(-1.0), medium);
+            let (mat, bsdf_ctx) =
+                spectral_hit(&mut wavelengths, &mut throughput, mat, bsdf_ctx, medium);
+            let emission = mat.emission();
+            if emission.r > 0.0 || emission.g > 0.0 || emission.b > 0.0 {
+                radiance = radiance + (throughput * emission);
+            }
+
+            // Opaque subsurface materials send light either off their specular coat or, through
+            // a diffuse transmission lobe, into the body below; dielectrics let it in by
+            // refraction further down.
+            let body = subsurface_params(mat)
+                .filter(|_| hit.front_face && !matches!(mat, Material::Dielectric(_)));
+            if let Some(params) = body {
+                let coat = subsurface_coat(mat);
+                let coat_pick = if coat.is_some() { 0.5 } else { 0.0 };
+                match coat.filter(|_| rng.next_f32() < coat_pick) {
+                    Some(coat) => {
+                        throughput = throughput.scale(1.0 / coat_pick);
+                        (hit, coat, bsdf_ctx)
+                    }
+                    None => {
+                        throughput =
+                            throughput.scale(subsurface_body_weight(mat) / (1.0 - coat_pick));
+                        let outward = hit.normal.normalize();
+                        dir = cosine_sample_hemisphere(outward.mul(-1.0), rng);
+                        origin =
+                            offset_ray_origin(hit.position, outward, dir, options.epsilon * 8.0);
+                        subsurface = Some((params, medium));
+                        prev_bsdf_pdf = None;
+                        prev_bsdf_ctx = None;
+                        prev_bsdf_mat = None;
+                        continue;
+                    }
+                }
+            } else {
+                (hit, mat, bsdf_ctx)
+            }
+        };
         let hit_point = hit.position;
-        let _ = (hit.object_id, hit.material_id);
-        let mat = resolve_material_at_hit(setup, hit, dir.mul(-1.0).normalize());
-        let bsdf_ctx = build_bsdf_context(setup, hit, dir.mul(-1.0), medium.ior);
-        let emission = mat.emission();
-        if emission.r > 0.0 || emission.g > 0.0 || emission.b > 0.0 {
-            radiance = radiance + (throughput * emission);
-        }
 
-        let direct = estimate_direct_mis(&direct_ctx, mat, hit_point, bsdf_ctx, rng);
+        let direct = estimate_direct_mis(&direct_ctx, mat, hit_point, bsdf_ctx, rng, time);
         radiance = radiance + clamp_spectrum(throughput * direct, 12.0);
 
         let sample = sample_bsdf_lobe(setup, mat, bsdf_ctx, rng);
@@ -76,13 +215,13 @@ pub(super) fn trace_path(
         }
         throughput = clamp_spectrum(throughput, 8.0);
         if sample.delta {
-            prev_env_mis_pdf = None;
-            prev_env_mis_ctx = None;
-            prev_env_mis_mat = None;
+            prev_bsdf_pdf = None;
+            prev_bsdf_ctx = None;
+            prev_bsdf_mat = None;
         } else {
-            prev_env_mis_pdf = Some(sample.pdf.max(1.0e-6));
-            prev_env_mis_ctx = Some(bsdf_ctx);
-            prev_env_mis_mat = Some(mat);
+            prev_bsdf_pdf = Some(sample.pdf.max(1.0e-6));
+            prev_bsdf_ctx = Some(bsdf_ctx);
+            prev_bsdf_mat = Some(mat);
         }
 
         if bounce >= 2 {
@@ -99,9 +238,15 @@ pub(super) fn trace_path(
         } else {
             hit.normal.mul(-1.0).normalize()
         };
+        if matches!(mat, Material::Dielectric(_))
+            && sample.transmission
+            && !sample.thin_walled
+            && hit.front_face
+        {
+            subsurface = subsurface_params(mat).map(|params| (params, medium));
+        }
         if sample.transmission && !sample.thin_walled {
-            medium = transition_medium(mat, hit.front_face, medium);
-            medium.ior = sample.next_ior.clamp(1.0, 3.0);
+            medium = transition_medium(mat, hit, medium);
             origin = offset_ray_origin(hit_point, geometric_normal, dir, options.epsilon * 8.0);
         } else {
             origin = offset_ray_origin(hit_point, geometric_normal, dir, options.epsilon);
@@ -110,3 +255,25 @@ pub(super) fn trace_path(
 
     radiance
 }
+
+/// `mat` and its BSDF context as a spectral path sees them; RGB paths keep both as they are.
+/// A dispersive `mat` fills the medium it bounds at the hero wavelength's index.
+fn spectral_hit(
+    wavelengths: &mut Option<Wavelengths>,
+    throughput: &mut Spectrum,
+    mat: MaterialKindRt,
+    bsdf_ctx: BsdfContextBase,
+    medium: MediumState,
+) -> (MaterialKindRt, BsdfContextBase) {
+    let Some(wavelengths) = wavelengths else {
+        return (mat, bsdf_ctx);
+    };
+    let (mat, weight) = wavelengths.material(mat);
+    *throughput = *throughput * weight;
+    let bsdf_ctx = BsdfContextBase {
+        interior_ior: transition_medium(mat, bsdf_ctx.hit, medium).ior,
+        wavelengths: Some(*wavelengths),
+        ..bsdf_ctx
+    };
+    (mat, bsdf_ctx)
+}
@@ -1,5 +1,6 @@
 use super::*;
 
+#[allow(clippy::too_many_arguments)]
 pub(super) fn trace_ray_recursive(
     accel: &(impl Accelerator + Sync),
     setup: &RenderSetup,
@@ -8,6 +9,7 @@ pub(super) fn trace_ray_recursive(
     dir: Vec3,
     medium: MediumState,
     depth: u32,
+    time: f32,
 ) -> Spectrum {
     if depth >= ctx.max_depth {
         return Spectrum::black();
@@ -18,23 +20,52 @@ pub(super) fn trace_ray_recursive(
     } else {
         secondary_min_t(ctx.options.epsilon)
     };
-    let Some(hit) = raymarch_hit(accel, origin, dir, ctx.options, min_t, ctx.options.max_dist)
-    else {
-        return apply_medium_attenuation(
+    let (hit, medium) = raymarch_medium_hit(
+        accel,
+        setup,
+        ctx.options,
+        origin,
+        dir,
+        min_t,
+        ctx.options.max_dist,
+        medium,
+        time,
+    );
+    let color = match hit {
+        None => apply_medium_attenuation(
             environment_color(setup, dir).unwrap_or_else(|| env_radiance(&setup.path_lights)),
             medium,
             ctx.options.max_dist,
-        );
+        ),
+        Some(hit) => {
+            let view_dir = dir.mul(-1.0).normalize();
+            if let Some((a, b, t)) = resolve_split_material_at_hit(setup, hit, view_dir) {
+                let a =
+                    trace_hit_with_material(accel, setup, ctx, hit, dir, medium, depth, a, time);
+                let b =
+                    trace_hit_with_material(accel, setup, ctx, hit, dir, medium, depth, b, time);
+                lerp_spectrum(a, b, t)
+            } else {
+                let mat = resolve_material_at_hit(setup, hit, view_dir);
+                trace_hit_with_material(accel, setup, ctx, hit, dir, medium, depth, mat, time)
+            }
+        }
     };
-
-    let view_dir = dir.mul(-1.0).normalize();
-    if let Some((a, b, t)) = resolve_split_material_at_hit(setup, hit, view_dir) {
-        let a = trace_hit_with_material(accel, setup, ctx, hit, dir, medium, depth, a);
-        let b = trace_hit_with_material(accel, setup, ctx, hit, dir, medium, depth, b);
-        return lerp_spectrum(a, b, t);
+    if !has_scattering_media(setup, medium) {
+        return color;
     }
-    let mat = resolve_material_at_hit(setup, hit, view_dir);
-    trace_hit_with_material(accel, setup, ctx, hit, dir, medium, depth, mat)
+    let t_end = hit.map_or(ctx.options.max_dist, |hit| hit.t);
+    scatter_along_ray(
+        accel,
+        setup,
+        ctx.options,
+        medium,
+        origin,
+        dir,
+        t_end,
+        color,
+        time,
+    )
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -47,6 +78,7 @@ fn trace_hit_with_material(
     medium: MediumState,
     depth: u32,
     mat: MaterialKindRt,
+    time: f32,
 ) -> Spectrum {
     let hit_point = hit.position;
     let normal = if hit.front_face {
@@ -54,7 +86,7 @@ fn trace_hit_with_material(
     } else {
         hit.normal.mul(-1.0).normalize()
     };
-    let bsdf_ctx = build_bsdf_context(setup, hit, dir.mul(-1.0), medium.ior);
+    let bsdf_ctx = build_bsdf_context(setup, hit, mat, dir.mul(-1.0), medium);
     let params = dominant_material_params(mat);
     let roughness = params.roughness.clamp(0.0, 1.0);
     let (
@@ -87,7 +119,12 @@ fn trace_hit_with_material(
             0.0,
             0.0,
         ),
-        MaterialKindTag::Metal => (0.0, 1.0, false, params.color, params.color, 1.0, 0.0, 1.0),
+        MaterialKindTag::Metal => {
+            let tint = params.conductor.map_or(params.color, |ior| {
+                params.color * ior.fresnel(bsdf_ctx.normal.dot(bsdf_ctx.wo).abs())
+            });
+            (0.0, 1.0, false, tint, tint, 1.0, 0.0, 1.0)
+        }
         MaterialKindTag::Dielectric => (
             1.0,
             params.ior.clamp(1.0, 3.0),
@@ -98,13 +135,23 @@ fn trace_hit_with_material(
             0.0,
             1.0,
         ),
+        MaterialKindTag::OpenPbr => (
+            params.transmission.clamp(0.0, 1.0),
+            params.ior.clamp(1.0, 3.0),
+            params.thin_walled,
+            params.specular_color,
+            params.color,
+            params.metallic.clamp(0.0, 1.0),
+            params.clearcoat.clamp(0.0, 1.0),
+            (params.specular * params.specular_weight).clamp(0.0, 1.0),
+        ),
     };
-    let (eta_i, eta_t, next_ior) = if thin {
-        (1.0, 1.0, medium.ior)
+    let (eta_i, eta_t) = if thin {
+        (1.0, 1.0)
     } else if hit.front_face {
-        (medium.ior, ior, ior)
+        (medium.ior, bsdf_ctx.interior_ior)
     } else {
-        (medium.ior, 1.0, 1.0)
+        (medium.ior, bsdf_ctx.exterior_ior)
     };
 
     let wo = bsdf_ctx.wo;
@@ -131,8 +178,34 @@ fn trace_hit_with_material(
     };
 
     let local_weight = ((1.0 - transmission) * (1.0 - reflect_weight)).clamp(0.0, 1.0);
-    let mut color =
-        shade_color(accel, setup, ctx.options, &setup.lights, mat, bsdf_ctx).scale(local_weight);
+    let subsurface = subsurface_params(mat).filter(|_| hit.front_face && !thin);
+    let local = match subsurface.filter(|_| transmission <= 1.0e-4) {
+        Some(params) => {
+            let coat = subsurface_coat(mat).map_or(Spectrum::black(), |coat| {
+                shade_color(
+                    accel,
+                    setup,
+                    ctx.options,
+                    &setup.lights,
+                    coat,
+                    bsdf_ctx,
+                    time,
+                )
+            });
+            coat + diffusion_radiance(accel, setup, ctx.options, params, bsdf_ctx, time)
+                .scale(subsurface_body_weight(mat))
+        }
+        None => shade_color(
+            accel,
+            setup,
+            ctx.options,
+            &setup.lights,
+            mat,
+            bsdf_ctx,
+            time,
+        ),
+    };
+    let mut color = local.scale(local_weight);
     let emission = mat.emission();
     color = color + emission;
 
@@ -149,6 +222,7 @@ fn trace_hit_with_material(
                 medium,
                 depth,
                 roughness,
+                time,
             )
         } else {
             let reflect_dir = reflect(dir, normal).normalize();
@@ -166,19 +240,23 @@ fn trace_hit_with_material(
                 reflect_dir,
                 medium,
                 depth + 1,
+                time,
             )
         };
         let reflect_tint = lerp_spectrum(specular_color, base_color, metallic.clamp(0.0, 1.0));
         color = color + (reflected * reflect_tint).scale(reflect_weight);
     }
 
-    if transmission > 1.0e-4 {
-        let mut next_medium = if thin {
+    if let Some(params) = subsurface.filter(|_| transmission > 1.0e-4) {
+        // Light refracted into a scattering body comes back out diffused rather than seen through.
+        let body = diffusion_radiance(accel, setup, ctx.options, params, bsdf_ctx, time);
+        color = color + body.scale(transmission * (1.0 - fresnel));
+    } else if transmission > 1.0e-4 {
+        let next_medium = if thin {
             medium
         } else {
-            transition_medium(mat, hit.front_face, medium)
+            transition_medium(mat, hit, medium)
         };
-        next_medium.ior = next_ior.clamp(1.0, 3.0);
         let refracted = if roughness > 0.06 {
             trace_rough_dielectric_refraction(
                 accel,
@@ -192,6 +270,7 @@ fn trace_hit_with_material(
                 next_medium,
                 depth,
                 roughness,
+                time,
             )
         } else if let Some(refract_dir) = refract(dir, normal, eta_i / eta_t) {
             let refract_origin = offset_ray_origin(
@@ -208,6 +287,7 @@ fn trace_hit_with_material(
                 refract_dir.normalize(),
                 next_medium,
                 depth + 1,
+                time,
             )
         } else {
             Spectrum::black()
@@ -228,62 +308,79 @@ pub(super) fn trace_ray_debug_aov(
     origin: Vec3,
     dir: Vec3,
     aov: RayDebugAov,
+    time: f32,
 ) -> Spectrum {
-    let Some(hit) = raymarch_hit(accel, origin, dir, ctx.options, 0.0, ctx.options.max_dist) else {
+    let Some(sample) = sample_primary_aovs(accel, setup, ctx.options, origin, dir, time) else {
         return Spectrum::black();
     };
-    let mat = resolve_material_at_hit(setup, hit, dir.mul(-1.0).normalize());
-    let normal = if hit.front_face {
-        hit.normal.normalize()
-    } else {
-        hit.normal.mul(-1.0).normalize()
-    };
     match aov {
         RayDebugAov::Depth => {
-            let d = (1.0 - (hit.t / ctx.options.max_dist)).clamp(0.0, 1.0);
+            let d = (1.0 - (sample.depth / ctx.options.max_dist)).clamp(0.0, 1.0);
             Spectrum::rgb(d, d, d)
         }
         RayDebugAov::Normal => Spectrum::rgb(
-            normal.x * 0.5 + 0.5,
-            normal.y * 0.5 + 0.5,
-            normal.z * 0.5 + 0.5,
+            sample.normal[0] * 0.5 + 0.5,
+            sample.normal[1] * 0.5 + 0.5,
+            sample.normal[2] * 0.5 + 0.5,
         ),
-        RayDebugAov::MaterialId => material_id_color(hit.material_id),
+        RayDebugAov::MaterialId => material_id_color(sample.material_id),
         RayDebugAov::Ior => {
-            let ior = match dominant_material_model(mat) {
-                MaterialKindTag::Dielectric | MaterialKindTag::Standard => {
-                    dominant_material_params(mat).ior.clamp(1.0, 3.0)
-                }
-                _ => 1.0,
-            };
-            let v = ((ior - 1.0) / 2.0).clamp(0.0, 1.0);
+            let v = ((sample.ior - 1.0) / 2.0).clamp(0.0, 1.0);
             Spectrum::rgb(v, v, v)
         }
         RayDebugAov::Transmission => {
-            let v = matches!(
-                dominant_material_model(mat),
-                MaterialKindTag::Dielectric | MaterialKindTag::Standard
-            ) as u8 as f32;
+            let v = sample.transmission;
             Spectrum::rgb(v, v, v)
         }
         RayDebugAov::Fresnel => {
-            let wo = dir.mul(-1.0).normalize();
-            let ior = match dominant_material_model(mat) {
-                MaterialKindTag::Dielectric | MaterialKindTag::Standard => {
-                    dominant_material_params(mat).ior.clamp(1.0, 3.0)
-                }
-                _ => 1.0,
-            };
-            let f = fresnel_dielectric_scalar(normal.dot(wo).abs(), 1.0, ior);
+            let f = sample.fresnel;
             Spectrum::rgb(f, f, f)
         }
         RayDebugAov::HitT => {
-            let v = (hit.t / ctx.options.max_dist).clamp(0.0, 1.0);
+            let v = (sample.depth / ctx.options.max_dist).clamp(0.0, 1.0);
             Spectrum::rgb(v, v, v)
         }
     }
 }
 
+/// Raw values behind every `RayDebugAov` at the first surface along the ray.
+pub(super) fn sample_primary_aovs(
+    accel: &(impl Accelerator + Sync),
+    setup: &RenderSetup,
+    options: RenderOptions,
+    origin: Vec3,
+    dir: Vec3,
+    time: f32,
+) -> Option<AovSample> {
+    let hit = raymarch_hit(accel, origin, dir, options, 0.0, options.max_dist, time)?;
+    let wo = dir.mul(-1.0).normalize();
+    let mat = resolve_material_at_hit(setup, hit, wo);
+    let normal = if hit.front_face {
+        hit.normal.normalize()
+    } else {
+        hit.normal.mul(-1.0).normalize()
+    };
+    let transmissive = matches!(
+        dominant_material_model(mat),
+        MaterialKindTag::Dielectric | MaterialKindTag::Standard | MaterialKindTag::OpenPbr
+    );
+    let params = dominant_material_params(mat);
+    let ior = if transmissive {
+        params.ior.clamp(1.0, 3.0)
+    } else {
+        1.0
+    };
+    Some(AovSample {
+        depth: hit.t,
+        normal: [normal.x, normal.y, normal.z],
+        albedo: [params.color.r, params.color.g, params.color.b].map(|c| c.clamp(0.0, 1.0)),
+        material_id: hit.material_id,
+        ior,
+        transmission: transmissive as u8 as f32,
+        fresnel: fresnel_dielectric_scalar(normal.dot(wo).abs(), 1.0, ior),
+    })
+}
+
 fn transmission_tint(base_color: Spectrum, transmission: f32) -> Spectrum {
     let t = transmission.clamp(0.0, 1.0);
     Spectrum::rgb(
@@ -305,6 +402,7 @@ fn trace_rough_dielectric_reflection(
     medium: MediumState,
     depth: u32,
     roughness: f32,
+    time: f32,
 ) -> Spectrum {
     let ideal = reflect(dir, normal).normalize();
     trace_lobe_average(
@@ -318,6 +416,7 @@ fn trace_rough_dielectric_reflection(
         depth,
         roughness,
         rough_dielectric_reflection_samples(roughness),
+        time,
     )
 }
 
@@ -334,6 +433,7 @@ fn trace_rough_dielectric_refraction(
     medium: MediumState,
     depth: u32,
     roughness: f32,
+    time: f32,
 ) -> Spectrum {
     let Some(ideal) = refract(dir, normal, eta) else {
         return Spectrum::black();
@@ -349,6 +449,7 @@ fn trace_rough_dielectric_refraction(
         depth,
         roughness,
         rough_dielectric_refraction_samples(roughness),
+        time,
     )
 }
 
@@ -364,6 +465,7 @@ fn trace_lobe_average(
     depth: u32,
     roughness: f32,
     sample_count: u32,
+    time: f32,
 ) -> Spectrum {
     let dirs = rough_lobe_directions(ideal_dir, roughness, sample_count);
     let inv = 1.0 / dirs.len() as f32;
@@ -375,7 +477,8 @@ fn trace_lobe_average(
             d,
             ctx.options.epsilon * (8.0 + roughness * 12.0),
         );
-        sum = sum + trace_ray_recursive(accel, setup, ctx, origin, d, medium, depth + 1).scale(inv);
+        sum = sum
+            + trace_ray_recursive(accel, setup, ctx, origin, d, medium, depth + 1, time).scale(inv);
     }
     sum
 }
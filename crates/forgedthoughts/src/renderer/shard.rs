@@ -0,0 +1,404 @@
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+
+use super::checkpoint::{read_f32, read_u32, write_atomically};
+use super::*;
+
+const MAGIC: &[u8; 4] = b"FTSH";
+const VERSION: u32 = 1;
+/// Magic, version, frame size, exposure, flags and rectangle count.
+const HEADER_BYTES: u64 = 4 + 3 * 4 + 4 + 3 + 4;
+const RECT_BYTES: u64 = 4 * 4;
+/// Beauty of a pixel plus, with AOVs, at least the byte marking an empty sample.
+const MIN_PIXEL_BYTES: u64 = 3 * 4;
+
+/// Part of the frame one process renders when a single image is split across machines.
+///
+/// Pixels outside the shard are left black and skipped by the samplers; [`ShardLayers`]
+/// keeps only the covered part so [`merge_shards`] can assemble the frame afterwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderShard {
+    /// Pixel rectangle from `(x0, y0)` up to, but excluding, `(x1, y1)`.
+    Region { x0: u32, y0: u32, x1: u32, y1: u32 },
+    /// Tiles `index`, `index + count`, `index + 2 * count`, ... of the frame cut into
+    /// `tile_size` squares in scanline order. Interleaving spreads expensive areas evenly.
+    Tiles {
+        index: u32,
+        count: u32,
+        tile_size: u32,
+    },
+}
+
+impl RenderShard {
+    /// Whether the pixel at `(x, y)` of a `width` wide frame belongs to this shard.
+    pub fn contains(self, x: u32, y: u32, width: u32) -> bool {
+        match self {
+            Self::Region { x0, y0, x1, y1 } => (x0..x1).contains(&x) && (y0..y1).contains(&y),
+            Self::Tiles {
+                index,
+                count,
+                tile_size,
+            } => {
+                let tile_size = tile_size.max(1);
+                let columns = width.div_ceil(tile_size);
+                let tile = (y / tile_size) * columns + x / tile_size;
+                tile % count.max(1) == index
+            }
+        }
+    }
+
+    /// Rectangles the shard covers, clipped to the frame.
+    fn rects(self, width: usize, height: usize) -> Vec<TileJob> {
+        match self {
+            Self::Region { x0, y0, x1, y1 } => {
+                let (x0, y0) = (x0 as usize, y0 as usize);
+                let (x1, y1) = ((x1 as usize).min(width), (y1 as usize).min(height));
+                if x0 >= x1 || y0 >= y1 {
+                    return Vec::new();
+                }
+                vec![TileJob {
+                    tx: x0,
+                    ty: y0,
+                    tile_w: x1 - x0,
+                    tile_h: y1 - y0,
+                }]
+            }
+            Self::Tiles {
+                index,
+                count,
+                tile_size,
+            } => tile_jobs(width, height, tile_size.max(1) as usize)
+                .into_iter()
+                .enumerate()
+                .filter(|(tile, _)| *tile as u32 % count.max(1) == index)
+                .map(|(_, job)| job)
+                .collect(),
+        }
+    }
+
+    /// Render tiles of at most `tile_size` covering the shard.
+    pub(super) fn tile_jobs(self, width: usize, height: usize, tile_size: usize) -> Vec<TileJob> {
+        self.rects(width, height)
+            .into_iter()
+            .flat_map(|rect| {
+                tile_jobs(rect.tile_w, rect.tile_h, tile_size)
+                    .into_iter()
+                    .map(move |job| TileJob {
+                        tx: rect.tx + job.tx,
+                        ty: rect.ty + job.ty,
+                        ..job
+                    })
+            })
+            .collect()
+    }
+}
+
+/// Render tiles for the whole frame, or just the shard in `options`.
+pub(super) fn frame_tile_jobs(options: RenderOptions, tile_size: usize) -> Vec<TileJob> {
+    let (width, height) = (options.width as usize, options.height as usize);
+    match options.shard {
+        Some(shard) => shard.tile_jobs(width, height, tile_size),
+        None => tile_jobs(width, height, tile_size),
+    }
+}
+
+/// Whether `options` asks for the pixel at `(x, y)`.
+pub(super) fn covers_pixel(options: RenderOptions, x: u32, y: u32) -> bool {
+    options
+        .shard
+        .is_none_or(|shard| shard.contains(x, y, options.width))
+}
+
+/// Float layers of one shard, plus what the merge step needs to finish the frame.
+///
+/// Rectangles are stored in the order they were cut, each as row-major pixels.
+#[derive(Debug, Clone)]
+pub struct ShardLayers {
+    width: u32,
+    height: u32,
+    display: DisplayTransform,
+    rects: Vec<TileJob>,
+    beauty: Vec<[f32; 3]>,
+    aovs: Option<Vec<Option<AovSample>>>,
+}
+
+impl ShardLayers {
+    /// Keeps the part of a finished render that `shard` covers.
+    pub fn from_layers(
+        layers: &RenderLayers,
+        shard: RenderShard,
+        display: DisplayTransform,
+    ) -> Self {
+        let (width, height) = (layers.width as usize, layers.height as usize);
+        let rects = shard.rects(width, height);
+        let pixels = rects
+            .iter()
+            .flat_map(|rect| {
+                (rect.ty..rect.ty + rect.tile_h)
+                    .flat_map(move |y| (rect.tx..rect.tx + rect.tile_w).map(move |x| y * width + x))
+            })
+            .collect::<Vec<_>>();
+        Self {
+            width: layers.width,
+            height: layers.height,
+            display,
+            beauty: pixels.iter().map(|&idx| layers.beauty[idx]).collect(),
+            aovs: (!layers.aovs.is_empty())
+                .then(|| pixels.iter().map(|&idx| layers.aovs[idx]).collect()),
+            rects,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Writes the shard next to `path` first and renames it into place, so a merge never picks
+    /// up a half-written shard.
+    pub fn save(&self, path: &Path) -> Result<(), RenderError> {
+        write_atomically(path, |out| self.write(out))
+    }
+
+    fn write(&self, out: &mut impl Write) -> Result<(), RenderError> {
+        out.write_all(MAGIC)?;
+        for value in [VERSION, self.width, self.height] {
+            out.write_all(&value.to_le_bytes())?;
+        }
+        out.write_all(&self.display.exposure.to_le_bytes())?;
+        out.write_all(&[
+            tonemap_code(self.display.tonemap),
+            self.display.dither as u8,
+            self.aovs.is_some() as u8,
+        ])?;
+        out.write_all(&(self.rects.len() as u32).to_le_bytes())?;
+        for rect in &self.rects {
+            for value in [rect.tx, rect.ty, rect.tile_w, rect.tile_h] {
+                out.write_all(&(value as u32).to_le_bytes())?;
+            }
+        }
+        for (i, rgb) in self.beauty.iter().enumerate() {
+            for value in rgb {
+                out.write_all(&value.to_le_bytes())?;
+            }
+            if let Some(aovs) = &self.aovs {
+                write_aov(out, aovs[i].as_ref())?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, RenderError> {
+        let file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let mut input = BufReader::new(file);
+        let corrupt = || RenderError::Shard(format!("{} is truncated or corrupt", path.display()));
+        let mut magic = [0_u8; 4];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(RenderError::Shard(format!(
+                "{} is not a shard file",
+                path.display()
+            )));
+        }
+        let version = read_u32(&mut input)?;
+        if version != VERSION {
+            return Err(RenderError::Shard(format!(
+                "unsupported shard version {version}"
+            )));
+        }
+        let width = read_u32(&mut input)?;
+        let height = read_u32(&mut input)?;
+        let exposure = read_f32(&mut input)?;
+        let mut flags = [0_u8; 3];
+        input.read_exact(&mut flags)?;
+        let display = DisplayTransform {
+            exposure,
+            tonemap: tonemap_from_code(flags[0])
+                .ok_or_else(|| RenderError::Shard(format!("unknown tonemap {}", flags[0])))?,
+            dither: flags[1] != 0,
+        };
+        let rect_count = read_u32(&mut input)?;
+        // Counts from the header are checked against the file before they size allocations.
+        let mut remaining = file_len.saturating_sub(HEADER_BYTES);
+        if u64::from(rect_count) * RECT_BYTES > remaining {
+            return Err(corrupt());
+        }
+        remaining -= u64::from(rect_count) * RECT_BYTES;
+        let mut rects = Vec::with_capacity(rect_count as usize);
+        for _ in 0..rect_count {
+            let [tx, ty, tile_w, tile_h] = [(); 4].map(|_| read_u32(&mut input));
+            let rect = TileJob {
+                tx: tx? as usize,
+                ty: ty? as usize,
+                tile_w: tile_w? as usize,
+                tile_h: tile_h? as usize,
+            };
+            if rect.tx + rect.tile_w > width as usize || rect.ty + rect.tile_h > height as usize {
+                return Err(RenderError::Shard(format!(
+                    "{} has a region outside the {width}x{height} frame",
+                    path.display()
+                )));
+            }
+            rects.push(rect);
+        }
+        let pixel_count = rects
+            .iter()
+            .try_fold(0_u64, |sum, r| {
+                sum.checked_add(r.tile_w as u64 * r.tile_h as u64)
+            })
+            .filter(|&count| count <= u64::from(width) * u64::from(height))
+            .filter(|&count| count * (MIN_PIXEL_BYTES + u64::from(flags[2] != 0)) <= remaining)
+            .ok_or_else(corrupt)? as usize;
+        let mut beauty = Vec::with_capacity(pixel_count);
+        let mut aovs = (flags[2] != 0).then(|| Vec::with_capacity(pixel_count));
+        for _ in 0..pixel_count {
+            beauty.push([
+                read_f32(&mut input)?,
+                read_f32(&mut input)?,
+                read_f32(&mut input)?,
+            ]);
+            if let Some(aovs) = &mut aovs {
+                aovs.push(read_aov(&mut input)?);
+            }
+        }
+        Ok(Self {
+            width,
+            height,
+            display,
+            rects,
+            beauty,
+            aovs,
+        })
+    }
+}
+
+/// Assembles shards of one frame into finished layers, using the display transform of the
+/// first shard. Fails if the shards disagree on the frame size or leave pixels uncovered.
+pub fn merge_shards(shards: &[ShardLayers]) -> Result<RenderLayers, RenderError> {
+    let first = shards
+        .first()
+        .ok_or_else(|| RenderError::Shard("no shards to merge".to_string()))?;
+    let (width, height) = (first.width, first.height);
+    if let Some(other) = shards
+        .iter()
+        .find(|shard| (shard.width, shard.height) != (width, height))
+    {
+        return Err(RenderError::Shard(format!(
+            "shards disagree on the frame size: {width}x{height} and {}x{}",
+            other.width, other.height
+        )));
+    }
+    let pixel_count = width as usize * height as usize;
+    let mut beauty = vec![[0.0_f32; 3]; pixel_count];
+    let with_aovs = shards.iter().all(|shard| shard.aovs.is_some());
+    let mut aovs = if with_aovs {
+        vec![None; pixel_count]
+    } else {
+        Vec::new()
+    };
+    let mut covered = vec![false; pixel_count];
+    for shard in shards {
+        let mut src = 0;
+        for rect in &shard.rects {
+            for y in rect.ty..rect.ty + rect.tile_h {
+                for x in rect.tx..rect.tx + rect.tile_w {
+                    let dst = y * width as usize + x;
+                    beauty[dst] = shard.beauty[src];
+                    if let Some(shard_aovs) = shard.aovs.as_ref().filter(|_| with_aovs) {
+                        aovs[dst] = shard_aovs[src];
+                    }
+                    covered[dst] = true;
+                    src += 1;
+                }
+            }
+        }
+    }
+    let missing = covered.iter().filter(|covered| !**covered).count();
+    if missing > 0 {
+        return Err(RenderError::Shard(format!(
+            "{missing} pixels are not covered by any shard"
+        )));
+    }
+    let options = RenderOptions {
+        width,
+        height,
+        display: first.display,
+        ..RenderOptions::default()
+    };
+    Ok(RenderLayers {
+        width,
+        height,
+        image: image_from_radiance(&beauty, options),
+        beauty,
+        aovs,
+    })
+}
+
+fn write_aov(out: &mut impl Write, sample: Option<&AovSample>) -> std::io::Result<()> {
+    let Some(s) = sample else {
+        return out.write_all(&[0]);
+    };
+    out.write_all(&[1])?;
+    for value in [
+        s.depth,
+        s.normal[0],
+        s.normal[1],
+        s.normal[2],
+        s.albedo[0],
+        s.albedo[1],
+        s.albedo[2],
+        f32::from_bits(s.material_id),
+        s.ior,
+        s.transmission,
+        s.fresnel,
+    ] {
+        out.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_aov(input: &mut impl Read) -> Result<Option<AovSample>, RenderError> {
+    let mut present = [0_u8];
+    input.read_exact(&mut present)?;
+    if present[0] == 0 {
+        return Ok(None);
+    }
+    let mut v = [0.0_f32; 11];
+    for value in &mut v {
+        *value = read_f32(input)?;
+    }
+    Ok(Some(AovSample {
+        depth: v[0],
+        normal: [v[1], v[2], v[3]],
+        albedo: [v[4], v[5], v[6]],
+        material_id: v[7].to_bits(),
+        ior: v[8],
+        transmission: v[9],
+        fresnel: v[10],
+    }))
+}
+
+fn tonemap_code(tonemap: Tonemap) -> u8 {
+    match tonemap {
+        Tonemap::Linear => 0,
+        Tonemap::Reinhard => 1,
+        Tonemap::Filmic => 2,
+        Tonemap::Aces => 3,
+        Tonemap::Agx => 4,
+    }
+}
+
+fn tonemap_from_code(code: u8) -> Option<Tonemap> {
+    match code {
+        0 => Some(Tonemap::Linear),
+        1 => Some(Tonemap::Reinhard),
+        2 => Some(Tonemap::Filmic),
+        3 => Some(Tonemap::Aces),
+        4 => Some(Tonemap::Agx),
+        _ => None,
+    }
+}
@@ -0,0 +1,209 @@
+use std::sync::OnceLock;
+
+use super::*;
+
+/// Visible range spectral paths sample, in nanometres.
+const LAMBDA_MIN: f32 = 380.0;
+const LAMBDA_MAX: f32 = 780.0;
+const LAMBDA_RANGE: f32 = LAMBDA_MAX - LAMBDA_MIN;
+
+/// Linear sRGB from CIE XYZ, D65 white.
+const XYZ_TO_SRGB: [[f32; 3]; 3] = [
+    [3.2406, -1.5372, -0.4986],
+    [-0.9689, 1.8758, 0.0415],
+    [0.0557, -0.2040, 1.0570],
+];
+
+/// Wavelengths a spectral path carries in the three channels of its `Spectrum`s: a hero
+/// drawn uniformly from the visible range and two companions a third of the range apart.
+#[derive(Clone, Copy)]
+pub(super) struct Wavelengths {
+    nanometres: [f32; 3],
+    /// Set once a dispersive surface has sent the path on for the hero wavelength alone.
+    hero_only: bool,
+}
+
+impl Wavelengths {
+    pub(super) fn sample(u: f32) -> Self {
+        let offset = u * LAMBDA_RANGE;
+        let at = |i: f32| LAMBDA_MIN + (offset + i * LAMBDA_RANGE / 3.0) % LAMBDA_RANGE;
+        Self {
+            nanometres: [at(0.0), at(1.0), at(2.0)],
+            hero_only: false,
+        }
+    }
+
+    pub(super) fn hero(self) -> f32 {
+        self.nanometres[0]
+    }
+
+    /// Upsamples an RGB reflectance or radiance to the carried wavelengths.
+    pub(super) fn lift(self, rgb: Spectrum) -> Spectrum {
+        let [r, g, b] = self
+            .nanometres
+            .map(|lambda| upsample(rgb, rgb_basis(lambda)));
+        Spectrum::rgb(r, g, b)
+    }
+
+    /// `mat` as seen at these wavelengths, with the weight the path throughput picks up.
+    ///
+    /// Colors are upsampled, measured metals looked up per wavelength and dispersive glass
+    /// takes the index of the hero. Its refraction only suits the hero, so the companions
+    /// drop out and the hero stands in for all three from then on.
+    pub(super) fn material(&mut self, mat: MaterialKindRt) -> (MaterialKindRt, Spectrum) {
+        let dispersive = match mat {
+            Material::Standard(params)
+            | Material::Lambert(params)
+            | Material::Metal(params)
+            | Material::Dielectric(params)
+            | Material::OpenPbr(params) => params.cauchy_b > 0.0,
+            Material::Blend(blend) => {
+                blend.a_params.cauchy_b > 0.0 || blend.b_params.cauchy_b > 0.0
+            }
+        };
+        let weight = if dispersive && !self.hero_only {
+            self.hero_only = true;
+            Spectrum::rgb(3.0, 0.0, 0.0)
+        } else {
+            Spectrum::rgb(1.0, 1.0, 1.0)
+        };
+        let view = |params: MaterialParams| MaterialParams {
+            color: self.lift(params.color),
+            specular_color: self.lift(params.specular_color),
+            emission_color: self.lift(params.emission_color),
+            ior: params.ior_at(self.hero()),
+            conductor: params
+                .conductor
+                .map(|ior| ComplexIor::at_wavelengths(ior.conductor, self.nanometres)),
+            layers: OpenPbrLayers {
+                coat_color: self.lift(params.layers.coat_color),
+                fuzz_color: self.lift(params.layers.fuzz_color),
+                wavelengths: self.nanometres,
+                ..params.layers
+            },
+            ..params
+        };
+        let mat = match mat {
+            Material::Standard(params) => Material::Standard(view(params)),
+            Material::Lambert(params) => Material::Lambert(view(params)),
+            Material::Metal(params) => Material::Metal(view(params)),
+            Material::Dielectric(params) => Material::Dielectric(view(params)),
+            Material::OpenPbr(params) => Material::OpenPbr(view(params)),
+            Material::Blend(blend) => Material::Blend(BlendedMaterial {
+                a_params: view(blend.a_params),
+                b_params: view(blend.b_params),
+                ..blend
+            }),
+        };
+        (mat, weight)
+    }
+
+    /// Converts the radiance a path gathered at these wavelengths to linear sRGB.
+    pub(super) fn to_rgb(self, radiance: Spectrum) -> Spectrum {
+        let values = [radiance.r, radiance.g, radiance.b];
+        let mut rgb = Spectrum::black();
+        for (lambda, value) in self.nanometres.into_iter().zip(values) {
+            rgb = rgb + wavelength_rgb(lambda).scale(value * LAMBDA_RANGE / 3.0);
+        }
+        rgb
+    }
+}
+
+/// Upsamples `rgb` on a spectral path and leaves it alone on an RGB one.
+pub(super) fn lift_rgb(wavelengths: Option<Wavelengths>, rgb: Spectrum) -> Spectrum {
+    wavelengths.map_or(rgb, |wavelengths| wavelengths.lift(rgb))
+}
+
+fn upsample(rgb: Spectrum, basis: [f32; 3]) -> f32 {
+    rgb.r * basis[0] + rgb.g * basis[1] + rgb.b * basis[2]
+}
+
+/// Smooth red, green and blue spectra that sum to one at every wavelength, so white stays
+/// flat and reflectances stay below one.
+fn rgb_basis(lambda: f32) -> [f32; 3] {
+    let smoothstep = |a: f32, b: f32| {
+        let t = ((lambda - a) / (b - a)).clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    };
+    let blue = 1.0 - smoothstep(470.0, 510.0);
+    let red = smoothstep(565.0, 605.0);
+    [red, 1.0 - red - blue, blue]
+}
+
+/// CIE 1931 color matching functions after the multi-lobe fit by Wyman, Sloan and Shirley.
+fn cie_xyz(lambda: f32) -> [f32; 3] {
+    let lobe = |mu: f32, below: f32, above: f32| {
+        let t = (lambda - mu) / if lambda < mu { below } else { above };
+        (-0.5 * t * t).exp()
+    };
+    [
+        1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7)
+            - 0.065 * lobe(501.1, 20.4, 26.2),
+        0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+        1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8),
+    ]
+}
+
+/// Linear sRGB a unit of radiance at `lambda` contributes per nanometre.
+///
+/// The color matching functions are calibrated against the upsampling basis, so an
+/// upsampled color converts back to itself and spectral renders of RGB scenes match their
+/// RGB counterparts on average.
+fn wavelength_rgb(lambda: f32) -> Spectrum {
+    static TABLE: OnceLock<Vec<Spectrum>> = OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        let samples = LAMBDA_RANGE as usize + 1;
+        let srgb = |i: usize| apply_3x3(XYZ_TO_SRGB, cie_xyz(LAMBDA_MIN + i as f32));
+        // Color each basis spectrum turns into, one column per basis.
+        let mut basis_rgb = [[0.0_f32; 3]; 3];
+        for i in 0..samples {
+            let rgb = srgb(i);
+            let basis = rgb_basis(LAMBDA_MIN + i as f32);
+            for (row, value) in basis_rgb.iter_mut().zip(rgb) {
+                for (cell, weight) in row.iter_mut().zip(basis) {
+                    *cell += value * weight;
+                }
+            }
+        }
+        let calibration = invert_3x3(basis_rgb);
+        (0..samples)
+            .map(|i| {
+                let [r, g, b] = apply_3x3(calibration, srgb(i));
+                Spectrum::rgb(r, g, b)
+            })
+            .collect()
+    });
+    let x = (lambda - LAMBDA_MIN).clamp(0.0, LAMBDA_RANGE);
+    let i = (x as usize).min(table.len() - 2);
+    let t = x - i as f32;
+    table[i].scale(1.0 - t) + table[i + 1].scale(t)
+}
+
+fn apply_3x3(m: [[f32; 3]; 3], v: [f32; 3]) -> [f32; 3] {
+    m.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+fn invert_3x3(m: [[f32; 3]; 3]) -> [[f32; 3]; 3] {
+    let cofactor =
+        |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+    let det = m[0][0] * cofactor(1, 2, 1, 2) - m[0][1] * cofactor(1, 2, 0, 2)
+        + m[0][2] * cofactor(1, 2, 0, 1);
+    let inv = 1.0 / det;
+    [
+        [
+            cofactor(1, 2, 1, 2) * inv,
+            -cofactor(0, 2, 1, 2) * inv,
+            cofactor(0, 1, 1, 2) * inv,
+        ],
+        [
+            -cofactor(1, 2, 0, 2) * inv,
+            cofactor(0, 2, 0, 2) * inv,
+            -cofactor(0, 1, 0, 2) * inv,
+        ],
+        [
+            cofactor(1, 2, 0, 1) * inv,
+            -cofactor(0, 2, 0, 1) * inv,
+            cofactor(0, 1, 0, 1) * inv,
+        ],
+    ]
+}
@@ -0,0 +1,240 @@
+use super::*;
+
+/// Scattering events after which a walk that has not found its way out counts as absorbed.
+const MAX_WALK_STEPS: u32 = 256;
+
+/// Where a random walk left the object and the throughput it carried out.
+pub(super) struct WalkExit {
+    pub(super) hit: RayHit,
+    pub(super) weight: Spectrum,
+}
+
+/// Subsurface data of a material that scatters light inside its shape; metals never do.
+pub(super) fn subsurface_params(mat: MaterialKindRt) -> Option<SubsurfaceParams> {
+    match mat {
+        Material::Metal(_) => None,
+        _ => mat.params().subsurface.filter(|params| params.scale > 0.0),
+    }
+}
+
+/// The part of an opaque subsurface material that stays on the surface: the specular and
+/// coat lobes of a `Standard` or `OpenPbr` material, whose diffuse base is replaced by the
+/// body below.
+pub(super) fn subsurface_coat(mat: MaterialKindRt) -> Option<MaterialKindRt> {
+    match mat {
+        Material::Standard(params) => Some(Material::Standard(MaterialParams {
+            color: Spectrum::black(),
+            ..params
+        })),
+        Material::OpenPbr(params) => Some(Material::OpenPbr(MaterialParams {
+            color: Spectrum::black(),
+            ..params
+        })),
+        _ => None,
+    }
+}
+
+/// Share of the light entering an opaque material that reaches the scattering body.
+pub(super) fn subsurface_body_weight(mat: MaterialKindRt) -> f32 {
+    match mat {
+        Material::Standard(params) | Material::OpenPbr(params) => {
+            (1.0 - params.metallic.clamp(0.0, 1.0)) * (1.0 - params.transmission.clamp(0.0, 1.0))
+        }
+        _ => 1.0,
+    }
+}
+
+/// Per-channel extinction and single-scattering albedo of the volume inside a shape.
+///
+/// `color` is the albedo the surface should show after all the scattering and `radius` the
+/// mean distance light travels, so both are remapped with the fits of Chiang et al.,
+/// "Practical and Controllable Subsurface Scattering for Production Path Tracing" (2016).
+struct WalkCoefficients {
+    sigma_t: [f32; 3],
+    albedo: [f32; 3],
+}
+
+impl WalkCoefficients {
+    fn new(params: SubsurfaceParams) -> Self {
+        let color = [params.color.r, params.color.g, params.color.b];
+        let radius = [params.radius.x, params.radius.y, params.radius.z];
+        let mut sigma_t = [0.0; 3];
+        let mut albedo = [0.0; 3];
+        for c in 0..3 {
+            let a = color[c].clamp(0.0, 0.999);
+            let s = 1.9 - a + 3.5 * (a - 0.8) * (a - 0.8);
+            let d = (radius[c] * params.scale).max(1.0e-4);
+            sigma_t[c] = 1.0 / (d * s);
+            let root = 4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt();
+            albedo[c] = (1.0 - root * root).clamp(0.0, 0.9999);
+        }
+        Self { sigma_t, albedo }
+    }
+}
+
+/// Follows light through the inside of a closed shape from `origin` along `dir`.
+///
+/// Free-flight distances are drawn for one color channel at a time, with the other channels
+/// reweighted so all three stay unbiased, and the shape's boundary is found by sphere tracing
+/// each flight. Returns `None` when the light is absorbed.
+pub(super) fn random_walk(
+    accel: &(impl Accelerator + Sync),
+    options: RenderOptions,
+    params: SubsurfaceParams,
+    mut origin: Vec3,
+    mut dir: Vec3,
+    rng: &mut XorShift64,
+    time: f32,
+) -> Option<WalkExit> {
+    let coeffs = WalkCoefficients::new(params);
+    let mut weight = [1.0_f32; 3];
+    for step in 0..MAX_WALK_STEPS {
+        let channel = ((rng.next_f32() * 3.0) as usize).min(2);
+        let flight = -(1.0 - rng.next_f32()).max(1.0e-7).ln() / coeffs.sigma_t[channel];
+        let hit = raymarch_hit(accel, origin, dir, options, 0.0, flight, time);
+        let distance = hit.map_or(flight, |hit| hit.t);
+        let transmittance = coeffs.sigma_t.map(|sigma| (-sigma * distance).exp());
+
+        if let Some(hit) = hit {
+            let pdf = transmittance.iter().sum::<f32>() / 3.0;
+            for c in 0..3 {
+                weight[c] *= transmittance[c] / pdf.max(1.0e-12);
+            }
+            return Some(WalkExit {
+                hit,
+                weight: Spectrum::rgb(weight[0], weight[1], weight[2]),
+            });
+        }
+
+        let pdf = (0..3)
+            .map(|c| coeffs.sigma_t[c] * transmittance[c])
+            .sum::<f32>()
+            / 3.0;
+        for c in 0..3 {
+            weight[c] *= coeffs.albedo[c] * coeffs.sigma_t[c] * transmittance[c] / pdf.max(1.0e-12);
+        }
+        origin = origin.add(dir.mul(flight));
+        dir = sample_henyey_greenstein(dir, params.anisotropy, rng.next_f32(), rng.next_f32());
+
+        if step >= 8 {
+            let survive = weight.iter().copied().fold(0.0, f32::max).min(1.0);
+            if rng.next_f32() >= survive {
+                return None;
+            }
+            weight = weight.map(|w| w / survive);
+        }
+    }
+    None
+}
+
+/// Shading setup where a walk reaches the boundary: the light leaves through a diffuse
+/// transmission lobe facing away from the shape, whatever the surface on top is, so lights
+/// can be sampled from the exit even under a smooth dielectric. `medium` is the one around
+/// the shape, which the light leaves into.
+pub(super) fn subsurface_exit(
+    setup: &RenderSetup,
+    hit: RayHit,
+    medium: MediumState,
+) -> (RayHit, MaterialKindRt, BsdfContextBase) {
+    let outside = RayHit {
+        front_face: true,
+        ..hit
+    };
+    let white = Spectrum::rgb(1.0, 1.0, 1.0);
+    let diffuse = Material::Lambert(MaterialParams::lambert(white, Spectrum::black(), 0.0));
+    (
+        outside,
+        diffuse,
+        build_bsdf_context(setup, outside, diffuse, hit.normal, medium),
+    )
+}
+
+/// Direction scattered off `dir` with the Henyey-Greenstein phase function of anisotropy `g`.
+pub(super) fn sample_henyey_greenstein(dir: Vec3, g: f32, u1: f32, u2: f32) -> Vec3 {
+    let cos_theta = if g.abs() < 1.0e-3 {
+        1.0 - 2.0 * u1
+    } else {
+        let term = (1.0 - g * g) / (1.0 - g + 2.0 * g * u1);
+        ((1.0 + g * g - term * term) / (2.0 * g)).clamp(-1.0, 1.0)
+    };
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * std::f32::consts::PI * u2;
+    let forward = dir.normalize();
+    let tangent = orthonormal_tangent(forward);
+    let bitangent = forward.cross(tangent);
+    tangent
+        .mul(sin_theta * phi.cos())
+        .add(bitangent.mul(sin_theta * phi.sin()))
+        .add(forward.mul(cos_theta))
+        .normalize()
+}
+
+/// Whitted-style stand-in for subsurface transport: light on the lit side scatters back out
+/// near where it entered, and light reaching the far side diffuses through the shape,
+/// falling off with the thickness it crosses by an exponential profile per color channel.
+pub(super) fn diffusion_radiance(
+    accel: &(impl Accelerator + Sync),
+    setup: &RenderSetup,
+    options: RenderOptions,
+    params: SubsurfaceParams,
+    bsdf_ctx: BsdfContextBase,
+    time: f32,
+) -> Spectrum {
+    let position = bsdf_ctx.hit.position;
+    let p = to_api_vec3(position);
+    let n = bsdf_ctx.normal.normalize();
+    let geometric_normal = if bsdf_ctx.hit.front_face {
+        bsdf_ctx.hit.normal.normalize()
+    } else {
+        bsdf_ctx.hit.normal.mul(-1.0).normalize()
+    };
+    let mean_free_path = params.radius * params.scale;
+    let mut color = Spectrum::black();
+    for light in &setup.lights {
+        let shadow_samples = light.shadow_sample_count().max(1);
+        let inv_samples = 1.0 / shadow_samples as f32;
+        for sample_index in 0..shadow_samples {
+            let sample = light.sample_li_indexed(p, sample_index, shadow_samples);
+            let wi = from_api_vec3(sample.wi.normalize());
+            let ndotl = n.dot(wi);
+            let max_dist = |travelled: f32| {
+                if sample.distance.is_finite() {
+                    (sample.distance - travelled - options.epsilon * 8.0).max(0.0)
+                } else {
+                    options.max_dist
+                }
+            };
+
+            let (origin, max_t, profile) = if ndotl > 0.0 {
+                let origin = offset_ray_origin(position, geometric_normal, wi, options.epsilon);
+                (origin, max_dist(0.0), Spectrum::rgb(1.0, 1.0, 1.0))
+            } else {
+                let inner =
+                    offset_ray_origin(position, geometric_normal, wi, options.epsilon * 8.0);
+                let Some(exit) =
+                    raymarch_hit(accel, inner, wi, options, 0.0, options.max_dist, time)
+                else {
+                    continue;
+                };
+                let thickness = exit.t;
+                let profile = Spectrum::rgb(
+                    (-thickness / mean_free_path.x.max(1.0e-4)).exp(),
+                    (-thickness / mean_free_path.y.max(1.0e-4)).exp(),
+                    (-thickness / mean_free_path.z.max(1.0e-4)).exp(),
+                );
+                let origin = offset_ray_origin(exit.position, exit.normal, wi, options.epsilon);
+                (origin, max_dist(thickness), profile)
+            };
+            if max_t <= 0.0 || profile == Spectrum::black() {
+                continue;
+            }
+            let shadow = shadow_visibility(accel, origin, wi, max_t, options.epsilon, time);
+            if shadow <= 0.0 {
+                continue;
+            }
+            let weight = ndotl.abs() * shadow * inv_samples / std::f32::consts::PI;
+            color = color + (params.color * profile * sample.radiance).scale(weight);
+        }
+    }
+    color
+}
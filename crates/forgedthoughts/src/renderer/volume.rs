@@ -0,0 +1,618 @@
+use super::*;
+
+/// Homogeneous fog filling the scene, read from the first `Fog` binding.
+#[derive(Clone, Copy)]
+pub(super) struct Fog {
+    density: f32,
+    color: Spectrum,
+    anisotropy: f32,
+    /// World height the fog stops at; unbounded when `None`.
+    height: Option<f32>,
+}
+
+/// A `volume` filling the shape of the object that carries it.
+#[derive(Clone)]
+pub(super) struct VolumeRegion {
+    shape: SdfNode,
+    bounds: Aabb,
+    transform: PrimitiveTransform,
+    density: f32,
+    color: Spectrum,
+    anisotropy: f32,
+    field: Option<Arc<DensityField>>,
+}
+
+/// The `density(p)` function of a volume definition, with its captured bindings resolved
+/// once for the native version.
+struct DensityField {
+    state: Arc<EvalState>,
+    name: String,
+    overrides: ObjectValue,
+    jit: Option<(JitSdfDistanceFunction, Vec<f32>)>,
+}
+
+/// A scattering event inside a participating medium.
+#[derive(Clone, Copy)]
+pub(super) struct MediumScatter {
+    pub(super) t: f32,
+    pub(super) albedo: Spectrum,
+    pub(super) anisotropy: f32,
+}
+
+/// Extinction, albedo and phase anisotropy of one medium at a point.
+#[derive(Clone, Copy)]
+struct MediumSample {
+    sigma: f32,
+    albedo: Spectrum,
+    anisotropy: f32,
+}
+
+/// Points per ray at which trace mode gathers the light scattered by media.
+const TRACE_MEDIUM_STEPS: u32 = 24;
+
+/// Stretch of a ray over which one medium's extinction stays below `sigma`.
+#[derive(Clone, Copy)]
+struct MajorantSpan {
+    start: f32,
+    end: f32,
+    sigma: f32,
+}
+
+pub(super) fn parse_fog(state: &EvalState) -> Option<Fog> {
+    let mut bindings = state.bindings.iter().collect::<Vec<_>>();
+    bindings.sort_unstable_by_key(|(name, _)| name.as_str());
+    let obj = bindings
+        .into_iter()
+        .find_map(|(_, binding)| match &binding.value {
+            Value::Object(obj) if obj.type_name.as_deref() == Some("Fog") => Some(obj),
+            _ => None,
+        })?;
+    let density = read_number_field(obj, &["density"])
+        .unwrap_or(0.05)
+        .max(0.0);
+    (density > 0.0).then(|| Fog {
+        density,
+        color: read_spectrum_field(obj, "color").unwrap_or(Spectrum::rgb(1.0, 1.0, 1.0)),
+        anisotropy: read_number_field(obj, &["anisotropy"])
+            .unwrap_or(0.0)
+            .clamp(-0.95, 0.95),
+        height: read_number_field(obj, &["height"]),
+    })
+}
+
+/// Splits the objects carrying a `volume` off the surfaces of the scene. Returns what is left
+/// to render as surfaces, or `None` when every object was a volume.
+pub(super) fn split_volume_objects(value: &Value, volumes: &mut Vec<ObjectValue>) -> Option<Value> {
+    let Value::Object(object) = value else {
+        return Some(value.clone());
+    };
+    match object.type_name.as_deref() {
+        _ if object.fields.contains_key("volume") => {
+            volumes.push(object.clone());
+            None
+        }
+        Some("add") => {
+            let (Some(lhs), Some(rhs)) = (object.fields.get("lhs"), object.fields.get("rhs"))
+            else {
+                return Some(value.clone());
+            };
+            match (
+                split_volume_objects(lhs, volumes),
+                split_volume_objects(rhs, volumes),
+            ) {
+                (Some(lhs), Some(rhs)) => {
+                    let mut object = object.clone();
+                    object.fields.insert("lhs".to_string(), lhs);
+                    object.fields.insert("rhs".to_string(), rhs);
+                    Some(Value::Object(object))
+                }
+                (lhs, rhs) => lhs.or(rhs),
+            }
+        }
+        Some("union_all") => {
+            let Some(Value::Array(items)) = object.fields.get("items") else {
+                return Some(value.clone());
+            };
+            let items = items
+                .iter()
+                .filter_map(|item| split_volume_objects(item, volumes))
+                .collect::<Vec<_>>();
+            if items.is_empty() {
+                return None;
+            }
+            let mut object = object.clone();
+            object
+                .fields
+                .insert("items".to_string(), Value::Array(items));
+            Some(Value::Object(object))
+        }
+        _ => Some(value.clone()),
+    }
+}
+
+/// Builds the region of an object whose `volume` is either an instance of a `volume`
+/// definition or a plain `Volume { ... }` of constant density.
+pub(super) fn compile_volume_region(
+    state: &Arc<EvalState>,
+    object: &ObjectValue,
+    ctx: &mut CompileContext,
+) -> Result<VolumeRegion, RenderError> {
+    let Some(Value::Object(volume)) = object.fields.get("volume") else {
+        return Err(RenderError::ExpectedObject);
+    };
+    let shape = compile_sdf(state, &Value::Object(object.clone()), ctx)?;
+    let definition = volume
+        .type_name
+        .as_deref()
+        .filter(|name| state.volume_defs.contains_key(*name));
+    let values = match definition {
+        Some(name) => ObjectValue {
+            type_name: Some(name.to_string()),
+            fields: eval_volume_values(state, name, Some(volume))
+                .map_err(|err| RenderError::Volume(format!("{name}: {err}")))?,
+        },
+        None => volume.clone(),
+    };
+    let field = definition
+        .filter(|name| has_density_function(state, name))
+        .map(|name| {
+            let jit = state
+                .jitted_volume_density_functions
+                .get(name)
+                .and_then(|jit| {
+                    let captures = jit
+                        .capture_names
+                        .iter()
+                        .map(|capture| match values.fields.get(capture) {
+                            Some(Value::Number(value)) => Some(*value),
+                            _ => None,
+                        })
+                        .collect::<Option<Vec<_>>>()?;
+                    Some((jit.clone(), captures))
+                });
+            Arc::new(DensityField {
+                state: Arc::clone(state),
+                name: name.to_string(),
+                overrides: volume.clone(),
+                jit,
+            })
+        });
+    Ok(VolumeRegion {
+        bounds: sdf_bounds(&shape),
+        shape,
+        transform: read_transform(object),
+        density: read_number_field(&values, &["density"])
+            .unwrap_or(1.0)
+            .max(0.0),
+        color: read_spectrum_field(&values, "color").unwrap_or(Spectrum::rgb(1.0, 1.0, 1.0)),
+        anisotropy: read_number_field(&values, &["anisotropy"])
+            .unwrap_or(0.0)
+            .clamp(-0.95, 0.95),
+        field,
+    })
+}
+
+fn has_density_function(state: &EvalState, name: &str) -> bool {
+    state.volume_defs.get(name).is_some_and(|def| {
+        def.statements.iter().any(|stmt| {
+            matches!(stmt, MaterialStatement::Function { name, params, .. }
+                if name == "density" && params.len() == 1)
+        })
+    })
+}
+
+impl DensityField {
+    fn sample(&self, p: Vec3) -> f32 {
+        if let Some((jit, captures)) = &self.jit
+            && let Some(value) = jit.invoke([p.x, p.y, p.z], captures)
+        {
+            return value;
+        }
+        match eval_volume_function(
+            &self.state,
+            &self.name,
+            "density",
+            &[vec3_value_value(p)],
+            Some(&self.overrides),
+        ) {
+            Ok(Value::Number(value)) => value,
+            _ => 0.0,
+        }
+    }
+}
+
+impl VolumeRegion {
+    /// Extinction at `p`: `density` inside the shape, scaled by `density(p)` in the object's
+    /// local space when the volume defines one.
+    fn sigma(&self, p: Vec3, time: f32) -> f32 {
+        if sdf_distance_info(&self.shape, p, time).distance > 0.0 {
+            return 0.0;
+        }
+        match &self.field {
+            Some(field) => self.density * field.sample(to_local(p, self.transform)).clamp(0.0, 1.0),
+            None => self.density,
+        }
+    }
+}
+
+impl Fog {
+    /// Part of `[0, t_max)` along the ray that lies below the fog's height.
+    fn interval(self, origin: Vec3, dir: Vec3, t_max: f32) -> Option<(f32, f32)> {
+        let Some(height) = self.height else {
+            return Some((0.0, t_max));
+        };
+        if dir.y.abs() <= 1.0e-8 {
+            return (origin.y <= height).then_some((0.0, t_max));
+        }
+        let crossing = (height - origin.y) / dir.y;
+        let (start, end) = if dir.y > 0.0 {
+            (0.0, crossing.min(t_max))
+        } else {
+            (crossing.max(0.0), t_max)
+        };
+        (start < end).then_some((start, end))
+    }
+}
+
+/// True when rays in `medium` may scatter or be attenuated by anything besides the medium's
+/// own absorption.
+pub(super) fn has_scattering_media(setup: &RenderSetup, medium: MediumState) -> bool {
+    setup.fog.is_some() || !setup.volumes.is_empty() || medium.scattering > 0.0
+}
+
+fn majorant_spans(
+    setup: &RenderSetup,
+    medium: MediumState,
+    origin: Vec3,
+    dir: Vec3,
+    t_max: f32,
+) -> Vec<MajorantSpan> {
+    let mut spans = Vec::new();
+    if medium.is_air()
+        && let Some(fog) = setup.fog
+        && let Some((start, end)) = fog.interval(origin, dir, t_max)
+    {
+        spans.push(MajorantSpan {
+            start,
+            end,
+            sigma: fog.density,
+        });
+    }
+    if medium.scattering > 0.0 {
+        spans.push(MajorantSpan {
+            start: 0.0,
+            end: t_max,
+            sigma: medium.scattering,
+        });
+    }
+    for volume in &setup.volumes {
+        if volume.density > 0.0
+            && let Some((start, end)) = ray_aabb_intersection(origin, dir, volume.bounds)
+            && end > 0.0
+            && start < t_max
+        {
+            spans.push(MajorantSpan {
+                start: start.max(0.0),
+                end: end.min(t_max),
+                sigma: volume.density,
+            });
+        }
+    }
+    spans
+}
+
+/// Majorant at `t` and the distance up to which it holds.
+fn majorant_at(spans: &[MajorantSpan], t: f32, t_max: f32) -> (f32, f32) {
+    let mut sigma = 0.0;
+    let mut until = t_max;
+    for span in spans {
+        if span.start > t {
+            until = until.min(span.start);
+        } else if span.end > t {
+            sigma += span.sigma;
+            until = until.min(span.end);
+        }
+    }
+    (sigma, until)
+}
+
+/// Collects the media present at `p` into `out`.
+fn media_at(
+    setup: &RenderSetup,
+    medium: MediumState,
+    p: Vec3,
+    time: f32,
+    out: &mut Vec<MediumSample>,
+) {
+    out.clear();
+    if medium.is_air()
+        && let Some(fog) = setup.fog
+        && fog.height.is_none_or(|height| p.y <= height)
+    {
+        out.push(MediumSample {
+            sigma: fog.density,
+            albedo: fog.color,
+            anisotropy: fog.anisotropy,
+        });
+    }
+    if medium.scattering > 0.0 {
+        out.push(MediumSample {
+            sigma: medium.scattering,
+            albedo: medium.scattering_color,
+            anisotropy: medium.anisotropy,
+        });
+    }
+    for volume in &setup.volumes {
+        let sigma = volume.sigma(p, time);
+        if sigma > 0.0 {
+            out.push(MediumSample {
+                sigma,
+                albedo: volume.color,
+                anisotropy: volume.anisotropy,
+            });
+        }
+    }
+}
+
+/// Draws where, if anywhere before `t_max`, a ray scatters in the media it crosses, using
+/// delta tracking against piecewise-constant majorants. Media are grey in extinction, so
+/// a ray that passes keeps its throughput and one that scatters picks up the albedo of the
+/// medium it hit.
+pub(super) fn sample_medium_scatter(
+    setup: &RenderSetup,
+    medium: MediumState,
+    origin: Vec3,
+    dir: Vec3,
+    t_max: f32,
+    rng: &mut XorShift64,
+    time: f32,
+) -> Option<MediumScatter> {
+    let spans = majorant_spans(setup, medium, origin, dir, t_max);
+    if spans.is_empty() {
+        return None;
+    }
+    let mut media = Vec::new();
+    let mut t = 0.0;
+    while t < t_max {
+        let (majorant, until) = majorant_at(&spans, t, t_max);
+        if majorant <= 0.0 {
+            t = until;
+            continue;
+        }
+        let step = -(1.0 - rng.next_f32()).max(1.0e-7).ln() / majorant;
+        if t + step >= until {
+            t = until;
+            continue;
+        }
+        t += step;
+        media_at(setup, medium, origin.add(dir.mul(t)), time, &mut media);
+        let mut pick = rng.next_f32() * majorant;
+        for sample in &media {
+            if pick < sample.sigma {
+                return Some(MediumScatter {
+                    t,
+                    albedo: sample.albedo,
+                    anisotropy: sample.anisotropy,
+                });
+            }
+            pick -= sample.sigma;
+        }
+    }
+    None
+}
+
+/// Fraction of light that crosses `[0, t_max)` of a shadow ray starting in `medium`. Fog and
+/// homogeneous media are integrated exactly; volumes use ratio tracking.
+pub(super) fn medium_shadow_transmittance(
+    setup: &RenderSetup,
+    medium: MediumState,
+    origin: Vec3,
+    dir: Vec3,
+    t_max: f32,
+    rng: &mut XorShift64,
+    time: f32,
+) -> f32 {
+    let mut optical_depth = medium.scattering * t_max;
+    if medium.is_air()
+        && let Some(fog) = setup.fog
+        && let Some((start, end)) = fog.interval(origin, dir, t_max)
+    {
+        optical_depth += fog.density * (end - start);
+    }
+    let mut transmittance = (-optical_depth).exp();
+    for volume in &setup.volumes {
+        if transmittance <= 1.0e-4 {
+            return 0.0;
+        }
+        let Some((start, end)) = ray_aabb_intersection(origin, dir, volume.bounds) else {
+            continue;
+        };
+        let end = end.min(t_max);
+        let mut t = start.max(0.0);
+        if volume.density <= 0.0 || t >= end {
+            continue;
+        }
+        loop {
+            t += -(1.0 - rng.next_f32()).max(1.0e-7).ln() / volume.density;
+            if t >= end {
+                break;
+            }
+            transmittance *= 1.0 - volume.sigma(origin.add(dir.mul(t)), time) / volume.density;
+        }
+    }
+    transmittance
+}
+
+/// Henyey-Greenstein phase function for the angle whose cosine is `cos_theta`.
+pub(super) fn henyey_greenstein(cos_theta: f32, g: f32) -> f32 {
+    let denom = (1.0 + g * g - 2.0 * g * cos_theta).max(1.0e-6);
+    (1.0 - g * g) / (4.0 * std::f32::consts::PI * denom * denom.sqrt())
+}
+
+/// Light arriving at a scattering point inside a medium, sampled from one light and weighted
+/// against phase-function sampling.
+pub(super) fn estimate_direct_phase<A: Accelerator + Sync>(
+    ctx: &DirectLightingCtx<'_, A>,
+    medium: MediumState,
+    point: Vec3,
+    dir: Vec3,
+    anisotropy: f32,
+    rng: &mut XorShift64,
+    time: f32,
+) -> Spectrum {
+    if ctx.lights.is_empty() {
+        return Spectrum::black();
+    }
+    let li = sample_one_light(ctx.setup, point, None, rng);
+    if li.pdf <= 1.0e-6 {
+        return Spectrum::black();
+    }
+    let max_t = li.max_t.min(ctx.options.max_dist);
+    let vis = shadow_visibility(ctx.accel, point, li.wi, max_t, ctx.options.epsilon, time);
+    if vis <= 0.0 {
+        return Spectrum::black();
+    }
+    let transmittance =
+        medium_shadow_transmittance(ctx.setup, medium, point, li.wi, max_t, rng, time);
+    let phase = henyey_greenstein(dir.dot(li.wi), anisotropy);
+    let w = if li.delta {
+        1.0
+    } else {
+        power_heuristic(li.pdf, phase)
+    };
+    let radiance = lift_rgb(ctx.wavelengths, li.radiance)
+        * lift_rgb(ctx.wavelengths, medium_transmittance(medium, max_t));
+    radiance.scale(phase * vis * transmittance * w / li.pdf.max(1.0e-6))
+}
+
+/// Density with which light sampling picks `dir` from a point inside a medium, where the
+/// environment is sampled over the whole sphere.
+pub(super) fn medium_env_light_pdf(setup: &RenderSetup, dir: Vec3) -> f32 {
+    let sampler = &setup.light_sampler;
+    let lights = &setup.path_lights;
+    let env_pdf = sampler.infinite_pmf(|index| matches!(lights[index], PathLight::Env { .. }))
+        / (4.0 * std::f32::consts::PI);
+    let map_pdf = setup.env_map.as_ref().map_or(0.0, |map| {
+        sampler.infinite_pmf(|index| matches!(lights[index], PathLight::EnvMap)) * map.pdf(dir)
+    });
+    env_pdf + map_pdf
+}
+
+/// Disjoint, sorted stretches of `[0, t_max)` where any medium may be present.
+fn medium_intervals(
+    setup: &RenderSetup,
+    medium: MediumState,
+    origin: Vec3,
+    dir: Vec3,
+    t_max: f32,
+) -> Vec<(f32, f32)> {
+    let mut spans = majorant_spans(setup, medium, origin, dir, t_max)
+        .into_iter()
+        .filter(|span| span.start < span.end)
+        .map(|span| (span.start, span.end))
+        .collect::<Vec<_>>();
+    spans.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let mut merged: Vec<(f32, f32)> = Vec::with_capacity(spans.len());
+    for (start, end) in spans {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// Transmittance of `[0, t_max)` by midpoint quadrature over `TRACE_MEDIUM_STEPS` points,
+/// the noise-free estimate trace mode uses for shadow rays.
+pub(super) fn marched_transmittance(
+    setup: &RenderSetup,
+    medium: MediumState,
+    origin: Vec3,
+    dir: Vec3,
+    t_max: f32,
+    time: f32,
+) -> f32 {
+    let mut media = Vec::new();
+    let mut optical_depth = 0.0;
+    for (start, end) in medium_intervals(setup, medium, origin, dir, t_max) {
+        let dt = (end - start) / TRACE_MEDIUM_STEPS as f32;
+        for step in 0..TRACE_MEDIUM_STEPS {
+            let p = origin.add(dir.mul(start + (step as f32 + 0.5) * dt));
+            media_at(setup, medium, p, time, &mut media);
+            optical_depth += media.iter().map(|sample| sample.sigma).sum::<f32>() * dt;
+        }
+    }
+    (-optical_depth).exp()
+}
+
+/// Trace-mode single scattering along `[0, t_end)`: `behind` is seen through the media, and
+/// stratified points along the ray add the light the media scatter towards its origin.
+/// Environment lights scatter in evenly from all around and are not shadowed.
+#[allow(clippy::too_many_arguments)]
+pub(super) fn scatter_along_ray(
+    accel: &(impl Accelerator + Sync),
+    setup: &RenderSetup,
+    options: RenderOptions,
+    medium: MediumState,
+    origin: Vec3,
+    dir: Vec3,
+    t_end: f32,
+    behind: Spectrum,
+    time: f32,
+) -> Spectrum {
+    let mut media = Vec::new();
+    let mut transmittance = 1.0_f32;
+    let mut inscatter = Spectrum::black();
+    for (start, end) in medium_intervals(setup, medium, origin, dir, t_end) {
+        let dt = (end - start) / TRACE_MEDIUM_STEPS as f32;
+        for step in 0..TRACE_MEDIUM_STEPS {
+            let t = start + (step as f32 + 0.5) * dt;
+            let p = origin.add(dir.mul(t));
+            media_at(setup, medium, p, time, &mut media);
+            let sigma_t = media.iter().map(|sample| sample.sigma).sum::<f32>();
+            if sigma_t <= 0.0 {
+                continue;
+            }
+            let point = to_api_vec3(p);
+            let mut scattered = Spectrum::black();
+            for light in &setup.lights {
+                let ambient = light.emitted_radiance(to_api_vec3(dir));
+                if ambient != Spectrum::black() {
+                    for sample in &media {
+                        scattered = scattered + (ambient * sample.albedo).scale(sample.sigma);
+                    }
+                    continue;
+                }
+                let shadow_samples = light.shadow_sample_count().max(1);
+                let inv_samples = 1.0 / shadow_samples as f32;
+                for sample_index in 0..shadow_samples {
+                    let sample = light.sample_li_indexed(point, sample_index, shadow_samples);
+                    let wi = from_api_vec3(sample.wi.normalize());
+                    let max_t = if sample.distance.is_finite() {
+                        (sample.distance - options.epsilon * 8.0).max(0.0)
+                    } else {
+                        options.max_dist
+                    };
+                    if max_t <= 0.0 {
+                        continue;
+                    }
+                    let shadow = shadow_visibility(accel, p, wi, max_t, options.epsilon, time);
+                    if shadow <= 0.0 {
+                        continue;
+                    }
+                    let shadow = shadow * marched_transmittance(setup, medium, p, wi, max_t, time);
+                    let cos_theta = dir.dot(wi);
+                    for medium_sample in &media {
+                        let phase = henyey_greenstein(cos_theta, medium_sample.anisotropy);
+                        scattered = scattered
+                            + (sample.radiance * medium_sample.albedo)
+                                .scale(medium_sample.sigma * phase * shadow * inv_samples);
+                    }
+                }
+            }
+            inscatter = inscatter + scattered.scale(transmittance * dt);
+            transmittance *= (-sigma_t * dt).exp();
+        }
+    }
+    inscatter + behind.scale(transmittance)
+}
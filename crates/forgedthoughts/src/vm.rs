@@ -1,4 +1,9 @@
-use crate::ast::{BinaryOp, Expr, FunctionDef, MaterialFunctionStatement, UnaryOp};
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{
+    BinaryOp, Expr, ExprKind, FunctionDef, MAX_LOOP_ITERATIONS, MaterialFunctionStatement, Span,
+    UnaryOp,
+};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum VmInstruction {
@@ -18,6 +23,8 @@ pub enum VmInstruction {
         argc: usize,
     },
     StoreLocal(String),
+    Jump(usize),
+    JumpIfFalse(usize),
     Return,
 }
 
@@ -25,6 +32,8 @@ pub enum VmInstruction {
 pub struct VmFunction {
     pub params: Vec<String>,
     pub code: Vec<VmInstruction>,
+    /// Source location of each entry in `code`, index for index.
+    pub spans: Vec<Span>,
 }
 
 pub fn compile_function(def: &FunctionDef) -> Option<VmFunction> {
@@ -35,87 +44,320 @@ pub fn compile_function_parts(
     params: &[String],
     body: &[MaterialFunctionStatement],
 ) -> Option<VmFunction> {
-    let mut code = Vec::new();
+    let mut compiler = FunctionCompiler::default();
     for stmt in body {
-        compile_stmt(stmt, &mut code)?;
+        compiler.compile_stmt(stmt)?;
     }
     Some(VmFunction {
         params: params.to_vec(),
-        code,
+        code: compiler.code,
+        spans: compiler.spans,
     })
 }
 
-fn compile_stmt(stmt: &MaterialFunctionStatement, code: &mut Vec<VmInstruction>) -> Option<()> {
-    match stmt {
-        MaterialFunctionStatement::Binding { name, expr } => {
-            compile_expr(expr, code)?;
-            code.push(VmInstruction::StoreLocal(name.clone()));
-        }
-        MaterialFunctionStatement::Return { expr } => {
-            compile_expr(expr, code)?;
-            code.push(VmInstruction::Return);
-        }
-    }
-    Some(())
+/// Bindings made inside an `if` or `for` block are stored under a block-unique name so
+/// they never leak into the enclosing scope, matching the interpreter.
+#[derive(Default)]
+struct FunctionCompiler {
+    code: Vec<VmInstruction>,
+    spans: Vec<Span>,
+    /// Span of the expression being compiled, recorded for each emitted instruction.
+    span: Span,
+    scopes: Vec<HashMap<String, String>>,
+    next_scoped_local: usize,
+    /// Resolved names of `var` bindings that may be assigned to.
+    mutable: HashSet<String>,
+    /// Pending `break` jumps for each enclosing loop.
+    loop_breaks: Vec<Vec<usize>>,
 }
 
-fn compile_expr(expr: &Expr, code: &mut Vec<VmInstruction>) -> Option<()> {
-    match expr {
-        Expr::Number(n) => code.push(VmInstruction::PushNumber(*n as f32)),
-        Expr::String(value) => code.push(VmInstruction::PushString(value.clone())),
-        Expr::Array(items) => {
-            for item in items {
-                compile_expr(item, code)?;
-            }
-            code.push(VmInstruction::BuildArray(items.len()));
+impl FunctionCompiler {
+    fn compile_stmt(&mut self, stmt: &MaterialFunctionStatement) -> Option<()> {
+        // Instructions a statement emits around its expressions, such as the store of a
+        // binding, are attributed to the statement's first expression.
+        match stmt {
+            MaterialFunctionStatement::Binding { expr, .. }
+            | MaterialFunctionStatement::Assign { expr, .. }
+            | MaterialFunctionStatement::Return { expr }
+            | MaterialFunctionStatement::If { cond: expr, .. }
+            | MaterialFunctionStatement::For { start: expr, .. } => self.span = expr.span,
+            MaterialFunctionStatement::Break => {}
         }
-        Expr::Ident(name) => code.push(VmInstruction::LoadName(name.clone())),
-        Expr::ObjectLiteral { type_name, fields } => {
-            for (_, expr) in fields {
-                compile_expr(expr, code)?;
+        match stmt {
+            MaterialFunctionStatement::Binding {
+                name,
+                mutable,
+                expr,
+            } => {
+                self.compile_expr(expr)?;
+                let local = self.declare_local(name);
+                if *mutable {
+                    self.mutable.insert(local.clone());
+                } else {
+                    self.mutable.remove(&local);
+                }
+                self.emit(VmInstruction::StoreLocal(local));
+            }
+            MaterialFunctionStatement::Assign { name, expr } => {
+                let local = self.resolve_local(name);
+                if !self.mutable.contains(&local) {
+                    // Leave the error to the interpreter.
+                    return None;
+                }
+                self.compile_expr(expr)?;
+                self.emit(VmInstruction::StoreLocal(local));
+            }
+            MaterialFunctionStatement::Break => {
+                let jump = self.emit_jump(VmInstruction::Jump(0));
+                self.loop_breaks.last_mut()?.push(jump);
+            }
+            MaterialFunctionStatement::Return { expr } => {
+                self.compile_expr(expr)?;
+                self.emit(VmInstruction::Return);
+            }
+            MaterialFunctionStatement::If {
+                cond,
+                then_body,
+                else_body,
+            } => {
+                self.compile_expr(cond)?;
+                let to_else = self.emit_jump(VmInstruction::JumpIfFalse(0));
+                self.compile_block(then_body)?;
+                let to_end = self.emit_jump(VmInstruction::Jump(0));
+                self.patch_jump(to_else);
+                self.compile_block(else_body)?;
+                self.patch_jump(to_end);
+            }
+            MaterialFunctionStatement::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                self.scopes.push(HashMap::new());
+                let result = self.compile_for(var, start, end, body);
+                self.scopes.pop();
+                result?;
             }
-            code.push(VmInstruction::BuildObject {
-                type_name: type_name.clone(),
-                field_names: fields.iter().map(|(name, _)| name.clone()).collect(),
-            });
         }
-        Expr::Binary { lhs, op, rhs } => {
-            compile_expr(lhs, code)?;
-            compile_expr(rhs, code)?;
-            code.push(VmInstruction::Binary(*op));
+        Some(())
+    }
+
+    /// Lowers `for var in start..end` to a loop over a whole-number iteration count `k`,
+    /// binding `var = start + k`. The bounds are evaluated once and the count stops at
+    /// `end - start` or `MAX_LOOP_ITERATIONS`, whichever comes first, matching the
+    /// interpreter even where `start + 1` rounds back to `start`.
+    fn compile_for(
+        &mut self,
+        var: &str,
+        start: &Expr,
+        end: &Expr,
+        body: &[MaterialFunctionStatement],
+    ) -> Option<()> {
+        self.compile_expr(start)?;
+        let start = self.declare_local("..start");
+        self.emit(VmInstruction::StoreLocal(start.clone()));
+        self.compile_expr(end)?;
+        self.emit(VmInstruction::LoadName(start.clone()));
+        self.emit(VmInstruction::Binary(BinaryOp::Sub));
+        let span = self.declare_local("..span");
+        self.emit(VmInstruction::StoreLocal(span.clone()));
+        self.emit(VmInstruction::PushNumber(0.0));
+        let counter = self.declare_local("..k");
+        self.emit(VmInstruction::StoreLocal(counter.clone()));
+
+        let header = self.code.len();
+        self.emit(VmInstruction::LoadName(counter.clone()));
+        self.emit(VmInstruction::LoadName(span));
+        self.emit(VmInstruction::Binary(BinaryOp::Less));
+        self.emit(VmInstruction::LoadName(counter.clone()));
+        self.emit(VmInstruction::PushNumber(MAX_LOOP_ITERATIONS as f32));
+        self.emit(VmInstruction::Binary(BinaryOp::Less));
+        self.emit(VmInstruction::Binary(BinaryOp::And));
+        let to_exit = self.emit_jump(VmInstruction::JumpIfFalse(0));
+        self.emit(VmInstruction::LoadName(start));
+        self.emit(VmInstruction::LoadName(counter.clone()));
+        self.emit(VmInstruction::Binary(BinaryOp::Add));
+        let index = self.declare_local(var);
+        self.mutable.remove(&index);
+        self.emit(VmInstruction::StoreLocal(index));
+        self.loop_breaks.push(Vec::new());
+        let result = self.compile_block(body);
+        let breaks = self.loop_breaks.pop()?;
+        result?;
+        self.emit(VmInstruction::LoadName(counter.clone()));
+        self.emit(VmInstruction::PushNumber(1.0));
+        self.emit(VmInstruction::Binary(BinaryOp::Add));
+        self.emit(VmInstruction::StoreLocal(counter));
+        self.emit(VmInstruction::Jump(header));
+        self.patch_jump(to_exit);
+        for jump in breaks {
+            self.patch_jump(jump);
         }
-        Expr::Member { target, field } => {
-            compile_expr(target, code)?;
-            code.push(VmInstruction::LoadMember(field.clone()));
+        Some(())
+    }
+
+    fn compile_block(&mut self, body: &[MaterialFunctionStatement]) -> Option<()> {
+        self.scopes.push(HashMap::new());
+        let result = body.iter().try_for_each(|stmt| self.compile_stmt(stmt));
+        self.scopes.pop();
+        result
+    }
+
+    fn declare_local(&mut self, name: &str) -> String {
+        let Some(scope) = self.scopes.last_mut() else {
+            return name.to_string();
+        };
+        let local = format!("{name}#{}", self.next_scoped_local);
+        self.next_scoped_local += 1;
+        scope.insert(name.to_string(), local.clone());
+        local
+    }
+
+    fn resolve_local(&self, name: &str) -> String {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).cloned())
+            .unwrap_or_else(|| name.to_string())
+    }
+
+    fn emit(&mut self, instruction: VmInstruction) {
+        self.code.push(instruction);
+        self.spans.push(self.span);
+    }
+
+    fn emit_jump(&mut self, instruction: VmInstruction) -> usize {
+        self.emit(instruction);
+        self.code.len() - 1
+    }
+
+    fn patch_jump(&mut self, at: usize) {
+        let target = self.code.len();
+        match &mut self.code[at] {
+            VmInstruction::Jump(to) | VmInstruction::JumpIfFalse(to) => *to = target,
+            _ => unreachable!("patch_jump on non-jump instruction"),
         }
-        Expr::Call { callee, args } => {
-            if let Expr::Member { field, .. } = callee.as_ref()
-                && is_unsupported_method_name(field)
-            {
-                return None;
+    }
+
+    fn compile_expr(&mut self, expr: &Expr) -> Option<()> {
+        let outer = std::mem::replace(&mut self.span, expr.span);
+        let result = self.compile_expr_kind(expr);
+        self.span = outer;
+        result
+    }
+
+    fn compile_expr_kind(&mut self, expr: &Expr) -> Option<()> {
+        match &expr.kind {
+            ExprKind::Number(n) => self.emit(VmInstruction::PushNumber(*n as f32)),
+            ExprKind::String(value) => self.emit(VmInstruction::PushString(value.clone())),
+            ExprKind::Array(items) => {
+                for item in items {
+                    self.compile_expr(item)?;
+                }
+                self.emit(VmInstruction::BuildArray(items.len()));
             }
-            let name = flatten_member_expr(callee)?;
-            for arg in args {
-                compile_expr(arg, code)?;
+            ExprKind::Ident(name) => self.emit(VmInstruction::LoadName(self.resolve_local(name))),
+            ExprKind::ObjectLiteral { type_name, fields } => {
+                for (_, expr) in fields {
+                    self.compile_expr(expr)?;
+                }
+                self.emit(VmInstruction::BuildObject {
+                    type_name: type_name.clone(),
+                    field_names: fields.iter().map(|(name, _)| name.clone()).collect(),
+                });
             }
-            code.push(VmInstruction::CallNamed {
-                name,
-                argc: args.len(),
-            });
-        }
-        Expr::Unary { op, expr } => {
-            compile_expr(expr, code)?;
-            code.push(VmInstruction::Unary(*op));
+            ExprKind::Binary {
+                lhs,
+                op: BinaryOp::And,
+                rhs,
+            } => {
+                self.compile_expr(lhs)?;
+                let to_false = self.emit_jump(VmInstruction::JumpIfFalse(0));
+                self.compile_truth(rhs)?;
+                let to_end = self.emit_jump(VmInstruction::Jump(0));
+                self.patch_jump(to_false);
+                self.emit(VmInstruction::PushNumber(0.0));
+                self.patch_jump(to_end);
+            }
+            ExprKind::Binary {
+                lhs,
+                op: BinaryOp::Or,
+                rhs,
+            } => {
+                self.compile_expr(lhs)?;
+                let to_rhs = self.emit_jump(VmInstruction::JumpIfFalse(0));
+                self.emit(VmInstruction::PushNumber(1.0));
+                let to_end = self.emit_jump(VmInstruction::Jump(0));
+                self.patch_jump(to_rhs);
+                self.compile_truth(rhs)?;
+                self.patch_jump(to_end);
+            }
+            ExprKind::Binary { lhs, op, rhs } => {
+                self.compile_expr(lhs)?;
+                self.compile_expr(rhs)?;
+                self.emit(VmInstruction::Binary(*op));
+            }
+            ExprKind::Member { target, field } => {
+                self.compile_expr(target)?;
+                self.emit(VmInstruction::LoadMember(field.clone()));
+            }
+            ExprKind::Call { callee, args } => {
+                if let ExprKind::Member { field, .. } = &callee.kind
+                    && is_unsupported_method_name(field)
+                {
+                    return None;
+                }
+                let name = flatten_member_expr(callee)?;
+                for arg in args {
+                    self.compile_expr(arg)?;
+                }
+                self.emit(VmInstruction::CallNamed {
+                    name,
+                    argc: args.len(),
+                });
+            }
+            ExprKind::Unary { op, expr } => {
+                self.compile_expr(expr)?;
+                self.emit(VmInstruction::Unary(*op));
+            }
+            ExprKind::For { .. } => return None,
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                self.compile_expr(cond)?;
+                let to_else = self.emit_jump(VmInstruction::JumpIfFalse(0));
+                self.compile_expr(then_branch)?;
+                let to_end = self.emit_jump(VmInstruction::Jump(0));
+                self.patch_jump(to_else);
+                self.compile_expr(else_branch)?;
+                self.patch_jump(to_end);
+            }
+            ExprKind::FunctionLiteral { .. } => return None,
         }
-        Expr::FunctionLiteral { .. } => return None,
+        Some(())
+    }
+
+    /// Pushes `expr` normalized to `1.0` or `0.0`. The test goes through `JumpIfFalse` so a
+    /// non-number condition fails the same way it does in the interpreter.
+    fn compile_truth(&mut self, expr: &Expr) -> Option<()> {
+        self.compile_expr(expr)?;
+        let to_false = self.emit_jump(VmInstruction::JumpIfFalse(0));
+        self.emit(VmInstruction::PushNumber(1.0));
+        let to_end = self.emit_jump(VmInstruction::Jump(0));
+        self.patch_jump(to_false);
+        self.emit(VmInstruction::PushNumber(0.0));
+        self.patch_jump(to_end);
+        Some(())
     }
-    Some(())
 }
 
 fn flatten_member_expr(expr: &Expr) -> Option<String> {
-    match expr {
-        Expr::Ident(name) => Some(name.clone()),
-        Expr::Member { target, field } => {
+    match &expr.kind {
+        ExprKind::Ident(name) => Some(name.clone()),
+        ExprKind::Member { target, field } => {
             Some(format!("{}.{}", flatten_member_expr(target)?, field))
         }
         _ => None,
@@ -4,7 +4,7 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use forgedthoughts::{EvalState, Value, eval_program, parse_program};
+use forgedthoughts::{EvalState, Statement, Value, eval_program, format_source, parse_program};
 
 #[test]
 fn parse_golden_fixtures() {
@@ -52,6 +52,47 @@ fn eval_error_fixtures() {
     }
 }
 
+#[test]
+fn format_round_trips_library_and_examples() {
+    let dirs = [
+        "library/materials",
+        "library/objects",
+        "library/scenes",
+        "library/skeletons",
+        "tests/fixtures/parse_ok",
+        "tests/fixtures/eval_ok",
+        "tests/fixtures/render_regression",
+        "../../examples",
+    ];
+    for fixture in dirs.into_iter().flat_map(list_ft_files) {
+        let source = read(&fixture);
+        let formatted = format_source(&source)
+            .unwrap_or_else(|err| panic!("{} should format: {err}", fixture.display()));
+        let reparsed = parse_program(&formatted)
+            .unwrap_or_else(|err| panic!("{} should reparse: {err}", fixture.display()));
+        let original = parse_program(&source).expect("fixture should parse");
+        assert_eq!(
+            without_spans(&reparsed.statements),
+            without_spans(&original.statements),
+            "fixture: {}",
+            fixture.display()
+        );
+        let again = format_source(&formatted).expect("formatted source should format");
+        assert_eq!(again, formatted, "fixture: {}", fixture.display());
+    }
+}
+
+/// `Debug` text of `statements` without source locations, which move when a file is
+/// reformatted.
+fn without_spans(statements: &[Statement]) -> String {
+    let mut text = format!("{statements:?}");
+    while let Some(start) = text.find("span: Span {") {
+        let len = text[start..].find('}').expect("span should close") + 1;
+        text.replace_range(start..start + len, "");
+    }
+    text
+}
+
 fn list_ft_files(relative_dir: &str) -> Vec<PathBuf> {
     let mut entries = Vec::new();
     let root = fixture_root().join(relative_dir);
@@ -4,8 +4,8 @@ use std::{
 };
 
 use forgedthoughts::{
-    AccelMode, EvalState, RaySettings, RenderOptions, eval_program, parse_program,
-    render_preview_progressive_with_accel, render_ray_progressive_with_accel,
+    AccelMode, DisplayTransform, EvalState, RaySettings, RenderOptions, Shutter, eval_program,
+    parse_program, render_preview_progressive_with_accel, render_ray_progressive_with_accel,
 };
 use image::{ImageReader, RgbImage};
 
@@ -47,6 +47,9 @@ fn regression_cases() -> Vec<RegressionCase> {
                 step_scale: 0.7,
                 camera_z: 6.0,
                 fov_y_degrees: 35.0,
+                display: DisplayTransform::default(),
+                shutter: Shutter::default(),
+                shard: None,
             },
         },
         RegressionCase {
@@ -66,6 +69,9 @@ fn regression_cases() -> Vec<RegressionCase> {
                 step_scale: 0.7,
                 camera_z: 6.0,
                 fov_y_degrees: 35.0,
+                display: DisplayTransform::default(),
+                shutter: Shutter::default(),
+                shard: None,
             },
         },
         RegressionCase {
@@ -85,6 +91,31 @@ fn regression_cases() -> Vec<RegressionCase> {
                 step_scale: 0.7,
                 camera_z: 6.0,
                 fov_y_degrees: 25.0,
+                display: DisplayTransform::default(),
+                shutter: Shutter::default(),
+                shard: None,
+            },
+        },
+        RegressionCase {
+            name: "ray_nested_dielectric",
+            scene_file: "ray_nested_dielectric.ft",
+            renderer: RegressionRenderer::Ray {
+                max_depth: 8,
+                aa_samples: 1,
+            },
+            accel: AccelMode::Bvh,
+            options: RenderOptions {
+                width: 96,
+                height: 96,
+                max_steps: 360,
+                max_dist: 50.0,
+                epsilon: 0.0002,
+                step_scale: 0.7,
+                camera_z: 6.0,
+                fov_y_degrees: 35.0,
+                display: DisplayTransform::default(),
+                shutter: Shutter::default(),
+                shard: None,
             },
         },
         RegressionCase {
@@ -101,6 +132,9 @@ fn regression_cases() -> Vec<RegressionCase> {
                 step_scale: 0.7,
                 camera_z: 6.0,
                 fov_y_degrees: 35.0,
+                display: DisplayTransform::default(),
+                shutter: Shutter::default(),
+                shard: None,
             },
         },
     ]
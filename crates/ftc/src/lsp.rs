@@ -0,0 +1,729 @@
+//! `ftc lsp`: a language server for `.ft` files over stdio.
+//!
+//! Speaks just enough JSON-RPC for diagnostics, hover, go-to-definition and completion.
+//! Documents are synced in full; open buffers shadow the files on disk so diagnostics
+//! follow unsaved edits across imports.
+
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use forgedthoughts::{
+    BuiltinLibraryMetadata, CoreError, Program, SourceMap, Span, Statement, Token, TokenKind,
+    builtin_function_doc, builtin_function_docs, builtin_library_item_metadata,
+    builtin_library_items, builtin_object_doc, builtin_object_docs, check_scene_with_overrides,
+    definition_metadata, load_program_with_overrides, tokenize_recovering,
+};
+use serde_json::{Value, json};
+use tracing::{debug, error};
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const SERVER_NOT_INITIALIZED: i64 = -32002;
+
+const COMPLETION_FUNCTION: u32 = 3;
+const COMPLETION_FIELD: u32 = 5;
+const COMPLETION_VARIABLE: u32 = 6;
+const COMPLETION_CLASS: u32 = 7;
+
+pub fn run_lsp() -> ExitCode {
+    let mut reader = io::stdin().lock();
+    let mut writer = io::stdout().lock();
+    let mut server = Server::new();
+    loop {
+        let body = match read_message(&mut reader) {
+            Ok(Some(body)) => body,
+            Ok(None) => return server.exit_code(),
+            Err(err) => {
+                error!("lsp read failed: {err}");
+                return ExitCode::from(1);
+            }
+        };
+        // A body that is not JSON gets a parse error and the server keeps going.
+        let replies = match serde_json::from_slice::<Value>(&body) {
+            Ok(message) => {
+                if message.get("method").and_then(Value::as_str) == Some("exit") {
+                    return server.exit_code();
+                }
+                server.handle(&message)
+            }
+            Err(err) => {
+                debug!("lsp message is not JSON: {err}");
+                vec![error_response(
+                    Value::Null,
+                    PARSE_ERROR,
+                    &format!("parse error: {err}"),
+                )]
+            }
+        };
+        for reply in replies {
+            if let Err(err) = write_message(&mut writer, &reply) {
+                error!("lsp write failed: {err}");
+                return ExitCode::from(1);
+            }
+        }
+    }
+}
+
+/// Reads the body of the next message, or `None` once the client closes the stream.
+fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Vec<u8>>> {
+    let mut content_length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("content-length")
+        {
+            content_length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let Some(length) = content_length else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "missing Content-Length header",
+        ));
+    };
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+fn write_message(writer: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    writer.flush()
+}
+
+struct Server {
+    initialized: bool,
+    shutdown: bool,
+    /// Open documents by URI.
+    documents: HashMap<String, String>,
+    /// Where embedded library files are written so editors can open them.
+    library_dir: PathBuf,
+}
+
+impl Server {
+    fn new() -> Self {
+        Self {
+            initialized: false,
+            shutdown: false,
+            documents: HashMap::new(),
+            library_dir: env::temp_dir().join("forgedthoughts-lsp").join("library"),
+        }
+    }
+
+    fn exit_code(&self) -> ExitCode {
+        if self.shutdown {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::from(1)
+        }
+    }
+
+    fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message.get("method").and_then(Value::as_str).unwrap_or("");
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+        let Some(id) = message.get("id").cloned() else {
+            return self.handle_notification(method, &params);
+        };
+        if !self.initialized && method != "initialize" {
+            return vec![error_response(
+                id,
+                SERVER_NOT_INITIALIZED,
+                "server not initialized",
+            )];
+        }
+        let result = match method {
+            "initialize" => {
+                self.initialized = true;
+                initialize_result()
+            }
+            "shutdown" => {
+                self.shutdown = true;
+                Value::Null
+            }
+            "textDocument/hover" => self.hover(&params),
+            "textDocument/definition" => self.definition(&params),
+            "textDocument/completion" => self.completion(&params),
+            _ => {
+                return vec![error_response(
+                    id,
+                    METHOD_NOT_FOUND,
+                    &format!("unhandled method {method}"),
+                )];
+            }
+        };
+        vec![json!({ "jsonrpc": "2.0", "id": id, "result": result })]
+    }
+
+    fn handle_notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params
+            .pointer("/textDocument/uri")
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        match (method, uri) {
+            ("textDocument/didOpen", Some(uri)) => {
+                let text = params
+                    .pointer("/textDocument/text")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                self.documents.insert(uri, text.to_string());
+                self.publish_all_diagnostics()
+            }
+            ("textDocument/didChange", Some(uri)) => {
+                let changes = params.get("contentChanges").and_then(Value::as_array);
+                if let Some(text) = changes
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change.get("text"))
+                    .and_then(Value::as_str)
+                {
+                    self.documents.insert(uri, text.to_string());
+                }
+                self.publish_all_diagnostics()
+            }
+            ("textDocument/didSave", Some(_)) => self.publish_all_diagnostics(),
+            ("textDocument/didClose", Some(uri)) => {
+                self.documents.remove(&uri);
+                let mut messages = vec![publish_diagnostics(&uri, Vec::new())];
+                messages.extend(self.publish_all_diagnostics());
+                messages
+            }
+            _ => {
+                debug!(method, "ignoring lsp notification");
+                Vec::new()
+            }
+        }
+    }
+
+    /// Open buffers keyed by canonical path, shadowing the files on disk.
+    fn overrides(&self) -> HashMap<PathBuf, String> {
+        self.documents
+            .iter()
+            .filter_map(|(uri, text)| {
+                let path = uri_to_path(uri)?.canonicalize().ok()?;
+                Some((path, text.clone()))
+            })
+            .collect()
+    }
+
+    /// Re-checks every open document, since an edit to a shared file can break its
+    /// importers.
+    fn publish_all_diagnostics(&self) -> Vec<Value> {
+        let overrides = self.overrides();
+        let mut uris = self.documents.keys().collect::<Vec<_>>();
+        uris.sort();
+        uris.into_iter()
+            .map(|uri| {
+                let text = &self.documents[uri];
+                publish_diagnostics(uri, document_diagnostics(uri, text, &overrides))
+            })
+            .collect()
+    }
+
+    fn document_at<'a>(&'a self, params: &'a Value) -> Option<(&'a str, &'a str, usize)> {
+        let uri = params.pointer("/textDocument/uri")?.as_str()?;
+        let text = self.documents.get(uri)?;
+        let line = params.pointer("/position/line")?.as_u64()? as usize;
+        let character = params.pointer("/position/character")?.as_u64()? as usize;
+        Some((uri, text, offset_at(text, line, character)))
+    }
+
+    fn load(&self, uri: &str) -> Option<(Program, SourceMap)> {
+        let path = uri_to_path(uri)?;
+        let (program, sources, _) = load_program_with_overrides(&path, &self.overrides()).ok()?;
+        Some((program, sources))
+    }
+
+    fn hover(&self, params: &Value) -> Value {
+        let Some((uri, text, offset)) = self.document_at(params) else {
+            return Value::Null;
+        };
+        let tokens = tokenize_recovering(text).0;
+        let Some(index) = ident_at(&tokens, offset) else {
+            return Value::Null;
+        };
+        let TokenKind::Ident(name) = &tokens[index].kind else {
+            return Value::Null;
+        };
+        let path = dotted_path(&tokens, index);
+        let markdown = if let Some(doc) = builtin_function_doc(name) {
+            format!("```forge\n{}\n```\n{}", doc.signature, doc.description)
+        } else if let Some(doc) = builtin_object_doc(name) {
+            let fields = doc
+                .fields
+                .iter()
+                .map(|field| format!("`{field}`"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!("**{}**: {}\n\nFields: {fields}", doc.name, doc.description)
+        } else if let Some(markdown) = self.load(uri).and_then(|(program, sources)| {
+            let (stmt, span) = find_definition(&program, &path)?;
+            Some(definition_hover(stmt, *span, &sources))
+        }) {
+            markdown
+        } else if let Some(item) = builtin_library_items(None)
+            .into_iter()
+            .find(|item| item.name == name)
+        {
+            metadata_markdown(&builtin_library_item_metadata(&item))
+        } else {
+            return Value::Null;
+        };
+        let start = tokens[index].start;
+        json!({
+            "contents": { "kind": "markdown", "value": markdown },
+            "range": range(text, start, start + name.len()),
+        })
+    }
+
+    fn definition(&self, params: &Value) -> Value {
+        let Some((uri, text, offset)) = self.document_at(params) else {
+            return Value::Null;
+        };
+        let tokens = tokenize_recovering(text).0;
+        if let Some(index) = import_string_at(text, &tokens, offset) {
+            let TokenKind::String(import) = &tokens[index].kind else {
+                return Value::Null;
+            };
+            return self.import_location(uri, import).unwrap_or(Value::Null);
+        }
+        let Some(index) = ident_at(&tokens, offset) else {
+            return Value::Null;
+        };
+        let path = dotted_path(&tokens, index);
+        self.load(uri)
+            .and_then(|(program, sources)| {
+                let (_, span) = find_definition(&program, &path)?;
+                self.span_location(*span, &sources)
+            })
+            .unwrap_or(Value::Null)
+    }
+
+    fn import_location(&self, uri: &str, import: &str) -> Option<Value> {
+        let target = if import.starts_with("./") || import.starts_with("../") {
+            let path = uri_to_path(uri)?.parent()?.join(import);
+            path_to_uri(&path.canonicalize().ok()?)
+        } else {
+            let item = builtin_library_items(None)
+                .into_iter()
+                .find(|item| item.path == import || item.name == import)?;
+            path_to_uri(&self.materialize_library_file(item.path, item.source)?)
+        };
+        Some(json!({ "uri": target, "range": range("", 0, 0) }))
+    }
+
+    fn span_location(&self, span: Span, sources: &SourceMap) -> Option<Value> {
+        let file = sources.file(span.file)?;
+        let path = match file.name.strip_prefix("builtin:") {
+            Some(library_path) => self.materialize_library_file(library_path, &file.source)?,
+            None => PathBuf::from(&file.name),
+        };
+        Some(json!({
+            "uri": path_to_uri(&path),
+            "range": range(&file.source, span.start, span.end),
+        }))
+    }
+
+    /// Writes an embedded library file to disk so the editor has something to open.
+    fn materialize_library_file(&self, library_path: &str, source: &str) -> Option<PathBuf> {
+        let path = self.library_dir.join(library_path);
+        if fs::read_to_string(&path).ok().as_deref() != Some(source) {
+            fs::create_dir_all(path.parent()?).ok()?;
+            fs::write(&path, source).ok()?;
+        }
+        Some(path)
+    }
+
+    fn completion(&self, params: &Value) -> Value {
+        let Some((uri, text, offset)) = self.document_at(params) else {
+            return Value::Null;
+        };
+        let tokens = tokenize_recovering(text).0;
+        let loaded = self.load(uri);
+        if let Some(type_path) = object_literal_field_context(&tokens, offset) {
+            let items = object_field_items(&type_path, loaded.as_ref().map(|(p, _)| p));
+            if !items.is_empty() {
+                return Value::Array(items);
+            }
+        }
+        Value::Array(global_items(loaded.as_ref().map(|(p, _)| p)))
+    }
+}
+
+fn initialize_result() -> Value {
+    json!({
+        "capabilities": {
+            "textDocumentSync": 1,
+            "hoverProvider": true,
+            "definitionProvider": true,
+            "completionProvider": { "triggerCharacters": ["{", ","] },
+        },
+        "serverInfo": { "name": "ftc", "version": env!("CARGO_PKG_VERSION") },
+    })
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+/// Runs `ftc check` on the document. Errors located in other files (imports) are pinned
+/// to the top of the document with their own location in the message.
+fn document_diagnostics(uri: &str, text: &str, overrides: &HashMap<PathBuf, String>) -> Vec<Value> {
+    let Some(path) = uri_to_path(uri) else {
+        return Vec::new();
+    };
+    let errors = match check_scene_with_overrides(&path, overrides) {
+        Ok(check) => check.errors,
+        Err(err) => vec![err],
+    };
+    let own_file = path
+        .canonicalize()
+        .map(|path| path.display().to_string())
+        .ok();
+    errors
+        .iter()
+        .map(|err| {
+            let (range, message) = match err {
+                CoreError::Located(diagnostic) if Some(&diagnostic.file) == own_file.as_ref() => {
+                    let line = diagnostic.line - 1;
+                    let start = utf16_len(diagnostic.line_text.chars().take(diagnostic.column - 1));
+                    let width = utf16_len(
+                        diagnostic
+                            .line_text
+                            .chars()
+                            .skip(diagnostic.column - 1)
+                            .take(diagnostic.width),
+                    );
+                    (
+                        json!({
+                            "start": { "line": line, "character": start },
+                            "end": { "line": line, "character": start + width.max(1) },
+                        }),
+                        diagnostic.message.clone(),
+                    )
+                }
+                CoreError::Located(diagnostic) => (
+                    range(text, 0, 0),
+                    format!(
+                        "{}:{}:{}: {}",
+                        diagnostic.file, diagnostic.line, diagnostic.column, diagnostic.message
+                    ),
+                ),
+                other => (range(text, 0, 0), other.to_string()),
+            };
+            json!({
+                "range": range,
+                "severity": 1,
+                "source": "forgedthoughts",
+                "message": message,
+            })
+        })
+        .collect()
+}
+
+fn statement_name(stmt: &Statement) -> Option<&str> {
+    match stmt {
+        Statement::Binding { name, .. } => Some(name),
+        Statement::FunctionDef(def) => Some(&def.name),
+        Statement::MaterialDef(def) => Some(&def.name),
+        Statement::SdfDef(def) => Some(&def.name),
+        Statement::SkeletonDef(def) => Some(&def.name),
+        Statement::EnvironmentDef(def) | Statement::VolumeDef(def) => Some(&def.name),
+        Statement::Assign { .. } | Statement::Import { .. } | Statement::Export(_) => None,
+    }
+}
+
+/// Top-level definition of `path` (e.g. `radius` or `lib.Gold`). Later bindings win,
+/// as they do when the scene is evaluated.
+fn find_definition<'a>(program: &'a Program, path: &str) -> Option<(&'a Statement, &'a Span)> {
+    program
+        .statements
+        .iter()
+        .zip(&program.spans)
+        .rev()
+        .find(|(stmt, _)| statement_name(stmt) == Some(path))
+}
+
+fn definition_hover(stmt: &Statement, span: Span, sources: &SourceMap) -> String {
+    let header = sources
+        .file(span.file)
+        .and_then(|file| file.source.get(span.start..span.end))
+        .and_then(|text| text.lines().next())
+        .unwrap_or_default()
+        .trim_end_matches('{')
+        .trim();
+    let mut markdown = format!("```forge\n{header}\n```");
+    if let Some(metadata) = definition_metadata(stmt) {
+        markdown.push('\n');
+        markdown.push_str(&metadata_markdown(&metadata));
+    }
+    markdown
+}
+
+fn metadata_markdown(metadata: &BuiltinLibraryMetadata) -> String {
+    let mut markdown = format!("**{}**", metadata.name);
+    if !metadata.description.is_empty() {
+        markdown.push_str(": ");
+        markdown.push_str(&metadata.description);
+    }
+    if !metadata.params.is_empty() {
+        markdown.push_str("\n\nParams:");
+        for param in &metadata.params {
+            markdown.push_str(&format!("\n- `{}`: {}", param.name, param.param_type));
+            if let Some(default) = &param.default {
+                markdown.push_str(&format!(" = {default}"));
+            }
+            if let Some(description) = &param.description {
+                markdown.push_str(&format!(" — {description}"));
+            }
+        }
+    }
+    markdown
+}
+
+/// Type path of the object literal whose field list contains `offset`, when the cursor
+/// is where a field name goes (right after `{` or `,`).
+fn object_literal_field_context(tokens: &[Token], offset: usize) -> Option<String> {
+    let mut open = Vec::new();
+    let mut previous = None;
+    for (index, token) in tokens.iter().enumerate() {
+        if token.start >= offset {
+            break;
+        }
+        // A word the cursor is still typing doesn't count as the previous token.
+        if let TokenKind::Ident(name) = &token.kind
+            && token.start + name.len() >= offset
+        {
+            break;
+        }
+        match token.kind {
+            TokenKind::LBrace => open.push(index),
+            TokenKind::RBrace => {
+                open.pop();
+            }
+            _ => {}
+        }
+        previous = Some(&token.kind);
+    }
+    if !matches!(previous, Some(TokenKind::LBrace | TokenKind::Comma)) {
+        return None;
+    }
+    let brace = *open.last()?;
+    let type_index = brace.checked_sub(1)?;
+    if !matches!(tokens[type_index].kind, TokenKind::Ident(_)) {
+        return None;
+    }
+    let path = dotted_path(tokens, type_index);
+    let first = type_index - 2 * (path.split('.').count() - 1);
+    let declares = first.checked_sub(1).is_some_and(|keyword| {
+        matches!(&tokens[keyword].kind, TokenKind::Ident(word)
+            if matches!(word.as_str(), "material" | "sdf" | "skeleton" | "environment"))
+    });
+    (!declares).then_some(path)
+}
+
+fn object_field_items(type_path: &str, program: Option<&Program>) -> Vec<Value> {
+    let type_name = type_path.rsplit('.').next().unwrap_or(type_path);
+    if let Some(doc) = builtin_object_doc(type_name) {
+        return doc
+            .fields
+            .iter()
+            .map(|field| {
+                json!({
+                    "label": field,
+                    "kind": COMPLETION_FIELD,
+                    "detail": doc.name,
+                })
+            })
+            .collect();
+    }
+    let metadata = program
+        .and_then(|program| find_definition(program, type_path))
+        .and_then(|(stmt, _)| definition_metadata(stmt))
+        .filter(|metadata| !metadata.params.is_empty())
+        .or_else(|| {
+            builtin_library_items(None)
+                .into_iter()
+                .find(|item| item.name == type_name)
+                .map(|item| builtin_library_item_metadata(&item))
+        });
+    let Some(metadata) = metadata else {
+        return Vec::new();
+    };
+    metadata
+        .params
+        .iter()
+        .map(|param| {
+            json!({
+                "label": param.name,
+                "kind": COMPLETION_FIELD,
+                "detail": param.param_type,
+                "documentation": param.description.clone().unwrap_or_default(),
+            })
+        })
+        .collect()
+}
+
+fn global_items(program: Option<&Program>) -> Vec<Value> {
+    let mut items = Vec::new();
+    for doc in builtin_function_docs() {
+        items.push(json!({
+            "label": doc.name,
+            "kind": COMPLETION_FUNCTION,
+            "detail": doc.signature,
+            "documentation": doc.description,
+        }));
+    }
+    for doc in builtin_object_docs() {
+        items.push(json!({
+            "label": doc.name,
+            "kind": COMPLETION_CLASS,
+            "documentation": doc.description,
+        }));
+    }
+    for item in builtin_library_items(None) {
+        items.push(json!({
+            "label": item.name,
+            "kind": COMPLETION_CLASS,
+            "detail": item.path,
+            "documentation": item.description,
+        }));
+    }
+    for name in program
+        .into_iter()
+        .flat_map(|program| &program.statements)
+        .filter_map(statement_name)
+    {
+        items.push(json!({ "label": name, "kind": COMPLETION_VARIABLE }));
+    }
+    items
+}
+
+/// Index of the identifier token touching `offset`.
+fn ident_at(tokens: &[Token], offset: usize) -> Option<usize> {
+    tokens.iter().position(|token| match &token.kind {
+        TokenKind::Ident(name) => (token.start..=token.start + name.len()).contains(&offset),
+        _ => false,
+    })
+}
+
+/// Index of the string token after `import` that contains `offset`.
+fn import_string_at(text: &str, tokens: &[Token], offset: usize) -> Option<usize> {
+    let index = tokens.iter().position(|token| {
+        matches!(token.kind, TokenKind::String(_))
+            && token.start <= offset
+            && text[token.start + 1..]
+                .find('"')
+                .is_some_and(|end| offset <= token.start + 1 + end)
+    })?;
+    matches!(tokens.get(index.checked_sub(1)?)?.kind, TokenKind::Import).then_some(index)
+}
+
+/// `a.b.c` for the identifier at `index` and the members leading up to it.
+fn dotted_path(tokens: &[Token], index: usize) -> String {
+    let mut parts = Vec::new();
+    let mut index = Some(index);
+    while let Some(i) = index {
+        let TokenKind::Ident(name) = &tokens[i].kind else {
+            break;
+        };
+        parts.push(name.as_str());
+        index = i
+            .checked_sub(2)
+            .filter(|&prev| matches!(tokens[prev + 1].kind, TokenKind::Dot));
+    }
+    parts.reverse();
+    parts.join(".")
+}
+
+fn utf16_len(chars: impl Iterator<Item = char>) -> usize {
+    chars.map(char::len_utf16).sum()
+}
+
+/// Byte offset of an LSP position (0-based line, UTF-16 column).
+fn offset_at(text: &str, line: usize, character: usize) -> usize {
+    let mut line_start = 0;
+    for (index, line_text) in text.split_inclusive('\n').enumerate() {
+        if index == line {
+            let mut units = 0;
+            for (byte, ch) in line_text.char_indices() {
+                if units >= character || ch == '\n' {
+                    return line_start + byte;
+                }
+                units += ch.len_utf16();
+            }
+            return line_start + line_text.len();
+        }
+        line_start += line_text.len();
+    }
+    text.len()
+}
+
+fn position_at(text: &str, offset: usize) -> Value {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    json!({
+        "line": before.matches('\n').count(),
+        "character": utf16_len(before[line_start..].chars()),
+    })
+}
+
+fn range(text: &str, start: usize, end: usize) -> Value {
+    json!({ "start": position_at(text, start), "end": position_at(text, end) })
+}
+
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let encoded = uri.strip_prefix("file://")?;
+    let bytes = encoded.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(byte) = encoded
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok().map(PathBuf::from)
+}
+
+fn path_to_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    uri
+}
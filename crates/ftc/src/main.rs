@@ -1,3 +1,5 @@
+mod lsp;
+
 use std::{
     ffi::OsString,
     fs,
@@ -9,10 +11,14 @@ use std::{
 
 use clap::{Parser, Subcommand, ValueEnum};
 use forgedthoughts::{
-    AccelMode, AppConfig, BuiltinLibraryCategory, CoreError, RayDebugAov, RaySettings,
-    RenderOptions, SceneRenderSettings, builtin_library_item_metadata, builtin_library_items,
-    extract_scene_render_settings, load_and_eval_scene, render_depth_png_with_accel,
-    render_preview_progressive_with_accel, render_ray_progressive_with_accel, resolve_scene_path,
+    AccelMode, AppConfig, BuiltinLibraryCategory, CoreError, DisplayTransform, OutputFormat,
+    PathtraceCheckpoint, PathtraceSettings, RayDebugAov, RaySettings, RenderError, RenderLayers,
+    RenderOptions, RenderShard, SceneRenderSettings, ShardLayers, Tonemap,
+    builtin_library_item_metadata, builtin_library_items, check_scene,
+    extract_scene_render_settings, format_file, load_and_eval_scene, merge_shards,
+    render_depth_png_with_accel, render_pathtrace_checkpointed_with_accel,
+    render_preview_layers_with_accel, render_ray_layers_with_accel, resolve_scene_path,
+    scene_content_hash,
 };
 use indicatif::{ProgressBar, ProgressStyle};
 use tracing::{error, info, level_filters::LevelFilter};
@@ -47,6 +53,18 @@ enum Command {
         #[arg(long)]
         watch: bool,
     },
+    /// Format .ft files in place
+    Fmt {
+        /// Files or directories to format; directories are searched for .ft files
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+
+        /// Report files that would change instead of rewriting them
+        #[arg(long)]
+        check: bool,
+    },
+    /// Run a language server for .ft files over stdio
+    Lsp,
     /// Render a classical Whitted-style traced PNG from a scene
     #[command(alias = "ray")]
     Trace {
@@ -54,7 +72,7 @@ enum Command {
         #[arg(short, long)]
         scene: Option<PathBuf>,
 
-        /// Output PNG path (default: <scene>.png)
+        /// Output path; .exr and .hdr write float layers (default: <scene>.png)
         #[arg(short, long)]
         output: Option<PathBuf>,
 
@@ -86,6 +104,90 @@ enum Command {
         #[arg(long, value_enum)]
         debug_aov: Option<CliRayDebugAov>,
 
+        #[command(flatten)]
+        display: DisplayArgs,
+
+        #[command(flatten)]
+        shard: ShardArgs,
+
+        /// Re-render when the scene file changes
+        #[arg(long)]
+        watch: bool,
+    },
+    /// Render an unbiased path-traced PNG from a scene
+    #[command(alias = "pt")]
+    Pathtrace {
+        /// Path to a .ft scene file
+        #[arg(short, long)]
+        scene: Option<PathBuf>,
+
+        /// Output path; .exr and .hdr write float layers (default: <scene>.png)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Render width
+        #[arg(long)]
+        width: Option<u32>,
+
+        /// Render height
+        #[arg(long)]
+        height: Option<u32>,
+
+        /// Acceleration backend
+        #[arg(long, value_enum)]
+        accel: Option<CliAccelMode>,
+
+        /// Samples per pixel (default: RenderSettings.spp or 16)
+        #[arg(long)]
+        spp: Option<u32>,
+
+        /// Max path bounces (default: RenderSettings.bounces or 4)
+        #[arg(long)]
+        bounces: Option<u32>,
+
+        /// Minimum samples before adaptive sampling may stop a pixel
+        #[arg(long)]
+        min_spp: Option<u32>,
+
+        /// Relative noise threshold for adaptive sampling (0 disables)
+        #[arg(long)]
+        noise_threshold: Option<f32>,
+
+        /// Denoise previews and the final image using albedo, normal and depth
+        #[arg(long)]
+        denoise: bool,
+
+        /// Trace sampled wavelengths instead of RGB, for dispersion and measured metals
+        #[arg(long)]
+        spectral: bool,
+
+        /// Stop after this long and keep the best-so-far image, e.g. 90s, 10m or 1h30m
+        #[arg(long, value_parser = parse_duration)]
+        time_limit: Option<Duration>,
+
+        /// Stop once the image-wide relative noise falls below this value
+        #[arg(long)]
+        target_noise: Option<f32>,
+
+        /// Checkpoint file for the sample accumulators (default: <output>.ftck when resuming or
+        /// time-limited)
+        #[arg(long)]
+        checkpoint: Option<PathBuf>,
+
+        /// Continue from the checkpoint file instead of starting over
+        #[arg(long)]
+        resume: bool,
+
+        /// Write a preview image every N samples
+        #[arg(long, default_value_t = 5)]
+        preview_every: u32,
+
+        #[command(flatten)]
+        display: DisplayArgs,
+
+        #[command(flatten)]
+        shard: ShardArgs,
+
         /// Re-render when the scene file changes
         #[arg(long)]
         watch: bool,
@@ -97,7 +199,7 @@ enum Command {
         #[arg(short, long)]
         scene: Option<PathBuf>,
 
-        /// Output PNG path (default: <scene>.png)
+        /// Output path; .exr and .hdr write float layers (default: <scene>.png)
         #[arg(short, long)]
         output: Option<PathBuf>,
 
@@ -147,6 +249,16 @@ enum Command {
         #[arg(long, default_value_t = 1)]
         warmup: u32,
     },
+    /// Assemble .ftshard files from --region or --tile-shard renders into one image
+    Merge {
+        /// Shard files covering the whole frame
+        #[arg(required = true)]
+        shards: Vec<PathBuf>,
+
+        /// Output path; .exr and .hdr write float layers
+        #[arg(short, long)]
+        output: PathBuf,
+    },
     /// List built-in library assets
     List {
         #[command(subcommand)]
@@ -180,13 +292,50 @@ enum CliRayDebugAov {
     HitT,
 }
 
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum CliTonemap {
+    Linear,
+    Reinhard,
+    Filmic,
+    Aces,
+    Agx,
+}
+
+/// Display transform flags; each one overrides the matching `RenderSettings` field.
+#[derive(Debug, Clone, Copy, clap::Args)]
+struct DisplayArgs {
+    /// Exposure in stops applied before tonemapping
+    #[arg(long, allow_negative_numbers = true)]
+    exposure: Option<f32>,
+
+    /// Tonemap operator for 8-bit output (default: RenderSettings.tonemap or reinhard)
+    #[arg(long, value_enum)]
+    tonemap: Option<CliTonemap>,
+
+    /// Dither 8-bit output to hide banding
+    #[arg(long)]
+    dither: bool,
+}
+
+/// Splits the frame so several processes can render it; each writes a `.ftshard` file.
+#[derive(Debug, Clone, Copy, clap::Args)]
+struct ShardArgs {
+    /// Render only the pixels x0,y0,x1,y1 (end exclusive)
+    #[arg(long, value_parser = parse_region, conflicts_with = "tile_shard")]
+    region: Option<RenderShard>,
+
+    /// Render every n-th tile starting with tile i, given as i/n
+    #[arg(long, value_parser = parse_tile_shard)]
+    tile_shard: Option<(u32, u32)>,
+}
+
 #[derive(Debug, clap::Args)]
 struct DefaultRayArgs {
     /// Path to a .ft scene file
     #[arg(short, long)]
     scene: Option<PathBuf>,
 
-    /// Output PNG path (default: <scene>.png)
+    /// Output path; .exr and .hdr write float layers (default: <scene>.png)
     #[arg(short, long)]
     output: Option<PathBuf>,
 
@@ -218,6 +367,12 @@ struct DefaultRayArgs {
     #[arg(long, value_enum)]
     debug_aov: Option<CliRayDebugAov>,
 
+    #[command(flatten)]
+    display: DisplayArgs,
+
+    #[command(flatten)]
+    shard: ShardArgs,
+
     /// Re-render when the scene file changes
     #[arg(long)]
     watch: bool,
@@ -233,6 +388,44 @@ impl From<CliAccelMode> for AccelMode {
     }
 }
 
+impl From<CliTonemap> for Tonemap {
+    fn from(value: CliTonemap) -> Self {
+        match value {
+            CliTonemap::Linear => Self::Linear,
+            CliTonemap::Reinhard => Self::Reinhard,
+            CliTonemap::Filmic => Self::Filmic,
+            CliTonemap::Aces => Self::Aces,
+            CliTonemap::Agx => Self::Agx,
+        }
+    }
+}
+
+impl DisplayArgs {
+    fn apply(&self, display: &mut DisplayTransform) {
+        if let Some(v) = self.exposure {
+            display.exposure = v;
+        }
+        if let Some(v) = self.tonemap {
+            display.tonemap = v.into();
+        }
+        if self.dither {
+            display.dither = true;
+        }
+    }
+}
+
+impl ShardArgs {
+    fn shard(&self, tile_size: u32) -> Option<RenderShard> {
+        self.region.or_else(|| {
+            self.tile_shard.map(|(index, count)| RenderShard::Tiles {
+                index,
+                count,
+                tile_size,
+            })
+        })
+    }
+}
+
 impl From<CliRayDebugAov> for RayDebugAov {
     fn from(value: CliRayDebugAov) -> Self {
         match value {
@@ -247,7 +440,9 @@ impl From<CliRayDebugAov> for RayDebugAov {
     }
 }
 
-fn init_logging(verbose: u8) {
+/// Logs go to stdout unless `to_stderr` is set, which `ftc lsp` needs since stdout
+/// carries the protocol.
+fn init_logging(verbose: u8, to_stderr: bool) {
     let level = match verbose {
         0 => LevelFilter::INFO,
         1 => LevelFilter::DEBUG,
@@ -264,17 +459,24 @@ fn init_logging(verbose: u8) {
                 .expect("valid forgedthoughts log level"),
         );
 
-    tracing_subscriber::fmt().with_env_filter(filter).init();
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+    if to_stderr {
+        subscriber.with_writer(std::io::stderr).init();
+    } else {
+        subscriber.init();
+    }
 }
 
 fn main() -> ExitCode {
     let cli = Cli::parse();
-    init_logging(cli.verbose);
+    init_logging(cli.verbose, matches!(cli.command, Some(Command::Lsp)));
 
     let cfg = AppConfig::from_env();
     match cli.command {
         None => run_ray(cli.default_ray.into_params(), &cfg),
         Some(Command::Check { scene, watch }) => run_check(scene, watch, &cfg),
+        Some(Command::Fmt { paths, check }) => run_fmt(&paths, check),
+        Some(Command::Lsp) => lsp::run_lsp(),
         Some(Command::Depth {
             scene,
             output,
@@ -307,6 +509,8 @@ fn main() -> ExitCode {
             tile_size,
             aa,
             debug_aov,
+            display,
+            shard,
             watch,
         }) => run_ray(
             RayParams {
@@ -319,6 +523,52 @@ fn main() -> ExitCode {
                 tile_size,
                 aa,
                 debug_aov: debug_aov.map(Into::into),
+                display,
+                shard,
+                watch,
+            },
+            &cfg,
+        ),
+        Some(Command::Pathtrace {
+            scene,
+            output,
+            width,
+            height,
+            accel,
+            spp,
+            bounces,
+            min_spp,
+            noise_threshold,
+            denoise,
+            spectral,
+            time_limit,
+            target_noise,
+            checkpoint,
+            resume,
+            preview_every,
+            display,
+            shard,
+            watch,
+        }) => run_pathtrace(
+            PathtraceParams {
+                scene,
+                output,
+                width,
+                height,
+                accel: accel.map(Into::into),
+                spp,
+                bounces,
+                min_spp,
+                noise_threshold,
+                denoise,
+                spectral,
+                time_limit,
+                target_noise,
+                checkpoint,
+                resume,
+                preview_every,
+                display,
+                shard,
                 watch,
             },
             &cfg,
@@ -330,6 +580,7 @@ fn main() -> ExitCode {
             iterations,
             warmup,
         }) => run_bench(scene, width, height, iterations, warmup, &cfg),
+        Some(Command::Merge { shards, output }) => run_merge(&shards, &output),
         Some(Command::List { kind }) => run_list(kind),
     }
 }
@@ -346,6 +597,8 @@ impl DefaultRayArgs {
             tile_size: self.tile_size,
             aa: self.aa,
             debug_aov: self.debug_aov.map(Into::into),
+            display: self.display,
+            shard: self.shard,
             watch: self.watch,
         }
     }
@@ -356,23 +609,108 @@ fn run_check(scene: Option<PathBuf>, watch: bool, cfg: &AppConfig) -> ExitCode {
 }
 
 fn run_check_once(scene_path: &Path) -> ExitCode {
-    match load_and_eval_scene(scene_path) {
-        Ok(state) => {
+    match check_scene(scene_path) {
+        Ok(check) if check.errors.is_empty() => {
             info!(
                 scene = %scene_path.display(),
-                bindings = state.bindings.len(),
+                bindings = check.state.bindings.len(),
                 "scene parsed and evaluated"
             );
             info!("check completed");
             ExitCode::SUCCESS
         }
+        Ok(check) => {
+            for err in &check.errors {
+                report_scene_error(scene_path, err);
+            }
+            let count = check.errors.len();
+            let noun = if count == 1 { "error" } else { "errors" };
+            error!(scene = %scene_path.display(), "check failed with {count} {noun}");
+            ExitCode::from(3)
+        }
         Err(err) => {
-            error!(scene = %scene_path.display(), "{err}");
+            report_scene_error(scene_path, &err);
             ExitCode::from(3)
         }
     }
 }
 
+fn run_fmt(paths: &[PathBuf], check: bool) -> ExitCode {
+    let mut files = Vec::new();
+    for path in paths {
+        if let Err(err) = collect_ft_files(path, &mut files) {
+            error!(path = %path.display(), "{err}");
+            return ExitCode::from(2);
+        }
+    }
+
+    let mut changed = 0_usize;
+    let mut failed = 0_usize;
+    for file in &files {
+        match format_file(file) {
+            Ok((source, formatted)) if source == formatted => {}
+            Ok(_) if check => {
+                println!("{}", file.display());
+                changed += 1;
+            }
+            Ok((_, formatted)) => match fs::write(file, formatted) {
+                Ok(()) => {
+                    info!(file = %file.display(), "formatted");
+                    changed += 1;
+                }
+                Err(err) => {
+                    error!(file = %file.display(), "failed to write: {err}");
+                    failed += 1;
+                }
+            },
+            Err(err) => {
+                report_scene_error(file, &err);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        return ExitCode::from(3);
+    }
+    let noun = if changed == 1 { "file" } else { "files" };
+    if check && changed > 0 {
+        error!("{changed} {noun} would be reformatted");
+        return ExitCode::from(1);
+    }
+    info!(files = files.len(), "{changed} {noun} reformatted");
+    ExitCode::SUCCESS
+}
+
+/// Adds `path` if it is a file, or every `.ft` file below it if it is a directory.
+fn collect_ft_files(path: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    let mut entries = fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() {
+            collect_ft_files(&entry, files)?;
+        } else if entry.extension().is_some_and(|ext| ext == "ft") {
+            files.push(entry);
+        }
+    }
+    Ok(())
+}
+
+/// Prints located errors as `file:line:col` with a source excerpt, everything else
+/// through the log.
+fn report_scene_error(scene_path: &Path, err: &CoreError) {
+    match err {
+        CoreError::Located(diagnostic) => eprintln!("{diagnostic}"),
+        _ => error!(scene = %scene_path.display(), "{err}"),
+    }
+}
+
 fn run_render(params: RenderParams, cfg: &AppConfig) -> ExitCode {
     run_with_watch(params.scene.clone(), params.watch, cfg, |scene_path| {
         run_render_once(scene_path, &params)
@@ -399,6 +737,7 @@ fn run_render_once(scene_path: &Path, params: &RenderParams) -> ExitCode {
                 .as_deref()
                 .map(Path::to_path_buf)
                 .unwrap_or_else(|| default_output_path(scene_path));
+            let float_output = OutputFormat::from_path(&output_path).is_float();
             let tile_size = params.tile_size.max(8);
             let tiles_x = options.width.div_ceil(tile_size);
             let tiles_y = options.height.div_ceil(tile_size);
@@ -411,27 +750,30 @@ fn run_render_once(scene_path: &Path, params: &RenderParams) -> ExitCode {
             .progress_chars("=>-");
             progress.set_style(style);
             let render_start = Instant::now();
-            let image = match render_preview_progressive_with_accel(
+            let layers = match render_preview_layers_with_accel(
                 &state,
                 options,
                 accel,
                 tile_size,
                 params.aa.max(1),
+                float_output,
                 |step, image| {
                     progress.set_position(u64::from(step.tiles_done));
                     progress.set_message(format!("{} ms", step.elapsed_ms));
-                    image.save(&output_path)?;
+                    if !float_output {
+                        image.save(&output_path)?;
+                    }
                     Ok(())
                 },
             ) {
-                Ok(image) => image,
+                Ok(layers) => layers,
                 Err(err) => {
                     progress.abandon_with_message("failed");
                     error!(output = %output_path.display(), "{err}");
                     return ExitCode::from(4);
                 }
             };
-            if let Err(err) = image.save(&output_path) {
+            if let Err(err) = layers.save(&output_path) {
                 progress.abandon_with_message("failed");
                 error!(output = %output_path.display(), "{err}");
                 return ExitCode::from(4);
@@ -460,12 +802,136 @@ fn run_render_once(scene_path: &Path, params: &RenderParams) -> ExitCode {
             ExitCode::SUCCESS
         }
         Err(err) => {
-            error!(scene = %scene_path.display(), "{err}");
+            report_scene_error(scene_path, &err);
             ExitCode::from(3)
         }
     }
 }
 
+/// Tile size `ftc pathtrace --tile-shard` cuts the frame into.
+const PATHTRACE_SHARD_TILE_SIZE: u32 = 64;
+
+/// Where a shard render goes: `-o` as given, or `<scene>.ftshard`.
+fn shard_output_path(output: Option<&Path>, scene_path: &Path) -> PathBuf {
+    output
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| scene_path.with_extension("ftshard"))
+}
+
+/// Writes finished layers, or just the shard's part of them when rendering a shard.
+fn save_layers(
+    layers: &RenderLayers,
+    options: RenderOptions,
+    path: &Path,
+) -> Result<(), RenderError> {
+    match options.shard {
+        Some(shard) => ShardLayers::from_layers(layers, shard, options.display).save(path),
+        None => layers.save(path),
+    }
+}
+
+fn run_merge(shard_paths: &[PathBuf], output_path: &Path) -> ExitCode {
+    let mut shards = Vec::with_capacity(shard_paths.len());
+    for path in shard_paths {
+        match ShardLayers::load(path) {
+            Ok(shard) => shards.push(shard),
+            Err(err) => {
+                error!(shard = %path.display(), "{err}");
+                return ExitCode::from(4);
+            }
+        }
+    }
+    let layers = match merge_shards(&shards) {
+        Ok(layers) => layers,
+        Err(err) => {
+            error!("{err}");
+            return ExitCode::from(4);
+        }
+    };
+    if let Err(err) = layers.save(output_path) {
+        error!(output = %output_path.display(), "{err}");
+        return ExitCode::from(4);
+    }
+    info!(
+        output = %output_path.display(),
+        width = layers.width,
+        height = layers.height,
+        shards = shards.len(),
+        "shards merged"
+    );
+    ExitCode::SUCCESS
+}
+
+/// Parses `--region x0,y0,x1,y1`.
+fn parse_region(text: &str) -> Result<RenderShard, String> {
+    let values = text
+        .split(',')
+        .map(|part| part.trim().parse::<u32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format!("expected x0,y0,x1,y1 pixel coordinates, got '{text}'"))?;
+    let [x0, y0, x1, y1] = values[..] else {
+        return Err(format!("expected four values x0,y0,x1,y1, got '{text}'"));
+    };
+    if x0 >= x1 || y0 >= y1 {
+        return Err(format!("region '{text}' is empty"));
+    }
+    Ok(RenderShard::Region { x0, y0, x1, y1 })
+}
+
+/// Parses `--tile-shard i/n` with `i` counted from zero.
+fn parse_tile_shard(text: &str) -> Result<(u32, u32), String> {
+    let (index, count) = text
+        .split_once('/')
+        .and_then(|(i, n)| Some((i.trim().parse::<u32>().ok()?, n.trim().parse::<u32>().ok()?)))
+        .ok_or_else(|| format!("expected i/n, got '{text}'"))?;
+    if count == 0 || index >= count {
+        return Err(format!(
+            "shard index must be below the shard count, got '{text}'"
+        ));
+    }
+    Ok((index, count))
+}
+
+/// Parses `--time-limit` values such as `45`, `90s`, `10m`, `1h30m` or `2.5h`; bare numbers are
+/// seconds.
+fn parse_duration(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    if let Ok(secs) = text.parse::<f64>() {
+        return duration_from_secs(secs, text);
+    }
+    let mut total = 0.0;
+    let mut number = String::new();
+    for ch in text.chars() {
+        if ch.is_ascii_digit() || ch == '.' {
+            number.push(ch);
+            continue;
+        }
+        let unit = match ch {
+            'h' => 3600.0,
+            'm' => 60.0,
+            's' => 1.0,
+            _ => return Err(format!("unknown duration unit '{ch}' in '{text}'")),
+        };
+        let value = number
+            .parse::<f64>()
+            .map_err(|_| format!("invalid duration '{text}'"))?;
+        total += value * unit;
+        number.clear();
+    }
+    if !number.is_empty() {
+        return Err(format!("missing unit after '{number}' in '{text}'"));
+    }
+    duration_from_secs(total, text)
+}
+
+fn duration_from_secs(secs: f64, text: &str) -> Result<Duration, String> {
+    if secs.is_finite() && secs > 0.0 {
+        Ok(Duration::from_secs_f64(secs))
+    } else {
+        Err(format!("duration must be positive, got '{text}'"))
+    }
+}
+
 fn default_output_path(scene_path: &Path) -> PathBuf {
     if scene_path.extension().is_some() {
         let mut output = scene_path.to_path_buf();
@@ -561,7 +1027,7 @@ fn run_bench(
                 ExitCode::SUCCESS
             }
             Err(err) => {
-                error!(scene = %scene_path.display(), "{err}");
+                report_scene_error(&scene_path, &err);
                 ExitCode::from(3)
             }
         },
@@ -632,7 +1098,8 @@ fn run_ray_once(scene_path: &Path, params: &RayParams) -> ExitCode {
     match load_and_eval_scene(scene_path) {
         Ok(state) => {
             let scene_settings = extract_scene_render_settings(&state);
-            let options = merged_render_options(&scene_settings, params.width, params.height);
+            let mut options = merged_render_options(&scene_settings, params.width, params.height);
+            params.display.apply(&mut options.display);
             let accel = params
                 .accel
                 .or(scene_settings.accel)
@@ -643,6 +1110,13 @@ fn run_ray_once(scene_path: &Path, params: &RayParams) -> ExitCode {
                 .map(Path::to_path_buf)
                 .unwrap_or_else(|| default_output_path(scene_path));
             let tile_size = params.tile_size.max(8);
+            options.shard = params.shard.shard(tile_size);
+            let output_path = match options.shard {
+                Some(_) => shard_output_path(params.output.as_deref(), scene_path),
+                None => output_path,
+            };
+            let float_output =
+                options.shard.is_some() || OutputFormat::from_path(&output_path).is_float();
             let tiles_x = options.width.div_ceil(tile_size);
             let tiles_y = options.height.div_ceil(tile_size);
             let tiles_total = u64::from(tiles_x) * u64::from(tiles_y);
@@ -655,7 +1129,7 @@ fn run_ray_once(scene_path: &Path, params: &RayParams) -> ExitCode {
             progress.set_style(style);
 
             let render_start = Instant::now();
-            let image = match render_ray_progressive_with_accel(
+            let layers = match render_ray_layers_with_accel(
                 &state,
                 options,
                 accel,
@@ -665,21 +1139,25 @@ fn run_ray_once(scene_path: &Path, params: &RayParams) -> ExitCode {
                     aa_samples: params.aa.max(1),
                     debug_aov: params.debug_aov,
                 },
+                float_output,
                 |step, image| {
+                    progress.set_length(u64::from(step.tiles_total));
                     progress.set_position(u64::from(step.tiles_done));
                     progress.set_message(format!("{} ms", step.elapsed_ms));
-                    image.save(&output_path)?;
+                    if !float_output {
+                        image.save(&output_path)?;
+                    }
                     Ok(())
                 },
             ) {
-                Ok(image) => image,
+                Ok(layers) => layers,
                 Err(err) => {
                     progress.abandon_with_message("failed");
                     error!(output = %output_path.display(), "{err}");
                     return ExitCode::from(4);
                 }
             };
-            if let Err(err) = image.save(&output_path) {
+            if let Err(err) = save_layers(&layers, options, &output_path) {
                 progress.abandon_with_message("failed");
                 error!(output = %output_path.display(), "{err}");
                 return ExitCode::from(4);
@@ -710,7 +1188,170 @@ fn run_ray_once(scene_path: &Path, params: &RayParams) -> ExitCode {
             ExitCode::SUCCESS
         }
         Err(err) => {
-            error!(scene = %scene_path.display(), "{err}");
+            report_scene_error(scene_path, &err);
+            ExitCode::from(3)
+        }
+    }
+}
+
+fn run_pathtrace(params: PathtraceParams, cfg: &AppConfig) -> ExitCode {
+    run_with_watch(params.scene.clone(), params.watch, cfg, |scene_path| {
+        run_pathtrace_once(scene_path, &params)
+    })
+}
+
+fn run_pathtrace_once(scene_path: &Path, params: &PathtraceParams) -> ExitCode {
+    match load_and_eval_scene(scene_path) {
+        Ok(state) => {
+            let scene_settings = extract_scene_render_settings(&state);
+            let mut options = merged_render_options(&scene_settings, params.width, params.height);
+            params.display.apply(&mut options.display);
+            let accel = params
+                .accel
+                .or(scene_settings.accel)
+                .unwrap_or(AccelMode::Bvh);
+            let settings = merged_pathtrace_settings(&scene_settings, params);
+            let output_path = params
+                .output
+                .as_deref()
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|| default_output_path(scene_path));
+            options.shard = params.shard.shard(PATHTRACE_SHARD_TILE_SIZE);
+            if settings.denoise && options.shard.is_some() {
+                // The denoiser cannot see across shard edges, so merged shards would show seams.
+                error!("denoising is not supported with --region or --tile-shard");
+                return ExitCode::from(2);
+            }
+            let output_path = match options.shard {
+                Some(_) => shard_output_path(params.output.as_deref(), scene_path),
+                None => output_path,
+            };
+            let float_output =
+                options.shard.is_some() || OutputFormat::from_path(&output_path).is_float();
+            let checkpoint_path = params.checkpoint.clone().or_else(|| {
+                (params.resume || settings.time_limit.is_some())
+                    .then(|| output_path.with_extension("ftck"))
+            });
+            let resume = match (&checkpoint_path, params.resume) {
+                (Some(path), true) if path.exists() => match PathtraceCheckpoint::load(path) {
+                    Ok(checkpoint) => {
+                        info!(
+                            checkpoint = %path.display(),
+                            samples_done = checkpoint.samples_done(),
+                            "resuming"
+                        );
+                        Some(checkpoint)
+                    }
+                    Err(err) => {
+                        error!(checkpoint = %path.display(), "{err}");
+                        return ExitCode::from(4);
+                    }
+                },
+                (Some(path), true) => {
+                    info!(checkpoint = %path.display(), "no checkpoint yet, starting fresh");
+                    None
+                }
+                _ => None,
+            };
+            let scene_hash = match scene_content_hash(scene_path, &state) {
+                Ok(hash) => hash,
+                Err(err) => {
+                    report_scene_error(scene_path, &err);
+                    return ExitCode::from(3);
+                }
+            };
+            let progress = ProgressBar::new(u64::from(settings.spp.max(1)));
+            let style = ProgressStyle::with_template(
+                "[{elapsed_precise}] {wide_bar} {pos}/{len} spp {msg}",
+            )
+            .unwrap_or_else(|_| ProgressStyle::default_bar())
+            .progress_chars("=>-");
+            progress.set_style(style);
+
+            let render_start = Instant::now();
+            let resumed_samples = resume.as_ref().map_or(0, PathtraceCheckpoint::samples_done);
+            let mut last_step = None;
+            let layers = match render_pathtrace_checkpointed_with_accel(
+                &state,
+                scene_hash,
+                options,
+                accel,
+                settings,
+                float_output,
+                resume,
+                |step, image| {
+                    last_step = Some(step);
+                    progress.set_position(u64::from(step.samples_done));
+                    progress.set_message(format!(
+                        "{} active px, noise {:.4}, {} ms",
+                        step.active_pixels, step.noise, step.elapsed_ms
+                    ));
+                    if !float_output {
+                        image.save(&output_path)?;
+                    }
+                    Ok(())
+                },
+                |checkpoint| match &checkpoint_path {
+                    Some(path) => checkpoint.save(path),
+                    None => Ok(()),
+                },
+            ) {
+                Ok(layers) => layers,
+                Err(err) => {
+                    progress.abandon_with_message("failed");
+                    error!(output = %output_path.display(), "{err}");
+                    return ExitCode::from(4);
+                }
+            };
+            if let Err(err) = save_layers(&layers, options, &output_path) {
+                progress.abandon_with_message("failed");
+                error!(output = %output_path.display(), "{err}");
+                return ExitCode::from(4);
+            }
+            let samples_done = last_step.map_or(resumed_samples, |step| step.samples_done);
+            if let Some(step) = last_step
+                && step.samples_done < settings.spp
+                && step.active_pixels > 0
+            {
+                progress.abandon_with_message("stopped early");
+                info!(
+                    samples_done = step.samples_done,
+                    noise = step.noise,
+                    checkpoint = ?checkpoint_path,
+                    "stopped before reaching spp; rerun with --resume to continue"
+                );
+            } else {
+                progress.finish_with_message("done");
+            }
+            let render_elapsed = render_start.elapsed();
+            let render_secs = render_elapsed.as_secs_f64();
+            let render_secs_display = format!("{render_secs:.3}s");
+            let megapixels = (f64::from(options.width) * f64::from(options.height)) / 1_000_000.0;
+            let mpix_per_sec = megapixels / render_secs.max(f64::EPSILON);
+            let mpix_per_sec_display = format!("{mpix_per_sec:.3}");
+            info!(
+                output = %output_path.display(),
+                width = options.width,
+                height = options.height,
+                accel = ?accel,
+                spp = settings.spp,
+                samples_done,
+                bounces = settings.max_bounces,
+                min_spp = settings.min_spp,
+                noise_threshold = settings.noise_threshold,
+                denoise = settings.denoise,
+                spectral = settings.spectral,
+                "pathtrace rendered"
+            );
+            info!(
+                render_secs = %render_secs_display,
+                mpix_per_sec = %mpix_per_sec_display,
+                "benchmark"
+            );
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            report_scene_error(scene_path, &err);
             ExitCode::from(3)
         }
     }
@@ -737,6 +1378,30 @@ struct RayParams {
     tile_size: u32,
     aa: u32,
     debug_aov: Option<RayDebugAov>,
+    display: DisplayArgs,
+    shard: ShardArgs,
+    watch: bool,
+}
+
+struct PathtraceParams {
+    scene: Option<PathBuf>,
+    output: Option<PathBuf>,
+    width: Option<u32>,
+    height: Option<u32>,
+    accel: Option<AccelMode>,
+    spp: Option<u32>,
+    bounces: Option<u32>,
+    min_spp: Option<u32>,
+    noise_threshold: Option<f32>,
+    denoise: bool,
+    spectral: bool,
+    time_limit: Option<Duration>,
+    target_noise: Option<f32>,
+    checkpoint: Option<PathBuf>,
+    resume: bool,
+    preview_every: u32,
+    display: DisplayArgs,
+    shard: ShardArgs,
     watch: bool,
 }
 
@@ -819,6 +1484,21 @@ fn merged_render_options(
     if let Some(v) = scene_settings.fov_y_degrees {
         options.fov_y_degrees = v;
     }
+    if let Some(v) = scene_settings.exposure {
+        options.display.exposure = v;
+    }
+    if let Some(v) = scene_settings.tonemap {
+        options.display.tonemap = v;
+    }
+    if let Some(v) = scene_settings.dither {
+        options.display.dither = v;
+    }
+    if let Some(v) = scene_settings.shutter_open {
+        options.shutter.open = v;
+    }
+    if let Some(v) = scene_settings.shutter_close {
+        options.shutter.close = v;
+    }
     if let Some(v) = cli_width {
         options.width = v;
     }
@@ -827,3 +1507,47 @@ fn merged_render_options(
     }
     options
 }
+
+fn merged_pathtrace_settings(
+    scene_settings: &SceneRenderSettings,
+    params: &PathtraceParams,
+) -> PathtraceSettings {
+    let defaults = PathtraceSettings::default();
+    let spp = params
+        .spp
+        .or(scene_settings.trace_spp)
+        .unwrap_or(defaults.spp)
+        .max(1);
+    PathtraceSettings {
+        spp,
+        max_bounces: params
+            .bounces
+            .or(scene_settings.trace_bounces)
+            .unwrap_or(defaults.max_bounces)
+            .max(1),
+        preview_every: params.preview_every.max(1),
+        min_spp: params
+            .min_spp
+            .or(scene_settings.trace_min_spp)
+            .unwrap_or(defaults.min_spp)
+            .clamp(1, spp),
+        noise_threshold: params
+            .noise_threshold
+            .or(scene_settings.trace_noise_threshold)
+            .unwrap_or(defaults.noise_threshold)
+            .max(0.0),
+        denoise: params.denoise || scene_settings.denoise.unwrap_or(defaults.denoise),
+        spectral: params.spectral || scene_settings.spectral.unwrap_or(defaults.spectral),
+        time_limit: params.time_limit.or_else(|| {
+            scene_settings
+                .time_limit_secs
+                .filter(|secs| *secs > 0.0)
+                .map(Duration::from_secs_f32)
+        }),
+        target_noise: params
+            .target_noise
+            .or(scene_settings.target_noise)
+            .unwrap_or(defaults.target_noise)
+            .max(0.0),
+    }
+}
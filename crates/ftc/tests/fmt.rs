@@ -0,0 +1,50 @@
+use std::{
+    fs,
+    path::PathBuf,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+fn temp_test_dir(label: &str) -> PathBuf {
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("clock should work")
+        .as_nanos();
+    let dir = std::env::temp_dir().join(format!("ftc-{label}-{stamp}"));
+    fs::create_dir_all(&dir).expect("temp dir should exist");
+    dir
+}
+
+fn ftc(args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_ftc"))
+        .args(args)
+        .output()
+        .expect("ftc should run")
+}
+
+#[test]
+fn fmt_check_reports_then_fmt_rewrites() {
+    let dir = temp_test_dir("fmt");
+    let scene = dir.join("scene.ft");
+    let source = "var s = Sphere{radius: 1.0}; // ball\n";
+    fs::write(&scene, source).expect("scene should write");
+    let dir_arg = dir.to_str().expect("temp dir should be utf-8");
+
+    let check = ftc(&["fmt", "--check", dir_arg]);
+    assert_eq!(check.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&check.stdout).contains("scene.ft"));
+    assert_eq!(
+        fs::read_to_string(&scene).expect("scene should read"),
+        source
+    );
+
+    assert!(ftc(&["fmt", dir_arg]).status.success());
+    assert_eq!(
+        fs::read_to_string(&scene).expect("scene should read"),
+        "var s = Sphere {\n  radius: 1.0\n}; // ball\n"
+    );
+    assert!(ftc(&["fmt", "--check", dir_arg]).status.success());
+
+    fs::write(&scene, "let = 1;\n").expect("scene should write");
+    assert_eq!(ftc(&["fmt", "--check", dir_arg]).status.code(), Some(3));
+}
@@ -0,0 +1,216 @@
+use std::{
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{Value, json};
+
+struct Client {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    next_id: u64,
+    notifications: Vec<Value>,
+}
+
+impl Client {
+    fn start() -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_ftc"))
+            .arg("lsp")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("ftc lsp should start");
+        let stdin = child.stdin.take().expect("stdin should be piped");
+        let stdout = BufReader::new(child.stdout.take().expect("stdout should be piped"));
+        Self {
+            child,
+            stdin,
+            stdout,
+            next_id: 1,
+            notifications: Vec::new(),
+        }
+    }
+
+    fn send(&mut self, message: &Value) {
+        self.send_raw(&message.to_string());
+    }
+
+    fn send_raw(&mut self, body: &str) {
+        write!(self.stdin, "Content-Length: {}\r\n\r\n{body}", body.len())
+            .expect("message should write");
+        self.stdin.flush().expect("stdin should flush");
+    }
+
+    fn receive(&mut self) -> Value {
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            self.stdout
+                .read_line(&mut line)
+                .expect("header should read");
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(value) = line.strip_prefix("Content-Length:") {
+                length = value.trim().parse().expect("length should parse");
+            }
+        }
+        let mut body = vec![0; length];
+        self.stdout.read_exact(&mut body).expect("body should read");
+        serde_json::from_slice(&body).expect("body should be json")
+    }
+
+    fn request(&mut self, method: &str, params: Value) -> Value {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(&json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }));
+        loop {
+            let message = self.receive();
+            if message.get("id") == Some(&json!(id)) {
+                return message["result"].clone();
+            }
+            self.notifications.push(message);
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        self.send(&json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+    }
+
+    /// Diagnostics most recently published for `uri`, waiting for the next publish.
+    fn diagnostics(&mut self, uri: &str) -> Vec<Value> {
+        loop {
+            let message = self.receive();
+            if message["method"] == "textDocument/publishDiagnostics"
+                && message["params"]["uri"] == uri
+            {
+                return message["params"]["diagnostics"]
+                    .as_array()
+                    .expect("diagnostics should be an array")
+                    .clone();
+            }
+        }
+    }
+}
+
+/// Creates a fresh directory, resolved so URIs compare equal to the ones the server
+/// reports even where the temp dir is a symlink.
+fn temp_test_dir(label: &str) -> PathBuf {
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("clock should work")
+        .as_nanos();
+    let dir = std::env::temp_dir().join(format!("ftc-{label}-{stamp}"));
+    fs::create_dir_all(&dir).expect("temp dir should exist");
+    dir.canonicalize().expect("temp dir should resolve")
+}
+
+fn file_uri(path: &Path) -> String {
+    format!("file://{}", path.display())
+}
+
+fn position(uri: &str, line: u32, character: u32) -> Value {
+    json!({
+        "textDocument": { "uri": uri },
+        "position": { "line": line, "character": character },
+    })
+}
+
+fn labels(completion: &Value) -> Vec<String> {
+    completion
+        .as_array()
+        .expect("completion should be a list")
+        .iter()
+        .map(|item| item["label"].as_str().unwrap_or_default().to_string())
+        .collect()
+}
+
+#[test]
+fn serves_diagnostics_hover_definition_and_completion() {
+    let dir = temp_test_dir("lsp");
+    fs::write(
+        dir.join("shapes.ft"),
+        "let scale = 2.0;\nfn radius(x) {\n    return x * scale;\n}\n",
+    )
+    .expect("shapes.ft should write");
+    let text = "import \"./shapes.ft\";\nimport \"Gold\";\nimport \"Cupboard\";\n\
+                let n = fbm_3d(vec3(0.0), 3.0);\nlet r = radius(1.0);\n\
+                let ball = Sphere {  };\nlet cup = Cupboard {  };\nlet bad = missing;\n";
+    fs::write(dir.join("main.ft"), text).expect("main.ft should write");
+    let uri = file_uri(&dir.join("main.ft"));
+
+    let mut client = Client::start();
+    let init = client.request("initialize", json!({ "capabilities": {} }));
+    assert_eq!(init["capabilities"]["hoverProvider"], true);
+    client.notify("initialized", json!({}));
+
+    client.notify(
+        "textDocument/didOpen",
+        json!({ "textDocument": { "uri": uri, "languageId": "forge", "version": 1, "text": text } }),
+    );
+    let diagnostics = client.diagnostics(&uri);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(
+        diagnostics[0]["message"],
+        "eval error: undefined identifier 'missing'"
+    );
+    assert_eq!(diagnostics[0]["range"]["start"]["line"], 7);
+
+    let hover = client.request("textDocument/hover", position(&uri, 3, 10));
+    let hover_text = hover["contents"]["value"].as_str().unwrap_or_default();
+    assert!(hover_text.contains("fbm_3d(p, octaves"), "{hover_text}");
+
+    let hover = client.request("textDocument/hover", position(&uri, 4, 10));
+    let hover_text = hover["contents"]["value"].as_str().unwrap_or_default();
+    assert!(hover_text.contains("fn radius(x)"), "{hover_text}");
+
+    let definition = client.request("textDocument/definition", position(&uri, 4, 10));
+    assert_eq!(definition["uri"], file_uri(&dir.join("shapes.ft")));
+    assert_eq!(definition["range"]["start"]["line"], 1);
+
+    let definition = client.request("textDocument/definition", position(&uri, 1, 9));
+    let library_uri = definition["uri"].as_str().unwrap_or_default();
+    assert!(library_uri.ends_with("materials/gold.ft"), "{library_uri}");
+    let library_file = PathBuf::from(library_uri.trim_start_matches("file://"));
+    assert!(
+        fs::read_to_string(library_file)
+            .expect("library file should be written")
+            .contains("material Gold")
+    );
+
+    let completion = client.request("textDocument/completion", position(&uri, 5, 20));
+    let fields = labels(&completion);
+    assert!(fields.contains(&"radius".to_string()), "{fields:?}");
+    assert!(!fields.contains(&"fbm_3d".to_string()), "{fields:?}");
+
+    let completion = client.request("textDocument/completion", position(&uri, 6, 21));
+    let params = labels(&completion);
+    assert!(params.contains(&"open_amount".to_string()), "{params:?}");
+
+    // A malformed body is answered with a parse error instead of ending the session.
+    client.send_raw("{ not json");
+    let reply = client.receive();
+    assert_eq!(reply["error"]["code"], -32700);
+    assert_eq!(reply["id"], Value::Null);
+
+    let fixed = text.replace("missing", "r");
+    client.notify(
+        "textDocument/didChange",
+        json!({
+            "textDocument": { "uri": uri, "version": 2 },
+            "contentChanges": [{ "text": fixed }],
+        }),
+    );
+    assert!(client.diagnostics(&uri).is_empty());
+
+    assert_eq!(client.request("shutdown", Value::Null), Value::Null);
+    client.notify("exit", Value::Null);
+    let status = client.child.wait().expect("ftc lsp should exit");
+    assert!(status.success());
+}